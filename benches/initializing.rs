@@ -0,0 +1,52 @@
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use post::{
+    config::ScryptParams,
+    initialize::{CpuInitializer, Initialize},
+    scrypt_opt::OptimizedCpuInitializer,
+};
+#[cfg(not(windows))]
+use pprof::criterion::{Output, PProfProfiler};
+
+fn initializing(c: &mut Criterion) {
+    let scrypt = ScryptParams::new(8192, 1, 1);
+    let labels = 0..256u64;
+    let mut group = c.benchmark_group("initialize");
+    group.throughput(Throughput::Elements(labels.end - labels.start));
+
+    let mut initializers: Vec<(&str, Box<dyn Initialize>)> = vec![
+        ("reference", Box::new(CpuInitializer::new(scrypt))),
+        (
+            "optimized",
+            Box::new(OptimizedCpuInitializer::new(scrypt).unwrap()),
+        ),
+    ];
+    for (name, initializer) in &mut initializers {
+        group.bench_function(BenchmarkId::new(*name, scrypt.n), |b| {
+            b.iter(|| {
+                let mut data = Vec::with_capacity(labels.clone().count() * 16);
+                initializer
+                    .initialize_to(&mut data, &[0u8; 32], labels.clone(), None)
+                    .unwrap();
+                data
+            });
+        });
+    }
+    group.finish();
+}
+
+#[cfg(not(windows))]
+fn config() -> Criterion {
+    Criterion::default().with_profiler(PProfProfiler::new(100, Output::Flamegraph(None)))
+}
+#[cfg(windows)]
+fn config() -> Criterion {
+    Criterion::default()
+}
+
+criterion_group!(
+    name = benches;
+    config = config();
+    targets = initializing
+);
+
+criterion_main!(benches);
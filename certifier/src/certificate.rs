@@ -0,0 +1,130 @@
+//! Certificates issued by the certifier.
+//!
+//! A certificate is a SCALE-encoded [Certificate] signed with the ed25519 key of the certifier.
+//! Services accepting certificates (i.e. poets) can use [verify] to check them.
+
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use ed25519_dalek::{Signature, Verifier, VerifyingKey};
+use parity_scale_codec::{Compact, Decode, DecodeAll, Encode};
+
+#[derive(Debug, Clone, PartialEq, Eq, Decode, Encode)]
+pub struct Certificate {
+    // ID of the node being certified
+    pub pub_key: Vec<u8>,
+    /// Unix timestamp
+    pub expiration: Option<Compact<u64>>,
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("decoding certificate: {0}")]
+    Decode(#[from] parity_scale_codec::Error),
+    #[error("invalid signature: {0}")]
+    InvalidSignature(#[from] ed25519_dalek::SignatureError),
+    #[error("certificate expired at {0:?}")]
+    Expired(SystemTime),
+}
+
+impl Certificate {
+    pub fn new(pub_key: &[u8], expiration: Option<SystemTime>) -> Self {
+        Self {
+            pub_key: pub_key.to_vec(),
+            expiration: expiration.map(|t| Compact(crate::time::unix_timestamp(t))),
+        }
+    }
+
+    /// Decode a certificate, rejecting trailing bytes after it.
+    pub fn from_bytes(mut bytes: &[u8]) -> Result<Self, parity_scale_codec::Error> {
+        Self::decode_all(&mut bytes)
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        self.encode()
+    }
+
+    /// The time when the certificate expires, `None` if it never expires.
+    pub fn expires_at(&self) -> Option<SystemTime> {
+        self.expiration
+            .map(|Compact(ts)| UNIX_EPOCH + Duration::from_secs(ts))
+    }
+
+    /// Has the certificate expired at `now`.
+    pub fn is_expired(&self, now: SystemTime) -> bool {
+        self.expires_at().is_some_and(|exp| exp <= now)
+    }
+}
+
+/// Verify the signature of an encoded certificate with the public key of the certifier,
+/// decode it and check that it has not expired at `now`.
+pub fn verify(
+    certificate: &[u8],
+    signature: &[u8],
+    certifier: &VerifyingKey,
+    now: SystemTime,
+) -> Result<Certificate, Error> {
+    let signature = Signature::from_slice(signature)?;
+    certifier.verify(certificate, &signature)?;
+    let cert = Certificate::from_bytes(certificate)?;
+    match cert.expires_at() {
+        Some(exp) if exp <= now => Err(Error::Expired(exp)),
+        _ => Ok(cert),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ed25519_dalek::{Signer, SigningKey};
+
+    use super::*;
+
+    #[test]
+    fn encoding_roundtrip() {
+        let cert = Certificate::new(&[7; 32], Some(UNIX_EPOCH + Duration::from_secs(1000)));
+        assert_eq!(cert, Certificate::from_bytes(&cert.to_bytes()).unwrap());
+        assert_eq!(
+            Some(UNIX_EPOCH + Duration::from_secs(1000)),
+            cert.expires_at()
+        );
+        assert!(Certificate::from_bytes(&[0xFF]).is_err());
+
+        let mut trailing = cert.to_bytes();
+        trailing.push(0);
+        assert!(Certificate::from_bytes(&trailing).is_err());
+    }
+
+    #[test]
+    fn checking_expiry() {
+        let exp = UNIX_EPOCH + Duration::from_secs(1000);
+        let cert = Certificate::new(&[7; 32], Some(exp));
+        assert!(!cert.is_expired(exp - Duration::from_secs(1)));
+        assert!(cert.is_expired(exp));
+        assert!(!Certificate::new(&[7; 32], None).is_expired(SystemTime::now()));
+    }
+
+    #[test]
+    fn verifying_certificate() {
+        let signer = SigningKey::generate(&mut rand::rngs::OsRng);
+        let exp = UNIX_EPOCH + Duration::from_secs(1000);
+        let encoded = Certificate::new(&[7; 32], Some(exp)).to_bytes();
+        let signature = signer.sign(&encoded).to_bytes();
+        let now = exp - Duration::from_secs(1);
+
+        let cert = verify(&encoded, &signature, &signer.verifying_key(), now).unwrap();
+        assert_eq!(vec![7; 32], cert.pub_key);
+
+        assert!(matches!(
+            verify(&encoded, &signature, &signer.verifying_key(), exp),
+            Err(Error::Expired(_))
+        ));
+        let other = SigningKey::generate(&mut rand::rngs::OsRng);
+        assert!(matches!(
+            verify(&encoded, &signature, &other.verifying_key(), now),
+            Err(Error::InvalidSignature(_))
+        ));
+        assert!(matches!(
+            verify(&encoded, &signature[1..], &signer.verifying_key(), now),
+            Err(Error::InvalidSignature(_))
+        ));
+    }
+}
@@ -1,35 +1,31 @@
-use std::sync::Arc;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
 use std::time::{Duration, SystemTime};
 
-use axum::error_handling::HandleErrorLayer;
-use axum::extract::DefaultBodyLimit;
+use axum::extract::{DefaultBodyLimit, Path, Request};
 use axum::http::StatusCode;
+use axum::middleware::{self, Next};
 use axum::response::{IntoResponse, Response};
-use axum::BoxError;
 use axum::{extract::State, Json};
 use axum::{routing::post, Router};
 use ed25519_dalek::{Signature, Signer, SigningKey};
-use parity_scale_codec::{Compact, Decode, Encode};
+use parity_scale_codec::Encode;
 use post::config::{InitConfig, ProofConfig};
-use post::pow::randomx::PoW;
-use post::verification::Mode;
+use post::pow::PowVerifier;
+use post::verification::{self, Mode};
 use serde::{Deserialize, Serialize};
 use serde_with::{base64::Base64, serde_as};
-use tower::buffer::BufferLayer;
-use tower::limit::ConcurrencyLimitLayer;
-use tower::load_shed::error::Overloaded;
-use tower::load_shed::LoadShedLayer;
-use tower::ServiceBuilder;
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+use tower::{Layer, ServiceExt};
 use tracing::instrument;
 
+pub use crate::certificate::Certificate;
 use crate::configuration::{Limits, RandomXMode};
-use crate::time::unix_timestamp;
 
-#[derive(Debug, Deserialize, Serialize)]
-pub struct CertifyRequest {
-    pub proof: post::prove::Proof<'static>,
-    pub metadata: post::metadata::ProofMetadata,
-}
+/// The body of a certify request, the same as the proof files written
+/// by the prover CLI and the post service.
+pub type CertifyRequest = post::prove::ProofWithMetadata;
 
 #[serde_as]
 #[derive(Debug, Serialize, Deserialize)]
@@ -45,19 +41,36 @@ pub struct CertifyResponse {
     pub pub_key: Vec<u8>,
 }
 
-#[derive(Debug, Decode, Encode)]
-pub struct Certificate {
-    // ID of the node being certified
-    pub pub_key: Vec<u8>,
-    /// Unix timestamp
-    pub expiration: Option<Compact<u64>>,
+#[instrument(skip(networks))]
+async fn certify_in_network(
+    State(networks): State<Arc<HashMap<String, Arc<ReloadableCertifier>>>>,
+    Path(network): Path<String>,
+    Json(req): Json<CertifyRequest>,
+) -> Result<Json<CertifyResponse>, (StatusCode, String)> {
+    let Some(state) = networks.get(&network) else {
+        return Err(unknown_network(&network));
+    };
+    certify(State(state.clone()), Json(req)).await
+}
+
+pub(crate) fn unknown_network(network: &str) -> (StatusCode, String) {
+    (StatusCode::NOT_FOUND, format!("unknown network: {network}"))
 }
 
 #[instrument(skip(state))]
 async fn certify(
-    State(state): State<Arc<Certifier>>,
+    State(state): State<Arc<ReloadableCertifier>>,
     Json(req): Json<CertifyRequest>,
 ) -> Result<Json<CertifyResponse>, (StatusCode, String)> {
+    // Finished with the current options, even if they are reloaded in the meantime.
+    certify_with(state.current(), req).await.map(Json)
+}
+
+/// Verify the proof and create a signed certificate.
+pub(crate) async fn certify_with(
+    state: Arc<Certifier>,
+    req: CertifyRequest,
+) -> Result<CertifyResponse, (StatusCode, String)> {
     tracing::debug!("certifying");
 
     let s = state.clone();
@@ -78,11 +91,17 @@ async fn certify(
                 signature: result.1.to_vec(),
                 pub_key: state.signer.verifying_key().to_bytes().to_vec(),
             };
-            Ok(Json(response))
+            Ok(response)
         }
-        Err(e) => {
-            return Err((StatusCode::FORBIDDEN, format!("invalid proof: {e:?}")));
+        // Not a verdict on the proof (the certifier may be overloaded), the client can retry.
+        Err(verification::Error::Timeout(timeout)) => {
+            tracing::warn!("proof verification timed out after {timeout:?}");
+            Err((
+                StatusCode::SERVICE_UNAVAILABLE,
+                format!("proof verification timed out after {timeout:?}"),
+            ))
         }
+        Err(e) => Err((StatusCode::FORBIDDEN, format!("invalid proof: {e:?}"))),
     }
 }
 
@@ -92,13 +111,24 @@ trait Verifier {
         &self,
         proof: &post::prove::Proof<'static>,
         metadata: &post::metadata::ProofMetadata,
-    ) -> Result<(), String>;
+    ) -> Result<(), verification::Error>;
 }
 
 struct PostVerifier {
     verifier: post::verification::Verifier,
     cfg: ProofConfig,
     init_cfg: InitConfig,
+    // Number of randomly selected indices to verify, all if not set.
+    subset: Option<usize>,
+}
+
+impl PostVerifier {
+    fn mode<'a>(&self, seed: &'a [u8]) -> Mode<'a> {
+        match self.subset {
+            Some(k3) => Mode::Subset { k3, seed },
+            None => Mode::All,
+        }
+    }
 }
 
 impl Verifier for PostVerifier {
@@ -106,14 +136,15 @@ impl Verifier for PostVerifier {
         &self,
         proof: &post::prove::Proof<'_>,
         metadata: &post::metadata::ProofMetadata,
-    ) -> Result<(), String> {
+    ) -> Result<(), verification::Error> {
+        // A new seed for every request, so that the verified indices can't be predicted.
+        let seed: [u8; 32] = rand::random();
         self.verifier
-            .verify(proof, metadata, &self.cfg, &self.init_cfg, Mode::All)
-            .map_err(|e| format!("{e:?}"))
+            .verify(proof, metadata, &self.cfg, &self.init_cfg, self.mode(&seed))
     }
 }
 
-struct Certifier {
+pub(crate) struct Certifier {
     verifier: Arc<dyn Verifier + Send + Sync>,
     signer: SigningKey,
     expiry: Option<Duration>,
@@ -124,7 +155,7 @@ impl Certifier {
         &self,
         proof: &post::prove::Proof<'static>,
         metadata: &post::metadata::ProofMetadata,
-    ) -> Result<(Vec<u8>, Signature), String> {
+    ) -> Result<(Vec<u8>, Signature), verification::Error> {
         self.verifier.verify(proof, metadata)?;
 
         let cert = self.create_certificate(&metadata.node_id);
@@ -135,64 +166,313 @@ impl Certifier {
     }
 
     fn create_certificate(&self, id: &[u8; 32]) -> Certificate {
-        let expiration = self
-            .expiry
-            .map(|exp| unix_timestamp(SystemTime::now() + exp));
-        Certificate {
-            pub_key: id.to_vec(),
-            expiration: expiration.map(Compact),
-        }
+        Certificate::new(id, self.expiry.map(|exp| SystemTime::now() + exp))
     }
 }
 
+/// Create the certifier service.
 pub fn new(
     cfg: ProofConfig,
     init_cfg: InitConfig,
     signer: SigningKey,
-    randomx_mode: RandomXMode,
-    expiry: Option<Duration>,
+    pow_version: post::pow::Version,
+    options: Options,
 ) -> Router {
-    let verifier = Arc::new(PostVerifier {
-        verifier: post::verification::Verifier::new(Box::new(
-            PoW::new(randomx_mode.into()).expect("creating RandomX PoW verifier"),
-        )),
+    let network = Network {
         cfg,
         init_cfg,
-    });
-    let certifier = Certifier {
-        verifier,
         signer,
-        expiry,
+        pow_version,
+        randomx_cache_key: post::pow::randomx::DEFAULT_CACHE_KEY.to_vec(),
     };
+    with_network(network, options)
+}
+
+/// Create the certifier service of a single network, served on `/certify`.
+pub fn with_network(network: Network, options: Options) -> Router {
+    Reloader::default().with_network(network, options)
+}
+
+/// Parameters of a network served by the certifier.
+pub struct Network {
+    pub cfg: ProofConfig,
+    pub init_cfg: InitConfig,
+    /// The key signing the certificates in this network.
+    pub signer: SigningKey,
+    pub pow_version: post::pow::Version,
+    /// See [post::pow::randomx::cache_key].
+    pub randomx_cache_key: Vec<u8>,
+}
+
+/// Settings shared by all networks served by the certifier.
+#[derive(Debug, Clone, Default)]
+pub struct Options {
+    pub randomx_mode: RandomXMode,
+    /// How long the certificates are valid, they never expire if not set.
+    pub expiry: Option<Duration>,
+    /// Verifications taking longer are aborted (and the request fails as unavailable).
+    pub verification_timeout: Option<Duration>,
+    /// If set, only that many randomly selected indices of every proof are verified
+    /// (instead of all of them).
+    pub verify_subset: Option<usize>,
+    /// Shared by the verifiers of all networks, to bound their CPU usage together.
+    pub thread_pool: Option<Arc<rayon::ThreadPool>>,
+}
+
+type SharedPowVerifier = Arc<dyn PowVerifier + Send + Sync>;
+
+impl Network {
+    fn pow_verifier(&self, randomx_mode: RandomXMode) -> SharedPowVerifier {
+        post::pow::new_verifier(
+            self.pow_version,
+            randomx_mode.into(),
+            &self.randomx_cache_key,
+        )
+        .expect("creating PoW verifier")
+        .into()
+    }
+
+    fn certifier(&self, pow_verifier: SharedPowVerifier, options: &Options) -> Certifier {
+        let mut verifier = verification::Verifier::new(Box::new(pow_verifier));
+        if let Some(timeout) = options.verification_timeout {
+            verifier = verifier.with_timeout(timeout);
+        }
+        if let Some(pool) = &options.thread_pool {
+            verifier = verifier.with_thread_pool(pool.clone());
+        }
+        Certifier {
+            verifier: Arc::new(PostVerifier {
+                verifier,
+                cfg: self.cfg,
+                init_cfg: self.init_cfg,
+                subset: options.verify_subset,
+            }),
+            signer: self.signer.clone(),
+            expiry: options.expiry,
+        }
+    }
+}
+
+/// The certifier of a network, recreated when the options are reloaded.
+struct ReloadableCertifier {
+    network: Network,
+    /// The PoW verifier, kept while the RandomX mode doesn't change
+    /// (creating one in the fast mode takes long).
+    pow_verifier: Mutex<(RandomXMode, SharedPowVerifier)>,
+    certifier: RwLock<Arc<Certifier>>,
+}
+
+impl ReloadableCertifier {
+    fn new(network: Network, options: &Options) -> Self {
+        let pow_verifier = network.pow_verifier(options.randomx_mode);
+        let certifier = network.certifier(pow_verifier.clone(), options);
+        Self {
+            network,
+            pow_verifier: Mutex::new((options.randomx_mode, pow_verifier)),
+            certifier: RwLock::new(Arc::new(certifier)),
+        }
+    }
+
+    fn current(&self) -> Arc<Certifier> {
+        self.certifier.read().unwrap().clone()
+    }
+
+    fn reload(&self, options: &Options) {
+        let mut pow_verifier = self.pow_verifier.lock().unwrap();
+        if pow_verifier.0 != options.randomx_mode {
+            *pow_verifier = (
+                options.randomx_mode,
+                self.network.pow_verifier(options.randomx_mode),
+            );
+        }
+        let certifier = self.network.certifier(pow_verifier.1.clone(), options);
+        *self.certifier.write().unwrap() = Arc::new(certifier);
+    }
+}
+
+/// The certifiers created by a [Reloader], `None` is the default network.
+type Certifiers = HashMap<Option<String>, Arc<ReloadableCertifier>>;
+
+/// Creates the certifier services and changes their [Options] while they serve requests.
+///
+/// The requests in progress are finished with the previous options.
+#[derive(Clone, Default)]
+pub struct Reloader {
+    certifiers: Arc<RwLock<Certifiers>>,
+}
+
+impl Reloader {
+    fn add(
+        &self,
+        id: Option<String>,
+        network: Network,
+        options: &Options,
+    ) -> Arc<ReloadableCertifier> {
+        let certifier = Arc::new(ReloadableCertifier::new(network, options));
+        self.certifiers
+            .write()
+            .unwrap()
+            .insert(id, certifier.clone());
+        certifier
+    }
+
+    /// See [with_network].
+    pub fn with_network(&self, network: Network, options: Options) -> Router {
+        Router::new()
+            .route("/certify", post(certify))
+            .with_state(self.add(None, network, &options))
+    }
+
+    /// See [with_networks].
+    pub fn with_networks(&self, networks: HashMap<String, Network>, options: Options) -> Router {
+        let certifiers = networks
+            .into_iter()
+            .map(|(id, network)| (id.clone(), self.add(Some(id), network, &options)))
+            .collect::<HashMap<_, _>>();
+        Router::new()
+            .route("/certify/{network}", post(certify_in_network))
+            .with_state(Arc::new(certifiers))
+    }
+
+    /// The current certifier of the `network` (the default one if `None`).
+    pub(crate) fn certifier(&self, network: Option<&str>) -> Option<Arc<Certifier>> {
+        let certifiers = self.certifiers.read().unwrap();
+        certifiers
+            .get(&network.map(str::to_string))
+            .map(|certifier| certifier.current())
+    }
+
+    /// Change the options of all certifiers created by this reloader.
+    ///
+    /// It blocks while new PoW verifiers are created if the RandomX mode changed.
+    pub fn reload(&self, options: &Options) {
+        let certifiers: Vec<_> = self.certifiers.read().unwrap().values().cloned().collect();
+        for certifier in certifiers {
+            certifier.reload(options);
+        }
+    }
+}
+
+/// Create the certifier service for several networks, each on `/certify/{network}`.
+///
+/// It can be merged with the router of the default network created with [new].
+pub fn with_networks(networks: HashMap<String, Network>, options: Options) -> Router {
+    Reloader::default().with_networks(networks, options)
+}
+
+/// Limits of the requests, which can be changed while serving them.
+pub struct Limiter {
+    limits: RwLock<Limits>,
+    permits: Arc<Semaphore>,
+    pending: AtomicUsize,
+}
+
+impl Limiter {
+    pub fn new(limits: Limits) -> Self {
+        Self {
+            permits: Arc::new(Semaphore::new(limits.max_concurrent_requests)),
+            limits: RwLock::new(limits),
+            pending: AtomicUsize::new(0),
+        }
+    }
+
+    pub fn limits(&self) -> Limits {
+        self.limits.read().unwrap().clone()
+    }
 
-    Router::new()
-        .route("/certify", post(certify))
-        .with_state(Arc::new(certifier))
+    /// Change the limits. The requests in progress are not interrupted,
+    /// a lower `max_concurrent_requests` takes effect as they finish.
+    pub fn set_limits(&self, limits: Limits) {
+        let mut current = self.limits.write().unwrap();
+        let (old, new) = (
+            current.max_concurrent_requests,
+            limits.max_concurrent_requests,
+        );
+        if new > old {
+            self.permits.add_permits(new - old);
+        } else if new < old {
+            let surplus = old - new;
+            let forgotten = self.permits.forget_permits(surplus);
+            if forgotten < surplus {
+                // Take the rest as the requests in progress release them.
+                let permits = self.permits.clone();
+                tokio::spawn(async move {
+                    if let Ok(permits) = permits
+                        .acquire_many_owned((surplus - forgotten) as u32)
+                        .await
+                    {
+                        permits.forget();
+                    }
+                });
+            }
+        }
+        *current = limits;
+    }
+
+    /// Wait for a turn to process a request, `None` if too many requests are pending already.
+    pub(crate) async fn admit(&self) -> Option<OwnedSemaphorePermit> {
+        if let Ok(permit) = self.permits.clone().try_acquire_owned() {
+            return Some(permit);
+        }
+        let max_pending = self.limits.read().unwrap().max_pending_requests;
+        let _pending = Pending::enter(&self.pending, max_pending)?;
+        // The semaphore is never closed.
+        self.permits.clone().acquire_owned().await.ok()
+    }
+}
+
+/// Decrements the number of pending requests when dropped.
+struct Pending<'a>(&'a AtomicUsize);
+
+impl<'a> Pending<'a> {
+    /// Count a pending request, unless there are `max` already.
+    fn enter(pending: &'a AtomicUsize, max: usize) -> Option<Self> {
+        pending
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |n| {
+                (n < max).then_some(n + 1)
+            })
+            .ok()
+            .map(|_| Self(pending))
+    }
+}
+
+impl Drop for Pending<'_> {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
+    }
 }
 
 pub trait RouterLimiter {
     fn apply_limits(self, limits: Limits) -> Self;
+
+    /// Apply the limits of the `limiter`, which can be changed later.
+    fn apply_limiter(self, limiter: Arc<Limiter>) -> Self;
 }
 
 impl RouterLimiter for Router {
     fn apply_limits(self, limits: Limits) -> Self {
-        self.layer(
-            ServiceBuilder::new()
-                .layer(DefaultBodyLimit::max(limits.max_body_size))
-                .layer(HandleErrorLayer::new(handle_error))
-                .layer(LoadShedLayer::new())
-                .layer(BufferLayer::new(limits.max_pending_requests))
-                .layer(ConcurrencyLimitLayer::new(limits.max_concurrent_requests))
-                .into_inner(),
-        )
+        self.apply_limiter(Arc::new(Limiter::new(limits)))
+    }
+
+    fn apply_limiter(self, limiter: Arc<Limiter>) -> Self {
+        self.layer(middleware::from_fn_with_state(limiter, limit))
     }
 }
 
-async fn handle_error(error: BoxError) -> Response {
-    if error.is::<Overloaded>() {
-        StatusCode::TOO_MANY_REQUESTS.into_response()
-    } else {
-        StatusCode::INTERNAL_SERVER_ERROR.into_response()
+/// Process up to `max_concurrent_requests` in parallel, queue up to `max_pending_requests`
+/// and reject the rest with 429 TOO_MANY_REQUESTS.
+async fn limit(State(limiter): State<Arc<Limiter>>, request: Request, next: Next) -> Response {
+    let limits = limiter.limits();
+    let Some(_permit) = limiter.admit().await else {
+        return StatusCode::TOO_MANY_REQUESTS.into_response();
+    };
+    match DefaultBodyLimit::max(limits.max_body_size)
+        .layer(next)
+        .oneshot(request)
+        .await
+    {
+        Ok(response) => response,
+        Err(never) => match never {},
     }
 }
 
@@ -205,18 +485,24 @@ mod tests {
 
     use crate::{certifier::RouterLimiter, configuration::Limits, time::unix_timestamp};
 
-    use super::{Certificate, Certifier, MockVerifier};
-    use axum::{body::Bytes, routing::post, Router};
+    use super::{Certificate, Certifier, Limiter, MockVerifier, PostVerifier};
+    use axum::{body::Bytes, http::StatusCode, routing::post, Router};
     use axum_test::TestServer;
     use ed25519_dalek::SigningKey;
-    use parity_scale_codec::Decode;
-    use post::{metadata::ProofMetadata, prove::Proof};
+    use post::{
+        config::{InitConfig, ProofConfig, ScryptParams},
+        difficulty,
+        metadata::ProofMetadata,
+        prove::Proof,
+        verification::{Error, Mode},
+    };
+    use tower::ServiceExt;
     #[test]
     fn certify_invalid_post() {
         let mut verifier = MockVerifier::new();
         verifier
             .expect_verify()
-            .returning(|_, _| Err("invalid".to_string()));
+            .returning(|_, _| Err(Error::InvalidDifficulty(difficulty::Error::ZeroLabels)));
 
         let certifier = Certifier {
             verifier: Arc::new(verifier),
@@ -274,8 +560,7 @@ mod tests {
             .verify(&encoded, &signature)
             .expect("signature should be valid");
 
-        let cert = Certificate::decode(&mut encoded.as_slice())
-            .expect("decoding certificate should succeed");
+        let cert = Certificate::from_bytes(&encoded).expect("decoding certificate should succeed");
         assert!(cert.expiration.is_none());
     }
 
@@ -296,6 +581,64 @@ mod tests {
         assert!(expiration <= unix_timestamp(SystemTime::now() + expiry));
     }
 
+    #[test]
+    fn verifying_subset() {
+        let verifier = |subset| PostVerifier {
+            verifier: post::verification::Verifier::new(
+                Box::new(post::pow::MockPowVerifier::new()),
+            ),
+            cfg: ProofConfig {
+                k1: 20,
+                k2: 10,
+                pow_difficulty: [0xFF; 32],
+            },
+            init_cfg: InitConfig {
+                min_num_units: 1,
+                max_num_units: 1000,
+                labels_per_unit: 200,
+                scrypt: ScryptParams::new(2, 1, 1),
+            },
+            subset,
+        };
+        assert!(matches!(verifier(None).mode(&[1; 32]), Mode::All));
+        assert!(matches!(
+            verifier(Some(3)).mode(&[1; 32]),
+            Mode::Subset {
+                k3: 3,
+                seed: &[1, ..]
+            }
+        ));
+    }
+
+    #[tokio::test]
+    async fn verification_timeout_is_not_rejection() {
+        let mut verifier = MockVerifier::new();
+        verifier
+            .expect_verify()
+            .returning(|_, _| Err(Error::Timeout(Duration::from_secs(10))));
+        let certifier = Arc::new(Certifier {
+            verifier: Arc::new(verifier),
+            signer: SigningKey::generate(&mut rand::rngs::OsRng),
+            expiry: None,
+        });
+        let req = post::prove::ProofWithMetadata {
+            proof: Proof {
+                nonce: 0,
+                indices: std::borrow::Cow::Owned(vec![1, 2, 3]),
+                pow: 0,
+            },
+            metadata: ProofMetadata {
+                node_id: [7; 32],
+                commitment_atx_id: [0u8; 32],
+                challenge: [0; 32],
+                num_units: 1,
+            },
+        };
+
+        let (status, _) = super::certify_with(certifier, req).await.unwrap_err();
+        assert_eq!(StatusCode::SERVICE_UNAVAILABLE, status);
+    }
+
     #[tokio::test]
     async fn limit_max_body_size() {
         let my_app = Router::new()
@@ -311,4 +654,57 @@ mod tests {
         let response = server.post("/").text("i'm a very long text").await;
         assert_eq!(response.status_code(), 413);
     }
+
+    #[tokio::test]
+    async fn changing_limits() {
+        let release = Arc::new(tokio::sync::Semaphore::new(0));
+        let r = release.clone();
+        let limiter = Arc::new(Limiter::new(Limits {
+            max_concurrent_requests: 1,
+            max_pending_requests: 0,
+            max_body_size: 100,
+        }));
+        let my_app = Router::new()
+            .route(
+                "/",
+                post(move || async move {
+                    r.acquire().await.unwrap().forget();
+                }),
+            )
+            .apply_limiter(limiter.clone());
+        let request = move || {
+            let request = axum::http::Request::post("/")
+                .body(axum::body::Body::empty())
+                .unwrap();
+            let response = my_app.clone().oneshot(request);
+            async move { response.await.unwrap().status() }
+        };
+
+        let in_progress = tokio::spawn(request());
+        while limiter.permits.available_permits() > 0 {
+            tokio::task::yield_now().await;
+        }
+        assert_eq!(StatusCode::TOO_MANY_REQUESTS, request().await);
+
+        // A pending request is queued now.
+        limiter.set_limits(Limits {
+            max_pending_requests: 1,
+            ..limiter.limits()
+        });
+        let queued = tokio::spawn(request());
+        release.add_permits(2);
+        assert_eq!(StatusCode::OK, in_progress.await.unwrap());
+        assert_eq!(StatusCode::OK, queued.await.unwrap());
+
+        // The free permits are taken right away when lowering the concurrency.
+        limiter.set_limits(Limits {
+            max_concurrent_requests: 2,
+            ..limiter.limits()
+        });
+        limiter.set_limits(Limits {
+            max_concurrent_requests: 0,
+            ..limiter.limits()
+        });
+        assert_eq!(0, limiter.permits.available_permits());
+    }
 }
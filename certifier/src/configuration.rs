@@ -1,7 +1,7 @@
-use std::{path::Path, time::Duration};
+use std::{collections::HashMap, path::Path, time::Duration};
 
 use ed25519_dalek::SecretKey;
-use post::pow::randomx::RandomXFlag;
+use post::{config::NetworkPreset, pow::randomx::RandomXFlag};
 use serde_with::{base64::Base64, serde_as};
 use tracing::info;
 
@@ -9,7 +9,7 @@ use tracing::info;
 ///
 /// They are interchangeable as they give the same results but have different
 /// purpose and memory requirements.
-#[derive(Debug, Default, Copy, Clone, serde::Deserialize)]
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq, serde::Deserialize)]
 pub enum RandomXMode {
     /// Fast mode for proving. Requires 2080 MiB of memory.
     Fast,
@@ -45,12 +45,25 @@ pub struct Config {
     /// The base64-encoded secret key used to sign the proofs.
     /// It's 256-bit key as defined in [RFC8032 § 5.1.5].
     pub signing_key: SecretKey,
+    /// Network parameters of a known network (`mainnet` or `testnet`).
+    /// They are the defaults of `post_cfg` and `init_cfg`, which then can be omitted.
+    #[serde(default)]
+    pub preset: NetworkPreset,
     pub post_cfg: post::config::ProofConfig,
     pub init_cfg: post::config::InitConfig,
 
     #[serde(default)]
     pub randomx_mode: RandomXMode,
 
+    /// The PoW algorithm (network parameter).
+    #[serde(default)]
+    pub pow_version: post::pow::Version,
+
+    /// ID of the network separating its RandomX PoWs from other networks (network parameter).
+    /// Mainnet has none.
+    #[serde(default)]
+    pub randomx_network_id: Option<String>,
+
     #[serde(
         default,
         deserialize_with = "duration_str::deserialize_option_duration"
@@ -58,9 +71,57 @@ pub struct Config {
     /// The time after which the certificates expire.
     pub certificate_expiration: Option<Duration>,
 
+    #[serde(
+        default,
+        deserialize_with = "duration_str::deserialize_option_duration"
+    )]
+    /// The maximum time a single proof verification may take.
+    /// Verification is not limited if not configured.
+    pub verification_timeout: Option<Duration>,
+
+    /// Verify only this many randomly selected indices (k3) of every proof instead of all k2.
+    /// A new random selection is made for every request.
+    /// Must be in `1..=k2`. All indices are verified if not configured.
+    #[serde(default)]
+    pub verify_subset: Option<usize>,
+
+    /// Verify the labels of proofs in parallel on a pool of this many threads,
+    /// shared by all requests (and networks). Bounds the CPU taken by the verification.
+    /// Every request is verified on its own thread if not configured.
+    #[serde(default)]
+    pub verification_threads: Option<usize>,
+
     /// Address to expose metrics on.
     /// Metrics are disabled if not configured.
     pub metrics: Option<std::net::SocketAddr>,
+
+    /// Address to serve the gRPC API on (see [crate::grpc]).
+    /// Only the HTTP API is served if not configured.
+    #[serde(default)]
+    pub grpc_listen: Option<std::net::SocketAddr>,
+
+    /// Additional networks, keyed by their ID. Each is served on `/certify/{ID}`,
+    /// while the network configured above is served on `/certify`.
+    #[serde(default)]
+    pub networks: HashMap<String, NetworkConfig>,
+}
+
+/// Parameters of an additional network served by the certifier.
+#[serde_as]
+#[derive(serde::Deserialize, Clone, PartialEq)]
+pub struct NetworkConfig {
+    #[serde_as(as = "Base64")]
+    /// The base64-encoded secret key used to sign the proofs in this network.
+    pub signing_key: SecretKey,
+    /// Network parameters of a known network, the defaults of `post_cfg` and `init_cfg`.
+    #[serde(default)]
+    pub preset: NetworkPreset,
+    pub post_cfg: post::config::ProofConfig,
+    pub init_cfg: post::config::InitConfig,
+    #[serde(default)]
+    pub pow_version: post::pow::Version,
+    #[serde(default)]
+    pub randomx_network_id: Option<String>,
 }
 
 #[derive(Debug, serde::Deserialize, Clone)]
@@ -83,13 +144,281 @@ fn default_max_body() -> usize {
     1024
 }
 
+impl Config {
+    /// The settings changed in the `reloaded` config that are applied only after a restart.
+    ///
+    /// Only `limits`, `randomx_mode`, `certificate_expiration`, `verification_timeout`
+    /// and `verify_subset` are reloaded, all the other settings are compared.
+    pub fn restart_required(&self, reloaded: &Config) -> Vec<&'static str> {
+        [
+            ("listen", self.listen != reloaded.listen),
+            ("signing_key", self.signing_key != reloaded.signing_key),
+            ("post_cfg", self.post_cfg != reloaded.post_cfg),
+            ("init_cfg", self.init_cfg != reloaded.init_cfg),
+            ("pow_version", self.pow_version != reloaded.pow_version),
+            (
+                "randomx_network_id",
+                self.randomx_network_id != reloaded.randomx_network_id,
+            ),
+            ("metrics", self.metrics != reloaded.metrics),
+            ("grpc_listen", self.grpc_listen != reloaded.grpc_listen),
+            (
+                "verification_threads",
+                self.verification_threads != reloaded.verification_threads,
+            ),
+            ("networks", self.networks != reloaded.networks),
+        ]
+        .into_iter()
+        .filter_map(|(name, changed)| changed.then_some(name))
+        .collect()
+    }
+}
+
 pub fn get_configuration(config_path: &Path) -> Result<Config, config::ConfigError> {
     info!("loading configuration from {config_path:?}");
 
-    let config = config::Config::builder()
+    let sources = config::Config::builder()
         .add_source(config::File::from(config_path).required(true))
-        .add_source(config::Environment::with_prefix("CERTIFIER").try_parsing(true))
-        .build()?;
+        .add_source(config::Environment::with_prefix("CERTIFIER").try_parsing(true));
+
+    let config = sources.build_cloned()?;
+    let networks = match config.get::<HashMap<String, config::Value>>("networks") {
+        Ok(networks) => networks.into_keys().collect(),
+        Err(config::ConfigError::NotFound(_)) => Vec::new(),
+        Err(e) => return Err(e),
+    };
+    let mut builder = set_preset_defaults(sources, &config, None)?;
+    for network in networks {
+        builder = set_preset_defaults(builder, &config, Some(&network))?;
+    }
+
+    builder.build()?.try_deserialize()
+}
+
+type ConfigBuilder = config::ConfigBuilder<config::builder::DefaultState>;
+
+/// Use the parameters of the preset selected in the config of the `network`
+/// (the top-level one if `None`) as its defaults.
+fn set_preset_defaults(
+    mut builder: ConfigBuilder,
+    config: &config::Config,
+    network: Option<&str>,
+) -> Result<ConfigBuilder, config::ConfigError> {
+    let prefix = network.map_or(String::new(), |id| format!("networks.{id}."));
+    let preset = match config.get::<NetworkPreset>(&format!("{prefix}preset")) {
+        Ok(preset) => preset,
+        Err(config::ConfigError::NotFound(_)) => NetworkPreset::Custom,
+        Err(e) => return Err(e),
+    };
+    if let Some(cfg) = preset.proof_config() {
+        match network {
+            Some(id) => info!("using network parameters of {preset} in network {id}"),
+            None => info!("using network parameters of {preset}"),
+        }
+        builder = builder
+            .set_default(format!("{prefix}post_cfg.k1"), cfg.k1)?
+            .set_default(format!("{prefix}post_cfg.k2"), cfg.k2)?
+            .set_default(
+                format!("{prefix}post_cfg.pow_difficulty"),
+                hex::encode(cfg.pow_difficulty),
+            )?;
+    }
+    if let Some(cfg) = preset.init_config() {
+        builder = builder
+            .set_default(format!("{prefix}init_cfg.min_num_units"), cfg.min_num_units)?
+            .set_default(format!("{prefix}init_cfg.max_num_units"), cfg.max_num_units)?
+            .set_default(
+                format!("{prefix}init_cfg.labels_per_unit"),
+                cfg.labels_per_unit,
+            )?
+            .set_default(format!("{prefix}init_cfg.scrypt.n"), cfg.scrypt.n as u64)?
+            .set_default(format!("{prefix}init_cfg.scrypt.r"), cfg.scrypt.r as u64)?
+            .set_default(format!("{prefix}init_cfg.scrypt.p"), cfg.scrypt.p as u64)?;
+    }
+    Ok(builder)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use base64::Engine;
+
+    use super::*;
+
+    #[test]
+    fn preset_provides_defaults() {
+        let mut file = tempfile::Builder::new().suffix(".yml").tempfile().unwrap();
+        write!(
+            file,
+            r#"
+listen: "127.0.0.1:8080"
+signing_key: "{}"
+preset: testnet
+init_cfg:
+  max_num_units: 8
+limits:
+  max_pending_requests: 10
+"#,
+            base64::engine::general_purpose::STANDARD.encode([0u8; 32])
+        )
+        .unwrap();
 
-    config.try_deserialize()
+        let config = get_configuration(file.path()).unwrap();
+        let expected = NetworkPreset::Testnet.init_config().unwrap();
+        assert_eq!(NetworkPreset::Testnet, config.preset);
+        assert_eq!(expected.labels_per_unit, config.init_cfg.labels_per_unit);
+        assert_eq!(expected.min_num_units, config.init_cfg.min_num_units);
+        assert_eq!(8, config.init_cfg.max_num_units);
+        assert_eq!(expected.scrypt, config.init_cfg.scrypt);
+        assert_eq!(
+            NetworkPreset::Testnet
+                .proof_config()
+                .unwrap()
+                .pow_difficulty,
+            config.post_cfg.pow_difficulty
+        );
+    }
+
+    #[test]
+    fn configuring_networks() {
+        let mut file = tempfile::Builder::new().suffix(".yml").tempfile().unwrap();
+        let key = base64::engine::general_purpose::STANDARD.encode([0u8; 32]);
+        write!(
+            file,
+            r#"
+listen: "127.0.0.1:8080"
+signing_key: "{key}"
+preset: mainnet
+limits:
+  max_pending_requests: 10
+networks:
+  testnet:
+    signing_key: "{key}"
+    preset: testnet
+    init_cfg:
+      max_num_units: 8
+  devnet:
+    signing_key: "{key}"
+    post_cfg:
+      k1: 10
+      k2: 5
+      pow_difficulty: "0fffffffffffffffffffffffffffffffffffffffffffffffffffffffffffffff"
+    init_cfg:
+      min_num_units: 1
+      max_num_units: 2
+      labels_per_unit: 100
+      scrypt:
+        n: 2
+        r: 1
+        p: 1
+"#,
+        )
+        .unwrap();
+
+        let config = get_configuration(file.path()).unwrap();
+        let mainnet = NetworkPreset::Mainnet.init_config().unwrap();
+        assert_eq!(mainnet.labels_per_unit, config.init_cfg.labels_per_unit);
+        assert_eq!(2, config.networks.len());
+
+        let testnet = &config.networks["testnet"];
+        let expected = NetworkPreset::Testnet.init_config().unwrap();
+        assert_eq!(expected.labels_per_unit, testnet.init_cfg.labels_per_unit);
+        assert_eq!(8, testnet.init_cfg.max_num_units);
+        assert_eq!(
+            NetworkPreset::Testnet.proof_config().unwrap().k2,
+            testnet.post_cfg.k2
+        );
+
+        let devnet = &config.networks["devnet"];
+        assert_eq!(5, devnet.post_cfg.k2);
+        assert_eq!(100, devnet.init_cfg.labels_per_unit);
+    }
+
+    #[test]
+    fn detecting_changes_requiring_restart() {
+        let mut file = tempfile::Builder::new().suffix(".yml").tempfile().unwrap();
+        write!(
+            file,
+            r#"
+listen: "127.0.0.1:8080"
+signing_key: "{}"
+preset: mainnet
+limits:
+  max_pending_requests: 10
+"#,
+            base64::engine::general_purpose::STANDARD.encode([0u8; 32])
+        )
+        .unwrap();
+        let config = get_configuration(file.path()).unwrap();
+
+        let mut reloaded = config.clone();
+        reloaded.limits.max_pending_requests = 20;
+        reloaded.randomx_mode = RandomXMode::Fast;
+        reloaded.certificate_expiration = Some(Duration::from_secs(60));
+        assert!(config.restart_required(&reloaded).is_empty());
+
+        reloaded.listen = "127.0.0.1:8081".parse().unwrap();
+        reloaded.signing_key = [1; 32];
+        assert_eq!(
+            vec!["listen", "signing_key"],
+            config.restart_required(&reloaded)
+        );
+
+        let mut reloaded = config.clone();
+        reloaded.post_cfg.k2 += 1;
+        reloaded.init_cfg.labels_per_unit += 1;
+        reloaded.randomx_network_id = Some("devnet".into());
+        assert_eq!(
+            vec!["post_cfg", "init_cfg", "randomx_network_id"],
+            config.restart_required(&reloaded)
+        );
+
+        // Any change of the parameters of the other networks
+        let network = NetworkConfig {
+            signing_key: [2; 32],
+            preset: NetworkPreset::Custom,
+            post_cfg: config.post_cfg,
+            init_cfg: config.init_cfg,
+            pow_version: post::pow::Version::RandomX,
+            randomx_network_id: None,
+        };
+        let mut config = config;
+        config.networks.insert("devnet".into(), network.clone());
+        let mut reloaded = config.clone();
+        reloaded
+            .networks
+            .get_mut("devnet")
+            .unwrap()
+            .randomx_network_id = Some("devnet".into());
+        assert_eq!(vec!["networks"], config.restart_required(&reloaded));
+    }
+
+    #[test]
+    fn rejecting_invalid_scrypt_params() {
+        let mut file = tempfile::Builder::new().suffix(".yml").tempfile().unwrap();
+        write!(
+            file,
+            r#"
+listen: "127.0.0.1:8080"
+signing_key: "{}"
+preset: mainnet
+init_cfg:
+  scrypt:
+    n: 1000
+    r: 1
+    p: 1
+limits:
+  max_pending_requests: 10
+"#,
+            base64::engine::general_purpose::STANDARD.encode([0u8; 32])
+        )
+        .unwrap();
+
+        let Err(err) = get_configuration(file.path()) else {
+            panic!("invalid scrypt N accepted");
+        };
+        let err = err.to_string();
+        assert!(err.contains("scrypt N must be a power of two"), "{err}");
+    }
 }
@@ -0,0 +1,172 @@
+//! The certify API over gRPC, an alternative to the JSON over HTTP endpoints.
+//!
+//! It serves the networks of the routers created by a [Reloader] (with their current options)
+//! and shares their [Limiter]. Besides certifying a single proof, it can certify a stream
+//! of proofs, i.e. of many nodes in a batch.
+
+use std::sync::Arc;
+
+use axum::http::StatusCode;
+use tokio::sync::{mpsc, Semaphore};
+use tokio_stream::wrappers::ReceiverStream;
+use tonic::{Request, Response, Status, Streaming};
+
+use crate::certificate::Certificate;
+use crate::certifier::{certify_with, unknown_network, Limiter, Reloader};
+use certifier_v1::certifier_service_server::{CertifierService, CertifierServiceServer};
+use certifier_v1::{certify_result, CertifyRequest, CertifyResponse, CertifyResult};
+
+pub mod certifier_v1 {
+    tonic::include_proto!("certifier.v1");
+}
+
+/// The number of requests of a stream processed in parallel.
+/// The stream isn't read further until one of them finishes.
+const STREAM_PIPELINE: usize = 16;
+
+#[derive(Clone)]
+pub struct GrpcCertifier {
+    reloader: Reloader,
+    limiter: Arc<Limiter>,
+}
+
+impl GrpcCertifier {
+    pub fn new(reloader: Reloader, limiter: Arc<Limiter>) -> Self {
+        Self { reloader, limiter }
+    }
+
+    /// The gRPC service, decoding messages of up to `max_message_size` bytes.
+    pub fn into_server(self, max_message_size: usize) -> CertifierServiceServer<Self> {
+        CertifierServiceServer::new(self).max_decoding_message_size(max_message_size)
+    }
+
+    async fn certify_one(&self, request: CertifyRequest) -> Result<CertifyResponse, Status> {
+        let network = Some(request.network.as_str()).filter(|n| !n.is_empty());
+        let certifier = self
+            .reloader
+            .certifier(network)
+            .ok_or_else(|| status(unknown_network(&request.network)))?;
+        let request = convert_request(request).map_err(Status::invalid_argument)?;
+        let Some(_permit) = self.limiter.admit().await else {
+            return Err(Status::resource_exhausted("too many requests"));
+        };
+        let response = certify_with(certifier, request).await.map_err(status)?;
+        let decoded = Certificate::from_bytes(&response.certificate)
+            .map_err(|e| Status::internal(format!("decoding certificate: {e}")))?;
+        Ok(CertifyResponse {
+            certificate: response.certificate,
+            signature: response.signature,
+            pub_key: response.pub_key,
+            decoded: Some(certifier_v1::Certificate {
+                pub_key: decoded.pub_key,
+                expiration: decoded.expiration.map(|e| e.0),
+            }),
+        })
+    }
+}
+
+fn status((code, message): (StatusCode, String)) -> Status {
+    match code {
+        StatusCode::FORBIDDEN => Status::permission_denied(message),
+        StatusCode::NOT_FOUND => Status::not_found(message),
+        StatusCode::TOO_MANY_REQUESTS => Status::resource_exhausted(message),
+        StatusCode::SERVICE_UNAVAILABLE => Status::unavailable(message),
+        _ => Status::internal(message),
+    }
+}
+
+fn bytes32(bytes: Vec<u8>, field: &str) -> Result<[u8; 32], String> {
+    bytes
+        .try_into()
+        .map_err(|_| format!("{field} must have 32 bytes"))
+}
+
+fn convert_request(request: CertifyRequest) -> Result<crate::certifier::CertifyRequest, String> {
+    let proof = request.proof.ok_or("missing proof")?;
+    let metadata = request.metadata.ok_or("missing metadata")?;
+    Ok(crate::certifier::CertifyRequest {
+        proof: post::prove::Proof {
+            nonce: proof.nonce,
+            indices: proof.indices.into(),
+            pow: proof.pow,
+        },
+        metadata: post::metadata::ProofMetadata {
+            node_id: bytes32(metadata.node_id, "node_id")?,
+            commitment_atx_id: bytes32(metadata.commitment_atx_id, "commitment_atx_id")?,
+            challenge: bytes32(metadata.challenge, "challenge")?,
+            num_units: metadata.num_units,
+        },
+    })
+}
+
+#[tonic::async_trait]
+impl CertifierService for GrpcCertifier {
+    async fn certify(
+        &self,
+        request: Request<CertifyRequest>,
+    ) -> Result<Response<CertifyResponse>, Status> {
+        self.certify_one(request.into_inner())
+            .await
+            .map(Response::new)
+    }
+
+    type CertifyStreamStream = ReceiverStream<Result<CertifyResult, Status>>;
+
+    async fn certify_stream(
+        &self,
+        request: Request<Streaming<CertifyRequest>>,
+    ) -> Result<Response<Self::CertifyStreamStream>, Status> {
+        let mut requests = request.into_inner();
+
+        // The requests are certified in parallel, the results are sent in their order.
+        // A stream can't take more than its share of the limiter, the next request is
+        // read only once fewer than STREAM_PIPELINE of them are being certified.
+        let (in_progress, mut results) = mpsc::channel(STREAM_PIPELINE);
+        let pipeline = Arc::new(Semaphore::new(STREAM_PIPELINE));
+        let service = self.clone();
+        tokio::spawn(async move {
+            loop {
+                // The semaphore is never closed.
+                let Ok(permit) = pipeline.clone().acquire_owned().await else {
+                    break;
+                };
+                let Ok(Some(request)) = requests.message().await else {
+                    break;
+                };
+                let service = service.clone();
+                let task = tokio::spawn(async move {
+                    let _permit = permit;
+                    service.certify_one(request).await
+                });
+                if in_progress.send(task).await.is_err() {
+                    break;
+                }
+            }
+        });
+
+        let (sender, receiver) = mpsc::channel(STREAM_PIPELINE);
+        tokio::spawn(async move {
+            while let Some(task) = results.recv().await {
+                let result = match task.await {
+                    Ok(Ok(response)) => certify_result::Result::Response(response),
+                    Ok(Err(status)) => certify_result::Result::Error(certifier_v1::Error {
+                        code: status.code() as i32,
+                        message: status.message().to_string(),
+                    }),
+                    Err(e) => certify_result::Result::Error(certifier_v1::Error {
+                        code: tonic::Code::Internal as i32,
+                        message: format!("certifying failed: {e}"),
+                    }),
+                };
+                let result = CertifyResult {
+                    result: Some(result),
+                };
+                if sender.send(Ok(result)).await.is_err() {
+                    break;
+                }
+            }
+        });
+
+        Ok(Response::new(ReceiverStream::new(receiver)))
+    }
+}
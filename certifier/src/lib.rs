@@ -1,3 +1,5 @@
+pub mod certificate;
 pub mod certifier;
 pub mod configuration;
+pub mod grpc;
 pub mod time;
@@ -1,13 +1,21 @@
-use std::{future::IntoFuture, path::PathBuf};
+use std::{
+    collections::HashMap,
+    future::IntoFuture,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::{Duration, SystemTime},
+};
 
 use axum::routing::get;
 use axum_prometheus::PrometheusMetricLayerBuilder;
 use base64::{engine::general_purpose, Engine as _};
-use certifier::certifier::RouterLimiter;
+use certifier::certifier::{Limiter, Options, Reloader, RouterLimiter};
+use certifier::configuration::Config;
 use clap::{arg, Parser, Subcommand};
 use ed25519_dalek::SigningKey;
+use post::pow::randomx::cache_key;
 use tokio::net::TcpListener;
-use tracing::info;
+use tracing::{error, info, warn};
 use tracing_log::LogTracer;
 use tracing_subscriber::{EnvFilter, FmtSubscriber};
 
@@ -54,6 +62,133 @@ fn generate_keys() -> Result<(), Box<dyn std::error::Error>> {
     Ok(())
 }
 
+/// How often the config file is checked for changes.
+const CONFIG_CHECK_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Check `verify_subset` against k2 of all networks.
+fn check_verify_subset(config: &Config) -> Result<(), String> {
+    let Some(k3) = config.verify_subset else {
+        return Ok(());
+    };
+    if k3 == 0 || k3 > config.post_cfg.k2 as usize {
+        return Err(format!(
+            "verify_subset must be in 1..={} (k2), got {k3}",
+            config.post_cfg.k2
+        ));
+    }
+    for (id, network) in &config.networks {
+        if k3 > network.post_cfg.k2 as usize {
+            return Err(format!(
+                "verify_subset must be in 1..={} (k2 of network {id}), got {k3}",
+                network.post_cfg.k2
+            ));
+        }
+    }
+    Ok(())
+}
+
+fn modified(path: &Path) -> Option<SystemTime> {
+    std::fs::metadata(path).and_then(|m| m.modified()).ok()
+}
+
+#[cfg(unix)]
+type Hangups = Option<tokio::signal::unix::Signal>;
+#[cfg(not(unix))]
+type Hangups = ();
+
+#[cfg(unix)]
+fn hangups() -> Hangups {
+    use tokio::signal::unix::{signal, SignalKind};
+    signal(SignalKind::hangup())
+        .inspect_err(|e| warn!("failed to listen for SIGHUP: {e}"))
+        .ok()
+}
+
+#[cfg(not(unix))]
+fn hangups() -> Hangups {}
+
+/// Resolves on the next SIGHUP, never on other systems.
+async fn hangup(hangups: &mut Hangups) {
+    #[cfg(unix)]
+    if let Some(hangups) = hangups {
+        hangups.recv().await;
+        return;
+    }
+    let _ = hangups;
+    std::future::pending().await
+}
+
+/// Reload the config on SIGHUP or when its file changes.
+///
+/// Only the settings listed in [Config::restart_required] are applied,
+/// the requests in progress are finished with the previous ones.
+async fn reload_config(
+    path: PathBuf,
+    config: Config,
+    options: Options,
+    reloader: Reloader,
+    limiter: Arc<Limiter>,
+) {
+    let mut last_modified = modified(&path);
+    let mut interval = tokio::time::interval(CONFIG_CHECK_INTERVAL);
+    let mut hangups = hangups();
+    loop {
+        tokio::select! {
+            _ = interval.tick() => {
+                let modified = modified(&path);
+                if modified == last_modified {
+                    continue;
+                }
+                info!("config file changed");
+                last_modified = modified;
+            }
+            _ = hangup(&mut hangups) => info!("received SIGHUP"),
+        }
+
+        let reloaded = match certifier::configuration::get_configuration(&path) {
+            Ok(reloaded) => reloaded,
+            Err(e) => {
+                error!("failed to reload configuration: {e}");
+                continue;
+            }
+        };
+        if let Err(e) = check_verify_subset(&reloaded) {
+            error!("failed to reload configuration: {e}");
+            continue;
+        }
+        let restart = config.restart_required(&reloaded);
+        if !restart.is_empty() {
+            warn!(
+                "changes of {} are applied only after a restart",
+                restart.join(", ")
+            );
+        }
+
+        let options = Options {
+            randomx_mode: reloaded.randomx_mode,
+            expiry: reloaded.certificate_expiration,
+            verification_timeout: reloaded.verification_timeout,
+            verify_subset: reloaded.verify_subset,
+            thread_pool: options.thread_pool.clone(),
+        };
+        let r = reloader.clone();
+        let o = options.clone();
+        if let Err(e) = tokio::task::spawn_blocking(move || r.reload(&o)).await {
+            error!("failed to reload certifiers: {e}");
+            continue;
+        }
+        limiter.set_limits(reloaded.limits.clone());
+        info!(
+            "reloaded configuration: {:?}, RandomX mode: {:?}, certificate expiration: {:?}, verification timeout: {:?}, verify subset: {:?}",
+            reloaded.limits,
+            options.randomx_mode,
+            options.expiry,
+            options.verification_timeout,
+            options.verify_subset,
+        );
+    }
+}
+
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
     let args = Cli::parse();
@@ -76,22 +211,82 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     info!("listening on: {:?}, pubkey: {}", config.listen, pubkey_b64,);
     info!("POST proof configuration: {:?}", config.post_cfg);
     info!("POST init configuration: {:?}", config.init_cfg);
+    info!("PoW version: {}", config.pow_version);
     info!("RandomX mode: {:?}", config.randomx_mode);
+    if let Some(id) = &config.randomx_network_id {
+        info!("RandomX network ID: {id}");
+    }
     info!("{:?}", config.limits);
     if let Some(expiry) = config.certificate_expiration {
         info!("generated certificates will expire after {expiry:?}");
     } else {
         info!("generated certificates won't expire");
     }
+    if let Some(timeout) = config.verification_timeout {
+        info!("proof verification will time out after {timeout:?}");
+    }
+    check_verify_subset(&config)?;
+    match config.verify_subset {
+        Some(k3) => info!(
+            "verifying {k3} random indices of {} in every proof",
+            config.post_cfg.k2
+        ),
+        None => info!("verifying all indices in every proof"),
+    }
+
+    let thread_pool = match config.verification_threads {
+        Some(threads) => {
+            info!("verifying proofs on {threads} threads");
+            let pool = rayon::ThreadPoolBuilder::new()
+                .num_threads(threads)
+                .thread_name(|i| format!("verifier-{i}"))
+                .build()?;
+            Some(Arc::new(pool))
+        }
+        None => None,
+    };
 
-    let mut app = certifier::certifier::new(
-        config.post_cfg,
-        config.init_cfg,
+    let options = certifier::certifier::Options {
+        randomx_mode: config.randomx_mode,
+        expiry: config.certificate_expiration,
+        verification_timeout: config.verification_timeout,
+        verify_subset: config.verify_subset,
+        thread_pool,
+    };
+    let network = certifier::certifier::Network {
+        cfg: config.post_cfg,
+        init_cfg: config.init_cfg,
         signer,
-        config.randomx_mode,
-        config.certificate_expiration,
-    )
-    .apply_limits(config.limits);
+        pow_version: config.pow_version,
+        randomx_cache_key: cache_key(config.randomx_network_id.as_deref()),
+    };
+    let reloader = Reloader::default();
+    let mut app = reloader.with_network(network, options.clone());
+
+    if !config.networks.is_empty() {
+        let mut networks = HashMap::new();
+        for (id, network) in config.networks.clone() {
+            let signer = SigningKey::from_bytes(&network.signing_key);
+            info!(
+                "serving network {id} on /certify/{id}, pubkey: {}, POST proof configuration: {:?}, POST init configuration: {:?}, PoW version: {}",
+                general_purpose::STANDARD.encode(signer.verifying_key().as_bytes()),
+                network.post_cfg,
+                network.init_cfg,
+                network.pow_version,
+            );
+            let network = certifier::certifier::Network {
+                cfg: network.post_cfg,
+                init_cfg: network.init_cfg,
+                signer,
+                pow_version: network.pow_version,
+                randomx_cache_key: cache_key(network.randomx_network_id.as_deref()),
+            };
+            networks.insert(id, network);
+        }
+        app = app.merge(reloader.with_networks(networks, options.clone()));
+    }
+    let limiter = Arc::new(Limiter::new(config.limits.clone()));
+    let mut app = app.apply_limiter(limiter.clone());
 
     if let Some(addr) = config.metrics {
         info!("metrics enabled on: http://{addr:?}/metrics");
@@ -106,7 +301,29 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         tokio::spawn(axum::serve(listener, metrics.into_make_service()).into_future());
     }
 
+    if let Some(addr) = config.grpc_listen {
+        info!("serving gRPC API on: {addr:?}");
+        let grpc = certifier::grpc::GrpcCertifier::new(reloader.clone(), limiter.clone())
+            .into_server(config.limits.max_body_size);
+        let listener = TcpListener::bind(addr).await?;
+        let server = tonic::transport::Server::builder()
+            .add_service(grpc)
+            .serve_with_incoming(tokio_stream::wrappers::TcpListenerStream::new(listener));
+        tokio::spawn(async move {
+            if let Err(e) = server.await {
+                error!("gRPC server failed: {e}");
+            }
+        });
+    }
+
     let listener = TcpListener::bind(config.listen).await?;
+    tokio::spawn(reload_config(
+        args.config,
+        config,
+        options,
+        reloader,
+        limiter,
+    ));
     axum::serve(listener, app.into_make_service()).await?;
     Ok(())
 }
@@ -4,7 +4,7 @@ use std::{
 };
 
 use certifier::{
-    certifier::{Certificate, CertifyRequest},
+    certifier::{Certificate, CertifyRequest, Options},
     configuration::RandomXMode,
     time::unix_timestamp,
 };
@@ -75,7 +75,16 @@ async fn test_certificate_post_proof() {
     };
     // Spawn the certifier service
     let signer = SigningKey::generate(&mut rand::rngs::OsRng);
-    let app = certifier::certifier::new(cfg, init_cfg, signer.clone(), RandomXMode::Light, None);
+    let app = certifier::certifier::new(
+        cfg,
+        init_cfg,
+        signer.clone(),
+        post::pow::Version::RandomX,
+        Options {
+            randomx_mode: RandomXMode::Light,
+            ..Default::default()
+        },
+    );
     let server = axum_test::TestServer::new(app).unwrap();
 
     let node_id = [
@@ -128,8 +137,12 @@ async fn test_certificate_post_proof_with_expiration() {
         cfg,
         init_cfg,
         signer.clone(),
-        RandomXMode::Light,
-        Some(expiry),
+        post::pow::Version::RandomX,
+        Options {
+            randomx_mode: RandomXMode::Light,
+            expiry: Some(expiry),
+            ..Default::default()
+        },
     );
     let server = axum_test::TestServer::new(app).unwrap();
 
@@ -150,3 +163,247 @@ async fn test_certificate_post_proof_with_expiration() {
     let signature = ed25519_dalek::Signature::from_slice(&cert_resp.signature).unwrap();
     assert!(signer.verify(&cert_resp.certificate, &signature).is_ok());
 }
+
+#[tokio::test]
+async fn test_certificate_verifying_subset_of_indices() {
+    let cfg = ProofConfig {
+        k1: 20,
+        k2: 10,
+        pow_difficulty: [0xFF; 32],
+    };
+    let init_cfg = InitConfig {
+        min_num_units: 1,
+        max_num_units: 1000,
+        labels_per_unit: 200,
+        scrypt: ScryptParams::new(2, 1, 1),
+    };
+    let signer = SigningKey::generate(&mut rand::rngs::OsRng);
+    let app = certifier::certifier::new(
+        cfg,
+        init_cfg,
+        signer.clone(),
+        post::pow::Version::RandomX,
+        Options {
+            randomx_mode: RandomXMode::Light,
+            verify_subset: Some(3),
+            ..Default::default()
+        },
+    );
+    let server = axum_test::TestServer::new(app).unwrap();
+
+    let (proof, metadata) = gen_proof(cfg, init_cfg, [7; 32]);
+    let req = CertifyRequest { proof, metadata };
+    let response = server.post("/certify").json(&req).await;
+    response.assert_status_ok();
+
+    // Invalid metadata is rejected regardless of the indices selected
+    let mut invalid_req = req;
+    invalid_req.metadata.num_units = 8;
+    let response = server.post("/certify").json(&invalid_req).await;
+    response.assert_status_forbidden();
+}
+
+#[tokio::test]
+async fn test_certificate_in_network() {
+    let cfg = ProofConfig {
+        k1: 20,
+        k2: 10,
+        pow_difficulty: [0xFF; 32],
+    };
+    let init_cfg = InitConfig {
+        min_num_units: 1,
+        max_num_units: 1000,
+        labels_per_unit: 200,
+        scrypt: ScryptParams::new(2, 1, 1),
+    };
+    let other_init_cfg = InitConfig {
+        labels_per_unit: 100,
+        ..init_cfg
+    };
+    let signer = SigningKey::generate(&mut rand::rngs::OsRng);
+    let networks = [
+        ("testnet", init_cfg, signer.clone()),
+        (
+            "devnet",
+            other_init_cfg,
+            SigningKey::generate(&mut rand::rngs::OsRng),
+        ),
+    ]
+    .map(|(id, init_cfg, signer)| {
+        let network = certifier::certifier::Network {
+            cfg,
+            init_cfg,
+            signer,
+            pow_version: post::pow::Version::RandomX,
+            randomx_cache_key: post::pow::randomx::DEFAULT_CACHE_KEY.to_vec(),
+        };
+        (id.to_string(), network)
+    });
+    let app = certifier::certifier::with_networks(
+        networks.into_iter().collect(),
+        certifier::certifier::Options::default(),
+    );
+    let server = axum_test::TestServer::new(app).unwrap();
+
+    let (proof, metadata) = gen_proof(cfg, init_cfg, [7; 32]);
+    let req = CertifyRequest { proof, metadata };
+    let response = server.post("/certify/testnet").json(&req).await;
+    response.assert_status_ok();
+    let cert_resp = response.json::<certifier::certifier::CertifyResponse>();
+    let signature = ed25519_dalek::Signature::from_slice(&cert_resp.signature).unwrap();
+    assert!(signer.verify(&cert_resp.certificate, &signature).is_ok());
+
+    // The proof is not valid in a network with different parameters
+    let response = server.post("/certify/devnet").json(&req).await;
+    response.assert_status_forbidden();
+
+    let response = server.post("/certify/unknown").json(&req).await;
+    response.assert_status_not_found();
+}
+
+#[tokio::test]
+async fn test_reloading_options() {
+    let cfg = ProofConfig {
+        k1: 20,
+        k2: 10,
+        pow_difficulty: [0xFF; 32],
+    };
+    let init_cfg = InitConfig {
+        min_num_units: 1,
+        max_num_units: 1000,
+        labels_per_unit: 200,
+        scrypt: ScryptParams::new(2, 1, 1),
+    };
+    let signer = SigningKey::generate(&mut rand::rngs::OsRng);
+    let network = certifier::certifier::Network {
+        cfg,
+        init_cfg,
+        signer: signer.clone(),
+        pow_version: post::pow::Version::RandomX,
+        randomx_cache_key: post::pow::randomx::DEFAULT_CACHE_KEY.to_vec(),
+    };
+    let reloader = certifier::certifier::Reloader::default();
+    let app = reloader.with_network(network, Default::default());
+    let server = axum_test::TestServer::new(app).unwrap();
+
+    let (proof, metadata) = gen_proof(cfg, init_cfg, [0u8; 32]);
+    let req = CertifyRequest { proof, metadata };
+    let certify = || async {
+        let response = server.post("/certify").json(&req).await;
+        response.assert_status_ok();
+        let cert_resp = response.json::<certifier::certifier::CertifyResponse>();
+        Certificate::decode(&mut cert_resp.certificate.as_slice()).unwrap()
+    };
+    assert!(certify().await.expiration.is_none());
+
+    let expiry = Duration::from_secs(60 * 60);
+    reloader.reload(&certifier::certifier::Options {
+        expiry: Some(expiry),
+        ..Default::default()
+    });
+    let req_time = SystemTime::now();
+    let cert = certify().await;
+    assert!(cert.expiration.unwrap().0 >= unix_timestamp(req_time + expiry));
+}
+
+#[tokio::test]
+async fn test_certify_over_grpc() {
+    use certifier::grpc::certifier_v1::{
+        certifier_service_client::CertifierServiceClient, certify_result, CertifyRequest,
+        ProofMetadata,
+    };
+
+    let cfg = ProofConfig {
+        k1: 20,
+        k2: 10,
+        pow_difficulty: [0xFF; 32],
+    };
+    let init_cfg = InitConfig {
+        min_num_units: 1,
+        max_num_units: 1000,
+        labels_per_unit: 200,
+        scrypt: ScryptParams::new(2, 1, 1),
+    };
+    let signer = SigningKey::generate(&mut rand::rngs::OsRng);
+    let network = certifier::certifier::Network {
+        cfg,
+        init_cfg,
+        signer: signer.clone(),
+        pow_version: post::pow::Version::RandomX,
+        randomx_cache_key: post::pow::randomx::DEFAULT_CACHE_KEY.to_vec(),
+    };
+    let reloader = certifier::certifier::Reloader::default();
+    let _ = reloader.with_network(network, Default::default());
+    let limiter = std::sync::Arc::new(certifier::certifier::Limiter::new(
+        certifier::configuration::Limits {
+            max_concurrent_requests: 2,
+            max_pending_requests: 10,
+            max_body_size: 1024,
+        },
+    ));
+    let grpc = certifier::grpc::GrpcCertifier::new(reloader, limiter).into_server(1024);
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let address = listener.local_addr().unwrap();
+    tokio::spawn(
+        tonic::transport::Server::builder()
+            .add_service(grpc)
+            .serve_with_incoming(tokio_stream::wrappers::TcpListenerStream::new(listener)),
+    );
+    let mut client = CertifierServiceClient::connect(format!("http://{address}"))
+        .await
+        .unwrap();
+
+    let node_id = [7u8; 32];
+    let (proof, metadata) = gen_proof(cfg, init_cfg, node_id);
+    let request = CertifyRequest {
+        network: String::new(),
+        proof: Some(certifier::grpc::certifier_v1::Proof {
+            nonce: proof.nonce,
+            indices: proof.indices.to_vec(),
+            pow: proof.pow,
+        }),
+        metadata: Some(ProofMetadata {
+            node_id: metadata.node_id.to_vec(),
+            commitment_atx_id: metadata.commitment_atx_id.to_vec(),
+            challenge: metadata.challenge.to_vec(),
+            num_units: metadata.num_units,
+        }),
+    };
+
+    let response = client.certify(request.clone()).await.unwrap().into_inner();
+    let signature = ed25519_dalek::Signature::from_slice(&response.signature).unwrap();
+    assert!(signer.verify(&response.certificate, &signature).is_ok());
+    assert_eq!(node_id.to_vec(), response.decoded.unwrap().pub_key);
+
+    let unknown = CertifyRequest {
+        network: "unknown".into(),
+        ..request.clone()
+    };
+    let status = client.certify(unknown).await.unwrap_err();
+    assert_eq!(tonic::Code::NotFound, status.code());
+
+    // A batch, the results in the order of the requests.
+    let mut invalid = request.clone();
+    invalid.metadata.as_mut().unwrap().num_units = 8;
+    let batch = vec![request.clone(), invalid, request];
+    let mut results = client
+        .certify_stream(tokio_stream::iter(batch))
+        .await
+        .unwrap()
+        .into_inner();
+    let mut codes = Vec::new();
+    while let Some(result) = results.message().await.unwrap() {
+        codes.push(match result.result.unwrap() {
+            certify_result::Result::Response(_) => tonic::Code::Ok,
+            certify_result::Result::Error(e) => tonic::Code::from(e.code),
+        });
+    }
+    assert_eq!(
+        vec![
+            tonic::Code::Ok,
+            tonic::Code::PermissionDenied,
+            tonic::Code::Ok
+        ],
+        codes
+    );
+}
@@ -10,6 +10,14 @@ fn main() {
     let mut config = Config::default();
     config.macro_expansion.bitflags = true;
     config.enumeration.prefix_with_name = true;
+    // Generating proofs needs the `parallel` feature.
+    if env::var_os("CARGO_FEATURE_PARALLEL").is_none() {
+        config.export.exclude = vec![
+            "generate_proof".to_string(),
+            "generate_proof_for_network".to_string(),
+            "generate_proof_of_initialized_units".to_string(),
+        ];
+    }
 
     cbindgen::Builder::new()
         .with_config(config)
@@ -0,0 +1,46 @@
+//! Codes of the errors of the last call.
+//!
+//! The functions returning null (or a generic failure) on error record the [ErrorCode]
+//! of the error, so that the caller can tell them apart (i.e. a full disk from invalid arguments).
+
+use std::cell::Cell;
+
+use post::{Error, ErrorCode};
+
+thread_local! {
+    static LAST_ERROR: Cell<ErrorCode> = const { Cell::new(ErrorCode::Ok) };
+}
+
+pub(crate) fn set_last_error(code: ErrorCode) {
+    LAST_ERROR.with(|last| last.set(code));
+}
+
+/// Record the result of a call as the last error (`Ok` if it succeeded).
+pub(crate) fn record<T>(result: Result<T, Error>) -> Result<T, Error> {
+    set_last_error(match &result {
+        Ok(_) => ErrorCode::Ok,
+        Err(e) => e.code(),
+    });
+    result
+}
+
+/// Get the code of the error of the last call made on the calling thread
+/// to `generate_proof`, `new_initializer`, `initialize` or `start_init_job`.
+/// Returns `Ok` if the call succeeded.
+#[no_mangle]
+pub extern "C" fn last_error_code() -> ErrorCode {
+    LAST_ERROR.with(|last| last.get())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn recording_last_error() {
+        assert!(record(Ok(())).is_ok());
+        assert_eq!(ErrorCode::Ok, last_error_code());
+        assert!(record::<()>(Err(Error::InvalidArgument("test".into()))).is_err());
+        assert_eq!(ErrorCode::InvalidArgument, last_error_code());
+    }
+}
@@ -0,0 +1,422 @@
+//! Initialization of POS data in the background.
+//!
+//! Unlike [crate::initialization::initialize], which initializes a range of labels,
+//! a job runs the complete [Initialize::initialize] flow (writing the files and metadata
+//! to the data directory, resuming an interrupted initialization). The caller polls its
+//! progress with [get_init_job_progress] and can cancel it with [cancel_init_job].
+
+use std::{
+    ffi::{c_char, CStr},
+    path::PathBuf,
+    sync::{mpsc, Arc, Mutex},
+    thread::JoinHandle,
+};
+
+use post::{
+    config::ScryptParams,
+    initialize::{CpuInitializer, InitTracker, Initialize, Tracked},
+    Error, ErrorCode,
+};
+use scrypt_ocl::{ocl::DeviceType, OpenClInitializer, ProviderId};
+
+use crate::{
+    error,
+    initialization::{provider_filter, InitializeResult, CPU_PROVIDER_ID},
+};
+
+pub enum InitJob {}
+
+struct InitJobHandle {
+    shared: Arc<Shared>,
+    thread: Option<JoinHandle<()>>,
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum InitJobStatus {
+    Running = 0,
+    Finished = 1,
+    Failed = 2,
+    Cancelled = 3,
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct InitJobProgress {
+    status: InitJobStatus,
+    /// Number of labels initialized, including the ones initialized before an interruption.
+    labels_done: u64,
+    total_labels: u64,
+    /// Index of the file being initialized.
+    current_file: u64,
+    /// Estimated time to finish in seconds, `u64::MAX` if not known yet.
+    eta_secs: u64,
+    /// Is `nonce` the index of the best VRF nonce found so far.
+    nonce_found: bool,
+    nonce: u64,
+    /// The kind of the error if the job `Failed`, `Ok` otherwise.
+    error: ErrorCode,
+}
+
+#[derive(Debug)]
+struct Shared {
+    tracker: InitTracker,
+    total_labels: u64,
+    labels_per_file: u64,
+    // The status, the best nonce and the error code when the job stops.
+    result: Mutex<Option<(InitJobStatus, Option<u64>, ErrorCode)>>,
+}
+
+impl Shared {
+    fn progress(&self) -> InitJobProgress {
+        let (status, labels_done, nonce, error) = match *self.result.lock().unwrap() {
+            Some((InitJobStatus::Finished, nonce, _)) => (
+                InitJobStatus::Finished,
+                self.total_labels,
+                nonce,
+                ErrorCode::Ok,
+            ),
+            Some((status, _, error)) => (
+                status,
+                self.tracker.labels_done(),
+                self.tracker.nonce(),
+                error,
+            ),
+            None => (
+                InitJobStatus::Running,
+                self.tracker.labels_done(),
+                self.tracker.nonce(),
+                ErrorCode::Ok,
+            ),
+        };
+        let eta_secs = match self.tracker.eta(self.total_labels) {
+            _ if status != InitJobStatus::Running => 0,
+            Some(eta) => eta.as_secs(),
+            None => u64::MAX,
+        };
+        InitJobProgress {
+            status,
+            labels_done,
+            total_labels: self.total_labels,
+            current_file: (labels_done / self.labels_per_file)
+                .min(self.total_labels.saturating_sub(1) / self.labels_per_file),
+            eta_secs,
+            nonce_found: nonce.is_some(),
+            nonce: nonce.unwrap_or_default(),
+            error,
+        }
+    }
+}
+
+/// Start initializing POS data in `datadir` in the background.
+///
+/// The parameters are the same as of [crate::initialization::new_initializer]
+/// and [Initialize::initialize]. Returns null if the initializer can't be created
+/// or the arguments are invalid, the kind of the error is available with
+/// [crate::error::last_error_code].
+/// The job must be freed with [free_init_job].
+#[allow(clippy::too_many_arguments)]
+#[no_mangle]
+pub extern "C" fn start_init_job(
+    provider_id: u32,
+    n: usize,
+    datadir: *const c_char,
+    node_id: *const u8,
+    commitment_atx_id: *const u8,
+    labels_per_unit: u64,
+    num_units: u32,
+    labels_per_file: u64,
+    vrf_difficulty: *const u8,
+) -> *mut InitJob {
+    match error::record(_start_init_job(
+        provider_id,
+        n,
+        datadir,
+        node_id,
+        commitment_atx_id,
+        labels_per_unit,
+        num_units,
+        labels_per_file,
+        vrf_difficulty,
+    )) {
+        Ok(job) => Box::into_raw(job) as _,
+        Err(e) => {
+            log::error!("error starting initialization: {e:?}");
+            std::ptr::null_mut()
+        }
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+fn _start_init_job(
+    provider_id: u32,
+    n: usize,
+    datadir: *const c_char,
+    node_id: *const u8,
+    commitment_atx_id: *const u8,
+    labels_per_unit: u64,
+    num_units: u32,
+    labels_per_file: u64,
+    vrf_difficulty: *const u8,
+) -> Result<Box<InitJobHandle>, Error> {
+    let invalid = |msg: &str| Err(Error::InvalidArgument(msg.into()));
+    if !n.is_power_of_two() {
+        return invalid("scrypt N must be a power of two");
+    }
+    if labels_per_file == 0 {
+        return invalid("labels per file must be > 0");
+    }
+    if datadir.is_null() || node_id.is_null() || commitment_atx_id.is_null() {
+        return invalid("datadir, node ID and commitment ATX ID are required");
+    }
+    let datadir = PathBuf::from(
+        unsafe { CStr::from_ptr(datadir) }
+            .to_str()
+            .map_err(|e| Error::InvalidArgument(format!("reading datadir: {e}")))?,
+    );
+    let node_id: [u8; 32] = unsafe { std::slice::from_raw_parts(node_id, 32) }
+        .try_into()
+        .unwrap();
+    let commitment_atx_id: [u8; 32] = unsafe { std::slice::from_raw_parts(commitment_atx_id, 32) }
+        .try_into()
+        .unwrap();
+    let vrf_difficulty: Option<[u8; 32]> = if vrf_difficulty.is_null() {
+        None
+    } else {
+        Some(
+            unsafe { std::slice::from_raw_parts(vrf_difficulty, 32) }
+                .try_into()
+                .unwrap(),
+        )
+    };
+
+    let shared = Arc::new(Shared {
+        tracker: InitTracker::default(),
+        total_labels: labels_per_unit * num_units as u64,
+        labels_per_file,
+        result: Mutex::new(None),
+    });
+
+    // The initializer is created on the job's thread, the OpenCL one can't be sent between threads.
+    let (created_tx, created_rx) = mpsc::sync_channel(1);
+    let job_shared = shared.clone();
+    let provider_filter = provider_filter();
+    let thread = std::thread::spawn(move || {
+        let mut inner: Box<dyn Initialize> = match provider_id {
+            CPU_PROVIDER_ID => Box::new(CpuInitializer::new(ScryptParams::new(n, 1, 1))),
+            id => match OpenClInitializer::new(
+                Some(ProviderId(id)),
+                n,
+                Some(DeviceType::GPU),
+                &provider_filter,
+            ) {
+                Ok(initializer) => Box::new(initializer),
+                Err(e) => {
+                    _ = created_tx.send(Err(Error::Initializer(Box::new(e))));
+                    return;
+                }
+            },
+        };
+        _ = created_tx.send(Ok(()));
+
+        let result = Tracked::new(inner.as_mut(), &job_shared.tracker).initialize(
+            &datadir,
+            &node_id,
+            &commitment_atx_id,
+            labels_per_unit,
+            num_units,
+            labels_per_file,
+            vrf_difficulty,
+        );
+        let result = match result {
+            Ok(metadata) => {
+                log::info!("initialization of {} finished", datadir.display());
+                (InitJobStatus::Finished, metadata.nonce, ErrorCode::Ok)
+            }
+            Err(_) if job_shared.tracker.is_stopped() => {
+                log::info!("initialization of {} cancelled", datadir.display());
+                (InitJobStatus::Cancelled, None, ErrorCode::Ok)
+            }
+            Err(e) => {
+                log::error!("initialization of {} failed: {e:?}", datadir.display());
+                (InitJobStatus::Failed, None, e.code())
+            }
+        };
+        *job_shared.result.lock().unwrap() = Some(result);
+    });
+
+    match created_rx.recv() {
+        Ok(Ok(())) => Ok(Box::new(InitJobHandle {
+            shared,
+            thread: Some(thread),
+        })),
+        Ok(Err(e)) => Err(e),
+        Err(_) => Err(Error::Initializer("initialization thread panicked".into())),
+    }
+}
+
+/// Get the progress of the initialization job.
+#[no_mangle]
+pub extern "C" fn get_init_job_progress(
+    job: *const InitJob,
+    out: *mut InitJobProgress,
+) -> InitializeResult {
+    if job.is_null() || out.is_null() {
+        log::error!("job and out must not be null");
+        return InitializeResult::InvalidArgument;
+    }
+    let job = unsafe { &*(job as *const InitJobHandle) };
+    unsafe { *out = job.shared.progress() };
+    InitializeResult::Ok
+}
+
+/// Request cancelling the initialization job. Doesn't wait for the job to stop,
+/// its status changes to `Cancelled` when it stops.
+///
+/// The files initialized completely are kept, initializing the same data directory
+/// again resumes the initialization.
+#[no_mangle]
+pub extern "C" fn cancel_init_job(job: *const InitJob) {
+    if job.is_null() {
+        return;
+    }
+    let job = unsafe { &*(job as *const InitJobHandle) };
+    job.shared.tracker.stop();
+}
+
+/// Free the initialization job. Cancels the job if it's still running
+/// and waits for it to stop.
+#[no_mangle]
+pub extern "C" fn free_init_job(job: *mut InitJob) {
+    if job.is_null() {
+        return;
+    }
+    let mut job = unsafe { Box::from_raw(job as *mut InitJobHandle) };
+    job.shared.tracker.stop();
+    if let Some(thread) = job.thread.take() {
+        _ = thread.join();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{ffi::CString, ptr::null, time::Duration};
+
+    use post::metadata;
+
+    use super::*;
+
+    fn wait_for_job(job: *const InitJob) -> InitJobProgress {
+        let mut progress = InitJobProgress {
+            status: InitJobStatus::Running,
+            labels_done: 0,
+            total_labels: 0,
+            current_file: 0,
+            eta_secs: 0,
+            nonce_found: false,
+            nonce: 0,
+            error: ErrorCode::Ok,
+        };
+        loop {
+            assert_eq!(
+                InitializeResult::Ok,
+                get_init_job_progress(job, &mut progress)
+            );
+            if progress.status != InitJobStatus::Running {
+                return progress;
+            }
+            std::thread::sleep(Duration::from_millis(10));
+        }
+    }
+
+    #[test]
+    fn initializing_in_background() {
+        let datadir = tempfile::tempdir().unwrap();
+        let path = CString::new(datadir.path().to_str().unwrap()).unwrap();
+        let job = start_init_job(
+            CPU_PROVIDER_ID,
+            2,
+            path.as_ptr(),
+            [1; 32].as_ptr(),
+            [2; 32].as_ptr(),
+            256,
+            4,
+            300,
+            [0xFF; 32].as_ptr(),
+        );
+        assert!(!job.is_null());
+
+        let progress = wait_for_job(job);
+        assert_eq!(InitJobStatus::Finished, progress.status);
+        assert_eq!(1024, progress.labels_done);
+        assert_eq!(1024, progress.total_labels);
+        assert_eq!(3, progress.current_file);
+        assert_eq!(0, progress.eta_secs);
+
+        let metadata = metadata::load(datadir.path()).unwrap();
+        assert!(progress.nonce_found);
+        assert_eq!(metadata.nonce, Some(progress.nonce));
+        free_init_job(job);
+    }
+
+    #[test]
+    fn cancelling_initialization() {
+        let datadir = tempfile::tempdir().unwrap();
+        let path = CString::new(datadir.path().to_str().unwrap()).unwrap();
+        let job = start_init_job(
+            CPU_PROVIDER_ID,
+            2,
+            path.as_ptr(),
+            [1; 32].as_ptr(),
+            [2; 32].as_ptr(),
+            1 << 30,
+            4,
+            1 << 20,
+            null(),
+        );
+        assert!(!job.is_null());
+        cancel_init_job(job);
+
+        let progress = wait_for_job(job);
+        assert_eq!(InitJobStatus::Cancelled, progress.status);
+        assert!(progress.labels_done < progress.total_labels);
+        assert!(!progress.nonce_found);
+        free_init_job(job);
+    }
+
+    #[test]
+    fn invalid_arguments() {
+        let path = CString::new("datadir").unwrap();
+        let job = start_init_job(
+            CPU_PROVIDER_ID,
+            3,
+            path.as_ptr(),
+            [1; 32].as_ptr(),
+            [2; 32].as_ptr(),
+            256,
+            4,
+            300,
+            null(),
+        );
+        assert!(job.is_null());
+        assert_eq!(ErrorCode::InvalidArgument, error::last_error_code());
+
+        let job = start_init_job(
+            CPU_PROVIDER_ID,
+            2,
+            null(),
+            [1; 32].as_ptr(),
+            [2; 32].as_ptr(),
+            256,
+            4,
+            300,
+            null(),
+        );
+        assert!(job.is_null());
+
+        assert_eq!(
+            InitializeResult::InvalidArgument,
+            get_init_job_progress(null(), std::ptr::null_mut())
+        );
+    }
+}
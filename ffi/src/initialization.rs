@@ -1,11 +1,18 @@
-use std::{error::Error, ffi::c_char, fmt::Debug};
+use std::{
+    ffi::{c_char, CStr},
+    fmt::Debug,
+    sync::RwLock,
+};
 
 use post::{
     config::ScryptParams,
     initialize::{CpuInitializer, Initialize},
     pos_verification::VerificationError,
+    Error,
 };
-use scrypt_ocl::{ocl::DeviceType, OpenClInitializer, ProviderId};
+use scrypt_ocl::{ocl::DeviceType, OpenClInitializer, ProviderFilter, ProviderId};
+
+use crate::{error, ArrayU64};
 
 pub enum Initializer {}
 
@@ -33,9 +40,80 @@ pub struct Provider {
     id: u32,
     class: DeviceClass,
 }
+/// A [Provider] with more details about the device, see [get_providers_v2].
+#[repr(C)]
+#[derive(Clone, PartialEq, Eq)]
+pub struct ProviderV2 {
+    name: [c_char; 64],
+    id: u32,
+    class: DeviceClass,
+    /// The device UUID or PCI bus address (empty if unknown).
+    /// Unlike `id`, it doesn't change when other devices are added or removed.
+    stable_id: [c_char; 64],
+    /// Size of the device memory in bytes (0 for the CPU provider).
+    memory: u64,
+}
+
+/// Copy the first `out.len() - 1` bytes of `s` and add a null terminator.
+fn copy_c_string(out: &mut [c_char], s: &str) {
+    let s = s
+        .bytes()
+        .map(|b| b as c_char)
+        .take(out.len() - 1)
+        .chain(std::iter::once(0))
+        .collect::<Vec<_>>();
+    out[..s.len()].copy_from_slice(&s);
+}
 
 pub const CPU_PROVIDER_ID: u32 = u32::MAX;
 
+/// The OpenCL providers excluded with [set_excluded_providers].
+static PROVIDER_FILTER: RwLock<ProviderFilter> = RwLock::new(ProviderFilter::new());
+
+pub(crate) fn provider_filter() -> ProviderFilter {
+    PROVIDER_FILTER.read().unwrap().clone()
+}
+
+/// Exclude the OpenCL providers which platform or device name matches any of
+/// the `patterns` (`len` null-terminated regular expressions) from now on.
+///
+/// The provider IDs of [get_providers], [get_providers_v2], [new_initializer]
+/// and [crate::init_job::start_init_job] are counted without the excluded providers.
+/// Calling it with no patterns includes all providers again.
+#[no_mangle]
+pub extern "C" fn set_excluded_providers(
+    patterns: *const *const c_char,
+    len: usize,
+) -> InitializeResult {
+    if patterns.is_null() && len > 0 {
+        log::error!("patterns is null");
+        return InitializeResult::InvalidArgument;
+    }
+    let patterns = match len {
+        0 => &[],
+        len => unsafe { std::slice::from_raw_parts(patterns, len) },
+    };
+    let patterns = patterns
+        .iter()
+        .map(|&p| match p.is_null() {
+            true => Err("pattern is null".to_string()),
+            false => unsafe { CStr::from_ptr(p) }
+                .to_str()
+                .map_err(|e| format!("invalid pattern: {e}")),
+        })
+        .collect::<Result<Vec<_>, _>>();
+    match patterns.and_then(|p| ProviderFilter::exclude(&p).map_err(|e| e.to_string())) {
+        Ok(filter) => {
+            *PROVIDER_FILTER.write().unwrap() = filter;
+            InitializeResult::Ok
+        }
+        Err(e) => {
+            log::error!("failed to exclude providers: {e}");
+            InitializeResult::InvalidArgument
+        }
+    }
+}
+
 #[repr(C)]
 #[derive(Debug, Copy, Clone, PartialEq, Eq)]
 #[allow(clippy::upper_case_acronyms)]
@@ -48,18 +126,57 @@ pub enum DeviceClass {
 #[no_mangle]
 pub extern "C" fn get_providers_count() -> usize {
     // Add one for the CPU provider.
-    scrypt_ocl::get_providers_count(Some(DeviceType::GPU)) + 1
+    scrypt_ocl::get_providers_count(Some(DeviceType::GPU), &provider_filter()) + 1
 }
 
 /// Returns all available providers.
 #[no_mangle]
 pub extern "C" fn get_providers(out: *mut Provider, out_len: usize) -> InitializeResult {
+    get_all_providers(out, out_len, |out, provider| {
+        *out = Provider {
+            name: [0; 64],
+            id: provider.id,
+            class: provider.class,
+        };
+        copy_c_string(&mut out.name, &provider.name);
+    })
+}
+
+/// Returns all available providers, with their stable ids and memory.
+#[no_mangle]
+pub extern "C" fn get_providers_v2(out: *mut ProviderV2, out_len: usize) -> InitializeResult {
+    get_all_providers(out, out_len, |out, provider| {
+        *out = ProviderV2 {
+            name: [0; 64],
+            id: provider.id,
+            class: provider.class,
+            stable_id: [0; 64],
+            memory: provider.memory,
+        };
+        copy_c_string(&mut out.name, &provider.name);
+        copy_c_string(&mut out.stable_id, &provider.stable_id);
+    })
+}
+
+struct ProviderInfo {
+    name: String,
+    id: u32,
+    class: DeviceClass,
+    stable_id: String,
+    memory: u64,
+}
+
+fn get_all_providers<T>(
+    out: *mut T,
+    out_len: usize,
+    fill: impl Fn(&mut T, ProviderInfo),
+) -> InitializeResult {
     if out.is_null() {
         log::error!("out is null");
         return InitializeResult::InvalidArgument;
     }
 
-    let providers = match scrypt_ocl::get_providers(Some(DeviceType::GPU)) {
+    let providers = match scrypt_ocl::get_providers(Some(DeviceType::GPU), &provider_filter()) {
         Ok(providers) => providers,
         Err(e) => {
             log::error!("failed to get providers: {e}");
@@ -71,26 +188,25 @@ pub extern "C" fn get_providers(out: *mut Provider, out_len: usize) -> Initializ
 
     let mut id = 0;
     for (out, provider) in out.iter_mut().zip(providers.iter()) {
-        // Copy over the first out.name.len() - 1 bytes, and then add a null terminator.
-        let name = format!("{provider}")
-            .bytes()
-            .map(|b| b as c_char)
-            .take(out.name.len() - 1)
-            .chain(std::iter::once(0))
-            .collect::<Vec<_>>();
-        out.name[..name.len()].copy_from_slice(&name);
-        out.id = id as u32;
-        out.class = DeviceClass::GPU;
+        let info = ProviderInfo {
+            name: provider.to_string(),
+            id: id as u32,
+            class: DeviceClass::GPU,
+            stable_id: provider.stable_id.clone().unwrap_or_default(),
+            memory: provider.memory,
+        };
+        fill(out, info);
         id += 1;
     }
-    if id < out.len() {
-        out[id] = Provider {
-            name: [0; 64],
+    if let Some(out) = out.get_mut(id) {
+        let info = ProviderInfo {
+            name: "[CPU] scrypt-jane".to_string(),
             id: CPU_PROVIDER_ID,
             class: DeviceClass::CPU,
+            stable_id: String::new(),
+            memory: 0,
         };
-        let name = b"[CPU] scrypt-jane\0";
-        out[id].name[..name.len()].copy_from_slice(&name.map(|b| b as c_char));
+        fill(out, info);
     }
 
     InitializeResult::Ok
@@ -99,6 +215,7 @@ pub extern "C" fn get_providers(out: *mut Provider, out_len: usize) -> Initializ
 /// Initializes labels for the given range.
 ///
 /// start and end are inclusive.
+/// On `Error` the kind of the error is available with [crate::error::last_error_code].
 #[no_mangle]
 pub extern "C" fn initialize(
     initializer: *mut Initializer,
@@ -124,12 +241,12 @@ pub extern "C" fn initialize(
     };
 
     let mut labels = unsafe { std::slice::from_raw_parts_mut(out_buffer, len) };
-    let vrf_nonce = match initializer.inner.initialize_to(
+    let vrf_nonce = match error::record(initializer.inner.initialize_to(
         &mut labels,
         &initializer.commitment,
         start..end,
         initializer.vrf_difficulty,
-    ) {
+    )) {
         Ok(nonce) => nonce,
         Err(e) => {
             log::error!("error initializing labels: {e:?}");
@@ -147,6 +264,8 @@ pub extern "C" fn initialize(
     InitializeResult::Ok
 }
 
+/// Create an initializer. Returns null on error,
+/// the kind of the error is available with [crate::error::last_error_code].
 #[no_mangle]
 pub extern "C" fn new_initializer(
     provider_id: u32,
@@ -154,7 +273,7 @@ pub extern "C" fn new_initializer(
     commitment: *const u8,
     vrf_difficulty: *const u8,
 ) -> *mut Initializer {
-    match _new_initializer(provider_id, n, commitment, vrf_difficulty) {
+    match error::record(_new_initializer(provider_id, n, commitment, vrf_difficulty)) {
         Ok(initializer) => Box::into_raw(initializer) as _,
         Err(e) => {
             log::error!("error creating initializer: {e:?}");
@@ -168,27 +287,33 @@ fn _new_initializer(
     n: usize,
     commitment: *const u8,
     vrf_difficulty: *const u8,
-) -> Result<Box<InitializerWrapper>, Box<dyn Error>> {
+) -> Result<Box<InitializerWrapper>, Error> {
     if !n.is_power_of_two() {
-        return Err("scrypt N must be a power of two".into());
+        return Err(Error::InvalidArgument(
+            "scrypt N must be a power of two".into(),
+        ));
     }
     let commitment = unsafe { std::slice::from_raw_parts(commitment, 32) };
-    let commitment = commitment.try_into()?;
+    let commitment = commitment.try_into().unwrap();
 
     let vrf_difficulty = if vrf_difficulty.is_null() {
         None
     } else {
         let vrf_difficulty = unsafe { std::slice::from_raw_parts(vrf_difficulty, 32) };
-        Some(vrf_difficulty.try_into()?)
+        Some(vrf_difficulty.try_into().unwrap())
     };
 
     let instance: Box<dyn Initialize> = match provider_id {
         CPU_PROVIDER_ID => Box::new(CpuInitializer::new(ScryptParams::new(n, 1, 1))),
-        id => Box::new(OpenClInitializer::new(
-            Some(ProviderId(id)),
-            n,
-            Some(DeviceType::GPU),
-        )?),
+        id => Box::new(
+            OpenClInitializer::new(
+                Some(ProviderId(id)),
+                n,
+                Some(DeviceType::GPU),
+                &provider_filter(),
+            )
+            .map_err(|e| Error::Initializer(Box::new(e)))?,
+        ),
     };
     let initializer = Box::new(InitializerWrapper {
         inner: instance,
@@ -254,10 +379,93 @@ pub extern "C" fn verify_pos(
     }
 }
 
+/// Result of [verify_pos_sample].
+#[repr(C)]
+#[derive(Debug, Default)]
+pub struct PosSampleReport {
+    /// Number of labels checked in all files.
+    labels_checked: u64,
+    /// Indices of the invalid labels (in the whole POS data), sorted.
+    invalid_labels: ArrayU64,
+}
+
+/// Verify a random sample (`fraction` %) of labels in every file of the POS data in `datadir`,
+/// finding all invalid labels in the sample.
+///
+/// Returns `Ok` if all checked labels are valid and `Invalid` (with the first invalid label) otherwise.
+/// On `Ok` and `Invalid` the `out` report is filled and must be freed with [free_pos_sample_report].
+#[no_mangle]
+pub extern "C" fn verify_pos_sample(
+    datadir: *const c_char,
+    fraction: f64,
+    scrypt: ScryptParams,
+    out: *mut PosSampleReport,
+) -> VerifyPosResult {
+    if datadir.is_null() || out.is_null() {
+        log::error!("datadir and out must not be null");
+        return VerifyPosResult::InvalidArgument;
+    }
+    let datadir = match unsafe { std::ffi::CStr::from_ptr(datadir) }.to_str() {
+        Ok(s) => std::path::Path::new(s),
+        Err(e) => {
+            log::error!("invalid datadir: {e}");
+            return VerifyPosResult::InvalidArgument;
+        }
+    };
+    let verified = post::metadata::load(datadir)
+        .map_err(|e| VerificationError::Unknown(e.into()))
+        .and_then(|metadata| {
+            let reports = post::pos_verification::verify_sample(datadir, fraction, scrypt)?;
+            Ok((metadata, reports))
+        });
+    let (metadata, reports) = match verified {
+        Ok(verified) => verified,
+        Err(e) => {
+            log::error!("Error verifying POS data: {e:?}");
+            return VerifyPosResult::Failed;
+        }
+    };
+
+    let first_invalid = reports
+        .iter()
+        .find_map(|r| r.invalid_labels.first().map(|&label| (r.file, label)));
+    unsafe {
+        *out = PosSampleReport {
+            labels_checked: reports.iter().map(|r| r.labels_checked as u64).sum(),
+            invalid_labels: reports
+                .into_iter()
+                .flat_map(|r| r.invalid_labels)
+                .collect::<Vec<_>>()
+                .into(),
+        }
+    };
+
+    match first_invalid {
+        None => VerifyPosResult::Ok,
+        Some((file, label)) => {
+            let offset = (label - file as u64 * metadata.max_file_size / 16) * 16;
+            log::info!("POS data is invalid: invalid label in file {file} at offset {offset}");
+            VerifyPosResult::Invalid { file, offset }
+        }
+    }
+}
+
+/// Free the invalid labels of a report filled by [verify_pos_sample].
+///
+/// # Safety
+/// `report` must be filled by [verify_pos_sample] and not freed before.
+#[no_mangle]
+pub unsafe extern "C" fn free_pos_sample_report(report: *mut PosSampleReport) {
+    if let Some(report) = report.as_mut() {
+        drop(std::mem::take(&mut report.invalid_labels).into_vec());
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use std::{
-        ffi::CString,
+        ffi::{c_char, CString},
+        io::{Seek, SeekFrom, Write},
         ptr::{null, null_mut},
     };
 
@@ -269,7 +477,10 @@ mod tests {
 
     use crate::initialization::{Initializer, InitializerWrapper, VerifyPosResult};
 
-    use super::{verify_pos, InitializeResult, CPU_PROVIDER_ID};
+    use super::{
+        free_pos_sample_report, verify_pos, verify_pos_sample, InitializeResult, PosSampleReport,
+        CPU_PROVIDER_ID,
+    };
 
     #[test]
     fn get_providers_null_out() {
@@ -324,7 +535,7 @@ mod tests {
         init_mock
             .expect_initialize_to()
             .once()
-            .returning(|_, _, _, _| Err("error".into()));
+            .returning(|_, _, _, _| Err(post::Error::Io(std::io::ErrorKind::StorageFull.into())));
 
         let mut initializer = Box::new(InitializerWrapper {
             inner: init_mock,
@@ -341,6 +552,7 @@ mod tests {
             null_mut(),
         );
         assert_eq!(InitializeResult::Error, result);
+        assert_eq!(post::ErrorCode::DiskFull, crate::error::last_error_code());
     }
 
     #[test]
@@ -398,6 +610,37 @@ mod tests {
         assert_ne!(0xCAFEDEAD, nonce);
     }
 
+    #[test]
+    fn excluding_providers() {
+        use super::set_excluded_providers;
+
+        let pattern = CString::new("^no such provider$").unwrap();
+        let result = set_excluded_providers([pattern.as_ptr()].as_ptr(), 1);
+        assert_eq!(InitializeResult::Ok, result);
+        assert_eq!(
+            InitializeResult::Ok,
+            set_excluded_providers(std::ptr::null(), 0)
+        );
+
+        let invalid = CString::new("fo(o").unwrap();
+        let result = set_excluded_providers([invalid.as_ptr()].as_ptr(), 1);
+        assert_eq!(InitializeResult::InvalidArgument, result);
+        let result = set_excluded_providers([std::ptr::null()].as_ptr(), 1);
+        assert_eq!(InitializeResult::InvalidArgument, result);
+        let result = set_excluded_providers(std::ptr::null(), 1);
+        assert_eq!(InitializeResult::InvalidArgument, result);
+    }
+
+    #[test]
+    fn copying_c_string() {
+        let mut out = [1 as c_char; 4];
+        super::copy_c_string(&mut out, "ab");
+        assert_eq!([b'a' as c_char, b'b' as c_char, 0, 1], out);
+
+        super::copy_c_string(&mut out, "abcdef");
+        assert_eq!([b'a' as c_char, b'b' as c_char, b'c' as c_char, 0], out);
+    }
+
     #[test]
     fn get_providers_count() {
         let count = super::get_providers_count();
@@ -411,7 +654,7 @@ mod tests {
             super::Provider {
                 name: [0; 64],
                 id: 0,
-                class: super::DeviceClass::CPU
+                class: super::DeviceClass::CPU,
             };
             count
         ];
@@ -422,6 +665,29 @@ mod tests {
         );
     }
 
+    #[test]
+    fn get_providers_v2() {
+        let count = super::get_providers_count();
+        let mut providers = vec![
+            super::ProviderV2 {
+                name: [0; 64],
+                id: 0,
+                class: super::DeviceClass::GPU,
+                stable_id: [0; 64],
+                memory: 0,
+            };
+            count
+        ];
+
+        assert_eq!(
+            InitializeResult::Ok,
+            super::get_providers_v2(providers.as_mut_ptr(), count)
+        );
+        let cpu = providers.last().unwrap();
+        assert_eq!(super::CPU_PROVIDER_ID, cpu.id);
+        assert_eq!(super::DeviceClass::CPU, cpu.class);
+    }
+
     #[test]
     fn initialize_and_verify() {
         // Initialize some data
@@ -447,4 +713,46 @@ mod tests {
         let result = verify_pos(path.as_ptr(), null(), null(), 100.0, scrypt);
         assert_eq!(VerifyPosResult::Failed, result);
     }
+
+    #[test]
+    fn verify_sample() {
+        let datadir = tempdir().unwrap();
+        let scrypt = ScryptParams::new(2, 1, 1);
+
+        CpuInitializer::new(scrypt)
+            .initialize(datadir.path(), &[0u8; 32], &[0u8; 32], 256, 4, 700, None)
+            .unwrap();
+
+        let datapath = CString::new(datadir.path().to_str().unwrap()).unwrap();
+        let mut report = PosSampleReport::default();
+        let result = verify_pos_sample(datapath.as_ptr(), 100.0, scrypt, &mut report);
+        assert_eq!(VerifyPosResult::Ok, result);
+        assert_eq!(1024, report.labels_checked);
+        assert_eq!(0, report.invalid_labels.len);
+        unsafe { free_pos_sample_report(&mut report) };
+
+        // Corrupt 2 labels in the second file
+        let mut file = std::fs::OpenOptions::new()
+            .write(true)
+            .open(datadir.path().join("postdata_1.bin"))
+            .unwrap();
+        file.seek(SeekFrom::Start(16)).unwrap();
+        file.write_all(&[0u8; 32]).unwrap();
+
+        let result = verify_pos_sample(datapath.as_ptr(), 100.0, scrypt, &mut report);
+        assert_eq!(
+            VerifyPosResult::Invalid {
+                file: 1,
+                offset: 16
+            },
+            result
+        );
+        assert_eq!(&[701, 702], unsafe {
+            std::slice::from_raw_parts(report.invalid_labels.ptr, report.invalid_labels.len)
+        });
+        unsafe { free_pos_sample_report(&mut report) };
+
+        let result = verify_pos_sample(std::ptr::null(), 100.0, scrypt, &mut report);
+        assert_eq!(VerifyPosResult::InvalidArgument, result);
+    }
 }
@@ -1,3 +1,5 @@
+mod error;
+mod init_job;
 mod initialization;
 mod log;
 mod post_impl;
@@ -43,6 +45,46 @@ impl ArrayU8 {
     }
 }
 
+/// An array of u64 owned by the library.
+#[repr(C)]
+#[derive(Debug)]
+pub struct ArrayU64 {
+    ptr: *mut u64,
+    len: usize,
+    cap: usize,
+}
+
+impl Default for ArrayU64 {
+    fn default() -> Self {
+        Self {
+            ptr: std::ptr::null_mut(),
+            len: 0,
+            cap: 0,
+        }
+    }
+}
+
+impl From<Vec<u64>> for ArrayU64 {
+    fn from(v: Vec<u64>) -> Self {
+        let mut v = std::mem::ManuallyDrop::new(v);
+        Self {
+            ptr: v.as_mut_ptr(),
+            len: v.len(),
+            cap: v.capacity(),
+        }
+    }
+}
+
+impl ArrayU64 {
+    // SAFETY: the array must be obtained with `From<Vec<u64>>` and not freed before.
+    pub(crate) unsafe fn into_vec(self) -> Vec<u64> {
+        if self.ptr.is_null() {
+            return Vec::new();
+        }
+        Vec::from_raw_parts(self.ptr, self.len, self.cap)
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -55,4 +97,12 @@ mod tests {
         assert_eq!(array.cap, v.capacity());
         assert_eq!(unsafe { array.as_slice() }, &v);
     }
+
+    #[test]
+    fn test_array_u64() {
+        let array = ArrayU64::from(vec![1, 2, 3]);
+        assert_eq!(3, array.len);
+        assert_eq!(vec![1, 2, 3], unsafe { array.into_vec() });
+        assert!(unsafe { ArrayU64::default().into_vec() }.is_empty());
+    }
 }
@@ -1,6 +1,8 @@
+// Generating proofs needs the `parallel` feature.
+#![cfg_attr(not(feature = "parallel"), allow(unused_imports))]
+
 use std::{
     borrow::Cow,
-    error::Error,
     ffi::{c_char, c_uchar, CStr},
     mem::ManuallyDrop,
     path::Path,
@@ -13,9 +15,10 @@ use post::{
     pow::randomx::{PoW, RandomXFlag},
     prove,
     verification::{Mode, Verifier},
+    Error,
 };
 
-use crate::ArrayU8;
+use crate::{error, ArrayU8};
 
 #[repr(C)]
 #[derive(Debug, Clone, Copy)]
@@ -62,9 +65,13 @@ pub unsafe extern "C" fn free_proof(proof: *mut Proof) {
 
 /// Generates a proof of space for the given challenge using the provided parameters.
 /// Returns a pointer to a Proof struct which should be freed with free_proof() after use.
-/// If an error occurs, prints it on stderr and returns null.
+/// If an error occurs, logs it and returns null. The kind of the error is available
+/// with [crate::error::last_error_code].
+///
+/// Fails for partially initialized POS data (see [generate_proof_of_initialized_units]).
 /// # Safety
 /// `challenge` must be a 32-byte array.
+#[cfg(feature = "parallel")]
 #[no_mangle]
 pub extern "C" fn generate_proof(
     datadir: *const c_char,
@@ -74,16 +81,111 @@ pub extern "C" fn generate_proof(
     threads: usize,
     pow_flags: RandomXFlag,
 ) -> *mut Proof {
-    match _generate_proof(datadir, challenge, cfg, nonces, threads, pow_flags) {
+    generate_proof_for_network(
+        datadir,
+        challenge,
+        cfg,
+        nonces,
+        threads,
+        pow_flags,
+        std::ptr::null(),
+    )
+}
+
+/// Like [generate_proof], with the RandomX PoW of the network `network_id`.
+/// A null `network_id` is mainnet (the same as [generate_proof]).
+/// # Safety
+/// `challenge` must be a 32-byte array.
+/// `network_id` must be null or a null-terminated string.
+#[cfg(feature = "parallel")]
+#[no_mangle]
+pub extern "C" fn generate_proof_for_network(
+    datadir: *const c_char,
+    challenge: *const c_uchar,
+    cfg: ProofConfig,
+    nonces: usize,
+    threads: usize,
+    pow_flags: RandomXFlag,
+    network_id: *const c_char,
+) -> *mut Proof {
+    let result = randomx_cache_key(network_id).and_then(|cache_key| {
+        _generate_proof(
+            datadir,
+            challenge,
+            cfg,
+            nonces,
+            threads,
+            pow_flags,
+            &cache_key,
+            &prove::ProvingOptions::default(),
+        )
+        .map(|(proof, _)| proof)
+    });
+    match error::record(result) {
         Ok(proof) => Box::into_raw(proof),
         Err(e) => {
-            //TODO(poszu) communicate errors better
             log::error!("{e:?}");
             std::ptr::null_mut()
         }
     }
 }
 
+/// Like [generate_proof_for_network], but partially initialized POS data is proven for
+/// the units initialized completely, instead of failing. Their number (the `num_units` of
+/// the proof's metadata) is written to `num_units`.
+/// # Safety
+/// `challenge` must be a 32-byte array.
+/// `network_id` must be null or a null-terminated string.
+/// `num_units` must point to a u32.
+#[allow(clippy::too_many_arguments)]
+#[cfg(feature = "parallel")]
+#[no_mangle]
+pub extern "C" fn generate_proof_of_initialized_units(
+    datadir: *const c_char,
+    challenge: *const c_uchar,
+    cfg: ProofConfig,
+    nonces: usize,
+    threads: usize,
+    pow_flags: RandomXFlag,
+    network_id: *const c_char,
+    num_units: *mut u32,
+) -> *mut Proof {
+    let result = randomx_cache_key(network_id).and_then(|cache_key| {
+        if num_units.is_null() {
+            return Err(Error::InvalidArgument("num_units is null".into()));
+        }
+        let options = prove::ProvingOptions {
+            partial_data: true,
+            ..Default::default()
+        };
+        let (proof, units) = _generate_proof(
+            datadir, challenge, cfg, nonces, threads, pow_flags, &cache_key, &options,
+        )?;
+        unsafe { *num_units = units };
+        Ok(proof)
+    });
+    match error::record(result) {
+        Ok(proof) => Box::into_raw(proof),
+        Err(e) => {
+            log::error!("{e:?}");
+            std::ptr::null_mut()
+        }
+    }
+}
+
+/// The RandomX cache key of the network `network_id` (mainnet if null).
+fn randomx_cache_key(network_id: *const c_char) -> Result<Vec<u8>, Error> {
+    if network_id.is_null() {
+        return Ok(post::pow::randomx::cache_key(None));
+    }
+    let network_id = unsafe { CStr::from_ptr(network_id) }
+        .to_str()
+        .map_err(|e| Error::InvalidArgument(format!("reading network ID: {e:?}")))?;
+    Ok(post::pow::randomx::cache_key(Some(network_id)))
+}
+
+#[allow(clippy::too_many_arguments)]
+#[cfg(feature = "parallel")]
 fn _generate_proof(
     datadir: *const c_char,
     challenge: *const c_uchar,
@@ -91,20 +193,23 @@ fn _generate_proof(
     nonces: usize,
     threads: usize,
     pow_flags: RandomXFlag,
-) -> Result<Box<Proof>, Box<dyn Error>> {
+    randomx_cache_key: &[u8],
+    options: &prove::ProvingOptions,
+) -> Result<(Box<Proof>, u32), Error> {
     let datadir = unsafe { CStr::from_ptr(datadir) };
     let datadir = Path::new(
         datadir
             .to_str()
-            .map_err(|e| format!("reading datadir: {e:?}"))?,
+            .map_err(|e| Error::InvalidArgument(format!("reading datadir: {e:?}")))?,
     );
 
     let challenge = unsafe { std::slice::from_raw_parts(challenge, 32) };
-    let challenge = challenge.try_into()?;
+    let challenge = challenge.try_into().unwrap();
 
+    let _lock = post::lock::DataDirLock::acquire(datadir)?;
     let stop = AtomicBool::new(false);
-    let pow_prover = post::pow::randomx::PoW::new(pow_flags).unwrap();
-    let proof = prove::generate_proof(
+    let pow_prover = PoW::with_cache_key(pow_flags, randomx_cache_key)?;
+    let proof = prove::generate_proof_with_options(
         datadir,
         challenge,
         cfg,
@@ -114,8 +219,10 @@ fn _generate_proof(
         stop,
         prove::NoopProgressReporter {},
         &pow_prover,
+        options,
     )?;
-    Ok(Box::new(Proof::from(proof)))
+    let num_units = post::metadata::load(datadir)?.initialized()?.num_units;
+    Ok((Box::new(Proof::from(proof)), num_units))
 }
 
 #[repr(C)]
@@ -171,10 +278,26 @@ pub extern "C" fn recommended_pow_flags() -> RandomXFlag {
 
 #[no_mangle]
 pub extern "C" fn new_verifier(flags: RandomXFlag, out: *mut *mut Verifier) -> NewVerifierResult {
+    new_verifier_for_network(flags, std::ptr::null(), out)
+}
+
+/// Like [new_verifier], verifying the RandomX PoWs of the network `network_id`.
+/// A null `network_id` is mainnet (the same as [new_verifier]).
+/// # Safety
+/// `network_id` must be null or a null-terminated string.
+#[no_mangle]
+pub extern "C" fn new_verifier_for_network(
+    flags: RandomXFlag,
+    network_id: *const c_char,
+    out: *mut *mut Verifier,
+) -> NewVerifierResult {
     if out.is_null() {
         return NewVerifierResult::InvalidArgument;
     }
-    match PoW::new(flags) {
+    let Ok(cache_key) = randomx_cache_key(network_id) else {
+        return NewVerifierResult::InvalidArgument;
+    };
+    match PoW::with_cache_key(flags, &cache_key) {
         Ok(verifier) => {
             unsafe { *out = Box::into_raw(Box::new(Verifier::new(Box::new(verifier)))) };
             NewVerifierResult::Ok
@@ -321,10 +444,34 @@ mod tests {
             1,
             0,
             Default::default(),
+            post::pow::randomx::DEFAULT_CACHE_KEY,
+            &Default::default(),
         );
         assert!(result.unwrap_err().to_string().contains("Utf8Error"));
     }
 
+    #[test]
+    fn create_verifier_for_network() {
+        let mut verifier = std::ptr::null_mut();
+        let network_id = std::ffi::CString::new("testnet").unwrap();
+        let result = super::new_verifier_for_network(
+            RandomXFlag::default(),
+            network_id.as_ptr(),
+            &mut verifier,
+        );
+        assert_eq!(result, super::NewVerifierResult::Ok);
+        assert!(!verifier.is_null());
+        super::free_verifier(verifier);
+
+        let network_id = std::ffi::CString::new([159, 146, 150]).unwrap();
+        let result = super::new_verifier_for_network(
+            RandomXFlag::default(),
+            network_id.as_ptr(),
+            &mut verifier,
+        );
+        assert_eq!(result, super::NewVerifierResult::InvalidArgument);
+    }
+
     #[test]
     fn create_and_free_verifier() {
         let mut verifier = std::ptr::null_mut();
@@ -463,4 +610,59 @@ mod tests {
         unsafe { super::free_proof(proof_ptr) };
         super::free_verifier(verifier);
     }
+
+    #[test]
+    fn proving_partially_initialized_data() {
+        let datadir = tempfile::tempdir().unwrap();
+        let cfg = post::config::ProofConfig {
+            k1: 20,
+            k2: 10,
+            pow_difficulty: [0xFF; 32],
+        };
+        let meta = post::initialize::CpuInitializer::new(ScryptParams::new(2, 1, 1))
+            .initialize(datadir.path(), &[77; 32], &[0u8; 32], 200, 2, 200, None)
+            .unwrap();
+        // Only the first unit is initialized
+        let meta = post::metadata::PostMetadata {
+            last_position: Some(300),
+            ..meta
+        };
+        std::fs::write(
+            datadir.path().join(post::metadata::METADATA_FILE_NAME),
+            serde_json::to_vec(&meta).unwrap(),
+        )
+        .unwrap();
+
+        let pow_flags = RandomXFlag::get_recommended_flags();
+        let challenge = b"hello world, challenge me!!!!!!!";
+        let data_dir_cstr = std::ffi::CString::new(datadir.path().to_str().unwrap()).unwrap();
+        let proof_ptr = crate::post_impl::generate_proof(
+            data_dir_cstr.as_ptr(),
+            challenge.as_ptr(),
+            cfg,
+            16,
+            1,
+            pow_flags,
+        );
+        assert!(proof_ptr.is_null());
+        assert_eq!(
+            post::ErrorCode::PartiallyInitialized,
+            crate::error::last_error_code()
+        );
+
+        let mut num_units = 0;
+        let proof_ptr = crate::post_impl::generate_proof_of_initialized_units(
+            data_dir_cstr.as_ptr(),
+            challenge.as_ptr(),
+            cfg,
+            16,
+            1,
+            pow_flags,
+            null(),
+            &mut num_units,
+        );
+        assert!(!proof_ptr.is_null());
+        assert_eq!(1, num_units);
+        unsafe { super::free_proof(proof_ptr) };
+    }
 }
@@ -0,0 +1,30 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use post::compression::{compress_indices, CompressedIndices};
+
+fuzz_target!(|input: (u8, u16, Vec<u8>)| {
+    let (bits, count, data) = input;
+    let (bits, count) = (bits as usize, count as usize);
+
+    // Malformed input must be rejected, not panic.
+    let Ok(indices) = CompressedIndices::new(&data, bits, count) else {
+        return;
+    };
+    let decoded: Vec<u64> = indices.iter().collect();
+    assert_eq!(count, decoded.len());
+    for (position, index) in decoded.iter().enumerate() {
+        assert_eq!(Some(*index), indices.get(position));
+    }
+
+    // Re-compressing gives back the input, except for the unused bits of the last byte.
+    let compressed = compress_indices(&decoded, bits);
+    assert_eq!(data.len(), compressed.len());
+    let used_bits = bits * count;
+    let full_bytes = used_bits / 8;
+    assert_eq!(data[..full_bytes], compressed[..full_bytes]);
+    if used_bits % 8 != 0 {
+        let mask = (1u8 << (used_bits % 8)) - 1;
+        assert_eq!(data[full_bytes] & mask, compressed[full_bytes]);
+    }
+});
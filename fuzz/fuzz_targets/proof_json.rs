@@ -0,0 +1,13 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use post::prove::Proof;
+
+fuzz_target!(|data: &[u8]| {
+    let Ok(proof) = serde_json::from_slice::<Proof>(data) else {
+        return;
+    };
+    let encoded = serde_json::to_vec(&proof).unwrap();
+    let decoded: Proof = serde_json::from_slice(&encoded).unwrap();
+    assert_eq!(proof, decoded);
+});
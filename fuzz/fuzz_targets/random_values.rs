@@ -0,0 +1,21 @@
+#![no_main]
+
+use std::collections::HashSet;
+
+use libfuzzer_sys::fuzz_target;
+use post::random_values_gen::RandomValuesIterator;
+
+fuzz_target!(|input: (u16, u16, Vec<u8>)| {
+    let (len, take, seed) = input;
+    let (len, take) = (len as usize % 4096, take as usize);
+
+    let values: Vec<usize> = RandomValuesIterator::new(len, &[&seed])
+        .take(take)
+        .collect();
+    assert_eq!(take.min(len), values.len());
+    let mut seen = HashSet::new();
+    for value in values {
+        assert!(value < len);
+        assert!(seen.insert(value), "{value} returned twice");
+    }
+});
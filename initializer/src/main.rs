@@ -1,7 +1,9 @@
 //! Post Service
 use std::{
     io::{Read, Seek},
-    path::PathBuf,
+    num::NonZeroU64,
+    path::{Path, PathBuf},
+    sync::Arc,
     time,
 };
 
@@ -9,12 +11,16 @@ use base64::{engine::general_purpose, Engine};
 use clap::{Args, Parser, Subcommand, ValueEnum};
 use eyre::Context;
 use post::{
-    config::ScryptParams,
-    initialize::{CpuInitializer, Initialize, LABEL_SIZE},
+    config::{InitConfig, NetworkPreset, ProofConfig, ScryptParams, UnitSize},
+    initialize::{CpuInitializer, Initialize, SyncPolicy, WipeMode, WriteOptions, LABEL_SIZE},
+    inspect::inspect,
+    metadata::ProofMetadata,
+    pos_verification::{verify_file, DataReport},
+    prove::{Proof, ProofWithMetadata},
 };
 use rand::seq::IteratorRandom;
 use rayon::prelude::{ParallelBridge, ParallelIterator};
-use scrypt_ocl::{ocl::DeviceType, OpenClInitializer, ProviderId};
+use scrypt_ocl::{ocl::DeviceType, OpenClInitializer, ProviderFilter, ProviderId};
 
 /// Initialize labels on GPU
 #[derive(Parser)]
@@ -31,14 +37,27 @@ struct Cli {
 enum Commands {
     /// does testing things
     Initialize(InitializeArgs),
-    ListProviders,
+    ListProviders(ListProvidersArgs),
     VerifyData(VerifyData),
+    /// Remove POS data and its metadata
+    ///
+    /// Refuses to run if the data is in use (i.e. by a running post-service).
+    Wipe(WipeArgs),
+    /// Decode a proof and check the labels of its indices
+    ///
+    /// Re-derives the label of every index and prints which difficulty condition
+    /// it satisfies. With `--dir`, also compares them with the labels stored in POS data.
+    InspectProof(InspectProofArgs),
 }
 
 #[derive(Args)]
 struct InitializeArgs {
-    /// Scrypt N parameter
-    #[arg(short, long, default_value_t = 8192)]
+    /// Use the scrypt N and labels per unit of a known network (`mainnet`, `testnet` or `custom`)
+    #[arg(long, default_value_t = NetworkPreset::Custom, conflicts_with_all = ["n", "labels_per_unit"])]
+    preset: NetworkPreset,
+
+    /// Scrypt N parameter, a power of two >= 2 (i.e. `8192` or `2^13`)
+    #[arg(short, long, default_value_t = 8192, value_parser = ScryptParams::parse_n)]
     n: usize,
 
     /// Labels per unit
@@ -65,11 +84,41 @@ struct InitializeArgs {
     #[arg(long, default_value = "./post-data")]
     output: PathBuf,
 
-    /// Provider ID to use for GPU initialization.
+    /// Write the labels directly to the block device (or a file) at `output`
+    /// instead of files in a directory. Overwrites the content of the device!
+    #[arg(long)]
+    block_device: bool,
+
+    /// Provider to use for GPU initialization: its position or its stable id
+    /// (the device UUID or PCI bus address), which doesn't change when devices are added or removed.
     /// Use `initializer list-providers` to list available providers.
     /// If not specified, the first available provider will be used.
     #[arg(long)]
-    provider: Option<u32>,
+    provider: Option<String>,
+
+    /// Skip the OpenCL providers which platform or device name matches the pattern
+    /// (a regular expression, i.e. `(?i)rusticl`). Can be repeated.
+    /// The provider IDs are counted without the excluded providers.
+    #[arg(long)]
+    exclude_provider: Vec<String>,
+
+    /// Number of labels calculated at once with the CPU methods.
+    /// Up to 3 chunks (32 bytes per label) are kept in memory.
+    #[arg(long, default_value_t = CpuInitializer::DEFAULT_CHUNK_SIZE)]
+    cpu_chunk_size: u64,
+
+    /// When to sync the written files to the disk (`per-file` or `at-end`).
+    /// With `at-end` an interrupted initialization can't be resumed.
+    #[arg(long, default_value_t = SyncPolicy::PerFile)]
+    sync: SyncPolicy,
+
+    /// Write the files with O_DIRECT, bypassing the page cache (Linux only).
+    #[arg(long, conflicts_with = "block_device")]
+    direct_io: bool,
+
+    /// Limit the rate of writing the files (in MiB/s).
+    #[arg(long, conflicts_with = "block_device")]
+    write_rate_limit: Option<NonZeroU64>,
 
     #[clap(value_enum, default_value_t = InitializationMethod::Gpu)]
     method: InitializationMethod,
@@ -78,17 +127,50 @@ struct InitializeArgs {
 #[derive(Clone, ValueEnum)]
 enum InitializationMethod {
     Cpu,
+    /// CPU with the optimized (AVX2) scrypt
+    #[cfg(feature = "cpu-opt")]
+    CpuOpt,
     Gpu,
 }
 
+#[derive(Args)]
+struct ListProvidersArgs {
+    /// Skip the OpenCL providers which platform or device name matches the pattern
+    /// (a regular expression). Can be repeated.
+    #[arg(long)]
+    exclude_provider: Vec<String>,
+
+    /// Scrypt N parameter used to measure the speed of the providers (with `--probe`)
+    #[arg(short, long, default_value_t = 8192, value_parser = ScryptParams::parse_n)]
+    n: usize,
+
+    /// Measure the speed of the providers (it initializes some labels on every device)
+    #[arg(long)]
+    probe: bool,
+}
+
 #[derive(Args)]
 struct VerifyData {
-    /// Scrypt N parameter
-    #[arg(short, long, default_value_t = 8192)]
+    /// Use the scrypt N of a known network (`mainnet`, `testnet` or `custom`)
+    #[arg(long, default_value_t = NetworkPreset::Custom, conflicts_with = "n")]
+    preset: NetworkPreset,
+    /// Scrypt N parameter, a power of two >= 2 (i.e. `8192` or `2^13`)
+    #[arg(short, long, default_value_t = 8192, value_parser = ScryptParams::parse_n)]
     n: usize,
     /// Path to file with POST data to verify
-    #[arg(short, long)]
-    input: PathBuf,
+    #[arg(short, long, required_unless_present = "dir", conflicts_with = "dir")]
+    input: Option<PathBuf>,
+    /// Path to POST data directory to verify (all files described by its metadata)
+    #[arg(long)]
+    dir: Option<PathBuf>,
+    /// Write a JSON report of the invalid labels of each verified file
+    /// (only with `--dir`). It's updated after every file.
+    #[arg(long, requires = "dir")]
+    report: Option<PathBuf>,
+    /// Resume an interrupted verification, skipping files already in the report.
+    /// The report must be of the same data directory, metadata, scrypt N and fraction.
+    #[arg(long, requires = "report")]
+    resume: bool,
     /// Fraction of data (in %) to initialize
     #[arg(short, long, default_value_t = 5.0)]
     fraction: f64,
@@ -103,6 +185,68 @@ struct VerifyData {
     commitment_atx_id: String,
 }
 
+#[derive(Args)]
+struct InspectProofArgs {
+    /// Path to a JSON file with the proof and its metadata (written by `prover`),
+    /// or only the proof (`{"nonce": .., "indices": "<base64>", "pow": ..}`)
+    #[arg(long, required_unless_present = "indices", conflicts_with_all = ["nonce", "indices", "pow"])]
+    proof: Option<PathBuf>,
+    /// Nonce of the proof (instead of `--proof`)
+    #[arg(long, requires_all = ["indices", "pow"])]
+    nonce: Option<u32>,
+    /// Base64-encoded (compressed) indices of the proof (instead of `--proof`)
+    #[arg(long, requires_all = ["nonce", "pow"])]
+    indices: Option<String>,
+    /// PoW of the proof (instead of `--proof`)
+    #[arg(long, requires_all = ["nonce", "indices"])]
+    pow: Option<u64>,
+    /// Base64-encoded challenge (unless the proof file has the metadata)
+    #[arg(long)]
+    challenge: Option<String>,
+    /// POS data directory (or a block device) of the proof.
+    /// The node ID, commitment ATX ID and number of units are taken from its metadata
+    /// (unless the proof file has the metadata).
+    #[arg(long)]
+    dir: Option<PathBuf>,
+    /// Base64-encoded node ID (without `--dir`)
+    #[arg(long, conflicts_with = "dir")]
+    node_id: Option<String>,
+    /// Base64-encoded commitment ATX ID (without `--dir`)
+    #[arg(long, conflicts_with = "dir")]
+    commitment_atx_id: Option<String>,
+    /// Number of units (without `--dir`)
+    #[arg(long, conflicts_with = "dir")]
+    num_units: Option<u32>,
+    /// Use the parameters of a known network (`mainnet`, `testnet` or `custom`)
+    #[arg(long, default_value_t = NetworkPreset::Mainnet)]
+    preset: NetworkPreset,
+    /// K1 parameter (overrides the preset)
+    #[arg(long)]
+    k1: Option<u32>,
+    /// K2 parameter (overrides the preset)
+    #[arg(long)]
+    k2: Option<u32>,
+    /// Scrypt N parameter (overrides the preset)
+    #[arg(short, long, value_parser = ScryptParams::parse_n)]
+    n: Option<usize>,
+    /// Labels per unit (overrides the preset)
+    #[arg(short, long)]
+    labels_per_unit: Option<u64>,
+    /// Print the report as JSON
+    #[arg(long)]
+    json: bool,
+}
+
+#[derive(Args)]
+struct WipeArgs {
+    /// Path to the POS data directory (or a block device)
+    #[arg(long)]
+    dir: PathBuf,
+    /// Overwrite the data with zeros before removing it
+    #[arg(long)]
+    overwrite: bool,
+}
+
 fn calc_commitment(node_id: &str, commitment_atx_id: &str) -> eyre::Result<[u8; 32]> {
     let node_id = general_purpose::STANDARD.decode(node_id)?;
     let commitment_atx_id = general_purpose::STANDARD.decode(commitment_atx_id)?;
@@ -119,11 +263,26 @@ fn calc_commitment(node_id: &str, commitment_atx_id: &str) -> eyre::Result<[u8;
     ))
 }
 
-fn verify_data(args: VerifyData) -> eyre::Result<()> {
+fn verify_data(mut args: VerifyData) -> eyre::Result<()> {
+    if let Some(cfg) = args.preset.init_config() {
+        args.n = cfg.scrypt.n;
+    }
+    let input = match (args.dir.clone(), args.input.clone()) {
+        (Some(dir), _) => return verify_datadir(&dir, args),
+        (None, Some(input)) => input,
+        (None, None) => eyre::bail!("either --input or --dir is required"),
+    };
     let commitment = calc_commitment(&args.node_id, &args.commitment_atx_id)?;
 
+    // lock the directory of the file, so that it's not modified while verifying
+    let datadir = match input.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => std::path::Path::new("."),
+    };
+    let _lock = post::lock::DataDirLock::acquire_shared(datadir)?;
+
     // open intput file for reading
-    let mut input_file = std::fs::File::open(args.input)?;
+    let mut input_file = std::fs::File::open(&input)?;
     // read input file size
     let input_file_size = input_file.metadata()?.len();
     let labels_in_file = input_file_size / 16;
@@ -167,24 +326,129 @@ fn verify_data(args: VerifyData) -> eyre::Result<()> {
     Ok(())
 }
 
-fn initialize(args: InitializeArgs) -> eyre::Result<()> {
-    eyre::ensure!(args.n.is_power_of_two(), "scrypt N must be a power of two");
+fn verify_datadir(datadir: &Path, args: VerifyData) -> eyre::Result<()> {
+    let metadata = post::metadata::load(datadir).wrap_err("loading metadata")?;
+    let _lock = post::lock::DataDirLock::acquire_shared(datadir)?;
+    let scrypt_params = ScryptParams::new(args.n, 1, 1);
+    let datadir = &datadir.canonicalize()?;
 
+    let mut data_report = match (&args.report, args.resume) {
+        (Some(path), true) if path.exists() => {
+            let report: DataReport =
+                serde_json::from_slice(&std::fs::read(path)?).wrap_err("reading report")?;
+            eyre::ensure!(
+                report.is_for(datadir, &metadata, scrypt_params, args.fraction),
+                "the report {} is of another data directory, metadata or verification parameters",
+                path.display()
+            );
+            report
+        }
+        _ => DataReport::new(datadir, metadata, scrypt_params, args.fraction),
+    };
+    let save = |report: &DataReport| -> eyre::Result<()> {
+        if let Some(path) = &args.report {
+            let tmp = path.with_extension("tmp");
+            std::fs::write(&tmp, serde_json::to_vec_pretty(report)?)?;
+            std::fs::rename(tmp, path)?;
+        }
+        Ok(())
+    };
+
+    for idx in 0..metadata.num_files() {
+        if data_report.files.iter().any(|r| r.file == idx) {
+            println!("skipping postdata_{idx}.bin (already verified)");
+            continue;
+        }
+        let report = verify_file(datadir, idx, args.fraction, &metadata, scrypt_params)?;
+        if !report.invalid_labels.is_empty() {
+            println!(
+                "postdata_{idx}.bin: {} invalid labels out of {} checked",
+                report.invalid_labels.len(),
+                report.labels_checked
+            );
+        }
+        data_report.files.push(report);
+        save(&data_report)?;
+    }
+
+    let invalid_files = data_report
+        .files
+        .iter()
+        .filter(|r| !r.invalid_labels.is_empty())
+        .map(|r| r.file)
+        .collect::<Vec<_>>();
+    eyre::ensure!(
+        invalid_files.is_empty(),
+        "found invalid labels in files: {invalid_files:?}"
+    );
+    println!("Data verified successfully");
+    Ok(())
+}
+
+fn initialize(mut args: InitializeArgs) -> eyre::Result<()> {
+    if let Some(cfg) = args.preset.init_config() {
+        args.n = cfg.scrypt.n;
+        args.labels_per_unit = cfg.labels_per_unit as usize;
+    }
     let mut initializer: Box<dyn Initialize> = match args.method {
-        InitializationMethod::Cpu => Box::new(CpuInitializer::new(ScryptParams::new(args.n, 1, 1))),
-        InitializationMethod::Gpu => Box::new(OpenClInitializer::new(
-            args.provider.map(ProviderId),
-            args.n,
-            Some(DeviceType::GPU | DeviceType::CPU),
-        )?),
+        InitializationMethod::Cpu => Box::new(
+            CpuInitializer::new(ScryptParams::new(args.n, 1, 1))
+                .with_chunk_size(args.cpu_chunk_size),
+        ),
+        #[cfg(feature = "cpu-opt")]
+        InitializationMethod::CpuOpt => Box::new(
+            post::scrypt_opt::OptimizedCpuInitializer::new(ScryptParams::new(args.n, 1, 1))?
+                .with_chunk_size(args.cpu_chunk_size),
+        ),
+        InitializationMethod::Gpu => {
+            let device_types = Some(DeviceType::GPU | DeviceType::CPU);
+            let filter = ProviderFilter::exclude(&args.exclude_provider)?;
+            let provider = args
+                .provider
+                .map(|id| ProviderId::find(&id, device_types, &filter))
+                .transpose()?;
+            Box::new(OpenClInitializer::new(
+                provider,
+                args.n,
+                device_types,
+                &filter,
+            )?)
+        }
     };
 
     let node_id = general_purpose::STANDARD.decode(args.node_id)?;
     let commitment_atx_id = general_purpose::STANDARD.decode(args.commitment_atx_id)?;
 
+    let unit_size = UnitSize::new(args.labels_per_unit as u64);
+    println!(
+        "Initializing {} units of {unit_size}: {} labels, {} bytes",
+        args.units,
+        unit_size.labels(args.units as u32),
+        unit_size.bytes(args.units as u32),
+    );
+
     let now = time::Instant::now();
-    let metadata = initializer
-        .initialize(
+    let metadata = if args.block_device {
+        post::block_device::initialize(
+            initializer.as_mut(),
+            &args.output,
+            node_id.as_slice().try_into()?,
+            commitment_atx_id.as_slice().try_into()?,
+            args.labels_per_unit as u64,
+            args.units as u32,
+            Some([0xFFu8; 32]),
+        )
+    } else {
+        let options = WriteOptions {
+            sync: args.sync,
+            direct_io: args.direct_io,
+            rate_limit: args.write_rate_limit.map(|mib| {
+                Arc::new(post::reader::RateLimiter::new(
+                    mib.saturating_mul(NonZeroU64::new(1024 * 1024).unwrap()),
+                ))
+            }),
+        };
+        initializer.initialize_with_options(
             &args.output,
             node_id.as_slice().try_into()?,
             commitment_atx_id.as_slice().try_into()?,
@@ -192,11 +456,13 @@ fn initialize(args: InitializeArgs) -> eyre::Result<()> {
             args.units as u32,
             (args.max_file_size / LABEL_SIZE) as u64,
             Some([0xFFu8; 32]),
+            &options,
         )
-        .map_err(|e| eyre::eyre!("initializing: {}", e))?;
+    }
+    .map_err(|e| eyre::eyre!("initializing: {}", e))?;
 
     let elapsed = now.elapsed();
-    let labels_initialized = args.labels_per_unit * args.units;
+    let labels_initialized = unit_size.labels(args.units as u32);
     println!(
             "Initializing {labels_initialized} labels took {:.2} seconds. Speed: {:.0} labels/sec ({:.2} MB/sec), vrf_nonce: {:?}",
             elapsed.as_secs_f64(),
@@ -207,10 +473,172 @@ fn initialize(args: InitializeArgs) -> eyre::Result<()> {
     Ok(())
 }
 
-fn list_providers() -> eyre::Result<()> {
-    let providers = scrypt_ocl::get_providers(Some(DeviceType::GPU | DeviceType::CPU))?;
+fn wipe(args: WipeArgs) -> eyre::Result<()> {
+    let mode = match args.overwrite {
+        true => WipeMode::Overwrite,
+        false => WipeMode::Delete,
+    };
+    post::initialize::wipe(&args.dir, mode)?;
+    println!("POS data in {} wiped", args.dir.display());
+    Ok(())
+}
+
+fn decode_32(value: &str, what: &str) -> eyre::Result<[u8; 32]> {
+    general_purpose::STANDARD
+        .decode(value)
+        .wrap_err_with(|| format!("decoding {what}"))?
+        .try_into()
+        .map_err(|_| eyre::eyre!("{what} should be 32B"))
+}
+
+fn inspect_proof(args: InspectProofArgs) -> eyre::Result<()> {
+    let (proof, file_metadata) = match (&args.proof, args.indices) {
+        (Some(path), _) => {
+            let data = std::fs::read(path)?;
+            match serde_json::from_slice::<ProofWithMetadata>(&data) {
+                Ok(file) => (file.proof, Some(file.metadata)),
+                Err(_) => (
+                    serde_json::from_slice(&data).wrap_err("decoding proof")?,
+                    None,
+                ),
+            }
+        }
+        (None, Some(indices)) => (
+            Proof {
+                nonce: args.nonce.unwrap(),
+                indices: general_purpose::STANDARD
+                    .decode(indices)
+                    .wrap_err("decoding indices")?
+                    .into(),
+                pow: args.pow.unwrap(),
+            },
+            None,
+        ),
+        (None, None) => eyre::bail!("either --proof or --indices is required"),
+    };
+
+    let custom =
+        || eyre::eyre!("--k1, --k2, --n and --labels-per-unit are required with the custom preset");
+    let preset_cfg = args.preset.proof_config();
+    let preset_init_cfg = args.preset.init_config();
+    let cfg = ProofConfig {
+        k1: args.k1.or(preset_cfg.map(|c| c.k1)).ok_or_else(custom)?,
+        k2: args.k2.or(preset_cfg.map(|c| c.k2)).ok_or_else(custom)?,
+        // The PoW is not inspected
+        pow_difficulty: [0xFF; 32],
+    };
+    let init_cfg = InitConfig {
+        min_num_units: 1,
+        max_num_units: u32::MAX,
+        labels_per_unit: args
+            .labels_per_unit
+            .or(preset_init_cfg.map(|c| c.labels_per_unit))
+            .ok_or_else(custom)?,
+        scrypt: match args.n {
+            Some(n) => ScryptParams::try_new(n, 1, 1)?,
+            None => preset_init_cfg.ok_or_else(custom)?.scrypt,
+        },
+    };
+
+    let required =
+        |arg: &str| eyre::eyre!("{arg} is required (unless the proof file has the metadata)");
+    let challenge = || {
+        let challenge = args
+            .challenge
+            .as_deref()
+            .ok_or_else(|| required("--challenge"))?;
+        decode_32(challenge, "challenge")
+    };
+    let post_metadata = match &args.dir {
+        Some(dir) => Some(post::metadata::load(dir).wrap_err("loading metadata")?),
+        None => None,
+    };
+    let metadata = match (file_metadata, post_metadata) {
+        (Some(metadata), _) => metadata,
+        (None, Some(post_metadata)) => ProofMetadata::new(post_metadata, challenge()?),
+        (None, None) => ProofMetadata {
+            node_id: decode_32(
+                args.node_id
+                    .as_deref()
+                    .ok_or_else(|| required("--node-id or --dir"))?,
+                "node ID",
+            )?,
+            commitment_atx_id: decode_32(
+                args.commitment_atx_id
+                    .as_deref()
+                    .ok_or_else(|| required("--commitment-atx-id or --dir"))?,
+                "commitment ATX ID",
+            )?,
+            challenge: challenge()?,
+            num_units: args
+                .num_units
+                .ok_or_else(|| required("--num-units or --dir"))?,
+        },
+    };
+
+    let mut report = inspect(&proof, &metadata, &cfg, &init_cfg)?;
+    if let (Some(dir), Some(post_metadata)) = (&args.dir, &post_metadata) {
+        report.read_labels(dir, post_metadata)?;
+    }
+
+    if args.json {
+        println!("{}", serde_json::to_string_pretty(&report)?);
+    } else {
+        println!(
+            "nonce: {} (nonce group {}), pow: {}",
+            report.nonce, report.nonce_group, report.pow
+        );
+        println!(
+            "difficulty: {:#018x} (msb: {}, lsb: {:#016x})",
+            report.difficulty, report.difficulty_msb, report.difficulty_lsb
+        );
+        for index in &report.indices {
+            let location = match index.location {
+                Some(l) => format!("postdata_{}.bin@{}", l.file, l.offset),
+                None => "-".to_string(),
+            };
+            let stored = match index.stored_label {
+                Some(label) if label != index.label => {
+                    format!(" STORED LABEL DIFFERS: {}", hex::encode(label))
+                }
+                _ => String::new(),
+            };
+            println!(
+                "#{:<3} index: {:<12} {location} label: {} msb: {:<3} lsb: {:#016x} {:?}{stored}",
+                index.index_id,
+                index.index,
+                hex::encode(index.label),
+                index.msb,
+                index.lsb,
+                index.condition,
+            );
+        }
+    }
+
+    let invalid = report.invalid().map(|r| r.index_id).collect::<Vec<_>>();
+    let mismatched = report.mismatched().map(|r| r.index_id).collect::<Vec<_>>();
+    eyre::ensure!(
+        invalid.is_empty() && mismatched.is_empty(),
+        "invalid indices: {invalid:?}, labels differing from POS data: {mismatched:?}"
+    );
+    Ok(())
+}
+
+fn list_providers(args: ListProvidersArgs) -> eyre::Result<()> {
+    let filter = ProviderFilter::exclude(&args.exclude_provider)?;
+    let providers = scrypt_ocl::get_providers(Some(DeviceType::GPU | DeviceType::CPU), &filter)?;
     for (id, provider) in providers.iter().enumerate() {
         println!("{id}: {provider}");
+        if let Some(stable_id) = &provider.stable_id {
+            println!("    id: {stable_id}");
+        }
+        println!("    memory: {} MiB", provider.memory / 1024 / 1024);
+        if args.probe {
+            match provider.probe_speed(args.n) {
+                Ok(speed) => println!("    speed: {speed:.0} labels/s (N = {})", args.n),
+                Err(e) => println!("    speed: unknown ({e})"),
+            }
+        }
     }
     println!("{}: [CPU] scrypt-jane", providers.len());
     Ok(())
@@ -225,8 +653,10 @@ fn main() -> eyre::Result<()> {
         .unwrap_or(Commands::Initialize(args.initialize))
     {
         Commands::Initialize(args) => initialize(args)?,
-        Commands::ListProviders => list_providers()?,
+        Commands::ListProviders(args) => list_providers(args)?,
         Commands::VerifyData(v) => verify_data(v)?,
+        Commands::Wipe(args) => wipe(args)?,
+        Commands::InspectProof(args) => inspect_proof(args)?,
     }
 
     Ok(())
@@ -0,0 +1,422 @@
+//! Coordinator mode: dispatching the jobs to downstream k2pow-service workers.
+//!
+//! The coordinator presents a single endpoint to the post services. Every job is
+//! sent to a healthy worker (round-robin) and polled there until it's done. Jobs
+//! rejected by a busy worker are retried on the next one, jobs of a worker that
+//! stopped responding are sent again to another one.
+
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use post::pow::service::OWNER_HEADER;
+use reqwest::StatusCode;
+use tokio::runtime::Handle;
+
+use crate::job_manager::{check_owner, GetOrCreate, Job, JobError, JobStatus, DONE_RETENTION};
+
+/// A worker not answering within the timeouts is considered failed.
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
+
+struct Worker {
+    url: String,
+    healthy: AtomicBool,
+}
+
+enum JobState {
+    Dispatched,
+    Done {
+        result: Result<u64, String>,
+        finished: Instant,
+    },
+}
+
+struct Jobs {
+    states: HashMap<Job, JobState>,
+    owners: HashMap<Job, String>,
+}
+
+impl Jobs {
+    /// Forget the jobs finished more than `retention` ago.
+    fn prune(&mut self, retention: Duration) {
+        self.states.retain(|_, state| match state {
+            JobState::Dispatched => true,
+            JobState::Done { finished, .. } => finished.elapsed() < retention,
+        });
+        let states = &self.states;
+        self.owners.retain(|job, _| states.contains_key(job));
+    }
+}
+
+struct Inner {
+    workers: Vec<Worker>,
+    next_worker: AtomicUsize,
+    jobs: Mutex<Jobs>,
+    client: reqwest::Client,
+    backoff: Duration,
+}
+
+/// The outcome of polling a job on a worker.
+enum Polled {
+    Done(Result<u64, String>),
+    Busy,
+}
+
+pub struct Coordinator {
+    inner: Arc<Inner>,
+    max_jobs: usize,
+    done_retention: Duration,
+    runtime: Handle,
+}
+
+impl Coordinator {
+    /// Create a coordinator for the workers at the given base URLs.
+    ///
+    /// Must be called within a tokio runtime, the jobs are dispatched on it.
+    /// Up to `max_jobs` jobs are dispatched at the same time.
+    pub fn new(workers: Vec<String>, max_jobs: usize, backoff: Duration) -> Self {
+        let workers = workers
+            .into_iter()
+            .map(|url| Worker {
+                url: url.trim_end_matches('/').to_string(),
+                healthy: AtomicBool::new(true),
+            })
+            .collect();
+        Self {
+            inner: Arc::new(Inner {
+                workers,
+                next_worker: AtomicUsize::new(0),
+                jobs: Mutex::new(Jobs {
+                    states: HashMap::new(),
+                    owners: HashMap::new(),
+                }),
+                client: reqwest::Client::builder()
+                    .connect_timeout(CONNECT_TIMEOUT)
+                    .timeout(REQUEST_TIMEOUT)
+                    .build()
+                    .expect("building HTTP client"),
+                backoff,
+            }),
+            max_jobs,
+            done_retention: DONE_RETENTION,
+            runtime: Handle::current(),
+        }
+    }
+
+    /// Keep the results of finished jobs for `retention` (10 minutes by default).
+    pub fn with_done_retention(mut self, retention: Duration) -> Self {
+        self.done_retention = retention;
+        self
+    }
+
+    /// Check the health endpoint of the workers every `interval`.
+    /// The unhealthy workers get no jobs until they recover.
+    pub fn spawn_health_checks(&self, interval: Duration) {
+        let inner = self.inner.clone();
+        self.runtime.spawn(async move {
+            let mut interval = tokio::time::interval(interval);
+            loop {
+                interval.tick().await;
+                for worker in &inner.workers {
+                    let healthy = match inner.client.get(&worker.url).send().await {
+                        Ok(res) => res.status().is_success(),
+                        Err(_) => false,
+                    };
+                    if worker.healthy.swap(healthy, Ordering::Relaxed) != healthy {
+                        tracing::info!(
+                            "worker {} is {}",
+                            worker.url,
+                            if healthy { "healthy" } else { "unhealthy" }
+                        );
+                    }
+                }
+            }
+        });
+    }
+}
+
+impl Inner {
+    /// Pick the next healthy worker (round-robin).
+    fn pick_worker(&self) -> Option<&Worker> {
+        let start = self.next_worker.fetch_add(1, Ordering::Relaxed);
+        (0..self.workers.len())
+            .map(|i| &self.workers[(start + i) % self.workers.len()])
+            .find(|w| w.healthy.load(Ordering::Relaxed))
+    }
+
+    async fn dispatch(&self, job: &Job, owner: Option<&str>, priority: i32) -> Result<u64, String> {
+        loop {
+            let Some(worker) = self.pick_worker() else {
+                tracing::warn!("no healthy workers, backing off before retry");
+                tokio::time::sleep(self.backoff).await;
+                continue;
+            };
+            match self.poll(worker, job, owner, priority).await {
+                Ok(Polled::Done(result)) => return result,
+                Ok(Polled::Busy) => {
+                    tracing::debug!("worker {} is busy, trying another one", worker.url);
+                    tokio::time::sleep(self.backoff).await;
+                }
+                Err(e) => {
+                    tracing::warn!("worker {} failed: {e}, trying another one", worker.url);
+                    worker.healthy.store(false, Ordering::Relaxed);
+                }
+            }
+        }
+    }
+
+    /// Poll the job on the worker until it's done or the worker is busy.
+    async fn poll(
+        &self,
+        worker: &Worker,
+        job: &Job,
+        owner: Option<&str>,
+        priority: i32,
+    ) -> Result<Polled, reqwest::Error> {
+        let url = format!(
+            "{}/job/{}/{}/{}/{}?priority={priority}",
+            worker.url,
+            hex::encode(job.miner),
+            job.nonce_group,
+            hex::encode(job.challenge),
+            hex::encode(job.difficulty),
+        );
+        loop {
+            let mut request = self.client.get(&url);
+            if let Some(owner) = owner {
+                request = request.header(OWNER_HEADER, owner);
+            }
+            let res = request.send().await?;
+            let status = res.status();
+            let text = res.text().await?;
+            let polled = match status {
+                StatusCode::OK => match text.parse() {
+                    Ok(pow) => Polled::Done(Ok(pow)),
+                    Err(e) => Polled::Done(Err(format!("invalid pow {text}: {e}"))),
+                },
+                StatusCode::INTERNAL_SERVER_ERROR => Polled::Done(Err(text)),
+                StatusCode::CREATED => {
+                    tokio::time::sleep(self.backoff).await;
+                    continue;
+                }
+                StatusCode::TOO_MANY_REQUESTS => Polled::Busy,
+                status => Polled::Done(Err(format!("unexpected status code {status}"))),
+            };
+            return Ok(polled);
+        }
+    }
+}
+
+impl GetOrCreate for Coordinator {
+    fn get_or_create(
+        &self,
+        job: Job,
+        owner: Option<String>,
+        priority: i32,
+    ) -> Result<(JobStatus, Option<String>), JobError> {
+        let mut jobs = self.inner.jobs.lock().unwrap();
+        jobs.prune(self.done_retention);
+        let job_owner = jobs.owners.get(&job).cloned();
+        check_owner(job_owner.as_ref(), owner.as_ref())?;
+        match jobs.states.get(&job) {
+            Some(JobState::Dispatched) => Ok((JobStatus::InProgress, job_owner)),
+            Some(JobState::Done { result, .. }) => Ok((JobStatus::Done(result.clone()), job_owner)),
+            None => {
+                let dispatched = jobs
+                    .states
+                    .values()
+                    .filter(|s| matches!(s, JobState::Dispatched))
+                    .count();
+                if dispatched >= self.max_jobs {
+                    return Err(JobError::TooManyJobs);
+                }
+                tracing::info!(
+                    "dispatching k2pow job: nonce group: {}, challenge: {}, miner {}",
+                    job.nonce_group,
+                    hex::encode(job.challenge),
+                    hex::encode(job.miner)
+                );
+                jobs.states.insert(job.clone(), JobState::Dispatched);
+                if let Some(owner) = &owner {
+                    jobs.owners.insert(job.clone(), owner.clone());
+                }
+
+                let inner = self.inner.clone();
+                let job_owner = owner.clone();
+                self.runtime.spawn(async move {
+                    let result = inner.dispatch(&job, job_owner.as_deref(), priority).await;
+                    tracing::debug!("k2pow job for nonce group {} done", job.nonce_group);
+                    let mut jobs = inner.jobs.lock().unwrap();
+                    let finished = Instant::now();
+                    jobs.states.insert(job, JobState::Done { result, finished });
+                });
+                Ok((JobStatus::Created, owner))
+            }
+        }
+    }
+
+    fn cancel(&self, job: Job, owner: Option<String>) -> Result<(), JobError> {
+        let mut jobs = self.inner.jobs.lock().unwrap();
+        jobs.prune(self.done_retention);
+        check_owner(jobs.owners.get(&job), owner.as_ref())?;
+        match jobs.states.get(&job) {
+            None => Err(JobError::NotFound),
+            // The worker might have started it already.
+            Some(JobState::Dispatched) => Err(JobError::InProgress),
+            Some(JobState::Done { .. }) => {
+                jobs.states.remove(&job);
+                jobs.owners.remove(&job);
+                Ok(())
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use httpmock::MockServer;
+
+    use super::*;
+
+    fn job(nonce_group: u8) -> Job {
+        Job {
+            nonce_group,
+            challenge: [1; 8],
+            difficulty: [0xff; 32],
+            miner: [2; 32],
+        }
+    }
+
+    async fn wait_done(
+        coordinator: &Coordinator,
+        job: Job,
+        owner: Option<&str>,
+    ) -> Result<u64, String> {
+        loop {
+            match coordinator.get_or_create(job.clone(), owner.map(str::to_string), 0) {
+                Ok((JobStatus::Done(result), _)) => return result,
+                Ok(_) => tokio::time::sleep(Duration::from_millis(10)).await,
+                Err(e) => panic!("unexpected {e:?}"),
+            }
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn dispatching_to_workers() {
+        let busy = MockServer::start_async().await;
+        let busy_job = busy
+            .mock_async(|when, then| {
+                when.path_contains("/job/");
+                then.status(429);
+            })
+            .await;
+        let worker = MockServer::start_async().await;
+        let worker_job = worker
+            .mock_async(|when, then| {
+                when.path_contains("/job/")
+                    .query_param("priority", "0")
+                    .header(OWNER_HEADER, "me");
+                then.status(200).body("42");
+            })
+            .await;
+
+        let coordinator = Coordinator::new(
+            vec![busy.url(""), worker.url("/")],
+            1,
+            Duration::from_millis(10),
+        );
+        assert_eq!(
+            Ok((JobStatus::Created, Some("me".into()))),
+            coordinator.get_or_create(job(0), Some("me".into()), 0)
+        );
+        // Only 1 job at a time
+        assert_eq!(
+            Err(JobError::TooManyJobs),
+            coordinator.get_or_create(job(1), None, 0)
+        );
+        assert_eq!(Ok(42), wait_done(&coordinator, job(0), Some("me")).await);
+        worker_job.assert_async().await;
+        assert!(busy_job.hits_async().await <= 1);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn skipping_unhealthy_workers() {
+        let worker = MockServer::start_async().await;
+        worker
+            .mock_async(|when, then| {
+                when.path_contains("/job/");
+                then.status(200).body("7");
+            })
+            .await;
+        worker
+            .mock_async(|when, then| {
+                when.path("/");
+                then.status(200);
+            })
+            .await;
+
+        // Nothing listens on the first one
+        let coordinator = Coordinator::new(
+            vec!["http://127.0.0.1:1".into(), worker.url("")],
+            2,
+            Duration::from_millis(10),
+        );
+        coordinator.spawn_health_checks(Duration::from_millis(10));
+        coordinator.get_or_create(job(0), None, 0).unwrap();
+        coordinator.get_or_create(job(1), None, 0).unwrap();
+        assert_eq!(Ok(7), wait_done(&coordinator, job(0), None).await);
+        assert_eq!(Ok(7), wait_done(&coordinator, job(1), None).await);
+        assert!(!coordinator.inner.workers[0].healthy.load(Ordering::Relaxed));
+        assert!(coordinator.inner.workers[1].healthy.load(Ordering::Relaxed));
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn reporting_job_errors() {
+        let worker = MockServer::start_async().await;
+        worker
+            .mock_async(|when, then| {
+                when.path_contains("/job/");
+                then.status(500).body("failed");
+            })
+            .await;
+        let coordinator = Coordinator::new(vec![worker.url("")], 1, Duration::from_millis(10));
+        coordinator.get_or_create(job(0), None, 0).unwrap();
+        assert_eq!(
+            Err("failed".into()),
+            wait_done(&coordinator, job(0), None).await
+        );
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn pruning_finished_jobs() {
+        let worker = MockServer::start_async().await;
+        worker
+            .mock_async(|when, then| {
+                when.path_contains("/job/");
+                then.status(200).body("42");
+            })
+            .await;
+        let coordinator = Coordinator::new(vec![worker.url("")], 1, Duration::from_millis(10))
+            .with_done_retention(Duration::from_millis(100));
+        coordinator
+            .get_or_create(job(0), Some("me".into()), 0)
+            .unwrap();
+        assert_eq!(Ok(42), wait_done(&coordinator, job(0), Some("me")).await);
+        assert_eq!(
+            Err(JobError::NotOwner),
+            coordinator.get_or_create(job(0), None, 0)
+        );
+        assert_eq!(
+            Err(JobError::NotOwner),
+            coordinator.cancel(job(0), Some("other".into()))
+        );
+
+        tokio::time::sleep(Duration::from_millis(150)).await;
+        coordinator.get_or_create(job(1), None, 0).unwrap();
+        let jobs = coordinator.inner.jobs.lock().unwrap();
+        assert!(!jobs.states.contains_key(&job(0)));
+        assert!(jobs.owners.is_empty());
+    }
+}
@@ -1,27 +1,52 @@
-use crate::{create_thread_pool, PoW};
-use post::pow::Prover;
+use crate::create_thread_pool;
+use post::topology::Topology;
+use std::cmp::Reverse;
 use std::collections::HashMap;
 use std::sync::Mutex;
+use std::time::{Duration, Instant};
 use thiserror::Error;
 
+/// How long the results of finished jobs are kept for the clients to fetch them.
+pub const DONE_RETENTION: Duration = Duration::from_secs(10 * 60);
+
 #[derive(Error, Debug, PartialEq)]
 pub enum JobError {
     #[error("too many active jobs")]
     TooManyJobs,
+    #[error("the job is owned by another client")]
+    NotOwner,
+    #[error("no such job")]
+    NotFound,
+    #[error("the job is already in progress")]
+    InProgress,
+}
+
+/// Check that a request for a job comes from its owner.
+/// The jobs created without an owner token are accessible to anyone.
+pub fn check_owner(job_owner: Option<&String>, owner: Option<&String>) -> Result<(), JobError> {
+    match job_owner {
+        Some(job_owner) if Some(job_owner) != owner => Err(JobError::NotOwner),
+        _ => Ok(()),
+    }
 }
 
 /// JobStatus is used to expose job state to external callers
 #[derive(Clone, Debug, PartialEq)]
 pub enum JobStatus {
     Created,
+    Queued,
     InProgress,
     Done(Result<u64, String>),
 }
 
 #[derive(Debug)]
 enum JobState {
+    Queued,
     InProgress(Option<std::thread::JoinHandle<Result<u64, post::pow::Error>>>),
-    Done(Result<u64, String>),
+    Done {
+        result: Result<u64, String>,
+        finished: Instant,
+    },
 }
 
 #[derive(Hash, Eq, PartialEq, Debug, Clone)]
@@ -34,32 +59,138 @@ pub struct Job {
 
 #[cfg_attr(test, mockall::automock)]
 pub trait GetOrCreate {
-    fn get_or_create(&self, job: Job) -> Result<JobStatus, JobError>;
+    /// Get the status of the job, creating it if it doesn't exist yet.
+    /// Returns the status and the owner of the job (the client that created it, if known).
+    /// A job with an owner is accessible only with the same `owner` token.
+    ///
+    /// The `priority` is a hint for ordering the queued jobs (higher first).
+    fn get_or_create(
+        &self,
+        job: Job,
+        owner: Option<String>,
+        priority: i32,
+    ) -> Result<(JobStatus, Option<String>), JobError>;
+
+    /// Cancel a job that didn't start yet or forget the result of a finished one.
+    /// A job with an owner can be cancelled only with the same `owner` token.
+    fn cancel(&self, job: Job, owner: Option<String>) -> Result<(), JobError>;
+}
+
+#[derive(Debug)]
+struct QueuedJob {
+    job: Job,
+    priority: i32,
+    // the order of arrival
+    seq: u64,
+}
+
+/// Pick the position of the job to start next: the one with the highest priority
+/// (the oldest one if equal), but the lowest nonce group of the same miner and challenge
+/// with the same priority, so a client gets the pows in the order it needs them.
+fn next_queued(queue: &[QueuedJob]) -> Option<usize> {
+    let best = queue.iter().max_by_key(|q| (q.priority, Reverse(q.seq)))?;
+    queue
+        .iter()
+        .enumerate()
+        .filter(|(_, q)| {
+            q.priority == best.priority
+                && q.job.miner == best.job.miner
+                && q.job.challenge == best.job.challenge
+        })
+        .min_by_key(|(_, q)| (q.job.nonce_group, q.seq))
+        .map(|(pos, _)| pos)
 }
 
 struct Jobs {
     in_progress: Option<Job>,
+    queue: Vec<QueuedJob>,
+    next_seq: u64,
     states: HashMap<Job, JobState>,
+    owners: HashMap<Job, String>,
 }
+
+impl Jobs {
+    /// Forget the jobs finished more than `retention` ago.
+    fn prune(&mut self, retention: Duration) {
+        self.states.retain(|_, state| match state {
+            JobState::Done { finished, .. } => finished.elapsed() < retention,
+            _ => true,
+        });
+        let states = &self.states;
+        self.owners.retain(|job, _| states.contains_key(job));
+    }
+
+    fn remove(&mut self, job: &Job) {
+        self.states.remove(job);
+        self.owners.remove(job);
+        self.queue.retain(|q| &q.job != job);
+    }
+}
+
 pub struct JobManager {
     jobs: Mutex<Jobs>,
+    queue_size: usize,
+    done_retention: Duration,
+    randomx_cache_key: Vec<u8>,
     cores: u8,
+    pow_version: post::pow::Version,
     randomx_mode: crate::RandomXMode,
     randomx_large_pages: bool,
+    efficiency_cores: bool,
 }
 
 impl JobManager {
-    pub fn new(cores: u8, randomx_mode: crate::RandomXMode, randomx_large_pages: bool) -> Self {
+    pub fn new(
+        cores: u8,
+        pow_version: post::pow::Version,
+        randomx_mode: crate::RandomXMode,
+        randomx_large_pages: bool,
+    ) -> Self {
         JobManager {
             jobs: Mutex::new(Jobs {
                 in_progress: None,
+                queue: Vec::new(),
+                next_seq: 0,
                 states: HashMap::new(),
+                owners: HashMap::new(),
             }),
+            queue_size: 0,
+            done_retention: DONE_RETENTION,
+            randomx_cache_key: post::pow::randomx::DEFAULT_CACHE_KEY.to_vec(),
             cores,
+            pow_version,
             randomx_mode,
             randomx_large_pages,
+            efficiency_cores: false,
         }
     }
+
+    /// Prove also on the efficiency cores of hybrid CPUs.
+    /// By default, the jobs run only on the performance cores.
+    pub fn with_efficiency_cores(mut self, allowed: bool) -> Self {
+        self.efficiency_cores = allowed;
+        self
+    }
+
+    /// Queue up to `queue_size` jobs while a job is in progress,
+    /// instead of rejecting them with [JobError::TooManyJobs].
+    pub fn with_queue_size(mut self, queue_size: usize) -> Self {
+        self.queue_size = queue_size;
+        self
+    }
+
+    /// Keep the results of finished jobs for `retention` (10 minutes by default).
+    pub fn with_done_retention(mut self, retention: Duration) -> Self {
+        self.done_retention = retention;
+        self
+    }
+
+    /// Prove with the RandomX cache key of a network (see [post::pow::randomx::cache_key]).
+    pub fn with_randomx_cache_key(mut self, cache_key: Vec<u8>) -> Self {
+        self.randomx_cache_key = cache_key;
+        self
+    }
+
     fn check_finished(&self) {
         let mut hs = self.jobs.lock().unwrap();
         if hs.in_progress.is_none() {
@@ -70,89 +201,151 @@ impl JobManager {
         if let JobState::InProgress(handle) = entry {
             if handle.as_ref().unwrap().is_finished() {
                 let val = match handle.take().unwrap().join() {
-                    Ok(result) => JobState::Done(match result {
-                        Ok(v) => Ok(v),
-                        Err(e) => Err(e.to_string()),
-                    }),
+                    Ok(result) => JobState::Done {
+                        result: result.map_err(|e| e.to_string()),
+                        finished: Instant::now(),
+                    },
                     Err(e) => std::panic::resume_unwind(e),
                 };
                 *entry = val;
                 hs.in_progress.take();
+                if let Some(pos) = next_queued(&hs.queue) {
+                    let next = hs.queue.remove(pos);
+                    self.start(&mut hs, next.job);
+                }
             }
         };
     }
+
+    fn start(&self, hs: &mut Jobs, job: Job) {
+        let mut randomx_flags = post::pow::randomx::RandomXFlag::from(self.randomx_mode);
+        if self.randomx_large_pages {
+            eprintln!("Using large pages for RandomX");
+            randomx_flags |= post::pow::randomx::RandomXFlag::FLAG_LARGE_PAGES;
+        }
+
+        eprintln!("RandomX flags: {}", randomx_flags);
+
+        tracing::info!(
+            "took k2pow job: nonce group: {}, challenge: {}, difficulty: {}, miner {}",
+            job.nonce_group,
+            hex::encode(job.challenge),
+            hex::encode(job.difficulty),
+            hex::encode(job.miner)
+        );
+        let cores = match self.cores {
+            0 => crate::Cores::All,
+            v => crate::Cores::Any(v as usize),
+        };
+        let cores = match (self.efficiency_cores, Topology::detect()) {
+            (false, Some(topology)) => topology.avoid_efficiency_cores(cores),
+            _ => cores,
+        };
+        let pow_version = self.pow_version;
+        let cache_key = self.randomx_cache_key.clone();
+        let job_clone = job.clone();
+        let span = tracing::info_span!("k2pow_job", nonce_group = job.nonce_group);
+        let handle = std::thread::spawn(move || {
+            let _span = span.entered();
+            let pool = create_thread_pool(cores, |_| {}).unwrap();
+            pool.install(|| -> Result<u64, post::pow::Error> {
+                let pow = post::pow::new_prover(pow_version, randomx_flags, &cache_key).unwrap();
+                tracing::debug!(
+                    "proving k2pow: nonce group: {}, challenge: {}, difficulty: {}, miner {}",
+                    job_clone.nonce_group,
+                    hex::encode(job_clone.challenge),
+                    hex::encode(job_clone.difficulty),
+                    hex::encode(job_clone.miner)
+                );
+                let res = pow.prove(
+                    job_clone.nonce_group,
+                    &job_clone.challenge,
+                    &job_clone.difficulty,
+                    &job_clone.miner,
+                )?;
+                tracing::debug!("k2pow result: {}", res);
+                Ok(res)
+            })
+        });
+
+        hs.in_progress = Some(job.clone());
+        hs.states.insert(job, JobState::InProgress(Some(handle)));
+    }
 }
 
 impl GetOrCreate for JobManager {
-    fn get_or_create(&self, job: Job) -> Result<JobStatus, JobError> {
+    fn get_or_create(
+        &self,
+        job: Job,
+        owner: Option<String>,
+        priority: i32,
+    ) -> Result<(JobStatus, Option<String>), JobError> {
         self.check_finished();
         let mut hs = self.jobs.lock().unwrap();
+        hs.prune(self.done_retention);
 
+        let job_owner = hs.owners.get(&job).cloned();
+        check_owner(job_owner.as_ref(), owner.as_ref())?;
         match hs.states.get(&job) {
-            Some(JobState::InProgress(_)) => Ok(JobStatus::InProgress),
-            Some(JobState::Done(result)) => Ok(JobStatus::Done(result.clone())),
+            Some(JobState::Queued) => {
+                // The highest priority hint of the callers wins.
+                if let Some(queued) = hs.queue.iter_mut().find(|q| q.job == job) {
+                    queued.priority = queued.priority.max(priority);
+                }
+                Ok((JobStatus::Queued, job_owner))
+            }
+            Some(JobState::InProgress(_)) => Ok((JobStatus::InProgress, job_owner)),
+            Some(JobState::Done { result, .. }) => Ok((JobStatus::Done(result.clone()), job_owner)),
             None => {
-                if hs.in_progress.is_some() {
+                let status = if hs.in_progress.is_none() {
+                    self.start(&mut hs, job.clone());
+                    JobStatus::Created
+                } else if hs.queue.len() < self.queue_size {
+                    tracing::info!(
+                        "queued k2pow job: nonce group: {}, priority: {priority}, queue length: {}",
+                        job.nonce_group,
+                        hs.queue.len() + 1
+                    );
+                    let seq = hs.next_seq;
+                    hs.next_seq += 1;
+                    hs.queue.push(QueuedJob {
+                        job: job.clone(),
+                        priority,
+                        seq,
+                    });
+                    hs.states.insert(job.clone(), JobState::Queued);
+                    JobStatus::Queued
+                } else {
                     // if we're here it means:
                     // - there's a job in progress
                     // - it's not this job (because we didn't get a result from HashMap.get)
                     // - it's not done either (covered by the earlier match arm)
+                    // - the queue is full
                     return Err(JobError::TooManyJobs);
-                }
-
-                let mut randomx_flags = match self.randomx_mode {
-                    crate::RandomXMode::Fast => {
-                        post::pow::randomx::RandomXFlag::get_recommended_flags()
-                            | post::pow::randomx::RandomXFlag::FLAG_FULL_MEM
-                    }
-                    crate::RandomXMode::Light => {
-                        post::pow::randomx::RandomXFlag::get_recommended_flags()
-                    }
                 };
-                if self.randomx_large_pages {
-                    eprintln!("Using large pages for RandomX");
-                    randomx_flags |= post::pow::randomx::RandomXFlag::FLAG_LARGE_PAGES;
+
+                if let Some(owner) = &owner {
+                    hs.owners.insert(job, owner.clone());
                 }
+                Ok((status, owner))
+            }
+        }
+    }
 
-                eprintln!("RandomX flags: {}", randomx_flags);
+    fn cancel(&self, job: Job, owner: Option<String>) -> Result<(), JobError> {
+        self.check_finished();
+        let mut hs = self.jobs.lock().unwrap();
+        hs.prune(self.done_retention);
 
-                tracing::info!(
-                    "took k2pow job: nonce group: {}, challenge: {}, difficulty: {}, miner {}",
-                    job.nonce_group,
-                    hex::encode(job.challenge),
-                    hex::encode(job.difficulty),
-                    hex::encode(job.miner)
-                );
-                let cores = match self.cores {
-                    0 => crate::Cores::All,
-                    v => crate::Cores::Any(v as usize),
-                };
-                let job_clone = job.clone();
-                let handle = std::thread::spawn(move || {
-                    let pool = create_thread_pool(cores, |_| {}).unwrap();
-                    pool.install(|| -> Result<u64, post::pow::Error> {
-                        let pow = PoW::new(randomx_flags).unwrap();
-                        tracing::debug!(
-                    "proving k2pow: nonce group: {}, challenge: {}, difficulty: {}, miner {}",
-                    job_clone.nonce_group,
-                    hex::encode(job_clone.challenge),
-                    hex::encode(job_clone.difficulty),
-                    hex::encode(job_clone.miner)
-                );
-                        let res = pow.prove(
-                            job_clone.nonce_group,
-                            &job_clone.challenge,
-                            &job_clone.difficulty,
-                            &job_clone.miner,
-                        )?;
-                        tracing::debug!("k2pow result: {}", res);
-                        Ok(res)
-                    })
-                });
-
-                hs.in_progress = Some(job.clone());
-                hs.states.insert(job, JobState::InProgress(Some(handle)));
-                Ok(JobStatus::Created)
+        check_owner(hs.owners.get(&job), owner.as_ref())?;
+        match hs.states.get(&job) {
+            None => Err(JobError::NotFound),
+            // The proving thread can't be interrupted.
+            Some(JobState::InProgress(_)) => Err(JobError::InProgress),
+            Some(JobState::Queued | JobState::Done { .. }) => {
+                tracing::info!("cancelled k2pow job: nonce group: {}", job.nonce_group);
+                hs.remove(&job);
+                Ok(())
             }
         }
     }
@@ -160,11 +353,94 @@ impl GetOrCreate for JobManager {
 
 #[cfg(test)]
 mod tests {
-    use super::{GetOrCreate, JobError, JobStatus};
+    use super::{next_queued, GetOrCreate, Job, JobError, JobStatus, QueuedJob};
+
+    fn job(miner: u8, nonce_group: u8) -> Job {
+        Job {
+            nonce_group,
+            challenge: [1, 2, 3, 4, 5, 6, 7, 8],
+            difficulty: [0xff; 32],
+            miner: [miner; 32],
+        }
+    }
+
+    #[test]
+    fn picking_next_queued_job() {
+        let queued = |miner, nonce_group, priority, seq| QueuedJob {
+            job: job(miner, nonce_group),
+            priority,
+            seq,
+        };
+        assert_eq!(None, next_queued(&[]));
+
+        // The oldest first
+        let queue = [queued(1, 0, 0, 0), queued(2, 0, 0, 1)];
+        assert_eq!(Some(0), next_queued(&queue));
+
+        // The lowest nonce group of the same miner first
+        let queue = [queued(1, 5, 0, 0), queued(2, 0, 0, 1), queued(1, 2, 0, 2)];
+        assert_eq!(Some(2), next_queued(&queue));
+
+        // The highest priority first
+        let queue = [queued(1, 5, 0, 0), queued(2, 3, 1, 1), queued(1, 2, 0, 2)];
+        assert_eq!(Some(1), next_queued(&queue));
+    }
+
+    #[test]
+    fn queueing_jobs() {
+        let job_manager = super::JobManager::new(
+            1,
+            post::pow::Version::RandomX,
+            crate::RandomXMode::Light,
+            false,
+        )
+        .with_queue_size(2);
+
+        assert_eq!(
+            Ok((JobStatus::Created, None)),
+            job_manager.get_or_create(job(1, 0), None, 0)
+        );
+        assert_eq!(
+            Ok((JobStatus::Queued, Some("me".into()))),
+            job_manager.get_or_create(job(1, 2), Some("me".into()), 0)
+        );
+        assert_eq!(
+            Ok((JobStatus::Queued, None)),
+            job_manager.get_or_create(job(1, 1), None, 0)
+        );
+        assert_eq!(
+            Err(JobError::TooManyJobs),
+            job_manager.get_or_create(job(1, 3), None, 0)
+        );
+
+        // The queued jobs are started as the previous ones finish
+        let mut done = Vec::new();
+        while done.len() < 3 {
+            for nonce_group in 0..3 {
+                if done.contains(&nonce_group) {
+                    continue;
+                }
+                let owner = (nonce_group == 2).then(|| "me".to_string());
+                match job_manager.get_or_create(job(1, nonce_group), owner, 0) {
+                    Ok((JobStatus::Done(Ok(_)), _)) => done.push(nonce_group),
+                    Ok((JobStatus::Queued | JobStatus::InProgress, _)) => {}
+                    other => panic!("unexpected {other:?}"),
+                }
+            }
+            std::thread::sleep(std::time::Duration::from_millis(10));
+        }
+        // In the nonce group order
+        assert_eq!(vec![0, 1, 2], done);
+    }
 
     #[test]
     fn test_job_manager() {
-        let job_manager = super::JobManager::new(1, crate::RandomXMode::Light, false);
+        let job_manager = super::JobManager::new(
+            1,
+            post::pow::Version::RandomX,
+            crate::RandomXMode::Light,
+            false,
+        );
         let job = super::Job {
             nonce_group: 11,
             challenge: [1, 2, 3, 4, 5, 6, 7, 8],
@@ -175,45 +451,129 @@ mod tests {
             ],
         };
 
-        match job_manager.get_or_create(job.clone()) {
-            Ok(JobStatus::Created) => (),
+        match job_manager.get_or_create(job.clone(), Some("me".into()), 0) {
+            Ok((JobStatus::Created, Some(owner))) if owner == "me" => (),
             _ => panic!("shouldnt happen"),
         };
-        // try to insert the same one twice
-        match job_manager.get_or_create(job.clone()) {
-            Ok(JobStatus::InProgress) => (),
+        // try to insert the same one twice, the job keeps its owner
+        match job_manager.get_or_create(job.clone(), Some("me".into()), 0) {
+            Ok((JobStatus::InProgress, Some(owner))) if owner == "me" => (),
             _ => panic!("shouldnt happen"),
         };
 
         // try to insert a new job but expect too many jobs
         let mut job2 = job.clone();
         job2.nonce_group = 14;
-        match job_manager.get_or_create(job2.clone()) {
+        match job_manager.get_or_create(job2.clone(), None, 0) {
             Err(JobError::TooManyJobs) => (),
             _ => panic!("shouldnt happen"),
         };
-        match job_manager.get_or_create(job.clone()) {
-            Ok(JobStatus::InProgress) => (),
+        match job_manager.get_or_create(job.clone(), Some("me".into()), 0) {
+            Ok((JobStatus::InProgress, _)) => (),
             _ => panic!("shouldnt happen"),
         };
 
         // loop until the calculation sets the correct result in the hashmap.
         // since the test difficulty is easy, this shouldn't take long.
         loop {
-            match job_manager.get_or_create(job.clone()) {
-                Ok(JobStatus::Done(Ok(_))) => break,
-                Ok(JobStatus::Done(Err(_))) => panic!("shouldnt happen"),
-                Ok(JobStatus::Created) => panic!("shouldnt happen"),
-                Ok(JobStatus::InProgress) => {
+            match job_manager.get_or_create(job.clone(), Some("me".into()), 0) {
+                Ok((JobStatus::Done(Ok(_)), _)) => break,
+                Ok((JobStatus::Done(Err(_)), _)) => panic!("shouldnt happen"),
+                Ok((JobStatus::Created | JobStatus::Queued, _)) => panic!("shouldnt happen"),
+                Ok((JobStatus::InProgress, _)) => {
                     std::thread::sleep(std::time::Duration::from_millis(50))
                 }
                 Err(_) => panic!(),
             }
         }
+        // other clients can't access it
+        assert_eq!(
+            Err(JobError::NotOwner),
+            job_manager.get_or_create(job.clone(), Some("other".into()), 0)
+        );
+        assert_eq!(
+            Err(JobError::NotOwner),
+            job_manager.get_or_create(job.clone(), None, 0)
+        );
         // since the first job is now marked as errored, we can insert job 2
-        match job_manager.get_or_create(job2) {
-            Ok(JobStatus::Created) => (),
+        match job_manager.get_or_create(job2, None, 0) {
+            Ok((JobStatus::Created, None)) => (),
             _ => panic!("shouldnt happen"),
         }
     }
+
+    #[test]
+    fn cancelling_jobs() {
+        let job_manager = super::JobManager::new(
+            1,
+            post::pow::Version::RandomX,
+            crate::RandomXMode::Light,
+            false,
+        )
+        .with_queue_size(1);
+        let me = || Some("me".to_string());
+
+        assert_eq!(Err(JobError::NotFound), job_manager.cancel(job(1, 0), me()));
+        // A job in progress until told to finish
+        let (finish, finished) = std::sync::mpsc::channel::<()>();
+        {
+            let mut jobs = job_manager.jobs.lock().unwrap();
+            let handle = std::thread::spawn(move || {
+                let _ = finished.recv();
+                Ok(0)
+            });
+            jobs.in_progress = Some(job(1, 0));
+            jobs.states
+                .insert(job(1, 0), super::JobState::InProgress(Some(handle)));
+            jobs.owners.insert(job(1, 0), "me".into());
+        }
+        job_manager.get_or_create(job(1, 1), me(), 0).unwrap();
+        assert_eq!(
+            Err(JobError::InProgress),
+            job_manager.cancel(job(1, 0), me())
+        );
+        assert_eq!(
+            Err(JobError::NotOwner),
+            job_manager.cancel(job(1, 1), Some("other".into()))
+        );
+        assert_eq!(Ok(()), job_manager.cancel(job(1, 1), me()));
+        // The queue has room again
+        job_manager.get_or_create(job(1, 2), None, 0).unwrap();
+        assert_eq!(Ok(()), job_manager.cancel(job(1, 2), None));
+        {
+            let jobs = job_manager.jobs.lock().unwrap();
+            assert!(jobs.queue.is_empty());
+            assert_eq!(1, jobs.owners.len());
+        }
+
+        drop(finish);
+        while job_manager.cancel(job(1, 0), me()) == Err(JobError::InProgress) {
+            std::thread::sleep(std::time::Duration::from_millis(10));
+        }
+        assert_eq!(Err(JobError::NotFound), job_manager.cancel(job(1, 0), me()));
+    }
+
+    #[test]
+    fn pruning_finished_jobs() {
+        let job_manager = super::JobManager::new(
+            1,
+            post::pow::Version::RandomX,
+            crate::RandomXMode::Light,
+            false,
+        )
+        .with_done_retention(std::time::Duration::from_millis(100));
+        let me = || Some("me".to_string());
+
+        while !matches!(
+            job_manager.get_or_create(job(1, 0), me(), 0),
+            Ok((JobStatus::Done(_), _))
+        ) {
+            std::thread::sleep(std::time::Duration::from_millis(10));
+        }
+        std::thread::sleep(std::time::Duration::from_millis(150));
+        job_manager.get_or_create(job(1, 1), None, 0).unwrap();
+        let jobs = job_manager.jobs.lock().unwrap();
+        assert!(!jobs.states.contains_key(&job(1, 0)));
+        assert!(jobs.owners.is_empty());
+    }
 }
@@ -1,26 +1,31 @@
 use crate::job_manager::GetOrCreate;
-use axum::extract::Path;
+use axum::extract::{Path, Query};
 use axum::response::IntoResponse;
 use axum::routing::{get, Router};
 use axum::{
     extract::State,
-    http::{Request, StatusCode},
+    http::{HeaderMap, HeaderValue, Request, StatusCode},
     response::Response,
 };
 use clap::{arg, Parser, ValueEnum};
 use post::config::Cores;
-use post::pow::randomx::PoW;
+use post::pow::randomx::{self, LargePages, RandomXFlag};
+use post::pow::service::OWNER_HEADER;
 use post::prove::create_thread_pool;
 use serde::Deserialize;
 use serde_with::serde_as;
+use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr};
 use std::sync::Arc;
 use std::time::Duration;
 use tower_http::{classify::ServerErrorsFailureClass, trace::TraceLayer};
 use tracing::{info_span, Span};
 use tracing_log::LogTracer;
-use tracing_subscriber::{EnvFilter, FmtSubscriber};
+use tracing_subscriber::{layer::SubscriberExt, EnvFilter};
 
+mod coordinator;
 mod job_manager;
+#[cfg(feature = "otlp")]
+mod telemetry;
 use job_manager::{JobError, JobStatus};
 
 #[derive(Parser, Debug)]
@@ -30,17 +35,71 @@ struct Cli {
     #[arg(short, long, default_value = "0.0.0.0:3000")]
     bind_address: String,
 
+    /// check the health of a running k2pow-service listening on `--bind-address` and exit.
+    /// Exits with an error if it doesn't respond with `200 OK`, i.e. for `HEALTHCHECK` in Docker.
+    #[arg(long)]
+    healthcheck: bool,
+
     /// the number of cores to use. the optimal value depends
     /// on the type of CPU used. `0` means use all cores.
     #[arg(long, default_value = "0")]
     cores: u8,
 
+    /// prove also on the efficiency cores of hybrid CPUs (i.e. Intel Alder Lake).
+    /// By default, only the performance cores are used on hybrid CPUs.
+    #[arg(long)]
+    efficiency_cores: bool,
+
+    /// the number of jobs to queue while a job is in progress.
+    /// Requests for more jobs are rejected with `429 Too Many Requests`.
+    #[arg(long, default_value_t = 0)]
+    queue_size: usize,
+
+    /// base URLs of downstream k2pow-service workers (comma separated).
+    /// If set, the service runs as a coordinator dispatching the jobs to the workers
+    /// instead of proving them itself.
+    ///
+    /// The number of jobs a worker takes at a time can be given as `<url>=<jobs>`,
+    /// by default it's one job plus `--queue-size`.
+    #[arg(long, value_delimiter = ',')]
+    workers: Vec<WorkerArg>,
+
+    /// interval (in seconds) of checking the health of the workers in the coordinator mode.
+    #[arg(long, default_value = "10", value_parser = |secs: &str| secs.parse().map(Duration::from_secs))]
+    health_check_interval_s: Duration,
+
+    /// interval (in milliseconds) of polling the jobs on the workers (and retrying
+    /// on busy workers) in the coordinator mode.
+    #[arg(long, default_value = "1000", value_parser = |ms: &str| ms.parse().map(Duration::from_millis))]
+    worker_backoff_ms: Duration,
+
+    /// how long (in seconds) the results of finished jobs are kept for the post services
+    /// to fetch them.
+    #[arg(long, default_value = "600", value_parser = |secs: &str| secs.parse().map(Duration::from_secs))]
+    job_retention_s: Duration,
+
+    /// the PoW algorithm. It must match the one used by the nodes.
+    #[arg(long, default_value_t = post::pow::Version::RandomX)]
+    pow_version: post::pow::Version,
+
+    /// ID of the network separating its RandomX PoWs from other networks (i.e. testnets).
+    /// It must match the one used by the nodes. Mainnet has none.
+    #[arg(long)]
+    randomx_network_id: Option<String>,
+
     #[arg(long, default_value_t = RandomXMode::Fast)]
     randomx_mode: RandomXMode,
 
-    /// allocate RandomX memory in large pages.
-    #[arg(long, default_value = "false")]
-    randomx_large_pages: bool,
+    /// allocate RandomX memory in large pages (`on`, `off` or `auto`).
+    /// `auto` uses large pages only if they are available in the OS.
+    #[arg(long, default_value_t = LargePages::Off, num_args = 0..=1, default_missing_value = "on")]
+    randomx_large_pages: LargePages,
+
+    /// OTLP (gRPC) endpoint of an OpenTelemetry collector to export traces to,
+    /// e.g. `http://localhost:4317`. Traces are not exported if not specified.
+    #[cfg(feature = "otlp")]
+    #[arg(long)]
+    otlp_endpoint: Option<String>,
 }
 
 /// RandomX modes of operation
@@ -53,6 +112,30 @@ enum RandomXMode {
     Fast,
     /// Light mode for verification. Requires only 256 MiB of memory, but runs significantly slower
     Light,
+    /// Fast mode if enough memory (or free huge pages) is available at startup, light otherwise.
+    Auto,
+}
+
+impl RandomXMode {
+    /// Resolve the [RandomXMode::Auto] mode by the memory available in the OS
+    /// (see [randomx::auto_fast_mode], which logs the decision).
+    fn resolve(self, large_pages: LargePages) -> Self {
+        match self {
+            RandomXMode::Auto if randomx::auto_fast_mode(large_pages) => RandomXMode::Fast,
+            RandomXMode::Auto => RandomXMode::Light,
+            mode => mode,
+        }
+    }
+}
+
+impl From<RandomXMode> for RandomXFlag {
+    fn from(val: RandomXMode) -> Self {
+        match val {
+            RandomXMode::Fast => RandomXFlag::get_recommended_flags() | RandomXFlag::FLAG_FULL_MEM,
+            // Auto is resolved at startup, fall back to the mode that fits anywhere.
+            RandomXMode::Light | RandomXMode::Auto => RandomXFlag::get_recommended_flags(),
+        }
+    }
 }
 
 impl std::fmt::Display for RandomXMode {
@@ -60,26 +143,122 @@ impl std::fmt::Display for RandomXMode {
         match self {
             RandomXMode::Fast => write!(f, "fast"),
             RandomXMode::Light => write!(f, "light"),
+            RandomXMode::Auto => write!(f, "auto"),
         }
     }
 }
 
+/// A downstream worker in the coordinator mode: `<url>` or `<url>=<jobs>`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct WorkerArg {
+    url: String,
+    /// How many jobs the worker takes at a time (proving and queued).
+    jobs: Option<usize>,
+}
+
+impl std::str::FromStr for WorkerArg {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let Some((url, jobs)) = s.rsplit_once('=') else {
+            return Ok(Self {
+                url: s.to_string(),
+                jobs: None,
+            });
+        };
+        match jobs.parse() {
+            Ok(0) => Err(format!("worker {url} must take at least one job")),
+            Ok(jobs) => Ok(Self {
+                url: url.to_string(),
+                jobs: Some(jobs),
+            }),
+            Err(e) => Err(format!("invalid number of jobs of worker {url}: {e}")),
+        }
+    }
+}
+
+/// The URL of the health endpoint of the service listening on `bind_address`,
+/// on the loopback interface if it listens on all interfaces.
+fn health_url(bind_address: &str) -> String {
+    let address = match bind_address.parse::<SocketAddr>() {
+        Ok(address) if address.ip().is_ipv4() && address.ip().is_unspecified() => {
+            SocketAddr::from((Ipv4Addr::LOCALHOST, address.port())).to_string()
+        }
+        Ok(address) if address.ip().is_unspecified() => {
+            SocketAddr::from((Ipv6Addr::LOCALHOST, address.port())).to_string()
+        }
+        _ => bind_address.to_string(),
+    };
+    format!("http://{address}/")
+}
+
+async fn healthcheck(bind_address: &str) -> Result<(), Box<dyn std::error::Error>> {
+    let url = health_url(bind_address);
+    let response = reqwest::Client::new()
+        .get(&url)
+        .timeout(Duration::from_secs(5))
+        .send()
+        .await?;
+    if !response.status().is_success() {
+        return Err(format!("unhealthy: {url} responded with {}", response.status()).into());
+    }
+    Ok(())
+}
+
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
     let args = Cli::parse();
+    if args.healthcheck {
+        return healthcheck(&args.bind_address).await;
+    }
 
     LogTracer::init()?;
     let env_filter = EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("INFO"));
-    let subscriber = FmtSubscriber::builder()
-        .with_env_filter(env_filter)
-        .finish();
+    let subscriber = tracing_subscriber::registry()
+        .with(env_filter)
+        .with(tracing_subscriber::fmt::layer());
+
+    #[cfg(feature = "otlp")]
+    let (subscriber, _otlp_guard) = match &args.otlp_endpoint {
+        Some(endpoint) => {
+            let (layer, guard) = telemetry::layer(endpoint)?;
+            (subscriber.with(Some(layer)), Some(guard))
+        }
+        None => (subscriber.with(None), None),
+    };
     tracing::subscriber::set_global_default(subscriber)?;
-    let job_manager = Arc::new(job_manager::JobManager::new(
-        args.cores,
-        args.randomx_mode,
-        args.randomx_large_pages,
-    ));
-    let router = router(job_manager);
+
+    let router = if args.workers.is_empty() {
+        let randomx_mode = args.randomx_mode.resolve(args.randomx_large_pages);
+        // Check if large pages are available upfront instead of failing on the first job.
+        let randomx_large_pages =
+            randomx::with_large_pages(randomx_mode.into(), args.randomx_large_pages)?
+                .contains(RandomXFlag::FLAG_LARGE_PAGES);
+        let job_manager = job_manager::JobManager::new(
+            args.cores,
+            args.pow_version,
+            randomx_mode,
+            randomx_large_pages,
+        )
+        .with_queue_size(args.queue_size)
+        .with_efficiency_cores(args.efficiency_cores)
+        .with_done_retention(args.job_retention_s)
+        .with_randomx_cache_key(randomx::cache_key(args.randomx_network_id.as_deref()));
+        router(Arc::new(job_manager))
+    } else {
+        tracing::info!("dispatching jobs to workers: {:?}", args.workers);
+        // Unless configured otherwise, a worker proves a job and queues up to `queue_size` more.
+        let max_jobs = args
+            .workers
+            .iter()
+            .map(|w| w.jobs.unwrap_or(1 + args.queue_size))
+            .sum();
+        let workers = args.workers.into_iter().map(|w| w.url).collect();
+        let coordinator = coordinator::Coordinator::new(workers, max_jobs, args.worker_backoff_ms)
+            .with_done_retention(args.job_retention_s);
+        coordinator.spawn_health_checks(args.health_check_interval_s);
+        router(Arc::new(coordinator))
+    };
     tracing::info!(
         "starting http server with bind address: {}",
         args.bind_address
@@ -97,7 +276,7 @@ fn router<T: GetOrCreate + Send + Sync + 'static>(job_manager: Arc<T>) -> Router
         .route("/", get(root))
         .route(
             "/job/{miner}/{nonce_group}/{challenge}/{difficulty}",
-            get(get_job),
+            get(get_job).delete(cancel_job),
         )
         .with_state(job_manager)
         .layer(
@@ -105,12 +284,15 @@ fn router<T: GetOrCreate + Send + Sync + 'static>(job_manager: Arc<T>) -> Router
                 .make_span_with(|request: &Request<_>| {
                     let matched_path = request.uri().to_string();
 
-                    info_span!(
+                    let span = info_span!(
                         "http_request",
                         method = ?request.method(),
                         uri =  matched_path,
                         status = tracing::field::Empty,
-                    )
+                    );
+                    #[cfg(feature = "otlp")]
+                    telemetry::set_remote_parent(&span, request.headers());
+                    span
                 })
                 .on_response(|response: &Response, _latency: Duration, span: &Span| {
                     span.record("status", response.status().as_str());
@@ -149,6 +331,13 @@ impl<const COUNT: usize> std::ops::Deref for HexStr<COUNT> {
     }
 }
 
+#[derive(Deserialize)]
+struct JobParams {
+    /// Hint for ordering the queued jobs (higher first).
+    #[serde(default)]
+    priority: i32,
+}
+
 async fn get_job<T: GetOrCreate>(
     State(manager): State<Arc<T>>,
     Path((miner, nonce_group, challenge, difficulty)): Path<(
@@ -157,19 +346,63 @@ async fn get_job<T: GetOrCreate>(
         HexStr<8>,
         HexStr<32>,
     )>,
-) -> Result<job_manager::JobStatus, job_manager::JobError> {
-    manager.get_or_create(job_manager::Job {
-        nonce_group,
-        challenge: *challenge,
-        difficulty: *difficulty,
-        miner: *miner,
-    })
+    Query(params): Query<JobParams>,
+    headers: HeaderMap,
+) -> Result<Response, job_manager::JobError> {
+    let (status, owner) = manager.get_or_create(
+        job_manager::Job {
+            nonce_group,
+            challenge: *challenge,
+            difficulty: *difficulty,
+            miner: *miner,
+        },
+        owner(&headers),
+        params.priority,
+    )?;
+    let mut response = status.into_response();
+    if let Some(owner) = owner.and_then(|o| HeaderValue::from_str(&o).ok()) {
+        response.headers_mut().insert(OWNER_HEADER, owner);
+    }
+    Ok(response)
+}
+
+async fn cancel_job<T: GetOrCreate>(
+    State(manager): State<Arc<T>>,
+    Path((miner, nonce_group, challenge, difficulty)): Path<(
+        HexStr<32>,
+        u8,
+        HexStr<8>,
+        HexStr<32>,
+    )>,
+    headers: HeaderMap,
+) -> Result<StatusCode, job_manager::JobError> {
+    manager.cancel(
+        job_manager::Job {
+            nonce_group,
+            challenge: *challenge,
+            difficulty: *difficulty,
+            miner: *miner,
+        },
+        owner(&headers),
+    )?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// The owner token of the client making the request.
+fn owner(headers: &HeaderMap) -> Option<String> {
+    headers
+        .get(OWNER_HEADER)
+        .and_then(|v| v.to_str().ok())
+        .map(str::to_string)
 }
 
 impl IntoResponse for job_manager::JobError {
     fn into_response(self) -> Response {
         match self {
             JobError::TooManyJobs => (StatusCode::TOO_MANY_REQUESTS, "").into_response(),
+            JobError::NotOwner => (StatusCode::FORBIDDEN, self.to_string()).into_response(),
+            JobError::NotFound => (StatusCode::NOT_FOUND, self.to_string()).into_response(),
+            JobError::InProgress => (StatusCode::CONFLICT, self.to_string()).into_response(),
         }
     }
 }
@@ -178,6 +411,7 @@ impl IntoResponse for job_manager::JobStatus {
     fn into_response(self) -> Response {
         match self {
             JobStatus::Created => (StatusCode::CREATED, "").into_response(),
+            JobStatus::Queued => (StatusCode::CREATED, "").into_response(),
             JobStatus::InProgress => (StatusCode::CREATED, "").into_response(),
             JobStatus::Done(Ok(res)) => (StatusCode::OK, format!("{res}")).into_response(),
             JobStatus::Done(Err(err)) => {
@@ -213,13 +447,68 @@ mod tests {
     async fn test_root() {
         let mut mock_manager = job_manager::MockGetOrCreate::new();
         mock_manager.expect_get_or_create().times(0);
-        let job_manager = job_manager::JobManager::new(1, crate::RandomXMode::Light, false);
+        let job_manager = job_manager::JobManager::new(
+            1,
+            post::pow::Version::RandomX,
+            crate::RandomXMode::Light,
+            false,
+        );
         let router = router(Arc::new(job_manager));
         let server = TestServer::new(router).unwrap();
         let response = server.get("/").await;
         assert_eq!(response.text(), super::ROOT_RESPONSE);
     }
 
+    #[test]
+    fn parsing_workers() {
+        use super::WorkerArg;
+
+        assert_eq!(
+            WorkerArg {
+                url: "http://worker:3000".into(),
+                jobs: None
+            },
+            "http://worker:3000".parse().unwrap()
+        );
+        assert_eq!(
+            WorkerArg {
+                url: "http://worker:3000".into(),
+                jobs: Some(4)
+            },
+            "http://worker:3000=4".parse().unwrap()
+        );
+        assert!("http://worker:3000=0".parse::<WorkerArg>().is_err());
+        assert!("http://worker:3000=many".parse::<WorkerArg>().is_err());
+    }
+
+    #[test]
+    fn health_url() {
+        assert_eq!("http://127.0.0.1:3000/", super::health_url("0.0.0.0:3000"));
+        assert_eq!("http://[::1]:3000/", super::health_url("[::]:3000"));
+        assert_eq!("http://10.0.0.1:3000/", super::health_url("10.0.0.1:3000"));
+        assert_eq!("http://k2pow:3000/", super::health_url("k2pow:3000"));
+    }
+
+    #[tokio::test]
+    async fn test_healthcheck() {
+        let job_manager = job_manager::JobManager::new(
+            1,
+            post::pow::Version::RandomX,
+            crate::RandomXMode::Light,
+            false,
+        );
+        let listener = tokio::net::TcpListener::bind("0.0.0.0:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, router(Arc::new(job_manager))).await });
+
+        super::healthcheck(&address.to_string()).await.unwrap();
+
+        let closed = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = closed.local_addr().unwrap();
+        drop(closed);
+        assert!(super::healthcheck(&address.to_string()).await.is_err());
+    }
+
     #[tokio::test]
     async fn test_get_job_created() {
         let (nonce_group, challenge, difficulty, miner) = (
@@ -231,9 +520,9 @@ mod tests {
         let mut mock_manager = job_manager::MockGetOrCreate::new();
         mock_manager
             .expect_get_or_create()
-            .with(eq(JOB))
+            .with(eq(JOB), eq(None), eq(0))
             .times(2)
-            .returning(|_| Ok(job_manager::JobStatus::Created));
+            .returning(|_, _, _| Ok((job_manager::JobStatus::Created, None)));
         let router = router(Arc::new(mock_manager));
         let server = TestServer::new(router).unwrap();
         let url = format!("/job/{miner}/{nonce_group}/{challenge}/{difficulty}");
@@ -256,9 +545,9 @@ mod tests {
         let mut mock_manager = job_manager::MockGetOrCreate::new();
         mock_manager
             .expect_get_or_create()
-            .with(eq(JOB))
+            .with(eq(JOB), eq(None), eq(0))
             .times(1)
-            .returning(|_| Ok(JobStatus::Done(Ok(RESULT))));
+            .returning(|_, _, _| Ok((JobStatus::Done(Ok(RESULT)), None)));
         let router = router(Arc::new(mock_manager));
         let server = TestServer::new(router).unwrap();
         let url = format!("/job/{miner}/{nonce_group}/{challenge}/{difficulty}");
@@ -267,6 +556,107 @@ mod tests {
         assert_eq!(response.text(), format!("{RESULT}"));
     }
 
+    #[tokio::test]
+    async fn test_get_job_owner() {
+        let (nonce_group, challenge, difficulty, miner) = (
+            JOB.nonce_group,
+            hex::encode(JOB.challenge),
+            hex::encode(JOB.difficulty),
+            hex::encode(JOB.miner),
+        );
+        let mut mock_manager = job_manager::MockGetOrCreate::new();
+        mock_manager
+            .expect_get_or_create()
+            .with(eq(JOB), eq(Some("me".to_string())), eq(0))
+            .times(1)
+            .returning(|_, _, _| Ok((JobStatus::InProgress, Some("me".to_string()))));
+        let router = router(Arc::new(mock_manager));
+        let server = TestServer::new(router).unwrap();
+        let url = format!("/job/{miner}/{nonce_group}/{challenge}/{difficulty}");
+        let response = server
+            .get(&url)
+            .add_header(post::pow::service::OWNER_HEADER, "me")
+            .await;
+        assert_eq!(response.status_code(), axum::http::StatusCode::CREATED);
+        assert_eq!(response.header(post::pow::service::OWNER_HEADER), "me");
+    }
+
+    #[tokio::test]
+    async fn test_get_job_not_owner() {
+        let (nonce_group, challenge, difficulty, miner) = (
+            JOB.nonce_group,
+            hex::encode(JOB.challenge),
+            hex::encode(JOB.difficulty),
+            hex::encode(JOB.miner),
+        );
+        let mut mock_manager = job_manager::MockGetOrCreate::new();
+        mock_manager
+            .expect_get_or_create()
+            .with(eq(JOB), eq(Some("other".to_string())), eq(0))
+            .times(1)
+            .returning(|_, _, _| Err(job_manager::JobError::NotOwner));
+        let router = router(Arc::new(mock_manager));
+        let server = TestServer::new(router).unwrap();
+        let url = format!("/job/{miner}/{nonce_group}/{challenge}/{difficulty}");
+        let response = server
+            .get(&url)
+            .add_header(post::pow::service::OWNER_HEADER, "other")
+            .await;
+        assert_eq!(response.status_code(), axum::http::StatusCode::FORBIDDEN);
+    }
+
+    #[tokio::test]
+    async fn test_cancel_job() {
+        let (nonce_group, challenge, difficulty, miner) = (
+            JOB.nonce_group,
+            hex::encode(JOB.challenge),
+            hex::encode(JOB.difficulty),
+            hex::encode(JOB.miner),
+        );
+        let mut mock_manager = job_manager::MockGetOrCreate::new();
+        mock_manager
+            .expect_cancel()
+            .with(eq(JOB), eq(Some("me".to_string())))
+            .times(1)
+            .returning(|_, _| Ok(()));
+        mock_manager
+            .expect_cancel()
+            .with(eq(JOB), eq(None))
+            .times(1)
+            .returning(|_, _| Err(job_manager::JobError::InProgress));
+        let router = router(Arc::new(mock_manager));
+        let server = TestServer::new(router).unwrap();
+        let url = format!("/job/{miner}/{nonce_group}/{challenge}/{difficulty}");
+        let response = server
+            .delete(&url)
+            .add_header(post::pow::service::OWNER_HEADER, "me")
+            .await;
+        assert_eq!(response.status_code(), axum::http::StatusCode::NO_CONTENT);
+        let response = server.delete(&url).await;
+        assert_eq!(response.status_code(), axum::http::StatusCode::CONFLICT);
+    }
+
+    #[tokio::test]
+    async fn test_get_job_priority() {
+        let (nonce_group, challenge, difficulty, miner) = (
+            JOB.nonce_group,
+            hex::encode(JOB.challenge),
+            hex::encode(JOB.difficulty),
+            hex::encode(JOB.miner),
+        );
+        let mut mock_manager = job_manager::MockGetOrCreate::new();
+        mock_manager
+            .expect_get_or_create()
+            .with(eq(JOB), eq(None), eq(5))
+            .times(1)
+            .returning(|_, _, _| Ok((JobStatus::Queued, None)));
+        let router = router(Arc::new(mock_manager));
+        let server = TestServer::new(router).unwrap();
+        let url = format!("/job/{miner}/{nonce_group}/{challenge}/{difficulty}?priority=5");
+        let response = server.get(&url).await;
+        assert_eq!(response.status_code(), axum::http::StatusCode::CREATED);
+    }
+
     #[tokio::test]
     async fn test_get_job_error() {
         let (nonce_group, challenge, difficulty, miner) = (
@@ -280,9 +670,11 @@ mod tests {
         let mut mock_manager = job_manager::MockGetOrCreate::new();
         mock_manager
             .expect_get_or_create()
-            .with(eq(JOB))
+            .with(eq(JOB), eq(None), eq(0))
             .times(1)
-            .returning(move |_| Ok(JobStatus::Done(Err(String::from("error message")))));
+            .returning(move |_, _, _| {
+                Ok((JobStatus::Done(Err(String::from("error message"))), None))
+            });
         let router = router(Arc::new(mock_manager));
         let server = TestServer::new(router).unwrap();
         let url = format!("/job/{miner}/{nonce_group}/{challenge}/{difficulty}");
@@ -0,0 +1,67 @@
+//! Exporting tracing spans to an OpenTelemetry collector via OTLP.
+//!
+//! The trace context sent by post-service in W3C `traceparent` headers
+//! is picked up, so k2pow jobs show up in the trace of the proof they belong to.
+
+use axum::http::HeaderMap;
+use opentelemetry::{trace::TracerProvider as _, KeyValue};
+use opentelemetry_http::HeaderExtractor;
+use opentelemetry_otlp::WithExportConfig;
+use opentelemetry_sdk::{
+    propagation::TraceContextPropagator,
+    runtime,
+    trace::{Tracer, TracerProvider},
+    Resource,
+};
+use tracing::{Span, Subscriber};
+use tracing_opentelemetry::{OpenTelemetryLayer, OpenTelemetrySpanExt};
+use tracing_subscriber::registry::LookupSpan;
+
+/// Flushes the spans that were not exported yet when dropped.
+pub struct Guard(TracerProvider);
+
+impl Drop for Guard {
+    fn drop(&mut self) {
+        if let Err(e) = self.0.shutdown() {
+            tracing::warn!("failed to shut down OTLP exporter: {e}");
+        }
+    }
+}
+
+/// Create a layer exporting spans to the OTLP (gRPC) collector listening on `endpoint`.
+///
+/// Must be called within a tokio runtime.
+pub fn layer<S>(
+    endpoint: &str,
+) -> Result<(OpenTelemetryLayer<S, Tracer>, Guard), Box<dyn std::error::Error>>
+where
+    S: Subscriber + for<'span> LookupSpan<'span>,
+{
+    let exporter = opentelemetry_otlp::SpanExporter::builder()
+        .with_tonic()
+        .with_endpoint(endpoint)
+        .build()?;
+    let provider = TracerProvider::builder()
+        .with_batch_exporter(exporter, runtime::Tokio)
+        .with_resource(Resource::new([KeyValue::new(
+            "service.name",
+            "k2pow-service",
+        )]))
+        .build();
+
+    opentelemetry::global::set_text_map_propagator(TraceContextPropagator::new());
+    opentelemetry::global::set_tracer_provider(provider.clone());
+
+    let tracer = provider.tracer("k2pow-service");
+    Ok((
+        tracing_opentelemetry::layer().with_tracer(tracer),
+        Guard(provider),
+    ))
+}
+
+/// Make the span a child of the remote span propagated in the request headers (if any).
+pub fn set_remote_parent(span: &Span, headers: &HeaderMap) {
+    let parent =
+        opentelemetry::global::get_text_map_propagator(|p| p.extract(&HeaderExtractor(headers)));
+    span.set_parent(parent);
+}
@@ -1,24 +1,38 @@
+mod report;
 mod util;
 
 use std::{
     cmp::min,
     env::temp_dir,
     fs::OpenOptions,
-    io::{BufReader, BufWriter, Seek, SeekFrom, Write},
+    io::{BufReader, BufWriter, Read, Seek, SeekFrom, Write},
+    ops::Range,
     path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering},
+        Mutex,
+    },
     time::{self, Duration},
 };
 
 use clap::{Args, Parser, Subcommand, ValueEnum};
 use eyre::Context;
 use post::{
-    pow::{self, randomx, Prover as PowProver},
-    prove::{Prover, Prover8_56, ProvingParams},
+    config::{Cores, InitConfig, ProofConfig, ScryptParams},
+    initialize::{CpuInitializer, Initialize},
+    metadata::ProofMetadata,
+    pow::{
+        self,
+        randomx::{self, LargePages},
+    },
+    prove::{self, ProgressReporter, Prover, Prover8_56, ProvingParams},
     reader::BatchingReader,
+    verification::{Mode, Verifier},
 };
+use primitive_types::U256;
 use rand::RngCore;
-use rayon::prelude::{ParallelBridge, ParallelIterator};
-use serde::Serialize;
+use rayon::prelude::{IntoParallelIterator, ParallelBridge, ParallelIterator};
+use serde::{Deserialize, Serialize};
 
 /// Profiler to measure the performance of generating the proof of space time
 /// given the parameters.
@@ -39,6 +53,20 @@ enum Commands {
     Proving(ProvingArgs),
     /// Bench proof of work.
     Pow(PowArgs),
+    /// Find the PoW difficulty giving the target k2pow duration on this machine.
+    CalibratePow(CalibratePowArgs),
+    /// Bench reading initialized POS data from disk.
+    /// Tells if the disk or the CPU will be the bottleneck of proving.
+    Disk(DiskArgs),
+    /// Generate a real proof for initialized POS data with a random challenge.
+    /// Reports how long k2pow and the passes over the data took.
+    E2e(E2eArgs),
+    /// Bench proof verification.
+    /// Measures how many proofs per second a single thread can verify.
+    Verify(VerifyArgs),
+    /// Report the machine capabilities (CPU, memory, RandomX, OpenCL devices, disk)
+    /// as JSON to attach to support requests.
+    Report(ReportArgs),
 }
 
 #[derive(Args, Debug)]
@@ -73,10 +101,49 @@ struct ProvingArgs {
     ///
     /// Higher value gives a better chance to find a proof within less passes over the POS data,
     /// but also slows down the process.
-    ///
-    /// Must be a multiple of 16.
     #[arg(short, long, default_value_t = 64, value_parser(parse_nonces))]
     nonces: u32,
+
+    /// JSON file to append the result (with the settings used) to.
+    ///
+    /// Allows comparing runs with different settings.
+    #[arg(long)]
+    history: Option<PathBuf>,
+
+    /// Run the benchmark for every combination of `--sweep-threads` and `--sweep-nonces`,
+    /// appending a record for each to the `--output` file.
+    #[arg(long, requires = "output")]
+    sweep: bool,
+
+    /// Numbers of threads to bench in sweep mode (a comma-separated list).
+    #[arg(long, value_delimiter = ',', default_values_t = [1, 2, 4, 8])]
+    sweep_threads: Vec<usize>,
+
+    /// Numbers of nonces to bench in sweep mode (a comma-separated list).
+    #[arg(long, value_delimiter = ',', default_values_t = [16, 32, 64, 128], value_parser(parse_nonces))]
+    sweep_nonces: Vec<u32>,
+
+    /// File to append the sweep records to.
+    #[arg(long)]
+    output: Option<PathBuf>,
+
+    /// Format of the sweep records.
+    #[arg(long, default_value_t = OutputFormat::Jsonl)]
+    output_format: OutputFormat,
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq, ValueEnum)]
+enum OutputFormat {
+    /// Comma-separated values with a header.
+    Csv,
+    /// One JSON object per line.
+    Jsonl,
+}
+
+impl std::fmt::Display for OutputFormat {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.to_possible_value().unwrap().get_name().fmt(f)
+    }
 }
 
 #[derive(Args, Debug)]
@@ -93,7 +160,7 @@ struct PowArgs {
 
     /// Number of nonces to attempt in single pass over POS data.
     ///
-    /// Each group of 16 nonces requires a separate PoW. Must be a multiple of 16.
+    /// Each (started) group of 16 nonces requires a separate PoW.
     ///
     /// Higher value gives a better chance to find a proof within less passes over the POS data,
     /// but also slows down the process.
@@ -116,6 +183,10 @@ struct PowArgs {
     )]
     difficulty: [u8; 32],
 
+    /// The PoW algorithm to bench.
+    #[arg(long, default_value_t = pow::Version::RandomX)]
+    algorithm: pow::Version,
+
     /// Modes of operation for RandomX.
     ///
     /// They are interchangeable as they give the same results but have different
@@ -127,8 +198,168 @@ struct PowArgs {
     ///
     /// This requires enabling large pages in the OS.
     /// Follow instructions here: https://xmrig.com/docs/miner/hugepages
-    #[arg(long, default_value_t = false)]
-    randomx_large_pages: bool,
+    ///
+    /// `auto` uses large pages only if they are available.
+    #[arg(long, default_value_t = LargePages::Off, num_args = 0..=1, default_missing_value = "on")]
+    randomx_large_pages: LargePages,
+}
+
+#[derive(Args, Debug)]
+struct DiskArgs {
+    /// Directory with initialized POS data (`postdata_N.bin` files).
+    ///
+    /// NOTE: On MacOS, the files MUST NOT be in chache already or the benchmark will give wrong results.
+    #[arg(long)]
+    data_dir: PathBuf,
+
+    /// Maximum amount of data to read in GiB.
+    #[arg(long, default_value_t = 4)]
+    data_size: u64,
+
+    /// Number of threads to use for proving.
+    /// '0' means use all available threads
+    #[arg(short, long, default_value_t = 4)]
+    threads: usize,
+
+    /// Number of nonces to attempt in single pass over POS data.
+    #[arg(short, long, default_value_t = 64, value_parser(parse_nonces))]
+    nonces: u32,
+}
+
+#[derive(Args, Debug)]
+struct E2eArgs {
+    /// Directory with initialized POS data.
+    #[arg(long)]
+    data_dir: PathBuf,
+
+    /// Number of threads to use.
+    /// '0' means use all available threads
+    #[arg(short, long, default_value_t = 4)]
+    threads: usize,
+
+    /// Number of nonces to attempt in single pass over POS data.
+    #[arg(short, long, default_value_t = 64, value_parser(parse_nonces))]
+    nonces: u32,
+
+    /// K1 specifies the difficulty for a label to be a candidate for a proof.
+    #[arg(long, default_value_t = 26)]
+    k1: u32,
+
+    /// K2 is the number of labels below the required difficulty required for a proof.
+    #[arg(long, default_value_t = 37)]
+    k2: u32,
+
+    /// PoW difficulty, a network parameter
+    #[arg(
+        long,
+        default_value = "000dfb23b0979b4b000000000000000000000000000000000000000000000000",
+        value_parser(parse_difficulty)
+    )]
+    pow_difficulty: [u8; 32],
+
+    /// The PoW algorithm to use.
+    #[arg(long, default_value_t = pow::Version::RandomX)]
+    algorithm: pow::Version,
+
+    /// Modes of operation for RandomX.
+    #[arg(long, default_value_t = RandomXMode::Fast)]
+    randomx_mode: RandomXMode,
+
+    /// Use large pages for RandomX (`on`, `off` or `auto` to use them only if available).
+    #[arg(long, default_value_t = LargePages::Off, num_args = 0..=1, default_missing_value = "on")]
+    randomx_large_pages: LargePages,
+}
+
+#[derive(Args, Debug)]
+struct VerifyArgs {
+    /// Number of proofs to verify for each combination of parameters.
+    /// The more, the more accurate the result is.
+    #[arg(long, short, default_value_t = 20)]
+    iterations: usize,
+
+    /// Number of threads verifying proofs in parallel.
+    /// '0' means use all available threads
+    #[arg(short, long, default_value_t = 1)]
+    threads: usize,
+
+    /// K2 is the number of labels in a proof.
+    #[arg(long, default_value_t = 37)]
+    k2: u32,
+
+    /// Numbers of labels to verify in each proof (a comma-separated list).
+    #[arg(long, value_delimiter = ',', default_values_t = [37])]
+    k3: Vec<usize>,
+
+    /// Scrypt N parameters of the POS data (a comma-separated list).
+    #[arg(long, value_delimiter = ',', default_values_t = [8192])]
+    scrypt_n: Vec<usize>,
+
+    /// Modes of RandomX to bench (a comma-separated list).
+    #[arg(long, value_delimiter = ',', default_values_t = [RandomXMode::Fast, RandomXMode::Light])]
+    randomx_mode: Vec<RandomXMode>,
+
+    /// Use large pages for RandomX (`on`, `off` or `auto` to use them only if available).
+    #[arg(long, default_value_t = LargePages::Off, num_args = 0..=1, default_missing_value = "on")]
+    randomx_large_pages: LargePages,
+}
+
+#[derive(Args, Debug)]
+struct ReportArgs {
+    /// Directory with initialized POS data to measure the disk throughput on.
+    /// The disk is not measured if not provided.
+    #[arg(long)]
+    data_dir: Option<PathBuf>,
+
+    /// Maximum amount of data to read in GiB.
+    #[arg(long, default_value_t = 1)]
+    data_size: u64,
+}
+
+#[derive(Args, Debug)]
+struct CalibratePowArgs {
+    /// The desired duration of k2pow (for all nonces and units) in seconds.
+    #[arg(long, default_value_t = 60.0)]
+    target: f64,
+
+    /// Accepted relative deviation from the target duration.
+    #[arg(long, default_value_t = 0.1)]
+    tolerance: f64,
+
+    /// Measurements to average for each tried difficulty.
+    /// The more, the more accurate the result is.
+    #[arg(long, short, default_value_t = 5)]
+    iterations: usize,
+
+    /// Maximum number of difficulties to try.
+    #[arg(long, default_value_t = 20)]
+    max_steps: usize,
+
+    /// Number of threads to use.
+    /// '0' means use all available threads
+    #[arg(short, long, default_value_t = 1)]
+    threads: usize,
+
+    /// Number of nonces to attempt in single pass over POS data.
+    ///
+    /// Each (started) group of 16 nonces requires a separate PoW.
+    #[arg(short, long, default_value_t = 64, value_parser(parse_nonces))]
+    nonces: u32,
+
+    /// Number of units of initialized POS data.
+    #[arg(long, default_value_t = 4)]
+    num_units: u32,
+
+    /// The PoW algorithm to calibrate.
+    #[arg(long, default_value_t = pow::Version::RandomX)]
+    algorithm: pow::Version,
+
+    /// Modes of operation for RandomX.
+    #[arg(long, default_value_t = RandomXMode::Fast)]
+    randomx_mode: RandomXMode,
+
+    /// Use large pages for RandomX (`on`, `off` or `auto` to use them only if available).
+    #[arg(long, default_value_t = LargePages::Off, num_args = 0..=1, default_missing_value = "on")]
+    randomx_large_pages: LargePages,
 }
 
 #[derive(Debug, Copy, Clone, Eq, PartialEq, ValueEnum)]
@@ -147,8 +378,8 @@ impl std::fmt::Display for RandomXMode {
 
 fn parse_nonces(arg: &str) -> eyre::Result<u32> {
     let nonces = arg.parse()?;
-    eyre::ensure!(nonces % 16 == 0, "nonces must be multiple of 16");
-    eyre::ensure!(nonces / 16 <= 256, format!("max nonces is {}", 256 * 16));
+    eyre::ensure!(nonces > 0, "nonces must be positive");
+    eyre::ensure!(nonces <= 256 * 16, format!("max nonces is {}", 256 * 16));
     Ok(nonces)
 }
 
@@ -159,12 +390,55 @@ fn parse_difficulty(arg: &str) -> eyre::Result<[u8; 32]> {
         .wrap_err("invalid difficulty length")
 }
 
-#[derive(Debug, Serialize)]
+#[derive(Debug, Serialize, Deserialize)]
 struct PerfResult {
     time_s: f64,
     speed_gib_s: f64,
 }
 
+/// A proving benchmark result kept in the history file.
+#[derive(Debug, Serialize, Deserialize)]
+struct HistoryEntry {
+    /// Seconds since the UNIX epoch
+    timestamp: u64,
+    threads: usize,
+    nonces: u32,
+    data_size: u64,
+    #[serde(flatten)]
+    result: PerfResult,
+}
+
+impl HistoryEntry {
+    const CSV_HEADER: &'static str = "timestamp,threads,nonces,data_size,time_s,speed_gib_s";
+
+    fn csv_row(&self) -> String {
+        format!(
+            "{},{},{},{},{},{}",
+            self.timestamp,
+            self.threads,
+            self.nonces,
+            self.data_size,
+            self.result.time_s,
+            self.result.speed_gib_s
+        )
+    }
+}
+
+/// Append the entry to the JSON history file, creating it if needed.
+fn append_history(path: &Path, entry: HistoryEntry) -> eyre::Result<()> {
+    let mut history: Vec<HistoryEntry> = match std::fs::read(path) {
+        Ok(data) => serde_json::from_slice(&data)
+            .wrap_err_with(|| format!("parsing history file {}", path.display()))?,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Vec::new(),
+        Err(e) => {
+            return Err(e).wrap_err_with(|| format!("reading history file {}", path.display()))
+        }
+    };
+    history.push(entry);
+    std::fs::write(path, serde_json::to_vec_pretty(&history)?)
+        .wrap_err_with(|| format!("writing history file {}", path.display()))
+}
+
 // Prepare file for benchmarking, possibly appending random data to it if needed.
 fn prepare_data_file(path: &Path, size: u64) -> eyre::Result<()> {
     if let Some(parent) = path.parent() {
@@ -213,11 +487,72 @@ fn main() -> eyre::Result<()> {
     match args.command.unwrap_or(Commands::Proving(args.default)) {
         Commands::Proving(args) => proving(args),
         Commands::Pow(args) => pow(args),
+        Commands::CalibratePow(args) => calibrate_pow(args),
+        Commands::Disk(args) => disk(args),
+        Commands::E2e(args) => e2e(args),
+        Commands::Verify(args) => verify(args),
+        Commands::Report(args) => {
+            let report = report::collect(
+                args.data_dir.as_deref(),
+                args.data_size * 1024 * 1024 * 1024,
+            )?;
+            println!("{}", serde_json::to_string_pretty(&report)?);
+            Ok(())
+        }
     }
 }
 
 /// Bench proving speed (going over POS data).
 fn proving(args: ProvingArgs) -> eyre::Result<()> {
+    let file_path = args
+        .data_file
+        .clone()
+        .unwrap_or_else(|| temp_dir().join("profiler_data.bin"));
+    prepare_data_file(&file_path, args.data_size * 1024 * 1024 * 1024)?;
+
+    if args.sweep {
+        let path = args
+            .output
+            .as_ref()
+            .ok_or_else(|| eyre::eyre!("--output is required in sweep mode"))?;
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .wrap_err_with(|| format!("opening output file {}", path.display()))?;
+        let mut writer = BufWriter::new(file);
+        if args.output_format == OutputFormat::Csv && std::fs::metadata(path)?.len() == 0 {
+            writeln!(writer, "{}", HistoryEntry::CSV_HEADER)?;
+        }
+        for &threads in &args.sweep_threads {
+            for &nonces in &args.sweep_nonces {
+                eprintln!("Benchmarking {threads} threads and {nonces} nonces");
+                let entry = bench_proving(&file_path, &args, threads, nonces)?;
+                match args.output_format {
+                    OutputFormat::Csv => writeln!(writer, "{}", entry.csv_row())?,
+                    OutputFormat::Jsonl => writeln!(writer, "{}", serde_json::to_string(&entry)?)?,
+                }
+                writer.flush()?;
+            }
+        }
+        return Ok(());
+    }
+
+    let entry = bench_proving(&file_path, &args, args.threads, args.nonces)?;
+    println!("{}", serde_json::to_string_pretty(&entry.result)?);
+    if let Some(path) = &args.history {
+        append_history(path, entry)?;
+    }
+    Ok(())
+}
+
+/// Bench proving over the data file with the given number of threads and nonces.
+fn bench_proving(
+    file_path: &Path,
+    args: &ProvingArgs,
+    threads: usize,
+    nonces: u32,
+) -> eyre::Result<HistoryEntry> {
     let challenge = b"hello world, challenge me!!!!!!!";
     let batch_size = 1024 * 1024;
     let total_size = args.data_size * 1024 * 1024 * 1024;
@@ -226,48 +561,433 @@ fn proving(args: ProvingArgs) -> eyre::Result<()> {
         pow_difficulty: [0xFF; 32],
     };
 
-    let file_path = args
-        .data_file
-        .unwrap_or_else(|| temp_dir().join("profiler_data.bin"));
-    prepare_data_file(&file_path, total_size)?;
-
     let pool = rayon::ThreadPoolBuilder::new()
-        .num_threads(args.threads)
+        .num_threads(threads)
         .build()?;
 
     let mut pow_prover = pow::MockProver::new();
     pow_prover.expect_prove().returning(|_, _, _, _| Ok(0));
     pow_prover.expect_par().returning(|| false);
-    let prover = Prover8_56::new(challenge, 0..args.nonces, params, &pow_prover, &[7; 32])?;
+    let prover = Prover8_56::new(challenge, 0..nonces, params, &pow_prover, &[7; 32])?;
 
     let mut total_time = time::Duration::from_secs(0);
     let mut processed = 0;
+    let processed_bytes = AtomicU64::new(0);
+    let done = AtomicBool::new(false);
 
-    while total_time < Duration::from_secs(args.duration) {
-        let file = util::open_without_cache(&file_path)?;
-        let reader = BatchingReader::new(BufReader::new(file), 0, batch_size, total_size);
-        let start = time::Instant::now();
-        pool.install(|| {
-            reader.par_bridge().for_each(|batch| {
-                prover.prove(&batch.data, batch.pos, |_, _| None);
-            })
+    std::thread::scope(|s| -> eyre::Result<()> {
+        // Report the current speed every second.
+        s.spawn(|| {
+            let mut last = 0;
+            while !done.load(Ordering::Relaxed) {
+                std::thread::sleep(Duration::from_secs(1));
+                let bytes = processed_bytes.load(Ordering::Relaxed);
+                eprintln!(
+                    "{:.2} GiB/s",
+                    (bytes - last) as f64 / (1024 * 1024 * 1024) as f64
+                );
+                last = bytes;
+            }
         });
-        total_time += start.elapsed();
-        processed += args.data_size;
+
+        let res = (|| {
+            while total_time < Duration::from_secs(args.duration) {
+                let file = util::open_without_cache(file_path)?;
+                let reader = BatchingReader::new(BufReader::new(file), 0, batch_size, total_size);
+                let start = time::Instant::now();
+                pool.install(|| {
+                    reader.par_bridge().try_for_each(|batch| {
+                        let batch = batch?;
+                        prover.prove(&batch.data, batch.pos, |_, _| None);
+                        processed_bytes.fetch_add(batch.data.len() as u64, Ordering::Relaxed);
+                        Ok::<_, std::io::Error>(())
+                    })
+                })?;
+                total_time += start.elapsed();
+                processed += args.data_size;
+            }
+            Ok(())
+        })();
+        done.store(true, Ordering::Relaxed);
+        res
+    })?;
+
+    Ok(HistoryEntry {
+        timestamp: time::SystemTime::now()
+            .duration_since(time::UNIX_EPOCH)?
+            .as_secs(),
+        threads: pool.current_num_threads(),
+        nonces,
+        data_size: args.data_size,
+        result: PerfResult {
+            time_s: total_time.as_secs_f64(),
+            speed_gib_s: processed as f64 / total_time.as_secs_f64(),
+        },
+    })
+}
+
+#[derive(Debug, Serialize)]
+struct ReadStats {
+    /// Amount of data read
+    read_gib: f64,
+    /// Sequential read speed of the POS data
+    read_speed_gib_s: f64,
+    /// Average latency of reading a batch
+    read_latency_avg: time::Duration,
+    /// 99th percentile of latency of reading a batch
+    read_latency_p99: time::Duration,
+}
+
+/// Read POS data files in `data_dir` sequentially (bypassing the cache), up to `max_size` bytes.
+fn measure_reads(data_dir: &Path, max_size: u64) -> eyre::Result<ReadStats> {
+    let mut buf = vec![0u8; 1024 * 1024];
+    let mut latencies = Vec::new();
+    let mut read = 0u64;
+    let start = time::Instant::now();
+    'files: for entry in post::reader::pos_files(data_dir)? {
+        eprintln!("Reading {}", entry.path().display());
+        let mut file = util::open_without_cache(&entry.path())?;
+        loop {
+            let batch_start = time::Instant::now();
+            let n = file.read(&mut buf)?;
+            if n == 0 {
+                break;
+            }
+            latencies.push(batch_start.elapsed());
+            read += n as u64;
+            if read >= max_size {
+                break 'files;
+            }
+        }
     }
+    let read_time = start.elapsed();
+    eyre::ensure!(read > 0, "no POS data found in {}", data_dir.display());
+    latencies.sort();
+    let read_gib = read as f64 / (1024 * 1024 * 1024) as f64;
+
+    Ok(ReadStats {
+        read_gib,
+        read_speed_gib_s: read_gib / read_time.as_secs_f64(),
+        read_latency_avg: latencies.iter().sum::<Duration>() / latencies.len() as u32,
+        read_latency_p99: latencies[(latencies.len() - 1) * 99 / 100],
+    })
+}
+
+#[derive(Debug, Serialize)]
+struct DiskPerfResult {
+    #[serde(flatten)]
+    reads: ReadStats,
+    /// Speed of proving over data in memory for the given nonces
+    proving_speed_gib_s: f64,
+    /// What limits the speed of proving: "disk" or "cpu"
+    bottleneck: &'static str,
+}
+
+/// Bench reading POS data from disk and compare it with the speed of proving.
+fn disk(args: DiskArgs) -> eyre::Result<()> {
+    const BATCH_SIZE: usize = 1024 * 1024;
+    let reads = measure_reads(&args.data_dir, args.data_size * 1024 * 1024 * 1024)?;
+
+    // Bench proving on data in memory to exclude the disk.
+    let pool = rayon::ThreadPoolBuilder::new()
+        .num_threads(args.threads)
+        .build()?;
+    let mut data = vec![0u8; BATCH_SIZE];
+    rand::thread_rng().fill_bytes(&mut data);
+    let data = &data[..];
+    let mut pow_prover = pow::MockProver::new();
+    pow_prover.expect_prove().returning(|_, _, _, _| Ok(0));
+    pow_prover.expect_par().returning(|| false);
+    let params = ProvingParams {
+        difficulty: 0, // impossible to find a proof
+        pow_difficulty: [0xFF; 32],
+    };
+    let prover = Prover8_56::new(&[0; 32], 0..args.nonces, params, &pow_prover, &[7; 32])?;
+    let batches = 256;
+    let start = time::Instant::now();
+    pool.install(|| {
+        (0..batches).into_par_iter().for_each(|i| {
+            prover.prove(data, i * BATCH_SIZE as u64, |_, _| None);
+        })
+    });
+    let proving_speed_gib_s = (batches * BATCH_SIZE as u64) as f64
+        / (1024 * 1024 * 1024) as f64
+        / start.elapsed().as_secs_f64();
 
-    let result = PerfResult {
-        time_s: total_time.as_secs_f64(),
-        speed_gib_s: processed as f64 / total_time.as_secs_f64(),
+    let result = DiskPerfResult {
+        bottleneck: if reads.read_speed_gib_s < proving_speed_gib_s {
+            "disk"
+        } else {
+            "cpu"
+        },
+        reads,
+        proving_speed_gib_s,
     };
     println!("{}", serde_json::to_string_pretty(&result)?);
+    Ok(())
+}
 
+/// Measures the wall-clock time while the wrapped PoW prover is busy.
+///
+/// Concurrent calls are counted once, so that k2pows proven in parallel
+/// don't add up to more than the time of the k2pow phase.
+struct TimedPowProver {
+    inner: Box<dyn pow::Prover + Send + Sync>,
+    busy: Mutex<BusyTime>,
+}
+
+#[derive(Default)]
+struct BusyTime {
+    calls: usize,
+    since: Option<time::Instant>,
+    elapsed: Duration,
+}
+
+impl TimedPowProver {
+    fn new(inner: Box<dyn pow::Prover + Send + Sync>) -> Self {
+        Self {
+            inner,
+            busy: Mutex::default(),
+        }
+    }
+
+    fn timed<T>(&self, f: impl FnOnce() -> T) -> T {
+        {
+            let mut busy = self.busy.lock().unwrap();
+            busy.calls += 1;
+            busy.since.get_or_insert_with(time::Instant::now);
+        }
+        let result = f();
+        let mut busy = self.busy.lock().unwrap();
+        busy.calls -= 1;
+        if busy.calls == 0 {
+            if let Some(since) = busy.since.take() {
+                busy.elapsed += since.elapsed();
+            }
+        }
+        result
+    }
+
+    fn elapsed(&self) -> Duration {
+        self.busy.lock().unwrap().elapsed
+    }
+}
+
+impl pow::Prover for TimedPowProver {
+    fn prove(
+        &self,
+        nonce_group: u8,
+        challenge: &[u8; 8],
+        difficulty: &[u8; 32],
+        miner_id: &[u8; 32],
+    ) -> Result<u64, pow::Error> {
+        self.timed(|| {
+            self.inner
+                .prove(nonce_group, challenge, difficulty, miner_id)
+        })
+    }
+
+    fn prove_many(
+        &self,
+        nonce_group: Range<u32>,
+        challenge: &[u8; 8],
+        difficulty: &[u8; 32],
+        miner_id: &[u8; 32],
+    ) -> Result<Vec<(u32, u64)>, pow::Error> {
+        self.timed(|| {
+            self.inner
+                .prove_many(nonce_group, challenge, difficulty, miner_id)
+        })
+    }
+
+    fn par(&self) -> bool {
+        self.inner.par()
+    }
+}
+
+/// Counts the passes over POS data.
+struct PassCounter<'a>(&'a AtomicUsize);
+
+impl ProgressReporter for PassCounter<'_> {
+    fn new_nonce_group(&self, nonces: Range<u32>) {
+        let pass = self.0.fetch_add(1, Ordering::Relaxed) + 1;
+        eprintln!("Pass {pass} with nonces {nonces:?}");
+    }
+
+    fn finished_chunk(&self, _: u64, _: usize) {}
+}
+
+#[derive(Debug, Serialize)]
+struct E2ePerfResult {
+    /// Total time of generating the proof
+    total_time: time::Duration,
+    /// Time spent in k2pow (in all passes)
+    k2pow_time: time::Duration,
+    /// Time spent reading and proving over POS data (in all passes)
+    passes_time: time::Duration,
+    /// Average time of a single pass over POS data (excluding k2pow)
+    average_pass_time: time::Duration,
+    /// Number of passes over POS data needed to find the proof
+    passes: usize,
+}
+
+/// Generate a real proof for initialized POS data.
+fn e2e(args: E2eArgs) -> eyre::Result<()> {
+    let randomx_flags = randomx_flags(args.randomx_mode, args.randomx_large_pages)?;
+    eprintln!("PoW algorithm: {}", args.algorithm);
+    eprintln!("Initializing PoW...");
+    let start = time::Instant::now();
+    let pow_prover = TimedPowProver::new(pow::new_prover(
+        args.algorithm,
+        randomx_flags,
+        randomx::DEFAULT_CACHE_KEY,
+    )?);
+    eprintln!("Done initializing PoW in {:.2?}", start.elapsed());
+
+    let mut challenge = [0u8; 32];
+    rand::thread_rng().fill_bytes(&mut challenge);
+    eprintln!("Generating proof for challenge {}", hex::encode(challenge));
+
+    let cfg = ProofConfig {
+        k1: args.k1,
+        k2: args.k2,
+        pow_difficulty: args.pow_difficulty,
+    };
+    let passes = AtomicUsize::new(0);
+    let start = time::Instant::now();
+    prove::generate_proof(
+        &args.data_dir,
+        &challenge,
+        cfg,
+        args.nonces as usize,
+        Cores::Any(args.threads),
+        randomx_flags,
+        AtomicBool::new(false),
+        PassCounter(&passes),
+        &pow_prover,
+    )?;
+    let total_time = start.elapsed();
+
+    let k2pow_time = pow_prover.elapsed();
+    let passes = passes.load(Ordering::Relaxed);
+    let passes_time = total_time.saturating_sub(k2pow_time);
+    println!(
+        "{}",
+        serde_json::to_string_pretty(&E2ePerfResult {
+            total_time,
+            k2pow_time,
+            passes_time,
+            average_pass_time: passes_time / passes.max(1) as u32,
+            passes,
+        })?
+    );
+    Ok(())
+}
+
+#[derive(Debug, Serialize)]
+struct VerifyPerfResult {
+    randomx_mode: String,
+    scrypt_n: usize,
+    k3: usize,
+    /// Average time to verify a proof
+    average_time: time::Duration,
+    /// Proofs verified per second by a single thread
+    proofs_per_second_per_thread: f64,
+}
+
+/// Bench verifying proofs for tiny POS data generated with given parameters.
+fn verify(args: VerifyArgs) -> eyre::Result<()> {
+    eyre::ensure!(args.iterations > 0, "iterations must be positive");
+    let pool = rayon::ThreadPoolBuilder::new()
+        .num_threads(args.threads)
+        .build()?;
+    let threads = pool.current_num_threads();
+
+    let verifiers = args
+        .randomx_mode
+        .iter()
+        .map(|&mode| {
+            eprintln!("Initializing RandomX VM in {mode} mode...");
+            let flags = randomx_flags(mode, args.randomx_large_pages)?;
+            Ok((mode, Verifier::new(Box::new(randomx::PoW::new(flags)?))))
+        })
+        .collect::<eyre::Result<Vec<_>>>()?;
+
+    let mut results = Vec::new();
+    for &scrypt_n in &args.scrypt_n {
+        eprintln!("Generating proof for POS data with scrypt N={scrypt_n}...");
+        let datadir = tempfile::tempdir()?;
+        let labels = 4 * args.k2 as u64;
+        let init_cfg = InitConfig {
+            min_num_units: 1,
+            max_num_units: 1,
+            labels_per_unit: labels,
+            scrypt: ScryptParams::new(scrypt_n, 1, 1),
+        };
+        let cfg = ProofConfig {
+            k1: labels as u32,
+            k2: args.k2,
+            pow_difficulty: [0xFF; 32],
+        };
+        let metadata = CpuInitializer::new(init_cfg.scrypt)
+            .initialize(
+                datadir.path(),
+                &[0xBE; 32],
+                &[0xCE; 32],
+                labels,
+                1,
+                labels,
+                None,
+            )
+            .map_err(|e| eyre::eyre!("initializing POS data: {e}"))?;
+        let challenge = [0xCA; 32];
+        let pow_flags = randomx::RandomXFlag::get_recommended_flags();
+        let proof = prove::generate_proof(
+            datadir.path(),
+            &challenge,
+            cfg,
+            16,
+            Cores::Any(1),
+            pow_flags,
+            AtomicBool::new(false),
+            prove::NoopProgressReporter {},
+            &randomx::PoW::new(pow_flags)?,
+        )?;
+        let metadata = ProofMetadata::new(metadata, challenge);
+
+        for (mode, verifier) in &verifiers {
+            for &k3 in &args.k3 {
+                let start = time::Instant::now();
+                pool.install(|| {
+                    (0..args.iterations).into_par_iter().try_for_each(|i| {
+                        let seed = i.to_le_bytes();
+                        let mode = Mode::Subset { k3, seed: &seed };
+                        verifier.verify(&proof, &metadata, &cfg, &init_cfg, mode)
+                    })
+                })?;
+                let elapsed = start.elapsed();
+                let result = VerifyPerfResult {
+                    randomx_mode: mode.to_string(),
+                    scrypt_n,
+                    k3,
+                    average_time: elapsed * threads as u32 / args.iterations as u32,
+                    proofs_per_second_per_thread: args.iterations as f64
+                        / elapsed.as_secs_f64()
+                        / threads as f64,
+                };
+                eprintln!("{result:?}");
+                results.push(result);
+            }
+        }
+    }
+
+    println!("{}", serde_json::to_string_pretty(&results)?);
     Ok(())
 }
 
 #[derive(Debug, Serialize)]
 struct PowPerfResult {
-    /// Time to initialize RandomX VM
+    /// Time to initialize RandomX VM (or other PoW algorithm)
     randomx_vm_init_time: time::Duration,
     /// Average time of PoW
     average_time: time::Duration,
@@ -282,23 +1002,15 @@ fn pow(args: PowArgs) -> eyre::Result<()> {
         args.num_units, args.nonces,
     );
 
-    let mut randomx_flags = match args.randomx_mode {
-        RandomXMode::Fast => {
-            randomx::RandomXFlag::get_recommended_flags() | randomx::RandomXFlag::FLAG_FULL_MEM
-        }
-        RandomXMode::Light => randomx::RandomXFlag::get_recommended_flags(),
-    };
-    if args.randomx_large_pages {
-        eprintln!("Using large pages for RandomX");
-        randomx_flags |= randomx::RandomXFlag::FLAG_LARGE_PAGES;
-    }
+    let randomx_flags = randomx_flags(args.randomx_mode, args.randomx_large_pages)?;
+    eprintln!("PoW algorithm: {}", args.algorithm);
     eprintln!("RandomX flags: {}", randomx_flags);
 
-    eprintln!("Initializing RandomX VMs...");
+    eprintln!("Initializing PoW...");
     let start = time::Instant::now();
-    let prover = randomx::PoW::new(randomx_flags)?;
+    let prover = pow::new_prover(args.algorithm, randomx_flags, randomx::DEFAULT_CACHE_KEY)?;
     let randomx_vm_init_time = start.elapsed();
-    eprintln!("Done initializing RandomX VMs in {randomx_vm_init_time:.2?}");
+    eprintln!("Done initializing PoW in {randomx_vm_init_time:.2?}");
 
     let mut durations = Vec::new();
     let pool = rayon::ThreadPoolBuilder::new()
@@ -312,14 +1024,15 @@ fn pow(args: PowArgs) -> eyre::Result<()> {
             let duration = start.elapsed();
             eprintln!(
                 "[{i}]: {duration:.2?} (scaled: {:.2?})",
-                duration * args.nonces / 16 * args.num_units
+                duration * args.nonces.div_ceil(16) * args.num_units
             );
             durations.push(duration);
         }
         Ok(())
     })?;
 
-    let total = durations.iter().sum::<time::Duration>() * (args.nonces / 16) * args.num_units;
+    let total =
+        durations.iter().sum::<time::Duration>() * args.nonces.div_ceil(16) * args.num_units;
     println!(
         "{}",
         serde_json::to_string_pretty(&PowPerfResult {
@@ -332,8 +1045,228 @@ fn pow(args: PowArgs) -> eyre::Result<()> {
     Ok(())
 }
 
+fn randomx_flags(mode: RandomXMode, large_pages: LargePages) -> eyre::Result<randomx::RandomXFlag> {
+    let flags = match mode {
+        RandomXMode::Fast => {
+            randomx::RandomXFlag::get_recommended_flags() | randomx::RandomXFlag::FLAG_FULL_MEM
+        }
+        RandomXMode::Light => randomx::RandomXFlag::get_recommended_flags(),
+    };
+    let flags = randomx::with_large_pages(flags, large_pages)?;
+    if flags.contains(randomx::RandomXFlag::FLAG_LARGE_PAGES) {
+        eprintln!("Using large pages for RandomX");
+    }
+    Ok(flags)
+}
+
+/// The difficulty requiring `hashes` attempts on average to find a PoW.
+fn difficulty_for_hashes(hashes: f64) -> U256 {
+    if hashes <= 1.0 {
+        return U256::MAX;
+    }
+    // 2^256 / hashes, computed on the top 128 bits to keep precision in f64 range.
+    let top = (2f64.powi(128) / hashes) as u128;
+    U256::from(top) << 128
+}
+
+#[derive(Debug, Serialize)]
+struct CalibrationResult {
+    /// The PoW difficulty (for 1 space unit) to use as the network parameter.
+    difficulty: String,
+    /// Measured average duration of k2pow for all nonces and units.
+    average_time: time::Duration,
+    /// Number of tried difficulties.
+    steps: usize,
+}
+
+/// Find the PoW difficulty yielding the target k2pow duration.
+///
+/// Bisects (in log space) over the expected number of hashes needed to find a PoW,
+/// measuring the average duration of several PoWs for each tried difficulty.
+fn calibrate_pow(args: CalibratePowArgs) -> eyre::Result<()> {
+    eyre::ensure!(args.target > 0.0, "target must be positive");
+    eyre::ensure!(args.iterations > 0, "iterations must be positive");
+    let randomx_flags = randomx_flags(args.randomx_mode, args.randomx_large_pages)?;
+    eprintln!("PoW algorithm: {}", args.algorithm);
+    let prover = pow::new_prover(args.algorithm, randomx_flags, randomx::DEFAULT_CACHE_KEY)?;
+    let pool = rayon::ThreadPoolBuilder::new()
+        .num_threads(args.threads)
+        .build()?;
+
+    // The proving difficulty is the base difficulty scaled down by the number of units
+    // and a PoW is needed for each group of 16 nonces.
+    let scale = args.num_units as f64 * (args.nonces.div_ceil(16)) as f64;
+    let target = args.target / scale;
+    eprintln!(
+        "Looking for difficulty giving {:.2}s per PoW ({}s for {} units and {} nonces)",
+        target, args.target, args.num_units, args.nonces
+    );
+
+    let mut rng = rand::thread_rng();
+    let (mut low, mut high) = (0f64, 64f64); // log2 of expected hashes
+    let mut result = None;
+    for step in 1..=args.max_steps {
+        let log_hashes = (low + high) / 2.0;
+        let difficulty = difficulty_for_hashes(2f64.powf(log_hashes));
+        let difficulty_bytes = difficulty.to_big_endian();
+
+        let mut total = Duration::ZERO;
+        for _ in 0..args.iterations {
+            let mut challenge = [0u8; 8];
+            rng.fill_bytes(&mut challenge);
+            let start = time::Instant::now();
+            pool.install(|| prover.prove(0, &challenge, &difficulty_bytes, &[7; 32]))?;
+            total += start.elapsed();
+        }
+        let average = total / args.iterations as u32;
+        eprintln!(
+            "[{step}]: {}: {average:.2?}",
+            post::difficulty::describe_pow_difficulty(&difficulty_bytes)
+        );
+        result = Some((difficulty, average, step));
+
+        let deviation = (average.as_secs_f64() - target) / target;
+        if deviation.abs() <= args.tolerance {
+            break;
+        }
+        if deviation > 0.0 {
+            high = log_hashes;
+        } else {
+            low = log_hashes;
+        }
+    }
+
+    let (difficulty, average, steps) = result.ok_or_else(|| eyre::eyre!("no steps were run"))?;
+    let base_difficulty = difficulty.saturating_mul(U256::from(args.num_units));
+    println!(
+        "{}",
+        serde_json::to_string_pretty(&CalibrationResult {
+            difficulty: hex::encode(base_difficulty.to_big_endian()),
+            average_time: average.mul_f64(scale),
+            steps,
+        })?
+    );
+    Ok(())
+}
+
 #[cfg(test)]
 mod tests {
+    use primitive_types::U256;
+
+    #[test]
+    fn difficulty_for_hashes() {
+        assert_eq!(U256::MAX, super::difficulty_for_hashes(0.5));
+        assert_eq!(U256::MAX, super::difficulty_for_hashes(1.0));
+        assert_eq!(U256::one() << 255, super::difficulty_for_hashes(2.0));
+        assert_eq!(
+            U256::one() << 216,
+            super::difficulty_for_hashes(2f64.powi(40))
+        );
+        // more hashes - lower (harder) difficulty
+        assert!(super::difficulty_for_hashes(1000.0) > super::difficulty_for_hashes(1001.0));
+    }
+
+    #[test]
+    fn append_history() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let path = temp_dir.path().join("history.json");
+        for threads in [1, 2] {
+            let entry = super::HistoryEntry {
+                timestamp: 0,
+                threads,
+                nonces: 64,
+                data_size: 1,
+                result: super::PerfResult {
+                    time_s: 1.0,
+                    speed_gib_s: 1.0,
+                },
+            };
+            super::append_history(&path, entry).unwrap();
+        }
+        let history: Vec<super::HistoryEntry> =
+            serde_json::from_slice(&std::fs::read(&path).unwrap()).unwrap();
+        assert_eq!(2, history.len());
+        assert_eq!(1, history[0].threads);
+        assert_eq!(2, history[1].threads);
+    }
+
+    #[test]
+    fn csv_row() {
+        let entry = super::HistoryEntry {
+            timestamp: 1,
+            threads: 2,
+            nonces: 64,
+            data_size: 1,
+            result: super::PerfResult {
+                time_s: 10.5,
+                speed_gib_s: 0.25,
+            },
+        };
+        assert_eq!("1,2,64,1,10.5,0.25", entry.csv_row());
+        assert_eq!(
+            super::HistoryEntry::CSV_HEADER.split(',').count(),
+            entry.csv_row().split(',').count()
+        );
+    }
+
+    #[test]
+    fn timing_concurrent_k2pows() {
+        use post::pow::{Error, Prover};
+        use std::{
+            ops::Range,
+            sync::{Arc, Barrier},
+            time::{Duration, Instant},
+        };
+
+        // Both proofs are in progress between the two waits on the barrier.
+        struct SlowProver(Arc<Barrier>);
+        impl Prover for SlowProver {
+            fn prove(&self, _: u8, _: &[u8; 8], _: &[u8; 32], _: &[u8; 32]) -> Result<u64, Error> {
+                self.0.wait();
+                self.0.wait();
+                Ok(0)
+            }
+            fn prove_many(
+                &self,
+                nonce_groups: Range<u32>,
+                _: &[u8; 8],
+                _: &[u8; 32],
+                _: &[u8; 32],
+            ) -> Result<Vec<(u32, u64)>, Error> {
+                Ok(nonce_groups.map(|group| (group, 0)).collect())
+            }
+            fn par(&self) -> bool {
+                false
+            }
+        }
+
+        let barrier = Arc::new(Barrier::new(3));
+        let prover = super::TimedPowProver::new(Box::new(SlowProver(barrier.clone())));
+        let start = Instant::now();
+        std::thread::scope(|s| {
+            for group in 0..2 {
+                let prover = &prover;
+                s.spawn(move || prover.prove(group, &[0; 8], &[0; 32], &[0; 32]).unwrap());
+            }
+            barrier.wait();
+            assert_eq!(2, prover.busy.lock().unwrap().calls);
+            std::thread::sleep(Duration::from_millis(100));
+            barrier.wait();
+        });
+        assert_eq!(0, prover.busy.lock().unwrap().calls);
+        // Proven in parallel, the time is not counted twice.
+        assert!(prover.elapsed() >= Duration::from_millis(100));
+        assert!(prover.elapsed() <= start.elapsed());
+
+        assert_eq!(
+            vec![(1, 0), (2, 0)],
+            prover
+                .prove_many(1..3, &[0; 8], &[0; 32], &[0; 32])
+                .unwrap()
+        );
+        assert_eq!(0, prover.busy.lock().unwrap().calls);
+    }
+
     #[test]
     fn prepare_data_file() {
         let temp_dir = tempfile::tempdir().unwrap();
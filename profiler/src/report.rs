@@ -0,0 +1,140 @@
+//! Report of the machine capabilities relevant for POST.
+
+use std::path::Path;
+
+use post::pow::randomx::{HugePages, RandomXFlag, FAST_MODE_MEMORY};
+use serde::Serialize;
+use sysinfo::{CpuRefreshKind, System};
+
+#[derive(Debug, Serialize)]
+pub(crate) struct Report {
+    cpu: Cpu,
+    memory: Memory,
+    randomx: RandomX,
+    /// OpenCL devices usable for initialization.
+    /// `None` if the profiler was built without the `opencl` feature.
+    opencl_devices: Option<Vec<String>>,
+    /// Reading POS data from disk. `None` if no data directory was given.
+    disk: Option<super::ReadStats>,
+}
+
+#[derive(Debug, Serialize)]
+struct Cpu {
+    model: String,
+    physical_cores: Option<usize>,
+    logical_cores: usize,
+    /// Number of NUMA nodes (Linux only)
+    numa_nodes: Option<usize>,
+    aes: bool,
+    vaes: bool,
+    avx2: bool,
+}
+
+#[derive(Debug, Serialize)]
+struct Memory {
+    total_bytes: u64,
+    available_bytes: u64,
+}
+
+#[derive(Debug, Serialize)]
+struct RandomX {
+    recommended_flags: String,
+    /// Is there enough available memory for the fast mode
+    fast_mode_feasible: bool,
+    /// Huge pages configured in the OS (Linux only)
+    hugepages: Option<HugePages>,
+}
+
+fn numa_nodes() -> Option<usize> {
+    let nodes = std::fs::read_dir("/sys/devices/system/node").ok()?;
+    let count = nodes
+        .filter_map(Result::ok)
+        .filter(|e| {
+            e.file_name()
+                .to_str()
+                .and_then(|n| n.strip_prefix("node"))
+                .is_some_and(|id| id.parse::<u32>().is_ok())
+        })
+        .count();
+    Some(count)
+}
+
+#[cfg(target_arch = "x86_64")]
+fn cpu_features() -> (bool, bool, bool) {
+    (
+        std::arch::is_x86_feature_detected!("aes"),
+        std::arch::is_x86_feature_detected!("vaes"),
+        std::arch::is_x86_feature_detected!("avx2"),
+    )
+}
+
+#[cfg(target_arch = "aarch64")]
+fn cpu_features() -> (bool, bool, bool) {
+    (std::arch::is_aarch64_feature_detected!("aes"), false, false)
+}
+
+#[cfg(not(any(target_arch = "x86_64", target_arch = "aarch64")))]
+fn cpu_features() -> (bool, bool, bool) {
+    (false, false, false)
+}
+
+#[cfg(feature = "opencl")]
+fn opencl_devices() -> Option<Vec<String>> {
+    match scrypt_ocl::get_providers(None, &scrypt_ocl::ProviderFilter::default()) {
+        Ok(providers) => Some(providers.iter().map(ToString::to_string).collect()),
+        Err(e) => {
+            eprintln!("failed to list OpenCL devices: {e}");
+            Some(Vec::new())
+        }
+    }
+}
+
+#[cfg(not(feature = "opencl"))]
+fn opencl_devices() -> Option<Vec<String>> {
+    None
+}
+
+/// Collect the report, measuring disk reads of up to `data_size` bytes if `data_dir` is given.
+pub(crate) fn collect(data_dir: Option<&Path>, data_size: u64) -> eyre::Result<Report> {
+    let mut sys = System::new();
+    sys.refresh_memory();
+    sys.refresh_cpu_list(CpuRefreshKind::nothing());
+
+    let (aes, vaes, avx2) = cpu_features();
+    let cpu = Cpu {
+        model: sys
+            .cpus()
+            .first()
+            .map(|c| c.brand().trim().to_string())
+            .unwrap_or_default(),
+        physical_cores: sys.physical_core_count(),
+        logical_cores: sys.cpus().len(),
+        numa_nodes: numa_nodes(),
+        aes,
+        vaes,
+        avx2,
+    };
+
+    let memory = Memory {
+        total_bytes: sys.total_memory(),
+        available_bytes: sys.available_memory(),
+    };
+
+    let randomx = RandomX {
+        recommended_flags: RandomXFlag::get_recommended_flags().to_string(),
+        fast_mode_feasible: memory.available_bytes >= FAST_MODE_MEMORY,
+        hugepages: HugePages::detect(),
+    };
+
+    let disk = data_dir
+        .map(|dir| super::measure_reads(dir, data_size))
+        .transpose()?;
+
+    Ok(Report {
+        cpu,
+        memory,
+        randomx,
+        opencl_devices: opencl_devices(),
+        disk,
+    })
+}
@@ -0,0 +1,301 @@
+//! Generate a proof of space-time for initialized POS data
+//! and submit it to a certifier.
+use std::{
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::Instant,
+};
+
+use base64::{engine::general_purpose, Engine};
+use clap::{Args, Parser, Subcommand, ValueEnum};
+use eyre::Context;
+use post::{
+    config::{Cores, InitConfig, NetworkPreset, ProofConfig, ScryptParams},
+    metadata::ProofMetadata,
+    pow::randomx::{self, LargePages, PoW, RandomXFlag},
+    prove::{generate_proof, NoopProgressReporter, ProofWithMetadata},
+    verification::{Mode, Verifier},
+};
+
+/// Generate a proof of space-time for initialized POS data.
+///
+/// The proof with its metadata is printed (or written to `--output`) as JSON,
+/// in the format accepted by the certifier (see the `submit` command).
+/// Interrupting with Ctrl-C stops proving gracefully.
+#[derive(Parser)]
+#[command(
+    author,
+    version,
+    about,
+    long_about = None,
+    args_conflicts_with_subcommands = true,
+    subcommand_negates_reqs = true
+)]
+struct Cli {
+    #[command(subcommand)]
+    command: Option<Commands>,
+
+    #[clap(flatten)]
+    prove: ProveArgs,
+}
+
+#[derive(Subcommand)]
+enum Commands {
+    /// Generate a proof (the default command)
+    Prove(ProveArgs),
+    /// Submit a proof written by `prove` to a certifier
+    ///
+    /// Prints the certificate returned by the certifier.
+    Submit(SubmitArgs),
+}
+
+#[derive(Args)]
+struct SubmitArgs {
+    /// Path to the proof file
+    #[arg(long)]
+    proof: PathBuf,
+
+    /// URL of the certify endpoint, i.e. `http://localhost:8080/certify`
+    /// (or `/certify/<network>` for a certifier serving several networks)
+    #[arg(long)]
+    certifier: String,
+
+    /// Write the certificate to the file instead of stdout
+    #[arg(short, long)]
+    output: Option<PathBuf>,
+}
+
+#[derive(Args)]
+struct ProveArgs {
+    /// Directory with initialized POS data
+    #[arg(long, required = true)]
+    datadir: Option<PathBuf>,
+
+    /// Base64-encoded challenge (32 bytes)
+    #[arg(long, required = true)]
+    challenge: Option<String>,
+
+    /// Use the parameters of a known network (`mainnet`, `testnet` or `custom`)
+    #[arg(long, default_value_t = NetworkPreset::Mainnet)]
+    preset: NetworkPreset,
+
+    /// K1 parameter (overrides the preset)
+    #[arg(long)]
+    k1: Option<u32>,
+
+    /// K2 parameter (overrides the preset)
+    #[arg(long)]
+    k2: Option<u32>,
+
+    /// Hex-encoded PoW difficulty (overrides the preset)
+    #[arg(long, value_parser(parse_difficulty))]
+    pow_difficulty: Option<[u8; 32]>,
+
+    /// Scrypt N parameter, needed only with `--verify` (overrides the preset)
+    #[arg(short, long)]
+    n: Option<usize>,
+
+    /// Number of nonces to attempt in a single pass over POS data (a multiple of 16)
+    #[arg(long, default_value_t = 128)]
+    nonces: usize,
+
+    /// Number of threads to use. '0' means use all available threads
+    #[arg(short, long, default_value_t = 0)]
+    threads: usize,
+
+    /// Modes of operation for RandomX.
+    #[arg(long, default_value_t = RandomXMode::Fast)]
+    randomx_mode: RandomXMode,
+
+    /// Use large pages for RandomX (`on`, `off` or `auto` to use them only if available).
+    #[arg(long, default_value_t = LargePages::Off, num_args = 0..=1, default_missing_value = "on")]
+    randomx_large_pages: LargePages,
+
+    /// Network ID for the RandomX cache key (the default key when not set)
+    #[arg(long)]
+    randomx_network_id: Option<String>,
+
+    /// Verify the generated proof
+    #[arg(long)]
+    verify: bool,
+
+    /// Write the proof to the file instead of stdout
+    #[arg(short, long)]
+    output: Option<PathBuf>,
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq, ValueEnum)]
+enum RandomXMode {
+    /// Fast mode for proving. Requires 2080 MiB of memory.
+    Fast,
+    /// Light mode. Requires only 256 MiB of memory, but runs significantly slower
+    Light,
+}
+
+impl std::fmt::Display for RandomXMode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.to_possible_value().unwrap().get_name().fmt(f)
+    }
+}
+
+fn parse_difficulty(arg: &str) -> eyre::Result<[u8; 32]> {
+    hex::decode(arg)?
+        .as_slice()
+        .try_into()
+        .wrap_err("invalid difficulty length")
+}
+
+fn randomx_flags(mode: RandomXMode, large_pages: LargePages) -> eyre::Result<RandomXFlag> {
+    let flags = match mode {
+        RandomXMode::Fast => RandomXFlag::get_recommended_flags() | RandomXFlag::FLAG_FULL_MEM,
+        RandomXMode::Light => RandomXFlag::get_recommended_flags(),
+    };
+    Ok(randomx::with_large_pages(flags, large_pages)?)
+}
+
+/// Set `stop` on Ctrl-C.
+fn stop_on_ctrl_c(stop: Arc<AtomicBool>) -> eyre::Result<()> {
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_io()
+        .build()?;
+    std::thread::spawn(move || {
+        runtime.block_on(async {
+            if tokio::signal::ctrl_c().await.is_ok() {
+                log::warn!("interrupted, stopping proving");
+                stop.store(true, Ordering::Relaxed);
+            }
+        })
+    });
+    Ok(())
+}
+
+fn main() -> eyre::Result<()> {
+    env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("info")).init();
+    let args = Cli::parse();
+
+    match args.command.unwrap_or(Commands::Prove(args.prove)) {
+        Commands::Prove(args) => prove(args),
+        Commands::Submit(args) => submit(args),
+    }
+}
+
+fn prove(args: ProveArgs) -> eyre::Result<()> {
+    let datadir = args.datadir.expect("required by clap");
+    let custom =
+        || eyre::eyre!("--k1, --k2 and --pow-difficulty are required with the custom preset");
+    let preset_cfg = args.preset.proof_config();
+    let cfg = ProofConfig {
+        k1: args.k1.or(preset_cfg.map(|c| c.k1)).ok_or_else(custom)?,
+        k2: args.k2.or(preset_cfg.map(|c| c.k2)).ok_or_else(custom)?,
+        pow_difficulty: args
+            .pow_difficulty
+            .or(preset_cfg.map(|c| c.pow_difficulty))
+            .ok_or_else(custom)?,
+    };
+    cfg.validate()?;
+
+    let challenge: [u8; 32] = general_purpose::STANDARD
+        .decode(args.challenge.expect("required by clap"))
+        .wrap_err("decoding challenge")?
+        .try_into()
+        .map_err(|_| eyre::eyre!("challenge should be 32B"))?;
+
+    let flags = randomx_flags(args.randomx_mode, args.randomx_large_pages)?;
+    let cache_key = randomx::cache_key(args.randomx_network_id.as_deref());
+    log::info!("initializing RandomX ({} mode)", args.randomx_mode);
+    let pow = PoW::with_cache_key(flags, &cache_key)?;
+
+    let stop = Arc::new(AtomicBool::new(false));
+    stop_on_ctrl_c(stop.clone())?;
+
+    log::info!("generating proof for challenge {}", hex::encode(challenge));
+    let started = Instant::now();
+    let proof = generate_proof(
+        &datadir,
+        &challenge,
+        cfg,
+        args.nonces,
+        Cores::Any(args.threads),
+        flags,
+        stop,
+        NoopProgressReporter {},
+        &pow,
+    )?;
+    log::info!(
+        "generated proof in {:.2?} (nonce: {}, pow: {})",
+        started.elapsed(),
+        proof.nonce,
+        proof.pow
+    );
+
+    // Only the initialized units of partially initialized POS data are proven.
+    let post_metadata = post::metadata::load(&datadir)
+        .wrap_err("loading metadata")?
+        .initialized()?;
+    let metadata = ProofMetadata::new(post_metadata, challenge);
+    if args.verify {
+        let scrypt = match args.n {
+            Some(n) => ScryptParams::try_new(n, 1, 1)?,
+            None => {
+                args.preset
+                    .init_config()
+                    .ok_or_else(|| eyre::eyre!("--n is required with the custom preset"))?
+                    .scrypt
+            }
+        };
+        let init_cfg = InitConfig {
+            min_num_units: 1,
+            max_num_units: u32::MAX,
+            labels_per_unit: post_metadata.labels_per_unit,
+            scrypt,
+        };
+        Verifier::new(Box::new(pow))
+            .verify(&proof, &metadata, &cfg, &init_cfg, Mode::All)
+            .wrap_err("verifying generated proof")?;
+        log::info!("proof is valid");
+    }
+
+    let proof = ProofWithMetadata { proof, metadata };
+    match args.output {
+        Some(path) => proof
+            .save(&path)
+            .wrap_err_with(|| format!("writing proof to {}", path.display()))?,
+        None => println!("{}", serde_json::to_string_pretty(&proof)?),
+    }
+    Ok(())
+}
+
+fn submit(args: SubmitArgs) -> eyre::Result<()> {
+    let proof = ProofWithMetadata::load(&args.proof)?;
+    log::info!(
+        "submitting proof for challenge {} to {}",
+        hex::encode(proof.metadata.challenge),
+        args.certifier
+    );
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()?;
+    let (status, body) = runtime.block_on(async {
+        let response = reqwest::Client::new()
+            .post(&args.certifier)
+            .header(reqwest::header::CONTENT_TYPE, "application/json")
+            .body(serde_json::to_vec(&proof)?)
+            .send()
+            .await?;
+        eyre::Ok((response.status(), response.text().await?))
+    })?;
+    eyre::ensure!(
+        status.is_success(),
+        "certifier rejected the proof ({status}): {body}"
+    );
+
+    match args.output {
+        Some(path) => std::fs::write(&path, body)
+            .wrap_err_with(|| format!("writing certificate to {}", path.display()))?,
+        None => println!("{body}"),
+    }
+    Ok(())
+}
@@ -19,6 +19,40 @@ fn create_blacklist_filter(blacklist_re: Option<&str>) -> Box<dyn Fn(&str) -> bo
     }
 }
 
+/// Excludes OpenCL providers by the names of their platforms and devices,
+/// in addition to the `POST_OCL_PLATFORMS_BLACKLIST` and `POST_OCL_DEVICES_BLACKLIST`
+/// environment variables.
+///
+/// The platforms are filtered before listing their devices, so that a platform which
+/// hangs on use (i.e. a virtual or remote one) is not touched.
+#[derive(Debug, Clone, Default)]
+pub struct ProviderFilter {
+    exclude: Vec<Regex>,
+}
+
+impl ProviderFilter {
+    /// A filter excluding no providers (apart from the environment variables).
+    pub const fn new() -> Self {
+        Self {
+            exclude: Vec::new(),
+        }
+    }
+
+    /// Exclude the providers which platform or device name matches any of the
+    /// `patterns` (regular expressions).
+    pub fn exclude<S: AsRef<str>>(patterns: &[S]) -> Result<Self, regex::Error> {
+        let exclude = patterns
+            .iter()
+            .map(|p| Regex::new(p.as_ref()))
+            .collect::<Result<_, _>>()?;
+        Ok(Self { exclude })
+    }
+
+    pub(crate) fn excludes(&self, name: &str) -> bool {
+        self.exclude.iter().any(|re| re.is_match(name))
+    }
+}
+
 pub(crate) fn create_platform_filter() -> Box<dyn Fn(&str) -> bool> {
     create_blacklist_filter(std::env::var(PLATFORMS_BLACKLIST_ENV).ok().as_deref())
 }
@@ -52,6 +86,21 @@ mod tests {
         assert!(filter("foo"));
     }
 
+    #[test]
+    fn test_provider_filter() {
+        let filter = super::ProviderFilter::default();
+        assert!(!filter.excludes("rusticl"));
+
+        let filter = super::ProviderFilter::exclude(&["(?i)rusticl", "^Remote"]).unwrap();
+        assert!(filter.excludes("rusticl"));
+        assert!(filter.excludes("RustiCL"));
+        assert!(filter.excludes("Remote GPU"));
+        assert!(!filter.excludes("NVIDIA CUDA"));
+        assert!(!filter.excludes("Not Remote"));
+
+        assert!(super::ProviderFilter::exclude(&["fo(o"]).is_err());
+    }
+
     #[test]
     fn test_device_filter_env_set() {
         std::env::set_var(super::DEVICES_BLACKLIST_ENV, "foo");
@@ -0,0 +1,128 @@
+//! Stable identifiers of OpenCL devices.
+//!
+//! The position of a device in [crate::get_providers] depends on the installed
+//! platforms and devices. The device UUID or its PCI bus address don't,
+//! so they are used to refer to a device across changes of the hardware.
+use ocl::{core::get_device_info_raw, Device};
+
+// cl_khr_device_uuid
+const CL_DEVICE_UUID_KHR: u32 = 0x106A;
+// cl_khr_pci_bus_info
+const CL_DEVICE_PCI_BUS_INFO_KHR: u32 = 0x410F;
+// cl_nv_device_attribute_query
+const CL_DEVICE_PCI_BUS_ID_NV: u32 = 0x4008;
+const CL_DEVICE_PCI_SLOT_ID_NV: u32 = 0x4009;
+const CL_DEVICE_PCI_DOMAIN_ID_NV: u32 = 0x400A;
+// cl_amd_device_attribute_query
+const CL_DEVICE_TOPOLOGY_AMD: u32 = 0x4037;
+const CL_DEVICE_TOPOLOGY_TYPE_PCIE_AMD: u32 = 1;
+
+/// Get the stable id of the device: its UUID if the driver reports it,
+/// otherwise its PCI bus address (`pci:domain:bus:device.function`).
+pub(crate) fn stable_id(device: &Device) -> Option<String> {
+    let query = |info| get_device_info_raw(device.as_core(), info).ok();
+
+    if let Some(uuid) = query(CL_DEVICE_UUID_KHR).and_then(|raw| format_uuid(&raw)) {
+        return Some(uuid);
+    }
+    if let Some(pci) = query(CL_DEVICE_PCI_BUS_INFO_KHR).and_then(|raw| pci_khr(&raw)) {
+        return Some(pci);
+    }
+    if let (Some(bus), Some(slot)) = (
+        query(CL_DEVICE_PCI_BUS_ID_NV).and_then(|raw| read_u32(&raw, 0)),
+        query(CL_DEVICE_PCI_SLOT_ID_NV).and_then(|raw| read_u32(&raw, 0)),
+    ) {
+        let domain = query(CL_DEVICE_PCI_DOMAIN_ID_NV)
+            .and_then(|raw| read_u32(&raw, 0))
+            .unwrap_or(0);
+        return Some(format_pci(domain, bus, slot >> 3, slot & 0x7));
+    }
+    query(CL_DEVICE_TOPOLOGY_AMD).and_then(|raw| pci_amd(&raw))
+}
+
+fn read_u32(raw: &[u8], offset: usize) -> Option<u32> {
+    let bytes = raw.get(offset..offset + 4)?;
+    Some(u32::from_ne_bytes(bytes.try_into().unwrap()))
+}
+
+fn format_pci(domain: u32, bus: u32, device: u32, function: u32) -> String {
+    format!("pci:{domain:04x}:{bus:02x}:{device:02x}.{function:x}")
+}
+
+fn format_uuid(raw: &[u8]) -> Option<String> {
+    let uuid: &[u8; 16] = raw.try_into().ok()?;
+    // Some drivers expose the extension without knowing the UUID.
+    if uuid.iter().all(|b| *b == 0) {
+        return None;
+    }
+    let hex = |bytes: &[u8]| bytes.iter().map(|b| format!("{b:02x}")).collect::<String>();
+    Some(format!(
+        "{}-{}-{}-{}-{}",
+        hex(&uuid[..4]),
+        hex(&uuid[4..6]),
+        hex(&uuid[6..8]),
+        hex(&uuid[8..10]),
+        hex(&uuid[10..])
+    ))
+}
+
+/// Parse `cl_device_pci_bus_info_khr` (domain, bus, device and function as `cl_uint`s).
+fn pci_khr(raw: &[u8]) -> Option<String> {
+    Some(format_pci(
+        read_u32(raw, 0)?,
+        read_u32(raw, 4)?,
+        read_u32(raw, 8)?,
+        read_u32(raw, 12)?,
+    ))
+}
+
+/// Parse `cl_device_topology_amd`: a `cl_uint` type followed by 17 unused bytes,
+/// and the bus, device and function bytes.
+fn pci_amd(raw: &[u8]) -> Option<String> {
+    if read_u32(raw, 0)? != CL_DEVICE_TOPOLOGY_TYPE_PCIE_AMD {
+        return None;
+    }
+    let pcie = raw.get(21..24)?;
+    Some(format_pci(
+        0,
+        pcie[0] as u32,
+        pcie[1] as u32,
+        pcie[2] as u32,
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn formatting_uuid() {
+        let raw: Vec<u8> = (0..16).collect();
+        assert_eq!(
+            Some("00010203-0405-0607-0809-0a0b0c0d0e0f".to_string()),
+            super::format_uuid(&raw)
+        );
+        assert_eq!(None, super::format_uuid(&[0; 16]));
+        assert_eq!(None, super::format_uuid(&[1; 8]));
+    }
+
+    #[test]
+    fn parsing_pci_bus_info() {
+        let raw: Vec<u8> = [0u32, 0x41, 0, 1]
+            .iter()
+            .flat_map(|v| v.to_ne_bytes())
+            .collect();
+        assert_eq!(Some("pci:0000:41:00.1".to_string()), super::pci_khr(&raw));
+        assert_eq!(None, super::pci_khr(&raw[..12]));
+    }
+
+    #[test]
+    fn parsing_amd_topology() {
+        let mut raw = vec![0u8; 24];
+        raw[..4].copy_from_slice(&super::CL_DEVICE_TOPOLOGY_TYPE_PCIE_AMD.to_ne_bytes());
+        raw[21..].copy_from_slice(&[0x0a, 0, 0]);
+        assert_eq!(Some("pci:0000:0a:00.0".to_string()), super::pci_amd(&raw));
+
+        raw[..4].copy_from_slice(&2u32.to_ne_bytes());
+        assert_eq!(None, super::pci_amd(&raw));
+        assert_eq!(None, super::pci_amd(&raw[..20]));
+    }
+}
@@ -1,12 +1,13 @@
 use ocl::{
     builders::ProgramBuilder,
+    core::Status,
     enums::{DeviceInfo, DeviceInfoResult, KernelWorkGroupInfo, KernelWorkGroupInfoResult},
     Buffer, Context, Device, DeviceType, Event, Kernel, MemFlags, Platform, ProQue, Queue,
     SpatialDims,
 };
 use post::initialize::{Initialize, VrfNonce, ENTIRE_LABEL_SIZE, LABEL_SIZE};
 use std::{
-    cmp::min,
+    cmp::{max, min},
     fmt::Display,
     io::Write,
     ops::Range,
@@ -17,15 +18,101 @@ use thiserror::Error;
 pub use ocl;
 
 mod filtering;
+mod identity;
+pub use filtering::ProviderFilter;
+
+/// How long to wait for the first batches, before the average batch duration is known.
+const INITIAL_HANG_TIMEOUT: Duration = Duration::from_secs(300);
+/// A batch taking this many times longer than the average is considered a hang.
+const HANG_FACTOR: u32 = 20;
+const MIN_HANG_TIMEOUT: Duration = Duration::from_secs(30);
+/// A batch is polled for completion this many times per its average duration.
+const POLLS_PER_BATCH: u32 = 20;
+const MIN_POLL_INTERVAL: Duration = Duration::from_millis(1);
+const MAX_POLL_INTERVAL: Duration = Duration::from_millis(50);
+/// Max number of device restarts after hangs in one [Scrypter::scrypt] call.
+const MAX_RESTARTS: usize = 3;
+
+/// How long to wait for a batch before considering the device hung.
+fn hang_timeout(average: Option<Duration>) -> Duration {
+    match average {
+        Some(average) => max(average * HANG_FACTOR, MIN_HANG_TIMEOUT),
+        None => INITIAL_HANG_TIMEOUT,
+    }
+}
+
+/// How often to check if a batch finished.
+fn poll_interval(average: Option<Duration>) -> Duration {
+    average.map_or(MAX_POLL_INTERVAL, |average| {
+        (average / POLLS_PER_BATCH).clamp(MIN_POLL_INTERVAL, MAX_POLL_INTERVAL)
+    })
+}
+
+/// Halve the global work size after running out of device memory,
+/// keeping it a multiple of the local work size. None if it can't be reduced anymore.
+fn reduced_global_work_size(global_work_size: usize, local_work_size: usize) -> Option<usize> {
+    let reduced = global_work_size / 2 / local_work_size * local_work_size;
+    (reduced >= local_work_size).then_some(reduced)
+}
+
+/// Allocate with `allocate` for batches of up to `global_work_size` labels,
+/// halving the batches while the device is out of memory.
+fn allocate_shrinking<T, E: Display>(
+    mut global_work_size: usize,
+    local_work_size: usize,
+    is_out_of_memory: impl Fn(&E) -> bool,
+    mut allocate: impl FnMut(usize) -> Result<T, E>,
+) -> Result<T, E> {
+    loop {
+        match allocate(global_work_size) {
+            Ok(allocated) => return Ok(allocated),
+            Err(e) if is_out_of_memory(&e) => {
+                let Some(reduced) = reduced_global_work_size(global_work_size, local_work_size)
+                else {
+                    return Err(e);
+                };
+                log::warn!(
+                    "device out of memory ({e}), reducing global work size from {global_work_size} to {reduced}"
+                );
+                global_work_size = reduced;
+            }
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+fn is_out_of_memory(e: &ocl::Error) -> bool {
+    matches!(
+        e.api_status(),
+        Some(Status::CL_MEM_OBJECT_ALLOCATION_FAILURE | Status::CL_OUT_OF_RESOURCES)
+    )
+}
+
+fn is_out_of_memory_error(e: &ScryptError) -> bool {
+    matches!(e, ScryptError::OclError(e) if is_out_of_memory(e))
+}
 
 #[derive(Debug)]
 struct Scrypter {
+    platform: Platform,
+    device: Device,
+    n: usize,
+    /// None after the device objects were released and couldn't be allocated again.
+    batches: Option<Batches>,
+    global_work_size: usize,
+    preferred_wg_size_mult: usize,
+    labels_buffer: Vec<u8>,
+}
+
+/// The OpenCL objects initializing batches of labels on a device.
+#[derive(Debug)]
+struct Batches {
+    queue: Queue,
     kernel: Kernel,
     input: Buffer<u32>,
     output: Buffer<u8>,
     global_work_size: usize,
     preferred_wg_size_mult: usize,
-    labels_buffer: Vec<u8>,
 }
 
 #[derive(Error, Debug)]
@@ -40,8 +127,12 @@ pub enum ScryptError {
     OclCoreError(#[from] ocl::OclCoreError),
     #[error("Invalid provider id: {0:?}")]
     InvalidProviderId(ProviderId),
+    #[error("No provider with id {0}")]
+    UnknownProvider(String),
     #[error("No providers available")]
     NoProvidersAvailable,
+    #[error("Device didn't finish a batch of labels in {0:?} after {MAX_RESTARTS} restarts")]
+    DeviceHang(Duration),
     #[error("Failed to write labels: {0}")]
     WriteError(#[from] std::io::Error),
 }
@@ -57,13 +148,57 @@ macro_rules! cast {
     }};
 }
 
+/// The position of a provider in [get_providers].
+///
+/// It changes when a device is added or removed, see [Provider::stable_id].
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub struct ProviderId(pub u32);
 
+impl ProviderId {
+    /// Find the provider with the `id` in [get_providers].
+    ///
+    /// The `id` is either the position of the provider or its [Provider::stable_id].
+    pub fn find(
+        id: &str,
+        device_types: Option<DeviceType>,
+        filter: &ProviderFilter,
+    ) -> Result<Self, ScryptError> {
+        if let Ok(position) = id.parse() {
+            return Ok(Self(position));
+        }
+        get_providers(device_types, filter)?
+            .iter()
+            .position(|p| p.stable_id.as_deref() == Some(id))
+            .map(|position| Self(position as u32))
+            .ok_or_else(|| ScryptError::UnknownProvider(id.to_string()))
+    }
+}
+
 pub struct Provider {
     pub platform: Platform,
     pub device: Device,
     pub class: DeviceType,
+    /// The UUID or the PCI bus address of the device, if the driver reports any.
+    /// Unlike [ProviderId], it doesn't change when other devices are added or removed.
+    pub stable_id: Option<String>,
+    /// Size of the global memory of the device in bytes.
+    pub memory: u64,
+}
+
+impl Provider {
+    /// Measure how many labels per second the provider initializes with the scrypt `n`.
+    ///
+    /// It compiles the kernel and initializes a few batches of labels,
+    /// so it takes up to a few seconds.
+    pub fn probe_speed(&self, n: usize) -> Result<f64, ScryptError> {
+        let mut scrypter = Scrypter::new(self.platform, self.device, n)?;
+        let batch = scrypter.global_work_size as u64;
+        // The first batch includes warming up the device.
+        scrypter.scrypt(&mut std::io::sink(), 0..batch, &[0; 32], None)?;
+        let start = Instant::now();
+        scrypter.scrypt(&mut std::io::sink(), batch..3 * batch, &[0; 32], None)?;
+        Ok((2 * batch) as f64 / start.elapsed().as_secs_f64())
+    }
 }
 
 impl Display for Provider {
@@ -78,8 +213,8 @@ impl Display for Provider {
     }
 }
 
-pub fn get_providers_count(device_types: Option<DeviceType>) -> usize {
-    get_providers(device_types).map_or_else(
+pub fn get_providers_count(device_types: Option<DeviceType>, filter: &ProviderFilter) -> usize {
+    get_providers(device_types, filter).map_or_else(
         |e| {
             log::error!("failed to get providers: {e}");
             0
@@ -88,28 +223,40 @@ pub fn get_providers_count(device_types: Option<DeviceType>) -> usize {
     )
 }
 
-pub fn get_providers(device_types: Option<DeviceType>) -> Result<Vec<Provider>, ScryptError> {
+/// List the OpenCL providers of the `device_types`, without the ones excluded by `filter`.
+pub fn get_providers(
+    device_types: Option<DeviceType>,
+    filter: &ProviderFilter,
+) -> Result<Vec<Provider>, ScryptError> {
     let list_core = ocl::core::get_platform_ids()?;
     let platforms = Platform::list_from_core(list_core);
 
     let platform_filter = filtering::create_platform_filter();
     let device_filter = filtering::create_device_filter();
 
-    let platforms_filtered = platforms
-        .into_iter()
-        .filter(|p| p.name().map(|n| platform_filter(&n)).unwrap_or(false));
+    let platforms_filtered = platforms.into_iter().filter(|p| {
+        p.name()
+            .map(|n| platform_filter(&n) && !filter.excludes(&n))
+            .unwrap_or(false)
+    });
 
     let mut providers = Vec::new();
     for platform in platforms_filtered {
         let devices = Device::list(platform, device_types)?;
-        for device in devices
-            .into_iter()
-            .filter(|d| d.name().map(|n| device_filter(&n)).unwrap_or(false))
-        {
+        for device in devices.into_iter().filter(|d| {
+            d.name()
+                .map(|n| device_filter(&n) && !filter.excludes(&n))
+                .unwrap_or(false)
+        }) {
             providers.push(Provider {
                 platform,
                 device,
                 class: cast!(device.info(DeviceInfo::Type)?, DeviceInfoResult::Type),
+                stable_id: identity::stable_id(&device),
+                memory: cast!(
+                    device.info(DeviceInfo::GlobalMemSize)?,
+                    DeviceInfoResult::GlobalMemSize
+                ),
             });
         }
     }
@@ -131,8 +278,13 @@ fn scan_for_vrf_nonce(labels: &[u8], mut difficulty: [u8; 32]) -> Option<VrfNonc
     nonce
 }
 
-impl Scrypter {
-    pub fn new(platform: Platform, device: Device, n: usize) -> Result<Self, ScryptError> {
+impl Batches {
+    fn new(
+        platform: Platform,
+        device: Device,
+        n: usize,
+        max_global_work_size: usize,
+    ) -> Result<Self, ScryptError> {
         // Calculate kernel memory requirements
         const LOOKUP_GAP: usize = 2;
         const SCRYPT_MEM: usize = 128;
@@ -203,8 +355,11 @@ impl Scrypter {
         let max_global_work_size_based_on_max_mem_alloc_size =
             (max_mem_alloc_size / kernel_lookup_mem_size as u64) as usize;
         let max_global_work_size = min(
-            max_global_work_size_based_on_max_mem_alloc_size,
-            max_global_work_size_based_on_total_mem,
+            max_global_work_size,
+            min(
+                max_global_work_size_based_on_max_mem_alloc_size,
+                max_global_work_size_based_on_total_mem,
+            ),
         );
         let local_work_size = preferred_wg_size_mult;
         // Round down to nearest multiple of local_work_size
@@ -248,12 +403,46 @@ impl Scrypter {
         kernel.set_default_local_work_size(SpatialDims::One(local_work_size));
 
         Ok(Self {
+            queue: pro_que.queue().clone(),
             kernel,
             input,
             output,
             global_work_size,
             preferred_wg_size_mult,
-            labels_buffer: vec![0u8; global_work_size * ENTIRE_LABEL_SIZE],
+        })
+    }
+
+    /// Wait until the `event` completes, checking it every `poll`.
+    /// Returns false if it doesn't in `timeout`.
+    fn wait_for(
+        &self,
+        event: &Event,
+        poll: Duration,
+        timeout: Duration,
+    ) -> Result<bool, ocl::Error> {
+        self.queue.flush()?;
+        let start = Instant::now();
+        while !event.is_complete()? {
+            if start.elapsed() > timeout {
+                return Ok(false);
+            }
+            std::thread::sleep(poll);
+        }
+        Ok(true)
+    }
+}
+
+impl Scrypter {
+    pub fn new(platform: Platform, device: Device, n: usize) -> Result<Self, ScryptError> {
+        let batches = Batches::new(platform, device, n, usize::MAX)?;
+        Ok(Self {
+            platform,
+            device,
+            n,
+            global_work_size: batches.global_work_size,
+            preferred_wg_size_mult: batches.preferred_wg_size_mult,
+            labels_buffer: vec![0u8; batches.global_work_size * ENTIRE_LABEL_SIZE],
+            batches: Some(batches),
         })
     }
 
@@ -268,16 +457,26 @@ impl Scrypter {
             .chunks(4)
             .map(|chunk| u32::from_le_bytes(chunk.try_into().unwrap()))
             .collect();
-        self.input.write(&commitment).enq()?;
+        match &self.batches {
+            Some(batches) => batches.input.write(&commitment).enq()?,
+            None => self.rebuild(self.global_work_size, &commitment)?,
+        }
 
         let mut best_nonce = None;
         let labels_end = labels.end;
 
         let mut total_kernel_duration = Duration::ZERO;
         let mut last_kernel_duration = Duration::ZERO;
-
-        for (iter, index) in labels.step_by(self.global_work_size).enumerate() {
-            self.kernel.set_arg(1, index)?;
+        let mut restarts = 0;
+
+        let mut iter = 0;
+        let mut index = labels.start;
+        while index < labels_end {
+            let Some(batches) = &mut self.batches else {
+                self.rebuild(self.global_work_size, &commitment)?;
+                continue;
+            };
+            batches.kernel.set_arg(1, index)?;
 
             let index_end = min(index + self.global_work_size as u64, labels_end);
             let labels_to_init = (index_end - index) as usize;
@@ -288,12 +487,14 @@ impl Scrypter {
             } else {
                 self.global_work_size
             };
-            self.kernel
+            batches
+                .kernel
                 .set_default_global_work_size(SpatialDims::One(gws));
 
             let mut kernel_event = Event::empty();
-            unsafe {
-                self.kernel.cmd().enew(&mut kernel_event).enq()?;
+            if let Err(e) = unsafe { batches.kernel.cmd().enew(&mut kernel_event).enq() } {
+                self.shrink_on_out_of_memory(e, &commitment)?;
+                continue;
             }
 
             let read_start = Instant::now();
@@ -305,8 +506,9 @@ impl Scrypter {
             //
             // We skip few 'warmup iterations', as the average kernel duration is not yet reliable.
             let warmup_iters = 10;
-            if iter > warmup_iters {
-                let average = total_kernel_duration.div_f32((iter - warmup_iters) as f32);
+            let average = (iter > warmup_iters)
+                .then(|| total_kernel_duration.div_f32((iter - warmup_iters) as f32));
+            if let Some(average) = average {
                 log::trace!("last execution time: {last_kernel_duration:?}, average: {average:?})");
 
                 #[cfg(not(target_os = "windows"))]
@@ -324,13 +526,41 @@ impl Scrypter {
                 }
             }
 
+            // Watchdog: a batch taking far longer than usual means the driver hung.
+            // Rebuild the kernel and the queues and retry the batch, the previous ones are already written.
+            let timeout = hang_timeout(average);
+            match batches.wait_for(&kernel_event, poll_interval(average), timeout) {
+                Ok(true) => {}
+                Ok(false) => {
+                    if restarts == MAX_RESTARTS {
+                        return Err(ScryptError::DeviceHang(timeout));
+                    }
+                    restarts += 1;
+                    log::warn!(
+                        "batch of labels from {index} didn't finish in {timeout:?}, restarting the device ({restarts}/{MAX_RESTARTS})"
+                    );
+                    // Rebuilding releases the hung queue before creating a new one.
+                    self.rebuild(self.global_work_size, &commitment)?;
+                    continue;
+                }
+                Err(e) => {
+                    self.shrink_on_out_of_memory(e, &commitment)?;
+                    continue;
+                }
+            }
+
             let labels_buffer =
                 &mut self.labels_buffer.as_mut_slice()[..labels_to_init * ENTIRE_LABEL_SIZE];
-            self.output
+            if let Err(e) = batches
+                .output
                 .cmd()
                 .ewait(&kernel_event)
                 .read(labels_buffer.as_mut())
-                .enq()?;
+                .enq()
+            {
+                self.shrink_on_out_of_memory(e, &commitment)?;
+                continue;
+            }
 
             if iter >= warmup_iters {
                 last_kernel_duration = read_start.elapsed();
@@ -359,9 +589,59 @@ impl Scrypter {
                 dst += LABEL_SIZE;
             }
             writer.write_all(&labels_buffer[..dst])?;
+
+            iter += 1;
+            index = index_end;
         }
         Ok(best_nonce)
     }
+
+    /// Rebuild the kernel, the buffers and the queues, with up to `max_global_work_size`
+    /// labels per batch (less if the device doesn't have enough memory for them).
+    fn rebuild(
+        &mut self,
+        max_global_work_size: usize,
+        commitment: &[u32],
+    ) -> Result<(), ScryptError> {
+        // Release the old objects first, the device might not fit both of them.
+        self.batches = None;
+        self.labels_buffer = Vec::new();
+
+        let batches = allocate_shrinking(
+            max_global_work_size,
+            self.preferred_wg_size_mult,
+            is_out_of_memory_error,
+            |global_work_size| Batches::new(self.platform, self.device, self.n, global_work_size),
+        )?;
+        batches.input.write(commitment).enq()?;
+        self.global_work_size = batches.global_work_size;
+        self.preferred_wg_size_mult = batches.preferred_wg_size_mult;
+        self.labels_buffer = vec![0u8; batches.global_work_size * ENTIRE_LABEL_SIZE];
+        self.batches = Some(batches);
+        Ok(())
+    }
+
+    /// Continue with smaller batches if the device ran out of memory
+    /// (i.e. another application allocated it during the initialization).
+    fn shrink_on_out_of_memory(
+        &mut self,
+        e: ocl::Error,
+        commitment: &[u32],
+    ) -> Result<(), ScryptError> {
+        if !is_out_of_memory(&e) {
+            return Err(e.into());
+        }
+        let Some(reduced) =
+            reduced_global_work_size(self.global_work_size, self.preferred_wg_size_mult)
+        else {
+            return Err(e.into());
+        };
+        log::warn!(
+            "device out of memory ({e}), reducing global work size from {} to {reduced}",
+            self.global_work_size
+        );
+        self.rebuild(reduced, commitment)
+    }
 }
 
 pub struct OpenClInitializer {
@@ -369,12 +649,14 @@ pub struct OpenClInitializer {
 }
 
 impl OpenClInitializer {
+    /// Initialize with the `provider_id` provider of [get_providers] (the first one if not set).
     pub fn new(
         provider_id: Option<ProviderId>,
         n: usize,
         device_types: Option<DeviceType>,
+        filter: &ProviderFilter,
     ) -> Result<Self, ScryptError> {
-        let providers = get_providers(device_types)?;
+        let providers = get_providers(device_types, filter)?;
         let provider = if let Some(id) = provider_id {
             log::info!(
                 "selecting {} provider from {} available",
@@ -404,10 +686,17 @@ impl Initialize for OpenClInitializer {
         commitment: &[u8; 32],
         labels: Range<u64>,
         vrf_difficulty: Option<[u8; 32]>,
-    ) -> Result<Option<VrfNonce>, Box<dyn std::error::Error>> {
+    ) -> Result<Option<VrfNonce>, post::Error> {
         self.scrypter
             .scrypt(writer, labels, commitment, vrf_difficulty)
-            .map_err(Into::into)
+            .map_err(|e| match e {
+                ScryptError::WriteError(e) => post::Error::Io(e),
+                e => post::Error::Initializer(Box::new(e)),
+            })
+    }
+
+    fn scrypt_params(&self) -> Option<post::config::ScryptParams> {
+        Some(post::config::ScryptParams::new(self.scrypter.n, 1, 1))
     }
 }
 
@@ -421,6 +710,88 @@ mod tests {
 
     use super::*;
 
+    #[test]
+    fn hang_timeout() {
+        assert_eq!(INITIAL_HANG_TIMEOUT, super::hang_timeout(None));
+        assert_eq!(
+            MIN_HANG_TIMEOUT,
+            super::hang_timeout(Some(Duration::from_millis(100)))
+        );
+        assert_eq!(
+            Duration::from_secs(40),
+            super::hang_timeout(Some(Duration::from_secs(2)))
+        );
+    }
+
+    #[test]
+    fn poll_interval() {
+        assert_eq!(MAX_POLL_INTERVAL, super::poll_interval(None));
+        assert_eq!(
+            Duration::from_millis(10),
+            super::poll_interval(Some(Duration::from_millis(200)))
+        );
+        assert_eq!(
+            MIN_POLL_INTERVAL,
+            super::poll_interval(Some(Duration::from_millis(5)))
+        );
+        assert_eq!(
+            MAX_POLL_INTERVAL,
+            super::poll_interval(Some(Duration::from_secs(10)))
+        );
+    }
+
+    #[test]
+    fn reducing_global_work_size() {
+        assert_eq!(Some(512), reduced_global_work_size(1024, 64));
+        assert_eq!(Some(448), reduced_global_work_size(960, 64));
+        assert_eq!(Some(64), reduced_global_work_size(128, 64));
+        assert_eq!(None, reduced_global_work_size(64, 64));
+        assert_eq!(None, reduced_global_work_size(100, 64));
+    }
+
+    #[test]
+    fn shrinking_allocation_on_out_of_memory() {
+        let mut attempts = Vec::new();
+        let allocated = allocate_shrinking(
+            1024,
+            64,
+            |e: &&str| *e == "out of memory",
+            |size| {
+                attempts.push(size);
+                if size > 256 {
+                    Err("out of memory")
+                } else {
+                    Ok(size)
+                }
+            },
+        );
+        assert_eq!(Ok(256), allocated);
+        assert_eq!(vec![1024, 512, 256], attempts);
+
+        // Gives up when the batches can't be smaller.
+        let allocated = allocate_shrinking(
+            128,
+            64,
+            |e: &&str| *e == "out of memory",
+            |_| Err::<usize, _>("out of memory"),
+        );
+        assert_eq!(Err("out of memory"), allocated);
+
+        // Other errors aren't retried.
+        let mut attempts = 0;
+        let allocated = allocate_shrinking(
+            1024,
+            64,
+            |e: &&str| *e == "out of memory",
+            |_| {
+                attempts += 1;
+                Err::<usize, _>("invalid device")
+            },
+        );
+        assert_eq!(Err("invalid device"), allocated);
+        assert_eq!(1, attempts);
+    }
+
     #[test]
     fn scanning_for_vrf_nonce() {
         let labels = [[0xFF; 32], [0xEE; 32], [0xDD; 32], [0xEE; 32]];
@@ -437,7 +808,8 @@ mod tests {
 
     #[test]
     fn scrypting_1_label() {
-        let mut scrypter = OpenClInitializer::new(None, 8192, None).unwrap();
+        let mut scrypter =
+            OpenClInitializer::new(None, 8192, None, &ProviderFilter::default()).unwrap();
         let mut labels = Vec::new();
         scrypter
             .initialize_to(&mut labels, &[0u8; 32], 0..1, None)
@@ -460,7 +832,8 @@ mod tests {
     fn scrypting_from_0(#[case] n: usize) {
         let indices = 0..4000;
 
-        let mut scrypter = OpenClInitializer::new(None, n, None).unwrap();
+        let mut scrypter =
+            OpenClInitializer::new(None, n, None, &ProviderFilter::default()).unwrap();
         let mut labels = Vec::new();
         scrypter
             .initialize_to(&mut labels, &[0u8; 32], indices.clone(), None)
@@ -485,7 +858,8 @@ mod tests {
     fn scrypting_over_4gb(#[case] n: usize) {
         let indices = u32::MAX as u64 - 1000..u32::MAX as u64 + 1000;
 
-        let mut scrypter = OpenClInitializer::new(None, n, None).unwrap();
+        let mut scrypter =
+            OpenClInitializer::new(None, n, None, &ProviderFilter::default()).unwrap();
         let mut labels = Vec::new();
         scrypter
             .initialize_to(&mut labels, &[0u8; 32], indices.clone(), None)
@@ -506,7 +880,8 @@ mod tests {
         let indices = 0..1000;
         let commitment = b"this is some commitment for init";
 
-        let mut scrypter = OpenClInitializer::new(None, 8192, None).unwrap();
+        let mut scrypter =
+            OpenClInitializer::new(None, 8192, None, &ProviderFilter::default()).unwrap();
         let mut labels = Vec::new();
         scrypter
             .initialize_to(&mut labels, commitment, indices.clone(), None)
@@ -535,7 +910,8 @@ mod tests {
         difficulty[0] = 0;
         difficulty[1] = 0x2F;
 
-        let mut scrypter = OpenClInitializer::new(None, n, None).unwrap();
+        let mut scrypter =
+            OpenClInitializer::new(None, n, None, &ProviderFilter::default()).unwrap();
         let mut labels = Vec::new();
         let opencl_nonce = scrypter
             .initialize_to(&mut labels, commitment, indices.clone(), Some(difficulty))
@@ -564,7 +940,8 @@ mod tests {
     fn initialize_in_batches() {
         const N: usize = 512;
 
-        let mut initializer = OpenClInitializer::new(None, N, None).unwrap();
+        let mut initializer =
+            OpenClInitializer::new(None, N, None, &ProviderFilter::default()).unwrap();
         let gws = initializer.scrypter.global_work_size as u64;
 
         let mut labels = Vec::<u8>::new();
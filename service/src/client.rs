@@ -5,7 +5,8 @@
 //! It then waits for requests from the node and forwards them to the Post Service.
 
 use http::uri::{Scheme, Uri};
-use std::time::Duration;
+use std::path::PathBuf;
+use std::time::{Duration, SystemTime};
 
 use post::metadata::PostMetadata;
 pub(crate) use spacemesh_v1::post_service_client::PostServiceClient;
@@ -24,15 +25,60 @@ use tonic::transport::Identity;
 use tonic::Request;
 
 use crate::client::spacemesh_v1::MetadataResponse;
-use crate::service::ProofGenState;
+use crate::service::{ChallengeError, ProofGenState};
 
 pub mod spacemesh_v1 {
     tonic::include_proto!("spacemesh.v1");
 }
 
+/// How often to check if the TLS files changed.
+const TLS_CHECK_INTERVAL: Duration = Duration::from_secs(30);
+
 pub struct ServiceClient<S: PostService> {
+    address: String,
     endpoint: Endpoint,
     service: S,
+    tls_files: Option<TlsFiles>,
+    tls_modified: Option<SystemTime>,
+}
+
+/// TLS configuration loaded from files.
+///
+/// The files are reloaded (and the connection to the node re-established)
+/// when they change or when the process receives SIGHUP.
+#[derive(Clone, Debug)]
+pub struct TlsFiles {
+    /// domain name to verify the certificate of server against
+    pub domain: Option<String>,
+    /// CA certificate of the server
+    pub ca_cert: PathBuf,
+    /// client certificate
+    pub cert: PathBuf,
+    /// client private key
+    pub key: PathBuf,
+}
+
+impl TlsFiles {
+    /// Read the certificates and the key.
+    pub fn load(&self) -> eyre::Result<(Option<String>, Certificate, Identity)> {
+        let read = |path: &PathBuf| {
+            std::fs::read_to_string(path)
+                .map_err(|e| eyre::eyre!("reading {}: {e}", path.display()))
+        };
+        Ok((
+            self.domain.clone(),
+            Certificate::from_pem(read(&self.ca_cert)?),
+            Identity::from_pem(read(&self.cert)?, read(&self.key)?),
+        ))
+    }
+
+    /// The most recent modification time of the files.
+    fn modified(&self) -> Option<SystemTime> {
+        [&self.ca_cert, &self.cert, &self.key]
+            .into_iter()
+            .filter_map(|p| std::fs::metadata(p).and_then(|m| m.modified()).ok())
+            .max()
+    }
 }
 
 #[mockall::automock]
@@ -69,48 +115,69 @@ impl<S: PostService> ServiceClient<S> {
         tls: Option<(Option<String>, Certificate, Identity)>,
         service: S,
     ) -> eyre::Result<Self> {
-        let listen_address = address.parse::<Uri>()?;
-        let parts = listen_address.into_parts();
-        let scheme = parts.scheme.unwrap_or(Scheme::HTTP);
-        if !["http", "https"].contains(&scheme.as_str()) {
-            return Err(eyre::eyre!("unknown client protocol"));
-        };
+        Ok(Self {
+            endpoint: create_endpoint(&address, tls)?,
+            address,
+            service,
+            tls_files: None,
+            tls_modified: None,
+        })
+    }
 
-        let endpoint = Channel::builder(address.parse()?)
-            .keep_alive_timeout(Duration::from_secs(20))
-            .http2_keep_alive_interval(Duration::from_secs(10 * 60));
-
-        let endpoint = match tls {
-            Some((domain, cert, identity)) => {
-                let domain = match domain {
-                    Some(domain) => domain,
-                    None => endpoint
-                        .uri()
-                        .authority()
-                        .ok_or_else(|| eyre::eyre!("no domain name in the endpoint"))?
-                        .host()
-                        .to_string(),
-                };
-
-                endpoint.tls_config(
-                    ClientTlsConfig::new()
-                        .domain_name(domain)
-                        .ca_certificate(cert)
-                        .identity(identity),
-                )?
-            }
-            None => {
-                if scheme == Scheme::HTTPS {
-                    return Err(eyre::eyre!(
-                        "client protocol set to https but tls configuration not provided"
-                    ));
-                }
+    /// Create a client using TLS configuration from files, which are reloaded when they change.
+    pub fn with_tls_files(address: String, tls: TlsFiles, service: S) -> eyre::Result<Self> {
+        let tls_modified = tls.modified();
+        Ok(Self {
+            endpoint: create_endpoint(&address, Some(tls.load()?))?,
+            address,
+            service,
+            tls_files: Some(tls),
+            tls_modified,
+        })
+    }
 
-                endpoint
-            }
+    /// Reload the TLS files (if used) to pick up renewed certificates.
+    /// Keeps the current configuration if the files cannot be loaded.
+    fn reload_tls(&mut self) {
+        let Some(files) = &self.tls_files else {
+            return;
         };
+        let modified = files.modified();
+        match files
+            .load()
+            .and_then(|tls| create_endpoint(&self.address, Some(tls)))
+        {
+            Ok(endpoint) => {
+                self.endpoint = endpoint;
+                self.tls_modified = modified;
+            }
+            Err(e) => log::error!("failed to reload TLS configuration: {e:?}"),
+        }
+    }
 
-        Ok(Self { endpoint, service })
+    /// Resolves when the TLS files changed or on SIGHUP.
+    /// Never resolves if TLS files are not used.
+    async fn tls_changed(&self) {
+        let Some(files) = &self.tls_files else {
+            return std::future::pending().await;
+        };
+        let mut interval = tokio::time::interval(TLS_CHECK_INTERVAL);
+        let hangup = hangup();
+        tokio::pin!(hangup);
+        loop {
+            tokio::select! {
+                _ = interval.tick() => {
+                    if files.modified() > self.tls_modified {
+                        log::info!("TLS files changed");
+                        return;
+                    }
+                }
+                _ = &mut hangup => {
+                    log::info!("received SIGHUP");
+                    return;
+                }
+            }
+        }
     }
 
     pub async fn run(
@@ -119,6 +186,7 @@ impl<S: PostService> ServiceClient<S> {
         reconnect_interval: Duration,
     ) -> eyre::Result<()> {
         loop {
+            self.reload_tls();
             let mut attempt = 1;
             let client = loop {
                 log::debug!(
@@ -154,7 +222,20 @@ impl<S: PostService> ServiceClient<S> {
             .await?;
         let mut inbound = response.into_inner();
 
-        while let Some(request) = inbound.message().await? {
+        let tls_changed = self.tls_changed();
+        tokio::pin!(tls_changed);
+
+        loop {
+            let request = tokio::select! {
+                request = inbound.message() => match request? {
+                    Some(request) => request,
+                    None => break,
+                },
+                _ = &mut tls_changed => {
+                    log::info!("reconnecting to reload TLS configuration");
+                    break;
+                }
+            };
             log::debug!("Got request from node: {request:?}");
             match request.kind {
                 Some(node_request::Kind::Metadata(_)) => {
@@ -181,6 +262,7 @@ impl<S: PostService> ServiceClient<S> {
         Ok(())
     }
 
+    #[tracing::instrument(skip_all, fields(challenge = hex::encode_upper(&request.challenge)))]
     fn generate_and_verify_proof(&self, request: GenProofRequest) -> ServiceResponse {
         let result = self.service.gen_proof(&request.challenge);
 
@@ -231,6 +313,48 @@ impl<S: PostService> ServiceClient<S> {
                     })),
                 }
             }
+            Err(e)
+                if matches!(
+                    e.downcast_ref::<ChallengeError>(),
+                    Some(ChallengeError::InProgress { .. } | ChallengeError::NotFetched { .. })
+                ) =>
+            {
+                // Busy with the challenge of another node: not a failure of this challenge,
+                // UNSPECIFIED (as for exhausted nonces) tells the node to ask again later.
+                log::warn!("rejected proof generation: {e}");
+                ServiceResponse {
+                    kind: Some(service_response::Kind::GenProof(GenProofResponse {
+                        status: GenProofStatus::Unspecified as i32,
+                        ..Default::default()
+                    })),
+                }
+            }
+            Err(e) if e.downcast_ref::<ChallengeError>().is_some() => {
+                log::warn!("rejected proof generation: {e}");
+                ServiceResponse {
+                    kind: Some(service_response::Kind::GenProof(GenProofResponse {
+                        status: GenProofStatus::Error as i32,
+                        ..Default::default()
+                    })),
+                }
+            }
+            Err(e)
+                if matches!(
+                    e.downcast_ref::<post::Error>(),
+                    Some(post::Error::NoncesExhausted { .. })
+                ) =>
+            {
+                // Nonces exhausted (`--max-nonces`) without a proof. The node API has
+                // no dedicated status for it, so it is answered with UNSPECIFIED
+                // (instead of ERROR) to tell it apart from the other failures.
+                log::error!("gave up generating proof: {e:#}");
+                ServiceResponse {
+                    kind: Some(service_response::Kind::GenProof(GenProofResponse {
+                        status: GenProofStatus::Unspecified as i32,
+                        ..Default::default()
+                    })),
+                }
+            }
             Err(e) => {
                 log::error!("failed to generate proof: {e:?}");
                 ServiceResponse {
@@ -243,6 +367,7 @@ impl<S: PostService> ServiceClient<S> {
         }
     }
 
+    #[tracing::instrument(skip_all)]
     fn get_metadata(&self) -> ServiceResponse {
         let meta = self.service.get_metadata();
         log::info!("obtained metadata: {meta:?}");
@@ -254,6 +379,87 @@ impl<S: PostService> ServiceClient<S> {
     }
 }
 
+/// Check that the node is reachable on `address` (with TLS if configured), without registering.
+pub async fn check_connection(
+    address: &str,
+    tls: Option<&TlsFiles>,
+    timeout: Duration,
+) -> eyre::Result<()> {
+    let tls = tls.map(TlsFiles::load).transpose()?;
+    create_endpoint(address, tls)?
+        .connect_timeout(timeout)
+        .connect()
+        .await?;
+    Ok(())
+}
+
+fn create_endpoint(
+    address: &str,
+    tls: Option<(Option<String>, Certificate, Identity)>,
+) -> eyre::Result<Endpoint> {
+    let listen_address = address.parse::<Uri>()?;
+    let parts = listen_address.into_parts();
+    let scheme = parts.scheme.unwrap_or(Scheme::HTTP);
+    if !["http", "https"].contains(&scheme.as_str()) {
+        return Err(eyre::eyre!("unknown client protocol"));
+    };
+
+    let endpoint = Channel::builder(address.parse()?)
+        .keep_alive_timeout(Duration::from_secs(20))
+        .http2_keep_alive_interval(Duration::from_secs(10 * 60));
+
+    let endpoint = match tls {
+        Some((domain, cert, identity)) => {
+            let domain = match domain {
+                Some(domain) => domain,
+                None => endpoint
+                    .uri()
+                    .authority()
+                    .ok_or_else(|| eyre::eyre!("no domain name in the endpoint"))?
+                    .host()
+                    .to_string(),
+            };
+
+            endpoint.tls_config(
+                ClientTlsConfig::new()
+                    .domain_name(domain)
+                    .ca_certificate(cert)
+                    .identity(identity),
+            )?
+        }
+        None => {
+            if scheme == Scheme::HTTPS {
+                return Err(eyre::eyre!(
+                    "client protocol set to https but tls configuration not provided"
+                ));
+            }
+
+            endpoint
+        }
+    };
+
+    Ok(endpoint)
+}
+
+#[cfg(unix)]
+async fn hangup() {
+    use tokio::signal::unix::{signal, SignalKind};
+    match signal(SignalKind::hangup()) {
+        Ok(mut signal) => {
+            signal.recv().await;
+        }
+        Err(e) => {
+            log::warn!("failed to listen for SIGHUP: {e}");
+            std::future::pending().await
+        }
+    }
+}
+
+#[cfg(not(unix))]
+async fn hangup() {
+    std::future::pending().await
+}
+
 fn convert_metadata(meta: PostMetadata) -> spacemesh_v1::Metadata {
     spacemesh_v1::Metadata {
         node_id: meta.node_id.to_vec(),
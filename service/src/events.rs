@@ -0,0 +1,183 @@
+//! Structured log of the events of a proof generation.
+//!
+//! The events of the last proof generation are appended (as JSON lines) to a file
+//! in the POST data directory, so that it can be investigated afterwards
+//! why generating a proof took long.
+
+use std::{
+    collections::BTreeMap,
+    fs::File,
+    io::{BufRead, BufReader, Write},
+    ops::Range,
+    path::{Path, PathBuf},
+    sync::Mutex,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use post::prove::Proof;
+use serde::{Deserialize, Serialize};
+use serde_with::{hex::Hex, serde_as, DisplayFromStr, DurationSecondsWithFrac};
+
+pub const EVENTS_FILE_NAME: &str = "proof_events.jsonl";
+
+#[serde_as]
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum Event {
+    /// Proof generation started.
+    Started {
+        #[serde_as(as = "Hex")]
+        challenge: [u8; 32],
+        nonces: usize,
+    },
+    /// K2pow was found for the nonce group.
+    K2pow {
+        nonce_group: u32,
+        pow: u64,
+        #[serde_as(as = "DurationSecondsWithFrac<f64>")]
+        duration: Duration,
+    },
+    /// A pass over POS data started.
+    PassStarted { nonces: Range<u32> },
+    /// A pass over POS data finished.
+    PassFinished {
+        nonces: Range<u32>,
+        /// The number of indices found for each nonce.
+        #[serde_as(as = "BTreeMap<DisplayFromStr, _>")]
+        indices_found: BTreeMap<u32, usize>,
+        #[serde_as(as = "DurationSecondsWithFrac<f64>")]
+        duration: Duration,
+    },
+    /// The proof was found.
+    Finished { proof: Proof<'static> },
+    /// Proof generation failed.
+    Failed { error: String },
+}
+
+/// An event with the time it happened at.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Record {
+    /// Milliseconds since the UNIX epoch.
+    pub timestamp: u64,
+    #[serde(flatten)]
+    pub event: Event,
+}
+
+/// Appends the events of a proof generation to a JSONL file.
+#[derive(Debug)]
+pub struct EventLog {
+    path: PathBuf,
+    file: Mutex<Option<File>>,
+    records: Mutex<Vec<Record>>,
+    pass_started: Mutex<Instant>,
+}
+
+impl EventLog {
+    /// Start a new log in `state_dir`, replacing the log of the previous proof generation.
+    pub fn create(state_dir: &Path) -> Self {
+        let path = state_dir.join(EVENTS_FILE_NAME);
+        let file = File::create(&path)
+            .inspect_err(|e| log::warn!("failed to create {}: {e}", path.display()))
+            .ok();
+        Self {
+            path,
+            file: Mutex::new(file),
+            records: Mutex::new(Vec::new()),
+            pass_started: Mutex::new(Instant::now()),
+        }
+    }
+
+    pub fn record(&self, event: Event) {
+        if matches!(event, Event::PassStarted { .. }) {
+            *self.pass_started.lock().unwrap() = Instant::now();
+        }
+        let record = Record {
+            timestamp: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_millis() as u64,
+            event,
+        };
+        let mut file = self.file.lock().unwrap();
+        if let Some(f) = file.as_mut() {
+            let res = serde_json::to_string(&record)
+                .map_err(std::io::Error::from)
+                .and_then(|line| writeln!(f, "{line}"));
+            if let Err(e) = res {
+                log::warn!("failed to write to {}: {e}", self.path.display());
+                *file = None;
+            }
+        }
+        self.records.lock().unwrap().push(record);
+    }
+
+    /// The events recorded so far.
+    pub fn records(&self) -> Vec<Record> {
+        self.records.lock().unwrap().clone()
+    }
+
+    /// Time elapsed since the current pass started.
+    pub fn pass_duration(&self) -> Duration {
+        self.pass_started.lock().unwrap().elapsed()
+    }
+}
+
+/// Read the events of the last proof generation from `state_dir`.
+pub fn read(state_dir: &Path) -> eyre::Result<Vec<Record>> {
+    let file = match File::open(state_dir.join(EVENTS_FILE_NAME)) {
+        Ok(file) => file,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e.into()),
+    };
+    BufReader::new(file)
+        .lines()
+        .map(|line| Ok(serde_json::from_str(&line?)?))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn record_and_read() {
+        let datadir = tempfile::tempdir().unwrap();
+        assert!(read(datadir.path()).unwrap().is_empty());
+
+        let events = vec![
+            Event::Started {
+                challenge: [0xCA; 32],
+                nonces: 32,
+            },
+            Event::PassStarted { nonces: 0..32 },
+            Event::K2pow {
+                nonce_group: 0,
+                pow: 77,
+                duration: Duration::from_millis(1500),
+            },
+            Event::PassFinished {
+                nonces: 0..32,
+                indices_found: BTreeMap::from([(0, 3), (7, 37)]),
+                duration: Duration::from_secs(60),
+            },
+        ];
+        let log = EventLog::create(datadir.path());
+        for event in &events {
+            log.record(event.clone());
+        }
+        let read_events: Vec<_> = read(datadir.path())
+            .unwrap()
+            .into_iter()
+            .map(|r| r.event)
+            .collect();
+        assert_eq!(events, read_events);
+
+        // A new log replaces the old one
+        let log = EventLog::create(datadir.path());
+        log.record(Event::Failed {
+            error: "stopped".into(),
+        });
+        let records = read(datadir.path()).unwrap();
+        assert_eq!(1, records.len());
+    }
+}
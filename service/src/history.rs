@@ -0,0 +1,220 @@
+//! Bounded history of the completed proof generations.
+//!
+//! A summary of every proof generation (found a proof or failed) is kept in a file
+//! in the POST data directory, so that operators can track the proving performance
+//! over epochs. Only the most recent [HISTORY_SIZE] generations are kept.
+
+use std::{
+    collections::VecDeque,
+    path::{Path, PathBuf},
+    sync::Mutex,
+    time::Duration,
+};
+
+use serde::{Deserialize, Serialize};
+use serde_with::{hex::Hex, serde_as, DurationSecondsWithFrac};
+
+use crate::events::{Event, Record};
+
+pub const HISTORY_FILE_NAME: &str = "proof_history.json";
+pub const HISTORY_SIZE: usize = 100;
+
+/// The summary of a completed proof generation.
+#[serde_as]
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ProofSummary {
+    #[serde_as(as = "Hex")]
+    pub challenge: [u8; 32],
+    /// When the proof generation started (milliseconds since the UNIX epoch).
+    pub started: u64,
+    #[serde_as(as = "DurationSecondsWithFrac<f64>")]
+    pub duration: Duration,
+    /// The number of passes over POS data.
+    pub passes: usize,
+    /// The number of nonces in a pass.
+    pub nonces: usize,
+    #[serde(flatten)]
+    pub result: ProofResult,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "result", rename_all = "snake_case")]
+pub enum ProofResult {
+    Found { nonce: u32, pow: u64 },
+    Failed { error: String },
+}
+
+impl ProofSummary {
+    /// Summarize the events of a proof generation.
+    /// `None` if the events don't cover a complete proof generation.
+    pub fn from_records(records: &[Record]) -> Option<Self> {
+        let (first, last) = (records.first()?, records.last()?);
+        let Event::Started { challenge, nonces } = &first.event else {
+            return None;
+        };
+        let result = match &last.event {
+            Event::Finished { proof } => ProofResult::Found {
+                nonce: proof.nonce,
+                pow: proof.pow,
+            },
+            Event::Failed { error } => ProofResult::Failed {
+                error: error.clone(),
+            },
+            _ => return None,
+        };
+        Some(Self {
+            challenge: *challenge,
+            started: first.timestamp,
+            duration: Duration::from_millis(last.timestamp.saturating_sub(first.timestamp)),
+            passes: records
+                .iter()
+                .filter(|r| matches!(r.event, Event::PassStarted { .. }))
+                .count(),
+            nonces: *nonces,
+            result,
+        })
+    }
+}
+
+/// The history of proof generations, persisted in the POST data directory.
+#[derive(Debug)]
+pub struct History {
+    path: PathBuf,
+    // The newest first.
+    entries: Mutex<VecDeque<ProofSummary>>,
+}
+
+impl History {
+    /// Load the history from `state_dir` (empty if there is none yet).
+    pub fn load(state_dir: &Path) -> Self {
+        let path = state_dir.join(HISTORY_FILE_NAME);
+        let entries = match std::fs::read(&path) {
+            Ok(data) => serde_json::from_slice(&data)
+                .inspect_err(|e| log::warn!("failed to decode {}: {e}", path.display()))
+                .unwrap_or_default(),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => VecDeque::new(),
+            Err(e) => {
+                log::warn!("failed to read {}: {e}", path.display());
+                VecDeque::new()
+            }
+        };
+        Self {
+            path,
+            entries: Mutex::new(entries),
+        }
+    }
+
+    /// Add the summary of a completed proof generation, dropping the oldest one if full.
+    pub fn push(&self, summary: ProofSummary) {
+        let mut entries = self.entries.lock().unwrap();
+        entries.push_front(summary);
+        entries.truncate(HISTORY_SIZE);
+        if let Err(e) = self.save(&entries) {
+            log::warn!("failed to write {}: {e:?}", self.path.display());
+        }
+    }
+
+    /// The summaries, the newest first.
+    pub fn entries(&self) -> Vec<ProofSummary> {
+        self.entries.lock().unwrap().iter().cloned().collect()
+    }
+
+    fn save(&self, entries: &VecDeque<ProofSummary>) -> eyre::Result<()> {
+        let tmp_path = self.path.with_extension("json.tmp");
+        std::fs::write(&tmp_path, serde_json::to_vec(entries)?)?;
+        std::fs::rename(&tmp_path, &self.path)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use post::prove::Proof;
+
+    use super::*;
+
+    fn record(timestamp: u64, event: Event) -> Record {
+        Record { timestamp, event }
+    }
+
+    #[test]
+    fn summarizing_events() {
+        let started = record(
+            1000,
+            Event::Started {
+                challenge: [0xCA; 32],
+                nonces: 32,
+            },
+        );
+        let mut records = vec![
+            started.clone(),
+            record(1001, Event::PassStarted { nonces: 0..32 }),
+            record(2000, Event::PassStarted { nonces: 32..64 }),
+        ];
+        // Not finished yet
+        assert_eq!(None, ProofSummary::from_records(&records));
+
+        records.push(record(
+            3500,
+            Event::Finished {
+                proof: Proof::new(40, &[1, 2], 4, 77),
+            },
+        ));
+        assert_eq!(
+            Some(ProofSummary {
+                challenge: [0xCA; 32],
+                started: 1000,
+                duration: Duration::from_millis(2500),
+                passes: 2,
+                nonces: 32,
+                result: ProofResult::Found { nonce: 40, pow: 77 },
+            }),
+            ProofSummary::from_records(&records)
+        );
+
+        let failed = [
+            started,
+            record(
+                1500,
+                Event::Failed {
+                    error: "stopped".into(),
+                },
+            ),
+        ];
+        let summary = ProofSummary::from_records(&failed).unwrap();
+        assert_eq!(0, summary.passes);
+        assert_eq!(
+            ProofResult::Failed {
+                error: "stopped".into()
+            },
+            summary.result
+        );
+        assert_eq!(None, ProofSummary::from_records(&failed[1..]));
+    }
+
+    #[test]
+    fn keeping_bounded_history() {
+        let datadir = tempfile::tempdir().unwrap();
+        let history = History::load(datadir.path());
+        assert!(history.entries().is_empty());
+
+        let summary = |started| ProofSummary {
+            challenge: [0xCA; 32],
+            started,
+            duration: Duration::from_secs(60),
+            passes: 1,
+            nonces: 16,
+            result: ProofResult::Found { nonce: 1, pow: 2 },
+        };
+        for started in 0..HISTORY_SIZE as u64 + 5 {
+            history.push(summary(started));
+        }
+        let entries = history.entries();
+        assert_eq!(HISTORY_SIZE, entries.len());
+        assert_eq!(summary(HISTORY_SIZE as u64 + 4), entries[0]);
+        assert_eq!(summary(5), entries[HISTORY_SIZE - 1]);
+
+        // Survives restarts
+        assert_eq!(entries, History::load(datadir.path()).entries());
+    }
+}
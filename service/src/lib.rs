@@ -1,3 +1,15 @@
 pub mod client;
+pub mod events;
+pub mod history;
+pub mod logging;
+pub mod memory;
 pub mod operator;
+pub mod planner;
+pub mod schedule;
+pub mod self_test;
 pub mod service;
+pub mod simulation;
+pub mod supervisor;
+#[cfg(feature = "otlp")]
+pub mod telemetry;
+pub mod workers;
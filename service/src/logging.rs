@@ -0,0 +1,310 @@
+//! Logger whose filter can be changed while the service is running
+//! (with the operator API or SIGUSR1, see [set_filter] and [toggle_debug]).
+//!
+//! The most recent log records are also kept in memory, so that they can be
+//! served by the operator API (see [logs]).
+
+use std::{
+    collections::VecDeque,
+    str::FromStr,
+    sync::{LazyLock, Mutex, OnceLock, RwLock},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use log::{Level, LevelFilter, Log, Metadata, Record};
+use serde::{Deserialize, Serialize};
+use serde_with::{serde_as, DisplayFromStr};
+use tokio::sync::broadcast;
+
+/// The number of the most recent log records kept in memory.
+pub const LOG_BUFFER_SIZE: usize = 1000;
+
+static LOGGER: OnceLock<ReloadableLogger> = OnceLock::new();
+static LOGS: LazyLock<LogBuffer> = LazyLock::new(|| LogBuffer::new(LOG_BUFFER_SIZE));
+
+/// A log record kept in memory.
+#[serde_as]
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct LogRecord {
+    /// Milliseconds since the UNIX epoch.
+    pub timestamp: u64,
+    #[serde_as(as = "DisplayFromStr")]
+    pub level: Level,
+    pub target: String,
+    pub message: String,
+}
+
+impl From<&Record<'_>> for LogRecord {
+    fn from(record: &Record) -> Self {
+        Self {
+            timestamp: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_millis() as u64,
+            level: record.level(),
+            target: record.target().to_string(),
+            message: record.args().to_string(),
+        }
+    }
+}
+
+/// A ring buffer of the most recent log records, which can also be followed.
+pub struct LogBuffer {
+    records: Mutex<VecDeque<LogRecord>>,
+    capacity: usize,
+    sender: broadcast::Sender<LogRecord>,
+}
+
+impl LogBuffer {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            records: Mutex::new(VecDeque::with_capacity(capacity)),
+            capacity,
+            sender: broadcast::channel(256).0,
+        }
+    }
+
+    /// Keep the record, dropping the oldest one if the buffer is full.
+    pub fn push(&self, record: LogRecord) {
+        let mut records = self.records.lock().unwrap();
+        if records.len() == self.capacity {
+            records.pop_front();
+        }
+        records.push_back(record.clone());
+        // Sent under the lock, so that a follower doesn't miss or repeat a record.
+        let _ = self.sender.send(record);
+    }
+
+    /// The kept records, the oldest first.
+    pub fn recent(&self) -> Vec<LogRecord> {
+        self.records.lock().unwrap().iter().cloned().collect()
+    }
+
+    /// The kept records and a receiver of the records pushed after them.
+    ///
+    /// A receiver lagging too far behind misses records (see [broadcast::Receiver::recv]).
+    pub fn follow(&self) -> (Vec<LogRecord>, broadcast::Receiver<LogRecord>) {
+        let records = self.records.lock().unwrap();
+        (records.iter().cloned().collect(), self.sender.subscribe())
+    }
+}
+
+/// The most recent records logged by the logger installed with [init].
+pub fn logs() -> &'static LogBuffer {
+    &LOGS
+}
+
+/// The crates whose debug logs are enabled by [toggle_debug].
+const DEBUG_TARGETS: [&str; 2] = ["post", "post_service"];
+
+struct ReloadableLogger {
+    inner: RwLock<env_logger::Logger>,
+    filter: RwLock<String>,
+    /// The filter the logger was installed with.
+    initial: String,
+}
+
+impl Log for ReloadableLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        self.inner.read().unwrap().enabled(metadata)
+    }
+
+    fn log(&self, record: &Record) {
+        let inner = self.inner.read().unwrap();
+        if inner.matches(record) {
+            LOGS.push(record.into());
+            inner.log(record)
+        }
+    }
+
+    fn flush(&self) {
+        self.inner.read().unwrap().flush()
+    }
+}
+
+fn build(filter: &str) -> env_logger::Logger {
+    let mut builder = env_logger::Builder::new();
+    if let Ok(style) = std::env::var("RUST_LOG_STYLE") {
+        builder.parse_write_style(&style);
+    }
+    builder.parse_filters(filter).build()
+}
+
+fn logger() -> eyre::Result<&'static ReloadableLogger> {
+    LOGGER
+        .get()
+        .ok_or_else(|| eyre::eyre!("logger is not initialized"))
+}
+
+/// Install the global logger with the `filter` (in the format of `RUST_LOG`).
+pub fn init(filter: &str) -> eyre::Result<()> {
+    let logger = build(filter);
+    let max_level = logger.filter();
+    let logger = LOGGER.get_or_init(|| ReloadableLogger {
+        inner: RwLock::new(logger),
+        filter: RwLock::new(filter.to_string()),
+        initial: filter.to_string(),
+    });
+    log::set_logger(logger)?;
+    log::set_max_level(max_level);
+    Ok(())
+}
+
+/// The filter of the logger installed with [init].
+pub fn filter() -> eyre::Result<String> {
+    Ok(logger()?.filter.read().unwrap().clone())
+}
+
+/// Change the filter of the logger installed with [init].
+///
+/// The filter is a comma-separated list of directives, each a level (i.e. `info`),
+/// a level of a target (i.e. `post::prove=debug`) or a target with all its logs enabled
+/// (i.e. `post::prove`), optionally followed by `/` and a regex filtering the messages.
+pub fn set_filter(filter: &str) -> eyre::Result<()> {
+    validate_filter(filter)?;
+    let logger = logger()?;
+    let inner = build(filter);
+    let max_level = inner.filter();
+    *logger.inner.write().unwrap() = inner;
+    *logger.filter.write().unwrap() = filter.to_string();
+    log::set_max_level(max_level);
+    log::info!("log filter changed to {filter:?}");
+    Ok(())
+}
+
+/// Enable the debug logs of the service, or restore the filter the logger
+/// was installed with if it was changed already.
+pub fn toggle_debug() -> eyre::Result<()> {
+    let logger = logger()?;
+    let changed = *logger.filter.read().unwrap() != logger.initial;
+    match changed {
+        true => set_filter(&logger.initial),
+        false => set_filter(&debug_filter(&logger.initial)),
+    }
+}
+
+/// Call [toggle_debug] on every SIGUSR1.
+#[cfg(unix)]
+pub async fn toggle_debug_on_signal() {
+    use tokio::signal::unix::{signal, SignalKind};
+    let mut signal = match signal(SignalKind::user_defined1()) {
+        Ok(signal) => signal,
+        Err(e) => {
+            log::warn!("failed to listen for SIGUSR1: {e}");
+            return;
+        }
+    };
+    while signal.recv().await.is_some() {
+        if let Err(e) = toggle_debug() {
+            log::warn!("failed to toggle the debug logs: {e}");
+        }
+    }
+}
+
+#[cfg(not(unix))]
+pub async fn toggle_debug_on_signal() {}
+
+/// Split a filter into its directives and the optional regex (following a '/').
+fn split_regex(filter: &str) -> (&str, Option<&str>) {
+    match filter.split_once('/') {
+        Some((directives, regex)) => (directives, Some(regex)),
+        None => (filter, None),
+    }
+}
+
+fn debug_filter(filter: &str) -> String {
+    let (directives, regex) = split_regex(filter);
+    let directives = DEBUG_TARGETS
+        .iter()
+        .fold(directives.to_string(), |filter, target| {
+            format!("{filter},{target}=debug")
+        });
+    match regex {
+        Some(regex) => format!("{directives}/{regex}"),
+        None => directives,
+    }
+}
+
+/// Check the directives of a filter, which env_logger would ignore with a warning.
+///
+/// As in `RUST_LOG`, a directive without a level is a target with all its logs enabled.
+fn validate_filter(filter: &str) -> eyre::Result<()> {
+    let (directives, _) = split_regex(filter);
+    for directive in directives
+        .split(',')
+        .map(str::trim)
+        .filter(|d| !d.is_empty())
+    {
+        let Some((target, level)) = directive.split_once('=') else {
+            // A level or a bare target
+            continue;
+        };
+        eyre::ensure!(
+            !target.trim().is_empty(),
+            "invalid log directive {directive:?}: empty target"
+        );
+        LevelFilter::from_str(level.trim())
+            .map_err(|_| eyre::eyre!("invalid log directive {directive:?}: unknown level"))?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use log::Level;
+
+    use super::{LogBuffer, LogRecord};
+
+    #[test]
+    fn validating_filter() {
+        for filter in [
+            "info",
+            "info,post::prove=debug",
+            "warn, post=trace/found",
+            "info,post::prove",
+            "post_service",
+            "",
+        ] {
+            assert!(super::validate_filter(filter).is_ok(), "{filter}");
+        }
+        for filter in ["=debug", "post=loud", "info,post=", "post=loud/found"] {
+            assert!(super::validate_filter(filter).is_err(), "{filter}");
+        }
+    }
+
+    #[test]
+    fn enabling_debug_logs() {
+        let filter = super::debug_filter("info,h2=warn");
+        assert_eq!("info,h2=warn,post=debug,post_service=debug", filter);
+        assert!(super::validate_filter(&filter).is_ok());
+
+        // Before the regex filtering the messages
+        let filter = super::debug_filter("info/proof");
+        assert_eq!("info,post=debug,post_service=debug/proof", filter);
+        assert!(super::validate_filter(&filter).is_ok());
+    }
+
+    fn record(message: &str) -> LogRecord {
+        LogRecord {
+            timestamp: 0,
+            level: Level::Info,
+            target: "test".into(),
+            message: message.into(),
+        }
+    }
+
+    #[test]
+    fn keeping_recent_records() {
+        let logs = LogBuffer::new(2);
+        logs.push(record("a"));
+        let (recent, mut receiver) = logs.follow();
+        assert_eq!(vec![record("a")], recent);
+
+        logs.push(record("b"));
+        logs.push(record("c"));
+        assert_eq!(vec![record("b"), record("c")], logs.recent());
+        assert_eq!(record("b"), receiver.try_recv().unwrap());
+        assert_eq!(record("c"), receiver.try_recv().unwrap());
+        assert!(receiver.try_recv().is_err());
+    }
+}
@@ -1,14 +1,32 @@
-use std::{fs::read_to_string, net::SocketAddr, path::PathBuf, sync::Arc, time::Duration};
+use std::{
+    net::SocketAddr,
+    num::NonZeroU64,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::Duration,
+};
 
 use clap::{Args, Parser, ValueEnum};
 use eyre::Context;
 use serde_with::{formats, hex::Hex, serde_as};
 use sysinfo::{Pid, ProcessRefreshKind, ProcessStatus, ProcessesToUpdate, System};
-use tokio::sync::oneshot::{self, error::TryRecvError, Receiver};
-use tonic::transport::{Certificate, Identity};
+use tokio::{
+    sync::oneshot::{self, error::TryRecvError, Receiver},
+    task::JoinSet,
+};
 
-use post::pow::randomx::RandomXFlag;
-use post_service::{client, operator, service::K2powConfig};
+use post::{
+    config::NetworkPreset,
+    pow::randomx::{self, LargePages, RandomXFlag},
+    prove::BatchSize,
+    topology::CoreKind,
+};
+use post_service::{
+    client, operator,
+    schedule::{Schedule, Window},
+    service::{K2powConfig, SelfVerification, STATE_LOCK_FILE_NAME},
+    supervisor::{InitConfig, Supervisor},
+};
 
 /// Post Service
 #[derive(Parser, Debug)]
@@ -17,12 +35,20 @@ struct Cli {
     /// directory of POST data
     #[arg(short, long)]
     dir: PathBuf,
-    /// address to connect to
-    #[arg(short, long)]
-    address: String,
+    /// address of the node to connect to
+    ///
+    /// Can be repeated to serve several nodes (i.e. redundant nodes of the same identity).
+    /// Only one proof is generated at a time, requests with a different challenge
+    /// are rejected until the proof is fetched by the node that requested it.
+    #[arg(short, long, required = true)]
+    address: Vec<String>,
     /// time to wait before reconnecting to the node
     #[arg(long, default_value = "5", value_parser = |secs: &str| secs.parse().map(Duration::from_secs))]
     reconnect_interval_s: Duration,
+    /// time to wait for a connection when checking that the node (or the k2pow service)
+    /// is reachable (`--self-test` and `--check`)
+    #[arg(long, default_value = "5", value_parser = |secs: &str| secs.parse().map(Duration::from_secs))]
+    connect_timeout_s: Duration,
     /// Maximum number of retries to connect to the node
     /// The default is infinite.
     #[arg(long)]
@@ -32,11 +58,92 @@ struct Cli {
     #[arg(long)]
     watch_pid: Option<sysinfo::Pid>,
 
+    /// simulate proving, for testing integrations with the node
+    ///
+    /// A fake proof, always the same for a challenge, is returned after `--mock-proving-delay`.
+    /// No POS data is read and no PoW is calculated, only the metadata is loaded from `--dir`.
+    /// The proofs are not valid for the network.
+    #[arg(long)]
+    mock_proving: bool,
+
+    /// time to "generate" a simulated proof (in seconds)
+    #[arg(long, default_value = "10", requires = "mock_proving", value_parser = |secs: &str| secs.parse().map(Duration::from_secs))]
+    mock_proving_delay: Duration,
+
+    /// check the setup, print a pass/fail summary and exit
+    ///
+    /// Loads the metadata, verifies a sample of labels, generates a proof from a few labels
+    /// initialized in memory (with mocked PoW), initializes RandomX in the configured mode
+    /// and checks that the nodes are reachable (without registering).
+    #[arg(long, conflicts_with_all = ["mock_proving", "init_units"])]
+    self_test: bool,
+
+    /// validate the configuration, print a pass/fail report and exit (a preflight check)
+    ///
+    /// Loads the metadata, checks the number of units against the network, the lock of
+    /// the POST data directory, the TLS files, the operator address and that the nodes
+    /// and the remote k2pow service are reachable (without registering). Unlike
+    /// `--self-test`, no labels are verified and RandomX is not initialized.
+    #[arg(long, conflicts_with_all = ["mock_proving", "init_units", "self_test"])]
+    check: bool,
+
+    /// check the health of a running post-service by its operator service and exit
+    ///
+    /// Exits with an error if `/status` of the operator service at `--operator-address` (with
+    /// `--operator-token`) doesn't respond with `200 OK`, i.e. for `HEALTHCHECK` in Docker.
+    /// The other arguments are not needed.
+    #[arg(long, requires = "operator_address")]
+    healthcheck: bool,
+
+    /// percentage of labels verified in every POS data file by `--self-test`
+    #[arg(long, default_value_t = 0.01, requires = "self_test")]
+    self_test_fraction: f64,
+
+    /// directory to write the generated proofs to, with their metadata
+    ///
+    /// Every proof fetched (and verified) is written to `proof_<challenge>.json`,
+    /// in the format accepted by the certifier (see `prover submit`).
+    #[arg(long)]
+    proof_dir: Option<PathBuf>,
+
+    /// directory for the files of the service (the cached proof, the proof history,
+    /// the events and the k2pows)
+    ///
+    /// Defaults to `--dir`. POS data on a block device has no room for them,
+    /// they are kept in the temporary directory then, unless this is set.
+    #[arg(long)]
+    state_dir: Option<PathBuf>,
+
     /// address to listen on for operator service
     /// the operator service is disabled if not specified
     #[arg(long)]
     operator_address: Option<SocketAddr>,
 
+    /// shared secret required in the `Authorization: Bearer <token>` header
+    /// of the operator service requests. No authorization if not specified.
+    ///
+    /// Prefer the environment variable or `--operator-token-file`,
+    /// command line arguments are visible to other users of the system.
+    #[arg(
+        long,
+        env = "POST_SERVICE_OPERATOR_TOKEN",
+        hide_env_values = true,
+        requires = "operator_address"
+    )]
+    operator_token: Option<String>,
+
+    /// file with the shared secret of `--operator-token` (surrounding whitespace is ignored)
+    #[arg(long, conflicts_with = "operator_token", requires = "operator_address")]
+    operator_token_file: Option<PathBuf>,
+
+    /// certificate (PEM) to serve the operator service over TLS
+    #[arg(long, requires_all = ["operator_address", "operator_tls_key"])]
+    operator_tls_cert: Option<PathBuf>,
+
+    /// private key (PEM) to serve the operator service over TLS
+    #[arg(long, requires = "operator_tls_cert")]
+    operator_tls_key: Option<PathBuf>,
+
     #[command(flatten, next_help_heading = "POST configuration")]
     post_config: PostConfig,
 
@@ -46,6 +153,9 @@ struct Cli {
     #[command(flatten, next_help_heading = "TLS configuration")]
     tls: Option<Tls>,
 
+    #[command(flatten, next_help_heading = "Initialization (supervisor mode)")]
+    init: InitArgs,
+
     /// Base URL for remote k2pow service.
     #[arg(long)]
     remote_k2pow: Option<String>,
@@ -55,16 +165,49 @@ struct Cli {
     #[arg(long, default_value = "5")]
     remote_k2pow_parallelism: usize,
 
+    /// How many remote k2pow jobs of a single identity to execute in parallel, so that an identity
+    /// with many nonce groups to prove doesn't take all the `--remote-k2pow-parallelism` slots.
+    /// Defaults to half of `--remote-k2pow-parallelism` (rounded up).
+    #[arg(long)]
+    remote_k2pow_identity_parallelism: Option<usize>,
+
     /// Time to back off before trying the k2pow service again while waiting for a result or to
     /// queue in a new job.
     #[arg(long, default_value = "5")]
     remote_k2pow_backoff: u64,
+
+    /// OTLP (gRPC) endpoint of an OpenTelemetry collector to export traces to,
+    /// e.g. `http://localhost:4317`. Traces are not exported if not specified.
+    #[cfg(feature = "otlp")]
+    #[arg(long)]
+    otlp_endpoint: Option<String>,
+
+    /// Base URL of the POS data files in object storage (i.e. a public S3 bucket),
+    /// read instead of the files in `--dir`. The metadata is still loaded from `--dir`.
+    #[cfg(feature = "object-storage")]
+    #[arg(long)]
+    data_url: Option<String>,
+    /// Region of the bucket of `--data-url` (i.e. `us-east-1`). Signs the requests (AWS Signature Version 4)
+    /// with the credentials in the `AWS_ACCESS_KEY_ID`, `AWS_SECRET_ACCESS_KEY`
+    /// and (optionally) `AWS_SESSION_TOKEN` environment variables.
+    #[cfg(feature = "object-storage")]
+    #[arg(long, requires = "data_url")]
+    data_region: Option<String>,
 }
 
 #[serde_as]
 #[derive(Args, Debug, serde::Serialize)]
 /// POST configuration - network parameters
 struct PostConfig {
+    /// use the network parameters of a known network (`mainnet`, `testnet` or `custom`)
+    ///
+    /// Can't be used with the individual network parameters.
+    #[arg(
+        long,
+        default_value_t = NetworkPreset::Custom,
+        conflicts_with_all = ["min_num_units", "max_num_units", "k1", "k2", "pow_difficulty", "n", "r", "p"],
+    )]
+    preset: NetworkPreset,
     /// The minimal number of units that must be initialized.
     #[arg(long, default_value_t = 4)]
     pub min_num_units: u32,
@@ -85,22 +228,49 @@ struct PostConfig {
     )]
     #[serde_as(as = "Hex<formats::Uppercase>")]
     pow_difficulty: [u8; 32],
+    /// version of the nonce proof of work algorithm
+    #[arg(long, default_value_t = post::pow::Version::RandomX)]
+    pow_version: post::pow::Version,
+    /// ID of the network separating its RandomX PoWs from other networks (i.e. testnets).
+    /// Mainnet has none.
+    #[arg(long)]
+    randomx_network_id: Option<String>,
     /// scrypt parameters for initialization
     #[command(flatten)]
     scrypt: ScryptParams,
 }
 
+impl PostConfig {
+    /// Replace the network parameters with the ones of the preset (if not `custom`).
+    fn apply_preset(&mut self) {
+        if let Some(cfg) = self.preset.proof_config() {
+            self.k1 = cfg.k1;
+            self.k2 = cfg.k2;
+            self.pow_difficulty = cfg.pow_difficulty;
+        }
+        if let Some(cfg) = self.preset.init_config() {
+            self.min_num_units = cfg.min_num_units;
+            self.max_num_units = cfg.max_num_units;
+            self.scrypt = ScryptParams {
+                n: cfg.scrypt.n,
+                r: cfg.scrypt.r,
+                p: cfg.scrypt.p,
+            };
+        }
+    }
+}
+
 /// Scrypt parameters for initialization
 #[derive(Args, Debug, serde::Serialize)]
 struct ScryptParams {
-    /// scrypt N parameter
-    #[arg(short, default_value_t = 8192)]
+    /// scrypt N parameter, a power of two >= 2 (i.e. `8192` or `2^13`)
+    #[arg(short, default_value_t = 8192, value_parser = post::config::ScryptParams::parse_n)]
     n: usize,
-    /// scrypt R parameter
-    #[arg(short, default_value_t = 1)]
+    /// scrypt R parameter, a power of two
+    #[arg(short, default_value_t = 1, value_parser = post::config::ScryptParams::parse_r)]
     r: usize,
-    /// scrypt P parameter
-    #[arg(short, default_value_t = 1)]
+    /// scrypt P parameter, a power of two
+    #[arg(short, default_value_t = 1, value_parser = post::config::ScryptParams::parse_p)]
     p: usize,
 }
 
@@ -112,15 +282,112 @@ struct PostSettings {
 
     /// number of nonces to attempt in single pass over POS data
     ///
-    /// Each group of 16 nonces requires a separate PoW. Must be a multiple of 16.
+    /// Each (started) group of 16 nonces requires a separate PoW.
     ///
     /// Higher value gives a better chance to find a proof within less passes over the POS data,
     /// but also slows down the process.
-    #[arg(long, default_value_t = 128, value_parser(parse_nonces))]
-    nonces: usize,
+    ///
+    /// `auto` measures the read and proving throughput at startup and chooses the number
+    /// of nonces that finds a proof (with `--proof-probability`) the fastest:
+    /// more nonces on machines with fast CPUs and slow disks, fewer otherwise.
+    #[arg(long, default_value = "128", value_parser(parse_nonces))]
+    nonces: Nonces,
+    /// the chance to find a proof the number of nonces is chosen for with `--nonces=auto`
+    #[arg(long, default_value_t = 0.99, value_parser(parse_probability))]
+    proof_probability: f64,
     /// modes of operation for RandomX
     #[arg(long, default_value_t = RandomXMode::Fast)]
     randomx_mode: RandomXMode,
+    /// allocate RandomX memory in large pages (`on`, `off` or `auto`)
+    ///
+    /// Requires configuring huge pages in the OS: https://xmrig.com/docs/miner/hugepages.
+    /// `auto` uses large pages only if they are available.
+    #[arg(long, default_value_t = LargePages::Off, num_args = 0..=1, default_missing_value = "on")]
+    randomx_large_pages: LargePages,
+    /// limit the rate of reading POS data during proving (in MiB/s)
+    ///
+    /// Useful when POS data is kept on storage shared with other workloads.
+    /// Proving takes longer when the limit is lower than the disk throughput.
+    #[arg(long)]
+    read_rate_limit: Option<NonZeroU64>,
+    /// size of the batches of POS data read for proving (in KiB) or `auto`
+    ///
+    /// `auto` tunes the size while reading (between 64 KiB and 16 MiB) for the best throughput
+    /// of the storage, i.e. bigger batches for HDDs or network storage.
+    #[arg(long, default_value = "1024", value_parser(parse_batch_size))]
+    batch_size: BatchSize,
+    /// limit the memory taken by the POS data read for proving (in MiB)
+    ///
+    /// Bounds all the batches (`--batch-size`) read ahead and held by the proving threads: with a budget
+    /// smaller than a batch per thread, some threads idle. Keeps the memory usage predictable on machines
+    /// with little RAM when the disk is faster than the CPUs.
+    /// Not limited if not set.
+    #[arg(long)]
+    memory_budget: Option<u64>,
+    /// soft limit of the memory used by proof generation (in MiB)
+    ///
+    /// Proof generations exceeding it (approximately: the RandomX memory and the batches of POS data)
+    /// use the RandomX light mode, and then smaller batches (`--batch-size`, `--memory-budget`).
+    /// Not limited if not set.
+    #[arg(long)]
+    memory_limit: Option<u64>,
+    /// abort a pass over POS data when the chance to find a proof in the rest of the data
+    /// drops below this probability (i.e. 0.001)
+    ///
+    /// The pass moves on to the next nonces right away instead of reading the rest of the data.
+    /// Saves disk reads in unlucky passes, but (rarely) misses a proof. Passes are never aborted if not set.
+    #[arg(long, value_parser(parse_probability))]
+    early_abort: Option<f64>,
+    /// give up generating a proof after trying this many nonces (up to 4096)
+    ///
+    /// The node is answered with GEN_PROOF_STATUS_UNSPECIFIED when no proof is found
+    /// with the nonces. All the 4096 nonces are tried if not set.
+    #[arg(long, value_parser(parse_max_nonces))]
+    max_nonces: Option<u32>,
+    /// start reading POS data as soon as the k2pow of the first nonce group is found
+    ///
+    /// The k2pows of the other nonce groups are calculated in the background, meanwhile.
+    /// The nonce groups that are not ready when a data pass starts are tried in the next pass.
+    /// Shortens proving on machines with slow k2pow. Ignored with a remote k2pow service.
+    #[arg(long)]
+    pipelined_k2pow: bool,
+    /// prove partially initialized POS data for the units initialized completely
+    ///
+    /// The proof is valid only for that number of units, which is reported to the node.
+    /// Proof generation fails for partially initialized POS data if not set.
+    #[arg(long)]
+    partial_data: bool,
+    /// check the POS data before each pass over it
+    ///
+    /// Checks that all POS data files exist with the expected sizes and that the metadata
+    /// has not changed since the service started. Proving fails with an error instead
+    /// of generating an invalid proof from damaged data (i.e. truncated files after a disk failure).
+    /// Only for local POS data, can't be used with `--data-url`.
+    #[arg(long)]
+    #[cfg_attr(feature = "object-storage", arg(conflicts_with = "data_url"))]
+    check_data: bool,
+    /// how many times to retry a failed read of POS data before proving fails
+    ///
+    /// Helps with transient errors of network or USB storage. The rest of the data is read
+    /// again from where the read failed.
+    #[arg(long, default_value_t = 3)]
+    read_retries: u32,
+    /// time to wait before the first retry of a failed read (in seconds), doubled for every next one
+    #[arg(long, default_value = "1", value_parser = |secs: &str| secs.parse().map(Duration::from_secs))]
+    read_retry_backoff: Duration,
+    /// how to verify the generated proofs before sending them to the node:
+    /// `all` labels, a number of randomly selected labels or `skip`
+    ///
+    /// Verifying fewer labels sends the proof to the node sooner, but an invalid proof
+    /// (i.e. due to POS data damaged by faulty hardware) is then found only by the node, which rejects it.
+    #[arg(long, default_value = "all", value_parser(parse_self_verification))]
+    self_verification: SelfVerification,
+    /// time windows (`HH:MM-HH:MM`, UTC) in which proving is allowed, comma separated
+    ///
+    /// Outside of the windows proof generation is paused and resumes when the next window opens.
+    /// A window can span midnight, e.g. `22:00-06:00`. Proving is allowed at any time if not set.
+    #[arg(long, value_delimiter = ',')]
+    proving_windows: Vec<Window>,
 }
 
 #[derive(Args, Debug, Clone, serde::Serialize)]
@@ -139,6 +406,82 @@ struct CoresConfig {
     /// Can't use with `threads`
     #[arg(long, num_args = 1.., value_delimiter = ',')]
     pinned_cores: Option<Vec<usize>>,
+
+    /// pin threads to cores chosen automatically (`auto`):
+    /// one logical core of each physical core (skipping hyper-thread siblings),
+    /// at most `threads` of them or all if `threads` is 0
+    ///
+    /// Can't use with `pinned-cores`
+    #[arg(long, conflicts_with = "pinned_cores")]
+    pin: Option<Pin>,
+
+    /// prove also on the efficiency cores of hybrid CPUs (i.e. Intel Alder Lake)
+    ///
+    /// By default, the proving threads (calculating k2pow too) run only on the performance cores
+    /// of hybrid CPUs, as the threads on the efficiency cores slow down proving unpredictably.
+    /// Cores pinned with `pinned-cores` are used as they are.
+    #[arg(long)]
+    efficiency_cores: bool,
+}
+
+/// Choosing the cores to pin threads to.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, ValueEnum, serde::Serialize)]
+enum Pin {
+    /// One core per physical core.
+    Auto,
+}
+
+/// Initializing POST data before proving
+#[derive(Args, Debug)]
+struct InitArgs {
+    /// initialize this many units of POST data in `--dir` before proving
+    ///
+    /// An interrupted initialization is resumed. Nothing is initialized if the POST data
+    /// is already initialized with the same parameters. The operator API reports
+    /// the progress and the service connects to the node when the initialization is done.
+    #[arg(long, requires_all = ["init_node_id", "init_commitment_atx_id"])]
+    init_units: Option<u32>,
+    /// ID of the node (hex) to initialize the POST data for
+    #[arg(long, requires = "init_units", value_parser(parse_id))]
+    init_node_id: Option<[u8; 32]>,
+    /// commitment ATX ID (hex) to initialize the POST data with
+    #[arg(long, requires = "init_units", value_parser(parse_id))]
+    init_commitment_atx_id: Option<[u8; 32]>,
+    /// labels per unit, defaults to the one of the `--preset`
+    #[arg(long, requires = "init_units")]
+    init_labels_per_unit: Option<u64>,
+    /// max size of a single POST data file (in bytes)
+    #[arg(long, requires = "init_units", default_value_t = 4 * 1024 * 1024 * 1024)]
+    init_max_file_size: u64,
+    /// OpenCL provider to initialize with (see `initializer list-providers`),
+    /// the CPU is used if not specified
+    #[cfg(feature = "opencl")]
+    #[arg(long, requires = "init_units")]
+    init_provider: Option<u32>,
+    /// skip the OpenCL providers which platform or device name matches the pattern
+    /// (a regular expression, i.e. `(?i)rusticl`), can be repeated
+    ///
+    /// The `--init-provider` ID is counted without the excluded providers
+    /// (as listed by `initializer list-providers --exclude-provider`).
+    #[cfg(feature = "opencl")]
+    #[arg(long, requires = "init_provider")]
+    init_exclude_provider: Vec<String>,
+}
+
+impl InitArgs {
+    fn provider(&self) -> Option<u32> {
+        #[cfg(feature = "opencl")]
+        return self.init_provider;
+        #[cfg(not(feature = "opencl"))]
+        None
+    }
+
+    fn excluded_providers(&self) -> Vec<String> {
+        #[cfg(feature = "opencl")]
+        return self.init_exclude_provider.clone();
+        #[cfg(not(feature = "opencl"))]
+        Vec::new()
+    }
 }
 
 /// RandomX modes of operation
@@ -151,6 +494,20 @@ enum RandomXMode {
     Fast,
     /// Light mode for verification. Requires only 256 MiB of memory, but runs significantly slower
     Light,
+    /// Fast mode if enough memory (or free huge pages) is available at startup, light otherwise.
+    Auto,
+}
+
+impl RandomXMode {
+    /// Resolve the [RandomXMode::Auto] mode by the memory available in the OS
+    /// (see [randomx::auto_fast_mode]).
+    fn resolve(self, large_pages: LargePages) -> Self {
+        match self {
+            RandomXMode::Auto if randomx::auto_fast_mode(large_pages) => RandomXMode::Fast,
+            RandomXMode::Auto => RandomXMode::Light,
+            mode => mode,
+        }
+    }
 }
 
 /// TLS configuration
@@ -182,18 +539,300 @@ impl From<RandomXMode> for RandomXFlag {
     fn from(val: RandomXMode) -> Self {
         match val {
             RandomXMode::Fast => RandomXFlag::get_recommended_flags() | RandomXFlag::FLAG_FULL_MEM,
-            RandomXMode::Light => RandomXFlag::get_recommended_flags(),
+            // Auto is resolved at startup, fall back to the mode that fits anywhere.
+            RandomXMode::Light | RandomXMode::Auto => RandomXFlag::get_recommended_flags(),
         }
     }
 }
 
-fn parse_nonces(arg: &str) -> eyre::Result<usize> {
-    let nonces = arg.parse()?;
-    eyre::ensure!(nonces % 16 == 0, "nonces must be multiple of 16");
-    eyre::ensure!(nonces / 16 <= 256, format!("max nonces is {}", 256 * 16));
+/// The number of nonces per pass over POS data.
+#[derive(Debug, Clone, Copy, PartialEq, serde::Serialize)]
+enum Nonces {
+    Fixed(usize),
+    /// Planned from the measured throughput.
+    Auto,
+}
+
+impl Nonces {
+    /// The number of nonces until (or if no) plan is made.
+    fn initial(self) -> usize {
+        match self {
+            Nonces::Fixed(nonces) => nonces,
+            Nonces::Auto => 128,
+        }
+    }
+}
+
+fn parse_nonces(arg: &str) -> eyre::Result<Nonces> {
+    if arg == "auto" {
+        return Ok(Nonces::Auto);
+    }
+    let nonces = arg
+        .parse()
+        .wrap_err("expected `auto` or a number of nonces")?;
+    eyre::ensure!(nonces > 0, "nonces must be positive");
+    eyre::ensure!(nonces <= 256 * 16, format!("max nonces is {}", 256 * 16));
+    Ok(Nonces::Fixed(nonces))
+}
+
+async fn self_test(args: &Cli, tls: Option<client::TlsFiles>) -> eyre::Result<()> {
+    let cfg = post_service::self_test::Config {
+        datadir: args.dir.clone(),
+        scrypt: post::config::ScryptParams::new(
+            args.post_config.scrypt.n,
+            args.post_config.scrypt.r,
+            args.post_config.scrypt.p,
+        ),
+        sample_fraction: args.self_test_fraction,
+        pow_flags: randomx::with_large_pages(
+            args.post_settings.randomx_mode.into(),
+            args.post_settings.randomx_large_pages,
+        )?,
+        addresses: args.address.clone(),
+        tls,
+        connect_timeout: args.connect_timeout_s,
+    };
+    let report = post_service::self_test::run(&cfg).await;
+    println!("{report}");
+    eyre::ensure!(report.passed(), "self-test failed");
+    Ok(())
+}
+
+async fn check(args: &Cli, tls: Option<client::TlsFiles>) -> eyre::Result<()> {
+    let mut report = post_service::self_test::Report::default();
+
+    #[cfg(feature = "object-storage")]
+    let local_data = args.data_url.is_none();
+    #[cfg(not(feature = "object-storage"))]
+    let local_data = true;
+    let metadata = match local_data {
+        true => post_service::self_test::check_metadata(&args.dir),
+        false => post::metadata::load(&args.dir)
+            .map(|m| format!("{} units (POS data in object storage)", m.num_units))
+            .map_err(eyre::Report::from),
+    };
+    report.add("metadata", metadata);
+
+    let num_units = || {
+        let metadata = post::metadata::load(&args.dir)?.initialized()?;
+        let cfg = &args.post_config;
+        verify_num_units(cfg.min_num_units..=cfg.max_num_units, metadata.num_units)?;
+        if let Some(init_cfg) = cfg.preset.init_config() {
+            verify_labels_per_unit(init_cfg.labels_per_unit, metadata.labels_per_unit)?;
+        }
+        Ok(format!(
+            "{} units in {}..={}",
+            metadata.num_units, cfg.min_num_units, cfg.max_num_units
+        ))
+    };
+    report.add("number of units", num_units());
+    report.add(
+        "data directory lock",
+        post::lock::DataDirLock::acquire_shared(&args.dir)
+            .map(|_| "not being initialized or wiped".to_string())
+            .map_err(eyre::Report::from),
+    );
+    let state_dir = args
+        .state_dir
+        .clone()
+        .unwrap_or_else(|| post::block_device::state_dir(&args.dir));
+    report.add(
+        "state directory lock",
+        post::lock::DataDirLock::acquire_named(&state_dir, STATE_LOCK_FILE_NAME)
+            .map(|_| "not used by another service".to_string())
+            .map_err(eyre::Report::from),
+    );
+
+    report.add(
+        "RandomX",
+        check_randomx_support(args).map(|()| match randomx::SUPPORTED {
+            true => "built in".to_string(),
+            false => "not built in, using the remote k2pow service".to_string(),
+        }),
+    );
+    if let Some(tls) = &tls {
+        report.add("TLS", tls.load().map(|_| "loaded".to_string()));
+    }
+    if let Some(address) = args.operator_address {
+        let operator = async {
+            let listener = tokio::net::TcpListener::bind(address).await?;
+            drop(listener);
+            match (&args.operator_tls_cert, &args.operator_tls_key) {
+                (Some(cert), Some(key)) => {
+                    let tls = operator::Tls {
+                        cert: cert.clone(),
+                        key: key.clone(),
+                    };
+                    operator::load_tls(&tls).await?;
+                    Ok(format!("{address} available, TLS loaded"))
+                }
+                _ => Ok(format!("{address} available")),
+            }
+        };
+        report.add("operator", operator.await);
+    }
+
+    for address in &args.address {
+        report.add(
+            format!("node {address}"),
+            client::check_connection(address, tls.as_ref(), args.connect_timeout_s)
+                .await
+                .map(|()| "reachable".to_string()),
+        );
+    }
+    if let Some(url) = &args.remote_k2pow {
+        let service = post::pow::service::K2powService::new(
+            url.clone(),
+            args.remote_k2pow_parallelism,
+            Duration::from_secs(args.remote_k2pow_backoff),
+        );
+        report.add(
+            format!("k2pow service {url}"),
+            service
+                .check_connection(args.connect_timeout_s)
+                .await
+                .map(|()| "reachable".to_string()),
+        );
+    }
+
+    println!("{report}");
+    eyre::ensure!(report.passed(), "check failed");
+    Ok(())
+}
+
+/// Builds without RandomX (the `randomx` feature) can't calculate the k2pow locally
+/// nor verify the generated proofs.
+fn check_randomx_support(args: &Cli) -> eyre::Result<()> {
+    if !randomx::SUPPORTED {
+        eyre::ensure!(
+            args.remote_k2pow.is_some(),
+            "built without RandomX, --remote-k2pow is required"
+        );
+        eyre::ensure!(
+            matches!(args.post_settings.self_verification, SelfVerification::Skip),
+            "built without RandomX, proofs can't be verified (use --self-verification=skip)"
+        );
+    }
+    Ok(())
+}
+
+fn cores_config(cfg: &CoresConfig) -> eyre::Result<post::config::Cores> {
+    let topology = post::topology::Topology::detect();
+    let hybrid = topology.as_ref().is_some_and(|t| t.is_hybrid());
+    if let (Some(topology), true) = (&topology, hybrid) {
+        let performance = topology.cores_of_kind(CoreKind::Performance);
+        let efficiency = topology.cores_of_kind(CoreKind::Efficiency);
+        match cfg.efficiency_cores {
+            true => log::info!(
+                "hybrid CPU: proving on the performance cores {performance:?} and the efficiency cores {efficiency:?}"
+            ),
+            false => log::info!(
+                "hybrid CPU: proving only on the performance cores {performance:?}, not on the efficiency cores {efficiency:?}"
+            ),
+        }
+    }
+    if let Some(pinned) = &cfg.pinned_cores {
+        post_service::service::check_pinned_cores(pinned)?;
+        if let (Some(topology), false) = (&topology, cfg.efficiency_cores) {
+            let efficiency = topology.cores_of_kind(CoreKind::Efficiency);
+            if hybrid && pinned.iter().any(|core| efficiency.contains(core)) {
+                log::warn!(
+                    "pinned cores include efficiency cores {efficiency:?} of the hybrid CPU"
+                );
+            }
+        }
+        log::info!(
+            "using {} threads, pinned to cores: {:?}",
+            pinned.len(),
+            pinned.as_slice()
+        );
+        return Ok(post::config::Cores::Pin(pinned.clone()));
+    }
+    if let Some(Pin::Auto) = cfg.pin {
+        let topology = topology
+            .ok_or_else(|| eyre::eyre!("can't list the cores of the machine to pin threads to"))?;
+        let topology = match cfg.efficiency_cores {
+            true => topology,
+            false => topology.without_efficiency_cores(),
+        };
+        if !topology.knows_physical_cores() {
+            log::warn!("physical cores are unknown, hyper-thread siblings might be pinned");
+        }
+        let limit = (cfg.threads != 0).then_some(cfg.threads);
+        let pinned = topology.one_per_physical_core(limit);
+        log::info!(
+            "using {} threads, pinned to one core per physical core: {pinned:?}",
+            pinned.len(),
+        );
+        return Ok(post::config::Cores::Pin(pinned));
+    }
+    match cfg.threads {
+        0 => {
+            log::info!("using all available cores");
+            Ok(post::config::Cores::All)
+        }
+        n => {
+            log::info!("using {n} cores");
+            Ok(post::config::Cores::Any(n))
+        }
+    }
+}
+
+fn parse_batch_size(arg: &str) -> eyre::Result<BatchSize> {
+    if arg == "auto" {
+        return Ok(BatchSize::Auto {
+            min: 64 * 1024,
+            max: 16 * 1024 * 1024,
+        });
+    }
+    let kib: usize = arg.parse().wrap_err("expected `auto` or a size in KiB")?;
+    eyre::ensure!(kib > 0, "batch size must be positive");
+    Ok(BatchSize::Fixed(kib.saturating_mul(1024)))
+}
+
+fn parse_max_nonces(arg: &str) -> eyre::Result<u32> {
+    let nonces = arg.parse().wrap_err("expected a number of nonces")?;
+    eyre::ensure!(nonces > 0, "max nonces must be positive");
+    eyre::ensure!(
+        nonces <= post::prove::MAX_NONCES,
+        format!("max nonces is at most {}", post::prove::MAX_NONCES)
+    );
     Ok(nonces)
 }
 
+fn parse_probability(arg: &str) -> eyre::Result<f64> {
+    let probability: f64 = arg.parse().wrap_err("expected a probability")?;
+    eyre::ensure!(
+        probability > 0.0 && probability < 1.0,
+        "probability must be between 0 and 1"
+    );
+    Ok(probability)
+}
+
+fn parse_self_verification(arg: &str) -> eyre::Result<SelfVerification> {
+    match arg {
+        "all" => Ok(SelfVerification::All),
+        "skip" => Ok(SelfVerification::Skip),
+        k3 => {
+            let k3 = k3
+                .parse()
+                .wrap_err("expected `all`, `skip` or a number of labels")?;
+            eyre::ensure!(
+                k3 > 0,
+                "number of labels must be positive (use `skip` instead)"
+            );
+            Ok(SelfVerification::Subset { k3 })
+        }
+    }
+}
+
+fn parse_id(arg: &str) -> eyre::Result<[u8; 32]> {
+    hex::decode(arg)?
+        .as_slice()
+        .try_into()
+        .wrap_err("invalid ID length")
+}
+
 fn parse_difficulty(arg: &str) -> eyre::Result<[u8; 32]> {
     hex::decode(arg)?
         .as_slice()
@@ -201,13 +840,107 @@ fn parse_difficulty(arg: &str) -> eyre::Result<[u8; 32]> {
         .wrap_err("invalid difficulty length")
 }
 
+/// The arguments of `--healthcheck` alone, parsed before [Cli] which requires
+/// the arguments of running the service.
+///
+/// Parsing stops at the first other argument, `--healthcheck` among them is handled by [Cli].
+#[derive(Parser, Debug)]
+#[command(
+    ignore_errors = true,
+    disable_help_flag = true,
+    disable_version_flag = true
+)]
+struct HealthcheckCli {
+    #[arg(long)]
+    healthcheck: bool,
+    #[arg(long)]
+    operator_address: Option<SocketAddr>,
+    #[arg(long, env = "POST_SERVICE_OPERATOR_TOKEN")]
+    operator_token: Option<String>,
+    #[arg(long)]
+    operator_token_file: Option<PathBuf>,
+    #[arg(long)]
+    operator_tls_cert: Option<PathBuf>,
+}
+
+/// The operator token, given directly or read from a file.
+fn operator_token(token: Option<String>, file: Option<&Path>) -> eyre::Result<Option<String>> {
+    match file {
+        Some(path) => {
+            let token = std::fs::read_to_string(path)
+                .wrap_err_with(|| format!("reading operator token from {}", path.display()))?;
+            let token = token.trim();
+            eyre::ensure!(
+                !token.is_empty(),
+                "empty operator token in {}",
+                path.display()
+            );
+            Ok(Some(token.to_string()))
+        }
+        None => Ok(token),
+    }
+}
+
+fn healthcheck(
+    address: Option<SocketAddr>,
+    token: Option<&str>,
+    tls_cert: Option<&PathBuf>,
+) -> eyre::Result<()> {
+    let address =
+        address.ok_or_else(|| eyre::eyre!("--healthcheck requires --operator-address"))?;
+    post_service::operator::healthcheck(address, token, tls_cert.is_some(), Duration::from_secs(5))
+}
+
 #[tokio::main]
 async fn main() -> eyre::Result<()> {
-    let args = Cli::parse();
+    let health = HealthcheckCli::parse();
+    if health.healthcheck {
+        let token = operator_token(health.operator_token, health.operator_token_file.as_deref())?;
+        return healthcheck(
+            health.operator_address,
+            token.as_deref(),
+            health.operator_tls_cert.as_ref(),
+        );
+    }
+    let mut args = Cli::parse();
+    args.operator_token = operator_token(
+        args.operator_token.take(),
+        args.operator_token_file.as_deref(),
+    )?;
+    if args.healthcheck {
+        return healthcheck(
+            args.operator_address,
+            args.operator_token.as_deref(),
+            args.operator_tls_cert.as_ref(),
+        );
+    }
+
+    let filter = std::env::var("RUST_LOG").unwrap_or_else(|_| "info".to_string());
+    post_service::logging::init(&filter)?;
+    tokio::spawn(post_service::logging::toggle_debug_on_signal());
 
-    let env = env_logger::Env::default().filter_or("RUST_LOG", "info");
-    env_logger::init_from_env(env);
+    #[cfg(feature = "otlp")]
+    let _otlp_guard = match &args.otlp_endpoint {
+        Some(endpoint) => {
+            use tracing_subscriber::{layer::SubscriberExt, EnvFilter};
 
+            log::info!("exporting traces to {endpoint}");
+            let (layer, guard) = post_service::telemetry::layer("post-service", endpoint)?;
+            let filter =
+                EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info"));
+            tracing::subscriber::set_global_default(
+                tracing_subscriber::registry().with(filter).with(layer),
+            )?;
+            Some(guard)
+        }
+        None => None,
+    };
+
+    args.post_config.apply_preset();
+    args.post_settings.randomx_mode = args
+        .post_settings
+        .randomx_mode
+        .resolve(args.post_settings.randomx_large_pages);
     log::info!(
         "POST network parameters: {}",
         serde_json::to_string(&args.post_config).unwrap()
@@ -219,41 +952,109 @@ async fn main() -> eyre::Result<()> {
     if let Some(uri) = &args.remote_k2pow {
         log::info!("remote k2pow uri: {}", uri);
     }
+
+    let tls = if let Some(tls) = args.tls.take() {
+        log::info!(
+            "configuring TLS: server: (CA cert: {}, domain: {:?}), client: (cert: {}, key: {})",
+            tls.ca_cert.display(),
+            tls.domain,
+            tls.cert.display(),
+            tls.key.display(),
+        );
+        Some(client::TlsFiles {
+            domain: tls.domain,
+            ca_cert: tls.ca_cert,
+            cert: tls.cert,
+            key: tls.key,
+        })
+    } else {
+        log::info!("not configuring TLS");
+        None
+    };
+    if args.self_test {
+        return self_test(&args, tls).await;
+    }
+    if args.check {
+        return check(&args, tls).await;
+    }
+    let operator = args.operator_address.map(|address| {
+        let config = operator::Config {
+            auth_token: args.operator_token.take(),
+            tls: args
+                .operator_tls_cert
+                .take()
+                .zip(args.operator_tls_key.take())
+                .map(|(cert, key)| operator::Tls { cert, key }),
+        };
+        (address, config)
+    });
+    let mut serve_config = ServeConfig {
+        addresses: std::mem::take(&mut args.address),
+        tls,
+        operator,
+        max_retries: args.max_retries,
+        reconnect_interval: args.reconnect_interval_s,
+        watch_pid: args.watch_pid,
+    };
+
+    if !args.mock_proving {
+        // Fail before spending hours on initializing POS data that can't be proven.
+        check_randomx_support(&args)?;
+    }
+
+    let supervisor = match args.init.init_units {
+        Some(num_units) => Some(initialize(&args, num_units, &mut serve_config).await?),
+        None => None,
+    };
+
+    if args.mock_proving {
+        log::warn!(
+            "simulating proving, proofs are returned after {:?} and are not valid",
+            args.mock_proving_delay
+        );
+        let service = post_service::simulation::SimulatedPostService::new(
+            &args.dir,
+            args.post_config.k2,
+            args.post_settings.nonces.initial(),
+            args.mock_proving_delay,
+        )
+        .wrap_err("creating simulated Post Service")?;
+        let service = Arc::new(service);
+        if let Some(supervisor) = supervisor {
+            supervisor.start_proving(service.clone());
+        }
+        return serve(service, serve_config).await;
+    }
     let scrypt = post::config::ScryptParams::new(
         args.post_config.scrypt.n,
         args.post_config.scrypt.r,
         args.post_config.scrypt.p,
     );
 
-    let cores_config = if let Some(pinned) = args.post_settings.cores.pinned_cores {
-        log::info!(
-            "using {} threads, pinned to cores: {:?}",
-            pinned.len(),
-            pinned.as_slice()
-        );
-        post::config::Cores::Pin(pinned)
-    } else {
-        match args.post_settings.cores.threads {
-            0 => {
-                log::info!("using all available cores");
-                post::config::Cores::All
-            }
-            n => {
-                log::info!("using {n} cores");
-                post::config::Cores::Any(n)
-            }
-        }
-    };
+    let cores_config = cores_config(&args.post_settings.cores)?;
 
     let remote_k2pow_config = match args.remote_k2pow {
         Some(url) => Some(K2powConfig {
             url,
             parallelism: args.remote_k2pow_parallelism,
+            identity_parallelism: args
+                .remote_k2pow_identity_parallelism
+                .unwrap_or(args.remote_k2pow_parallelism.div_ceil(2)),
             backoff: Duration::from_secs(args.remote_k2pow_backoff),
         }),
         None => None,
     };
 
+    // Catch POS data resized without updating the metadata before generating invalid proofs.
+    #[cfg(feature = "object-storage")]
+    let local_data = args.data_url.is_none();
+    #[cfg(not(feature = "object-storage"))]
+    let local_data = true;
+    if local_data {
+        post::pos_verification::check_data_size(&args.dir)
+            .wrap_err("POS data doesn't match its metadata")?;
+    }
+
     let service = post_service::service::PostService::new(
         args.dir,
         post::config::ProofConfig {
@@ -262,60 +1063,256 @@ async fn main() -> eyre::Result<()> {
             pow_difficulty: args.post_config.pow_difficulty,
         },
         scrypt,
-        args.post_settings.nonces,
+        args.post_settings.nonces.initial(),
         cores_config,
-        args.post_settings.randomx_mode.into(),
+        randomx::with_large_pages(
+            args.post_settings.randomx_mode.into(),
+            args.post_settings.randomx_large_pages,
+        )?,
         remote_k2pow_config,
     )
-    .wrap_err("creating Post Service")?;
+    .wrap_err("creating Post Service")?
+    .with_pow_version(args.post_config.pow_version)
+    .with_randomx_cache_key(randomx::cache_key(
+        args.post_config.randomx_network_id.as_deref(),
+    ))
+    .with_read_rate_limit(
+        args.post_settings
+            .read_rate_limit
+            .map(|mib| mib.saturating_mul(NonZeroU64::new(1024 * 1024).unwrap())),
+    )
+    .with_pipelined_k2pow(args.post_settings.pipelined_k2pow)
+    .with_read_retry(post::prove::ReadRetry {
+        retries: args.post_settings.read_retries,
+        backoff: args.post_settings.read_retry_backoff,
+    })
+    .with_self_verification(args.post_settings.self_verification)
+    .with_proof_dir(args.proof_dir.clone())
+    .with_batch_size(args.post_settings.batch_size)
+    .with_memory_budget(
+        args.post_settings
+            .memory_budget
+            .map(|mib| mib.saturating_mul(1024 * 1024)),
+    )
+    .with_efficiency_cores(args.post_settings.cores.efficiency_cores)
+    .with_memory_limit(
+        args.post_settings
+            .memory_limit
+            .map(|mib| mib.saturating_mul(1024 * 1024)),
+    )
+    .with_early_abort(args.post_settings.early_abort)
+    .with_max_nonces(args.post_settings.max_nonces);
+    let service = match args.post_settings.partial_data {
+        true => service.with_partial_data()?,
+        false => service,
+    };
+    let service = match args.post_settings.check_data {
+        true => service.with_data_check()?,
+        false => service,
+    };
+    let service = match args.state_dir {
+        Some(dir) => service.with_state_dir(dir)?,
+        None => service,
+    };
+    #[cfg(feature = "object-storage")]
+    let service = match args.data_url {
+        Some(url) => {
+            let metadata = client::PostService::get_metadata(&service);
+            let source = post::object_storage::HttpDataSource::new(
+                url.clone(),
+                metadata.total_size(),
+                metadata.max_file_size,
+            )?;
+            let source = match args.data_region {
+                Some(region) => {
+                    let env =
+                        |name| std::env::var(name).wrap_err_with(|| format!("reading {name}"));
+                    source.with_credentials(post::object_storage::Credentials {
+                        access_key_id: env("AWS_ACCESS_KEY_ID")?,
+                        secret_access_key: env("AWS_SECRET_ACCESS_KEY")?,
+                        session_token: std::env::var("AWS_SESSION_TOKEN").ok(),
+                        region,
+                    })
+                }
+                None => source,
+            };
+            log::info!("reading POS data from {url}");
+            let file_size = metadata.max_file_size;
+            service.with_data_source(Arc::new(post::prove::PosFiles::new(source, file_size)))
+        }
+        None => service,
+    };
+    let service = match args.post_settings.nonces {
+        Nonces::Auto => service
+            .with_planned_nonces(args.post_settings.proof_probability)
+            .wrap_err("planning nonces")?,
+        Nonces::Fixed(_) => service,
+    };
+    let service = if args.post_settings.proving_windows.is_empty() {
+        service
+    } else {
+        service.with_schedule(Schedule::new(args.post_settings.proving_windows.clone()))
+    };
 
     let post_metadata = client::PostService::get_metadata(&service);
     verify_num_units(
         args.post_config.min_num_units..=args.post_config.max_num_units,
         post_metadata.num_units,
     )?;
+    if let Some(cfg) = args.post_config.preset.init_config() {
+        verify_labels_per_unit(cfg.labels_per_unit, post_metadata.labels_per_unit)?;
+    }
+    log_difficulties(&args.post_config, post_metadata);
 
-    let tls = if let Some(tls) = args.tls {
-        log::info!(
-            "configuring TLS: server: (CA cert: {}, domain: {:?}), client: (cert: {}, key: {})",
-            tls.ca_cert.display(),
-            tls.domain,
-            tls.cert.display(),
-            tls.key.display(),
-        );
-        let server_ca_cert = read_to_string(tls.ca_cert)?;
-        let cert = read_to_string(tls.cert)?;
-        let key = read_to_string(tls.key)?;
-        Some((
-            tls.domain,
-            Certificate::from_pem(server_ca_cert),
-            Identity::from_pem(cert, key),
-        ))
-    } else {
-        log::info!("not configuring TLS");
-        None
+    let service = Arc::new(service);
+    if let Some(supervisor) = supervisor {
+        supervisor.start_proving(service.clone());
+    }
+
+    let scheduled_service = service.clone();
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(Duration::from_secs(10));
+        loop {
+            interval.tick().await;
+            scheduled_service.apply_schedule(std::time::SystemTime::now());
+        }
+    });
+
+    serve(service, serve_config).await
+}
+
+/// Initialize the POST data in `--dir` (supervisor mode).
+///
+/// The operator API is served (reporting the progress) while initializing,
+/// it's taken out of `serve_config`.
+async fn initialize(
+    args: &Cli,
+    num_units: u32,
+    serve_config: &mut ServeConfig,
+) -> eyre::Result<Arc<Supervisor>> {
+    let labels_per_unit = match (
+        args.init.init_labels_per_unit,
+        args.post_config.preset.init_config(),
+    ) {
+        (Some(labels), _) => labels,
+        (None, Some(cfg)) => cfg.labels_per_unit,
+        (None, None) => eyre::bail!("--init-labels-per-unit is required without a --preset"),
+    };
+    verify_num_units(
+        args.post_config.min_num_units..=args.post_config.max_num_units,
+        num_units,
+    )?;
+    let config = InitConfig {
+        node_id: args.init.init_node_id.unwrap(),
+        commitment_atx_id: args.init.init_commitment_atx_id.unwrap(),
+        labels_per_unit,
+        num_units,
+        labels_per_file: args.init.init_max_file_size / post::initialize::LABEL_SIZE as u64,
     };
+    let supervisor = Arc::new(Supervisor::new(args.dir.clone(), config));
+    if let Some((address, operator_config)) = serve_config.operator.take() {
+        tokio::spawn(operator::run(address, supervisor.clone(), operator_config));
+    }
 
-    let service = Arc::new(service);
+    let scrypt = post::config::ScryptParams::new(
+        args.post_config.scrypt.n,
+        args.post_config.scrypt.r,
+        args.post_config.scrypt.p,
+    );
+    let provider = args.init.provider();
+    let excluded_providers = args.init.excluded_providers();
+    let init = supervisor.clone();
+    let mut task = tokio::task::spawn_blocking(move || {
+        // The OpenCL initializer can't be sent between threads, it's created here.
+        let mut initializer = new_initializer(scrypt, provider, &excluded_providers)?;
+        init.initialize(initializer.as_mut())
+    });
 
-    if let Some(address) = args.operator_address {
-        tokio::spawn(operator::run(address, service.clone()));
+    let mut interval = tokio::time::interval(Duration::from_secs(60));
+    loop {
+        tokio::select! {
+            result = &mut task => {
+                let metadata = result??;
+                log::info!("POS data initialized, starting proving (metadata: {metadata:?})");
+                break;
+            }
+            _ = interval.tick() => supervisor.log_progress(),
+        }
     }
+    Ok(supervisor)
+}
 
-    let client = client::ServiceClient::new(args.address, tls, service)?;
-    let client_handle = tokio::spawn(client.run(args.max_retries, args.reconnect_interval_s));
+fn new_initializer(
+    scrypt: post::config::ScryptParams,
+    provider: Option<u32>,
+    excluded_providers: &[String],
+) -> eyre::Result<Box<dyn post::initialize::Initialize>> {
+    #[cfg(feature = "opencl")]
+    if let Some(id) = provider {
+        eyre::ensure!(
+            scrypt.r == 1 && scrypt.p == 1,
+            "the OpenCL initializer supports only scrypt r = 1 and p = 1 (got r = {}, p = {})",
+            scrypt.r,
+            scrypt.p
+        );
+        log::info!("initializing with OpenCL provider {id}");
+        return Ok(Box::new(scrypt_ocl::OpenClInitializer::new(
+            Some(scrypt_ocl::ProviderId(id)),
+            scrypt.n,
+            Some(scrypt_ocl::ocl::DeviceType::GPU),
+            &scrypt_ocl::ProviderFilter::exclude(excluded_providers)?,
+        )?));
+    }
+    eyre::ensure!(
+        provider.is_none() && excluded_providers.is_empty(),
+        "initializing with an OpenCL provider requires the `opencl` feature"
+    );
+    log::info!("initializing with CPU");
+    Ok(Box::new(post::initialize::CpuInitializer::new(scrypt)))
+}
+
+/// How to serve the nodes (and the operator API).
+struct ServeConfig {
+    addresses: Vec<String>,
+    tls: Option<client::TlsFiles>,
+    operator: Option<(SocketAddr, operator::Config)>,
+    max_retries: Option<usize>,
+    reconnect_interval: Duration,
+    watch_pid: Option<Pid>,
+}
+
+/// Serve the nodes until a connection fails for good or the watched PID dies.
+async fn serve<S>(service: Arc<S>, config: ServeConfig) -> eyre::Result<()>
+where
+    S: client::PostService + operator::Service + Send + Sync + 'static,
+{
+    if let Some((address, operator_config)) = config.operator {
+        tokio::spawn(operator::run(address, service.clone(), operator_config));
+    }
+
+    let mut clients = JoinSet::new();
+    for address in config.addresses {
+        log::info!("connecting to node at {address}");
+        let client = match &config.tls {
+            Some(tls) => {
+                client::ServiceClient::with_tls_files(address, tls.clone(), service.clone())?
+            }
+            None => client::ServiceClient::new(address, None, service.clone())?,
+        };
+        clients.spawn(client.run(config.max_retries, config.reconnect_interval));
+    }
 
     // A channel to communicate when the blocking task should quit.
     let (term_tx, term_rx) = oneshot::channel();
 
     tokio::select! {
-        Some(err) = watch_pid_if_needed(args.watch_pid.map(|p| (p, term_rx))) => {
+        Some(err) = watch_pid_if_needed(config.watch_pid.map(|p| (p, term_rx))) => {
             log::info!("PID watcher exited: {err:?}");
-            return Ok(())
+            Ok(())
         }
-        err = client_handle => {
+        Some(err) = clients.join_next() => {
             drop(term_tx);
-            return err.unwrap();
+            err.unwrap()
         }
     }
 }
@@ -366,6 +1363,27 @@ fn watch_pid(pid: Pid, interval: Duration, mut term: Receiver<()>) {
     }
 }
 
+/// Log the difficulties for the POS data, to help diagnose slow proving.
+fn log_difficulties(cfg: &PostConfig, metadata: &post::metadata::PostMetadata) {
+    use post::difficulty;
+
+    match difficulty::proving_difficulty(cfg.k1, metadata.total_labels()) {
+        Ok(d) => log::info!(
+            "proving difficulty: {}",
+            difficulty::describe_proving_difficulty(d)
+        ),
+        Err(e) => log::warn!("invalid proving difficulty: {e}"),
+    }
+    match difficulty::scale_pow_difficulty(&cfg.pow_difficulty, metadata.num_units) {
+        Ok(d) => log::info!(
+            "k2pow difficulty for {} units: {}",
+            metadata.num_units,
+            difficulty::describe_pow_difficulty(&d)
+        ),
+        Err(e) => log::warn!("invalid k2pow difficulty: {e}"),
+    }
+}
+
 fn verify_num_units(range: std::ops::RangeInclusive<u32>, num_units: u32) -> eyre::Result<()> {
     if !range.contains(&num_units) {
         return Err(eyre::eyre!(
@@ -377,13 +1395,143 @@ fn verify_num_units(range: std::ops::RangeInclusive<u32>, num_units: u32) -> eyr
     }
     Ok(())
 }
+
+fn verify_labels_per_unit(expected: u64, labels_per_unit: u64) -> eyre::Result<()> {
+    eyre::ensure!(
+        labels_per_unit == expected,
+        "labels per unit in the POST data don't match the network: {labels_per_unit} != {expected}"
+    );
+    Ok(())
+}
+
 #[cfg(test)]
 mod tests {
     use std::process::Command;
 
+    use post::prove::BatchSize;
+    use post_service::service::SelfVerification;
     use sysinfo::Pid;
     use tokio::sync::oneshot;
 
+    #[cfg(feature = "object-storage")]
+    #[test]
+    fn checking_remote_data_is_rejected() {
+        use clap::Parser;
+
+        let err = super::Cli::try_parse_from([
+            "post-service",
+            "--dir=./post-data",
+            "--data-url=https://my-bucket.s3.amazonaws.com/post-data",
+            "--check-data",
+        ])
+        .unwrap_err();
+        assert_eq!(clap::error::ErrorKind::ArgumentConflict, err.kind());
+    }
+
+    #[test]
+    fn parsing_healthcheck_args() {
+        use clap::Parser;
+
+        let args = super::HealthcheckCli::parse_from([
+            "post-service",
+            "--healthcheck",
+            "--operator-address",
+            "127.0.0.1:50051",
+        ]);
+        assert!(args.healthcheck);
+        assert_eq!(
+            Some("127.0.0.1:50051".parse().unwrap()),
+            args.operator_address
+        );
+
+        let args = super::HealthcheckCli::parse_from([
+            "post-service",
+            "--operator-token",
+            "secret",
+            "--operator-address=0.0.0.0:50051",
+            "--healthcheck",
+        ]);
+        assert!(args.healthcheck);
+        assert_eq!(Some("secret"), args.operator_token.as_deref());
+
+        // The arguments of running the service are left to `Cli`.
+        let args = super::HealthcheckCli::parse_from([
+            "post-service",
+            "--dir",
+            "/data",
+            "--address",
+            "http://localhost:9094",
+            "--healthcheck",
+        ]);
+        assert!(!args.healthcheck);
+    }
+
+    #[test]
+    fn reading_operator_token_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("token");
+        std::fs::write(&path, "secret\n").unwrap();
+        assert_eq!(
+            Some("secret".to_string()),
+            super::operator_token(None, Some(&path)).unwrap()
+        );
+        assert_eq!(
+            Some("direct".to_string()),
+            super::operator_token(Some("direct".into()), None).unwrap()
+        );
+        assert_eq!(None, super::operator_token(None, None).unwrap());
+
+        std::fs::write(&path, " \n").unwrap();
+        assert!(super::operator_token(None, Some(&path)).is_err());
+        assert!(super::operator_token(None, Some(&dir.path().join("missing"))).is_err());
+    }
+
+    #[test]
+    fn parsing_batch_size() {
+        let parse = super::parse_batch_size;
+        assert_eq!(BatchSize::Fixed(256 * 1024), parse("256").unwrap());
+        assert!(matches!(parse("auto").unwrap(), BatchSize::Auto { .. }));
+        assert!(parse("0").is_err());
+        assert!(parse("1MiB").is_err());
+    }
+
+    #[test]
+    fn parsing_nonces() {
+        let parse = super::parse_nonces;
+        assert_eq!(super::Nonces::Fixed(64), parse("64").unwrap());
+        assert_eq!(super::Nonces::Auto, parse("auto").unwrap());
+        assert!(parse("0").is_err());
+        assert!(parse("4097").is_err());
+    }
+
+    #[test]
+    fn parsing_max_nonces() {
+        let parse = super::parse_max_nonces;
+        assert_eq!(128, parse("128").unwrap());
+        assert_eq!(4096, parse("4096").unwrap());
+        assert!(parse("0").is_err());
+        assert!(parse("4097").is_err());
+    }
+
+    #[test]
+    fn parsing_probability() {
+        let parse = super::parse_probability;
+        assert_eq!(0.001, parse("0.001").unwrap());
+        assert!(parse("0").is_err());
+        assert!(parse("1").is_err());
+        assert!(parse("NaN").is_err());
+    }
+
+    #[test]
+    fn parsing_self_verification() {
+        let parse = super::parse_self_verification;
+        assert_eq!(SelfVerification::All, parse("all").unwrap());
+        assert_eq!(SelfVerification::Skip, parse("skip").unwrap());
+        assert_eq!(SelfVerification::Subset { k3: 10 }, parse("10").unwrap());
+        assert!(parse("0").is_err());
+        assert!(parse("some").is_err());
+    }
+
     #[tokio::test]
     async fn watch_pid_if_needed() {
         // Don't watch
@@ -478,4 +1626,10 @@ mod tests {
         assert!(super::verify_num_units(1..=10, 0).is_err());
         assert!(super::verify_num_units(1..=10, 11).is_err());
     }
+
+    #[test]
+    fn verify_labels_per_unit() {
+        super::verify_labels_per_unit(1024, 1024).unwrap();
+        assert!(super::verify_labels_per_unit(1 << 32, 1024).is_err());
+    }
 }
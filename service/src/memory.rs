@@ -0,0 +1,215 @@
+//! Approximate memory used by proof generation and fitting it in a soft limit.
+//!
+//! The biggest consumers are the RandomX dataset (in the fast mode), the RandomX cache
+//! and the batches of POS data read for proving. The rest of the service takes
+//! a few MiB and is not accounted for.
+
+use post::{
+    config::Cores,
+    pow::randomx::{RandomXFlag, FAST_MODE_MEMORY, LIGHT_MODE_MEMORY},
+    prove::BatchSize,
+};
+use serde::{Deserialize, Serialize};
+
+/// The smallest batch the batches of POS data are shrunk to when fitting in the limit.
+const MIN_BATCH_SIZE: usize = 64 * 1024;
+
+/// Approximate memory (in bytes) used by proof generation.
+#[derive(Clone, Copy, Debug, Default, Serialize, Deserialize, PartialEq, Eq)]
+pub struct MemoryUsage {
+    /// The RandomX dataset of the fast mode (calculating k2pow).
+    pub randomx_dataset: u64,
+    /// The RandomX cache (calculating k2pow and verifying proofs).
+    pub randomx_cache: u64,
+    /// The batches of POS data read for proving.
+    pub proving_buffers: u64,
+    /// The soft limit of the memory usage, if any.
+    pub limit: Option<u64>,
+}
+
+impl MemoryUsage {
+    pub fn total(&self) -> u64 {
+        self.randomx_dataset + self.randomx_cache + self.proving_buffers
+    }
+}
+
+/// The settings of proof generation that the memory usage depends on.
+#[derive(Clone, Debug, PartialEq)]
+pub struct MemorySettings {
+    /// The RandomX flags of a local k2pow prover, `None` with a remote k2pow service
+    /// or another PoW algorithm.
+    pub pow_flags: Option<RandomXFlag>,
+    /// Whether the generated proofs are verified (with a RandomX cache).
+    pub verify: bool,
+    pub threads: usize,
+    pub batch_size: BatchSize,
+    pub memory_budget: Option<u64>,
+}
+
+impl MemorySettings {
+    pub fn usage(&self) -> MemoryUsage {
+        let fast = self
+            .pow_flags
+            .is_some_and(|flags| flags.contains(RandomXFlag::FLAG_FULL_MEM));
+        // Every proving thread holds a batch, unless the memory budget bounds all the batches
+        // (letting at least one through).
+        let batches = self.threads as u64 * self.batch_size.max() as u64;
+        MemoryUsage {
+            randomx_dataset: if fast {
+                FAST_MODE_MEMORY - LIGHT_MODE_MEMORY
+            } else {
+                0
+            },
+            randomx_cache: if self.pow_flags.is_some() || self.verify {
+                LIGHT_MODE_MEMORY
+            } else {
+                0
+            },
+            proving_buffers: self
+                .memory_budget
+                .map_or(batches, |budget| budget.max(self.batch_size.max() as u64)),
+            limit: None,
+        }
+    }
+
+    /// Downgrade the settings to fit the memory usage in the `limit`:
+    /// use the RandomX light mode first, then read POS data in smaller batches.
+    ///
+    /// The limit is soft, the settings are left as small as possible if they still
+    /// don't fit (i.e. too many threads).
+    pub fn fit(mut self, limit: u64) -> Self {
+        if self.usage().total() <= limit {
+            return self;
+        }
+        if let Some(flags) = self.pow_flags {
+            if flags.contains(RandomXFlag::FLAG_FULL_MEM) {
+                log::warn!("memory limit exceeded, using the RandomX light mode");
+                self.pow_flags = Some(flags - RandomXFlag::FLAG_FULL_MEM);
+            }
+        }
+        let usage = self.usage();
+        if usage.total() <= limit {
+            return self;
+        }
+
+        let buffers = limit.saturating_sub(usage.randomx_dataset + usage.randomx_cache);
+        let batch = (buffers / self.threads.max(1) as u64) as usize;
+        // Round down to a power of two, like the tuned batch sizes.
+        let batch = batch
+            .checked_ilog2()
+            .map_or(0, |log| 1 << log)
+            .max(MIN_BATCH_SIZE);
+        self.batch_size = match self.batch_size {
+            BatchSize::Fixed(size) => BatchSize::Fixed(size.min(batch)),
+            BatchSize::Auto { min, max } => BatchSize::Auto {
+                min: min.min(batch),
+                max: max.min(batch),
+            },
+        };
+        self.memory_budget = self.memory_budget.map(|budget| budget.min(buffers));
+        log::warn!(
+            "memory limit exceeded, reading POS data in batches of up to {} KiB",
+            self.batch_size.max() / 1024
+        );
+        let total = self.usage().total();
+        if total > limit {
+            log::warn!(
+                "proving needs ~{} MiB of memory, over the limit of {} MiB",
+                total / 1024 / 1024,
+                limit / 1024 / 1024
+            );
+        }
+        self
+    }
+}
+
+/// The number of proving threads.
+pub fn threads(cores: &Cores) -> usize {
+    match cores {
+        Cores::All => std::thread::available_parallelism().map_or(1, |n| n.get()),
+        Cores::Any(n) => *n,
+        Cores::Pin(cores) => cores.len(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use post::pow::randomx::{RandomXFlag, FAST_MODE_MEMORY, LIGHT_MODE_MEMORY};
+    use post::prove::BatchSize;
+
+    use super::MemorySettings;
+
+    const MIB: u64 = 1024 * 1024;
+
+    fn settings() -> MemorySettings {
+        MemorySettings {
+            pow_flags: Some(RandomXFlag::get_recommended_flags() | RandomXFlag::FLAG_FULL_MEM),
+            verify: true,
+            threads: 4,
+            batch_size: BatchSize::Fixed(1024 * 1024),
+            memory_budget: None,
+        }
+    }
+
+    #[test]
+    fn estimating_usage() {
+        let usage = settings().usage();
+        assert_eq!(FAST_MODE_MEMORY - LIGHT_MODE_MEMORY, usage.randomx_dataset);
+        assert_eq!(LIGHT_MODE_MEMORY, usage.randomx_cache);
+        assert_eq!(4 * MIB, usage.proving_buffers);
+        assert_eq!(FAST_MODE_MEMORY + 4 * MIB, usage.total());
+
+        let remote = MemorySettings {
+            pow_flags: None,
+            verify: false,
+            memory_budget: Some(64 * MIB),
+            ..settings()
+        };
+        assert_eq!(64 * MIB, remote.usage().total());
+
+        // The budget bounds the batches held by the proving threads too
+        let tight = MemorySettings {
+            memory_budget: Some(2 * MIB),
+            ..remote
+        };
+        assert_eq!(2 * MIB, tight.usage().proving_buffers);
+    }
+
+    #[test]
+    fn fitting_in_limit() {
+        // Fits already.
+        assert_eq!(settings(), settings().fit(4096 * MIB));
+
+        // Light mode is enough.
+        let fitted = settings().fit(1024 * MIB);
+        assert!(!fitted
+            .pow_flags
+            .unwrap()
+            .contains(RandomXFlag::FLAG_FULL_MEM));
+        assert_eq!(BatchSize::Fixed(1024 * 1024), fitted.batch_size);
+
+        // The batches must shrink too.
+        let fitted = MemorySettings {
+            batch_size: BatchSize::Auto {
+                min: 64 * 1024,
+                max: 16 * 1024 * 1024,
+            },
+            memory_budget: Some(256 * MIB),
+            ..settings()
+        }
+        .fit(LIGHT_MODE_MEMORY + 3 * MIB);
+        assert_eq!(
+            BatchSize::Auto {
+                min: 64 * 1024,
+                max: 512 * 1024
+            },
+            fitted.batch_size
+        );
+        assert_eq!(Some(3 * MIB), fitted.memory_budget);
+        assert!(fitted.usage().total() <= LIGHT_MODE_MEMORY + 3 * MIB);
+
+        // Too little memory, the batches stay at the minimum.
+        let fitted = settings().fit(LIGHT_MODE_MEMORY);
+        assert_eq!(BatchSize::Fixed(64 * 1024), fitted.batch_size);
+    }
+}
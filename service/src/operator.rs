@@ -1,17 +1,115 @@
 //! Operator service for controlling the post service.
 //!
 //! It exposes an HTTP API.
-//! Allows to query the status of the post service.
+//! Allows to query the status of the post service and to change its settings.
+//!
+//! The API can be served over TLS and protected with a shared secret token,
+//! which must be passed in the `Authorization: Bearer <token>` header.
 
-use std::{net::SocketAddr, ops::Range, sync::Arc};
+use std::{
+    collections::BTreeMap,
+    io::{BufRead, Write},
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+    ops::Range,
+    path::PathBuf,
+    sync::Arc,
+    time::Duration,
+};
 
-use axum::{extract::State, routing::get, Json, Router};
+use axum::{
+    extract::{Query, Request, State},
+    http::{header::AUTHORIZATION, StatusCode},
+    middleware::{self, Next},
+    response::{
+        sse::{Event, KeepAlive, Sse},
+        IntoResponse, Response,
+    },
+    routing::{get, put},
+    Json, Router,
+};
+use axum_server::tls_rustls::RustlsConfig;
+use eyre::Context;
+use post::prove::Proof;
 use serde::{Deserialize, Serialize};
+use serde_with::{hex::Hex, serde_as};
 use tokio::net::TcpListener;
+use tokio_stream::{wrappers::BroadcastStream, StreamExt};
+use tower_http::trace::TraceLayer;
+
+use crate::history::{ProofSummary, HISTORY_SIZE};
+use crate::logging::LogRecord;
+use crate::memory::MemoryUsage;
+use crate::workers::WorkerStats;
 
 #[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
 /// The Post-service state
 pub enum ServiceState {
+    /// The service is initializing the POST data before proving (see `--init-units`).
+    Initializing {
+        /// The number of labels already initialized.
+        labels_done: u64,
+        /// The number of labels to initialize.
+        total: u64,
+        /// The number of units already initialized completely.
+        #[serde(default)]
+        units_done: u64,
+        /// The number of units to initialize.
+        #[serde(default)]
+        units: u32,
+    },
+    /// The service is idle.
+    Idle,
+    /// The service is calculating the k2pows of the nonce groups before a data pass.
+    ComputingPow {
+        /// The range of nonces being proven in the current data pass.
+        nonces: Range<u32>,
+        /// The number of nonce groups with the k2pow already found.
+        done: u32,
+        /// The number of nonce groups in the pass.
+        total: u32,
+    },
+    /// The service is reading the POST data.
+    ReadingData {
+        /// The range of nonces being proven in the current data pass.
+        nonces: Range<u32>,
+        /// The position (in bytes) in the POST data that is already checked.
+        position: u64,
+        /// The size (in bytes) of the POST data.
+        total: u64,
+        /// The number of indices a nonce needs to find to generate a proof.
+        #[serde(default)]
+        k2: u32,
+        /// The number of indices found so far in the current pass for the nonces
+        /// with at least one found.
+        #[serde(default)]
+        indices_found: BTreeMap<u32, usize>,
+    },
+    /// Proving is paused until the next proving window opens.
+    Scheduled {
+        /// The range of nonces being proven in the current data pass.
+        nonces: Range<u32>,
+        /// The position (in bytes) in the POST data that is already checked.
+        position: u64,
+    },
+    /// Finished proving, but the proof has not been fetched yet.
+    DoneProving,
+}
+
+/// The state of the service in the shape served by `/status`, kept for the existing clients.
+///
+/// Both phases of a data pass ([ServiceState::ComputingPow] and [ServiceState::ReadingData])
+/// are reported as `Proving`. The detailed state is served by `/v2/status`.
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+pub enum ServiceStateV1 {
+    /// See [ServiceState::Initializing].
+    Initializing {
+        labels_done: u64,
+        total: u64,
+        #[serde(default)]
+        units_done: u64,
+        #[serde(default)]
+        units: u32,
+    },
     /// The service is idle.
     Idle,
     /// The service is currently proving.
@@ -19,17 +117,130 @@ pub enum ServiceState {
         /// The range of nonces being proven in the current data pass.
         nonces: Range<u32>,
         /// The position (in bytes) in the POST data that is already checked.
+        /// It's 0 while calculating the k2pows.
         position: u64,
     },
+    /// See [ServiceState::Scheduled].
+    Scheduled { nonces: Range<u32>, position: u64 },
     /// Finished proving, but the proof has not been fetched yet.
     DoneProving,
 }
 
+impl From<ServiceState> for ServiceStateV1 {
+    fn from(state: ServiceState) -> Self {
+        match state {
+            ServiceState::Initializing {
+                labels_done,
+                total,
+                units_done,
+                units,
+            } => Self::Initializing {
+                labels_done,
+                total,
+                units_done,
+                units,
+            },
+            ServiceState::Idle => Self::Idle,
+            ServiceState::ComputingPow { nonces, .. } => Self::Proving {
+                nonces,
+                position: 0,
+            },
+            ServiceState::ReadingData {
+                nonces, position, ..
+            } => Self::Proving { nonces, position },
+            ServiceState::Scheduled { nonces, position } => Self::Scheduled { nonces, position },
+            ServiceState::DoneProving => Self::DoneProving,
+        }
+    }
+}
+
+/// The page of the proof history to return.
+#[derive(Clone, Debug, Deserialize)]
+pub struct HistoryQuery {
+    /// The number of the most recent entries to skip.
+    #[serde(default)]
+    pub offset: usize,
+    /// The maximum number of entries to return.
+    #[serde(default = "default_history_limit")]
+    pub limit: usize,
+}
+
+fn default_history_limit() -> usize {
+    20
+}
+
+/// The log records to return.
+#[derive(Clone, Debug, Deserialize)]
+pub struct LogsQuery {
+    /// Keep streaming the new records (as server-sent events) after the recent ones.
+    #[serde(default)]
+    pub follow: bool,
+    /// The maximum number of the recent records to return (all kept if not set).
+    pub limit: Option<usize>,
+}
+
+/// The filter of the service logs.
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+pub struct LogFilter {
+    /// I.e. "info,post::prove=debug" (see [crate::logging::set_filter]).
+    pub filter: String,
+}
+
+/// A page of the history of completed proof generations.
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+pub struct HistoryPage {
+    /// The number of entries in the whole history.
+    pub total: usize,
+    /// The entries of the page, the newest first.
+    pub entries: Vec<ProofSummary>,
+}
+
+/// The most recent proof generated by the post service.
+#[serde_as]
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+pub struct LastProof {
+    /// The challenge the proof was generated for.
+    #[serde_as(as = "Hex")]
+    pub challenge: [u8; 32],
+    #[serde(flatten)]
+    pub proof: Proof<'static>,
+}
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize, PartialEq)]
+/// Changes to the settings of the post service.
+///
+/// Fields that are not set are left unchanged.
+/// The new proving settings take effect in the next proof generation.
+pub struct SettingsUpdate {
+    /// The number of threads to use for proving, `0` means all available.
+    pub threads: Option<usize>,
+    /// The cores to pin proving threads to.
+    pub pinned_cores: Option<Vec<usize>>,
+    /// The number of nonces to attempt in a single pass over POS data.
+    pub nonces: Option<usize>,
+    /// The log filter (i.e. "debug" or "info,post::prove=debug", see [crate::logging::set_filter]).
+    pub log_level: Option<String>,
+}
+
 #[mockall::automock]
 /// The Service trait provides funcionality required by the OperatorService.
 pub trait Service {
     /// Returns the current state of the service.
     fn status(&self) -> ServiceState;
+    /// Applies changes to the settings of the service.
+    fn update_settings(&self, update: SettingsUpdate) -> eyre::Result<()>;
+    /// Returns the most recent proof, if any.
+    fn last_proof(&self) -> Option<LastProof>;
+    /// Returns the events of the last proof generation.
+    fn proof_events(&self) -> eyre::Result<Vec<crate::events::Record>>;
+    /// Returns the summaries of the completed proof generations, the newest first.
+    fn proof_history(&self) -> Vec<ProofSummary>;
+    /// Returns how the number of nonces was planned (with `--nonces=auto`).
+    fn proving_plan(&self) -> Option<crate::planner::Plan>;
+    /// Returns the approximate memory used by proof generation with the current settings.
+    fn memory_usage(&self) -> MemoryUsage;
+    /// Returns the statistics of the proving threads in the current (or last) proof generation.
+    fn worker_stats(&self) -> Vec<WorkerStats>;
 }
 
 pub fn create_router<S>(service: Arc<S>) -> Router
@@ -37,19 +248,141 @@ where
     S: Service + Sync + Send + 'static,
 {
     Router::new()
-        .route("/status", get(status))
+        .route("/status", get(status_v1))
+        .route("/v2/status", get(status))
+        .route("/settings", put(update_settings))
+        .route("/proof", get(last_proof))
+        .route("/proof/events", get(proof_events))
+        .route("/history", get(proof_history))
+        .route("/plan", get(proving_plan))
+        .route("/memory", get(memory_usage))
+        .route("/workers", get(worker_stats))
+        .route("/logs", get(logs))
+        .route("/logging", get(log_filter).put(set_log_filter))
         .with_state(service)
+        .layer(TraceLayer::new_for_http())
+}
+
+/// Require requests to carry the given token in the `Authorization: Bearer <token>` header.
+pub fn require_token(router: Router, token: String) -> Router {
+    router.layer(middleware::from_fn_with_state(Arc::new(token), authorize))
+}
+
+async fn authorize(
+    State(token): State<Arc<String>>,
+    request: Request,
+    next: Next,
+) -> Result<Response, StatusCode> {
+    let provided = request
+        .headers()
+        .get(AUTHORIZATION)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.strip_prefix("Bearer "));
+    match provided {
+        Some(provided) if constant_time_eq(provided.as_bytes(), token.as_bytes()) => {
+            Ok(next.run(request).await)
+        }
+        _ => Err(StatusCode::UNAUTHORIZED),
+    }
+}
+
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+/// TLS configuration of the operator API server.
+#[derive(Clone, Debug)]
+pub struct Tls {
+    /// Server certificate (PEM)
+    pub cert: PathBuf,
+    /// Server private key (PEM)
+    pub key: PathBuf,
 }
 
-pub async fn run<S>(address: SocketAddr, service: Arc<S>) -> eyre::Result<()>
+/// Configuration of the operator API server.
+#[derive(Clone, Debug, Default)]
+pub struct Config {
+    /// If set, requests must be authorized with this token.
+    pub auth_token: Option<String>,
+    /// If set, the API is served over TLS.
+    pub tls: Option<Tls>,
+}
+
+pub async fn run<S>(address: SocketAddr, service: Arc<S>, config: Config) -> eyre::Result<()>
 where
     S: Service + Sync + Send + 'static,
 {
-    let listener = TcpListener::bind(address).await?;
-    log::info!("running operator service on {}", listener.local_addr()?);
-    axum::serve(listener, create_router(service))
+    let mut router = create_router(service);
+    if let Some(token) = config.auth_token {
+        log::info!("operator service requires authorization");
+        router = require_token(router, token);
+    }
+
+    match config.tls {
+        Some(tls) => {
+            let tls_config = load_tls(&tls).await?;
+            log::info!("running operator service on {address} (TLS)");
+            axum_server::bind_rustls(address, tls_config)
+                .serve(router.into_make_service())
+                .await
+                .map_err(|e| eyre::eyre!("failed to serve: {e}"))
+        }
+        None => {
+            let listener = TcpListener::bind(address).await?;
+            log::info!("running operator service on {}", listener.local_addr()?);
+            axum::serve(listener, router)
+                .await
+                .map_err(|e| eyre::eyre!("failed to serve: {e}"))
+        }
+    }
+}
+
+/// Check that the operator API listening on `address` is healthy: responds to `/status`
+/// with `200 OK`. For health checks of containers without an HTTP client (i.e. curl).
+///
+/// An unspecified address (i.e. `0.0.0.0`) is checked on the loopback interface.
+/// The API served over TLS is only checked to accept connections.
+pub fn healthcheck(
+    address: SocketAddr,
+    token: Option<&str>,
+    tls: bool,
+    timeout: Duration,
+) -> eyre::Result<()> {
+    let address = match address.ip() {
+        IpAddr::V4(ip) if ip.is_unspecified() => (Ipv4Addr::LOCALHOST, address.port()).into(),
+        IpAddr::V6(ip) if ip.is_unspecified() => (Ipv6Addr::LOCALHOST, address.port()).into(),
+        _ => address,
+    };
+    let mut stream = std::net::TcpStream::connect_timeout(&address, timeout)
+        .wrap_err_with(|| format!("connecting to {address}"))?;
+    if tls {
+        return Ok(());
+    }
+    stream.set_read_timeout(Some(timeout))?;
+    stream.set_write_timeout(Some(timeout))?;
+    let authorization = token
+        .map(|token| format!("Authorization: Bearer {token}\r\n"))
+        .unwrap_or_default();
+    write!(
+        stream,
+        "GET /status HTTP/1.1\r\nHost: {address}\r\n{authorization}Connection: close\r\n\r\n"
+    )?;
+    let mut status_line = String::new();
+    std::io::BufReader::new(stream)
+        .read_line(&mut status_line)
+        .wrap_err("reading the response")?;
+    match status_line.split_whitespace().nth(1) {
+        Some("200") => Ok(()),
+        Some(_) => eyre::bail!("unhealthy: {}", status_line.trim()),
+        None => eyre::bail!("invalid response: {:?}", status_line.trim()),
+    }
+}
+
+/// Load the TLS certificate and key of the operator API server.
+pub async fn load_tls(tls: &Tls) -> eyre::Result<RustlsConfig> {
+    RustlsConfig::from_pem_file(&tls.cert, &tls.key)
         .await
-        .map_err(|e| eyre::eyre!("failed to serve: {e}"))
+        .map_err(|e| eyre::eyre!("failed to load TLS certificate or key: {e}"))
 }
 
 async fn status<S>(State(service): State<Arc<S>>) -> Json<ServiceState>
@@ -59,9 +392,121 @@ where
     Json(service.status())
 }
 
+async fn status_v1<S>(State(service): State<Arc<S>>) -> Json<ServiceStateV1>
+where
+    S: Service + Sync + Send + 'static,
+{
+    Json(service.status().into())
+}
+
+async fn last_proof<S>(State(service): State<Arc<S>>) -> Result<Json<LastProof>, StatusCode>
+where
+    S: Service + Sync + Send + 'static,
+{
+    service.last_proof().map(Json).ok_or(StatusCode::NOT_FOUND)
+}
+
+async fn proving_plan<S>(
+    State(service): State<Arc<S>>,
+) -> Result<Json<crate::planner::Plan>, StatusCode>
+where
+    S: Service + Sync + Send + 'static,
+{
+    service
+        .proving_plan()
+        .map(Json)
+        .ok_or(StatusCode::NOT_FOUND)
+}
+
+async fn memory_usage<S>(State(service): State<Arc<S>>) -> Json<MemoryUsage>
+where
+    S: Service + Sync + Send + 'static,
+{
+    Json(service.memory_usage())
+}
+
+async fn worker_stats<S>(State(service): State<Arc<S>>) -> Json<Vec<WorkerStats>>
+where
+    S: Service + Sync + Send + 'static,
+{
+    Json(service.worker_stats())
+}
+
+async fn logs(Query(query): Query<LogsQuery>) -> Response {
+    let (mut recent, receiver) = crate::logging::logs().follow();
+    if let Some(limit) = query.limit {
+        recent.drain(..recent.len().saturating_sub(limit));
+    }
+    if !query.follow {
+        return Json(recent).into_response();
+    }
+    // A follower lagging behind skips the records it missed.
+    let new = BroadcastStream::new(receiver).filter_map(Result::ok);
+    let events = tokio_stream::iter(recent)
+        .chain(new)
+        .map(|record: LogRecord| Event::default().json_data(record));
+    Sse::new(events)
+        .keep_alive(KeepAlive::default())
+        .into_response()
+}
+
+async fn log_filter() -> Result<Json<LogFilter>, (StatusCode, String)> {
+    crate::logging::filter()
+        .map(|filter| Json(LogFilter { filter }))
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))
+}
+
+async fn set_log_filter(Json(update): Json<LogFilter>) -> Result<(), (StatusCode, String)> {
+    crate::logging::set_filter(&update.filter).map_err(|e| (StatusCode::BAD_REQUEST, e.to_string()))
+}
+
+async fn proof_events<S>(
+    State(service): State<Arc<S>>,
+) -> Result<Json<Vec<crate::events::Record>>, (StatusCode, String)>
+where
+    S: Service + Sync + Send + 'static,
+{
+    service
+        .proof_events()
+        .map(Json)
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))
+}
+
+async fn proof_history<S>(
+    State(service): State<Arc<S>>,
+    Query(query): Query<HistoryQuery>,
+) -> Json<HistoryPage>
+where
+    S: Service + Sync + Send + 'static,
+{
+    let history = service.proof_history();
+    Json(HistoryPage {
+        total: history.len(),
+        entries: history
+            .into_iter()
+            .skip(query.offset)
+            .take(query.limit.min(HISTORY_SIZE))
+            .collect(),
+    })
+}
+
+async fn update_settings<S>(
+    State(service): State<Arc<S>>,
+    Json(update): Json<SettingsUpdate>,
+) -> Result<(), (StatusCode, String)>
+where
+    S: Service + Sync + Send + 'static,
+{
+    service
+        .update_settings(update)
+        .map_err(|e| (StatusCode::BAD_REQUEST, e.to_string()))
+}
+
 #[cfg(test)]
 mod tests {
-    use std::sync::Arc;
+    use std::{collections::BTreeMap, sync::Arc};
+
+    use tokio::io::{AsyncBufReadExt, AsyncWriteExt};
 
     #[tokio::test]
     async fn test_status() {
@@ -69,9 +514,18 @@ mod tests {
         svc.expect_status()
             .once()
             .returning(|| super::ServiceState::Idle);
-        let proving_status = super::ServiceState::Proving {
+        let pow_status = super::ServiceState::ComputingPow {
+            nonces: 0..64,
+            done: 1,
+            total: 4,
+        };
+        svc.expect_status().once().return_const(pow_status.clone());
+        let proving_status = super::ServiceState::ReadingData {
             nonces: 0..64,
             position: 1000,
+            total: 2000,
+            k2: 37,
+            indices_found: BTreeMap::from([(0, 12), (5, 30)]),
         };
         svc.expect_status()
             .once()
@@ -79,13 +533,371 @@ mod tests {
 
         let server = axum_test::TestServer::new(super::create_router(Arc::new(svc))).unwrap();
 
-        let resp = server.get("/status").await;
+        let resp = server.get("/v2/status").await;
         assert_eq!(
             super::ServiceState::Idle,
             resp.json::<super::ServiceState>(),
         );
 
-        let resp = server.get("/status").await;
+        let resp = server.get("/v2/status").await;
+        assert_eq!(pow_status, resp.json::<super::ServiceState>());
+
+        let resp = server.get("/v2/status").await;
         assert_eq!(proving_status, resp.json::<super::ServiceState>(),);
     }
+
+    #[tokio::test]
+    async fn test_status_v1() {
+        let mut svc = super::MockService::new();
+        svc.expect_status()
+            .once()
+            .return_const(super::ServiceState::ComputingPow {
+                nonces: 0..64,
+                done: 1,
+                total: 4,
+            });
+        svc.expect_status()
+            .once()
+            .return_const(super::ServiceState::ReadingData {
+                nonces: 0..64,
+                position: 1000,
+                total: 2000,
+                k2: 37,
+                indices_found: BTreeMap::from([(0, 12)]),
+            });
+        svc.expect_status()
+            .once()
+            .return_const(super::ServiceState::DoneProving);
+
+        let server = axum_test::TestServer::new(super::create_router(Arc::new(svc))).unwrap();
+
+        let resp = server.get("/status").await;
+        assert_eq!(
+            serde_json::json!({"Proving": {"nonces": {"start": 0, "end": 64}, "position": 0}}),
+            resp.json::<serde_json::Value>()
+        );
+        let resp = server.get("/status").await;
+        assert_eq!(
+            serde_json::json!({"Proving": {"nonces": {"start": 0, "end": 64}, "position": 1000}}),
+            resp.json::<serde_json::Value>()
+        );
+        let resp = server.get("/status").await;
+        assert_eq!(
+            super::ServiceStateV1::DoneProving,
+            resp.json::<super::ServiceStateV1>()
+        );
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_healthcheck() {
+        let mut svc = super::MockService::new();
+        svc.expect_status().returning(|| super::ServiceState::Idle);
+        let router = super::require_token(super::create_router(Arc::new(svc)), "secret".into());
+        let listener = tokio::net::TcpListener::bind("0.0.0.0:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, router).await });
+
+        let timeout = std::time::Duration::from_secs(5);
+        let check = move |token: Option<&'static str>| {
+            tokio::task::spawn_blocking(move || super::healthcheck(address, token, false, timeout))
+        };
+        check(Some("secret")).await.unwrap().unwrap();
+        let err = check(None).await.unwrap().unwrap_err();
+        assert!(err.to_string().contains("401"), "{err}");
+
+        let closed = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = closed.local_addr().unwrap();
+        drop(closed);
+        assert!(super::healthcheck(address, None, false, timeout).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_update_settings() {
+        let update = super::SettingsUpdate {
+            nonces: Some(64),
+            ..Default::default()
+        };
+        let mut svc = super::MockService::new();
+        svc.expect_update_settings()
+            .once()
+            .with(mockall::predicate::eq(update.clone()))
+            .returning(|_| Ok(()));
+        svc.expect_update_settings()
+            .once()
+            .returning(|_| Err(eyre::eyre!("invalid settings")));
+
+        let server = axum_test::TestServer::new(super::create_router(Arc::new(svc))).unwrap();
+
+        let resp = server.put("/settings").json(&update).await;
+        resp.assert_status_ok();
+
+        let resp = server.put("/settings").json(&update).await;
+        resp.assert_status_bad_request();
+        assert_eq!("invalid settings", resp.text());
+    }
+
+    #[tokio::test]
+    async fn test_last_proof() {
+        let last_proof = super::LastProof {
+            challenge: [0xCA; 32],
+            proof: post::prove::Proof {
+                nonce: 7,
+                indices: std::borrow::Cow::Owned(vec![1, 2, 3]),
+                pow: 1234,
+            },
+        };
+        let mut svc = super::MockService::new();
+        svc.expect_last_proof().once().return_const(None);
+        svc.expect_last_proof()
+            .once()
+            .return_const(Some(last_proof.clone()));
+
+        let server = axum_test::TestServer::new(super::create_router(Arc::new(svc))).unwrap();
+
+        let resp = server.get("/proof").await;
+        resp.assert_status_not_found();
+
+        let resp = server.get("/proof").await;
+        resp.assert_status_ok();
+        assert_eq!(last_proof, resp.json::<super::LastProof>());
+        assert_eq!(
+            serde_json::json!({
+                "challenge": "ca".repeat(32),
+                "nonce": 7,
+                "indices": "AQID",
+                "pow": 1234,
+            }),
+            resp.json::<serde_json::Value>()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_proving_plan() {
+        let plan = crate::planner::Plan {
+            target_probability: 0.99,
+            nonces: 64,
+            passes: 4,
+            probability: 0.99,
+            pass_probability: 0.75,
+            pass_duration: std::time::Duration::from_secs(600),
+            throughput: crate::planner::Throughput {
+                read: 1e9,
+                proving: 2e9,
+            },
+        };
+        let mut svc = super::MockService::new();
+        svc.expect_proving_plan().once().return_const(None);
+        svc.expect_proving_plan()
+            .once()
+            .return_const(Some(plan.clone()));
+
+        let server = axum_test::TestServer::new(super::create_router(Arc::new(svc))).unwrap();
+
+        let resp = server.get("/plan").await;
+        resp.assert_status_not_found();
+
+        let resp = server.get("/plan").await;
+        resp.assert_status_ok();
+        assert_eq!(plan, resp.json::<crate::planner::Plan>());
+    }
+
+    #[tokio::test]
+    async fn test_memory_usage() {
+        let usage = crate::memory::MemoryUsage {
+            randomx_dataset: 1824 * 1024 * 1024,
+            randomx_cache: 256 * 1024 * 1024,
+            proving_buffers: 16 * 1024 * 1024,
+            limit: Some(4096 * 1024 * 1024),
+        };
+        let mut svc = super::MockService::new();
+        svc.expect_memory_usage().once().return_const(usage);
+
+        let server = axum_test::TestServer::new(super::create_router(Arc::new(svc))).unwrap();
+
+        let resp = server.get("/memory").await;
+        resp.assert_status_ok();
+        assert_eq!(usage, resp.json::<crate::memory::MemoryUsage>());
+    }
+
+    #[tokio::test]
+    async fn test_worker_stats() {
+        let stats = vec![crate::workers::WorkerStats {
+            thread: 0,
+            core: Some(3),
+            batches: 10,
+            bytes: 10 * 1024 * 1024,
+            aes_bytes: 80 * 1024 * 1024,
+            busy: 0.5,
+        }];
+        let mut svc = super::MockService::new();
+        svc.expect_worker_stats().once().return_const(stats.clone());
+
+        let server = axum_test::TestServer::new(super::create_router(Arc::new(svc))).unwrap();
+
+        let resp = server.get("/workers").await;
+        resp.assert_status_ok();
+        assert_eq!(stats, resp.json::<Vec<crate::workers::WorkerStats>>());
+    }
+
+    #[tokio::test]
+    async fn test_logs() {
+        let logs = crate::logging::logs();
+        let record = |message: &str| crate::logging::LogRecord {
+            timestamp: 0,
+            level: log::Level::Warn,
+            target: "test_logs".into(),
+            message: message.into(),
+        };
+        logs.push(record("old"));
+        logs.push(record("recent"));
+
+        let router = super::create_router(Arc::new(super::MockService::new()));
+        let server = axum_test::TestServer::new(router.clone()).unwrap();
+        let resp = server.get("/logs").add_query_param("limit", 1).await;
+        resp.assert_status_ok();
+        assert_eq!(
+            vec![record("recent")],
+            resp.json::<Vec<crate::logging::LogRecord>>()
+        );
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, router).await });
+        let mut stream = tokio::net::TcpStream::connect(address).await.unwrap();
+        stream
+            .write_all(b"GET /logs?follow=true&limit=1 HTTP/1.1\r\nHost: test\r\n\r\n")
+            .await
+            .unwrap();
+        let mut lines = tokio::io::BufReader::new(stream).lines();
+        async fn next_record(
+            lines: &mut tokio::io::Lines<tokio::io::BufReader<tokio::net::TcpStream>>,
+        ) -> crate::logging::LogRecord {
+            loop {
+                let line = lines.next_line().await.unwrap().unwrap();
+                if let Some(data) = line.strip_prefix("data: ") {
+                    return serde_json::from_str(data).unwrap();
+                }
+            }
+        }
+        assert_eq!(record("recent"), next_record(&mut lines).await);
+        logs.push(record("new"));
+        let timeout = std::time::Duration::from_secs(5);
+        let new = tokio::time::timeout(timeout, next_record(&mut lines))
+            .await
+            .unwrap();
+        assert_eq!(record("new"), new);
+    }
+
+    #[tokio::test]
+    async fn test_proof_events() {
+        let records = vec![crate::events::Record {
+            timestamp: 1,
+            event: crate::events::Event::PassStarted { nonces: 0..16 },
+        }];
+        let mut svc = super::MockService::new();
+        svc.expect_proof_events()
+            .once()
+            .return_once(move || Ok(records));
+
+        let server = axum_test::TestServer::new(super::create_router(Arc::new(svc))).unwrap();
+
+        let resp = server.get("/proof/events").await;
+        resp.assert_status_ok();
+        assert_eq!(
+            serde_json::json!([{
+                "timestamp": 1,
+                "event": "pass_started",
+                "nonces": {"start": 0, "end": 16},
+            }]),
+            resp.json::<serde_json::Value>()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_proof_history() {
+        use crate::history::{ProofResult, ProofSummary};
+
+        let summary = |started| ProofSummary {
+            challenge: [0xCA; 32],
+            started,
+            duration: std::time::Duration::from_millis(1500),
+            passes: 1,
+            nonces: 16,
+            result: ProofResult::Found { nonce: 3, pow: 7 },
+        };
+        let history: Vec<_> = (0..30).rev().map(summary).collect();
+        let mut svc = super::MockService::new();
+        svc.expect_proof_history()
+            .times(3)
+            .returning(move || history.clone());
+
+        let server = axum_test::TestServer::new(super::create_router(Arc::new(svc))).unwrap();
+
+        // The first page by default
+        let resp = server.get("/history").await;
+        resp.assert_status_ok();
+        let page = resp.json::<super::HistoryPage>();
+        assert_eq!(30, page.total);
+        assert_eq!(
+            (10..30).rev().map(summary).collect::<Vec<_>>(),
+            page.entries
+        );
+
+        let resp = server
+            .get("/history")
+            .add_query_param("offset", 25)
+            .add_query_param("limit", 10)
+            .await;
+        resp.assert_status_ok();
+        let page = resp.json::<super::HistoryPage>();
+        assert_eq!(30, page.total);
+        assert_eq!((0..5).rev().map(summary).collect::<Vec<_>>(), page.entries);
+
+        let resp = server.get("/history").add_query_param("limit", 1).await;
+        assert_eq!(
+            serde_json::json!({
+                "total": 30,
+                "entries": [{
+                    "challenge": "ca".repeat(32),
+                    "started": 29,
+                    "duration": 1.5,
+                    "passes": 1,
+                    "nonces": 16,
+                    "result": "found",
+                    "nonce": 3,
+                    "pow": 7,
+                }],
+            }),
+            resp.json::<serde_json::Value>()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_authorization() {
+        let mut svc = super::MockService::new();
+        svc.expect_status()
+            .once()
+            .returning(|| super::ServiceState::Idle);
+
+        let router =
+            super::require_token(super::create_router(Arc::new(svc)), "secret".to_string());
+        let server = axum_test::TestServer::new(router).unwrap();
+
+        let resp = server.get("/status").await;
+        resp.assert_status_unauthorized();
+
+        let resp = server.get("/status").authorization_bearer("wrong").await;
+        resp.assert_status_unauthorized();
+
+        let resp = server.get("/status").authorization_bearer("secret").await;
+        resp.assert_status_ok();
+    }
+
+    #[test]
+    fn test_constant_time_eq() {
+        assert!(super::constant_time_eq(b"", b""));
+        assert!(super::constant_time_eq(b"abc", b"abc"));
+        assert!(!super::constant_time_eq(b"abc", b"abd"));
+        assert!(!super::constant_time_eq(b"abc", b"abcd"));
+    }
 }
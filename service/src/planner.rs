@@ -0,0 +1,385 @@
+//! Planning the number of nonces per pass over POS data (`--nonces=auto`).
+//!
+//! The chance to find a proof depends only on the total number of nonces tried,
+//! so the target probability sets how many nonces are needed. Trying more nonces
+//! in a pass takes more CPU, but saves passes (and so disk reads). The planner
+//! picks the number of nonces per pass that finds a proof the fastest, given
+//! the measured read and proving throughput of the machine.
+//!
+//! Measuring the read throughput takes a while (it reads a sample of the POS data),
+//! so it's kept in the state directory and measured again only when the POS data
+//! or the batch size change. Proving is measured every time, it's quick and depends
+//! on the cores used.
+
+use std::{
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use eyre::Context;
+use post::{
+    config::{Cores, ProofConfig},
+    metadata::PostMetadata,
+    pow,
+    prove::{self, Prover, Prover8_56, ProvingParams},
+    reader::BatchSize,
+};
+use serde::{Deserialize, Serialize};
+
+/// The number of nonces proven together (with a single AES).
+const NONCES_PER_GROUP: usize = 16;
+/// The maximum number of nonce groups in a pass.
+const MAX_GROUPS: usize = 256;
+/// The file (in the state directory) keeping the measured read throughput.
+const READ_THROUGHPUT_FILE_NAME: &str = "read_throughput.json";
+
+/// Measured throughput (in bytes per second) of the machine.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Throughput {
+    /// Reading POS data.
+    pub read: f64,
+    /// Proving POS data for a single nonce group (the time grows linearly with the number of groups).
+    pub proving: f64,
+}
+
+/// The chosen number of nonces per pass.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Plan {
+    /// The probability the plan was made for.
+    pub target_probability: f64,
+    /// The number of nonces to try in a pass.
+    pub nonces: usize,
+    /// The number of passes needed to reach the target probability.
+    pub passes: usize,
+    /// The chance to find a proof within the passes.
+    pub probability: f64,
+    /// The chance to find a proof in a single pass.
+    pub pass_probability: f64,
+    /// The estimated duration of a pass (without k2pow).
+    pub pass_duration: Duration,
+    /// The throughput the plan is based on.
+    pub throughput: Throughput,
+}
+
+/// Plan the nonces per pass to find a proof with (at least) the given probability
+/// in the shortest expected time.
+///
+/// A pass may find a proof, so the later passes are only needed sometimes. The expected
+/// time weights each pass by the chance that the passes before didn't find a proof.
+///
+/// The k2pow is calculated for every nonce group tried, so the number of nonces
+/// is never larger than needed for the target probability in a single pass.
+pub fn plan(cfg: &ProofConfig, probability: f64, data_size: u64, throughput: Throughput) -> Plan {
+    let nonce_chance = prove::nonce_chance(cfg, 1.0, 0);
+    let needed = match nonce_chance {
+        c if c >= 1.0 => 1,
+        c if c <= 0.0 => usize::MAX,
+        c => ((1.0 - probability).ln() / (1.0 - c).ln()).ceil().max(1.0) as usize,
+    };
+    let size = data_size as f64;
+    let read_secs = size / throughput.read;
+    let max_groups = needed.div_ceil(NONCES_PER_GROUP).clamp(1, MAX_GROUPS);
+    let pass_probability =
+        |groups: usize| 1.0 - (1.0 - nonce_chance).powf((groups * NONCES_PER_GROUP) as f64);
+    let (groups, passes, pass_secs) = (1..=max_groups)
+        .map(|groups| {
+            let passes = needed.div_ceil(groups * NONCES_PER_GROUP);
+            let pass_secs = read_secs.max(size * groups as f64 / throughput.proving);
+            let expected_secs = expected_passes(pass_probability(groups), passes) * pass_secs;
+            (groups, passes, pass_secs, expected_secs)
+        })
+        // Prefer fewer nonces (k2pows) when the expected durations are the same.
+        .min_by(|(.., a), (.., b)| a.total_cmp(b))
+        .map(|(groups, passes, pass_secs, _)| (groups, passes, pass_secs))
+        .unwrap();
+    let nonces = groups * NONCES_PER_GROUP;
+    let pass_probability = pass_probability(groups);
+    Plan {
+        target_probability: probability,
+        nonces,
+        passes,
+        probability: 1.0 - (1.0 - pass_probability).powf(passes as f64),
+        pass_probability,
+        pass_duration: Duration::try_from_secs_f64(pass_secs).unwrap_or(Duration::MAX),
+        throughput,
+    }
+}
+
+/// The expected number of passes made (at most `passes`), stopping at the first
+/// one finding a proof.
+fn expected_passes(pass_probability: f64, passes: usize) -> f64 {
+    if pass_probability <= 0.0 {
+        return passes as f64;
+    }
+    (1.0 - (1.0 - pass_probability).powf(passes as f64)) / pass_probability
+}
+
+/// The read throughput measured for the POS data.
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+struct ReadThroughput {
+    data_size: u64,
+    batch_size: BatchSize,
+    read: f64,
+}
+
+impl ReadThroughput {
+    fn path(state_dir: &Path) -> PathBuf {
+        state_dir.join(READ_THROUGHPUT_FILE_NAME)
+    }
+
+    fn load(state_dir: &Path) -> eyre::Result<Option<Self>> {
+        let path = Self::path(state_dir);
+        match std::fs::read(&path) {
+            Ok(bytes) => Ok(Some(
+                serde_json::from_slice(&bytes).wrap_err_with(|| format!("parsing {path:?}"))?,
+            )),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e).wrap_err_with(|| format!("reading {path:?}")),
+        }
+    }
+
+    fn save(&self, state_dir: &Path) -> eyre::Result<()> {
+        let path = Self::path(state_dir);
+        std::fs::write(&path, serde_json::to_vec(self)?)
+            .wrap_err_with(|| format!("writing {path:?}"))
+    }
+}
+
+/// Measure the throughput of reading (up to `sample` bytes of) the POS data
+/// and of proving on the given cores.
+///
+/// With a `state_dir`, the read throughput measured before for the same POS data
+/// is reused, and a new measurement is kept there.
+pub fn measure(
+    datadir: &Path,
+    metadata: &PostMetadata,
+    cfg: &ProofConfig,
+    cores: Cores,
+    options: &prove::ProvingOptions,
+    sample: u64,
+    state_dir: Option<&Path>,
+) -> eyre::Result<Throughput> {
+    let data_size = metadata.total_size();
+    let cached = state_dir
+        .and_then(|dir| {
+            ReadThroughput::load(dir)
+                .inspect_err(|e| log::warn!("failed to load the read throughput: {e:?}"))
+                .ok()
+                .flatten()
+        })
+        .filter(|cached| cached.data_size == data_size && cached.batch_size == options.batch_size);
+    let read = match cached {
+        Some(cached) => {
+            log::debug!("reusing the read throughput measured before");
+            cached.read
+        }
+        None => {
+            let source = prove::pos_data_source(datadir, metadata, options);
+            let read = measure_read(source, data_size.min(sample), options)?;
+            if let Some(dir) = state_dir {
+                let measured = ReadThroughput {
+                    data_size,
+                    batch_size: options.batch_size,
+                    read,
+                };
+                if let Err(e) = measured.save(dir) {
+                    log::warn!("failed to save the read throughput: {e:?}");
+                }
+            }
+            read
+        }
+    };
+    let proving = measure_proving(metadata, cfg, cores)?;
+    Ok(Throughput { read, proving })
+}
+
+fn measure_read(
+    source: Arc<dyn prove::PosDataSource>,
+    len: u64,
+    options: &prove::ProvingOptions,
+) -> eyre::Result<f64> {
+    let started = Instant::now();
+    let mut read = 0;
+    for batch in post::reader::read_batches(source, 0, len, options.batch_size, options.read_retry)
+    {
+        let batch = batch.wrap_err("reading POS data")?;
+        if let Some(limiter) = &options.read_rate_limit {
+            limiter.acquire(batch.data.len() as u64);
+        }
+        read += batch.data.len();
+    }
+    eyre::ensure!(read > 0, "no POS data to read");
+    Ok(read as f64 / started.elapsed().as_secs_f64())
+}
+
+/// Measure the throughput of proving a single nonce group on the given cores.
+pub fn measure_proving(
+    metadata: &PostMetadata,
+    cfg: &ProofConfig,
+    cores: Cores,
+) -> eyre::Result<f64> {
+    const BATCH: usize = 1024 * 1024;
+    const BATCHES_PER_THREAD: usize = 4;
+
+    let pool = prove::create_thread_pool(cores, |id| {
+        log::warn!("failed to set core affinity for thread to {id}")
+    })
+    .wrap_err("building thread pool")?;
+    let prover = Prover8_56::new(
+        &[0; 32],
+        0..NONCES_PER_GROUP as u32,
+        ProvingParams::new(metadata, cfg)?,
+        &pow::FixedPow(0),
+        &[0; 32],
+    )?;
+
+    let threads = pool.current_num_threads();
+    let data = vec![0xA5; BATCH];
+    let started = Instant::now();
+    pool.scope(|s| {
+        for _ in 0..threads {
+            s.spawn(|_| {
+                for _ in 0..BATCHES_PER_THREAD {
+                    prover.prove(&data, 0, |_, _| None);
+                }
+            });
+        }
+    });
+    let proven = threads * BATCHES_PER_THREAD * BATCH;
+    Ok(proven as f64 / started.elapsed().as_secs_f64())
+}
+
+#[cfg(test)]
+mod tests {
+    use post::{
+        config::ScryptParams,
+        initialize::{CpuInitializer, Initialize},
+    };
+
+    use super::*;
+
+    const GIB: f64 = 1024.0 * 1024.0 * 1024.0;
+
+    fn cfg() -> ProofConfig {
+        ProofConfig {
+            k1: 26,
+            k2: 37,
+            pow_difficulty: [0xFF; 32],
+        }
+    }
+
+    #[test]
+    fn planning_for_slow_cpu() {
+        // Proving a single nonce group is slower than reading
+        let throughput = Throughput {
+            read: GIB,
+            proving: GIB / 2.0,
+        };
+        let plan = plan(&cfg(), 0.99, 256 * GIB as u64, throughput);
+        assert_eq!(NONCES_PER_GROUP, plan.nonces);
+        assert!(plan.passes > 1);
+        assert!(plan.probability >= 0.99);
+        assert_eq!(Duration::from_secs(512), plan.pass_duration);
+    }
+
+    #[test]
+    fn planning_for_fast_cpu() {
+        // Proving all the nonces needed is faster than reading
+        let throughput = Throughput {
+            read: 0.1 * GIB,
+            proving: 100.0 * GIB,
+        };
+        let plan = plan(&cfg(), 0.99, 256 * GIB as u64, throughput);
+        assert_eq!(1, plan.passes);
+        assert!(plan.probability >= 0.99);
+        assert_eq!(plan.probability, plan.pass_probability);
+
+        // Needs more nonces for a higher probability
+        let safer = super::plan(&cfg(), 0.999, 256 * GIB as u64, throughput);
+        assert!(safer.nonces > plan.nonces);
+    }
+
+    #[test]
+    fn planning_for_balanced_machine() {
+        // Reading takes as long as proving 4 nonce groups
+        let throughput = Throughput {
+            read: GIB,
+            proving: 4.0 * GIB,
+        };
+        let plan = plan(&cfg(), 0.99, 256 * GIB as u64, throughput);
+        assert!(plan.nonces > NONCES_PER_GROUP);
+        assert!(plan.passes > 1);
+        assert!(plan.probability >= 0.99);
+    }
+
+    #[test]
+    fn measuring_throughput() {
+        let datadir = tempfile::tempdir().unwrap();
+        let metadata = CpuInitializer::new(ScryptParams::new(2, 1, 1))
+            .initialize(datadir.path(), &[1; 32], &[2; 32], 256, 2, 256, None)
+            .unwrap();
+        let throughput = measure(
+            datadir.path(),
+            &metadata,
+            &cfg(),
+            Cores::Any(1),
+            &Default::default(),
+            1024,
+            None,
+        )
+        .unwrap();
+        assert!(throughput.read > 0.0);
+        assert!(throughput.proving > 0.0);
+    }
+
+    #[test]
+    fn reusing_read_throughput() {
+        let datadir = tempfile::tempdir().unwrap();
+        let state_dir = tempfile::tempdir().unwrap();
+        let metadata = CpuInitializer::new(ScryptParams::new(2, 1, 1))
+            .initialize(datadir.path(), &[1; 32], &[2; 32], 256, 2, 256, None)
+            .unwrap();
+        let options = prove::ProvingOptions::default();
+        let measure = |metadata: &PostMetadata| {
+            measure(
+                datadir.path(),
+                metadata,
+                &cfg(),
+                Cores::Any(1),
+                &options,
+                1024,
+                Some(state_dir.path()),
+            )
+            .unwrap()
+        };
+
+        let measured = measure(&metadata);
+        let cached = ReadThroughput::load(state_dir.path()).unwrap().unwrap();
+        // Not always bit-exact after a round-trip through JSON
+        assert!((measured.read - cached.read).abs() <= measured.read * 1e-9);
+
+        // Reused for the same POS data
+        ReadThroughput {
+            read: 42.0,
+            ..cached
+        }
+        .save(state_dir.path())
+        .unwrap();
+        assert_eq!(42.0, measure(&metadata).read);
+
+        // Measured again when the POS data changes
+        let metadata = PostMetadata {
+            num_units: 1,
+            ..metadata
+        };
+        assert_ne!(42.0, measure(&metadata).read);
+    }
+
+    #[test]
+    fn expected_number_of_passes() {
+        assert_eq!(1.0, expected_passes(1.0, 4));
+        assert_eq!(4.0, expected_passes(0.0, 4));
+        assert_eq!(1.5, expected_passes(0.5, 2));
+    }
+}
@@ -0,0 +1,167 @@
+//! Time windows in which proof generation is allowed to run.
+//!
+//! A window is given as `HH:MM-HH:MM` in UTC. A window ending before it starts
+//! spans midnight, e.g. `22:00-06:00`. Outside of all windows a running
+//! proof generation is paused and resumes when the next window opens.
+
+use std::{
+    fmt,
+    str::FromStr,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+const MINUTES_PER_DAY: u32 = 24 * 60;
+
+#[derive(Debug, thiserror::Error, PartialEq, Eq)]
+pub enum ParseError {
+    #[error("invalid window '{0}' (expected HH:MM-HH:MM)")]
+    InvalidFormat(String),
+    #[error("invalid time '{0}' (expected HH:MM)")]
+    InvalidTime(String),
+}
+
+/// A daily time window, in minutes since midnight (UTC).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde_with::SerializeDisplay)]
+pub struct Window {
+    start: u32,
+    end: u32,
+}
+
+impl Window {
+    fn contains(&self, minute: u32) -> bool {
+        if self.start <= self.end {
+            (self.start..self.end).contains(&minute)
+        } else {
+            minute >= self.start || minute < self.end
+        }
+    }
+}
+
+fn parse_time(s: &str) -> Result<u32, ParseError> {
+    let err = || ParseError::InvalidTime(s.to_string());
+    let (h, m) = s.trim().split_once(':').ok_or_else(err)?;
+    let h: u32 = h.parse().map_err(|_| err())?;
+    let m: u32 = m.parse().map_err(|_| err())?;
+    if h > 24 || m > 59 || (h == 24 && m != 0) {
+        return Err(err());
+    }
+    Ok(h * 60 + m)
+}
+
+impl fmt::Display for Window {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{:02}:{:02}-{:02}:{:02}",
+            self.start / 60,
+            self.start % 60,
+            self.end / 60,
+            self.end % 60
+        )
+    }
+}
+
+impl FromStr for Window {
+    type Err = ParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (start, end) = s
+            .split_once('-')
+            .ok_or_else(|| ParseError::InvalidFormat(s.to_string()))?;
+        Ok(Self {
+            start: parse_time(start)?,
+            end: parse_time(end)?,
+        })
+    }
+}
+
+/// The proving windows. An empty schedule allows proving at any time.
+#[derive(Debug, Clone, Default, PartialEq, Eq, serde::Serialize)]
+pub struct Schedule {
+    windows: Vec<Window>,
+}
+
+impl Schedule {
+    pub fn new(windows: Vec<Window>) -> Self {
+        Self { windows }
+    }
+
+    /// Is proving allowed at the given time.
+    pub fn allows(&self, time: SystemTime) -> bool {
+        if self.windows.is_empty() {
+            return true;
+        }
+        let secs = time
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        let minute = (secs / 60 % MINUTES_PER_DAY as u64) as u32;
+        self.windows.iter().any(|w| w.contains(minute))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    fn at(h: u64, m: u64) -> SystemTime {
+        // Some day, not the epoch day.
+        UNIX_EPOCH + Duration::from_secs(20_000 * 86400 + h * 3600 + m * 60)
+    }
+
+    #[test]
+    fn parsing_windows() {
+        assert_eq!(
+            Window {
+                start: 60,
+                end: 23 * 60 + 30
+            },
+            "01:00-23:30".parse().unwrap()
+        );
+        assert_eq!(
+            Window {
+                start: 0,
+                end: MINUTES_PER_DAY
+            },
+            "0:00 - 24:00".parse().unwrap()
+        );
+        assert_eq!(
+            "22:00-06:05",
+            "22:00-6:05".parse::<Window>().unwrap().to_string()
+        );
+        assert_eq!(
+            Err(ParseError::InvalidFormat("01:00".into())),
+            "01:00".parse::<Window>()
+        );
+        assert_eq!(
+            Err(ParseError::InvalidTime("25:00".into())),
+            "01:00-25:00".parse::<Window>()
+        );
+        assert_eq!(
+            Err(ParseError::InvalidTime("1:60".into())),
+            "1:60-2:00".parse::<Window>()
+        );
+    }
+
+    #[test]
+    fn empty_schedule_allows_always() {
+        assert!(Schedule::default().allows(at(12, 0)));
+    }
+
+    #[test]
+    fn schedule_windows() {
+        let schedule = Schedule::new(vec![
+            "22:00-06:00".parse().unwrap(),
+            "12:00-13:00".parse().unwrap(),
+        ]);
+        assert!(schedule.allows(at(23, 0)));
+        assert!(schedule.allows(at(0, 0)));
+        assert!(schedule.allows(at(5, 59)));
+        assert!(!schedule.allows(at(6, 0)));
+        assert!(schedule.allows(at(12, 30)));
+        assert!(!schedule.allows(at(13, 0)));
+        assert!(!schedule.allows(at(21, 59)));
+    }
+}
@@ -0,0 +1,245 @@
+//! One-shot diagnostic of the setup of the service (`post-service --self-test`).
+//!
+//! The checks are independent of each other, a failed one doesn't stop the others.
+//! Nothing is registered with the nodes and no proof is generated from the POS data.
+
+use std::{
+    collections::HashMap,
+    fmt,
+    path::{Path, PathBuf},
+    time::{Duration, Instant},
+};
+
+use eyre::{Context, OptionExt};
+use post::{
+    config::{InitConfig, ProofConfig, ScryptParams},
+    initialize::{calc_commitment, CpuInitializer, Initialize},
+    metadata::{PostMetadata, ProofMetadata},
+    pow::{self, randomx::RandomXFlag},
+    prove::{Proof, Prover, Prover8_56, ProvingParams},
+    verification::{Mode, Verifier},
+};
+
+use crate::client::{self, TlsFiles};
+
+pub struct Config {
+    pub datadir: PathBuf,
+    pub scrypt: ScryptParams,
+    /// Percentage of labels to verify in every POS data file.
+    pub sample_fraction: f64,
+    pub pow_flags: RandomXFlag,
+    /// Addresses of the nodes to connect to.
+    pub addresses: Vec<String>,
+    pub tls: Option<TlsFiles>,
+    pub connect_timeout: Duration,
+}
+
+#[derive(Debug)]
+pub struct Check {
+    pub name: String,
+    /// Details of a passed check or the reason it failed.
+    pub result: eyre::Result<String>,
+}
+
+/// Pass/fail summary of the checks.
+#[derive(Debug, Default)]
+pub struct Report {
+    pub checks: Vec<Check>,
+}
+
+impl Report {
+    pub fn add(&mut self, name: impl Into<String>, result: eyre::Result<String>) {
+        let name = name.into();
+        match &result {
+            Ok(details) => log::info!("{name}: passed ({details})"),
+            Err(e) => log::error!("{name}: failed: {e:?}"),
+        }
+        self.checks.push(Check { name, result });
+    }
+
+    pub fn passed(&self) -> bool {
+        self.checks.iter().all(|c| c.result.is_ok())
+    }
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for check in &self.checks {
+            match &check.result {
+                Ok(details) => writeln!(f, "PASS {}: {details}", check.name)?,
+                Err(e) => writeln!(f, "FAIL {}: {e:#}", check.name)?,
+            }
+        }
+        let passed = self.checks.iter().filter(|c| c.result.is_ok()).count();
+        write!(f, "{passed}/{} checks passed", self.checks.len())
+    }
+}
+
+/// Run all checks.
+pub async fn run(cfg: &Config) -> Report {
+    let mut report = Report::default();
+    report.add("metadata", check_metadata(&cfg.datadir));
+    report.add(
+        "labels",
+        check_labels(&cfg.datadir, cfg.sample_fraction, cfg.scrypt),
+    );
+    report.add("proving", check_proving());
+    report.add("RandomX", check_randomx(cfg.pow_flags));
+    for address in &cfg.addresses {
+        report.add(
+            format!("node {address}"),
+            client::check_connection(address, cfg.tls.as_ref(), cfg.connect_timeout)
+                .await
+                .map(|()| "reachable".to_string()),
+        );
+    }
+    report
+}
+
+/// Load the metadata and check that the POS data matches it.
+pub fn check_metadata(datadir: &Path) -> eyre::Result<String> {
+    let metadata = post::metadata::load(datadir)?;
+    post::pos_verification::check_data_size(datadir)?;
+    Ok(format!(
+        "{} units of {} labels, {} labels initialized",
+        metadata.num_units,
+        metadata.labels_per_unit,
+        metadata.initialized_labels()
+    ))
+}
+
+/// Verify a random sample (`fraction` %) of labels in every POS data file.
+pub fn check_labels(datadir: &Path, fraction: f64, scrypt: ScryptParams) -> eyre::Result<String> {
+    if post::block_device::is_block_data(datadir) {
+        return Ok("skipped, POS data is on a block device".to_string());
+    }
+    let reports = post::pos_verification::verify_sample(datadir, fraction, scrypt)?;
+    let checked: usize = reports.iter().map(|r| r.labels_checked).sum();
+    let invalid: Vec<u64> = reports.into_iter().flat_map(|r| r.invalid_labels).collect();
+    eyre::ensure!(
+        invalid.is_empty(),
+        "{} of {checked} checked labels are invalid, the first one: {}",
+        invalid.len(),
+        invalid[0]
+    );
+    Ok(format!("{checked} labels checked"))
+}
+
+/// Generate a proof for a few labels initialized in memory (with mocked PoW) and verify it.
+pub fn check_proving() -> eyre::Result<String> {
+    const LABELS: u64 = 1024;
+    let metadata = PostMetadata {
+        node_id: [1; 32],
+        commitment_atx_id: [2; 32],
+        labels_per_unit: LABELS,
+        num_units: 1,
+        max_file_size: LABELS * 16,
+        nonce: None,
+        last_position: None,
+    };
+    // A quarter of the labels are candidates for a proof, enough to find one with any nonce.
+    let cfg = ProofConfig {
+        k1: LABELS as u32 / 4,
+        k2: 16,
+        pow_difficulty: [0xFF; 32],
+    };
+    let scrypt = ScryptParams::new(2, 1, 1);
+    let challenge = [3; 32];
+
+    let started = Instant::now();
+    let mut labels = Vec::new();
+    let commitment = calc_commitment(&metadata.node_id, &metadata.commitment_atx_id);
+    CpuInitializer::new(scrypt).initialize_to(&mut labels, &commitment, 0..LABELS, None)?;
+
+    // The PoW is checked separately (see check_randomx).
+    let pow = pow::FixedPow(0);
+    let prover = Prover8_56::new(
+        &challenge,
+        0..16,
+        ProvingParams::new(&metadata, &cfg)?,
+        &pow,
+        &metadata.node_id,
+    )?;
+    let mut candidates = HashMap::<u32, Vec<u64>>::new();
+    let (nonce, indices) = prover
+        .prove(&labels, 0, |nonce, index| {
+            let indices = candidates.entry(nonce).or_default();
+            indices.push(index);
+            (indices.len() >= cfg.k2 as usize).then(|| std::mem::take(indices))
+        })
+        .ok_or_eyre("no proof found")?;
+    let proof = Proof::new(nonce, &indices, LABELS, pow.0);
+    let init_cfg = InitConfig {
+        min_num_units: 1,
+        max_num_units: 1,
+        labels_per_unit: LABELS,
+        scrypt,
+    };
+    Verifier::new(Box::new(pow))
+        .verify(
+            &proof,
+            &ProofMetadata::new(metadata, challenge),
+            &cfg,
+            &init_cfg,
+            Mode::All,
+        )
+        .wrap_err("verifying the proof")?;
+    Ok(format!(
+        "generated and verified a proof in {:.2?}",
+        started.elapsed()
+    ))
+}
+
+/// Initialize RandomX with the flags used for proving.
+pub fn check_randomx(flags: RandomXFlag) -> eyre::Result<String> {
+    let started = Instant::now();
+    pow::randomx::PoW::new(flags)?;
+    Ok(format!(
+        "initialized with {flags:?} in {:.2?}",
+        started.elapsed()
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn checking_pos_data() {
+        let datadir = tempfile::tempdir().unwrap();
+        let scrypt = ScryptParams::new(2, 1, 1);
+        assert!(check_metadata(datadir.path()).is_err());
+
+        CpuInitializer::new(scrypt)
+            .initialize(datadir.path(), &[1; 32], &[2; 32], 256, 2, 200, None)
+            .unwrap();
+        check_metadata(datadir.path()).unwrap();
+        check_labels(datadir.path(), 100.0, scrypt).unwrap();
+
+        // Damage a label
+        let path = datadir.path().join("postdata_1.bin");
+        let mut data = std::fs::read(&path).unwrap();
+        data[0] ^= 0xFF;
+        std::fs::write(&path, data).unwrap();
+        check_metadata(datadir.path()).unwrap();
+        assert!(check_labels(datadir.path(), 100.0, scrypt).is_err());
+    }
+
+    #[test]
+    fn checking_proving() {
+        check_proving().unwrap();
+    }
+
+    #[test]
+    fn report_summary() {
+        let mut report = Report::default();
+        report.add("passing", Ok("details".to_string()));
+        assert!(report.passed());
+        report.add("failing", Err(eyre::eyre!("reason")));
+        assert!(!report.passed());
+        assert_eq!(
+            "PASS passing: details\nFAIL failing: reason\n1/2 checks passed",
+            report.to_string()
+        );
+    }
+}
@@ -1,21 +1,121 @@
 //! Post Service
 
 use std::{
+    collections::{BTreeMap, HashMap},
+    num::NonZeroU64,
     ops::{Range, RangeInclusive},
-    path::PathBuf,
+    path::{Path, PathBuf},
     sync::{atomic::AtomicBool, Arc, Mutex},
-    time::Duration,
+    time::{Duration, Instant, SystemTime},
 };
 
 use eyre::Context;
 use post::{
     metadata::{PostMetadata, ProofMetadata},
-    pow::randomx::{PoW, RandomXFlag},
+    pos_verification::DataSnapshot,
+    pow::randomx::RandomXFlag,
     prove::{self, Proof},
+    topology::PinningIssue,
     verification::{Mode, Verifier},
 };
 
-use crate::operator::ServiceState;
+use crate::{
+    events::{Event, EventLog},
+    history::{History, ProofSummary},
+    memory::{MemorySettings, MemoryUsage},
+    operator::{LastProof, ServiceState, SettingsUpdate},
+    planner::Plan,
+    schedule::Schedule,
+    workers::{WorkerStats, Workers},
+};
+
+/// A challenge rejected before starting the proof generation.
+#[derive(Debug, thiserror::Error)]
+pub enum ChallengeError {
+    #[error("invalid challenge length: {0} (expected 32)")]
+    InvalidLength(usize),
+    /// The service is busy with another challenge (i.e. of another node).
+    #[error(
+        "proof generation is in progress for a different challenge (current: {}, requested: {})",
+        hex::encode_upper(.current),
+        hex::encode_upper(.requested)
+    )]
+    InProgress {
+        current: [u8; 32],
+        requested: Vec<u8>,
+    },
+    /// The proof of another challenge waits for the node that requested it.
+    #[error(
+        "proof for a different challenge is waiting to be fetched (current: {}, requested: {})",
+        hex::encode_upper(.current),
+        hex::encode_upper(.requested)
+    )]
+    NotFetched {
+        current: [u8; 32],
+        requested: Vec<u8>,
+    },
+}
+
+/// The last verified proof, persisted in the state directory
+/// so that it survives restarts of the service.
+#[serde_with::serde_as]
+#[derive(Clone, Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+struct CachedProof {
+    #[serde_as(as = "serde_with::hex::Hex")]
+    challenge: [u8; 32],
+    proof: Proof<'static>,
+    /// The metadata of the POS data proven, not set in caches of older versions
+    /// (which are never reused).
+    #[serde(default)]
+    metadata: Option<PostMetadata>,
+    /// The POS data proven, not set for POS data read from another source
+    /// (see [PostService::with_data_source]).
+    #[serde(default)]
+    data: Option<DataSnapshot>,
+}
+
+const PROOF_CACHE_FILE_NAME: &str = "proof_cache.json";
+/// Lock file of the state directory, held by the service using it.
+pub const STATE_LOCK_FILE_NAME: &str = "post_service.lock";
+/// Outstanding jobs of the remote k2pow service, resumed after a restart.
+const K2POW_JOBS_FILE_NAME: &str = "k2pow_jobs.json";
+/// How much POS data is read to measure the read throughput for planning the nonces.
+const PLANNING_SAMPLE: u64 = 256 * 1024 * 1024;
+
+impl CachedProof {
+    fn load(state_dir: &Path) -> eyre::Result<Option<Self>> {
+        let path = state_dir.join(PROOF_CACHE_FILE_NAME);
+        match std::fs::read(&path) {
+            Ok(data) => Ok(Some(serde_json::from_slice(&data)?)),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Whether the proof can be returned for the `challenge`: it's for the same POS data
+    /// (the metadata and the files) as now.
+    fn is_for(&self, challenge: &[u8; 32], metadata: &PostMetadata, datadir: &Path) -> bool {
+        if self.challenge != *challenge || self.metadata.as_ref() != Some(metadata) {
+            return false;
+        }
+        match &self.data {
+            Some(snapshot) => snapshot
+                .check(datadir)
+                .inspect_err(|e| log::info!("not reusing the cached proof: {e}"))
+                .is_ok(),
+            None => true,
+        }
+    }
+
+    fn save(&self, state_dir: &Path) -> eyre::Result<()> {
+        // Write to a temporary file first to not leave a partially written cache behind.
+        let path = state_dir.join(PROOF_CACHE_FILE_NAME);
+        let tmp_path = path.with_extension("json.tmp");
+        std::fs::write(&tmp_path, serde_json::to_vec(self)?)?;
+        std::fs::rename(&tmp_path, &path)?;
+        Ok(())
+    }
+}
 
 #[derive(Debug)]
 pub enum ProofGenState {
@@ -32,13 +132,17 @@ enum ProofGenProcess {
         progress: ProvingProgress,
     },
     Done {
+        challenge: [u8; 32],
         proof: eyre::Result<Proof<'static>>,
     },
 }
 
 impl ProofGenProcess {
     fn check_finished(&mut self) {
-        if let ProofGenProcess::Running { handle, .. } = self {
+        if let ProofGenProcess::Running {
+            handle, challenge, ..
+        } = self
+        {
             if handle.as_ref().unwrap().is_finished() {
                 let proof = match handle.take().unwrap().join() {
                     Ok(result) => result,
@@ -46,35 +150,63 @@ impl ProofGenProcess {
                         std::panic::resume_unwind(err);
                     }
                 };
-                *self = ProofGenProcess::Done { proof };
+                *self = ProofGenProcess::Done {
+                    challenge: *challenge,
+                    proof,
+                };
             }
         }
     }
 }
 
-#[derive(Clone, Debug, Default)]
+#[derive(Clone, Debug)]
 struct ProvingProgress {
     inner: Arc<Mutex<ProvingProgressInner>>,
+    events: Arc<EventLog>,
+    workers: Arc<Mutex<Workers>>,
 }
 
 #[derive(Clone, Debug)]
 struct ProvingProgressInner {
     // currently processed nonces
     nonces: std::ops::Range<u32>,
+    // nonce groups of the current pass with the k2pow already found
+    k2pows: u32,
+    // when reading the data started (after finding the k2pows of the pass)
+    reading: Option<Instant>,
     // already finished chunks of data
     // the chunks are automatically merged when possible
     chunks: range_set::RangeSet<[RangeInclusive<u64>; 20]>,
+    // number of indices found so far in the pass for each nonce
+    indices_found: BTreeMap<u32, usize>,
 }
 
 impl Default for ProvingProgressInner {
     fn default() -> Self {
         Self {
             nonces: 0..0,
+            k2pows: 0,
+            reading: None,
             chunks: range_set::RangeSet::new(),
+            indices_found: BTreeMap::new(),
         }
     }
 }
 
+/// The phase of the current data pass.
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum PassPhase {
+    ComputingPow {
+        done: u32,
+        total: u32,
+    },
+    ReadingData {
+        position: u64,
+        elapsed: Duration,
+        indices_found: BTreeMap<u32, usize>,
+    },
+}
+
 impl prove::ProgressReporter for ProvingProgress {
     fn finished_chunk(&self, pos: u64, len: usize) {
         if len == 0 {
@@ -87,41 +219,255 @@ impl prove::ProgressReporter for ProvingProgress {
 
     fn new_nonce_group(&self, nonces: std::ops::Range<u32>) {
         let mut progress = self.inner.lock().unwrap();
-        progress.nonces = nonces;
+        log::info!(
+            "computing k2pow of {} nonce groups for nonces {nonces:?}",
+            prove::nonce_groups(nonces.clone()).len()
+        );
+        progress.nonces = nonces.clone();
+        progress.k2pows = 0;
+        progress.reading = None;
         progress.chunks.clear();
+        progress.indices_found.clear();
+        self.events.record(Event::PassStarted { nonces });
+    }
+
+    fn finished_k2pow(&self, nonce_group: u32, _pow: u64) {
+        let mut progress = self.inner.lock().unwrap();
+        // In the pipelined mode, the k2pows of the next passes are found in the background.
+        if progress.reading.is_none()
+            && prove::nonce_groups(progress.nonces.clone()).contains(&nonce_group)
+        {
+            progress.k2pows += 1;
+        }
+    }
+
+    fn started_data_pass(&self, nonces: Range<u32>) {
+        log::info!("k2pow finished, reading POS data for nonces {nonces:?}");
+        self.inner.lock().unwrap().reading = Some(Instant::now());
+    }
+
+    fn found_index(&self, nonce: u32, indices_found: usize) {
+        self.inner
+            .lock()
+            .unwrap()
+            .indices_found
+            .insert(nonce, indices_found);
+    }
+
+    fn finished_batch(&self, worker: usize, len: usize, duration: Duration) {
+        let nonce_groups = prove::nonce_groups(self.inner.lock().unwrap().nonces.clone()).len();
+        self.workers
+            .lock()
+            .unwrap()
+            .record(worker, len, nonce_groups, duration);
+    }
+
+    fn finished_pass(&self, nonces: Range<u32>, indices_found: &HashMap<u32, usize>) {
+        for slow in self.workers.lock().unwrap().slow() {
+            log::warn!(
+                "proving thread {} (core: {:?}) is slow: {:.1} MiB/s",
+                slow.thread,
+                slow.core,
+                slow.throughput() / (1024.0 * 1024.0),
+            );
+        }
+        self.events.record(Event::PassFinished {
+            nonces,
+            indices_found: indices_found.iter().map(|(&n, &c)| (n, c)).collect(),
+            duration: self.events.pass_duration(),
+        });
+    }
+}
+
+/// Records the k2pow of each nonce group in the event log.
+struct RecordingPowProver {
+    inner: Box<dyn post::pow::Prover + Send + Sync>,
+    events: Arc<EventLog>,
+}
+
+impl post::pow::Prover for RecordingPowProver {
+    fn prove(
+        &self,
+        nonce_group: u8,
+        challenge: &[u8; 8],
+        difficulty: &[u8; 32],
+        miner_id: &[u8; 32],
+    ) -> Result<u64, post::pow::Error> {
+        let started = Instant::now();
+        let pow = self
+            .inner
+            .prove(nonce_group, challenge, difficulty, miner_id)?;
+        self.events.record(Event::K2pow {
+            nonce_group: nonce_group as u32,
+            pow,
+            duration: started.elapsed(),
+        });
+        Ok(pow)
+    }
+
+    fn prove_many(
+        &self,
+        nonce_groups: Range<u32>,
+        challenge: &[u8; 8],
+        difficulty: &[u8; 32],
+        miner_id: &[u8; 32],
+    ) -> Result<Vec<(u32, u64)>, post::pow::Error> {
+        let started = Instant::now();
+        let pows = self
+            .inner
+            .prove_many(nonce_groups, challenge, difficulty, miner_id)?;
+        // The nonce groups are proven together, record the duration of all of them.
+        let duration = started.elapsed();
+        for &(nonce_group, pow) in &pows {
+            self.events.record(Event::K2pow {
+                nonce_group,
+                pow,
+                duration,
+            });
+        }
+        Ok(pows)
+    }
+
+    fn par(&self) -> bool {
+        self.inner.par()
     }
 }
 
 impl ProvingProgress {
-    fn get(&self) -> (Range<u32>, u64) {
+    fn new(events: Arc<EventLog>, workers: Arc<Mutex<Workers>>) -> Self {
+        Self {
+            inner: Default::default(),
+            events,
+            workers,
+        }
+    }
+
+    fn get(&self) -> (Range<u32>, PassPhase) {
         let progress = self.inner.lock().unwrap();
-        (
-            progress.nonces.clone(),
-            progress.chunks.as_ref().first().map_or(0, |r| *r.end() + 1),
-        )
+        let phase = match progress.reading {
+            Some(started) => PassPhase::ReadingData {
+                position: progress.chunks.as_ref().first().map_or(0, |r| *r.end() + 1),
+                elapsed: started.elapsed(),
+                indices_found: progress.indices_found.clone(),
+            },
+            None => PassPhase::ComputingPow {
+                done: progress.k2pows,
+                total: prove::nonce_groups(progress.nonces.clone()).len() as u32,
+            },
+        };
+        (progress.nonces.clone(), phase)
     }
 }
 
 pub struct K2powConfig {
     pub url: String,
+    /// Max remote k2pow jobs in flight.
     pub parallelism: usize,
+    /// Max remote k2pow jobs in flight of a single identity, so that it can't take all the slots.
+    pub identity_parallelism: usize,
     pub backoff: Duration,
 }
 
+/// How the service verifies the proofs it generates before handing them to the node.
+///
+/// Verifying all labels of a proof recomputes `k2` labels, which takes a while
+/// with big scrypt parameters. Verifying less answers the node sooner, but an invalid
+/// proof (i.e. generated from POS data damaged by faulty hardware) may reach the node,
+/// which rejects it, instead of being caught here.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, serde::Serialize)]
+pub enum SelfVerification {
+    /// Verify all labels of the proof.
+    #[default]
+    All,
+    /// Verify a random subset of `k3` labels of the proof.
+    Subset { k3: usize },
+    /// Don't verify the proof.
+    Skip,
+}
+
+/// Check the cores to pin the proving threads to against the topology of the machine:
+/// fail on offline cores, warn on hyper-thread siblings and duplicates.
+pub fn check_pinned_cores(cores: &[usize]) -> eyre::Result<()> {
+    let Some(topology) = post::topology::Topology::detect() else {
+        log::warn!("can't list the cores of the machine, not checking the pinned cores");
+        return Ok(());
+    };
+    for issue in topology.validate(cores) {
+        match issue {
+            PinningIssue::Unavailable(_) => eyre::bail!("invalid pinned cores: {issue}"),
+            PinningIssue::Duplicate(_) | PinningIssue::Siblings(..) => {
+                log::warn!("pinned cores: {issue}, the threads share a physical core")
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Proving settings that can be changed while the service is running.
+#[derive(Clone, Debug)]
+struct ProvingSettings {
+    nonces: usize,
+    threads: post::config::Cores,
+    // how the nonces were chosen (if planned)
+    plan: Option<Plan>,
+}
+
 pub struct PostService {
     datadir: PathBuf,
+    // Where the service keeps its own files (see [Self::with_state_dir]).
+    state_dir: PathBuf,
     metadata: post::metadata::PostMetadata,
     cfg: post::config::ProofConfig,
     scrypt: post::config::ScryptParams,
-    nonces: usize,
-    threads: post::config::Cores,
+    settings: Mutex<ProvingSettings>,
     pow_flags: RandomXFlag,
+    pow_version: post::pow::Version,
+    randomx_cache_key: Vec<u8>,
+    proving_options: prove::ProvingOptions,
+    self_verification: SelfVerification,
+    schedule: Schedule,
     proof_generation: Mutex<ProofGenProcess>,
+    // The last proof fetched by the node.
+    cached_proof: Mutex<Option<CachedProof>>,
+    // Where to write the verified proofs with their metadata.
+    proof_dir: Option<PathBuf>,
+    // Summaries of the completed proof generations.
+    history: Arc<History>,
+    // Statistics of the proving threads of the last proof generation.
+    workers: Arc<Mutex<Workers>>,
     remote_k2pow_config: Option<K2powConfig>,
+    // The limits of the remote k2pow jobs in flight, shared by all proof generations.
+    k2pow_limits: Option<Arc<post::pow::service::K2powLimits>>,
+    // The soft limit of the memory used by proof generation (see [crate::memory]).
+    memory_limit: Option<u64>,
+    // Prove also on the efficiency cores of hybrid CPUs.
+    efficiency_cores: bool,
+    // Held while the service runs, so that the POS data is not wiped or initialized under it.
+    // Shared with the readers of the POS data (i.e. `initializer verify-data`).
+    _lock: post::lock::DataDirLock,
+    // Keeps other services off the state directory. None only while switching it.
+    state_lock: Option<post::lock::DataDirLock>,
 
     stop: Arc<AtomicBool>,
 }
 
+/// Lock the state directory, creating it if needed,
+/// and load the cached proof and the proof history from it.
+fn load_state(
+    state_dir: &Path,
+) -> eyre::Result<(post::lock::DataDirLock, Option<CachedProof>, History)> {
+    if let Err(e) = std::fs::create_dir_all(state_dir) {
+        log::warn!("failed to create {}: {e}", state_dir.display());
+    }
+    let lock = post::lock::DataDirLock::acquire_named(state_dir, STATE_LOCK_FILE_NAME)
+        .wrap_err("locking the state directory")?;
+    let cached_proof = CachedProof::load(state_dir).unwrap_or_else(|e| {
+        log::warn!("failed to load cached proof: {e:?}");
+        None
+    });
+    Ok((lock, cached_proof, History::load(state_dir)))
+}
+
 impl PostService {
     pub fn new(
         datadir: PathBuf,
@@ -132,86 +478,524 @@ impl PostService {
         pow_flags: RandomXFlag,
         remote_k2pow_config: Option<K2powConfig>,
     ) -> eyre::Result<Self> {
+        // Locked before loading the metadata, so that it can't change under the service.
+        let lock = post::lock::DataDirLock::acquire_shared(&datadir)?;
+        // Partially initialized POS data is proven only with `with_partial_data`.
+        let metadata = post::metadata::load(&datadir).wrap_err("loading POST metadata")?;
+        let state_dir = post::block_device::state_dir(&datadir);
+        let (state_lock, cached_proof, history) = load_state(&state_dir)?;
         Ok(Self {
-            metadata: post::metadata::load(&datadir).wrap_err("loading POST metadata")?,
+            metadata,
             datadir,
+            state_dir,
             cfg,
             scrypt,
-            nonces,
-            threads,
+            settings: Mutex::new(ProvingSettings {
+                nonces,
+                threads,
+                plan: None,
+            }),
             pow_flags,
+            pow_version: post::pow::Version::default(),
+            randomx_cache_key: post::pow::randomx::DEFAULT_CACHE_KEY.to_vec(),
+            proving_options: prove::ProvingOptions {
+                k2pow_cache: true,
+                ..Default::default()
+            },
+            self_verification: SelfVerification::default(),
+            schedule: Schedule::default(),
             proof_generation: Mutex::new(ProofGenProcess::Idle),
+            cached_proof: Mutex::new(cached_proof),
+            proof_dir: None,
+            history: Arc::new(history),
+            workers: Default::default(),
+            k2pow_limits: remote_k2pow_config.as_ref().map(|cfg| {
+                post::pow::service::K2powLimits::new(cfg.parallelism, cfg.identity_parallelism)
+            }),
             remote_k2pow_config,
+            memory_limit: None,
+            efficiency_cores: false,
+            _lock: lock,
+            state_lock: Some(state_lock),
 
             stop: Arc::new(AtomicBool::new(false)),
         })
     }
+
+    /// Use the given PoW algorithm (RandomX by default).
+    pub fn with_pow_version(mut self, version: post::pow::Version) -> Self {
+        self.pow_version = version;
+        self
+    }
+
+    /// Use the RandomX cache key of a network (see [post::pow::randomx::cache_key]).
+    pub fn with_randomx_cache_key(mut self, cache_key: Vec<u8>) -> Self {
+        self.randomx_cache_key = cache_key;
+        self
+    }
+
+    /// Keep the service's own files (the cached proof, the proof history, the events
+    /// and the k2pows) in `dir` rather than along the POS data
+    /// (see [post::block_device::state_dir]).
+    ///
+    /// Fails if another service uses the directory.
+    pub fn with_state_dir(mut self, dir: PathBuf) -> eyre::Result<Self> {
+        // Released first, the directory might be the default one.
+        self.state_lock = None;
+        let (state_lock, cached_proof, history) = load_state(&dir)?;
+        self.state_lock = Some(state_lock);
+        self.cached_proof = Mutex::new(cached_proof);
+        self.history = Arc::new(history);
+        self.state_dir = dir;
+        Ok(self)
+    }
+
+    /// Write the verified proofs with their metadata to `proof_<challenge>.json` files
+    /// in the directory (see [post::prove::ProofWithMetadata]).
+    pub fn with_proof_dir(mut self, dir: Option<PathBuf>) -> Self {
+        self.proof_dir = dir;
+        self
+    }
+
+    /// Limit the rate of reading POS data during proving (in bytes per second).
+    pub fn with_read_rate_limit(mut self, bytes_per_sec: Option<NonZeroU64>) -> Self {
+        self.proving_options.read_rate_limit =
+            bytes_per_sec.map(|rate| Arc::new(prove::RateLimiter::new(rate)));
+        self
+    }
+
+    /// Start data passes with the nonce groups whose k2pow is ready
+    /// (see [prove::ProvingOptions::pipelined_k2pow]).
+    pub fn with_pipelined_k2pow(mut self, pipelined: bool) -> Self {
+        self.proving_options.pipelined_k2pow = pipelined;
+        self
+    }
+
+    /// Prove only the units initialized completely of partially initialized POS data
+    /// and report that number of units to the node (see [prove::ProvingOptions::partial_data]).
+    ///
+    /// Proof generation is refused for partially initialized POS data otherwise.
+    /// Fails if not even one unit is initialized.
+    pub fn with_partial_data(mut self) -> eyre::Result<Self> {
+        self.metadata = self.metadata.initialized()?;
+        self.proving_options.partial_data = true;
+        Ok(self)
+    }
+
+    /// Before each pass over the POS data, check that the files and the metadata
+    /// have not changed since now (see [prove::ProvingOptions::data_snapshot]).
+    pub fn with_data_check(mut self) -> eyre::Result<Self> {
+        let snapshot = post::pos_verification::DataSnapshot::take(&self.datadir)
+            .wrap_err("checking POS data")?;
+        self.proving_options.data_snapshot = Some(snapshot);
+        Ok(self)
+    }
+
+    /// Choose the number of nonces per pass finding a proof with the given probability
+    /// the fastest, based on the throughput measured now (see [crate::planner]).
+    /// The read throughput measured before (kept in the state directory) is reused.
+    ///
+    /// Call after setting the other proving options, the throughput depends on them.
+    pub fn with_planned_nonces(self, probability: f64) -> eyre::Result<Self> {
+        let throughput = {
+            let settings = self.settings.lock().unwrap();
+            crate::planner::measure(
+                &self.datadir,
+                &self.metadata,
+                &self.cfg,
+                self.proving_cores(settings.threads.clone()),
+                &self.proving_options,
+                PLANNING_SAMPLE,
+                Some(&self.state_dir),
+            )?
+        };
+        let plan = crate::planner::plan(
+            &self.cfg,
+            probability,
+            self.metadata.total_size(),
+            throughput,
+        );
+        log::info!(
+            "planned {} nonces per pass: {} passes (~{:?} each, without k2pow) for a {:.2}% chance to find a proof \
+            (read: {:.1} MiB/s, proving: {:.1} MiB/s per nonce group)",
+            plan.nonces,
+            plan.passes,
+            Duration::from_secs(plan.pass_duration.as_secs()),
+            plan.probability * 100.0,
+            throughput.read / (1024.0 * 1024.0),
+            throughput.proving / (1024.0 * 1024.0),
+        );
+        {
+            let mut settings = self.settings.lock().unwrap();
+            settings.nonces = plan.nonces;
+            settings.plan = Some(plan);
+        }
+        Ok(self)
+    }
+
+    /// Retry failed reads of POS data (see [prove::ProvingOptions::read_retry]).
+    pub fn with_read_retry(mut self, retry: prove::ReadRetry) -> Self {
+        self.proving_options.read_retry = retry;
+        self
+    }
+
+    /// Verify the generated proofs as configured (all labels by default).
+    pub fn with_self_verification(mut self, self_verification: SelfVerification) -> Self {
+        match self_verification {
+            SelfVerification::All => {}
+            SelfVerification::Subset { k3 } if k3 >= self.cfg.k2 as usize => {}
+            SelfVerification::Subset { k3 } => log::warn!(
+                "verifying only {k3} of {} labels of the generated proofs: \
+                the proofs reach the node sooner, but invalid labels outside of the subset \
+                are found only by the node, which rejects the proof",
+                self.cfg.k2
+            ),
+            SelfVerification::Skip => log::warn!(
+                "not verifying the generated proofs: the proofs reach the node sooner, \
+                but an invalid proof (i.e. due to damaged POS data) is found only by the node, \
+                which rejects it"
+            ),
+        }
+        self.self_verification = self_verification;
+        self
+    }
+
+    /// Read POS data in batches of the given size (see [prove::ProvingOptions::batch_size]).
+    pub fn with_batch_size(mut self, batch_size: prove::BatchSize) -> Self {
+        self.proving_options.batch_size = batch_size;
+        self
+    }
+
+    /// Limit the memory taken by the POS data read for proving (in bytes)
+    /// (see [prove::ProvingOptions::memory_budget]).
+    pub fn with_memory_budget(mut self, bytes: Option<u64>) -> Self {
+        self.proving_options.memory_budget = bytes;
+        self
+    }
+
+    /// Limit the memory used by proof generation (in bytes), approximately.
+    ///
+    /// Every proof generation exceeding the limit uses the RandomX light mode
+    /// and smaller batches of POS data (see [crate::memory::MemorySettings::fit]).
+    pub fn with_memory_limit(mut self, bytes: Option<u64>) -> Self {
+        self.memory_limit = bytes;
+        self
+    }
+
+    /// Prove also on the efficiency cores of hybrid CPUs. By default, the threads
+    /// not pinned explicitly run only on the performance cores.
+    pub fn with_efficiency_cores(mut self, allowed: bool) -> Self {
+        self.efficiency_cores = allowed;
+        self
+    }
+
+    /// The cores to prove on with the `threads` setting (see [Self::with_efficiency_cores]).
+    fn proving_cores(&self, threads: post::config::Cores) -> post::config::Cores {
+        match (self.efficiency_cores, post::topology::Topology::detect()) {
+            (false, Some(topology)) => topology.avoid_efficiency_cores(threads),
+            _ => threads,
+        }
+    }
+
+    /// The settings of proof generation with `threads` that the memory usage depends on,
+    /// fitted in the memory limit.
+    fn memory_settings(&self, threads: &post::config::Cores) -> MemorySettings {
+        let local_randomx = self.remote_k2pow_config.is_none()
+            && matches!(self.pow_version, post::pow::Version::RandomX);
+        let settings = MemorySettings {
+            pow_flags: local_randomx.then_some(self.pow_flags),
+            verify: !matches!(self.self_verification, SelfVerification::Skip),
+            threads: crate::memory::threads(threads),
+            batch_size: self.proving_options.batch_size,
+            memory_budget: self.proving_options.memory_budget,
+        };
+        match self.memory_limit {
+            Some(limit) => settings.fit(limit),
+            None => settings,
+        }
+    }
+
+    /// Abort the passes over POS data unlikely to find a proof
+    /// (see [prove::ProvingOptions::early_abort]).
+    pub fn with_early_abort(mut self, probability: Option<f64>) -> Self {
+        self.proving_options.early_abort = probability;
+        self
+    }
+
+    /// Give up proving when no proof is found with this many nonces
+    /// (see [prove::ProvingOptions::max_nonces]).
+    pub fn with_max_nonces(mut self, max_nonces: Option<u32>) -> Self {
+        self.proving_options.max_nonces = max_nonces;
+        self
+    }
+
+    /// Read POS data from the given source instead of the data directory.
+    pub fn with_data_source(mut self, source: Arc<dyn prove::PosDataSource>) -> Self {
+        self.proving_options.data_source = Some(source);
+        self
+    }
+
+    /// Allow proving only in the windows of the schedule.
+    ///
+    /// The schedule is checked in [Self::apply_schedule],
+    /// which must be called periodically.
+    pub fn with_schedule(mut self, schedule: Schedule) -> Self {
+        self.schedule = schedule;
+        self.proving_options.pause = Some(Arc::new(prove::Pause::default()));
+        self
+    }
+
+    /// Pause or resume proof generation depending on whether the schedule allows proving at `now`.
+    pub fn apply_schedule(&self, now: SystemTime) {
+        let Some(pause) = &self.proving_options.pause else {
+            return;
+        };
+        let paused = !self.schedule.allows(now);
+        if pause.is_paused() != paused {
+            match paused {
+                true => log::info!("outside of the proving windows, pausing proof generation"),
+                false => log::info!("in a proving window, resuming proof generation"),
+            }
+            pause.set(paused);
+        }
+    }
+
+    fn is_paused(&self) -> bool {
+        self.proving_options
+            .pause
+            .as_ref()
+            .is_some_and(|p| p.is_paused())
+    }
+
+    /// Verify a generated proof as configured with [Self::with_self_verification].
+    fn self_verify(&self, proof: &Proof, challenge: &[u8]) -> eyre::Result<()> {
+        // Pick different labels of the subset every time.
+        let seed = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_nanos()
+            .to_le_bytes();
+        let mode = match self.self_verification {
+            SelfVerification::All => Mode::All,
+            SelfVerification::Subset { k3 } => Mode::Subset { k3, seed: &seed },
+            SelfVerification::Skip => {
+                log::info!("skipping verification of the proof");
+                return Ok(());
+            }
+        };
+        let pow_verifier = post::pow::new_verifier(
+            self.pow_version,
+            RandomXFlag::get_recommended_flags(),
+            &self.randomx_cache_key,
+        )
+        .context("creating PoW verifier")?;
+        let verifier = Verifier::new(pow_verifier);
+        let metadata = &ProofMetadata::new(self.metadata, challenge.try_into()?);
+        let init_cfg = post::config::InitConfig {
+            // we assume our POST is correctly initialized.
+            min_num_units: self.metadata.num_units,
+            max_num_units: self.metadata.num_units,
+            labels_per_unit: self.metadata.labels_per_unit,
+            scrypt: self.scrypt,
+        };
+        let started = Instant::now();
+        verifier
+            .verify(proof, metadata, &self.cfg, &init_cfg, mode)
+            .context("verifying proof")?;
+        log::info!("verified the proof in {:.2?}", started.elapsed());
+        Ok(())
+    }
+
+    /// Write the proof with its metadata to the directory (see [Self::with_proof_dir]).
+    fn export_proof(&self, dir: &Path, cached: &CachedProof) -> eyre::Result<()> {
+        let proof = post::prove::ProofWithMetadata {
+            proof: cached.proof.clone(),
+            metadata: ProofMetadata::new(self.metadata, cached.challenge),
+        };
+        let path = dir.join(format!("proof_{}.json", hex::encode(cached.challenge)));
+        std::fs::create_dir_all(dir)?;
+        proof.save(&path)?;
+        log::info!("wrote proof to {}", path.display());
+        Ok(())
+    }
 }
 
 impl crate::client::PostService for PostService {
+    /// Start (or poll) the proof generation for the challenge.
+    ///
+    /// Repeated challenges are not rejected:
+    /// - the challenge of the last verified proof gets that proof again (it's cached,
+    ///   also across restarts) as long as the POS data hasn't changed,
+    /// - the challenge being proven gets [ProofGenState::InProgress].
+    ///
+    /// A challenge is rejected with a [ChallengeError] when it's malformed or when
+    /// the service is busy with a different challenge (in progress or waiting to be
+    /// fetched, i.e. by another node), see [crate::client] for the statuses of the node API.
+    /// There is no allow-list of challenges nor epoch check: the node API carries
+    /// no epoch hint and the challenges are not known ahead.
     fn gen_proof(&self, ch: &[u8]) -> eyre::Result<ProofGenState> {
         let mut proof_gen = self.proof_generation.lock().unwrap();
         proof_gen.check_finished();
         match &*proof_gen {
             ProofGenProcess::Running { challenge, .. } => {
-                eyre::ensure!(
-                challenge.as_slice() == ch,
-                 "proof generation is in progress for a different challenge (current: {}, requested: {})",
-                  hex::encode_upper(challenge),
-                  hex::encode_upper(ch),
-                );
+                if challenge.as_slice() != ch {
+                    return Err(ChallengeError::InProgress {
+                        current: *challenge,
+                        requested: ch.to_vec(),
+                    }
+                    .into());
+                }
                 return Ok(ProofGenState::InProgress);
             }
             ProofGenProcess::Idle => {
                 let challenge: [u8; 32] = ch
                     .try_into()
-                    .map_err(|_| eyre::eyre!("invalid challenge format"))?;
+                    .map_err(|_| ChallengeError::InvalidLength(ch.len()))?;
+                // Without `with_partial_data`, the metadata is the one of all the POS data.
+                if self.metadata.initialized_labels() < self.metadata.total_labels() {
+                    let units = self
+                        .metadata
+                        .unit_size()
+                        .complete_units(self.metadata.initialized_labels());
+                    return Err(eyre::Report::new(post::Error::PartiallyInitialized {
+                        units: units as u32,
+                        total_units: self.metadata.num_units,
+                    })
+                    .wrap_err("refusing to prove partially initialized POS data"));
+                }
+                if let Some(cached) = &*self.cached_proof.lock().unwrap() {
+                    if cached.is_for(&challenge, &self.metadata, &self.datadir) {
+                        log::info!("returning cached proof");
+                        return Ok(ProofGenState::Finished {
+                            proof: cached.proof.clone(),
+                        });
+                    }
+                }
                 log::info!(
                     "starting proof generation for challenge {}",
                     hex::encode_upper(challenge)
                 );
-                let pow_flags = self.pow_flags;
                 let cfg = self.cfg;
                 let datadir = self.datadir.clone();
-                let nonces = self.nonces;
-                let threads = self.threads.clone();
+                let ProvingSettings {
+                    nonces, threads, ..
+                } = self.settings.lock().unwrap().clone();
+                let threads = self.proving_cores(threads);
+                let memory = self.memory_settings(&threads);
+                log::info!(
+                    "proving needs ~{} MiB of memory",
+                    memory.usage().total() / 1024 / 1024
+                );
+                let pow_flags = memory.pow_flags.unwrap_or(self.pow_flags);
                 let stop = self.stop.clone();
-                let progress = ProvingProgress::default();
-                let pow_prover: Box<dyn post::pow::Prover + Send + Sync> =
-                    match &self.remote_k2pow_config {
-                        Some(cfg) => Box::new(post::pow::service::K2powService::new(
+                self.apply_schedule(SystemTime::now());
+                let options = prove::ProvingOptions {
+                    batch_size: memory.batch_size,
+                    memory_budget: memory.memory_budget,
+                    state_dir: Some(self.state_dir.clone()),
+                    pow_algorithm: post::pow::cache::PowAlgorithm {
+                        version: self.pow_version,
+                        randomx_cache_key: self.randomx_cache_key.clone(),
+                    },
+                    ..self.proving_options.clone()
+                };
+                let events = Arc::new(EventLog::create(&self.state_dir));
+                events.record(Event::Started { challenge, nonces });
+                *self.workers.lock().unwrap() = Workers::new(&threads);
+                let progress = ProvingProgress::new(events.clone(), self.workers.clone());
+                let pow_prover: Box<dyn post::pow::Prover + Send + Sync> = match &self
+                    .remote_k2pow_config
+                {
+                    Some(cfg) => {
+                        let mut service = post::pow::service::K2powService::new(
                             cfg.url.clone(),
                             cfg.parallelism,
                             cfg.backoff,
-                        )),
-                        None => Box::new(post::pow::randomx::PoW::new(pow_flags).unwrap()),
-                    };
+                        );
+                        if let Some(limits) = &self.k2pow_limits {
+                            service = service.with_limits(limits.clone());
+                        }
+                        let jobs_path = self.state_dir.join(K2POW_JOBS_FILE_NAME);
+                        match post::pow::service::JobStore::open(&jobs_path) {
+                            Ok(store) => Box::new(service.with_job_store(Arc::new(store))),
+                            Err(e) => {
+                                log::warn!(
+                                    "failed to load k2pow jobs from {}: {e:?}",
+                                    jobs_path.display()
+                                );
+                                Box::new(service)
+                            }
+                        }
+                    }
+                    None => {
+                        post::pow::new_prover(self.pow_version, pow_flags, &self.randomx_cache_key)
+                            .context("creating PoW prover")?
+                    }
+                };
+                let pow_prover = RecordingPowProver {
+                    inner: pow_prover,
+                    events: events.clone(),
+                };
                 let reporter = progress.clone();
+                let history = self.history.clone();
+                let span = tracing::Span::current();
                 *proof_gen = ProofGenProcess::Running {
                     challenge,
                     handle: Some(std::thread::spawn(move || {
-                        post::prove::generate_proof(
-                            &datadir,
-                            &challenge,
-                            cfg,
-                            nonces,
-                            threads,
-                            pow_flags,
-                            stop,
-                            reporter,
-                            &*pow_prover,
-                        )
+                        let result = span.in_scope(|| {
+                            post::prove::generate_proof_with_options(
+                                &datadir,
+                                &challenge,
+                                cfg,
+                                nonces,
+                                threads,
+                                pow_flags,
+                                stop,
+                                reporter,
+                                &pow_prover,
+                                &options,
+                            )
+                            .map_err(eyre::Report::from)
+                        });
+                        events.record(match &result {
+                            Ok(proof) => Event::Finished {
+                                proof: proof.clone(),
+                            },
+                            Err(e) => Event::Failed {
+                                error: format!("{e:?}"),
+                            },
+                        });
+                        if let Some(summary) = ProofSummary::from_records(&events.records()) {
+                            history.push(summary);
+                        }
+                        result
                     })),
                     progress,
                 };
             }
-            ProofGenProcess::Done { proof } => {
+            ProofGenProcess::Done { challenge, proof } => {
+                // The service might be serving several nodes.
+                // Keep the proof until the node that requested it fetches it.
+                if challenge.as_slice() != ch {
+                    return Err(ChallengeError::NotFetched {
+                        current: *challenge,
+                        requested: ch.to_vec(),
+                    }
+                    .into());
+                }
                 log::info!("proof generation is finished");
                 return match proof {
                     Ok(proof) => Ok(ProofGenState::Finished {
                         proof: proof.clone(),
                     }),
-                    Err(e) => Err(eyre::eyre!("proof generation failed: {}", e)),
+                    // Keep the error typed, so that it's reported distinctly to the node.
+                    Err(e) => match e.downcast_ref::<post::Error>() {
+                        Some(&post::Error::NoncesExhausted { nonces }) => {
+                            Err(eyre::Report::new(post::Error::NoncesExhausted { nonces })
+                                .wrap_err("proof generation failed"))
+                        }
+                        _ => Err(eyre::eyre!("proof generation failed: {}", e)),
+                    },
                 };
             }
         }
@@ -219,22 +1003,46 @@ impl crate::client::PostService for PostService {
         Ok(ProofGenState::InProgress)
     }
 
+    #[tracing::instrument(skip_all, fields(nonce = proof.nonce))]
     fn verify_proof(&self, proof: &Proof, challenge: &[u8]) -> eyre::Result<()> {
-        let pow_verifier =
-            PoW::new(RandomXFlag::get_recommended_flags()).context("creating PoW verifier")?;
-        let verifier = Verifier::new(Box::new(pow_verifier));
-        let metadata = &ProofMetadata::new(self.metadata, challenge.try_into()?);
-        let init_cfg = post::config::InitConfig {
-            // we assume our POST is correctly initialized.
-            min_num_units: self.metadata.num_units,
-            max_num_units: self.metadata.num_units,
-            labels_per_unit: self.metadata.labels_per_unit,
-            scrypt: self.scrypt,
-        };
-        let result = verifier
-            .verify(proof, metadata, &self.cfg, &init_cfg, Mode::All)
-            .context("verifying proof");
-        *self.proof_generation.lock().unwrap() = ProofGenProcess::Idle;
+        let result = self.self_verify(proof, challenge);
+        let mut proof_gen = self.proof_generation.lock().unwrap();
+        if let ProofGenProcess::Done {
+            challenge: c,
+            proof,
+        } = &*proof_gen
+        {
+            if c.as_slice() == challenge {
+                if let (Ok(()), Ok(generated)) = (&result, proof) {
+                    // POS data read from another source can't be snapshotted.
+                    let data = match self.proving_options.data_source {
+                        Some(_) => Ok(None),
+                        None => DataSnapshot::take(&self.datadir).map(Some),
+                    };
+                    match data {
+                        Ok(data) => {
+                            let cached = CachedProof {
+                                challenge: *c,
+                                proof: generated.clone(),
+                                metadata: Some(self.metadata),
+                                data,
+                            };
+                            if let Err(e) = cached.save(&self.state_dir) {
+                                log::warn!("failed to persist proof: {e:?}");
+                            }
+                            if let Some(dir) = &self.proof_dir {
+                                if let Err(e) = self.export_proof(dir, &cached) {
+                                    log::warn!("failed to write proof to {}: {e:?}", dir.display());
+                                }
+                            }
+                            *self.cached_proof.lock().unwrap() = Some(cached);
+                        }
+                        Err(e) => log::warn!("not caching the proof: {e}"),
+                    }
+                }
+                *proof_gen = ProofGenProcess::Idle;
+            }
+        }
         result
     }
 
@@ -249,16 +1057,133 @@ impl crate::operator::Service for PostService {
         proof_gen.check_finished();
         match &*proof_gen {
             ProofGenProcess::Running { progress, .. } => {
-                let (nonces, offset) = progress.get();
-                ServiceState::Proving {
-                    nonces,
-                    position: offset,
+                let (nonces, phase) = progress.get();
+                match phase {
+                    PassPhase::ComputingPow { done, total } => ServiceState::ComputingPow {
+                        nonces,
+                        done,
+                        total,
+                    },
+                    PassPhase::ReadingData { position, .. } if self.is_paused() => {
+                        ServiceState::Scheduled { nonces, position }
+                    }
+                    PassPhase::ReadingData {
+                        position,
+                        indices_found,
+                        ..
+                    } => ServiceState::ReadingData {
+                        nonces,
+                        position,
+                        total: self.metadata.total_size(),
+                        k2: self.cfg.k2,
+                        indices_found,
+                    },
                 }
             }
             ProofGenProcess::Idle => ServiceState::Idle,
             ProofGenProcess::Done { .. } => ServiceState::DoneProving,
         }
     }
+
+    fn proof_events(&self) -> eyre::Result<Vec<crate::events::Record>> {
+        crate::events::read(&self.state_dir)
+    }
+
+    fn proof_history(&self) -> Vec<ProofSummary> {
+        self.history.entries()
+    }
+
+    fn proving_plan(&self) -> Option<Plan> {
+        self.settings.lock().unwrap().plan.clone()
+    }
+
+    fn worker_stats(&self) -> Vec<WorkerStats> {
+        self.workers.lock().unwrap().stats()
+    }
+
+    fn memory_usage(&self) -> MemoryUsage {
+        let threads = self.proving_cores(self.settings.lock().unwrap().threads.clone());
+        MemoryUsage {
+            limit: self.memory_limit,
+            ..self.memory_settings(&threads).usage()
+        }
+    }
+
+    fn last_proof(&self) -> Option<LastProof> {
+        let mut proof_gen = self.proof_generation.lock().unwrap();
+        proof_gen.check_finished();
+        if let ProofGenProcess::Done {
+            challenge,
+            proof: Ok(proof),
+        } = &*proof_gen
+        {
+            return Some(LastProof {
+                challenge: *challenge,
+                proof: proof.clone(),
+            });
+        }
+        self.cached_proof
+            .lock()
+            .unwrap()
+            .as_ref()
+            .map(|cached| LastProof {
+                challenge: cached.challenge,
+                proof: cached.proof.clone(),
+            })
+    }
+
+    fn update_settings(&self, update: SettingsUpdate) -> eyre::Result<()> {
+        let mut settings = self.settings.lock().unwrap();
+        let mut new = settings.clone();
+        match (update.threads, update.pinned_cores) {
+            (Some(_), Some(_)) => eyre::bail!("threads and pinned cores are mutually exclusive"),
+            (Some(0), None) => new.threads = post::config::Cores::All,
+            (Some(n), None) => new.threads = post::config::Cores::Any(n),
+            (None, Some(cores)) => {
+                eyre::ensure!(!cores.is_empty(), "pinned cores cannot be empty");
+                check_pinned_cores(&cores)?;
+                new.threads = post::config::Cores::Pin(cores);
+            }
+            (None, None) => {}
+        }
+        if let Some(nonces) = update.nonces {
+            eyre::ensure!(nonces != 0, "nonces must be positive");
+            eyre::ensure!(nonces <= 256 * 16, "max nonces is {}", 256 * 16);
+            new.nonces = nonces;
+            new.plan = None;
+        } else if let (Some(plan), true) = (&new.plan, new.threads != settings.threads) {
+            // The proving throughput depends on the cores, the read throughput doesn't.
+            let proving = crate::planner::measure_proving(
+                &self.metadata,
+                &self.cfg,
+                self.proving_cores(new.threads.clone()),
+            )?;
+            let plan = crate::planner::plan(
+                &self.cfg,
+                plan.target_probability,
+                self.metadata.total_size(),
+                crate::planner::Throughput {
+                    read: plan.throughput.read,
+                    proving,
+                },
+            );
+            log::info!(
+                "planned {} nonces per pass for the new threads: {} passes (~{:?} each, without k2pow)",
+                plan.nonces,
+                plan.passes,
+                Duration::from_secs(plan.pass_duration.as_secs()),
+            );
+            new.nonces = plan.nonces;
+            new.plan = Some(plan);
+        }
+        if let Some(filter) = update.log_level {
+            crate::logging::set_filter(&filter)?;
+        }
+
+        log::info!("updated proving settings: {new:?}");
+        *settings = new;
+        Ok(())
+    }
 }
 
 impl Drop for PostService {
@@ -0,0 +1,222 @@
+//! Simulated proving for testing integrations with the node.
+//!
+//! [SimulatedPostService] follows the protocol of [crate::service::PostService],
+//! but doesn't read POS data nor calculate any PoW. The proof of a challenge is
+//! "generated" after a configured delay and is always the same for the challenge.
+//! The proofs are not valid for the network.
+
+use std::{
+    path::Path,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use post::{metadata::PostMetadata, prove::Proof};
+
+use crate::{
+    operator::{LastProof, ServiceState},
+    service::ProofGenState,
+};
+
+#[derive(Debug)]
+enum State {
+    Idle,
+    Proving {
+        challenge: [u8; 32],
+        started: Instant,
+    },
+}
+
+#[derive(Debug)]
+pub struct SimulatedPostService {
+    metadata: PostMetadata,
+    k2: u32,
+    nonces: usize,
+    delay: Duration,
+    state: Mutex<State>,
+    last_proof: Mutex<Option<LastProof>>,
+}
+
+impl SimulatedPostService {
+    /// Only the POST metadata is loaded from `datadir`.
+    pub fn new(datadir: &Path, k2: u32, nonces: usize, delay: Duration) -> eyre::Result<Self> {
+        let metadata = post::metadata::load(datadir)?;
+        Ok(Self {
+            metadata,
+            k2,
+            nonces,
+            delay,
+            state: Mutex::new(State::Idle),
+            last_proof: Mutex::new(None),
+        })
+    }
+
+    /// The proof "generated" for the challenge.
+    pub fn proof(&self, challenge: &[u8; 32]) -> Proof<'static> {
+        let num_labels = self.metadata.total_labels();
+        let seed = u64::from_le_bytes(challenge[..8].try_into().unwrap());
+        let indices = (0..self.k2 as u64)
+            .map(|i| seed.wrapping_add(i) % num_labels.max(1))
+            .collect::<Vec<_>>();
+        Proof::new(0, &indices, num_labels, 0)
+    }
+
+    fn finished(&self, started: Instant) -> bool {
+        started.elapsed() >= self.delay
+    }
+}
+
+impl crate::client::PostService for SimulatedPostService {
+    fn get_metadata(&self) -> &PostMetadata {
+        &self.metadata
+    }
+
+    fn gen_proof(&self, ch: &[u8]) -> eyre::Result<ProofGenState> {
+        let challenge: [u8; 32] = ch
+            .try_into()
+            .map_err(|_| eyre::eyre!("invalid challenge length: {}", ch.len()))?;
+        let mut state = self.state.lock().unwrap();
+        match &*state {
+            State::Idle => {
+                log::info!(
+                    "simulating proof generation for challenge {}",
+                    hex::encode_upper(challenge)
+                );
+                *state = State::Proving {
+                    challenge,
+                    started: Instant::now(),
+                };
+                Ok(ProofGenState::InProgress)
+            }
+            State::Proving { challenge: c, .. } if c != &challenge => eyre::bail!(
+                "proof generation is in progress for a different challenge (current: {}, requested: {})",
+                hex::encode_upper(c),
+                hex::encode_upper(challenge),
+            ),
+            State::Proving { started, .. } if self.finished(*started) => {
+                let proof = self.proof(&challenge);
+                *self.last_proof.lock().unwrap() = Some(LastProof {
+                    challenge,
+                    proof: proof.clone(),
+                });
+                Ok(ProofGenState::Finished { proof })
+            }
+            State::Proving { .. } => Ok(ProofGenState::InProgress),
+        }
+    }
+
+    fn verify_proof(&self, proof: &Proof, challenge: &[u8]) -> eyre::Result<()> {
+        let challenge: [u8; 32] = challenge.try_into()?;
+        eyre::ensure!(
+            proof == &self.proof(&challenge),
+            "not the simulated proof for the challenge"
+        );
+        let mut state = self.state.lock().unwrap();
+        if matches!(&*state, State::Proving { challenge: c, .. } if c == &challenge) {
+            *state = State::Idle;
+        }
+        Ok(())
+    }
+}
+
+impl crate::operator::Service for SimulatedPostService {
+    fn status(&self) -> ServiceState {
+        match &*self.state.lock().unwrap() {
+            State::Idle => ServiceState::Idle,
+            State::Proving { started, .. } if self.finished(*started) => ServiceState::DoneProving,
+            State::Proving { started, .. } => {
+                let done = started.elapsed().as_secs_f64() / self.delay.as_secs_f64();
+                ServiceState::ReadingData {
+                    nonces: 0..self.nonces as u32,
+                    position: (self.metadata.total_size() as f64 * done) as u64,
+                    total: self.metadata.total_size(),
+                    k2: 0,
+                    indices_found: Default::default(),
+                }
+            }
+        }
+    }
+
+    fn update_settings(&self, update: crate::operator::SettingsUpdate) -> eyre::Result<()> {
+        log::info!("ignoring settings update in simulation: {update:?}");
+        Ok(())
+    }
+
+    fn last_proof(&self) -> Option<LastProof> {
+        self.last_proof.lock().unwrap().clone()
+    }
+
+    fn proof_events(&self) -> eyre::Result<Vec<crate::events::Record>> {
+        Ok(Vec::new())
+    }
+
+    fn proof_history(&self) -> Vec<crate::history::ProofSummary> {
+        Vec::new()
+    }
+
+    fn proving_plan(&self) -> Option<crate::planner::Plan> {
+        None
+    }
+
+    fn worker_stats(&self) -> Vec<crate::workers::WorkerStats> {
+        Vec::new()
+    }
+
+    fn memory_usage(&self) -> crate::memory::MemoryUsage {
+        crate::memory::MemoryUsage::default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{client::PostService, operator::Service};
+
+    fn service(delay: Duration) -> SimulatedPostService {
+        let datadir = tempfile::tempdir().unwrap();
+        let metadata = PostMetadata {
+            labels_per_unit: 100,
+            num_units: 2,
+            max_file_size: 1600,
+            ..Default::default()
+        };
+        std::fs::write(
+            datadir.path().join(post::metadata::METADATA_FILE_NAME),
+            serde_json::to_vec(&metadata).unwrap(),
+        )
+        .unwrap();
+        SimulatedPostService::new(datadir.path(), 10, 32, delay).unwrap()
+    }
+
+    #[test]
+    fn simulating_proof_generation() {
+        let service = service(Duration::from_millis(100));
+        assert_eq!(ServiceState::Idle, service.status());
+
+        assert!(matches!(
+            service.gen_proof(&[1; 32]).unwrap(),
+            ProofGenState::InProgress
+        ));
+        assert!(matches!(service.status(), ServiceState::ReadingData { .. }));
+        assert!(service.gen_proof(&[2; 32]).is_err());
+
+        std::thread::sleep(Duration::from_millis(100));
+        assert_eq!(ServiceState::DoneProving, service.status());
+        let ProofGenState::Finished { proof } = service.gen_proof(&[1; 32]).unwrap() else {
+            panic!("proof should be finished");
+        };
+        assert_eq!(service.proof(&[1; 32]), proof);
+        assert_eq!(proof, service.last_proof().unwrap().proof);
+
+        assert!(service.verify_proof(&proof, &[2; 32]).is_err());
+        service.verify_proof(&proof, &[1; 32]).unwrap();
+        assert_eq!(ServiceState::Idle, service.status());
+    }
+
+    #[test]
+    fn simulated_proofs_are_deterministic() {
+        let service = service(Duration::ZERO);
+        assert_eq!(service.proof(&[1; 32]), service.proof(&[1; 32]));
+        assert_ne!(service.proof(&[1; 32]), service.proof(&[2; 32]));
+    }
+}
@@ -0,0 +1,251 @@
+//! Initializing POS data before proving (supervisor mode).
+//!
+//! The service initializes the POS data in its data directory itself and hands over
+//! to proving when it's done. Meanwhile, the operator API reports the initialization progress.
+
+use std::{
+    path::PathBuf,
+    sync::{Arc, OnceLock},
+    time::Duration,
+};
+
+use post::{
+    config::UnitSize,
+    initialize::{InitTracker, Initialize, Tracked},
+    metadata::PostMetadata,
+};
+
+use crate::operator::{self, LastProof, ServiceState};
+
+/// What to initialize.
+#[derive(Debug, Clone)]
+pub struct InitConfig {
+    pub node_id: [u8; 32],
+    pub commitment_atx_id: [u8; 32],
+    pub labels_per_unit: u64,
+    pub num_units: u32,
+    pub labels_per_file: u64,
+}
+
+impl InitConfig {
+    fn unit_size(&self) -> UnitSize {
+        UnitSize::new(self.labels_per_unit)
+    }
+
+    fn total_labels(&self) -> u64 {
+        self.unit_size().labels(self.num_units)
+    }
+}
+
+pub struct Supervisor {
+    datadir: PathBuf,
+    config: InitConfig,
+    tracker: InitTracker,
+    service: OnceLock<Arc<dyn operator::Service + Send + Sync>>,
+}
+
+impl Supervisor {
+    pub fn new(datadir: PathBuf, config: InitConfig) -> Self {
+        Self {
+            datadir,
+            config,
+            tracker: InitTracker::default(),
+            service: OnceLock::new(),
+        }
+    }
+
+    /// Initialize the POS data, unless it's already initialized. Blocks until it's done.
+    ///
+    /// An interrupted initialization is resumed. Fails if the data directory contains
+    /// POS data initialized with different parameters.
+    pub fn initialize(&self, initializer: &mut dyn Initialize) -> eyre::Result<PostMetadata> {
+        let cfg = &self.config;
+        if let Ok(metadata) = post::metadata::load(&self.datadir) {
+            eyre::ensure!(
+                metadata.node_id == cfg.node_id
+                    && metadata.commitment_atx_id == cfg.commitment_atx_id
+                    && metadata.labels_per_unit == cfg.labels_per_unit
+                    && metadata.num_units == cfg.num_units,
+                "POS data in {} is initialized with different parameters: {metadata:?}",
+                self.datadir.display(),
+            );
+            log::info!("POS data in {} is initialized", self.datadir.display());
+            return Ok(metadata);
+        }
+
+        log::info!(
+            "initializing {} units of {} ({} labels) of POS data in {}",
+            cfg.num_units,
+            cfg.unit_size(),
+            cfg.total_labels(),
+            self.datadir.display()
+        );
+        Tracked::new(initializer, &self.tracker)
+            .initialize(
+                &self.datadir,
+                &cfg.node_id,
+                &cfg.commitment_atx_id,
+                cfg.labels_per_unit,
+                cfg.num_units,
+                cfg.labels_per_file,
+                Some([0xFF; 32]),
+            )
+            .map_err(|e| eyre::eyre!("initializing POS data: {e}"))
+    }
+
+    pub fn log_progress(&self) {
+        let total = self.config.total_labels();
+        let done = self.tracker.labels_done();
+        log::info!(
+            "initialized {done}/{total} labels ({:.2}%), ETA: {:?}",
+            done as f64 / total as f64 * 100.0,
+            self.tracker
+                .eta(total)
+                .map(|eta| Duration::from_secs(eta.as_secs())),
+        );
+    }
+
+    /// Hand over to the proving service. The operator API reports its state from now on.
+    pub fn start_proving(&self, service: Arc<dyn operator::Service + Send + Sync>) {
+        if self.service.set(service).is_err() {
+            log::warn!("proving already started");
+        }
+    }
+}
+
+impl operator::Service for Supervisor {
+    fn status(&self) -> ServiceState {
+        match self.service.get() {
+            Some(service) => service.status(),
+            None => {
+                let labels_done = self.tracker.labels_done();
+                ServiceState::Initializing {
+                    labels_done,
+                    total: self.config.total_labels(),
+                    units_done: self.config.unit_size().complete_units(labels_done),
+                    units: self.config.num_units,
+                }
+            }
+        }
+    }
+
+    fn update_settings(&self, update: operator::SettingsUpdate) -> eyre::Result<()> {
+        match self.service.get() {
+            Some(service) => service.update_settings(update),
+            None => eyre::bail!("settings can't be updated while initializing POS data"),
+        }
+    }
+
+    fn last_proof(&self) -> Option<LastProof> {
+        self.service.get()?.last_proof()
+    }
+
+    fn proof_events(&self) -> eyre::Result<Vec<crate::events::Record>> {
+        match self.service.get() {
+            Some(service) => service.proof_events(),
+            None => Ok(Vec::new()),
+        }
+    }
+
+    fn proof_history(&self) -> Vec<crate::history::ProofSummary> {
+        match self.service.get() {
+            Some(service) => service.proof_history(),
+            None => Vec::new(),
+        }
+    }
+
+    fn proving_plan(&self) -> Option<crate::planner::Plan> {
+        self.service.get()?.proving_plan()
+    }
+
+    fn worker_stats(&self) -> Vec<crate::workers::WorkerStats> {
+        match self.service.get() {
+            Some(service) => service.worker_stats(),
+            None => Vec::new(),
+        }
+    }
+
+    fn memory_usage(&self) -> crate::memory::MemoryUsage {
+        self.service
+            .get()
+            .map(|service| service.memory_usage())
+            .unwrap_or_default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use post::{config::ScryptParams, initialize::CpuInitializer};
+
+    use super::*;
+    use crate::operator::{MockService, Service};
+
+    fn config() -> InitConfig {
+        InitConfig {
+            node_id: [1; 32],
+            commitment_atx_id: [2; 32],
+            labels_per_unit: 100,
+            num_units: 2,
+            labels_per_file: 64,
+        }
+    }
+
+    #[test]
+    fn initializing_and_handing_over() {
+        let datadir = tempfile::tempdir().unwrap();
+        let supervisor = Supervisor::new(datadir.path().to_path_buf(), config());
+        assert_eq!(
+            ServiceState::Initializing {
+                labels_done: 0,
+                total: 200,
+                units_done: 0,
+                units: 2,
+            },
+            supervisor.status()
+        );
+        assert!(supervisor.update_settings(Default::default()).is_err());
+
+        let metadata = supervisor
+            .initialize(&mut CpuInitializer::new(ScryptParams::new(2, 1, 1)))
+            .unwrap();
+        assert_eq!(2, metadata.num_units);
+        assert_eq!(
+            ServiceState::Initializing {
+                labels_done: 200,
+                total: 200,
+                units_done: 2,
+                units: 2,
+            },
+            supervisor.status()
+        );
+
+        let mut service = MockService::new();
+        service.expect_status().returning(|| ServiceState::Idle);
+        supervisor.start_proving(Arc::new(service));
+        assert_eq!(ServiceState::Idle, supervisor.status());
+    }
+
+    #[test]
+    fn skipping_initialized_data() {
+        let datadir = tempfile::tempdir().unwrap();
+        let metadata = Supervisor::new(datadir.path().to_path_buf(), config())
+            .initialize(&mut CpuInitializer::new(ScryptParams::new(2, 1, 1)))
+            .unwrap();
+
+        // Nothing is initialized again
+        let supervisor = Supervisor::new(datadir.path().to_path_buf(), config());
+        let mut initializer = post::initialize::MockInitialize::new();
+        initializer.expect_initialize_to().never();
+        assert_eq!(metadata, supervisor.initialize(&mut initializer).unwrap());
+
+        // Different parameters
+        let supervisor = Supervisor::new(
+            datadir.path().to_path_buf(),
+            InitConfig {
+                num_units: 4,
+                ..config()
+            },
+        );
+        assert!(supervisor.initialize(&mut initializer).is_err());
+    }
+}
@@ -0,0 +1,56 @@
+//! Exporting tracing spans to an OpenTelemetry collector via OTLP.
+//!
+//! The trace context is propagated to the remote k2pow service (if used)
+//! in W3C `traceparent` headers, so a slow proof can be followed across services.
+
+use opentelemetry::{trace::TracerProvider as _, KeyValue};
+use opentelemetry_otlp::WithExportConfig;
+use opentelemetry_sdk::{
+    propagation::TraceContextPropagator,
+    runtime,
+    trace::{Tracer, TracerProvider},
+    Resource,
+};
+use tracing::Subscriber;
+use tracing_opentelemetry::OpenTelemetryLayer;
+use tracing_subscriber::registry::LookupSpan;
+
+/// Flushes the spans that were not exported yet when dropped.
+pub struct Guard(TracerProvider);
+
+impl Drop for Guard {
+    fn drop(&mut self) {
+        if let Err(e) = self.0.shutdown() {
+            log::warn!("failed to shut down OTLP exporter: {e}");
+        }
+    }
+}
+
+/// Create a layer exporting spans to the OTLP (gRPC) collector listening on `endpoint`.
+///
+/// Must be called within a tokio runtime.
+pub fn layer<S>(
+    service_name: &'static str,
+    endpoint: &str,
+) -> eyre::Result<(OpenTelemetryLayer<S, Tracer>, Guard)>
+where
+    S: Subscriber + for<'span> LookupSpan<'span>,
+{
+    let exporter = opentelemetry_otlp::SpanExporter::builder()
+        .with_tonic()
+        .with_endpoint(endpoint)
+        .build()?;
+    let provider = TracerProvider::builder()
+        .with_batch_exporter(exporter, runtime::Tokio)
+        .with_resource(Resource::new([KeyValue::new("service.name", service_name)]))
+        .build();
+
+    opentelemetry::global::set_text_map_propagator(TraceContextPropagator::new());
+    opentelemetry::global::set_tracer_provider(provider.clone());
+
+    let tracer = provider.tracer(service_name);
+    Ok((
+        tracing_opentelemetry::layer().with_tracer(tracer),
+        Guard(provider),
+    ))
+}
@@ -0,0 +1,155 @@
+//! Statistics of the threads of the proving pool.
+//!
+//! Every thread proves batches of POS data with the AES ciphers of all nonce groups
+//! of a pass. Comparing the throughput of the threads shows an under-performing core
+//! (i.e. a throttled or busy one).
+
+use post::config::Cores;
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+
+/// A thread proving at less than this fraction of the median throughput is reported as slow.
+const SLOW_FRACTION: f64 = 0.5;
+
+/// Statistics of a thread of the proving pool in a proof generation.
+#[derive(Clone, Debug, Default, Serialize, Deserialize, PartialEq)]
+pub struct WorkerStats {
+    /// The index of the thread in the pool.
+    pub thread: usize,
+    /// The core the thread is pinned to (with pinned cores).
+    pub core: Option<usize>,
+    /// The number of batches of POS data proven.
+    pub batches: u64,
+    /// The bytes of POS data proven.
+    pub bytes: u64,
+    /// The bytes encrypted with AES (the bytes proven times the nonce groups of their passes).
+    pub aes_bytes: u64,
+    /// The time spent proving (in seconds).
+    pub busy: f64,
+}
+
+impl WorkerStats {
+    /// Bytes of POS data proven per second.
+    pub fn throughput(&self) -> f64 {
+        if self.busy > 0.0 {
+            self.bytes as f64 / self.busy
+        } else {
+            0.0
+        }
+    }
+
+    /// Bytes encrypted with AES per second.
+    pub fn aes_throughput(&self) -> f64 {
+        if self.busy > 0.0 {
+            self.aes_bytes as f64 / self.busy
+        } else {
+            0.0
+        }
+    }
+}
+
+/// Collects the [WorkerStats] of the threads of a proof generation.
+#[derive(Clone, Debug, Default)]
+pub struct Workers {
+    stats: Vec<WorkerStats>,
+    pinned: Vec<usize>,
+}
+
+impl Workers {
+    /// Start collecting the statistics of a proof generation with `cores`.
+    pub fn new(cores: &Cores) -> Self {
+        let pinned = match cores {
+            Cores::Pin(cores) => cores.clone(),
+            _ => Vec::new(),
+        };
+        Self {
+            stats: Vec::new(),
+            pinned,
+        }
+    }
+
+    /// Record a batch of `len` bytes proven by `thread` with `nonce_groups` AES ciphers.
+    pub fn record(&mut self, thread: usize, len: usize, nonce_groups: usize, duration: Duration) {
+        while self.stats.len() <= thread {
+            let thread = self.stats.len();
+            self.stats.push(WorkerStats {
+                thread,
+                core: self.pinned.get(thread).copied(),
+                ..Default::default()
+            });
+        }
+        let stats = &mut self.stats[thread];
+        stats.batches += 1;
+        stats.bytes += len as u64;
+        stats.aes_bytes += (len * nonce_groups) as u64;
+        stats.busy += duration.as_secs_f64();
+    }
+
+    /// The statistics of the threads that proved any batch.
+    pub fn stats(&self) -> Vec<WorkerStats> {
+        self.stats
+            .iter()
+            .filter(|s| s.batches > 0)
+            .cloned()
+            .collect()
+    }
+
+    /// The threads proving at less than half of the median throughput.
+    pub fn slow(&self) -> Vec<WorkerStats> {
+        let stats = self.stats();
+        let mut throughputs: Vec<f64> = stats.iter().map(WorkerStats::throughput).collect();
+        throughputs.sort_by(f64::total_cmp);
+        let Some(&median) = throughputs.get(throughputs.len() / 2) else {
+            return Vec::new();
+        };
+        stats
+            .into_iter()
+            .filter(|s| s.throughput() < median * SLOW_FRACTION)
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use post::config::Cores;
+
+    use super::Workers;
+
+    #[test]
+    fn collecting_stats() {
+        let mut workers = Workers::new(&Cores::Pin(vec![4, 5, 6]));
+        workers.record(0, 1024, 2, Duration::from_millis(500));
+        workers.record(0, 1024, 2, Duration::from_millis(500));
+        workers.record(2, 1024, 2, Duration::from_millis(250));
+
+        let stats = workers.stats();
+        assert_eq!(2, stats.len());
+        assert_eq!((0, Some(4), 2, 2048, 4096), {
+            let s = &stats[0];
+            (s.thread, s.core, s.batches, s.bytes, s.aes_bytes)
+        });
+        assert_eq!(2048.0, stats[0].throughput());
+        assert_eq!(4096.0, stats[0].aes_throughput());
+        assert_eq!((2, Some(6)), (stats[1].thread, stats[1].core));
+        assert_eq!(4096.0, stats[1].throughput());
+
+        assert_eq!(None, Workers::new(&Cores::Any(2)).stats().first());
+    }
+
+    #[test]
+    fn finding_slow_workers() {
+        let mut workers = Workers::new(&Cores::All);
+        assert!(workers.slow().is_empty());
+        for thread in 0..4 {
+            workers.record(thread, 1024, 1, Duration::from_secs(1));
+        }
+        assert!(workers.slow().is_empty());
+
+        workers.record(1, 0, 1, Duration::from_secs(2));
+        let slow = workers.slow();
+        assert_eq!(1, slow.len());
+        assert_eq!(1, slow[0].thread);
+    }
+}
@@ -1,5 +1,3 @@
-mod server;
-
 use std::{borrow::Cow, sync::Arc};
 
 use rstest::rstest;
@@ -17,11 +15,11 @@ use post_service::{
             self, service_response, GenProofResponse, GenProofStatus, Metadata, MetadataResponse,
             NodeRequest,
         },
-        MockPostService, ServiceClient,
+        MockPostService, ServiceClient, TlsFiles,
     },
-    service::ProofGenState,
+    service::{ChallengeError, ProofGenState},
 };
-use server::{TestNodeRequest, TestServer, TlsConfig};
+use post_test_util::server::{TestNodeRequest, TestServer, TlsConfig};
 use tonic::transport::{Certificate, Identity};
 
 #[tokio::test]
@@ -166,6 +164,71 @@ async fn test_gen_proof_failed() {
     let _ = client_handle.await;
 }
 
+#[tokio::test]
+async fn test_gen_proof_nonces_exhausted() {
+    let mut test_server = TestServer::new(None).await;
+
+    let mut service = MockPostService::new();
+    service.expect_gen_proof().returning(|_| {
+        Err(
+            eyre::Report::new(post::Error::NoncesExhausted { nonces: 4096 })
+                .wrap_err("proof generation failed"),
+        )
+    });
+
+    let service = Arc::new(service);
+    let client = test_server.create_client(service.clone());
+    let client_handle = tokio::spawn(client.run(None, std::time::Duration::from_secs(1)));
+
+    let connected = test_server.connected.recv().await.unwrap();
+    let response = TestServer::generate_proof(&connected, vec![0xCA; 32]).await;
+
+    assert_eq!(
+        response.kind,
+        Some(service_response::Kind::GenProof(GenProofResponse {
+            status: GenProofStatus::Unspecified as _,
+            proof: None,
+            metadata: None
+        }))
+    );
+
+    client_handle.abort();
+    let _ = client_handle.await;
+}
+
+#[tokio::test]
+async fn test_gen_proof_busy_with_another_challenge() {
+    let mut test_server = TestServer::new(None).await;
+
+    let mut service = MockPostService::new();
+    service.expect_gen_proof().returning(|ch| {
+        Err(ChallengeError::InProgress {
+            current: [0xAA; 32],
+            requested: ch.to_vec(),
+        }
+        .into())
+    });
+
+    let service = Arc::new(service);
+    let client = test_server.create_client(service.clone());
+    let client_handle = tokio::spawn(client.run(None, std::time::Duration::from_secs(1)));
+
+    let connected = test_server.connected.recv().await.unwrap();
+    let response = TestServer::generate_proof(&connected, vec![0xCA; 32]).await;
+
+    assert_eq!(
+        response.kind,
+        Some(service_response::Kind::GenProof(GenProofResponse {
+            status: GenProofStatus::Unspecified as _,
+            proof: None,
+            metadata: None
+        }))
+    );
+
+    client_handle.abort();
+    let _ = client_handle.await;
+}
+
 #[tokio::test]
 async fn test_gen_proof_finished() {
     let mut test_server = TestServer::new(None).await;
@@ -350,3 +413,41 @@ async fn test_get_metadata(#[case] vrf_difficulty: Option<[u8; 32]>) {
     client_handle.abort();
     let _ = client_handle.await;
 }
+
+#[test]
+fn test_client_tls_files() {
+    let ca = rcgen::generate_simple_self_signed(vec![]).unwrap();
+    let client = rcgen::generate_simple_self_signed(vec!["localhost".into()]).unwrap();
+    let dir = tempdir().unwrap();
+    std::fs::write(dir.path().join("ca.pem"), ca.serialize_pem().unwrap()).unwrap();
+    std::fs::write(
+        dir.path().join("cert.pem"),
+        client.serialize_pem_with_signer(&ca).unwrap(),
+    )
+    .unwrap();
+    std::fs::write(
+        dir.path().join("key.pem"),
+        client.serialize_private_key_pem(),
+    )
+    .unwrap();
+
+    let files = TlsFiles {
+        domain: Some("localhost".to_string()),
+        ca_cert: dir.path().join("ca.pem"),
+        cert: dir.path().join("cert.pem"),
+        key: dir.path().join("key.pem"),
+    };
+    let service = Arc::new(MockPostService::new());
+    let result = ServiceClient::with_tls_files(
+        "https://localhost:1234".to_string(),
+        files.clone(),
+        service.clone(),
+    );
+    assert!(result.is_ok());
+
+    // should fail if the files cannot be read
+    std::fs::remove_file(&files.key).unwrap();
+    let result =
+        ServiceClient::with_tls_files("https://localhost:1234".to_string(), files, service);
+    assert!(result.is_err());
+}
@@ -10,9 +10,7 @@ use post::{
 };
 use post_service::client::spacemesh_v1::{service_response, GenProofStatus};
 
-#[allow(dead_code)]
-mod server;
-use server::TestServer;
+use post_test_util::server::TestServer;
 
 #[tokio::test]
 async fn test_gen_proof_in_progress() {
@@ -52,17 +50,17 @@ async fn test_gen_proof_in_progress() {
     // Create operator server and client
     let operator_server = axum_test::TestServer::new(operator::create_router(service)).unwrap();
 
-    let resp = operator_server.get("/status").await;
+    let resp = operator_server.get("/v2/status").await;
     let status = resp.json::<operator::ServiceState>();
     // It starts in idle state
     assert!(matches!(status, ServiceState::Idle));
 
-    // It transforms to Proving when a proof generation starts
+    // It transforms to ComputingPow/ReadingData when a proof generation starts
     let connected = test_server.connected.recv().await.unwrap();
 
     loop {
         let response = TestServer::generate_proof(&connected, vec![0xCA; 32]).await;
-        let resp = operator_server.get("/status").await;
+        let resp = operator_server.get("/v2/status").await;
         let status = resp.json::<operator::ServiceState>();
 
         if let Some(service_response::Kind::GenProof(resp)) = response.kind {
@@ -74,7 +72,9 @@ async fn test_gen_proof_in_progress() {
                     }
                     assert!(matches!(
                         status,
-                        ServiceState::Proving { .. } | ServiceState::DoneProving
+                        ServiceState::ComputingPow { .. }
+                            | ServiceState::ReadingData { .. }
+                            | ServiceState::DoneProving
                     ));
                 }
                 _ => {
@@ -87,3 +87,48 @@ async fn test_gen_proof_in_progress() {
         sleep(Duration::from_millis(10)).await;
     }
 }
+
+#[tokio::test]
+async fn test_changing_log_filter() {
+    post_service::logging::init("info").unwrap();
+    let router = operator::create_router(Arc::new(operator::MockService::new()));
+    let server = axum_test::TestServer::new(router).unwrap();
+    let filter = |filter: &str| operator::LogFilter {
+        filter: filter.to_string(),
+    };
+
+    let resp = server.get("/logging").await;
+    resp.assert_status_ok();
+    assert_eq!(filter("info"), resp.json::<operator::LogFilter>());
+
+    server
+        .put("/logging")
+        .json(&filter("info,post::prove=loud"))
+        .expect_failure()
+        .await
+        .assert_status_bad_request();
+
+    server
+        .put("/logging")
+        .json(&filter("info,post::prove=debug"))
+        .await
+        .assert_status_ok();
+    log::debug!(target: "post::prove", "debug log enabled");
+    log::debug!(target: "post::pow", "debug log disabled");
+    let messages: Vec<_> = post_service::logging::logs()
+        .recent()
+        .into_iter()
+        .map(|r| r.message)
+        .collect();
+    assert!(messages.contains(&"debug log enabled".to_string()));
+    assert!(!messages.contains(&"debug log disabled".to_string()));
+
+    // SIGUSR1 restores the initial filter, then enables the debug logs.
+    post_service::logging::toggle_debug().unwrap();
+    assert_eq!("info", post_service::logging::filter().unwrap());
+    post_service::logging::toggle_debug().unwrap();
+    assert_eq!(
+        "info,post=debug,post_service=debug",
+        post_service::logging::filter().unwrap()
+    );
+}
@@ -1,11 +1,23 @@
-use std::{thread::sleep, time::Duration};
+use std::{
+    thread::sleep,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
 
 use post::{
     config::{ProofConfig, ScryptParams},
     initialize::{CpuInitializer, Initialize},
+    metadata::ProofMetadata,
     pow::randomx::RandomXFlag,
+    prove::ProofWithMetadata,
+};
+use post_service::{
+    client::PostService,
+    events::Event,
+    history::ProofResult,
+    operator::{Service, ServiceState, SettingsUpdate},
+    schedule::Schedule,
+    service::{ChallengeError, ProofGenState, SelfVerification},
 };
-use post_service::{client::PostService, service::ProofGenState};
 
 use httpmock::prelude::*;
 
@@ -114,10 +126,598 @@ fn cannot_run_parallel_proof_gens() {
     assert!(matches!(result, Ok(ProofGenState::InProgress)));
     // Try to generate another proof with a different challenge
     assert!(service.gen_proof(&[0xBB; 5]).is_err());
+    let err = service.gen_proof(&[0xBB; 32]).unwrap_err();
+    assert!(matches!(
+        err.downcast_ref::<ChallengeError>(),
+        Some(ChallengeError::InProgress { .. } | ChallengeError::NotFetched { .. })
+    ));
     // Try again with the same challenge
     assert!(matches!(result, Ok(ProofGenState::InProgress)));
 }
 
+#[test]
+fn proof_is_kept_for_its_challenge() {
+    let datadir = tempfile::tempdir().unwrap();
+
+    let cfg = ProofConfig {
+        k1: 8,
+        k2: 4,
+        pow_difficulty: [0xFF; 32],
+    };
+    let scrypt = ScryptParams::new(2, 1, 1);
+
+    CpuInitializer::new(scrypt)
+        .initialize(datadir.path(), &[0xBE; 32], &[0xCE; 32], 256, 4, 256, None)
+        .unwrap();
+
+    let service = post_service::service::PostService::new(
+        datadir.into_path(),
+        cfg,
+        scrypt,
+        16,
+        post::config::Cores::Any(1),
+        RandomXFlag::get_recommended_flags(),
+        None,
+    )
+    .unwrap();
+
+    let proof = loop {
+        if let ProofGenState::Finished { proof } = service.gen_proof(&[0xAA; 32]).unwrap() {
+            break proof;
+        }
+        sleep(Duration::from_millis(10));
+    };
+
+    // Another node asks for a proof for a different challenge
+    let err = service.gen_proof(&[0xBB; 32]).unwrap_err();
+    assert!(matches!(
+        err.downcast_ref::<ChallengeError>(),
+        Some(ChallengeError::NotFetched { .. })
+    ));
+    assert!(service.verify_proof(&proof, &[0xBB; 32]).is_err());
+
+    // The proof is still available for the node that requested it
+    assert!(matches!(
+        service.gen_proof(&[0xAA; 32]),
+        Ok(ProofGenState::Finished { .. })
+    ));
+    service.verify_proof(&proof, &[0xAA; 32]).unwrap();
+
+    // Once fetched, a proof for another challenge can be generated
+    assert!(matches!(
+        service.gen_proof(&[0xBB; 32]),
+        Ok(ProofGenState::InProgress)
+    ));
+}
+
+#[test]
+fn proving_partial_data_requires_opt_in() {
+    let datadir = tempfile::tempdir().unwrap();
+
+    let cfg = ProofConfig {
+        k1: 8,
+        k2: 4,
+        pow_difficulty: [0xFF; 32],
+    };
+    let scrypt = ScryptParams::new(2, 1, 1);
+
+    CpuInitializer::new(scrypt)
+        .initialize(datadir.path(), &[0xBE; 32], &[0xCE; 32], 256, 4, 256, None)
+        .unwrap();
+    // Only 2 of the 4 units are initialized completely.
+    let metadata = post::metadata::PostMetadata {
+        last_position: Some(2 * 256 + 100),
+        ..post::metadata::load(datadir.path()).unwrap()
+    };
+    let metadata_file = datadir.path().join(post::metadata::METADATA_FILE_NAME);
+    std::fs::write(metadata_file, serde_json::to_vec(&metadata).unwrap()).unwrap();
+
+    let new_service = || {
+        post_service::service::PostService::new(
+            datadir.path().to_path_buf(),
+            cfg,
+            scrypt,
+            16,
+            post::config::Cores::Any(1),
+            RandomXFlag::get_recommended_flags(),
+            None,
+        )
+        .unwrap()
+    };
+
+    let service = new_service();
+    assert_eq!(4, service.get_metadata().num_units);
+    let err = service.gen_proof(&[0xCA; 32]).unwrap_err();
+    assert!(matches!(
+        err.downcast_ref::<post::Error>(),
+        Some(post::Error::PartiallyInitialized {
+            units: 2,
+            total_units: 4
+        })
+    ));
+    drop(service);
+
+    let service = new_service().with_partial_data().unwrap();
+    assert_eq!(2, service.get_metadata().num_units);
+    let proof = loop {
+        if let ProofGenState::Finished { proof } = service.gen_proof(&[0xCA; 32]).unwrap() {
+            break proof;
+        }
+        sleep(Duration::from_millis(10));
+    };
+    service.verify_proof(&proof, &[0xCA; 32]).unwrap();
+}
+
+#[test]
+fn skipping_self_verification() {
+    let datadir = tempfile::tempdir().unwrap();
+
+    let cfg = ProofConfig {
+        k1: 8,
+        k2: 4,
+        pow_difficulty: [0xFF; 32],
+    };
+    let scrypt = ScryptParams::new(2, 1, 1);
+
+    CpuInitializer::new(scrypt)
+        .initialize(datadir.path(), &[0xBE; 32], &[0xCE; 32], 256, 4, 256, None)
+        .unwrap();
+
+    let new_service = |self_verification| {
+        post_service::service::PostService::new(
+            datadir.path().to_path_buf(),
+            cfg,
+            scrypt,
+            16,
+            post::config::Cores::Any(1),
+            RandomXFlag::get_recommended_flags(),
+            None,
+        )
+        .unwrap()
+        .with_self_verification(self_verification)
+    };
+
+    let invalid = post::prove::Proof {
+        nonce: 0,
+        indices: std::borrow::Cow::Owned(vec![0xFF; 4]),
+        pow: 0,
+    };
+    assert!(new_service(SelfVerification::All)
+        .verify_proof(&invalid, &[0xAA; 32])
+        .is_err());
+    assert!(new_service(SelfVerification::Subset { k3: 2 })
+        .verify_proof(&invalid, &[0xAA; 32])
+        .is_err());
+    new_service(SelfVerification::Skip)
+        .verify_proof(&invalid, &[0xAA; 32])
+        .unwrap();
+}
+
+#[test]
+fn reject_invalid_challenge_length() {
+    let datadir = tempfile::tempdir().unwrap();
+
+    let cfg = ProofConfig {
+        k1: 8,
+        k2: 4,
+        pow_difficulty: [0xFF; 32],
+    };
+    let scrypt = ScryptParams::new(2, 1, 1);
+
+    CpuInitializer::new(scrypt)
+        .initialize(datadir.path(), &[0xBE; 32], &[0xCE; 32], 256, 4, 256, None)
+        .unwrap();
+
+    let service = post_service::service::PostService::new(
+        datadir.into_path(),
+        cfg,
+        scrypt,
+        16,
+        post::config::Cores::Any(1),
+        RandomXFlag::get_recommended_flags(),
+        None,
+    )
+    .unwrap();
+
+    let err = service.gen_proof(&[0xAA; 5]).unwrap_err();
+    assert!(matches!(
+        err.downcast_ref::<ChallengeError>(),
+        Some(ChallengeError::InvalidLength(5))
+    ));
+}
+
+#[test]
+fn cached_proof_survives_restart() {
+    let datadir = tempfile::tempdir().unwrap();
+
+    let cfg = ProofConfig {
+        k1: 8,
+        k2: 4,
+        pow_difficulty: [0xFF; 32],
+    };
+    let scrypt = ScryptParams::new(2, 1, 1);
+
+    CpuInitializer::new(scrypt)
+        .initialize(datadir.path(), &[0xBE; 32], &[0xCE; 32], 256, 4, 256, None)
+        .unwrap();
+
+    let new_service = || {
+        post_service::service::PostService::new(
+            datadir.path().to_path_buf(),
+            cfg,
+            scrypt,
+            16,
+            post::config::Cores::Any(1),
+            RandomXFlag::get_recommended_flags(),
+            None,
+        )
+        .unwrap()
+    };
+
+    let service = new_service();
+    let proof = loop {
+        if let ProofGenState::Finished { proof } = service.gen_proof(&[0xAA; 32]).unwrap() {
+            break proof;
+        }
+        sleep(Duration::from_millis(10));
+    };
+    service.verify_proof(&proof, &[0xAA; 32]).unwrap();
+
+    // The events of the proof generation are recorded
+    let events: Vec<_> = service
+        .proof_events()
+        .unwrap()
+        .into_iter()
+        .map(|r| r.event)
+        .collect();
+    assert!(matches!(
+        events.first(),
+        Some(Event::Started { challenge, nonces: 16 }) if challenge == &[0xAA; 32]
+    ));
+    assert!(events.contains(&Event::PassStarted { nonces: 0..16 }));
+    assert!(events
+        .iter()
+        .any(|e| matches!(e, Event::K2pow { nonce_group: 0, .. })));
+    assert!(events
+        .iter()
+        .any(|e| matches!(e, Event::PassFinished { nonces, .. } if *nonces == (0..16))));
+    assert_eq!(
+        Some(&Event::Finished {
+            proof: proof.clone()
+        }),
+        events.last()
+    );
+
+    // And summarized in the history
+    let history = service.proof_history();
+    assert_eq!(1, history.len());
+    assert_eq!([0xAA; 32], history[0].challenge);
+    assert!(history[0].passes >= 1);
+    assert_eq!(
+        ProofResult::Found {
+            nonce: proof.nonce,
+            pow: proof.pow
+        },
+        history[0].result
+    );
+
+    // The same proof is returned immediately, also after a restart
+    let returns_cached_proof = |service: &post_service::service::PostService| {
+        let last_proof = service.last_proof().unwrap();
+        assert_eq!([0xAA; 32], last_proof.challenge);
+        assert_eq!(proof, last_proof.proof);
+        match service.gen_proof(&[0xAA; 32]) {
+            Ok(ProofGenState::Finished { proof: cached }) => assert_eq!(proof, cached),
+            other => panic!("expected cached proof, got {other:?}"),
+        }
+    };
+    returns_cached_proof(&service);
+    drop(service);
+    let service = new_service();
+    returns_cached_proof(&service);
+    assert_eq!(history, service.proof_history());
+    drop(service);
+
+    // Not reused once the POS data has changed
+    let metadata_path = datadir.path().join(post::metadata::METADATA_FILE_NAME);
+    let metadata = post::metadata::load(datadir.path()).unwrap();
+    let changed = post::metadata::PostMetadata {
+        nonce: Some(7),
+        ..metadata
+    };
+    std::fs::write(&metadata_path, serde_json::to_vec(&changed).unwrap()).unwrap();
+    let service = new_service();
+    assert!(matches!(
+        service.gen_proof(&[0xAA; 32]),
+        Ok(ProofGenState::InProgress)
+    ));
+    drop(service);
+    std::fs::write(&metadata_path, serde_json::to_vec(&metadata).unwrap()).unwrap();
+
+    // A new challenge is proven
+    std::fs::remove_file(datadir.path().join("proof_cache.json")).unwrap();
+    let service = new_service();
+    assert!(service.last_proof().is_none());
+    assert!(matches!(
+        service.gen_proof(&[0xBB; 32]),
+        Ok(ProofGenState::InProgress)
+    ));
+}
+
+#[test]
+fn writing_proofs_to_dir() {
+    let datadir = tempfile::tempdir().unwrap();
+    let proof_dir = tempfile::tempdir().unwrap();
+
+    let cfg = ProofConfig {
+        k1: 8,
+        k2: 4,
+        pow_difficulty: [0xFF; 32],
+    };
+    let scrypt = ScryptParams::new(2, 1, 1);
+
+    CpuInitializer::new(scrypt)
+        .initialize(datadir.path(), &[0xBE; 32], &[0xCE; 32], 256, 4, 256, None)
+        .unwrap();
+
+    let service = post_service::service::PostService::new(
+        datadir.path().to_path_buf(),
+        cfg,
+        scrypt,
+        16,
+        post::config::Cores::Any(1),
+        RandomXFlag::get_recommended_flags(),
+        None,
+    )
+    .unwrap()
+    .with_proof_dir(Some(proof_dir.path().join("proofs")));
+
+    let proof = loop {
+        if let ProofGenState::Finished { proof } = service.gen_proof(&[0xAA; 32]).unwrap() {
+            break proof;
+        }
+        sleep(Duration::from_millis(10));
+    };
+    service.verify_proof(&proof, &[0xAA; 32]).unwrap();
+
+    let path = proof_dir
+        .path()
+        .join("proofs")
+        .join(format!("proof_{}.json", hex::encode([0xAA; 32])));
+    let written = ProofWithMetadata::load(&path).unwrap();
+    assert_eq!(proof, written.proof);
+    assert_eq!(
+        ProofMetadata {
+            node_id: [0xBE; 32],
+            commitment_atx_id: [0xCE; 32],
+            challenge: [0xAA; 32],
+            num_units: 4,
+        },
+        written.metadata
+    );
+}
+
+#[test]
+fn datadir_is_locked() {
+    let datadir = tempfile::tempdir().unwrap();
+    let scrypt = ScryptParams::new(2, 1, 1);
+    CpuInitializer::new(scrypt)
+        .initialize(datadir.path(), &[0xBE; 32], &[0xCE; 32], 256, 4, 256, None)
+        .unwrap();
+
+    let new_service = || {
+        post_service::service::PostService::new(
+            datadir.path().to_path_buf(),
+            ProofConfig {
+                k1: 8,
+                k2: 4,
+                pow_difficulty: [0xFF; 32],
+            },
+            scrypt,
+            16,
+            post::config::Cores::Any(1),
+            RandomXFlag::get_recommended_flags(),
+            None,
+        )
+    };
+
+    let _service = new_service().unwrap();
+    // Another service would share the state directory
+    assert!(new_service().is_err());
+    assert!(post::initialize::wipe(datadir.path(), Default::default()).is_err());
+    // Reading the POS data is fine
+    post::lock::DataDirLock::acquire_shared(datadir.path()).unwrap();
+}
+
+#[test]
+fn proving_is_paused_outside_of_windows() {
+    let datadir = tempfile::tempdir().unwrap();
+
+    let cfg = ProofConfig {
+        k1: 8,
+        k2: 4,
+        pow_difficulty: [0xFF; 32],
+    };
+    let scrypt = ScryptParams::new(2, 1, 1);
+
+    CpuInitializer::new(scrypt)
+        .initialize(datadir.path(), &[0xBE; 32], &[0xCE; 32], 256, 4, 256, None)
+        .unwrap();
+
+    // A window that doesn't include the current time
+    let minute = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs()
+        / 60
+        % (24 * 60);
+    let start = (minute + 60) % (24 * 60);
+    let end = (minute + 120) % (24 * 60);
+    let window = format!(
+        "{:02}:{:02}-{:02}:{:02}",
+        start / 60,
+        start % 60,
+        end / 60,
+        end % 60
+    );
+
+    let service = post_service::service::PostService::new(
+        datadir.into_path(),
+        cfg,
+        scrypt,
+        16,
+        post::config::Cores::Any(1),
+        RandomXFlag::get_recommended_flags(),
+        None,
+    )
+    .unwrap()
+    .with_schedule(Schedule::new(vec![window.parse().unwrap()]));
+
+    assert!(matches!(
+        service.gen_proof(&[0xCA; 32]),
+        Ok(ProofGenState::InProgress)
+    ));
+    // The k2pow is calculated outside of the windows too, only reading the data waits.
+    while !matches!(service.status(), ServiceState::Scheduled { .. }) {
+        assert!(matches!(
+            service.status(),
+            ServiceState::ComputingPow { .. } | ServiceState::Scheduled { .. }
+        ));
+        sleep(Duration::from_millis(10));
+    }
+    assert!(matches!(
+        service.status(),
+        ServiceState::Scheduled { position: 0, .. }
+    ));
+
+    // Resumes in the window
+    service.apply_schedule(SystemTime::now() + Duration::from_secs(90 * 60));
+    let proof = loop {
+        if let ProofGenState::Finished { proof } = service.gen_proof(&[0xCA; 32]).unwrap() {
+            break proof;
+        }
+        sleep(Duration::from_millis(10));
+    };
+    service.verify_proof(&proof, &[0xCA; 32]).unwrap();
+}
+
+#[test]
+fn updating_settings() {
+    let datadir = tempfile::tempdir().unwrap();
+
+    let cfg = ProofConfig {
+        k1: 8,
+        k2: 4,
+        pow_difficulty: [0xFF; 32],
+    };
+    let scrypt = ScryptParams::new(2, 1, 1);
+
+    CpuInitializer::new(scrypt)
+        .initialize(datadir.path(), &[0xBE; 32], &[0xCE; 32], 256, 4, 256, None)
+        .unwrap();
+
+    let service = post_service::service::PostService::new(
+        datadir.into_path(),
+        cfg,
+        scrypt,
+        16,
+        post::config::Cores::Any(1),
+        RandomXFlag::get_recommended_flags(),
+        None,
+    )
+    .unwrap();
+
+    service
+        .update_settings(SettingsUpdate {
+            threads: Some(2),
+            nonces: Some(20),
+            ..Default::default()
+        })
+        .unwrap();
+    service
+        .update_settings(SettingsUpdate {
+            pinned_cores: Some(vec![0]),
+            ..Default::default()
+        })
+        .unwrap();
+
+    // invalid updates are rejected
+    for update in [
+        SettingsUpdate {
+            threads: Some(1),
+            pinned_cores: Some(vec![0]),
+            ..Default::default()
+        },
+        SettingsUpdate {
+            pinned_cores: Some(vec![]),
+            ..Default::default()
+        },
+        SettingsUpdate {
+            nonces: Some(0),
+            ..Default::default()
+        },
+        SettingsUpdate {
+            nonces: Some(16 * 257),
+            ..Default::default()
+        },
+        SettingsUpdate {
+            log_level: Some("post=loud".to_string()),
+            ..Default::default()
+        },
+    ] {
+        assert!(service.update_settings(update).is_err());
+    }
+}
+
+#[test]
+fn planning_again_for_new_threads() {
+    let datadir = tempfile::tempdir().unwrap();
+
+    let cfg = ProofConfig {
+        k1: 8,
+        k2: 4,
+        pow_difficulty: [0xFF; 32],
+    };
+    let scrypt = ScryptParams::new(2, 1, 1);
+
+    CpuInitializer::new(scrypt)
+        .initialize(datadir.path(), &[0xBE; 32], &[0xCE; 32], 256, 4, 256, None)
+        .unwrap();
+
+    let service = post_service::service::PostService::new(
+        datadir.path().to_path_buf(),
+        cfg,
+        scrypt,
+        16,
+        post::config::Cores::Any(1),
+        RandomXFlag::get_recommended_flags(),
+        None,
+    )
+    .unwrap()
+    .with_planned_nonces(0.99)
+    .unwrap();
+    let plan = service.proving_plan().unwrap();
+
+    service
+        .update_settings(SettingsUpdate {
+            threads: Some(2),
+            ..Default::default()
+        })
+        .unwrap();
+    let replanned = service.proving_plan().unwrap();
+    assert_eq!(plan.target_probability, replanned.target_probability);
+    assert_eq!(plan.throughput.read, replanned.throughput.read);
+
+    // Setting the nonces discards the plan
+    service
+        .update_settings(SettingsUpdate {
+            nonces: Some(32),
+            ..Default::default()
+        })
+        .unwrap();
+    assert_eq!(None, service.proving_plan());
+}
+
 #[tokio::test]
 async fn remote_k2pow() {
     let server = MockServer::start();
@@ -151,6 +751,7 @@ async fn remote_k2pow() {
         Some(post_service::service::K2powConfig {
             url: server.url(""),
             parallelism: 1,
+            identity_parallelism: 1,
             backoff: Duration::from_millis(1),
         }),
     )
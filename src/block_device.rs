@@ -0,0 +1,332 @@
+//! POS data on a raw block device (i.e. `/dev/sdb`).
+//!
+//! The device starts with a superblock holding the POST metadata,
+//! followed by the labels. It avoids the overhead of a filesystem
+//! on disks dedicated to POST data. A regular file with a superblock
+//! (i.e. an image of such a device) is handled the same way.
+//!
+//! The device has no room for the files kept along POS data in a data directory
+//! (i.e. caches and the proof history), they go to a separate [state_dir].
+//!
+//! Superblock layout ([SUPERBLOCK_SIZE] bytes):
+//! - magic (8B): `POSTBLK1`
+//! - length of the metadata (4B, LE)
+//! - metadata (JSON)
+//! - zero padding
+
+use std::{
+    fs::{File, OpenOptions},
+    io::{BufWriter, Read, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    error::Error,
+    initialize::{calc_commitment, Initialize, LABEL_SIZE},
+    lock::DataDirLock,
+    metadata::PostMetadata,
+    reader::{Batch, BatchingReader, PosDataSource},
+};
+
+/// Size of the superblock at the start of the device. The labels start right after it.
+pub const SUPERBLOCK_SIZE: u64 = 4096;
+const MAGIC: &[u8; 8] = b"POSTBLK1";
+
+/// Is `path` POS data on a block device (or in a file with a superblock) rather than a data directory.
+pub fn is_block_data(path: &Path) -> bool {
+    let Ok(metadata) = path.metadata() else {
+        return false;
+    };
+    #[cfg(unix)]
+    if std::os::unix::fs::FileTypeExt::is_block_device(&metadata.file_type()) {
+        return true;
+    }
+    metadata.is_file() && has_superblock(path)
+}
+
+fn has_superblock(path: &Path) -> bool {
+    let mut magic = [0u8; MAGIC.len()];
+    File::open(path)
+        .and_then(|mut file| file.read_exact(&mut magic))
+        .is_ok_and(|_| &magic == MAGIC)
+}
+
+/// The directory for the files kept along the POS data in `datadir` (i.e. caches).
+///
+/// It's the data directory itself. For POS data on a block device it's
+/// `post-state-<device name>` in the temporary directory,
+/// which might not survive a reboot, so better configure another one.
+pub fn state_dir(datadir: &Path) -> PathBuf {
+    if !is_block_data(datadir) {
+        return datadir.to_path_buf();
+    }
+    let name = datadir
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    std::env::temp_dir().join(format!("post-state-{name}"))
+}
+
+/// Initialize POS data on the device at `path`, overwriting its content.
+///
+/// The superblock is cleared first and written last, so a partially (re)initialized
+/// device is not recognized as POS data.
+#[allow(clippy::too_many_arguments)]
+pub fn initialize(
+    initializer: &mut dyn Initialize,
+    path: &Path,
+    node_id: &[u8; 32],
+    commitment_atx_id: &[u8; 32],
+    labels_per_unit: u64,
+    num_units: u32,
+    vrf_difficulty: Option<[u8; 32]>,
+) -> Result<PostMetadata, Error> {
+    let total_labels = labels_per_unit * num_units as u64;
+    let mut device = OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(false)
+        .open(path)?;
+    let _lock = DataDirLock::acquire(path)?;
+
+    // Regular files grow as needed, block devices have a fixed capacity.
+    let capacity = device.seek(SeekFrom::End(0))?;
+    let required = SUPERBLOCK_SIZE + total_labels * LABEL_SIZE as u64;
+    if path.metadata()?.file_type().is_file() {
+        device.set_len(required)?;
+    } else if capacity < required {
+        return Err(Error::InvalidArgument(format!(
+            "{} is too small: {capacity} < {required} bytes",
+            path.display()
+        )));
+    }
+
+    // Invalidate the superblock of previous POS data before overwriting its labels.
+    device.seek(SeekFrom::Start(0))?;
+    device.write_all(&[0u8; SUPERBLOCK_SIZE as usize])?;
+    device.sync_data()?;
+
+    let commitment = calc_commitment(node_id, commitment_atx_id);
+    device.seek(SeekFrom::Start(SUPERBLOCK_SIZE))?;
+    let mut writer = BufWriter::new(&device);
+    let nonce =
+        initializer.initialize_to(&mut writer, &commitment, 0..total_labels, vrf_difficulty)?;
+    writer.flush()?;
+    drop(writer);
+    device.sync_data()?;
+
+    let metadata = PostMetadata {
+        node_id: *node_id,
+        commitment_atx_id: *commitment_atx_id,
+        labels_per_unit,
+        num_units,
+        // All labels are in a single "file"
+        max_file_size: total_labels * LABEL_SIZE as u64,
+        nonce: nonce.map(|n| n.index),
+        last_position: None,
+    };
+    write_superblock(&mut device, &metadata)?;
+    device.sync_all()?;
+    Ok(metadata)
+}
+
+fn write_superblock(device: &mut File, metadata: &PostMetadata) -> Result<(), Error> {
+    let json = serde_json::to_vec(metadata)?;
+    let mut superblock = vec![0u8; SUPERBLOCK_SIZE as usize];
+    let end = MAGIC.len() + 4 + json.len();
+    if end > superblock.len() {
+        return Err(Error::InvalidMetadata(
+            "metadata doesn't fit in the superblock".into(),
+        ));
+    }
+    superblock[..MAGIC.len()].copy_from_slice(MAGIC);
+    superblock[MAGIC.len()..MAGIC.len() + 4].copy_from_slice(&(json.len() as u32).to_le_bytes());
+    superblock[MAGIC.len() + 4..end].copy_from_slice(&json);
+    device.seek(SeekFrom::Start(0))?;
+    device.write_all(&superblock)?;
+    Ok(())
+}
+
+/// Load the POST metadata from the superblock of the device.
+pub fn load_metadata(path: &Path) -> Result<PostMetadata, Error> {
+    let mut superblock = vec![0u8; SUPERBLOCK_SIZE as usize];
+    File::open(path)?.read_exact(&mut superblock)?;
+    if !superblock.starts_with(MAGIC) {
+        return Err(Error::InvalidMetadata(format!(
+            "{} doesn't contain POS data (missing superblock)",
+            path.display()
+        )));
+    }
+    let len = u32::from_le_bytes(superblock[MAGIC.len()..MAGIC.len() + 4].try_into().unwrap());
+    let json = superblock
+        .get(MAGIC.len() + 4..MAGIC.len() + 4 + len as usize)
+        .ok_or_else(|| {
+            Error::InvalidMetadata(format!("invalid metadata length in superblock: {len}"))
+        })?;
+    Ok(serde_json::from_slice(json)?)
+}
+
+/// POS data on a block device initialized with [initialize].
+#[derive(Debug, Clone)]
+pub struct BlockDevice(pub PathBuf);
+
+impl PosDataSource for BlockDevice {
+    fn read(
+        &self,
+        offset: u64,
+        len: u64,
+        batch_size: usize,
+    ) -> eyre::Result<Box<dyn Iterator<Item = std::io::Result<Batch>> + Send>> {
+        log::info!("Reading device: {}", self.0.display());
+        let mut device = File::open(&self.0)?;
+        device.seek(SeekFrom::Start(SUPERBLOCK_SIZE + offset))?;
+        Ok(Box::new(BatchingReader::new(
+            device, offset, batch_size, len,
+        )))
+    }
+}
+
+/// Labels on a device initialized with [initialize], starting at `offset` bytes into the labels.
+///
+/// Positions are relative to `offset`, so it reads and seeks like a `postdata_{idx}.bin` file.
+#[derive(Debug)]
+pub struct Labels {
+    device: File,
+    start: u64,
+}
+
+impl Labels {
+    pub fn open(path: &Path, offset: u64) -> std::io::Result<Self> {
+        let start = SUPERBLOCK_SIZE + offset;
+        let mut device = File::open(path)?;
+        device.seek(SeekFrom::Start(start))?;
+        Ok(Self { device, start })
+    }
+}
+
+impl Read for Labels {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        self.device.read(buf)
+    }
+}
+
+impl Seek for Labels {
+    fn seek(&mut self, pos: SeekFrom) -> std::io::Result<u64> {
+        let pos = match pos {
+            SeekFrom::Start(pos) => SeekFrom::Start(self.start + pos),
+            pos => pos,
+        };
+        let pos = self.device.seek(pos)?;
+        pos.checked_sub(self.start).ok_or_else(|| {
+            std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                "seeking before the start of the labels",
+            )
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{config::ScryptParams, initialize::CpuInitializer, metadata};
+
+    #[test]
+    fn initialize_and_read() {
+        let tmp = tempfile::tempdir().unwrap();
+        let scrypt = ScryptParams::new(2, 1, 1);
+
+        // The same labels as in files
+        let datadir = tmp.path().join("files");
+        CpuInitializer::new(scrypt)
+            .initialize(&datadir, &[1; 32], &[2; 32], 100, 3, 64, Some([0xFF; 32]))
+            .unwrap();
+        let expected = (0..5)
+            .flat_map(|id| std::fs::read(datadir.join(format!("postdata_{id}.bin"))).unwrap())
+            .collect::<Vec<_>>();
+
+        let device = tmp.path().join("device");
+        let metadata = initialize(
+            &mut CpuInitializer::new(scrypt),
+            &device,
+            &[1; 32],
+            &[2; 32],
+            100,
+            3,
+            Some([0xFF; 32]),
+        )
+        .unwrap();
+        assert!(is_block_data(&device));
+        assert!(!is_block_data(&datadir));
+        assert_eq!(datadir, state_dir(&datadir));
+        assert_ne!(device, state_dir(&device));
+
+        let loaded = metadata::load(&device).unwrap();
+        assert_eq!(metadata.num_units, loaded.num_units);
+        assert_eq!(metadata.nonce, loaded.nonce);
+        assert_eq!(metadata::load(&datadir).unwrap().nonce, loaded.nonce);
+
+        let data = BlockDevice(device.clone())
+            .read(0, metadata.total_size(), 1000)
+            .unwrap()
+            .flat_map(|b| b.unwrap().data)
+            .collect::<Vec<_>>();
+        assert_eq!(expected, data);
+
+        let batches = BlockDevice(device.clone())
+            .read(16, 32, 1000)
+            .unwrap()
+            .map(Result::unwrap)
+            .collect::<Vec<_>>();
+        assert_eq!(
+            vec![Batch {
+                data: expected[16..48].to_vec(),
+                pos: 16
+            }],
+            batches
+        );
+
+        let mut labels = Labels::open(&device, 32).unwrap();
+        let mut label = [0u8; 16];
+        labels.read_exact(&mut label).unwrap();
+        assert_eq!(expected[32..48], label);
+        assert_eq!(64, labels.seek(SeekFrom::Start(64)).unwrap());
+        labels.read_exact(&mut label).unwrap();
+        assert_eq!(expected[96..112], label);
+        assert!(labels.seek(SeekFrom::Current(-200)).is_err());
+    }
+
+    #[test]
+    fn rejects_data_without_superblock() {
+        let tmp = tempfile::NamedTempFile::new().unwrap();
+        std::fs::write(tmp.path(), vec![0u8; SUPERBLOCK_SIZE as usize]).unwrap();
+        assert!(!is_block_data(tmp.path()));
+        assert!(load_metadata(tmp.path()).is_err());
+    }
+
+    #[test]
+    fn interrupted_reinitialization_invalidates_superblock() {
+        let tmp = tempfile::tempdir().unwrap();
+        let device = tmp.path().join("device");
+        let scrypt = ScryptParams::new(2, 1, 1);
+        initialize(
+            &mut CpuInitializer::new(scrypt),
+            &device,
+            &[1; 32],
+            &[2; 32],
+            100,
+            1,
+            None,
+        )
+        .unwrap();
+        assert!(is_block_data(&device));
+
+        let mut failing = crate::initialize::MockInitialize::new();
+        failing
+            .expect_initialize_to()
+            .returning(|_, _, _, _| Err(Error::InvalidArgument("interrupted".into())));
+        initialize(&mut failing, &device, &[1; 32], &[2; 32], 100, 1, None).unwrap_err();
+        assert!(!is_block_data(&device));
+        assert!(load_metadata(&device).is_err());
+    }
+}
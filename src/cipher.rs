@@ -1,44 +1,92 @@
-use aes::cipher::{generic_array::GenericArray, KeyInit};
+//! The AES ciphers of proving.
+//!
+//! A label (16 bytes) is a candidate for a proof with a nonce if its encryption
+//! is below the proving difficulty. The difficulty (`u64`) is split into its most
+//! significant byte `difficulty_msb = difficulty >> 56` and its 56 lowest bits
+//! `difficulty_lsb = difficulty & 0x00ff_ffff_ffff_ffff`:
+//!
+//! 1. `msb` is the byte at `nonce % 16` of the label encrypted with the cipher of the
+//!    nonce group ([AesCipher::new], `nonce_group = nonce / 16`). The label qualifies
+//!    if `msb < difficulty_msb` and doesn't if `msb > difficulty_msb`.
+//! 2. Only if `msb == difficulty_msb`, `lsb` is the 56 lowest bits of the label encrypted
+//!    with the cipher of the nonce ([AesCipher::new_lazy]) read as a little-endian `u64`.
+//!    The label qualifies if `lsb < difficulty_lsb`.
+//!
+//! The `pow` is the k2pow found for the nonce group. Known answers are in the tests
+//! of this module, more vectors are generated with the `test-vectors` feature.
+
+use aes::cipher::{generic_array::GenericArray, BlockEncrypt, KeyInit};
 use aes::Aes128;
 
+/// AES-128 cipher of a nonce group (or of a single nonce).
 #[derive(Debug)]
-pub(crate) struct AesCipher {
+pub struct AesCipher {
     pub(crate) aes: Aes128,
     pub(crate) nonce_group: u32,
     pub(crate) pow: u64,
 }
 
+/// The AES key of a nonce group: the first 16 bytes of
+/// `blake3(challenge || nonce_group (u32 LE) || pow (u64 LE))`.
+pub fn key(challenge: &[u8; 32], nonce_group: u32, pow: u64) -> [u8; 16] {
+    let mut hasher = blake3::Hasher::new();
+    hasher.update(challenge);
+    hasher.update(&nonce_group.to_le_bytes());
+    hasher.update(&pow.to_le_bytes());
+    hasher.finalize().as_bytes()[..16].try_into().unwrap()
+}
+
+/// The AES key of a single nonce: the first 16 bytes of
+/// `blake3(challenge || nonce_group (u32 LE) || pow (u64 LE) || nonce (u32 LE))`.
+pub fn lazy_key(challenge: &[u8; 32], nonce: u32, nonce_group: u32, pow: u64) -> [u8; 16] {
+    let mut hasher = blake3::Hasher::new();
+    hasher.update(challenge);
+    hasher.update(&nonce_group.to_le_bytes());
+    hasher.update(&pow.to_le_bytes());
+    hasher.update(&nonce.to_le_bytes());
+    hasher.finalize().as_bytes()[..16].try_into().unwrap()
+}
+
 impl AesCipher {
-    /// Create new AES cipher for the given challenge and nonce.
-    /// AES key = blake3(challenge, nonce_group, pow)
-    pub(crate) fn new(challenge: &[u8; 32], nonce_group: u32, pow: u64) -> Self {
-        let mut hasher = blake3::Hasher::new();
-        hasher.update(challenge);
-        hasher.update(&nonce_group.to_le_bytes());
-        hasher.update(&pow.to_le_bytes());
-        Self {
-            aes: Aes128::new(GenericArray::from_slice(
-                &hasher.finalize().as_bytes()[..16],
-            )),
+    /// Create the cipher of the nonce group (checking the MSB of the difficulty).
+    /// AES key = blake3(challenge, nonce_group, pow), see [key].
+    pub fn new(challenge: &[u8; 32], nonce_group: u32, pow: u64) -> Self {
+        Self::with_key(&key(challenge, nonce_group, pow), nonce_group, pow)
+    }
+
+    /// Create the cipher of a single nonce (checking the LSB of the difficulty).
+    /// AES key = blake3(challenge, nonce_group, pow, nonce), see [lazy_key].
+    pub fn new_lazy(challenge: &[u8; 32], nonce: u32, nonce_group: u32, pow: u64) -> Self {
+        Self::with_key(
+            &lazy_key(challenge, nonce, nonce_group, pow),
             nonce_group,
             pow,
-        }
+        )
     }
 
-    pub(crate) fn new_lazy(challenge: &[u8; 32], nonce: u32, nonce_group: u32, pow: u64) -> Self {
-        let mut hasher = blake3::Hasher::new();
-        hasher.update(challenge);
-        hasher.update(&nonce_group.to_le_bytes());
-        hasher.update(&pow.to_le_bytes());
-        hasher.update(&nonce.to_le_bytes());
+    fn with_key(key: &[u8; 16], nonce_group: u32, pow: u64) -> Self {
         Self {
-            aes: Aes128::new(GenericArray::from_slice(
-                &hasher.finalize().as_bytes()[..16],
-            )),
+            aes: Aes128::new(GenericArray::from_slice(key)),
             nonce_group,
             pow,
         }
     }
+
+    /// Encrypt a single block (a label).
+    pub fn encrypt(&self, block: &[u8; 16]) -> [u8; 16] {
+        let mut output = [0u8; 16];
+        self.aes
+            .encrypt_block_b2b(block.into(), (&mut output).into());
+        output
+    }
+
+    pub fn nonce_group(&self) -> u32 {
+        self.nonce_group
+    }
+
+    pub fn pow(&self) -> u64 {
+        self.pow
+    }
 }
 
 #[cfg(test)]
@@ -86,4 +134,46 @@ mod tests {
             }
         }
     }
+
+    #[test]
+    fn known_answers() {
+        let challenge = [0xCA; 32];
+        let label = [0xAB; 16];
+        // (nonce, pow, key, nonce group cipher output, nonce cipher output)
+        let vectors = [
+            (
+                0,
+                0,
+                "ef8b7731d15ec193b693a33de78b5350",
+                "6b684cba181462570a634173ed3bd50f",
+                "b341646ae52bcaf4cefa0d64881e0edd",
+            ),
+            (
+                17,
+                7,
+                "e64c94227ac3d988ee8d4063d9fe415b",
+                "e7c7d4f29f0f3938a5029fb2b90f5feb",
+                "f4879165aeab447a8a4fc6103b681ddc",
+            ),
+            (
+                4095,
+                u64::MAX,
+                "80d0638fff9ee499623117d44c857796",
+                "0485ef35c809f84d71d8a46964ab9a40",
+                "07901e3f638afcf1a6b8803b0fca7358",
+            ),
+        ];
+        for (nonce, pow, key, group_out, nonce_out) in vectors {
+            let nonce_group = nonce / 16;
+            assert_eq!(key, hex::encode(super::key(&challenge, nonce_group, pow)));
+
+            let cipher = AesCipher::new(&challenge, nonce_group, pow);
+            assert_eq!(nonce_group, cipher.nonce_group());
+            assert_eq!(pow, cipher.pow());
+            assert_eq!(group_out, hex::encode(cipher.encrypt(&label)));
+
+            let lazy = AesCipher::new_lazy(&challenge, nonce, nonce_group, pow);
+            assert_eq!(nonce_out, hex::encode(lazy.encrypt(&label)));
+        }
+    }
 }
@@ -1,8 +1,25 @@
+//! Bit-packing of proof indices.
+//!
+//! Each index is stored on the lowest `bits` bits (little endian),
+//! where `bits` is the number of bits needed to store the largest possible index
+//! (see [required_bits]).
+
 use bitvec::prelude::*;
 use bitvec::{slice::BitSlice, view::BitView};
 
+#[derive(thiserror::Error, Debug, Clone, PartialEq, Eq)]
+pub enum Error {
+    #[error("invalid number of bits per index: {0} (must be between 1 and 64)")]
+    InvalidBitsPerIndex(usize),
+    #[error("invalid length of compressed indices (expected: {expected} bytes, got: {got})")]
+    InvalidLength { expected: usize, got: usize },
+}
+
 /// Compress indexes into a byte slice.
 /// The number of bits used to store each index is `keep_bits`.
+///
+/// # Panics
+/// If `keep_bits` is greater than 64.
 pub fn compress_indices(indexes: &[u64], keep_bits: usize) -> Vec<u8> {
     let mut bv = bitvec![u8, Lsb0;];
     for index in indexes {
@@ -13,12 +30,78 @@ pub fn compress_indices(indexes: &[u64], keep_bits: usize) -> Vec<u8> {
 
 /// Decompress indexes from a byte slice, previously compressed with `compress_indices`.
 /// Might return more indexes than the original, if the last byte contains unused bits.
+///
+/// See [CompressedIndices] for a variant validating the input.
+///
+/// # Panics
+/// If `bits` is 0.
 pub fn decompress_indexes(indexes: &[u8], bits: usize) -> impl Iterator<Item = u64> + '_ {
     BitSlice::<_, Lsb0>::from_slice(indexes)
         .chunks_exact(bits)
         .map(|chunk| chunk.load_le::<u64>())
 }
 
+/// A view of `count` indices compressed with [compress_indices].
+///
+/// The indices are decoded on access, without allocating.
+#[derive(Debug, Clone, Copy)]
+pub struct CompressedIndices<'a> {
+    bits: &'a BitSlice<u8, Lsb0>,
+    bits_per_index: usize,
+    count: usize,
+}
+
+impl<'a> CompressedIndices<'a> {
+    /// Wrap `data` holding exactly `count` indices stored on `bits_per_index` bits each.
+    pub fn new(data: &'a [u8], bits_per_index: usize, count: usize) -> Result<Self, Error> {
+        if !(1..=64).contains(&bits_per_index) {
+            return Err(Error::InvalidBitsPerIndex(bits_per_index));
+        }
+        let expected = bits_per_index
+            .checked_mul(count)
+            .map(|total_bits| total_bits.div_ceil(8))
+            .ok_or(Error::InvalidLength {
+                expected: usize::MAX,
+                got: data.len(),
+            })?;
+        if data.len() != expected {
+            return Err(Error::InvalidLength {
+                expected,
+                got: data.len(),
+            });
+        }
+        Ok(Self {
+            bits: BitSlice::from_slice(data),
+            bits_per_index,
+            count,
+        })
+    }
+
+    pub fn len(&self) -> usize {
+        self.count
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.count == 0
+    }
+
+    /// Get the index at the given position.
+    pub fn get(&self, position: usize) -> Option<u64> {
+        if position >= self.count {
+            return None;
+        }
+        let start = position * self.bits_per_index;
+        Some(self.bits[start..start + self.bits_per_index].load_le::<u64>())
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = u64> + 'a {
+        self.bits
+            .chunks_exact(self.bits_per_index)
+            .take(self.count)
+            .map(|chunk| chunk.load_le::<u64>())
+    }
+}
+
 /// Calculate the number of bits required to store the value.
 pub fn required_bits(value: u64) -> usize {
     if value == 0 {
@@ -64,6 +147,54 @@ mod tests {
             let decompressed: Vec<_> = decompress_indexes(&compressed, bits).take(indexes.len()).collect();
             assert_eq!(indexes.as_slice(), &decompressed);
         }
+
+        #[test]
+        fn compressed_indices_prop(indexes: Vec<u64>, max_value: u64) {
+            let bits = required_bits(max_value).max(1);
+            let indexes: Vec<_> = indexes.into_iter().map(|i| i & (u64::MAX >> (64 - bits))).collect();
+            let compressed = compress_indices(&indexes, bits);
+            let view = CompressedIndices::new(&compressed, bits, indexes.len()).unwrap();
+            assert_eq!(indexes.len(), view.len());
+            assert_eq!(indexes, view.iter().collect::<Vec<_>>());
+            for (position, index) in indexes.iter().enumerate() {
+                assert_eq!(Some(*index), view.get(position));
+            }
+            assert_eq!(None, view.get(indexes.len()));
+        }
+
+        #[test]
+        fn compressed_indices_malformed_input(data: Vec<u8>, bits in 0..70usize, count in 0..1000usize) {
+            match CompressedIndices::new(&data, bits, count) {
+                Ok(view) => {
+                    assert_eq!(count, view.iter().count());
+                    assert!(view.get(count.saturating_sub(1)).is_some() || count == 0);
+                }
+                Err(Error::InvalidBitsPerIndex(b)) => assert!(b == 0 || b > 64),
+                Err(Error::InvalidLength { expected, got }) => {
+                    assert_eq!(data.len(), got);
+                    assert_ne!(expected, got);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn compressed_indices_errors() {
+        assert_eq!(
+            Err(Error::InvalidBitsPerIndex(0)),
+            CompressedIndices::new(&[0; 4], 0, 4).map(|_| ())
+        );
+        assert_eq!(
+            Err(Error::InvalidBitsPerIndex(65)),
+            CompressedIndices::new(&[0; 4], 65, 4).map(|_| ())
+        );
+        assert_eq!(
+            Err(Error::InvalidLength {
+                expected: 2,
+                got: 3
+            }),
+            CompressedIndices::new(&[0; 3], 3, 5).map(|_| ())
+        );
     }
 
     #[test]
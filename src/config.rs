@@ -1,9 +1,13 @@
-use serde::Deserialize;
+use std::fmt::Display;
+
+use serde::{Deserialize, Serialize};
+
+use crate::initialize::LABEL_SIZE;
 
 /// POST configuration (network parameter)
 #[repr(C)]
 #[serde_with::serde_as]
-#[derive(Debug, Clone, Copy, Deserialize)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
 pub struct InitConfig {
     /// The minimal number of units that must be initialized.
     pub min_num_units: u32,
@@ -17,7 +21,7 @@ pub struct InitConfig {
 
 #[repr(C)]
 #[serde_with::serde_as]
-#[derive(Debug, Clone, Copy, Deserialize)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
 pub struct ProofConfig {
     /// K1 specifies the difficulty for a label to be a candidate for a proof.
     pub k1: u32,
@@ -30,13 +34,30 @@ pub struct ProofConfig {
 }
 
 #[repr(C)]
-#[derive(Debug, Clone, Copy, Deserialize)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(try_from = "RawScryptParams")]
 pub struct ScryptParams {
     pub n: usize,
     pub r: usize,
     pub p: usize,
 }
 
+/// Scrypt parameters before validation, so that a config with invalid ones fails to load.
+#[derive(Deserialize)]
+struct RawScryptParams {
+    n: usize,
+    r: usize,
+    p: usize,
+}
+
+impl TryFrom<RawScryptParams> for ScryptParams {
+    type Error = ConfigError;
+
+    fn try_from(raw: RawScryptParams) -> Result<Self, Self::Error> {
+        Self::try_new(raw.n, raw.r, raw.p)
+    }
+}
+
 impl ScryptParams {
     pub fn new(n: usize, r: usize, p: usize) -> Self {
         assert!(n >= 2);
@@ -47,6 +68,294 @@ impl ScryptParams {
     }
 }
 
+impl ScryptParams {
+    /// Like [ScryptParams::new], but returns an error instead of panicking on invalid parameters.
+    pub fn try_new(n: usize, r: usize, p: usize) -> Result<Self, ConfigError> {
+        let params = Self { n, r, p };
+        params.validate()?;
+        Ok(params)
+    }
+
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        if self.n < 2 || !self.n.is_power_of_two() {
+            return Err(ConfigError::InvalidScryptN(self.n));
+        }
+        if !self.r.is_power_of_two() {
+            return Err(ConfigError::InvalidScryptR(self.r));
+        }
+        if !self.p.is_power_of_two() {
+            return Err(ConfigError::InvalidScryptP(self.p));
+        }
+        Ok(())
+    }
+
+    /// Parse the scrypt N, written as a number (`8192`) or a power (`2^13`).
+    ///
+    /// Meant as a command line value parser, explaining the constraints on invalid values.
+    pub fn parse_n(s: &str) -> Result<usize, ConfigError> {
+        let n = parse_number(s)?;
+        Self { n, r: 1, p: 1 }.validate().map(|_| n)
+    }
+
+    /// Parse the scrypt r, like [ScryptParams::parse_n].
+    pub fn parse_r(s: &str) -> Result<usize, ConfigError> {
+        let r = parse_number(s)?;
+        Self { n: 2, r, p: 1 }.validate().map(|_| r)
+    }
+
+    /// Parse the scrypt p, like [ScryptParams::parse_n].
+    pub fn parse_p(s: &str) -> Result<usize, ConfigError> {
+        let p = parse_number(s)?;
+        Self { n: 2, r: 1, p }.validate().map(|_| p)
+    }
+}
+
+/// Parse a number written in decimal or as a power (`base^exponent`).
+fn parse_number(s: &str) -> Result<usize, ConfigError> {
+    let not_a_number = || ConfigError::NotANumber(s.to_string());
+    match s.trim().split_once('^') {
+        Some((base, exponent)) => {
+            let base: usize = base.trim().parse().map_err(|_| not_a_number())?;
+            let exponent: u32 = exponent.trim().parse().map_err(|_| not_a_number())?;
+            base.checked_pow(exponent).ok_or_else(not_a_number)
+        }
+        None => s.trim().parse().map_err(|_| not_a_number()),
+    }
+}
+
+/// Size of a unit of POS data, the number of labels in it.
+///
+/// Converts between the number of units and the number of labels or bytes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct UnitSize {
+    labels_per_unit: u64,
+}
+
+impl UnitSize {
+    pub const fn new(labels_per_unit: u64) -> Self {
+        Self { labels_per_unit }
+    }
+
+    pub const fn labels_per_unit(&self) -> u64 {
+        self.labels_per_unit
+    }
+
+    /// Size of one unit in bytes.
+    pub const fn bytes_per_unit(&self) -> u64 {
+        self.labels_per_unit * LABEL_SIZE as u64
+    }
+
+    /// Number of labels in `num_units` units.
+    pub const fn labels(&self, num_units: u32) -> u64 {
+        num_units as u64 * self.labels_per_unit
+    }
+
+    /// Size of `num_units` units in bytes.
+    pub const fn bytes(&self, num_units: u32) -> u64 {
+        num_units as u64 * self.bytes_per_unit()
+    }
+
+    /// Number of complete units in `labels` labels.
+    pub const fn complete_units(&self, labels: u64) -> u64 {
+        labels / self.labels_per_unit
+    }
+
+    /// Number of units in `labels` labels, if it's a whole number.
+    pub const fn exact_units(&self, labels: u64) -> Option<u64> {
+        match labels.checked_rem(self.labels_per_unit) {
+            Some(0) => Some(labels / self.labels_per_unit),
+            _ => None,
+        }
+    }
+
+    /// Number of complete units that fit in `bytes` bytes.
+    pub const fn units_in_bytes(&self, bytes: u64) -> u64 {
+        self.complete_units(bytes / LABEL_SIZE as u64)
+    }
+}
+
+impl Display for UnitSize {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        const UNITS: [&str; 5] = ["B", "KiB", "MiB", "GiB", "TiB"];
+        let mut size = self.bytes_per_unit() as f64;
+        let mut unit = 0;
+        while size >= 1024.0 && unit < UNITS.len() - 1 {
+            size /= 1024.0;
+            unit += 1;
+        }
+        write!(f, "{} labels ", self.labels_per_unit)?;
+        if size.fract() == 0.0 {
+            write!(f, "({size} {})", UNITS[unit])
+        } else {
+            write!(f, "({size:.2} {})", UNITS[unit])
+        }
+    }
+}
+
+/// Invalid POST configuration.
+#[derive(Debug, thiserror::Error, PartialEq, Eq)]
+pub enum ConfigError {
+    #[error("missing parameter: {0}")]
+    Missing(&'static str),
+    #[error("k1 must be positive")]
+    ZeroK1,
+    #[error("k2 ({k2}) must be greater than k1 ({k1})")]
+    K2NotAboveK1 { k1: u32, k2: u32 },
+    #[error("pow difficulty must not be zero")]
+    ZeroPowDifficulty,
+    #[error("number of units must be positive")]
+    ZeroNumUnits,
+    #[error("min number of units ({min}) is greater than max number of units ({max})")]
+    InvalidNumUnitsRange { min: u32, max: u32 },
+    #[error("labels per unit must be positive")]
+    ZeroLabelsPerUnit,
+    #[error("number of labels ({num_labels}) must be greater than k1 ({k1})")]
+    TooFewLabels { num_labels: u64, k1: u32 },
+    #[error("scrypt N must be a power of two >= 2 (got {0})")]
+    InvalidScryptN(usize),
+    #[error("scrypt r must be a power of two (got {0})")]
+    InvalidScryptR(usize),
+    #[error("scrypt p must be a power of two (got {0})")]
+    InvalidScryptP(usize),
+    #[error("{0:?} is not a number (expected i.e. `8192` or `2^13`)")]
+    NotANumber(String),
+}
+
+impl ProofConfig {
+    pub fn builder() -> ProofConfigBuilder {
+        ProofConfigBuilder::default()
+    }
+
+    /// Check the relationships between the parameters.
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        if self.k1 == 0 {
+            return Err(ConfigError::ZeroK1);
+        }
+        if self.k2 <= self.k1 {
+            return Err(ConfigError::K2NotAboveK1 {
+                k1: self.k1,
+                k2: self.k2,
+            });
+        }
+        if self.pow_difficulty == [0; 32] {
+            return Err(ConfigError::ZeroPowDifficulty);
+        }
+        Ok(())
+    }
+}
+
+/// Builder of a validated [ProofConfig].
+#[derive(Debug, Default, Clone)]
+pub struct ProofConfigBuilder {
+    k1: Option<u32>,
+    k2: Option<u32>,
+    pow_difficulty: Option<[u8; 32]>,
+}
+
+impl ProofConfigBuilder {
+    pub fn k1(mut self, k1: u32) -> Self {
+        self.k1 = Some(k1);
+        self
+    }
+
+    pub fn k2(mut self, k2: u32) -> Self {
+        self.k2 = Some(k2);
+        self
+    }
+
+    pub fn pow_difficulty(mut self, pow_difficulty: [u8; 32]) -> Self {
+        self.pow_difficulty = Some(pow_difficulty);
+        self
+    }
+
+    pub fn build(self) -> Result<ProofConfig, ConfigError> {
+        let cfg = ProofConfig {
+            k1: self.k1.ok_or(ConfigError::Missing("k1"))?,
+            k2: self.k2.ok_or(ConfigError::Missing("k2"))?,
+            pow_difficulty: self
+                .pow_difficulty
+                .ok_or(ConfigError::Missing("pow_difficulty"))?,
+        };
+        cfg.validate()?;
+        Ok(cfg)
+    }
+}
+
+impl InitConfig {
+    pub fn builder() -> InitConfigBuilder {
+        InitConfigBuilder::default()
+    }
+
+    pub fn unit_size(&self) -> UnitSize {
+        UnitSize::new(self.labels_per_unit)
+    }
+
+    /// Check the relationships between the parameters.
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        if self.min_num_units == 0 {
+            return Err(ConfigError::ZeroNumUnits);
+        }
+        if self.min_num_units > self.max_num_units {
+            return Err(ConfigError::InvalidNumUnitsRange {
+                min: self.min_num_units,
+                max: self.max_num_units,
+            });
+        }
+        if self.labels_per_unit == 0 {
+            return Err(ConfigError::ZeroLabelsPerUnit);
+        }
+        self.scrypt.validate()
+    }
+}
+
+/// Builder of a validated [InitConfig].
+#[derive(Debug, Default, Clone)]
+pub struct InitConfigBuilder {
+    min_num_units: Option<u32>,
+    max_num_units: Option<u32>,
+    labels_per_unit: Option<u64>,
+    scrypt: Option<ScryptParams>,
+}
+
+impl InitConfigBuilder {
+    pub fn min_num_units(mut self, min_num_units: u32) -> Self {
+        self.min_num_units = Some(min_num_units);
+        self
+    }
+
+    pub fn max_num_units(mut self, max_num_units: u32) -> Self {
+        self.max_num_units = Some(max_num_units);
+        self
+    }
+
+    pub fn labels_per_unit(mut self, labels_per_unit: u64) -> Self {
+        self.labels_per_unit = Some(labels_per_unit);
+        self
+    }
+
+    pub fn scrypt(mut self, scrypt: ScryptParams) -> Self {
+        self.scrypt = Some(scrypt);
+        self
+    }
+
+    pub fn build(self) -> Result<InitConfig, ConfigError> {
+        let cfg = InitConfig {
+            min_num_units: self
+                .min_num_units
+                .ok_or(ConfigError::Missing("min_num_units"))?,
+            max_num_units: self
+                .max_num_units
+                .ok_or(ConfigError::Missing("max_num_units"))?,
+            labels_per_unit: self
+                .labels_per_unit
+                .ok_or(ConfigError::Missing("labels_per_unit"))?,
+            scrypt: self.scrypt.ok_or(ConfigError::Missing("scrypt"))?,
+        };
+        cfg.validate()?;
+        Ok(cfg)
+    }
+}
+
 impl From<ScryptParams> for scrypt_jane::scrypt::ScryptParams {
     fn from(params: ScryptParams) -> Self {
         Self::new(
@@ -57,7 +366,83 @@ impl From<ScryptParams> for scrypt_jane::scrypt::ScryptParams {
     }
 }
 
-#[derive(Debug, Clone, Default)]
+/// Network parameters of the known networks.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum NetworkPreset {
+    Mainnet,
+    /// Small units (16 KiB) for testing.
+    Testnet,
+    /// Parameters are configured explicitly.
+    #[default]
+    Custom,
+}
+
+const MAINNET_POW_DIFFICULTY: [u8; 32] = [
+    0x00, 0x0d, 0xfb, 0x23, 0xb0, 0x97, 0x9b, 0x4b, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
+    0, 0, 0, 0, 0, 0, 0, 0,
+];
+
+impl NetworkPreset {
+    /// The proof parameters, `None` for [NetworkPreset::Custom].
+    pub fn proof_config(self) -> Option<ProofConfig> {
+        match self {
+            Self::Mainnet | Self::Testnet => Some(ProofConfig {
+                k1: 26,
+                k2: 37,
+                pow_difficulty: MAINNET_POW_DIFFICULTY,
+            }),
+            Self::Custom => None,
+        }
+    }
+
+    /// The initialization parameters, `None` for [NetworkPreset::Custom].
+    pub fn init_config(self) -> Option<InitConfig> {
+        let scrypt = ScryptParams::new(8192, 1, 1);
+        match self {
+            Self::Mainnet => Some(InitConfig {
+                min_num_units: 4,
+                max_num_units: u32::MAX,
+                labels_per_unit: 1 << 32,
+                scrypt,
+            }),
+            Self::Testnet => Some(InitConfig {
+                min_num_units: 2,
+                max_num_units: 4,
+                labels_per_unit: 1024,
+                scrypt,
+            }),
+            Self::Custom => None,
+        }
+    }
+}
+
+impl std::str::FromStr for NetworkPreset {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "mainnet" => Ok(Self::Mainnet),
+            "testnet" => Ok(Self::Testnet),
+            "custom" => Ok(Self::Custom),
+            _ => Err(format!(
+                "invalid network preset: {s} (expected mainnet, testnet or custom)"
+            )),
+        }
+    }
+}
+
+impl std::fmt::Display for NetworkPreset {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Mainnet => write!(f, "mainnet"),
+            Self::Testnet => write!(f, "testnet"),
+            Self::Custom => write!(f, "custom"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
 pub enum Cores {
     #[default]
     /// Use all cores (maxes out at 64 on Windows)
@@ -68,3 +453,186 @@ pub enum Cores {
     /// Will use length of vector as the number of cores (threads)
     Pin(Vec<usize>),
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_network_preset() {
+        for preset in [
+            NetworkPreset::Mainnet,
+            NetworkPreset::Testnet,
+            NetworkPreset::Custom,
+        ] {
+            assert_eq!(preset, preset.to_string().parse().unwrap());
+        }
+        assert_eq!(NetworkPreset::Mainnet, "MAINNET".parse().unwrap());
+        assert!("devnet".parse::<NetworkPreset>().is_err());
+    }
+
+    #[test]
+    fn mainnet_preset() {
+        let cfg = NetworkPreset::Mainnet.proof_config().unwrap();
+        assert_eq!(
+            "000dfb23b0979b4b000000000000000000000000000000000000000000000000",
+            hex::encode(cfg.pow_difficulty)
+        );
+        let init_cfg = NetworkPreset::Mainnet.init_config().unwrap();
+        assert_eq!(64 << 30, init_cfg.labels_per_unit * 16);
+        assert!(NetworkPreset::Custom.proof_config().is_none());
+        assert!(NetworkPreset::Custom.init_config().is_none());
+    }
+
+    #[test]
+    fn presets_are_valid() {
+        for preset in [NetworkPreset::Mainnet, NetworkPreset::Testnet] {
+            preset.proof_config().unwrap().validate().unwrap();
+            preset.init_config().unwrap().validate().unwrap();
+        }
+    }
+
+    #[test]
+    fn building_proof_config() {
+        let builder = ProofConfig::builder().k1(26).pow_difficulty([0xFF; 32]);
+        assert_eq!(
+            Err(ConfigError::Missing("k2")),
+            builder.clone().build().map(|_| ())
+        );
+        assert_eq!(
+            Err(ConfigError::K2NotAboveK1 { k1: 26, k2: 26 }),
+            builder.clone().k2(26).build().map(|_| ())
+        );
+        assert_eq!(
+            Err(ConfigError::ZeroK1),
+            builder.clone().k1(0).k2(1).build().map(|_| ())
+        );
+        assert_eq!(
+            Err(ConfigError::ZeroPowDifficulty),
+            builder
+                .clone()
+                .k2(37)
+                .pow_difficulty([0; 32])
+                .build()
+                .map(|_| ())
+        );
+        let cfg = builder.k2(37).build().unwrap();
+        assert_eq!((26, 37, [0xFF; 32]), (cfg.k1, cfg.k2, cfg.pow_difficulty));
+    }
+
+    #[test]
+    fn building_init_config() {
+        let builder = InitConfig::builder()
+            .min_num_units(1)
+            .max_num_units(4)
+            .labels_per_unit(256);
+        assert_eq!(
+            Err(ConfigError::Missing("scrypt")),
+            builder.clone().build().map(|_| ())
+        );
+        let builder = builder.scrypt(ScryptParams::new(8192, 1, 1));
+        assert_eq!(
+            Err(ConfigError::InvalidNumUnitsRange { min: 5, max: 4 }),
+            builder.clone().min_num_units(5).build().map(|_| ())
+        );
+        assert_eq!(
+            Err(ConfigError::ZeroNumUnits),
+            builder.clone().min_num_units(0).build().map(|_| ())
+        );
+        assert_eq!(
+            Err(ConfigError::ZeroLabelsPerUnit),
+            builder.clone().labels_per_unit(0).build().map(|_| ())
+        );
+        assert_eq!(
+            Err(ConfigError::InvalidScryptN(1000)),
+            builder
+                .clone()
+                .scrypt(ScryptParams {
+                    n: 1000,
+                    r: 1,
+                    p: 1
+                })
+                .build()
+                .map(|_| ())
+        );
+        builder.build().unwrap();
+    }
+
+    #[test]
+    fn validating_scrypt_params() {
+        ScryptParams::try_new(2, 1, 1).unwrap();
+        assert_eq!(
+            Err(ConfigError::InvalidScryptN(1)),
+            ScryptParams::try_new(1, 1, 1)
+        );
+        assert_eq!(
+            Err(ConfigError::InvalidScryptR(3)),
+            ScryptParams::try_new(4, 3, 1)
+        );
+        assert_eq!(
+            Err(ConfigError::InvalidScryptP(0)),
+            ScryptParams::try_new(4, 1, 0)
+        );
+    }
+
+    #[test]
+    fn unit_size() {
+        let unit_size = NetworkPreset::Mainnet.init_config().unwrap().unit_size();
+        assert_eq!(64 << 30, unit_size.bytes_per_unit());
+        assert_eq!(4 << 32, unit_size.labels(4));
+        assert_eq!(256 << 30, unit_size.bytes(4));
+        assert_eq!(3, unit_size.units_in_bytes((256 << 30) - 1));
+        assert_eq!("4294967296 labels (64 GiB)", unit_size.to_string());
+
+        let unit_size = UnitSize::new(100);
+        assert_eq!(2, unit_size.complete_units(250));
+        assert_eq!(None, unit_size.exact_units(250));
+        assert_eq!(Some(3), unit_size.exact_units(300));
+        assert_eq!(None, UnitSize::new(0).exact_units(300));
+        assert_eq!("100 labels (1.56 KiB)", unit_size.to_string());
+    }
+
+    #[test]
+    fn parsing_scrypt_params() {
+        assert_eq!(Ok(8192), ScryptParams::parse_n("8192"));
+        assert_eq!(Ok(8192), ScryptParams::parse_n("2^13"));
+        assert_eq!(Ok(8192), ScryptParams::parse_n(" 2 ^ 13 "));
+        assert_eq!(
+            Err(ConfigError::InvalidScryptN(1000)),
+            ScryptParams::parse_n("1000")
+        );
+        assert_eq!(
+            Err(ConfigError::InvalidScryptN(1)),
+            ScryptParams::parse_n("2^0")
+        );
+        assert_eq!(
+            Err(ConfigError::NotANumber("8k".into())),
+            ScryptParams::parse_n("8k")
+        );
+        assert_eq!(
+            Err(ConfigError::NotANumber("2^100".into())),
+            ScryptParams::parse_n("2^100")
+        );
+        assert_eq!(Ok(1), ScryptParams::parse_r("1"));
+        assert_eq!(
+            Err(ConfigError::InvalidScryptR(3)),
+            ScryptParams::parse_r("3")
+        );
+        assert_eq!(Ok(2), ScryptParams::parse_p("2^1"));
+        assert_eq!(
+            Err(ConfigError::InvalidScryptP(0)),
+            ScryptParams::parse_p("0")
+        );
+    }
+
+    #[test]
+    fn deserializing_scrypt_params() {
+        let params: ScryptParams = serde_json::from_str(r#"{"n": 8192, "r": 1, "p": 1}"#).unwrap();
+        assert_eq!(ScryptParams::new(8192, 1, 1), params);
+
+        let err = serde_json::from_str::<ScryptParams>(r#"{"n": 1000, "r": 1, "p": 1}"#)
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("scrypt N must be a power of two"), "{err}");
+    }
+}
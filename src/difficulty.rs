@@ -1,5 +1,25 @@
+//! Difficulties of proving.
+//!
+//! A label is a candidate for a proof if its value is below the proving difficulty
+//! ([proving_difficulty]) and a k2pow is valid if its hash is below the PoW difficulty
+//! scaled by the number of units ([scale_pow_difficulty]). In both cases, the lower
+//! the difficulty, the harder it is to satisfy. PoW difficulties are 256-bit big-endian
+//! numbers, so comparing the byte arrays compares the difficulties.
+
 use primitive_types::U256;
 
+#[derive(thiserror::Error, Debug, Clone, PartialEq, Eq)]
+pub enum Error {
+    #[error("number of labels must be > 0")]
+    ZeroLabels,
+    #[error("number of labels ({num_labels}) must be bigger than k1 ({k1})")]
+    TooFewLabels { num_labels: u64, k1: u32 },
+    #[error("number of units must be > 0")]
+    ZeroUnits,
+    #[error("PoW difficulty is too low for {num_units} units (scaled down to zero, no PoW could satisfy it)")]
+    PowDifficultyTooLow { num_units: u32 },
+}
+
 /// Calculate proving difficulty.
 ///
 /// K1 defines how many good labels are expected to be within all the labels.
@@ -7,17 +27,15 @@ use primitive_types::U256;
 ///
 /// The difficulty is calculated as:
 /// difficulty = 2^64 * K1 / num_labels
-pub(crate) fn proving_difficulty(k1: u32, num_labels: u64) -> Result<u64, String> {
+pub fn proving_difficulty(k1: u32, num_labels: u64) -> Result<u64, Error> {
     if num_labels == 0 {
-        return Err("number of label blocks must be > 0".to_string());
+        return Err(Error::ZeroLabels);
     }
     if num_labels <= k1 as u64 {
-        return Err(format!(
-            "number of labels ({num_labels}) must be bigger than k1 ({k1})"
-        ));
+        return Err(Error::TooFewLabels { num_labels, k1 });
     }
-    let difficulty = (1u128 << 64) * k1 as u128 / num_labels as u128;
-    u64::try_from(difficulty).or(Err("difficulty doesn't fit in u64".to_string()))
+    // Fits in u64 as K1 < num_labels.
+    Ok(((1u128 << 64) * k1 as u128 / num_labels as u128) as u64)
 }
 
 /// Scale PoW difficulty by the number of units.
@@ -26,19 +44,71 @@ pub(crate) fn proving_difficulty(k1: u32, num_labels: u64) -> Result<u64, String
 /// Because the PoW looks for values < difficulty, we need to scale the difficulty down.
 /// The difficulty threshold is calculated as:
 /// difficulty = difficulty / num_units
-pub(crate) fn scale_pow_difficulty(difficulty: &[u8; 32], num_units: u32) -> [u8; 32] {
+///
+/// The division rounds down (as the node does), so the scaled difficulty is never easier
+/// than intended. Fails if `num_units` is zero or bigger than the difficulty, which would
+/// scale it down to zero.
+pub fn scale_pow_difficulty(difficulty: &[u8; 32], num_units: u32) -> Result<[u8; 32], Error> {
+    if num_units == 0 {
+        return Err(Error::ZeroUnits);
+    }
     let difficulty_scaled = U256::from_big_endian(difficulty) / num_units;
-    difficulty_scaled.to_big_endian()
+    if difficulty_scaled.is_zero() {
+        return Err(Error::PowDifficultyTooLow { num_units });
+    }
+    Ok(difficulty_scaled.to_big_endian())
+}
+
+/// Number of leading zero bits of a PoW difficulty.
+pub fn leading_zero_bits(difficulty: &[u8; 32]) -> u32 {
+    U256::from_big_endian(difficulty).leading_zeros()
+}
+
+/// The work needed to find a PoW below `difficulty`, as log2 of the expected number of hashes.
+///
+/// Unlike [leading_zero_bits], it's fractional, i.e. halving the difficulty adds 1 bit of work.
+/// Infinite for a zero difficulty.
+pub fn pow_work_bits(difficulty: &[u8; 32]) -> f64 {
+    let difficulty = U256::from_big_endian(difficulty);
+    let bits = difficulty.bits();
+    // log2 of the difficulty from its (up to) 64 most significant bits
+    let shift = bits.saturating_sub(64);
+    let top = (difficulty >> shift).low_u64() as f64;
+    256.0 - (top.log2() + shift as f64)
+}
+
+/// Describe a PoW difficulty for humans, i.e. `0fff...ff (4 leading zero bits, 2^4.00 hashes expected)`.
+pub fn describe_pow_difficulty(difficulty: &[u8; 32]) -> String {
+    format!(
+        "{} ({} leading zero bits, 2^{:.2} hashes expected)",
+        hex::encode(difficulty),
+        leading_zero_bits(difficulty),
+        pow_work_bits(difficulty)
+    )
+}
+
+/// Describe a proving difficulty for humans, i.e. `4611686018427387904 (1 in 4.0 labels)`.
+pub fn describe_proving_difficulty(difficulty: u64) -> String {
+    format!(
+        "{difficulty} (1 in {:.1} labels)",
+        2f64.powi(64) / difficulty as f64
+    )
 }
 
 #[test]
 fn zero_labels() {
-    assert!(proving_difficulty(1, 0).is_err());
+    assert_eq!(Err(Error::ZeroLabels), proving_difficulty(1, 0));
 }
 
 #[test]
 fn too_big_k1() {
-    assert!(proving_difficulty(2, 1).is_err());
+    assert_eq!(
+        Err(Error::TooFewLabels {
+            num_labels: 1,
+            k1: 2
+        }),
+        proving_difficulty(2, 1)
+    );
     assert!(proving_difficulty(1, 1).is_err());
 }
 
@@ -54,12 +124,12 @@ fn difficulty_calculation() {
 fn scaling_pow_thresholds() {
     {
         // don't scale when num_units is 1
-        let difficulty = scale_pow_difficulty(&[0xFF; 32], 1);
+        let difficulty = scale_pow_difficulty(&[0xFF; 32], 1).unwrap();
         assert_eq!(difficulty, [0xFF; 32]);
     }
     {
         // scale with num_units
-        let difficulty = scale_pow_difficulty(&[0xFF; 32], 2);
+        let difficulty = scale_pow_difficulty(&[0xFF; 32], 2).unwrap();
         assert!(difficulty < [0xFF; 32]);
         assert_eq!(
             difficulty.as_slice(),
@@ -68,7 +138,7 @@ fn scaling_pow_thresholds() {
     }
     {
         // scale with num_units
-        let difficulty = scale_pow_difficulty(&[0xFF; 32], 2_u32.pow(5));
+        let difficulty = scale_pow_difficulty(&[0xFF; 32], 2_u32.pow(5)).unwrap();
         assert!(difficulty < [0xFF; 32]);
         assert_eq!(
             difficulty.as_slice(),
@@ -76,3 +146,73 @@ fn scaling_pow_thresholds() {
         );
     }
 }
+
+#[test]
+fn zero_units() {
+    assert_eq!(Err(Error::ZeroUnits), scale_pow_difficulty(&[0xFF; 32], 0));
+}
+
+#[test]
+fn scaling_pow_difficulty_edge_cases() {
+    // rounds down
+    let seven = U256::from(7u64).to_big_endian();
+    assert_eq!(
+        U256::from(3u64).to_big_endian(),
+        scale_pow_difficulty(&seven, 2).unwrap()
+    );
+    // as small as the number of units
+    assert_eq!(
+        U256::one().to_big_endian(),
+        scale_pow_difficulty(&seven, 7).unwrap()
+    );
+    // smaller than the number of units
+    assert_eq!(
+        Err(Error::PowDifficultyTooLow { num_units: 8 }),
+        scale_pow_difficulty(&seven, 8)
+    );
+    assert_eq!(
+        Err(Error::PowDifficultyTooLow { num_units: 1 }),
+        scale_pow_difficulty(&[0; 32], 1)
+    );
+    // the biggest difficulty and number of units don't overflow
+    let scaled = U256::from_big_endian(&scale_pow_difficulty(&[0xFF; 32], u32::MAX).unwrap());
+    assert_eq!(U256::MAX / u32::MAX, scaled);
+}
+
+#[test]
+fn pow_difficulty_bits() {
+    let mut difficulty = [0xFF; 32];
+    assert_eq!(0, leading_zero_bits(&difficulty));
+    assert!(pow_work_bits(&difficulty).abs() < 1e-9);
+
+    difficulty[0] = 0x0F;
+    assert_eq!(4, leading_zero_bits(&difficulty));
+    assert!((pow_work_bits(&difficulty) - 4.0).abs() < 1e-9);
+
+    // Halving the difficulty adds one bit of work
+    let halved = scale_pow_difficulty(&difficulty, 2).unwrap();
+    assert!(halved < difficulty);
+    assert!((pow_work_bits(&halved) - 5.0).abs() < 1e-9);
+
+    let small = U256::from(0xFFu64).to_big_endian();
+    assert_eq!(248, leading_zero_bits(&small));
+    assert!((pow_work_bits(&small) - 248.0).abs() < 0.01);
+    assert_eq!(f64::INFINITY, pow_work_bits(&[0; 32]));
+}
+
+#[test]
+fn describing_difficulties() {
+    let mut difficulty = [0xFF; 32];
+    difficulty[0] = 0x0F;
+    assert_eq!(
+        format!(
+            "{} (4 leading zero bits, 2^4.00 hashes expected)",
+            hex::encode(difficulty)
+        ),
+        describe_pow_difficulty(&difficulty)
+    );
+    assert_eq!(
+        "4611686018427387904 (1 in 4.0 labels)",
+        describe_proving_difficulty(proving_difficulty(1, 4).unwrap())
+    );
+}
@@ -0,0 +1,161 @@
+//! Errors of the crate.
+//!
+//! [Error] unifies the errors of initializing POS data and generating proofs,
+//! so that callers can tell them apart (i.e. a full disk from invalid parameters).
+//! [ErrorCode] is its stable numeric form for the FFI.
+
+use crate::{
+    config::ConfigError, difficulty, lock::LockError, pos_verification::VerificationError, pow,
+    reader::ReadError, verification,
+};
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("invalid argument: {0}")]
+    InvalidArgument(String),
+    #[error(transparent)]
+    Config(#[from] ConfigError),
+    #[error(transparent)]
+    Difficulty(#[from] difficulty::Error),
+    #[error("IO error: {0}")]
+    Io(#[from] std::io::Error),
+    #[error(transparent)]
+    Locked(#[from] LockError),
+    #[error("invalid metadata: {0}")]
+    InvalidMetadata(String),
+    #[error(transparent)]
+    Read(#[from] ReadError),
+    #[error("proof of work: {0}")]
+    Pow(#[from] pow::Error),
+    #[error("invalid proof: {0}")]
+    InvalidProof(#[from] verification::Error),
+    #[error("invalid POS data: {0}")]
+    InvalidPosData(#[from] VerificationError),
+    #[error("no proof found with all the {nonces} nonces")]
+    NoncesExhausted { nonces: u32 },
+    #[error("generated proof failed the self-check (label index: {index:?}): {source}")]
+    SelfCheckFailed {
+        index: Option<u64>,
+        source: verification::Error,
+    },
+    #[error("initializer error: {0}")]
+    Initializer(Box<dyn std::error::Error + Send + Sync>),
+    #[error("stopped")]
+    Stopped,
+    #[error("POS data is not initialized yet ({initialized} of {total} labels), not even one unit is complete")]
+    NotInitialized { initialized: u64, total: u64 },
+    #[error("POS data has changed: {0}")]
+    DataChanged(String),
+    #[error("POS data is partially initialized ({units} of {total_units} units are complete)")]
+    PartiallyInitialized { units: u32, total_units: u32 },
+    #[error("{0:#}")]
+    Other(#[from] eyre::Report),
+}
+
+/// Stable numeric codes of [Error]s. New codes can be added, the existing ones never change.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorCode {
+    Ok = 0,
+    Unknown = 1,
+    InvalidArgument = 2,
+    Io = 3,
+    DiskFull = 4,
+    NotFound = 5,
+    PermissionDenied = 6,
+    Locked = 7,
+    InvalidMetadata = 8,
+    ReadFailed = 9,
+    Pow = 10,
+    InvalidProof = 11,
+    InvalidPosData = 12,
+    Initializer = 13,
+    Stopped = 14,
+    SelfCheckFailed = 15,
+    NoncesExhausted = 16,
+    NotInitialized = 17,
+    DataChanged = 18,
+    PartiallyInitialized = 19,
+}
+
+impl Error {
+    pub fn code(&self) -> ErrorCode {
+        match self {
+            Error::InvalidArgument(_) | Error::Config(_) | Error::Difficulty(_) => {
+                ErrorCode::InvalidArgument
+            }
+            Error::Io(e) => io_error_code(e),
+            Error::Locked(LockError::Io(e)) => io_error_code(e),
+            Error::Locked(_) => ErrorCode::Locked,
+            Error::InvalidMetadata(_) => ErrorCode::InvalidMetadata,
+            Error::Read(_) => ErrorCode::ReadFailed,
+            Error::Pow(_) => ErrorCode::Pow,
+            Error::InvalidProof(_) => ErrorCode::InvalidProof,
+            Error::InvalidPosData(VerificationError::Io(e)) => io_error_code(e),
+            Error::InvalidPosData(VerificationError::Locked(_)) => ErrorCode::Locked,
+            Error::InvalidPosData(_) => ErrorCode::InvalidPosData,
+            Error::Initializer(_) => ErrorCode::Initializer,
+            Error::Stopped => ErrorCode::Stopped,
+            Error::SelfCheckFailed { .. } => ErrorCode::SelfCheckFailed,
+            Error::NoncesExhausted { .. } => ErrorCode::NoncesExhausted,
+            Error::NotInitialized { .. } => ErrorCode::NotInitialized,
+            Error::DataChanged(_) => ErrorCode::DataChanged,
+            Error::PartiallyInitialized { .. } => ErrorCode::PartiallyInitialized,
+            // I.e. errors of custom POS data sources, IO errors can still be told apart.
+            Error::Other(report) => report
+                .chain()
+                .find_map(|e| e.downcast_ref::<std::io::Error>())
+                .map_or(ErrorCode::Unknown, io_error_code),
+        }
+    }
+}
+
+fn io_error_code(err: &std::io::Error) -> ErrorCode {
+    match err.kind() {
+        std::io::ErrorKind::StorageFull => ErrorCode::DiskFull,
+        std::io::ErrorKind::NotFound => ErrorCode::NotFound,
+        std::io::ErrorKind::PermissionDenied => ErrorCode::PermissionDenied,
+        _ => ErrorCode::Io,
+    }
+}
+
+impl From<serde_json::Error> for Error {
+    fn from(err: serde_json::Error) -> Self {
+        match err.io_error_kind() {
+            Some(_) => Error::Io(err.into()),
+            None => Error::InvalidMetadata(err.to_string()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn error_codes() {
+        let io = |kind| Error::Io(std::io::Error::from(kind));
+        assert_eq!(
+            ErrorCode::DiskFull,
+            io(std::io::ErrorKind::StorageFull).code()
+        );
+        assert_eq!(ErrorCode::NotFound, io(std::io::ErrorKind::NotFound).code());
+        assert_eq!(ErrorCode::Io, io(std::io::ErrorKind::UnexpectedEof).code());
+        assert_eq!(
+            ErrorCode::InvalidArgument,
+            Error::from(difficulty::Error::ZeroLabels).code()
+        );
+        assert_eq!(
+            ErrorCode::InvalidMetadata,
+            Error::from(serde_json::from_str::<u32>("x").unwrap_err()).code()
+        );
+        assert_eq!(ErrorCode::Stopped, Error::Stopped.code());
+        let report = eyre::Report::new(std::io::Error::from(std::io::ErrorKind::NotFound))
+            .wrap_err("listing POS data files");
+        assert_eq!(ErrorCode::NotFound, Error::from(report).code());
+        assert_eq!(
+            ErrorCode::Unknown,
+            Error::from(eyre::eyre!("unknown")).code()
+        );
+    }
+}
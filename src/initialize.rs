@@ -1,16 +1,31 @@
 use std::{
-    error::Error,
-    fs::{create_dir_all, File},
-    io::Write,
+    fs::{create_dir_all, File, OpenOptions},
+    io::{Seek, SeekFrom, Write},
     ops::Range,
     path::Path,
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        mpsc::sync_channel,
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
 };
 
 use mockall::automock;
+#[cfg(feature = "parallel")]
 use rayon::prelude::{IntoParallelIterator, ParallelIterator};
 use scrypt_jane::scrypt::scrypt;
+use serde::{Deserialize, Serialize};
+use serde_with::{base64::Base64, serde_as};
 
-use crate::{config::ScryptParams, metadata::PostMetadata};
+use crate::{
+    block_device::{self, SUPERBLOCK_SIZE},
+    config::ScryptParams,
+    error::Error,
+    lock::DataDirLock,
+    metadata::{PostMetadata, METADATA_FILE_NAME},
+    reader::{pos_files, RateLimiter},
+};
 
 pub const LABEL_SIZE: usize = 16;
 pub const ENTIRE_LABEL_SIZE: usize = 32;
@@ -32,6 +47,30 @@ pub struct VrfNonce {
 pub trait Initialize {
     #[allow(clippy::too_many_arguments)]
     fn initialize(
+        &mut self,
+        datadir: &Path,
+        node_id: &[u8; 32],
+        commitment_atx_id: &[u8; 32],
+        labels_per_unit: u64,
+        num_units: u32,
+        labels_per_file: u64,
+        vrf_difficulty: Option<[u8; 32]>,
+    ) -> Result<PostMetadata, Error> {
+        self.initialize_with_options(
+            datadir,
+            node_id,
+            commitment_atx_id,
+            labels_per_unit,
+            num_units,
+            labels_per_file,
+            vrf_difficulty,
+            &WriteOptions::default(),
+        )
+    }
+
+    /// [Initialize::initialize] with control over writing the files.
+    #[allow(clippy::too_many_arguments)]
+    fn initialize_with_options(
         &mut self,
         datadir: &Path,
         node_id: &[u8; 32],
@@ -40,9 +79,20 @@ pub trait Initialize {
         num_units: u32,
         labels_per_file: u64,
         mut vrf_difficulty: Option<[u8; 32]>,
-    ) -> Result<PostMetadata, Box<dyn Error>> {
+        options: &WriteOptions,
+    ) -> Result<PostMetadata, Error> {
+        if options.direct_io && !cfg!(target_os = "linux") {
+            return Err(Error::InvalidArgument(
+                "direct I/O is supported only on Linux".into(),
+            ));
+        }
         // Ensure that datadir exists
         create_dir_all(datadir)?;
+        let _lock = DataDirLock::acquire(datadir)?;
+        let params = InitParams {
+            scrypt: self.scrypt_params(),
+            vrf_difficulty,
+        };
 
         let commitment = calc_commitment(node_id, commitment_atx_id);
 
@@ -52,9 +102,30 @@ pub trait Initialize {
         if total_labels % labels_per_file != 0 {
             files_number += 1;
         }
+        let mut metadata = PostMetadata {
+            node_id: *node_id,
+            commitment_atx_id: *commitment_atx_id,
+            labels_per_unit,
+            num_units,
+            max_file_size: labels_per_file * 16,
+            nonce: None,
+            last_position: None,
+        };
+
         let mut nonce = None;
-        for file_id in 0..files_number {
-            let mut post_data = File::create(datadir.join(format!("postdata_{}.bin", file_id)))?;
+        let mut first_file = 0;
+        if let Some(progress) = InitProgress::load(datadir, &metadata, &params)? {
+            first_file = progress.completed_files(datadir, labels_per_file, total_labels);
+            if let Some(n) = progress.nonce() {
+                vrf_difficulty = Some(n.label);
+                nonce = Some(n);
+            }
+            log::info!("resuming initialization from file {first_file} (best nonce: {nonce:?})");
+        }
+
+        let file_path = |file_id: u64| datadir.join(format!("postdata_{file_id}.bin"));
+        for file_id in first_file..files_number {
+            let mut post_data = PosFileWriter::create(&file_path(file_id), options)?;
             let index = file_id * labels_per_file;
             let labels = index..total_labels.min(index + labels_per_file);
             let new_nonce =
@@ -63,19 +134,23 @@ pub trait Initialize {
                 vrf_difficulty = Some(n.label);
                 nonce = Some(n);
             }
+            let post_data = post_data.finish()?;
+            // The checkpoint must not get ahead of the data.
+            if options.sync == SyncPolicy::PerFile {
+                post_data.sync_all()?;
+                InitProgress::new(&metadata, &params, file_id + 1, nonce).save(datadir)?;
+            }
+        }
+        if options.sync == SyncPolicy::AtEnd {
+            for file_id in first_file..files_number {
+                File::open(file_path(file_id))?.sync_all()?;
+            }
         }
 
-        let metadata = PostMetadata {
-            node_id: *node_id,
-            commitment_atx_id: *commitment_atx_id,
-            labels_per_unit,
-            num_units,
-            max_file_size: labels_per_file * 16,
-            nonce: nonce.map(|n| n.index),
-            last_position: None,
-        };
+        metadata.nonce = nonce.map(|n| n.index);
         let metadata_file = File::create(datadir.join("postdata_metadata.json"))?;
         serde_json::to_writer_pretty(metadata_file, &metadata)?;
+        InitProgress::remove(datadir)?;
 
         Ok(metadata)
     }
@@ -86,30 +161,45 @@ pub trait Initialize {
         commitment: &[u8; 32],
         labels: Range<u64>,
         vrf_difficulty: Option<[u8; 32]>,
-    ) -> Result<Option<VrfNonce>, Box<dyn Error>>;
+    ) -> Result<Option<VrfNonce>, Error>;
+
+    /// The scrypt parameters of the labels, if known.
+    /// An interrupted initialization is resumed only with the same ones.
+    fn scrypt_params(&self) -> Option<ScryptParams> {
+        None
+    }
 }
 
 pub struct CpuInitializer {
     scrypt_params: ScryptParams,
+    chunk_size: u64,
 }
 
 impl CpuInitializer {
+    /// Number of labels calculated at once by default.
+    pub const DEFAULT_CHUNK_SIZE: u64 = 256 * 1024;
+
     pub fn new(scrypt_params: ScryptParams) -> Self {
-        Self { scrypt_params }
+        Self {
+            scrypt_params,
+            chunk_size: Self::DEFAULT_CHUNK_SIZE,
+        }
     }
-}
 
-impl Initialize for CpuInitializer {
-    fn initialize_to(
-        &mut self,
-        writer: &mut dyn Write,
-        commitment: &[u8; 32],
-        labels: Range<u64>,
-        mut vrf_difficulty: Option<[u8; 32]>,
-    ) -> Result<Option<VrfNonce>, Box<dyn Error>> {
-        let data = labels
-            .clone()
-            .into_par_iter()
+    /// Set the number of labels calculated at once.
+    ///
+    /// The next chunk is calculated while the previous one is written,
+    /// so up to 3 chunks (32 bytes per label) are kept in memory.
+    pub fn with_chunk_size(mut self, chunk_size: u64) -> Self {
+        self.chunk_size = chunk_size.max(1);
+        self
+    }
+
+    fn scrypt_labels(&self, commitment: &[u8; 32], labels: Range<u64>) -> Vec<[u8; 32]> {
+        // Without the `parallel` feature, on the calling thread.
+        #[cfg(feature = "parallel")]
+        let labels = labels.into_par_iter();
+        labels
             .map(|index| {
                 let mut label = [0u8; 32];
                 let mut scrypt_data = [0u8; 72];
@@ -118,25 +208,538 @@ impl Initialize for CpuInitializer {
                 scrypt(&scrypt_data, &[], self.scrypt_params.into(), &mut label);
                 label
             })
-            .collect::<Vec<_>>();
+            .collect()
+    }
+}
 
-        let mut best_nonce = None;
-        for (id, label) in data.into_iter().enumerate() {
-            if let Some(difficulty) = vrf_difficulty {
-                if label < difficulty {
-                    best_nonce = Some(VrfNonce {
-                        index: labels.start + id as u64,
-                        label,
-                    });
-                    vrf_difficulty = Some(label);
-                    log::trace!("Found new smallest nonce: {best_nonce:?}");
-                }
+/// When the written POS data is synced to the disk.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum SyncPolicy {
+    /// Sync every file when it's written. The progress is checkpointed after every file,
+    /// so an interrupted initialization is resumed from the last file synced.
+    #[default]
+    PerFile,
+    /// Sync all files at the end. An interrupted initialization starts from the beginning.
+    AtEnd,
+}
+
+impl std::str::FromStr for SyncPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "per-file" => Ok(Self::PerFile),
+            "at-end" => Ok(Self::AtEnd),
+            _ => Err(format!(
+                "invalid sync policy: {s} (expected per-file or at-end)"
+            )),
+        }
+    }
+}
+
+impl std::fmt::Display for SyncPolicy {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::PerFile => write!(f, "per-file"),
+            Self::AtEnd => write!(f, "at-end"),
+        }
+    }
+}
+
+/// Optional settings of writing POS data files in [Initialize::initialize_with_options].
+#[derive(Debug, Default, Clone)]
+pub struct WriteOptions {
+    pub sync: SyncPolicy,
+    /// Write with `O_DIRECT`, bypassing the page cache (Linux only).
+    pub direct_io: bool,
+    /// Limits the rate of writing POS data.
+    /// Can be shared with other initializations to limit them together.
+    pub rate_limit: Option<Arc<RateLimiter>>,
+}
+
+/// Alignment of the buffer, file offsets and lengths of `O_DIRECT` writes.
+const DIRECT_IO_ALIGNMENT: usize = 4096;
+/// Size of the buffer of `O_DIRECT` writes and of the writes limited by the rate limit.
+const WRITE_BLOCK_SIZE: usize = 1024 * 1024;
+
+/// Writes a POS data file according to [WriteOptions].
+struct PosFileWriter {
+    file: File,
+    rate_limit: Option<Arc<RateLimiter>>,
+    // `O_DIRECT` writes must be aligned, the data is buffered in aligned blocks.
+    direct: Option<AlignedBuffer>,
+}
+
+impl PosFileWriter {
+    fn create(path: &Path, options: &WriteOptions) -> std::io::Result<Self> {
+        let mut open_options = OpenOptions::new();
+        open_options.write(true).create(true).truncate(true);
+        #[cfg(target_os = "linux")]
+        if options.direct_io {
+            use std::os::unix::fs::OpenOptionsExt;
+            open_options.custom_flags(libc::O_DIRECT);
+        }
+        Ok(Self {
+            file: open_options.open(path)?,
+            rate_limit: options.rate_limit.clone(),
+            direct: options.direct_io.then(AlignedBuffer::new),
+        })
+    }
+
+    /// Write the remaining buffered data.
+    fn finish(mut self) -> std::io::Result<File> {
+        if let Some(direct) = &mut self.direct {
+            direct.finish(&mut self.file)?;
+        }
+        Ok(self.file)
+    }
+}
+
+impl Write for PosFileWriter {
+    fn write(&mut self, data: &[u8]) -> std::io::Result<usize> {
+        let data = &data[..data.len().min(WRITE_BLOCK_SIZE)];
+        if let Some(limiter) = &self.rate_limit {
+            limiter.acquire(data.len() as u64);
+        }
+        match &mut self.direct {
+            Some(direct) => direct.write_all(&mut self.file, data)?,
+            None => self.file.write_all(data)?,
+        }
+        Ok(data.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        // The last partial block of `O_DIRECT` writes is written in `finish`.
+        self.file.flush()
+    }
+}
+
+struct AlignedBuffer {
+    buf: Vec<u8>,
+    // start of the aligned block in `buf`
+    offset: usize,
+    len: usize,
+    written: u64,
+}
+
+impl AlignedBuffer {
+    fn new() -> Self {
+        let buf = vec![0u8; WRITE_BLOCK_SIZE + DIRECT_IO_ALIGNMENT];
+        let offset = buf.as_ptr().align_offset(DIRECT_IO_ALIGNMENT);
+        Self {
+            buf,
+            offset,
+            len: 0,
+            written: 0,
+        }
+    }
+
+    fn write_all(&mut self, file: &mut File, mut data: &[u8]) -> std::io::Result<()> {
+        while !data.is_empty() {
+            let n = data.len().min(WRITE_BLOCK_SIZE - self.len);
+            let start = self.offset + self.len;
+            self.buf[start..start + n].copy_from_slice(&data[..n]);
+            self.len += n;
+            data = &data[n..];
+            if self.len == WRITE_BLOCK_SIZE {
+                file.write_all(&self.buf[self.offset..self.offset + WRITE_BLOCK_SIZE])?;
+                self.written += self.len as u64;
+                self.len = 0;
             }
-            writer.write_all(&label[..16])?;
         }
+        Ok(())
+    }
+
+    /// Write the last partial block padded with zeros and truncate the padding.
+    fn finish(&mut self, file: &mut File) -> std::io::Result<()> {
+        if self.len == 0 {
+            return Ok(());
+        }
+        let padded = self.len.next_multiple_of(DIRECT_IO_ALIGNMENT);
+        self.buf[self.offset + self.len..self.offset + padded].fill(0);
+        file.write_all(&self.buf[self.offset..self.offset + padded])?;
+        self.written += self.len as u64;
+        self.len = 0;
+        file.set_len(self.written)
+    }
+}
+
+pub const INIT_PROGRESS_FILE_NAME: &str = "postdata_init.json";
+
+/// Checkpoint of [Initialize::initialize], updated after every completed file,
+/// so that an interrupted initialization resumes with the best VRF nonce found so far.
+#[serde_as]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct InitProgress {
+    /// The metadata of the data being initialized with the best nonce so far.
+    metadata: PostMetadata,
+    #[serde(flatten)]
+    params: InitParams,
+    completed_files: u64,
+    #[serde_as(as = "Option<Base64>")]
+    nonce_label: Option<[u8; 32]>,
+}
+
+/// The parameters of an initialization which aren't in its metadata.
+#[serde_as]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct InitParams {
+    #[serde(default)]
+    scrypt: Option<ScryptParams>,
+    /// The VRF difficulty the initialization started with.
+    #[serde(default)]
+    #[serde_as(as = "Option<Base64>")]
+    vrf_difficulty: Option<[u8; 32]>,
+}
+
+impl InitProgress {
+    fn new(
+        metadata: &PostMetadata,
+        params: &InitParams,
+        completed_files: u64,
+        nonce: Option<VrfNonce>,
+    ) -> Self {
+        Self {
+            metadata: PostMetadata {
+                nonce: nonce.map(|n| n.index),
+                ..*metadata
+            },
+            params: params.clone(),
+            completed_files,
+            nonce_label: nonce.map(|n| n.label),
+        }
+    }
+
+    /// Load the progress of initializing the data described by `metadata` with `params`.
+    /// Progress of initializing different data (or with other parameters) is ignored.
+    fn load(
+        datadir: &Path,
+        metadata: &PostMetadata,
+        params: &InitParams,
+    ) -> Result<Option<Self>, Error> {
+        let progress: Self = match std::fs::read(datadir.join(INIT_PROGRESS_FILE_NAME)) {
+            Ok(data) => serde_json::from_slice(&data)?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e.into()),
+        };
+        let same_data = PostMetadata {
+            nonce: None,
+            ..progress.metadata
+        } == *metadata
+            && progress.params == *params;
+        if !same_data {
+            log::warn!("ignoring progress of initializing different data: {progress:?}");
+            return Ok(None);
+        }
+        Ok(Some(progress))
+    }
+
+    fn save(&self, datadir: &Path) -> Result<(), Error> {
+        let path = datadir.join(INIT_PROGRESS_FILE_NAME);
+        let tmp_path = path.with_extension("json.tmp");
+        std::fs::write(&tmp_path, serde_json::to_vec(self)?)?;
+        std::fs::rename(&tmp_path, &path)?;
+        Ok(())
+    }
+
+    fn remove(datadir: &Path) -> std::io::Result<()> {
+        match std::fs::remove_file(datadir.join(INIT_PROGRESS_FILE_NAME)) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e),
+            _ => Ok(()),
+        }
+    }
+
+    fn nonce(&self) -> Option<VrfNonce> {
+        Some(VrfNonce {
+            index: self.metadata.nonce?,
+            label: self.nonce_label?,
+        })
+    }
+
+    /// The number of completed files still present with the expected size.
+    fn completed_files(&self, datadir: &Path, labels_per_file: u64, total_labels: u64) -> u64 {
+        (0..self.completed_files)
+            .take_while(|&file_id| {
+                let index = file_id * labels_per_file;
+                let expected =
+                    (total_labels.min(index + labels_per_file) - index) * LABEL_SIZE as u64;
+                std::fs::metadata(datadir.join(format!("postdata_{file_id}.bin")))
+                    .is_ok_and(|m| m.len() == expected)
+            })
+            .count() as u64
+    }
+}
+
+/// Calculate the labels with `scrypt_labels` in chunks of `chunk_size` and write them.
+pub(crate) fn initialize_in_chunks<F>(
+    writer: &mut dyn Write,
+    labels: Range<u64>,
+    chunk_size: u64,
+    mut vrf_difficulty: Option<[u8; 32]>,
+    scrypt_labels: F,
+) -> Result<Option<VrfNonce>, Error>
+where
+    F: Fn(Range<u64>) -> Vec<[u8; 32]> + Sync,
+{
+    if labels.end.saturating_sub(labels.start) <= chunk_size {
+        let data = scrypt_labels(labels.clone());
+        return Ok(write_labels(
+            writer,
+            &data,
+            labels.start,
+            &mut vrf_difficulty,
+        )?);
+    }
 
+    std::thread::scope(|scope| {
+        // Calculate the next chunk while the previous one is being written.
+        let (tx, rx) = sync_channel(1);
+        let scrypt_labels = &scrypt_labels;
+        scope.spawn(move || {
+            let mut start = labels.start;
+            while start < labels.end {
+                let end = labels.end.min(start + chunk_size);
+                // Stop when writing failed.
+                if tx.send((start, scrypt_labels(start..end))).is_err() {
+                    return;
+                }
+                start = end;
+            }
+        });
+
+        let mut best_nonce = None;
+        for (start, data) in rx {
+            if let Some(nonce) = write_labels(writer, &data, start, &mut vrf_difficulty)? {
+                best_nonce = Some(nonce);
+            }
+        }
         Ok(best_nonce)
+    })
+}
+
+/// Write the labels starting at `first_index`, looking for a VRF nonce below `vrf_difficulty`.
+fn write_labels(
+    writer: &mut dyn Write,
+    labels: &[[u8; 32]],
+    first_index: u64,
+    vrf_difficulty: &mut Option<[u8; 32]>,
+) -> std::io::Result<Option<VrfNonce>> {
+    let mut best_nonce = None;
+    let mut data = Vec::with_capacity(labels.len() * LABEL_SIZE);
+    for (id, label) in labels.iter().enumerate() {
+        if let Some(difficulty) = vrf_difficulty {
+            if label < difficulty {
+                best_nonce = Some(VrfNonce {
+                    index: first_index + id as u64,
+                    label: *label,
+                });
+                *vrf_difficulty = Some(*label);
+                log::trace!("Found new smallest nonce: {best_nonce:?}");
+            }
+        }
+        data.extend_from_slice(&label[..LABEL_SIZE]);
     }
+    writer.write_all(&data)?;
+    Ok(best_nonce)
+}
+
+impl Initialize for CpuInitializer {
+    fn initialize_to(
+        &mut self,
+        writer: &mut dyn Write,
+        commitment: &[u8; 32],
+        labels: Range<u64>,
+        vrf_difficulty: Option<[u8; 32]>,
+    ) -> Result<Option<VrfNonce>, Error> {
+        initialize_in_chunks(writer, labels, self.chunk_size, vrf_difficulty, |labels| {
+            self.scrypt_labels(commitment, labels)
+        })
+    }
+
+    fn scrypt_params(&self) -> Option<ScryptParams> {
+        Some(self.scrypt_params)
+    }
+}
+
+/// Progress of an initialization with [Tracked], shared with the threads watching it.
+#[derive(Debug, Default)]
+pub struct InitTracker {
+    labels_done: AtomicU64,
+    // When the initialization started and the number of labels initialized before.
+    started: Mutex<Option<(Instant, u64)>>,
+    nonce: Mutex<Option<u64>>,
+    stopped: AtomicBool,
+}
+
+impl InitTracker {
+    /// The number of labels initialized, including the ones initialized before an interruption.
+    pub fn labels_done(&self) -> u64 {
+        self.labels_done.load(Ordering::Relaxed)
+    }
+
+    /// The index of the best VRF nonce found so far in this initialization.
+    pub fn nonce(&self) -> Option<u64> {
+        *self.nonce.lock().unwrap()
+    }
+
+    /// Estimated time to finish initializing `total_labels`,
+    /// `None` until some labels are initialized.
+    pub fn eta(&self, total_labels: u64) -> Option<Duration> {
+        let (started, done_before) = (*self.started.lock().unwrap())?;
+        let done = self
+            .labels_done()
+            .checked_sub(done_before)
+            .filter(|&d| d > 0)?;
+        let left = total_labels.saturating_sub(self.labels_done());
+        Some(started.elapsed().mul_f64(left as f64 / done as f64))
+    }
+
+    /// Stop the initialization. It fails with an error on its next write.
+    pub fn stop(&self) {
+        self.stopped.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_stopped(&self) -> bool {
+        self.stopped.load(Ordering::Relaxed)
+    }
+}
+
+/// Tracks the progress of the inner initializer in an [InitTracker]
+/// and stops it when the tracker is stopped.
+pub struct Tracked<'a> {
+    inner: &'a mut dyn Initialize,
+    tracker: &'a InitTracker,
+}
+
+impl<'a> Tracked<'a> {
+    pub fn new(inner: &'a mut dyn Initialize, tracker: &'a InitTracker) -> Self {
+        Self { inner, tracker }
+    }
+}
+
+impl Initialize for Tracked<'_> {
+    fn initialize_to(
+        &mut self,
+        writer: &mut dyn Write,
+        commitment: &[u8; 32],
+        labels: Range<u64>,
+        vrf_difficulty: Option<[u8; 32]>,
+    ) -> Result<Option<VrfNonce>, Error> {
+        self.tracker
+            .labels_done
+            .store(labels.start, Ordering::Relaxed);
+        self.tracker
+            .started
+            .lock()
+            .unwrap()
+            .get_or_insert((Instant::now(), labels.start));
+        let mut writer = TrackingWriter {
+            inner: writer,
+            tracker: self.tracker,
+            pending: 0,
+        };
+        let nonce = self
+            .inner
+            .initialize_to(&mut writer, commitment, labels, vrf_difficulty)
+            .map_err(|e| match self.tracker.is_stopped() {
+                true => Error::Stopped,
+                false => e,
+            })?;
+        if let Some(nonce) = &nonce {
+            *self.tracker.nonce.lock().unwrap() = Some(nonce.index);
+        }
+        Ok(nonce)
+    }
+
+    fn scrypt_params(&self) -> Option<ScryptParams> {
+        self.inner.scrypt_params()
+    }
+}
+
+struct TrackingWriter<'a> {
+    inner: &'a mut dyn Write,
+    tracker: &'a InitTracker,
+    // Bytes of a partially written label.
+    pending: u64,
+}
+
+impl Write for TrackingWriter<'_> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        if self.tracker.is_stopped() {
+            return Err(std::io::Error::other("initialization stopped"));
+        }
+        let written = self.inner.write(buf)?;
+        self.pending += written as u64;
+        self.tracker
+            .labels_done
+            .fetch_add(self.pending / LABEL_SIZE as u64, Ordering::Relaxed);
+        self.pending %= LABEL_SIZE as u64;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// How [wipe] removes POS data.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum WipeMode {
+    /// Delete the files. The filesystem TRIMs the freed blocks if it's configured
+    /// to (i.e. mounted with `discard`).
+    #[default]
+    Delete,
+    /// Overwrite the data with zeros before deleting it.
+    Overwrite,
+}
+
+/// Remove POS data (the labels and the metadata) in `datadir`.
+///
+/// Refuses to run if the data is in use by another process (see [DataDirLock]).
+/// POS data on a block device is made unusable by clearing its superblock.
+pub fn wipe(datadir: &Path, mode: WipeMode) -> eyre::Result<()> {
+    let lock = DataDirLock::acquire(datadir)?;
+
+    if block_device::is_block_data(datadir) {
+        let len = match mode {
+            WipeMode::Delete => SUPERBLOCK_SIZE,
+            WipeMode::Overwrite => File::open(datadir)?.seek(SeekFrom::End(0))?,
+        };
+        log::info!("clearing {len} bytes of {}", datadir.display());
+        zero(datadir, len)?;
+        return Ok(());
+    }
+
+    for entry in pos_files(datadir)? {
+        let path = entry.path();
+        if mode == WipeMode::Overwrite {
+            log::info!("overwriting {}", path.display());
+            zero(&path, entry.metadata()?.len())?;
+        }
+        log::info!("removing {}", path.display());
+        std::fs::remove_file(&path)?;
+    }
+    match std::fs::remove_file(datadir.join(METADATA_FILE_NAME)) {
+        Err(e) if e.kind() != std::io::ErrorKind::NotFound => return Err(e.into()),
+        _ => {}
+    }
+    InitProgress::remove(datadir)?;
+    lock.remove()?;
+    Ok(())
+}
+
+fn zero(path: &Path, len: u64) -> std::io::Result<()> {
+    let mut file = OpenOptions::new().write(true).open(path)?;
+    let zeros = vec![0u8; 1024 * 1024];
+    let mut written = 0;
+    while written < len {
+        let n = zeros.len().min((len - written) as usize);
+        file.write_all(&zeros[..n])?;
+        written += n as u64;
+    }
+    file.sync_all()
 }
 
 #[inline]
@@ -157,7 +760,7 @@ pub(crate) fn generate_label(commitment: &[u8; 32], params: ScryptParams, index:
 mod tests {
     use std::io::Read;
 
-    use crate::{metadata, reader};
+    use crate::{lock::LOCK_FILE_NAME, metadata, reader};
 
     use super::*;
 
@@ -176,6 +779,170 @@ mod tests {
         assert_eq!(expected_size, pos_file.metadata().unwrap().len());
     }
 
+    #[test]
+    fn initializing_in_chunks() {
+        let labels = 7..100;
+        let commitment = [0u8; 32];
+        let scrypt_params = ScryptParams::new(4, 1, 1);
+
+        let mut expected = Vec::new();
+        let expected_nonce = CpuInitializer::new(scrypt_params)
+            .initialize_to(&mut expected, &commitment, labels.clone(), Some([0xFF; 32]))
+            .unwrap();
+
+        for chunk_size in [1, 10, 93] {
+            let mut data = Vec::new();
+            let nonce = CpuInitializer::new(scrypt_params)
+                .with_chunk_size(chunk_size)
+                .initialize_to(&mut data, &commitment, labels.clone(), Some([0xFF; 32]))
+                .unwrap();
+            assert_eq!(expected, data);
+            assert_eq!(expected_nonce, nonce);
+        }
+    }
+
+    /// Fails after initializing `files` files.
+    struct Interrupted {
+        inner: CpuInitializer,
+        files: usize,
+        initialized: Vec<Range<u64>>,
+    }
+
+    impl Initialize for Interrupted {
+        fn initialize_to(
+            &mut self,
+            writer: &mut dyn Write,
+            commitment: &[u8; 32],
+            labels: Range<u64>,
+            vrf_difficulty: Option<[u8; 32]>,
+        ) -> Result<Option<VrfNonce>, Error> {
+            if self.files == 0 {
+                return Err(Error::Stopped);
+            }
+            self.files -= 1;
+            self.initialized.push(labels.clone());
+            self.inner
+                .initialize_to(writer, commitment, labels, vrf_difficulty)
+        }
+
+        fn scrypt_params(&self) -> Option<ScryptParams> {
+            self.inner.scrypt_params()
+        }
+    }
+
+    #[test]
+    fn resuming_interrupted_initialization() {
+        let scrypt_params = ScryptParams::new(2, 1, 1);
+        let expected_dir = tempfile::tempdir().unwrap();
+        let expected = CpuInitializer::new(scrypt_params)
+            .initialize(
+                expected_dir.path(),
+                &[1; 32],
+                &[2; 32],
+                100,
+                10,
+                100,
+                Some([0xFF; 32]),
+            )
+            .unwrap();
+        assert!(expected.nonce.is_some());
+
+        let datadir = tempfile::tempdir().unwrap();
+        let mut interrupted = Interrupted {
+            inner: CpuInitializer::new(scrypt_params),
+            files: 4,
+            initialized: Vec::new(),
+        };
+        assert!(interrupted
+            .initialize(
+                datadir.path(),
+                &[1; 32],
+                &[2; 32],
+                100,
+                10,
+                100,
+                Some([0xFF; 32])
+            )
+            .is_err());
+        // The best nonce of the completed files is kept
+        let best_nonce = CpuInitializer::new(scrypt_params)
+            .initialize_to(
+                &mut std::io::sink(),
+                &calc_commitment(&[1; 32], &[2; 32]),
+                0..400,
+                Some([0xFF; 32]),
+            )
+            .unwrap();
+        let params = InitParams {
+            scrypt: Some(scrypt_params),
+            vrf_difficulty: Some([0xFF; 32]),
+        };
+        let progress = InitProgress::load(
+            datadir.path(),
+            &PostMetadata {
+                nonce: None,
+                ..expected
+            },
+            &params,
+        )
+        .unwrap()
+        .unwrap();
+        assert_eq!(4, progress.completed_files);
+        assert_eq!(best_nonce, progress.nonce());
+
+        // Progress of initializing with other parameters is ignored
+        let metadata = PostMetadata {
+            nonce: None,
+            ..expected
+        };
+        for other in [
+            InitParams {
+                scrypt: Some(ScryptParams::new(4, 1, 1)),
+                ..params.clone()
+            },
+            InitParams {
+                vrf_difficulty: None,
+                ..params.clone()
+            },
+        ] {
+            let progress = InitProgress::load(datadir.path(), &metadata, &other).unwrap();
+            assert!(progress.is_none());
+        }
+
+        let mut resumed = Interrupted {
+            inner: CpuInitializer::new(scrypt_params),
+            files: usize::MAX,
+            initialized: Vec::new(),
+        };
+        let metadata = resumed
+            .initialize(
+                datadir.path(),
+                &[1; 32],
+                &[2; 32],
+                100,
+                10,
+                100,
+                Some([0xFF; 32]),
+            )
+            .unwrap();
+        // Only the remaining files are initialized
+        assert_eq!(
+            (4..10)
+                .map(|id| id * 100..(id + 1) * 100)
+                .collect::<Vec<_>>(),
+            resumed.initialized
+        );
+        assert_eq!(expected, metadata);
+        assert!(!datadir.path().join(INIT_PROGRESS_FILE_NAME).exists());
+        for id in 0..10 {
+            let name = format!("postdata_{id}.bin");
+            assert_eq!(
+                std::fs::read(expected_dir.path().join(&name)).unwrap(),
+                std::fs::read(datadir.path().join(&name)).unwrap()
+            );
+        }
+    }
+
     #[test]
     fn test_initialize_fits_in_single_file() {
         let scrypt_params = ScryptParams::new(4, 1, 1);
@@ -190,11 +957,13 @@ mod tests {
 
         for entry in std::fs::read_dir(data_path).unwrap() {
             let path = entry.unwrap().path();
-            if path.extension().unwrap() == "bin" {
-                assert_eq!(Some("postdata_0.bin".as_ref()), path.file_name());
-                assert_eq!(16000, path.metadata().unwrap().len());
-            } else {
-                assert_eq!(Some("postdata_metadata.json".as_ref()), path.file_name());
+            match path.extension().unwrap().to_str().unwrap() {
+                "bin" => {
+                    assert_eq!(Some("postdata_0.bin".as_ref()), path.file_name());
+                    assert_eq!(16000, path.metadata().unwrap().len());
+                }
+                "lock" => assert_eq!(Some(LOCK_FILE_NAME.as_ref()), path.file_name()),
+                _ => assert_eq!(Some("postdata_metadata.json".as_ref()), path.file_name()),
             }
         }
     }
@@ -246,7 +1015,7 @@ mod tests {
             let path = entry.unwrap().path();
             let size = path.metadata().unwrap().len();
             match path.file_name().unwrap().to_str().unwrap() {
-                "postdata_metadata.json" => {}
+                "postdata_metadata.json" | LOCK_FILE_NAME => {}
                 "postdata_66.bin" => {
                     assert_eq!(16 * 10, size); // Last one is smaller
                     total_size += size;
@@ -309,4 +1078,186 @@ mod tests {
         let metadata_single = metadata::load(&data_path.join("single")).unwrap();
         assert_eq!(metadata_many.nonce, metadata_single.nonce);
     }
+
+    #[test]
+    fn writing_with_options_gives_same_result() {
+        let scrypt_params = ScryptParams::new(4, 1, 1);
+        let data_dir = tempfile::tempdir().unwrap();
+        let data_path = data_dir.path();
+        let init = |name: &str, options: &WriteOptions| {
+            CpuInitializer::new(scrypt_params)
+                .initialize_with_options(
+                    &data_path.join(name),
+                    &[0u8; 32],
+                    &[0u8; 32],
+                    250,
+                    4,
+                    250,
+                    Some([0xFFu8; 32]),
+                    options,
+                )
+                .unwrap()
+        };
+
+        let expected = init("default", &WriteOptions::default());
+        let options = WriteOptions {
+            sync: SyncPolicy::AtEnd,
+            direct_io: false,
+            rate_limit: Some(Arc::new(RateLimiter::new(8000.try_into().unwrap()))),
+        };
+        let start = std::time::Instant::now();
+        let metadata = init("options", &options);
+        // 16000 bytes at 8000 B/s, the first write is not delayed.
+        let elapsed = start.elapsed();
+        assert!(
+            elapsed >= std::time::Duration::from_millis(1400),
+            "{elapsed:?}"
+        );
+        assert_eq!(expected, metadata);
+        assert!(!data_path
+            .join("options")
+            .join(INIT_PROGRESS_FILE_NAME)
+            .exists());
+        for id in 0..4 {
+            let file = format!("postdata_{id}.bin");
+            assert_eq!(
+                std::fs::read(data_path.join("default").join(&file)).unwrap(),
+                std::fs::read(data_path.join("options").join(file)).unwrap()
+            );
+        }
+    }
+
+    #[test]
+    fn tracking_initialization() {
+        let data_dir = tempfile::tempdir().unwrap();
+        let tracker = InitTracker::default();
+        assert_eq!(None, tracker.eta(1000));
+
+        let mut initializer = CpuInitializer::new(ScryptParams::new(2, 1, 1));
+        let metadata = Tracked::new(&mut initializer, &tracker)
+            .initialize(
+                data_dir.path(),
+                &[0u8; 32],
+                &[0u8; 32],
+                250,
+                4,
+                300,
+                Some([0xFFu8; 32]),
+            )
+            .unwrap();
+        assert_eq!(1000, tracker.labels_done());
+        assert_eq!(metadata.nonce, tracker.nonce());
+        assert_eq!(Some(Duration::ZERO), tracker.eta(1000));
+
+        tracker.stop();
+        let mut initializer = CpuInitializer::new(ScryptParams::new(2, 1, 1));
+        assert!(Tracked::new(&mut initializer, &tracker)
+            .initialize(
+                &data_dir.path().join("stopped"),
+                &[0u8; 32],
+                &[0u8; 32],
+                250,
+                4,
+                300,
+                None,
+            )
+            .is_err());
+    }
+
+    #[test]
+    fn writing_aligned_blocks() {
+        let file = tempfile::NamedTempFile::new().unwrap();
+        let data = (0..WRITE_BLOCK_SIZE + 5000)
+            .map(|i| i as u8)
+            .collect::<Vec<_>>();
+        let mut writer = PosFileWriter {
+            file: file.reopen().unwrap(),
+            rate_limit: None,
+            direct: Some(AlignedBuffer::new()),
+        };
+        for chunk in data.chunks(3000) {
+            writer.write_all(chunk).unwrap();
+        }
+        writer.finish().unwrap();
+        assert_eq!(data, std::fs::read(file.path()).unwrap());
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn initializing_with_direct_io() {
+        let data_dir = tempfile::tempdir().unwrap();
+        let options = WriteOptions {
+            direct_io: true,
+            ..Default::default()
+        };
+        // Not every filesystem (i.e. tmpfs) supports O_DIRECT.
+        if let Ok(metadata) = CpuInitializer::new(ScryptParams::new(4, 1, 1))
+            .initialize_with_options(
+                data_dir.path(),
+                &[0u8; 32],
+                &[0u8; 32],
+                300,
+                1,
+                300,
+                None,
+                &options,
+            )
+        {
+            assert_eq!(
+                metadata.total_size(),
+                std::fs::metadata(data_dir.path().join("postdata_0.bin"))
+                    .unwrap()
+                    .len()
+            );
+        }
+    }
+
+    #[rstest::rstest]
+    #[case(WipeMode::Delete)]
+    #[case(WipeMode::Overwrite)]
+    fn wiping_pos_data(#[case] mode: WipeMode) {
+        let data_dir = tempfile::tempdir().unwrap();
+        let data_path = data_dir.path();
+        CpuInitializer::new(ScryptParams::new(2, 1, 1))
+            .initialize(data_path, &[0u8; 32], &[0u8; 32], 100, 2, 30, None)
+            .unwrap();
+        std::fs::write(data_path.join("other.txt"), "keep me").unwrap();
+
+        // Refuses to wipe data in use
+        let lock = DataDirLock::acquire(data_path).unwrap();
+        assert!(CpuInitializer::new(ScryptParams::new(2, 1, 1))
+            .initialize(data_path, &[0u8; 32], &[0u8; 32], 100, 2, 30, None)
+            .is_err());
+        assert!(wipe(data_path, mode).is_err());
+        assert!(metadata::load(data_path).is_ok());
+        drop(lock);
+
+        wipe(data_path, mode).unwrap();
+        let remaining: Vec<_> = std::fs::read_dir(data_path)
+            .unwrap()
+            .map(|e| e.unwrap().file_name())
+            .collect();
+        assert_eq!(vec!["other.txt"], remaining);
+    }
+
+    #[test]
+    fn wiping_block_device() {
+        let tmp = tempfile::tempdir().unwrap();
+        let device = tmp.path().join("device");
+        block_device::initialize(
+            &mut CpuInitializer::new(ScryptParams::new(2, 1, 1)),
+            &device,
+            &[0u8; 32],
+            &[0u8; 32],
+            100,
+            2,
+            None,
+        )
+        .unwrap();
+        assert!(metadata::load(&device).is_ok());
+
+        wipe(&device, WipeMode::Overwrite).unwrap();
+        assert!(metadata::load(&device).is_err());
+        assert!(std::fs::read(&device).unwrap().iter().all(|b| *b == 0));
+    }
 }
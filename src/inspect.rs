@@ -0,0 +1,248 @@
+//! Inspection of proofs, for debugging reports of invalid proofs.
+//!
+//! [inspect] decompresses the indices of a proof, re-derives the label of every index
+//! and reports which difficulty condition it satisfies (see [crate::cipher]).
+//! Unlike the [Verifier](crate::verification::Verifier), it doesn't stop on the first
+//! invalid index. [ProofReport::read_labels] compares the labels with the ones stored
+//! in POS data.
+
+use std::{
+    fs::File,
+    io::{Read, Seek, SeekFrom},
+    path::Path,
+};
+
+use rayon::prelude::{IntoParallelIterator, ParallelIterator};
+use serde::Serialize;
+use serde_with::{hex::Hex, serde_as};
+
+use crate::{
+    block_device,
+    cipher::AesCipher,
+    compression::{self, required_bits, CompressedIndices},
+    config::{InitConfig, ProofConfig},
+    difficulty::{self, proving_difficulty},
+    initialize::{calc_commitment, generate_label, LABEL_SIZE},
+    metadata::{PostMetadata, ProofMetadata},
+    prove::{Proof, Prover8_56},
+};
+
+#[derive(thiserror::Error, Debug)]
+pub enum Error {
+    #[error("invalid indices: {0}")]
+    Indices(#[from] compression::Error),
+    #[error("invalid difficulty: {0}")]
+    Difficulty(#[from] difficulty::Error),
+    #[error("reading label {index}: {source}")]
+    ReadLabel { index: u64, source: std::io::Error },
+}
+
+/// The difficulty condition satisfied (or not) by the label of an index.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Condition {
+    /// The MSB is below the MSB of the difficulty.
+    Msb,
+    /// The MSB is equal to the MSB of the difficulty and the LSB are below the rest of it.
+    Lsb,
+    /// The MSB is above the MSB of the difficulty.
+    InvalidMsb,
+    /// The MSB is equal to the MSB of the difficulty, but the LSB are not below the rest of it.
+    InvalidLsb,
+}
+
+impl Condition {
+    pub fn is_satisfied(self) -> bool {
+        matches!(self, Self::Msb | Self::Lsb)
+    }
+}
+
+/// The place of a label in POS data files: `postdata_{file}.bin` at `offset` bytes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct LabelLocation {
+    pub file: usize,
+    pub offset: u64,
+}
+
+impl LabelLocation {
+    pub fn new(index: u64, max_file_size: u64) -> Self {
+        let position = index * LABEL_SIZE as u64;
+        Self {
+            file: (position / max_file_size) as usize,
+            offset: position % max_file_size,
+        }
+    }
+}
+
+#[serde_as]
+#[derive(Debug, Clone, Serialize)]
+pub struct IndexReport {
+    /// The position of the index in the proof.
+    pub index_id: usize,
+    /// The index of the label.
+    pub index: u64,
+    /// The label re-derived from the commitment.
+    #[serde_as(as = "Hex")]
+    pub label: [u8; 16],
+    /// The byte of the label encrypted with the cipher of the nonce group.
+    pub msb: u8,
+    /// The 56 lowest bits of the label encrypted with the cipher of the nonce.
+    pub lsb: u64,
+    pub condition: Condition,
+    /// Set by [ProofReport::read_labels], `None` for POS data on a block device
+    /// and for indices out of its range.
+    pub location: Option<LabelLocation>,
+    /// The label stored in POS data, set by [ProofReport::read_labels].
+    #[serde_as(as = "Option<Hex>")]
+    pub stored_label: Option<[u8; 16]>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ProofReport {
+    pub nonce: u32,
+    pub nonce_group: u32,
+    pub pow: u64,
+    /// The proving difficulty (see [proving_difficulty]).
+    pub difficulty: u64,
+    pub difficulty_msb: u8,
+    pub difficulty_lsb: u64,
+    pub indices: Vec<IndexReport>,
+}
+
+impl ProofReport {
+    /// The indices which labels don't satisfy the difficulty.
+    pub fn invalid(&self) -> impl Iterator<Item = &IndexReport> {
+        self.indices.iter().filter(|r| !r.condition.is_satisfied())
+    }
+
+    /// The indices which labels differ from the ones stored in POS data.
+    pub fn mismatched(&self) -> impl Iterator<Item = &IndexReport> {
+        self.indices
+            .iter()
+            .filter(|r| r.stored_label.is_some_and(|label| label != r.label))
+    }
+
+    /// Read the labels of the indices from POS data in `datadir` (or a block device).
+    /// Indices out of the range of the POS data are skipped.
+    pub fn read_labels(&mut self, datadir: &Path, metadata: &PostMetadata) -> Result<(), Error> {
+        let block_device = block_device::is_block_data(datadir);
+        let total_labels = metadata.total_labels();
+        for report in self.indices.iter_mut().filter(|r| r.index < total_labels) {
+            let location = LabelLocation::new(report.index, metadata.max_file_size);
+            let (path, offset) = match block_device {
+                true => (
+                    datadir.to_path_buf(),
+                    block_device::SUPERBLOCK_SIZE + report.index * LABEL_SIZE as u64,
+                ),
+                false => (
+                    datadir.join(format!("postdata_{}.bin", location.file)),
+                    location.offset,
+                ),
+            };
+            let read = || -> std::io::Result<[u8; 16]> {
+                let mut file = File::open(path)?;
+                file.seek(SeekFrom::Start(offset))?;
+                let mut label = [0u8; 16];
+                file.read_exact(&mut label)?;
+                Ok(label)
+            };
+            report.stored_label = Some(read().map_err(|source| Error::ReadLabel {
+                index: report.index,
+                source,
+            })?);
+            report.location = (!block_device).then_some(location);
+        }
+        Ok(())
+    }
+}
+
+/// Inspect the labels of all indices of the proof.
+///
+/// Neither the PoW nor the metadata are verified.
+pub fn inspect(
+    proof: &Proof,
+    metadata: &ProofMetadata,
+    cfg: &ProofConfig,
+    init_cfg: &InitConfig,
+) -> Result<ProofReport, Error> {
+    let num_labels = init_cfg.unit_size().labels(metadata.num_units);
+    let difficulty = proving_difficulty(cfg.k1, num_labels)?;
+    let (difficulty_msb, difficulty_lsb) = Prover8_56::split_difficulty(difficulty);
+    let indices = CompressedIndices::new(&proof.indices, required_bits(num_labels), cfg.k2 as _)?;
+
+    let nonce_group = proof.nonce / Prover8_56::NONCES_PER_AES;
+    let cipher = AesCipher::new(&metadata.challenge, nonce_group, proof.pow);
+    let lazy_cipher = AesCipher::new_lazy(&metadata.challenge, proof.nonce, nonce_group, proof.pow);
+    let output_index = (proof.nonce % Prover8_56::NONCES_PER_AES) as usize;
+    let commitment = calc_commitment(&metadata.node_id, &metadata.commitment_atx_id);
+
+    let indices = indices
+        .iter()
+        .enumerate()
+        .collect::<Vec<_>>()
+        .into_par_iter()
+        .map(|(index_id, index)| {
+            let label = generate_label(&commitment, init_cfg.scrypt, index);
+            let msb = cipher.encrypt(&label)[output_index];
+            let output = lazy_cipher.encrypt(&label);
+            let lsb = u64::from_le_bytes(output[..8].try_into().unwrap()) & 0x00ff_ffff_ffff_ffff;
+            let condition = match msb.cmp(&difficulty_msb) {
+                std::cmp::Ordering::Less => Condition::Msb,
+                std::cmp::Ordering::Greater => Condition::InvalidMsb,
+                std::cmp::Ordering::Equal if lsb < difficulty_lsb => Condition::Lsb,
+                std::cmp::Ordering::Equal => Condition::InvalidLsb,
+            };
+            IndexReport {
+                index_id,
+                index,
+                label,
+                msb,
+                lsb,
+                condition,
+                location: None,
+                stored_label: None,
+            }
+        })
+        .collect();
+
+    Ok(ProofReport {
+        nonce: proof.nonce,
+        nonce_group,
+        pow: proof.pow,
+        difficulty,
+        difficulty_msb,
+        difficulty_lsb,
+        indices,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn locating_labels() {
+        assert_eq!(
+            LabelLocation { file: 0, offset: 0 },
+            LabelLocation::new(0, 64)
+        );
+        assert_eq!(
+            LabelLocation {
+                file: 0,
+                offset: 48
+            },
+            LabelLocation::new(3, 64)
+        );
+        assert_eq!(
+            LabelLocation { file: 1, offset: 0 },
+            LabelLocation::new(4, 64)
+        );
+        assert_eq!(
+            LabelLocation {
+                file: 2,
+                offset: 16
+            },
+            LabelLocation::new(9, 64)
+        );
+    }
+}
@@ -1,12 +1,26 @@
-mod cipher;
+pub mod block_device;
+pub mod cipher;
 pub mod compression;
 pub mod config;
-mod difficulty;
+pub mod difficulty;
+mod error;
 pub mod initialize;
+#[cfg(feature = "parallel")]
+pub mod inspect;
+pub mod lock;
 pub mod metadata;
+#[cfg(feature = "object-storage")]
+pub mod object_storage;
 pub mod pos_verification;
 pub mod pow;
 pub mod prove;
-mod random_values_gen;
+pub mod random_values_gen;
 pub mod reader;
+#[cfg(feature = "cpu-opt")]
+pub mod scrypt_opt;
+#[cfg(feature = "test-vectors")]
+pub mod test_vectors;
+pub mod topology;
 pub mod verification;
+
+pub use error::{Error, ErrorCode};
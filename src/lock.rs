@@ -0,0 +1,255 @@
+//! Advisory locking of POS data.
+//!
+//! A process using POS data holds the lock, so that other processes
+//! (i.e. wiping the data) can detect it and refuse to touch it.
+//! Processes only reading the data (i.e. verifying it) share the lock.
+
+use std::{
+    fs::{File, OpenOptions},
+    io::{ErrorKind, Write},
+    path::{Path, PathBuf},
+};
+
+pub const LOCK_FILE_NAME: &str = "postdata.lock";
+
+#[derive(Debug, thiserror::Error)]
+pub enum LockError {
+    #[error("POS data in {0} is in use by another process")]
+    Locked(PathBuf),
+    #[error("locking POS data: {0}")]
+    Io(#[from] std::io::Error),
+}
+
+/// Lock of POS data, released when dropped.
+#[derive(Debug)]
+pub struct DataDirLock {
+    _file: File,
+    /// The lock file, `None` for a block device.
+    path: Option<PathBuf>,
+}
+
+impl DataDirLock {
+    /// Lock POS data in `datadir` exclusively.
+    ///
+    /// A block device with POS data is locked directly,
+    /// a data directory with a lock file in it.
+    pub fn acquire(datadir: &Path) -> Result<Self, LockError> {
+        Self::lock(datadir, false)
+    }
+
+    /// Lock POS data in `datadir` for reading only. Other readers can hold the lock
+    /// at the same time, but not an exclusive [Self::acquire].
+    pub fn acquire_shared(datadir: &Path) -> Result<Self, LockError> {
+        Self::lock(datadir, true)
+    }
+
+    /// Lock the directory `dir` exclusively with the lock file `name` in it, independently
+    /// of the lock of POS data (i.e. for the files a reader of the POS data keeps in `dir`).
+    pub fn acquire_named(dir: &Path, name: &str) -> Result<Self, LockError> {
+        Self::lock_file(dir.join(name), dir, false)
+    }
+
+    fn lock(datadir: &Path, shared: bool) -> Result<Self, LockError> {
+        if datadir.metadata().is_ok_and(|m| !m.is_dir()) {
+            let file = open(datadir, OpenOptions::new().read(true), datadir, shared)?;
+            try_lock(&file, datadir, shared)?;
+            return Ok(Self {
+                _file: file,
+                path: None,
+            });
+        }
+        Self::lock_file(datadir.join(LOCK_FILE_NAME), datadir, shared)
+    }
+
+    fn lock_file(path: PathBuf, datadir: &Path, shared: bool) -> Result<Self, LockError> {
+        loop {
+            let mut options = OpenOptions::new();
+            options.create(true).truncate(false).write(true);
+            if shared {
+                // Also when the directory is not writable, if the lock file exists.
+                options.read(true);
+            }
+            let mut file = match open(&path, &mut options, datadir, shared) {
+                Err(LockError::Io(e)) if shared && e.kind() == ErrorKind::PermissionDenied => {
+                    open(&path, OpenOptions::new().read(true), datadir, shared)?
+                }
+                result => result?,
+            };
+            try_lock(&file, datadir, shared)?;
+            // The lock file might have been removed (i.e. by wiping the data)
+            // between opening and locking it, then the lock is worthless.
+            if !is_same_file(&file, &path)? {
+                continue;
+            }
+            if !shared {
+                // For information only, the lock is what matters.
+                file.set_len(0)?;
+                writeln!(file, "{}", std::process::id())?;
+            }
+            return Ok(Self {
+                _file: file,
+                path: Some(path),
+            });
+        }
+    }
+
+    /// Remove the lock file, while still holding the lock, and release the lock.
+    pub fn remove(self) -> std::io::Result<()> {
+        match &self.path {
+            Some(path) => std::fs::remove_file(path),
+            None => Ok(()),
+        }
+    }
+}
+
+#[cfg(unix)]
+fn open(
+    path: &Path,
+    options: &mut OpenOptions,
+    _datadir: &Path,
+    _shared: bool,
+) -> Result<File, LockError> {
+    Ok(options.open(path)?)
+}
+
+/// On Windows, the file opened without sharing (except deleting) is the lock.
+/// The shared lock shares reading, so readers coexist, but not with a writer.
+#[cfg(windows)]
+fn open(
+    path: &Path,
+    options: &mut OpenOptions,
+    datadir: &Path,
+    shared: bool,
+) -> Result<File, LockError> {
+    use std::os::windows::fs::OpenOptionsExt;
+    const FILE_SHARE_READ: u32 = 0x1;
+    const FILE_SHARE_WRITE: u32 = 0x2;
+    const FILE_SHARE_DELETE: u32 = 0x4;
+    const ERROR_SHARING_VIOLATION: i32 = 32;
+    let share_mode = match shared {
+        true => FILE_SHARE_READ | FILE_SHARE_DELETE,
+        false => FILE_SHARE_DELETE,
+    };
+    // The shared lock must not ask for writing, it would conflict with the other readers.
+    // The lock file is created first if missing, without holding it.
+    if shared {
+        if !path.exists() {
+            _ = OpenOptions::new()
+                .write(true)
+                .create(true)
+                .truncate(false)
+                .share_mode(FILE_SHARE_READ | FILE_SHARE_WRITE | FILE_SHARE_DELETE)
+                .open(path);
+        }
+        options.write(false).create(false);
+    }
+    match options.share_mode(share_mode).open(path) {
+        Ok(file) => Ok(file),
+        Err(e) if e.raw_os_error() == Some(ERROR_SHARING_VIOLATION) => {
+            Err(LockError::Locked(datadir.to_path_buf()))
+        }
+        Err(e) => Err(e.into()),
+    }
+}
+
+#[cfg(unix)]
+fn try_lock(file: &File, datadir: &Path, shared: bool) -> Result<(), LockError> {
+    use std::os::fd::AsRawFd;
+    let operation = match shared {
+        true => libc::LOCK_SH,
+        false => libc::LOCK_EX,
+    };
+    // SAFETY: the file descriptor is valid while `file` lives.
+    if unsafe { libc::flock(file.as_raw_fd(), operation | libc::LOCK_NB) } == 0 {
+        return Ok(());
+    }
+    let e = std::io::Error::last_os_error();
+    match e.kind() {
+        std::io::ErrorKind::WouldBlock => Err(LockError::Locked(datadir.to_path_buf())),
+        _ => Err(e.into()),
+    }
+}
+
+#[cfg(windows)]
+fn try_lock(_file: &File, _datadir: &Path, _shared: bool) -> Result<(), LockError> {
+    Ok(())
+}
+
+#[cfg(unix)]
+fn is_same_file(file: &File, path: &Path) -> std::io::Result<bool> {
+    use std::os::unix::fs::MetadataExt;
+    let locked = file.metadata()?;
+    match std::fs::metadata(path) {
+        Ok(current) => Ok(locked.dev() == current.dev() && locked.ino() == current.ino()),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(false),
+        Err(e) => Err(e),
+    }
+}
+
+/// The file can't be removed by others while it's open without sharing.
+#[cfg(windows)]
+fn is_same_file(_file: &File, _path: &Path) -> std::io::Result<bool> {
+    Ok(true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn locking_datadir() {
+        let datadir = tempfile::tempdir().unwrap();
+        let lock = DataDirLock::acquire(datadir.path()).unwrap();
+        assert!(matches!(
+            DataDirLock::acquire(datadir.path()),
+            Err(LockError::Locked(_))
+        ));
+        drop(lock);
+        let lock = DataDirLock::acquire(datadir.path()).unwrap();
+
+        lock.remove().unwrap();
+        assert!(!datadir.path().join(LOCK_FILE_NAME).exists());
+        DataDirLock::acquire(datadir.path()).unwrap();
+    }
+
+    #[test]
+    fn sharing_lock() {
+        let datadir = tempfile::tempdir().unwrap();
+        let reader = DataDirLock::acquire_shared(datadir.path()).unwrap();
+        let other_reader = DataDirLock::acquire_shared(datadir.path()).unwrap();
+        assert!(matches!(
+            DataDirLock::acquire(datadir.path()),
+            Err(LockError::Locked(_))
+        ));
+        drop((reader, other_reader));
+
+        let _lock = DataDirLock::acquire(datadir.path()).unwrap();
+        assert!(matches!(
+            DataDirLock::acquire_shared(datadir.path()),
+            Err(LockError::Locked(_))
+        ));
+    }
+
+    #[test]
+    fn locking_named() {
+        let dir = tempfile::tempdir().unwrap();
+        let _reader = DataDirLock::acquire_shared(dir.path()).unwrap();
+        let lock = DataDirLock::acquire_named(dir.path(), "other.lock").unwrap();
+        assert!(matches!(
+            DataDirLock::acquire_named(dir.path(), "other.lock"),
+            Err(LockError::Locked(_))
+        ));
+        drop(lock);
+        DataDirLock::acquire_named(dir.path(), "other.lock").unwrap();
+    }
+
+    #[test]
+    fn locking_block_device() {
+        let device = tempfile::NamedTempFile::new().unwrap();
+        let _lock = DataDirLock::acquire(device.path()).unwrap();
+        assert!(matches!(
+            DataDirLock::acquire(device.path()),
+            Err(LockError::Locked(_))
+        ));
+    }
+}
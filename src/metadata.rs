@@ -4,10 +4,12 @@ use serde::{Deserialize, Serialize};
 use serde_with::base64::Base64;
 use serde_with::serde_as;
 
-const METADATA_FILE_NAME: &str = "postdata_metadata.json";
+use crate::config::UnitSize;
+
+pub const METADATA_FILE_NAME: &str = "postdata_metadata.json";
 
 #[serde_as]
-#[derive(Debug, Deserialize, Serialize, Clone, Copy, Default)]
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, Default, PartialEq, Eq)]
 #[serde(rename_all = "PascalCase")]
 pub struct PostMetadata {
     #[serde_as(as = "Base64")]
@@ -18,16 +20,51 @@ pub struct PostMetadata {
     pub num_units: u32,
     pub max_file_size: u64,
     pub nonce: Option<u64>,
+    /// Set for partially initialized POS data: the labels before this position are initialized.
     pub last_position: Option<u64>,
 }
 
 impl PostMetadata {
+    pub fn unit_size(&self) -> UnitSize {
+        UnitSize::new(self.labels_per_unit)
+    }
+
     pub fn total_labels(&self) -> u64 {
-        self.num_units as u64 * self.labels_per_unit
+        self.unit_size().labels(self.num_units)
     }
 
     pub fn total_size(&self) -> u64 {
-        self.total_labels() * 16
+        self.unit_size().bytes(self.num_units)
+    }
+
+    /// Number of labels initialized (see [Self::last_position]).
+    pub fn initialized_labels(&self) -> u64 {
+        match self.last_position {
+            Some(pos) => pos.min(self.total_labels()),
+            None => self.total_labels(),
+        }
+    }
+
+    /// The metadata of the units that are initialized completely.
+    ///
+    /// For partially initialized POS data, proofs can be generated only for the initialized
+    /// prefix. Fails if not even one unit is initialized.
+    pub fn initialized(&self) -> Result<PostMetadata, crate::Error> {
+        if self.initialized_labels() == self.total_labels() {
+            return Ok(*self);
+        }
+        let num_units = self.unit_size().complete_units(self.initialized_labels());
+        if num_units == 0 {
+            return Err(crate::Error::NotInitialized {
+                initialized: self.initialized_labels(),
+                total: self.total_labels(),
+            });
+        }
+        Ok(PostMetadata {
+            num_units: num_units as u32,
+            last_position: None,
+            ..*self
+        })
     }
 
     pub fn num_files(&self) -> usize {
@@ -52,9 +89,15 @@ impl PostMetadata {
     }
 }
 
-pub fn load(datadir: &Path) -> eyre::Result<PostMetadata> {
+/// Load the metadata of POS data in `datadir`.
+/// `datadir` can also be a block device initialized with [crate::block_device::initialize].
+pub fn load(datadir: &Path) -> Result<PostMetadata, crate::Error> {
+    if crate::block_device::is_block_data(datadir) {
+        return crate::block_device::load_metadata(datadir);
+    }
     let metatada_path = datadir.join(METADATA_FILE_NAME);
-    let metadata_file = File::open(metatada_path)?;
+    let metadata_file = File::open(&metatada_path)
+        .map_err(|e| std::io::Error::new(e.kind(), format!("{}: {e}", metatada_path.display())))?;
     let reader = BufReader::new(metadata_file);
     let m = serde_json::from_reader(reader)?;
     Ok(m)
@@ -62,7 +105,7 @@ pub fn load(datadir: &Path) -> eyre::Result<PostMetadata> {
 
 #[repr(C)]
 #[serde_as]
-#[derive(Debug, Clone, Deserialize, Serialize)]
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
 pub struct ProofMetadata {
     #[serde_as(as = "Base64")]
     pub node_id: [u8; 32],
@@ -107,6 +150,45 @@ mod tests {
         assert_eq!(m.num_files(), 121);
     }
 
+    #[test]
+    fn initialized_prefix() {
+        let m = PostMetadata {
+            labels_per_unit: 100,
+            num_units: 4,
+            max_file_size: 1024,
+            ..Default::default()
+        };
+        assert_eq!(400, m.initialized_labels());
+        assert_eq!(m, m.initialized().unwrap());
+
+        // The position of the nonce search can be past the end of data
+        let complete = PostMetadata {
+            last_position: Some(1000),
+            ..m
+        };
+        assert_eq!(400, complete.initialized_labels());
+        assert_eq!(complete, complete.initialized().unwrap());
+
+        let partial = PostMetadata {
+            last_position: Some(250),
+            ..m
+        };
+        assert_eq!(250, partial.initialized_labels());
+        let initialized = partial.initialized().unwrap();
+        assert_eq!(2, initialized.num_units);
+        assert_eq!(None, initialized.last_position);
+        assert_eq!(200, initialized.initialized_labels());
+
+        let empty = PostMetadata {
+            last_position: Some(99),
+            ..m
+        };
+        assert!(matches!(
+            empty.initialized(),
+            Err(crate::Error::NotInitialized { .. })
+        ));
+    }
+
     #[test]
     fn test_labels_in_file() {
         let m = PostMetadata {
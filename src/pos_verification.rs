@@ -1,15 +1,23 @@
 //! Proof of Space data verification
 
-use std::{io::Read, io::Seek, path::Path};
+use std::{
+    io::Read,
+    io::Seek,
+    path::{Path, PathBuf},
+};
 
 use itertools::Itertools;
 use rand::seq::IteratorRandom;
+#[cfg(feature = "parallel")]
 use rayon::prelude::{ParallelBridge, ParallelIterator};
+use serde::{Deserialize, Serialize};
 
 use crate::{
+    block_device,
     config::ScryptParams,
-    initialize::{calc_commitment, CpuInitializer, Initialize},
-    metadata,
+    initialize::{calc_commitment, CpuInitializer, Initialize, LABEL_SIZE},
+    lock::{DataDirLock, LockError},
+    metadata, reader,
 };
 
 #[derive(Debug, thiserror::Error)]
@@ -22,6 +30,127 @@ pub enum VerificationError {
     Unknown(#[from] eyre::Error),
     #[error("failed to initialize: {0}")]
     InitError(String),
+    #[error(transparent)]
+    Locked(#[from] LockError),
+}
+
+/// A mismatch between the metadata and the size of POS data files (see [check_data_size]).
+#[derive(Debug, thiserror::Error)]
+pub enum DataSizeError {
+    #[error("loading metadata: {0}")]
+    Metadata(crate::Error),
+    #[error("IO error: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("POS data files have {size} bytes, which is not a whole number of labels")]
+    PartialLabel { size: u64 },
+    #[error("POS data files have {labels} labels, which is not a whole number of units of {labels_per_unit} labels (as in the metadata)")]
+    LabelsPerUnit { labels: u64, labels_per_unit: u64 },
+    #[error("POS data files have {data} units, but the metadata has {metadata} units")]
+    NumUnits { metadata: u32, data: u64 },
+    #[error("POS data files have {labels} labels, but {expected} labels are initialized according to the metadata")]
+    MissingLabels { labels: u64, expected: u64 },
+}
+
+/// Check that the total size of POS data files in `datadir` matches the number of units
+/// and the labels per unit in the metadata, i.e. after the data was resized without
+/// updating the metadata.
+///
+/// Partially initialized data must have at least the initialized labels.
+/// POS data on a block device is not checked.
+pub fn check_data_size(datadir: &Path) -> Result<(), DataSizeError> {
+    if block_device::is_block_data(datadir) {
+        return Ok(());
+    }
+    let metadata = metadata::load(datadir).map_err(DataSizeError::Metadata)?;
+    let mut size = 0;
+    for file in reader::pos_files(datadir).map_err(std::io::Error::other)? {
+        size += file.metadata()?.len();
+    }
+    if size % LABEL_SIZE as u64 != 0 {
+        return Err(DataSizeError::PartialLabel { size });
+    }
+
+    let labels = size / LABEL_SIZE as u64;
+    let expected = metadata.initialized_labels();
+    if expected < metadata.total_labels() {
+        return match labels < expected {
+            true => Err(DataSizeError::MissingLabels { labels, expected }),
+            false => Ok(()),
+        };
+    }
+    if labels == expected {
+        return Ok(());
+    }
+    match metadata.unit_size().exact_units(labels) {
+        Some(units) => Err(DataSizeError::NumUnits {
+            metadata: metadata.num_units,
+            data: units,
+        }),
+        None => Err(DataSizeError::LabelsPerUnit {
+            labels,
+            labels_per_unit: metadata.labels_per_unit,
+        }),
+    }
+}
+
+/// Result of verifying a sample of labels in a POS data file.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FileReport {
+    /// Index of the file (`postdata_N.bin`).
+    pub file: usize,
+    /// Number of labels checked.
+    pub labels_checked: usize,
+    /// Indices of the invalid labels (in the whole POS data), sorted.
+    pub invalid_labels: Vec<u64>,
+}
+
+/// Result of verifying the files of a POS data directory, one by one.
+///
+/// An interrupted verification can be resumed from it, skipping the files already verified,
+/// but only for the same data and with the same parameters (see [DataReport::is_for]).
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct DataReport {
+    /// The verified POS data directory.
+    pub datadir: PathBuf,
+    /// Metadata of the POS data at the start of the verification.
+    pub metadata: metadata::PostMetadata,
+    /// Scrypt parameters the labels are verified with.
+    pub scrypt: ScryptParams,
+    /// Fraction (in %) of the labels verified in every file.
+    pub fraction: f64,
+    /// Reports of the files verified so far.
+    pub files: Vec<FileReport>,
+}
+
+impl DataReport {
+    pub fn new(
+        datadir: &Path,
+        metadata: metadata::PostMetadata,
+        scrypt: ScryptParams,
+        fraction: f64,
+    ) -> Self {
+        Self {
+            datadir: datadir.to_path_buf(),
+            metadata,
+            scrypt,
+            fraction,
+            files: Vec::new(),
+        }
+    }
+
+    /// Whether the report is of verifying the same POS data with the same parameters.
+    pub fn is_for(
+        &self,
+        datadir: &Path,
+        metadata: &metadata::PostMetadata,
+        scrypt: ScryptParams,
+        fraction: f64,
+    ) -> bool {
+        self.datadir == datadir
+            && &self.metadata == metadata
+            && self.scrypt == scrypt
+            && self.fraction == fraction
+    }
 }
 
 pub fn verify_files(
@@ -32,23 +161,150 @@ pub fn verify_files(
     scrypt: ScryptParams,
 ) -> Result<(), VerificationError> {
     log::info!("verifying POS data in {}", datadir.display());
-    let metadata = metadata::load(datadir)?;
+    let metadata = metadata::load(datadir).map_err(|e| VerificationError::Unknown(e.into()))?;
+    let _lock = DataDirLock::acquire_shared(datadir)?;
 
     let from_file = from_file.unwrap_or(0);
     let to_file = to_file.unwrap_or(metadata.num_files() - 1);
     log::info!("verifying POS files {from_file} -> {to_file}");
 
     for idx in from_file..=to_file {
-        let file_path = datadir.join(format!("postdata_{}.bin", idx));
-        log::info!("verifying file {}", file_path.display());
+        let report = verify_file(datadir, idx, fraction, &metadata, scrypt)?;
+        if let Some(label) = report.invalid_labels.first() {
+            let labels_offset = idx as u64 * metadata.max_file_size / 16;
+            return Err(VerificationError::InvalidLabel {
+                idx,
+                offset: (label - labels_offset) * 16,
+            });
+        }
+    }
 
-        let file = std::fs::File::open(file_path)?;
-        let reader = std::io::BufReader::new(file);
+    Ok(())
+}
+
+/// The layout of POS data (the metadata and the sizes of the files) at some point,
+/// to check quickly that the data has not been changed (i.e. truncated) since.
+///
+/// The labels are not checked, see [verify_files] for that.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DataSnapshot {
+    metadata_hash: [u8; 32],
+    // Sizes of the files, `None` for POS data on a block device.
+    file_sizes: Option<Vec<u64>>,
+}
 
-        verify(reader, idx, fraction, &metadata, scrypt)?;
+impl DataSnapshot {
+    /// Take a snapshot of the POS data in `datadir`.
+    /// Fails if some files are missing or their size differs from the one expected by the metadata.
+    ///
+    /// Only the files of the initialized units are checked (see [metadata::PostMetadata::initialized]).
+    pub fn take(datadir: &Path) -> Result<Self, crate::Error> {
+        let metadata = metadata::load(datadir)?;
+        let file_sizes = (!block_device::is_block_data(datadir)).then(|| {
+            let initialized = metadata.initialized()?;
+            Ok::<_, crate::Error>(
+                (0..initialized.num_files())
+                    .map(|idx| initialized.labels_in_file(idx) as u64 * LABEL_SIZE as u64)
+                    .collect::<Vec<_>>(),
+            )
+        });
+        let file_sizes = file_sizes.transpose()?;
+        let snapshot = Self {
+            metadata_hash: *blake3::hash(&serde_json::to_vec(&metadata)?).as_bytes(),
+            file_sizes,
+        };
+        snapshot.check(datadir)?;
+        Ok(snapshot)
     }
 
-    Ok(())
+    /// Check that the metadata in `datadir` is the same as when the snapshot was taken
+    /// and that all files exist and have the expected size.
+    ///
+    /// For partially initialized POS data, the last file of the initialized units
+    /// might already hold some labels of the next unit, so it may be larger.
+    ///
+    /// Fails with [crate::Error::DataChanged] if they are not.
+    pub fn check(&self, datadir: &Path) -> Result<(), crate::Error> {
+        let metadata = metadata::load(datadir)?;
+        let hash = blake3::hash(&serde_json::to_vec(&metadata)?);
+        if hash.as_bytes() != &self.metadata_hash {
+            return Err(crate::Error::DataChanged(format!(
+                "metadata in {} has changed: {metadata:?}",
+                datadir.display()
+            )));
+        }
+        let partial = metadata.initialized_labels() < metadata.total_labels();
+        let file_sizes = self.file_sizes.as_deref().unwrap_or_default();
+        for (idx, &expected) in file_sizes.iter().enumerate() {
+            let path = datadir.join(format!("postdata_{idx}.bin"));
+            let size = match std::fs::metadata(&path) {
+                Ok(m) => m.len(),
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                    return Err(crate::Error::DataChanged(format!(
+                        "POS data file {} is missing",
+                        path.display()
+                    )));
+                }
+                Err(e) => return Err(e.into()),
+            };
+            if size < expected {
+                return Err(crate::Error::DataChanged(format!(
+                    "POS data file {} is truncated: {size} < {expected} bytes",
+                    path.display()
+                )));
+            }
+            if size > expected && !(partial && idx == file_sizes.len() - 1) {
+                return Err(crate::Error::DataChanged(format!(
+                    "POS data file {} is extended: {size} > {expected} bytes",
+                    path.display()
+                )));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Verify a random sample (`fraction` %) of labels in every file, finding all invalid labels in the samples.
+///
+/// Unlike [verify_files], it doesn't stop at the first invalid label.
+pub fn verify_sample(
+    datadir: &Path,
+    fraction: f64,
+    scrypt: ScryptParams,
+) -> Result<Vec<FileReport>, VerificationError> {
+    log::info!("verifying a sample of POS data in {}", datadir.display());
+    let metadata = metadata::load(datadir).map_err(|e| VerificationError::Unknown(e.into()))?;
+    let _lock = DataDirLock::acquire_shared(datadir)?;
+
+    (0..metadata.num_files())
+        .map(|idx| verify_file(datadir, idx, fraction, &metadata, scrypt))
+        .collect()
+}
+
+/// Verify a random sample (`fraction` %) of labels in the file `idx`, finding all invalid labels in the sample.
+///
+/// Unlike [verify_files], it doesn't lock the POS data.
+/// For POS data on a block device the labels are read from the device.
+pub fn verify_file(
+    datadir: &Path,
+    idx: usize,
+    fraction: f64,
+    metadata: &metadata::PostMetadata,
+    scrypt: ScryptParams,
+) -> Result<FileReport, VerificationError> {
+    if block_device::is_block_data(datadir) {
+        log::info!("verifying file {idx} on device {}", datadir.display());
+        let labels = block_device::Labels::open(datadir, idx as u64 * metadata.max_file_size)?;
+        let reader = std::io::BufReader::new(labels);
+        return verify(reader, idx, fraction, metadata, scrypt);
+    }
+    let file_path = datadir.join(format!("postdata_{}.bin", idx));
+    log::info!("verifying file {}", file_path.display());
+
+    let file = std::fs::File::open(file_path)?;
+    let reader = std::io::BufReader::new(file);
+
+    verify(reader, idx, fraction, metadata, scrypt)
 }
 
 fn verify<R: Read + Seek + Send>(
@@ -57,7 +313,7 @@ fn verify<R: Read + Seek + Send>(
     fraction: f64,
     metadata: &metadata::PostMetadata,
     scrypt_params: ScryptParams,
-) -> Result<(), VerificationError> {
+) -> Result<FileReport, VerificationError> {
     let commitment = calc_commitment(&metadata.node_id, &metadata.commitment_atx_id);
 
     let labels_count = metadata.labels_in_file(file_idx);
@@ -66,7 +322,7 @@ fn verify<R: Read + Seek + Send>(
     log::info!("verifying {labels_to_verify} labels");
 
     let mut rng = rand::thread_rng();
-    (0..labels_count as u64)
+    let read_labels = (0..labels_count as u64)
         .choose_multiple(&mut rng, labels_to_verify)
         .into_iter()
         .sorted()
@@ -75,31 +331,37 @@ fn verify<R: Read + Seek + Send>(
             labels.seek(std::io::SeekFrom::Start(index * 16))?;
             labels.read_exact(&mut label)?;
             Ok((index, label))
-        })
-        .par_bridge()
-        .map(|index_and_label| -> Result<(), VerificationError> {
-            let (index, label) = index_and_label?;
-            let mut expected_label = [0u8; 16];
-            let label_index = index + labels_offset;
-
-            CpuInitializer::new(scrypt_params)
-                .initialize_to(
+        });
+    // Without the `parallel` feature, on the calling thread.
+    #[cfg(feature = "parallel")]
+    let read_labels = read_labels.par_bridge();
+    let invalid = read_labels
+        .filter_map(
+            |index_and_label| -> Option<Result<u64, VerificationError>> {
+                let (index, label) = match index_and_label {
+                    Ok(v) => v,
+                    Err(e) => return Some(Err(e)),
+                };
+                let mut expected_label = [0u8; 16];
+                let label_index = index + labels_offset;
+
+                if let Err(e) = CpuInitializer::new(scrypt_params).initialize_to(
                     &mut expected_label.as_mut_slice(),
                     &commitment,
                     label_index..label_index + 1,
                     None,
-                )
-                .map_err(|e| VerificationError::InitError(format!("{e:?}")))?;
-
-            if label != expected_label {
-                return Err(VerificationError::InvalidLabel {
-                    idx: file_idx,
-                    offset: index * 16,
-                });
-            }
-            Ok(())
-        })
+                ) {
+                    return Some(Err(VerificationError::InitError(format!("{e:?}"))));
+                }
+
+                (label != expected_label).then_some(Ok(label_index))
+            },
+        )
         .collect::<Result<Vec<_>, _>>()?;
 
-    Ok(())
+    Ok(FileReport {
+        file: file_idx,
+        labels_checked: labels_to_verify,
+        invalid_labels: invalid.into_iter().sorted().collect(),
+    })
 }
@@ -0,0 +1,147 @@
+//! Argon2id proof of work
+//!
+//! An experimental alternative to RandomX, to compare grinding resistance
+//! and verification cost. It hashes the same input as RandomX (see [super::randomx])
+//! with Argon2id and compares the output with the difficulty.
+
+use std::ops::Range;
+
+use argon2::{Algorithm, Argon2, Block, Params};
+use rayon::prelude::{IntoParallelIterator, ParallelIterator};
+
+use super::{randomx::pow_input, Error, PowVerifier, Prover};
+
+const SALT: &[u8] = b"spacemesh-argon2id-pow";
+
+/// Default memory cost in KiB (64 MiB).
+pub const DEFAULT_MEMORY_KIB: u32 = 64 * 1024;
+/// Default number of passes over the memory.
+pub const DEFAULT_ITERATIONS: u32 = 1;
+
+pub struct Argon2PoW {
+    params: Params,
+}
+
+impl Argon2PoW {
+    /// Create Argon2id PoW using `memory_kib` KiB of memory per hash
+    /// and `iterations` passes over it.
+    pub fn new(memory_kib: u32, iterations: u32) -> Result<Self, Error> {
+        let params = Params::new(memory_kib, iterations, 1, Some(32))
+            .map_err(|e| Error::Internal(e.to_string().into()))?;
+        Ok(Self { params })
+    }
+
+    fn hash(&self, input: &[u8], memory: &mut [Block]) -> Result<[u8; 32], Error> {
+        let mut output = [0u8; 32];
+        Argon2::new(
+            Algorithm::Argon2id,
+            argon2::Version::V0x13,
+            self.params.clone(),
+        )
+        .hash_password_into_with_memory(input, SALT, &mut output, memory)
+        .map_err(|e| Error::Internal(e.to_string().into()))?;
+        Ok(output)
+    }
+
+    fn memory(&self) -> Vec<Block> {
+        vec![Block::default(); self.params.block_count()]
+    }
+}
+
+impl Default for Argon2PoW {
+    fn default() -> Self {
+        Self::new(DEFAULT_MEMORY_KIB, DEFAULT_ITERATIONS).expect("valid default Argon2 params")
+    }
+}
+
+impl Prover for Argon2PoW {
+    fn prove(
+        &self,
+        nonce_group: u8,
+        challenge: &[u8; 8],
+        difficulty: &[u8; 32],
+        miner_id: &[u8; 32],
+    ) -> Result<u64, Error> {
+        let pow_input = pow_input(0, nonce_group, challenge, miner_id);
+
+        let (pow_nonce, _) = (0..2u64.pow(56))
+            .into_par_iter()
+            .map_init(
+                || (self.memory(), pow_input.clone()),
+                |(memory, pow_input), pow_nonce| {
+                    pow_input[0..7].copy_from_slice(&pow_nonce.to_le_bytes()[0..7]);
+                    let hash = self.hash(pow_input, memory).ok()?;
+                    Some((pow_nonce, hash))
+                },
+            )
+            .filter_map(|res| res)
+            .find_any(|(_, hash)| hash.as_slice() < difficulty.as_slice())
+            .ok_or(Error::PoWNotFound)?;
+
+        Ok(pow_nonce)
+    }
+
+    fn prove_many(
+        &self,
+        _: Range<u32>,
+        _: &[u8; 8],
+        _: &[u8; 32],
+        _: &[u8; 32],
+    ) -> Result<Vec<(u32, u64)>, Error> {
+        panic!("not implemented")
+    }
+
+    fn par(&self) -> bool {
+        false
+    }
+}
+
+impl PowVerifier for Argon2PoW {
+    fn verify(
+        &self,
+        pow: u64,
+        nonce_group: u8,
+        challenge: &[u8; 8],
+        difficulty: &[u8; 32],
+        miner_id: &[u8; 32],
+    ) -> Result<(), Error> {
+        let pow_input = pow_input(pow, nonce_group, challenge, miner_id);
+        let hash = self.hash(&pow_input, &mut self.memory())?;
+        if hash.as_slice() >= difficulty.as_slice() {
+            return Err(Error::InvalidPoW);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn prove_and_verify() {
+        let pow = Argon2PoW::new(64, 1).unwrap();
+        let challenge = [1, 2, 3, 4, 5, 6, 7, 8];
+        let difficulty = &[
+            0x0f, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff,
+            0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff,
+            0xff, 0xff, 0xff, 0xff,
+        ];
+        let nonce = pow.prove(7, &challenge, difficulty, &[7; 32]).unwrap();
+        pow.verify(nonce, 7, &challenge, difficulty, &[7; 32])
+            .unwrap();
+
+        // a different miner must find its own nonce
+        let res = (0..100).find(|miner| {
+            pow.verify(nonce, 7, &challenge, difficulty, &[*miner; 32])
+                .is_err()
+        });
+        assert!(res.is_some());
+    }
+
+    #[test]
+    fn invalid_params() {
+        assert!(Argon2PoW::new(1, 1).is_err());
+        assert!(Argon2PoW::new(64, 0).is_err());
+    }
+}
@@ -0,0 +1,378 @@
+//! Persisting the k2pows found, so that they are not calculated again when
+//! proving the same challenge is retried (i.e. after a crash or a restart).
+//!
+//! The file has a JSON record per line. New k2pows are appended, the file is rewritten
+//! only when the records of other challenges are dropped.
+
+use std::{
+    io::Write,
+    ops::Range,
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+
+use serde::{Deserialize, Serialize};
+
+use super::{randomx, Error, Prover, Version};
+
+/// The name of the file in the data directory with the cached k2pows.
+pub const FILE_NAME: &str = "k2pow_cache.jsonl";
+
+/// The PoW the k2pows are calculated with. The k2pows of another PoW are not reused.
+#[serde_with::serde_as]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PowAlgorithm {
+    pub version: Version,
+    /// Used only by RandomX.
+    #[serde_as(as = "serde_with::hex::Hex")]
+    pub randomx_cache_key: Vec<u8>,
+}
+
+/// RandomX with the [randomx::DEFAULT_CACHE_KEY].
+impl Default for PowAlgorithm {
+    fn default() -> Self {
+        Self {
+            version: Version::default(),
+            randomx_cache_key: randomx::DEFAULT_CACHE_KEY.to_vec(),
+        }
+    }
+}
+
+#[serde_with::serde_as]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct PowRecord {
+    #[serde(flatten)]
+    algorithm: PowAlgorithm,
+    #[serde_as(as = "serde_with::hex::Hex")]
+    challenge: [u8; 8],
+    nonce_group: u32,
+    #[serde_as(as = "serde_with::hex::Hex")]
+    difficulty: [u8; 32],
+    #[serde_as(as = "serde_with::hex::Hex")]
+    miner_id: [u8; 32],
+    pow: u64,
+}
+
+/// The k2pows found with a PoW, persisted in a file.
+#[derive(Debug)]
+pub struct PowCache {
+    path: PathBuf,
+    algorithm: PowAlgorithm,
+    records: Mutex<Vec<PowRecord>>,
+}
+
+impl PowCache {
+    /// Load the k2pows of `algorithm` from `path` or start with none if it doesn't exist.
+    ///
+    /// Records that can't be parsed (i.e. after a crash while appending) are dropped.
+    pub fn open(path: &Path, algorithm: PowAlgorithm) -> eyre::Result<Self> {
+        let (records, invalid) = match std::fs::read_to_string(path) {
+            Ok(data) => {
+                let lines = data.lines().filter(|l| !l.trim().is_empty());
+                let (valid, invalid): (Vec<_>, Vec<_>) = lines
+                    .map(serde_json::from_str::<PowRecord>)
+                    .partition(Result::is_ok);
+                (
+                    valid.into_iter().map(Result::unwrap).collect(),
+                    invalid.len(),
+                )
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => (Vec::new(), 0),
+            Err(e) => return Err(e.into()),
+        };
+        let cache = Self {
+            path: path.to_path_buf(),
+            algorithm,
+            records: Mutex::new(records),
+        };
+        if invalid > 0 {
+            log::warn!(
+                "dropping {invalid} invalid k2pow records from {}",
+                path.display()
+            );
+            cache.rewrite(&cache.records.lock().unwrap());
+        }
+        Ok(cache)
+    }
+
+    fn get(
+        &self,
+        nonce_group: u32,
+        challenge: &[u8; 8],
+        difficulty: &[u8; 32],
+        miner_id: &[u8; 32],
+    ) -> Option<u64> {
+        self.records
+            .lock()
+            .unwrap()
+            .iter()
+            .find(|r| {
+                r.algorithm == self.algorithm
+                    && r.nonce_group == nonce_group
+                    && &r.challenge == challenge
+                    && &r.difficulty == difficulty
+                    && &r.miner_id == miner_id
+            })
+            .map(|r| r.pow)
+    }
+
+    /// Forget the k2pows of other challenges and PoWs. They are not needed anymore.
+    pub fn retain_challenge(&self, challenge: &[u8; 8]) {
+        let mut records = self.records.lock().unwrap();
+        let len = records.len();
+        records.retain(|r| &r.challenge == challenge && r.algorithm == self.algorithm);
+        if records.len() != len {
+            self.rewrite(&records);
+        }
+    }
+
+    fn insert(&self, record: PowRecord) {
+        let mut records = self.records.lock().unwrap();
+        if records.contains(&record) {
+            return;
+        }
+        // Losing the records only costs calculating the k2pows again after a restart.
+        if let Err(e) = self.append(&record) {
+            log::warn!("failed to persist k2pows in {}: {e:?}", self.path.display());
+        }
+        records.push(record);
+    }
+
+    fn append(&self, record: &PowRecord) -> eyre::Result<()> {
+        let mut line = serde_json::to_vec(record)?;
+        line.push(b'\n');
+        let mut file = std::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)?;
+        file.write_all(&line)?;
+        Ok(())
+    }
+
+    fn rewrite(&self, records: &[PowRecord]) {
+        let save = || -> eyre::Result<()> {
+            let mut data = Vec::new();
+            for record in records {
+                serde_json::to_writer(&mut data, record)?;
+                data.push(b'\n');
+            }
+            let tmp_path = self.path.with_extension("tmp");
+            std::fs::write(&tmp_path, data)?;
+            std::fs::rename(&tmp_path, &self.path)?;
+            Ok(())
+        };
+        if let Err(e) = save() {
+            log::warn!("failed to persist k2pows in {}: {e:?}", self.path.display());
+        }
+    }
+}
+
+/// Takes the k2pows from the cache, calculating (and caching) only the missing ones.
+pub struct CachingProver<'a> {
+    pub inner: &'a (dyn Prover + Send + Sync),
+    pub cache: &'a PowCache,
+}
+
+impl CachingProver<'_> {
+    fn cache(
+        &self,
+        nonce_group: u32,
+        challenge: &[u8; 8],
+        difficulty: &[u8; 32],
+        miner_id: &[u8; 32],
+        pow: u64,
+    ) {
+        self.cache.insert(PowRecord {
+            algorithm: self.cache.algorithm.clone(),
+            challenge: *challenge,
+            nonce_group,
+            difficulty: *difficulty,
+            miner_id: *miner_id,
+            pow,
+        });
+    }
+
+    fn prove_missing(
+        &self,
+        nonce_groups: Range<u32>,
+        challenge: &[u8; 8],
+        difficulty: &[u8; 32],
+        miner_id: &[u8; 32],
+    ) -> Result<Vec<(u32, u64)>, Error> {
+        let pows = self
+            .inner
+            .prove_many(nonce_groups, challenge, difficulty, miner_id)?;
+        for &(group, pow) in &pows {
+            self.cache(group, challenge, difficulty, miner_id, pow);
+        }
+        Ok(pows)
+    }
+}
+
+impl Prover for CachingProver<'_> {
+    fn prove(
+        &self,
+        nonce_group: u8,
+        challenge: &[u8; 8],
+        difficulty: &[u8; 32],
+        miner_id: &[u8; 32],
+    ) -> Result<u64, Error> {
+        let group = nonce_group as u32;
+        if let Some(pow) = self.cache.get(group, challenge, difficulty, miner_id) {
+            log::info!("reusing the k2pow of nonce group {group}: {pow}");
+            return Ok(pow);
+        }
+        let pow = self
+            .inner
+            .prove(nonce_group, challenge, difficulty, miner_id)?;
+        self.cache(group, challenge, difficulty, miner_id, pow);
+        Ok(pow)
+    }
+
+    fn prove_many(
+        &self,
+        nonce_groups: Range<u32>,
+        challenge: &[u8; 8],
+        difficulty: &[u8; 32],
+        miner_id: &[u8; 32],
+    ) -> Result<Vec<(u32, u64)>, Error> {
+        let mut pows = Vec::new();
+        // The nonce groups missing in the cache, proven together if they are adjacent.
+        let mut missing = nonce_groups.start..nonce_groups.start;
+        for group in nonce_groups.clone() {
+            match self.cache.get(group, challenge, difficulty, miner_id) {
+                Some(pow) => {
+                    log::info!("reusing the k2pow of nonce group {group}: {pow}");
+                    pows.push((group, pow));
+                    if !missing.is_empty() {
+                        pows.extend(self.prove_missing(missing, challenge, difficulty, miner_id)?);
+                    }
+                    missing = group + 1..group + 1;
+                }
+                None => missing.end = group + 1,
+            }
+        }
+        if !missing.is_empty() {
+            pows.extend(self.prove_missing(missing, challenge, difficulty, miner_id)?);
+        }
+        pows.sort_by_key(|&(group, _)| group);
+        Ok(pows)
+    }
+
+    fn par(&self) -> bool {
+        self.inner.par()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use mockall::predicate::eq;
+
+    use super::*;
+    use crate::pow::MockProver;
+
+    #[test]
+    fn reusing_cached_pows() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join(FILE_NAME);
+
+        let mut inner = MockProver::new();
+        inner
+            .expect_prove()
+            .once()
+            .withf(|group, _, _, _| *group == 1)
+            .returning(|_, _, _, _| Ok(11));
+        let cache = PowCache::open(&path, PowAlgorithm::default()).unwrap();
+        let prover = CachingProver {
+            inner: &inner,
+            cache: &cache,
+        };
+        assert_eq!(11, prover.prove(1, &[1; 8], &[2; 32], &[3; 32]).unwrap());
+        assert_eq!(11, prover.prove(1, &[1; 8], &[2; 32], &[3; 32]).unwrap());
+
+        // Reopened after a restart, only the missing (or different) pows are calculated
+        let cache = PowCache::open(&path, PowAlgorithm::default()).unwrap();
+        let mut inner = MockProver::new();
+        inner
+            .expect_prove_many()
+            .once()
+            .with(eq(0..1), eq([1; 8]), eq([2; 32]), eq([3; 32]))
+            .returning(|_, _, _, _| Ok(vec![(0, 10)]));
+        inner
+            .expect_prove_many()
+            .once()
+            .with(eq(2..4), eq([1; 8]), eq([2; 32]), eq([3; 32]))
+            .returning(|_, _, _, _| Ok(vec![(2, 12), (3, 13)]));
+        inner
+            .expect_prove()
+            .once()
+            .withf(|group, _, difficulty, _| *group == 1 && difficulty == &[4; 32])
+            .returning(|_, _, _, _| Ok(21));
+        let prover = CachingProver {
+            inner: &inner,
+            cache: &cache,
+        };
+        assert_eq!(
+            vec![(0, 10), (1, 11), (2, 12), (3, 13)],
+            prover
+                .prove_many(0..4, &[1; 8], &[2; 32], &[3; 32])
+                .unwrap()
+        );
+        assert_eq!(21, prover.prove(1, &[1; 8], &[4; 32], &[3; 32]).unwrap());
+
+        // The pows of other challenges are forgotten
+        cache.retain_challenge(&[9; 8]);
+        let cache = PowCache::open(&path, PowAlgorithm::default()).unwrap();
+        assert!(cache.get(1, &[1; 8], &[2; 32], &[3; 32]).is_none());
+    }
+
+    #[test]
+    fn pows_of_other_algorithms_are_not_reused() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join(FILE_NAME);
+
+        let cache = PowCache::open(&path, PowAlgorithm::default()).unwrap();
+        let mut inner = MockProver::new();
+        inner.expect_prove().once().returning(|_, _, _, _| Ok(11));
+        let prover = CachingProver {
+            inner: &inner,
+            cache: &cache,
+        };
+        prover.prove(1, &[1; 8], &[2; 32], &[3; 32]).unwrap();
+
+        let testnet = PowAlgorithm {
+            randomx_cache_key: randomx::cache_key(Some("testnet")),
+            ..Default::default()
+        };
+        let cache = PowCache::open(&path, testnet).unwrap();
+        assert!(cache.get(1, &[1; 8], &[2; 32], &[3; 32]).is_none());
+        let cache = PowCache::open(&path, PowAlgorithm::default()).unwrap();
+        assert_eq!(Some(11), cache.get(1, &[1; 8], &[2; 32], &[3; 32]));
+    }
+
+    #[test]
+    fn dropping_invalid_records() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join(FILE_NAME);
+
+        let cache = PowCache::open(&path, PowAlgorithm::default()).unwrap();
+        let mut inner = MockProver::new();
+        inner.expect_prove().once().returning(|_, _, _, _| Ok(11));
+        let prover = CachingProver {
+            inner: &inner,
+            cache: &cache,
+        };
+        prover.prove(1, &[1; 8], &[2; 32], &[3; 32]).unwrap();
+
+        // A record cut short by a crash
+        let mut file = std::fs::OpenOptions::new()
+            .append(true)
+            .open(&path)
+            .unwrap();
+        file.write_all(b"{\"version\":\"randomx\",\"chall").unwrap();
+
+        let cache = PowCache::open(&path, PowAlgorithm::default()).unwrap();
+        assert_eq!(Some(11), cache.get(1, &[1; 8], &[2; 32], &[3; 32]));
+        assert_eq!(1, std::fs::read_to_string(&path).unwrap().lines().count());
+    }
+}
@@ -6,10 +6,14 @@
 //! at the same time. In effect a proof could be found
 //! without actually holding the whole POST data.
 
+#[cfg(feature = "argon2")]
+pub mod argon2id;
+pub mod cache;
 pub mod randomx;
 pub mod service;
 use mockall::*;
-use std::ops::Range;
+use randomx::RandomXFlag;
+use std::{fmt::Display, ops::Range, str::FromStr};
 use thiserror::Error;
 
 #[derive(Error, Debug)]
@@ -18,10 +22,88 @@ pub enum Error {
     PoWNotFound,
     #[error("proof of work is invalid")]
     InvalidPoW,
+    #[error("large pages requested for RandomX, but {0}. Configure huge pages in the OS (see https://xmrig.com/docs/miner/hugepages) or let large pages be used only if available")]
+    LargePagesUnavailable(String),
+    #[error("{0} is not supported by this build")]
+    Unsupported(&'static str),
     #[error(transparent)]
     Internal(Box<dyn std::error::Error + Send + Sync>),
 }
 
+/// Version of the PoW algorithm.
+///
+/// It's a network parameter. A network upgrade changing the PoW adds a new version,
+/// constructed by [new_prover] and [new_verifier].
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Version {
+    #[default]
+    RandomX,
+    /// Experimental, see [argon2id].
+    #[cfg(feature = "argon2")]
+    Argon2id,
+}
+
+impl FromStr for Version {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "randomx" => Ok(Version::RandomX),
+            #[cfg(feature = "argon2")]
+            "argon2id" => Ok(Version::Argon2id),
+            _ => Err(format!("unknown PoW version: {s}")),
+        }
+    }
+}
+
+impl Display for Version {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Version::RandomX => write!(f, "randomx"),
+            #[cfg(feature = "argon2")]
+            Version::Argon2id => write!(f, "argon2id"),
+        }
+    }
+}
+
+/// Create a local prover for the given PoW version.
+///
+/// `randomx_flags` and `randomx_cache_key` are used only by RandomX.
+#[cfg(feature = "parallel")]
+pub fn new_prover(
+    version: Version,
+    randomx_flags: RandomXFlag,
+    randomx_cache_key: &[u8],
+) -> Result<Box<dyn Prover + Send + Sync>, Error> {
+    match version {
+        Version::RandomX => Ok(Box::new(randomx::PoW::with_cache_key(
+            randomx_flags,
+            randomx_cache_key,
+        )?)),
+        #[cfg(feature = "argon2")]
+        Version::Argon2id => Ok(Box::new(argon2id::Argon2PoW::default())),
+    }
+}
+
+/// Create a verifier for the given PoW version.
+///
+/// `randomx_flags` and `randomx_cache_key` are used only by RandomX.
+pub fn new_verifier(
+    version: Version,
+    randomx_flags: RandomXFlag,
+    randomx_cache_key: &[u8],
+) -> Result<Box<dyn PowVerifier + Send + Sync>, Error> {
+    match version {
+        Version::RandomX => Ok(Box::new(randomx::PoW::with_cache_key(
+            randomx_flags,
+            randomx_cache_key,
+        )?)),
+        #[cfg(feature = "argon2")]
+        Version::Argon2id => Ok(Box::new(argon2id::Argon2PoW::default())),
+    }
+}
+
 #[automock]
 pub trait Prover {
     fn prove(
@@ -54,3 +136,96 @@ pub trait PowVerifier {
         miner_id: &[u8; 32],
     ) -> Result<(), Error>;
 }
+
+/// A PoW verifier shared by many users (i.e. many proof verifiers).
+impl<T: PowVerifier + ?Sized> PowVerifier for std::sync::Arc<T> {
+    fn verify(
+        &self,
+        pow: u64,
+        nonce_group: u8,
+        challenge: &[u8; 8],
+        difficulty: &[u8; 32],
+        miner_id: &[u8; 32],
+    ) -> Result<(), Error> {
+        (**self).verify(pow, nonce_group, challenge, difficulty, miner_id)
+    }
+}
+
+/// The same k2pow for every nonce group, without calculating it.
+///
+/// For measuring and checking the proving and verification of labels without the cost
+/// of the PoW (i.e. in self-tests and benchmarks). The proofs are not valid.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct FixedPow(pub u64);
+
+impl Prover for FixedPow {
+    fn prove(&self, _: u8, _: &[u8; 8], _: &[u8; 32], _: &[u8; 32]) -> Result<u64, Error> {
+        Ok(self.0)
+    }
+
+    fn prove_many(
+        &self,
+        nonce_groups: Range<u32>,
+        _: &[u8; 8],
+        _: &[u8; 32],
+        _: &[u8; 32],
+    ) -> Result<Vec<(u32, u64)>, Error> {
+        Ok(nonce_groups.map(|group| (group, self.0)).collect())
+    }
+
+    fn par(&self) -> bool {
+        false
+    }
+}
+
+/// Accepts only the fixed k2pow.
+impl PowVerifier for FixedPow {
+    fn verify(
+        &self,
+        pow: u64,
+        _: u8,
+        _: &[u8; 8],
+        _: &[u8; 32],
+        _: &[u8; 32],
+    ) -> Result<(), Error> {
+        match pow == self.0 {
+            true => Ok(()),
+            false => Err(Error::InvalidPoW),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{FixedPow, PowVerifier, Prover, Version};
+
+    #[test]
+    fn fixed_pow() {
+        let pow = FixedPow(7);
+        assert_eq!(7, pow.prove(3, &[0; 8], &[0; 32], &[0; 32]).unwrap());
+        assert_eq!(
+            vec![(1, 7), (2, 7)],
+            pow.prove_many(1..3, &[0; 8], &[0; 32], &[0; 32]).unwrap()
+        );
+        assert!(pow.verify(7, 3, &[0; 8], &[0; 32], &[0; 32]).is_ok());
+        assert!(pow.verify(8, 3, &[0; 8], &[0; 32], &[0; 32]).is_err());
+    }
+
+    #[test]
+    fn parse_version() {
+        assert_eq!(Ok(Version::RandomX), "randomx".parse());
+        assert_eq!(Ok(Version::RandomX), "RandomX".parse());
+        assert!("sha256".parse::<Version>().is_err());
+        assert_eq!(
+            Ok(Version::RandomX),
+            Version::RandomX.to_string().parse::<Version>()
+        );
+    }
+
+    #[test]
+    fn deserialize_version() {
+        let version: Version = serde_json::from_str("\"randomx\"").unwrap();
+        assert_eq!(Version::RandomX, version);
+        assert!(serde_json::from_str::<Version>("\"unknown\"").is_err());
+    }
+}
@@ -1,20 +1,291 @@
+#[cfg(feature = "randomx")]
 pub use randomx_rs::RandomXFlag;
+#[cfg(feature = "randomx")]
 use randomx_rs::{RandomXCache, RandomXDataset, RandomXError, RandomXVM};
+#[cfg(all(feature = "randomx", feature = "parallel"))]
 use rayon::prelude::{IntoParallelIterator, ParallelIterator};
+use std::fmt::Display;
+#[cfg(all(feature = "randomx", feature = "parallel"))]
 use std::ops::Range;
+use std::str::FromStr;
+#[cfg(all(feature = "randomx", feature = "parallel"))]
 use std::sync::atomic::{AtomicUsize, Ordering};
+#[cfg(feature = "randomx")]
 use thread_local::ThreadLocal;
 
-use super::{Error, PowVerifier, Prover};
+use super::Error;
+#[cfg(feature = "randomx")]
+use super::PowVerifier;
+#[cfg(all(feature = "randomx", feature = "parallel"))]
+use super::Prover;
 
-const RANDOMX_CACHE_KEY: &[u8] = b"spacemesh-randomx-cache-key";
+#[cfg(not(feature = "randomx"))]
+mod unsupported;
+#[cfg(not(feature = "randomx"))]
+pub use unsupported::{PoW, RandomXFlag};
 
+/// Whether RandomX is built in (the `randomx` feature). Without it, [PoW] can't be
+/// created and the PoW must be calculated by a remote k2pow service.
+pub const SUPPORTED: bool = cfg!(feature = "randomx");
+
+/// The key of the RandomX cache of the networks without an ID (i.e. mainnet).
+pub const DEFAULT_CACHE_KEY: &[u8] = b"spacemesh-randomx-cache-key";
+
+/// The key of the RandomX cache of a network. Different keys separate the PoWs
+/// of the networks (a PoW of one network is invalid in the others).
+/// Networks without an ID use [DEFAULT_CACHE_KEY].
+pub fn cache_key(network_id: Option<&str>) -> Vec<u8> {
+    match network_id {
+        None => DEFAULT_CACHE_KEY.to_vec(),
+        Some(id) => [DEFAULT_CACHE_KEY, b"-", id.as_bytes()].concat(),
+    }
+}
+
+/// The input hashed with RandomX: 7 lowest bytes of the `pow` nonce (LE),
+/// the nonce group, 8 bytes of the challenge and the miner ID.
+#[cfg(any(feature = "randomx", feature = "argon2", feature = "test-vectors"))]
+pub(crate) fn pow_input(
+    pow: u64,
+    nonce_group: u8,
+    challenge: &[u8; 8],
+    miner_id: &[u8; 32],
+) -> Vec<u8> {
+    [
+        &pow.to_le_bytes()[0..7],
+        [nonce_group].as_slice(),
+        challenge,
+        miner_id,
+    ]
+    .concat()
+}
+
+/// Memory needed by RandomX in fast mode (dataset and cache).
+pub const FAST_MODE_MEMORY: u64 = 2080 * 1024 * 1024;
+/// Memory needed by RandomX in light mode (cache).
+pub const LIGHT_MODE_MEMORY: u64 = 256 * 1024 * 1024;
+
+/// Whether to allocate RandomX memory in large pages.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LargePages {
+    #[default]
+    Off,
+    /// Fail if large pages are not available.
+    On,
+    /// Use large pages if available.
+    Auto,
+}
+
+impl FromStr for LargePages {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "off" | "false" => Ok(Self::Off),
+            "on" | "true" => Ok(Self::On),
+            "auto" => Ok(Self::Auto),
+            _ => Err(format!(
+                "invalid large pages mode: {s} (expected on, off or auto)"
+            )),
+        }
+    }
+}
+
+impl Display for LargePages {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Off => write!(f, "off"),
+            Self::On => write!(f, "on"),
+            Self::Auto => write!(f, "auto"),
+        }
+    }
+}
+
+/// Huge pages configured in the OS.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, serde::Serialize)]
+pub struct HugePages {
+    pub total: u64,
+    pub free: u64,
+    pub size_bytes: u64,
+}
+
+impl HugePages {
+    /// Read huge pages from `/proc/meminfo`.
+    /// Returns `None` on other systems than Linux.
+    pub fn detect() -> Option<Self> {
+        if cfg!(target_os = "linux") {
+            let meminfo = std::fs::read_to_string("/proc/meminfo").ok()?;
+            Some(Self::parse_meminfo(&meminfo))
+        } else {
+            None
+        }
+    }
+
+    fn parse_meminfo(meminfo: &str) -> Self {
+        let mut hugepages = Self::default();
+        for line in meminfo.lines() {
+            let Some((key, value)) = line.split_once(':') else {
+                continue;
+            };
+            let mut value = value.split_whitespace();
+            let Some(Ok(number)) = value.next().map(str::parse::<u64>) else {
+                continue;
+            };
+            match key {
+                "HugePages_Total" => hugepages.total = number,
+                "HugePages_Free" => hugepages.free = number,
+                "Hugepagesize" if value.next() == Some("kB") => {
+                    hugepages.size_bytes = number * 1024
+                }
+                _ => {}
+            }
+        }
+        hugepages
+    }
+
+    pub fn free_bytes(&self) -> u64 {
+        self.free * self.size_bytes
+    }
+}
+
+/// Memory left for the rest of the process when choosing the fast mode
+/// by the available memory.
+const FAST_MODE_HEADROOM: u64 = 256 * 1024 * 1024;
+
+/// Memory of the OS relevant for choosing the RandomX mode.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, serde::Serialize)]
+pub struct MemInfo {
+    /// Memory available for starting new processes without swapping (`MemAvailable`).
+    pub available_bytes: u64,
+    pub hugepages: HugePages,
+}
+
+impl MemInfo {
+    /// Read the memory info from `/proc/meminfo`.
+    /// Returns `None` on other systems than Linux.
+    pub fn detect() -> Option<Self> {
+        if cfg!(target_os = "linux") {
+            let meminfo = std::fs::read_to_string("/proc/meminfo").ok()?;
+            Self::parse_meminfo(&meminfo)
+        } else {
+            None
+        }
+    }
+
+    fn parse_meminfo(meminfo: &str) -> Option<Self> {
+        let available_kb = meminfo.lines().find_map(|line| {
+            let value = line.strip_prefix("MemAvailable:")?;
+            match value.split_whitespace().collect::<Vec<_>>()[..] {
+                [number, "kB"] => number.parse::<u64>().ok(),
+                _ => None,
+            }
+        })?;
+        Some(Self {
+            available_bytes: available_kb * 1024,
+            hugepages: HugePages::parse_meminfo(meminfo),
+        })
+    }
+
+    /// Whether RandomX in fast mode fits in the memory.
+    ///
+    /// The memory of the fast mode is taken from free huge pages if there are enough
+    /// of them and `large_pages` allows using them. Otherwise the available memory must hold it
+    /// and leave some headroom for the rest of the process.
+    pub fn fits_fast_mode(&self, large_pages: LargePages) -> bool {
+        if large_pages != LargePages::Off && self.hugepages.free_bytes() >= FAST_MODE_MEMORY {
+            return true;
+        }
+        self.available_bytes >= FAST_MODE_MEMORY + FAST_MODE_HEADROOM
+    }
+}
+
+/// Whether to use the RandomX fast mode when the mode is chosen by the memory
+/// available in the OS (see [MemInfo::fits_fast_mode]).
+///
+/// Falls back to the light mode when the available memory is unknown
+/// (i.e. on other systems than Linux).
+pub fn auto_fast_mode(large_pages: LargePages) -> bool {
+    fits_fast_mode(MemInfo::detect(), large_pages)
+}
+
+fn fits_fast_mode(meminfo: Option<MemInfo>, large_pages: LargePages) -> bool {
+    let Some(meminfo) = meminfo else {
+        log::info!("RandomX mode auto: available memory is unknown, using the light mode");
+        return false;
+    };
+    let fast = meminfo.fits_fast_mode(large_pages);
+    log::info!(
+        "RandomX mode auto: using the {} mode ({} MiB of memory and {} MiB of huge pages available)",
+        if fast { "fast" } else { "light" },
+        meminfo.available_bytes / 1024 / 1024,
+        meminfo.hugepages.free_bytes() / 1024 / 1024,
+    );
+    fast
+}
+
+/// Check if RandomX with `flags` can allocate its memory in large pages.
+///
+/// On Linux, the free huge pages are checked. On other systems,
+/// a RandomX cache is allocated in large pages to find out.
+fn check_large_pages(flags: RandomXFlag) -> Result<(), Error> {
+    let required = if flags.contains(RandomXFlag::FLAG_FULL_MEM) {
+        FAST_MODE_MEMORY
+    } else {
+        LIGHT_MODE_MEMORY
+    };
+    match HugePages::detect() {
+        Some(hugepages) if hugepages.free_bytes() < required => {
+            Err(Error::LargePagesUnavailable(format!(
+                "only {} MiB of free huge pages ({} MiB required)",
+                hugepages.free_bytes() / 1024 / 1024,
+                required / 1024 / 1024,
+            )))
+        }
+        Some(_) => Ok(()),
+        #[cfg(not(feature = "randomx"))]
+        None => Err(Error::Unsupported("RandomX")),
+        #[cfg(feature = "randomx")]
+        None => {
+            let flags = (flags | RandomXFlag::FLAG_LARGE_PAGES) - RandomXFlag::FLAG_FULL_MEM;
+            RandomXCache::new(flags, DEFAULT_CACHE_KEY)
+                .map(|_| ())
+                .map_err(|e| {
+                    Error::LargePagesUnavailable(format!("allocating memory failed ({e})"))
+                })
+        }
+    }
+}
+
+/// Set or clear [RandomXFlag::FLAG_LARGE_PAGES] in `flags` according to `large_pages`.
+///
+/// Fails if large pages are required ([LargePages::On]) but not available.
+/// With [LargePages::Auto], large pages are used only if they are available.
+pub fn with_large_pages(flags: RandomXFlag, large_pages: LargePages) -> Result<RandomXFlag, Error> {
+    let flags = flags - RandomXFlag::FLAG_LARGE_PAGES;
+    match large_pages {
+        LargePages::Off => Ok(flags),
+        LargePages::On => {
+            check_large_pages(flags)?;
+            Ok(flags | RandomXFlag::FLAG_LARGE_PAGES)
+        }
+        LargePages::Auto => match check_large_pages(flags) {
+            Ok(()) => Ok(flags | RandomXFlag::FLAG_LARGE_PAGES),
+            Err(e) => {
+                log::warn!("not using large pages for RandomX: {e}");
+                Ok(flags)
+            }
+        },
+    }
+}
+
+#[cfg(feature = "randomx")]
 impl From<randomx_rs::RandomXError> for Error {
     fn from(e: randomx_rs::RandomXError) -> Self {
         Error::Internal(Box::new(e))
     }
 }
 
+#[cfg(feature = "randomx")]
 pub struct PoW {
     cache: Option<RandomXCache>,
     dataset: Option<RandomXDataset>,
@@ -22,10 +293,17 @@ pub struct PoW {
     vms: ThreadLocal<RandomXVM>,
 }
 
+#[cfg(feature = "randomx")]
 impl PoW {
+    /// Initialize RandomX with the [DEFAULT_CACHE_KEY].
     pub fn new(flags: RandomXFlag) -> Result<PoW, Error> {
+        Self::with_cache_key(flags, DEFAULT_CACHE_KEY)
+    }
+
+    /// Initialize RandomX with the cache key of a network (see [cache_key]).
+    pub fn with_cache_key(flags: RandomXFlag, cache_key: &[u8]) -> Result<PoW, Error> {
         log::debug!("initializing RandomX");
-        let cache = RandomXCache::new(flags, RANDOMX_CACHE_KEY)?;
+        let cache = RandomXCache::new(flags, cache_key)?;
         let (cache, dataset) = if flags.contains(RandomXFlag::FLAG_FULL_MEM) {
             (None, Some(RandomXDataset::new(flags, cache, 0)?))
         } else {
@@ -47,6 +325,8 @@ impl PoW {
     }
 }
 
+/// Searching for the PoW needs the `parallel` feature, only verifying it doesn't.
+#[cfg(all(feature = "randomx", feature = "parallel"))]
 impl Prover for PoW {
     fn prove(
         &self,
@@ -55,13 +335,7 @@ impl Prover for PoW {
         difficulty: &[u8; 32],
         miner_id: &[u8; 32],
     ) -> Result<u64, Error> {
-        let pow_input = [
-            [0u8; 7].as_slice(),
-            [nonce_group].as_slice(),
-            challenge,
-            miner_id,
-        ]
-        .concat();
+        let pow_input = pow_input(0, nonce_group, challenge, miner_id);
 
         // the call to difficulty.as_slice() below (in find_any) is needed because of a compiler bug:
         // https://github.com/rust-lang/rust/issues/130464
@@ -105,6 +379,7 @@ impl Prover for PoW {
     }
 }
 
+#[cfg(feature = "randomx")]
 impl PowVerifier for PoW {
     fn verify(
         &self,
@@ -114,13 +389,7 @@ impl PowVerifier for PoW {
         difficulty: &[u8; 32],
         miner_id: &[u8; 32],
     ) -> Result<(), Error> {
-        let pow_input = [
-            &pow.to_le_bytes()[0..7],
-            [nonce_group].as_slice(),
-            challenge,
-            miner_id,
-        ]
-        .concat();
+        let pow_input = pow_input(pow, nonce_group, challenge, miner_id);
 
         let vm = self.get_vm()?;
         let hash = vm.calculate_hash(pow_input.as_slice())?;
@@ -136,11 +405,10 @@ impl PowVerifier for PoW {
 
 #[cfg(test)]
 mod tests {
-    use crate::pow::PowVerifier;
-
     use super::*;
 
     #[test]
+    #[cfg(feature = "randomx")]
     fn test_pow() {
         let nonce = 7;
         let challenge = b"hello!!!";
@@ -159,6 +427,7 @@ mod tests {
     }
 
     #[test]
+    #[cfg(feature = "randomx")]
     fn test_pow_miner_id_matters() {
         let nonce = 7;
         let challenge = b"hello!!!";
@@ -182,6 +451,7 @@ mod tests {
     }
 
     #[test]
+    #[cfg(feature = "randomx")]
     fn reject_invalid_pow() {
         let prover = PoW::new(RandomXFlag::get_recommended_flags()).unwrap();
         // difficulty 0 is impossible to be met
@@ -191,6 +461,7 @@ mod tests {
     }
 
     #[test]
+    #[cfg(feature = "randomx")]
     fn different_cache_key_gives_different_hash() {
         let input = b"hello world";
         let flags = RandomXFlag::get_recommended_flags();
@@ -206,6 +477,137 @@ mod tests {
         assert_ne!(hash_0, hash_1);
     }
 
+    #[test]
+    #[cfg(feature = "randomx")]
+    fn networks_are_separated() {
+        assert_eq!(DEFAULT_CACHE_KEY, cache_key(None));
+        let testnet_key = cache_key(Some("testnet"));
+        assert_ne!(DEFAULT_CACHE_KEY, testnet_key);
+
+        let (nonce, challenge, difficulty) = (7, b"hello!!!", &[0x0f; 32]);
+        let flags = RandomXFlag::get_recommended_flags();
+        let testnet = PoW::with_cache_key(flags, &testnet_key).unwrap();
+        let mainnet = PoW::new(flags).unwrap();
+        // A PoW can meet the difficulty in both networks by chance, but not all of them.
+        let mut rejected = 0;
+        for miner_id in [[1; 32], [2; 32], [3; 32], [4; 32]] {
+            let pow = testnet
+                .prove(nonce, challenge, difficulty, &miner_id)
+                .unwrap();
+            testnet
+                .verify(pow, nonce, challenge, difficulty, &miner_id)
+                .unwrap();
+            if mainnet
+                .verify(pow, nonce, challenge, difficulty, &miner_id)
+                .is_err()
+            {
+                rejected += 1;
+            }
+        }
+        assert!(rejected > 0);
+    }
+
+    #[test]
+    #[cfg(not(feature = "randomx"))]
+    fn reporting_missing_randomx() {
+        let flags = RandomXFlag::get_recommended_flags() | RandomXFlag::FLAG_FULL_MEM;
+        assert!(matches!(
+            PoW::new(flags),
+            Err(Error::Unsupported("RandomX"))
+        ));
+        assert!(matches!(
+            with_large_pages(flags, LargePages::Off),
+            Ok(f) if f == flags
+        ));
+    }
+
+    #[test]
+    fn parse_meminfo() {
+        let meminfo = "MemTotal:       32577132 kB\n\
+                       HugePages_Total:    1168\n\
+                       HugePages_Free:     1100\n\
+                       HugePages_Rsvd:        0\n\
+                       Hugepagesize:       2048 kB\n";
+        let hugepages = HugePages::parse_meminfo(meminfo);
+        assert_eq!(
+            HugePages {
+                total: 1168,
+                free: 1100,
+                size_bytes: 2048 * 1024,
+            },
+            hugepages
+        );
+        assert_eq!(1100 * 2048 * 1024, hugepages.free_bytes());
+        assert_eq!(HugePages::default(), HugePages::parse_meminfo(""));
+    }
+
+    #[test]
+    fn fitting_fast_mode() {
+        let meminfo = "MemTotal:        4026532 kB\n\
+                       MemFree:          302168 kB\n\
+                       MemAvailable:    1845012 kB\n\
+                       HugePages_Total:    1168\n\
+                       HugePages_Free:     1168\n\
+                       Hugepagesize:       2048 kB\n";
+        let info = MemInfo::parse_meminfo(meminfo).unwrap();
+        assert_eq!(1845012 * 1024, info.available_bytes);
+        assert_eq!(1168, info.hugepages.free);
+        // The dataset fits only in the huge pages.
+        assert!(!info.fits_fast_mode(LargePages::Off));
+        assert!(info.fits_fast_mode(LargePages::Auto));
+        assert!(info.fits_fast_mode(LargePages::On));
+
+        let info = MemInfo {
+            available_bytes: FAST_MODE_MEMORY,
+            hugepages: HugePages::default(),
+        };
+        assert!(!info.fits_fast_mode(LargePages::Auto));
+        let info = MemInfo {
+            available_bytes: FAST_MODE_MEMORY + FAST_MODE_HEADROOM,
+            ..info
+        };
+        assert!(info.fits_fast_mode(LargePages::Off));
+
+        assert_eq!(None, MemInfo::parse_meminfo("MemTotal: 4026532 kB\n"));
+        assert_eq!(None, MemInfo::parse_meminfo("MemAvailable: 1845012\n"));
+    }
+
+    #[test]
+    fn auto_mode_falls_back_to_light() {
+        assert!(!fits_fast_mode(None, LargePages::Auto));
+        let info = MemInfo {
+            available_bytes: FAST_MODE_MEMORY + FAST_MODE_HEADROOM,
+            hugepages: HugePages::default(),
+        };
+        assert!(fits_fast_mode(Some(info), LargePages::Off));
+    }
+
+    #[test]
+    fn parse_large_pages() {
+        assert_eq!(Ok(LargePages::On), "on".parse());
+        assert_eq!(Ok(LargePages::On), "true".parse());
+        assert_eq!(Ok(LargePages::Off), "false".parse());
+        assert_eq!(Ok(LargePages::Auto), "Auto".parse());
+        assert!("maybe".parse::<LargePages>().is_err());
+        for mode in [LargePages::Off, LargePages::On, LargePages::Auto] {
+            assert_eq!(Ok(mode), mode.to_string().parse());
+        }
+    }
+
+    #[test]
+    fn large_pages_off_clears_flag() {
+        let flags = RandomXFlag::get_recommended_flags() | RandomXFlag::FLAG_LARGE_PAGES;
+        let flags = with_large_pages(flags, LargePages::Off).unwrap();
+        assert!(!flags.contains(RandomXFlag::FLAG_LARGE_PAGES));
+    }
+
+    #[test]
+    fn large_pages_auto_never_fails() {
+        let flags = RandomXFlag::get_recommended_flags() | RandomXFlag::FLAG_FULL_MEM;
+        let flags = with_large_pages(flags, LargePages::Auto).unwrap();
+        assert!(flags.contains(RandomXFlag::FLAG_FULL_MEM));
+    }
+
     #[test]
     fn get_recommended_flags() {
         dbg!(RandomXFlag::get_recommended_flags());
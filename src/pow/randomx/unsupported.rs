@@ -0,0 +1,158 @@
+//! Stand-ins for builds without the `randomx` feature (i.e. targets RandomX doesn't build for).
+//!
+//! The flags keep the same values as in RandomX, so that the configuration code is
+//! the same for all builds. [PoW] can't be created, the PoW must be calculated
+//! by a remote k2pow service instead.
+
+use std::ops::{BitOr, BitOrAssign, Range, Sub};
+
+use crate::pow::{Error, PowVerifier, Prover};
+
+/// The flags of RandomX.
+#[repr(transparent)]
+#[derive(Default, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct RandomXFlag(u32);
+
+impl RandomXFlag {
+    pub const FLAG_DEFAULT: Self = Self(0);
+    pub const FLAG_LARGE_PAGES: Self = Self(1);
+    pub const FLAG_HARD_AES: Self = Self(2);
+    pub const FLAG_FULL_MEM: Self = Self(4);
+    pub const FLAG_JIT: Self = Self(8);
+    pub const FLAG_SECURE: Self = Self(16);
+    pub const FLAG_ARGON2_SSSE3: Self = Self(32);
+    pub const FLAG_ARGON2_AVX2: Self = Self(64);
+    pub const FLAG_ARGON2: Self = Self(96);
+
+    const NAMES: [(&'static str, Self); 7] = [
+        ("FLAG_LARGE_PAGES", Self::FLAG_LARGE_PAGES),
+        ("FLAG_HARD_AES", Self::FLAG_HARD_AES),
+        ("FLAG_FULL_MEM", Self::FLAG_FULL_MEM),
+        ("FLAG_JIT", Self::FLAG_JIT),
+        ("FLAG_SECURE", Self::FLAG_SECURE),
+        ("FLAG_ARGON2_SSSE3", Self::FLAG_ARGON2_SSSE3),
+        ("FLAG_ARGON2_AVX2", Self::FLAG_ARGON2_AVX2),
+    ];
+
+    pub fn get_recommended_flags() -> Self {
+        Self::FLAG_DEFAULT
+    }
+
+    pub const fn bits(&self) -> u32 {
+        self.0
+    }
+
+    pub const fn contains(&self, other: Self) -> bool {
+        self.0 & other.0 == other.0
+    }
+}
+
+impl BitOr for RandomXFlag {
+    type Output = Self;
+
+    fn bitor(self, rhs: Self) -> Self {
+        Self(self.0 | rhs.0)
+    }
+}
+
+impl BitOrAssign for RandomXFlag {
+    fn bitor_assign(&mut self, rhs: Self) {
+        self.0 |= rhs.0;
+    }
+}
+
+impl Sub for RandomXFlag {
+    type Output = Self;
+
+    fn sub(self, rhs: Self) -> Self {
+        Self(self.0 & !rhs.0)
+    }
+}
+
+/// The names of the set flags separated with ` | `, like the flags of RandomX.
+impl std::fmt::Display for RandomXFlag {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let mut remaining = *self;
+        let mut first = true;
+        for (name, flag) in Self::NAMES {
+            if self.contains(flag) {
+                if !first {
+                    f.write_str(" | ")?;
+                }
+                f.write_str(name)?;
+                remaining = remaining - flag;
+                first = false;
+            }
+        }
+        if remaining.0 != 0 {
+            if !first {
+                f.write_str(" | ")?;
+            }
+            write!(f, "{:#x}", remaining.0)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::fmt::Debug for RandomXFlag {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "RandomXFlag({self})")
+    }
+}
+
+pub struct PoW {
+    _private: (),
+}
+
+impl PoW {
+    pub fn new(_: RandomXFlag) -> Result<PoW, Error> {
+        Err(Error::Unsupported("RandomX"))
+    }
+
+    pub fn with_cache_key(_: RandomXFlag, _: &[u8]) -> Result<PoW, Error> {
+        Err(Error::Unsupported("RandomX"))
+    }
+}
+
+impl Prover for PoW {
+    fn prove(&self, _: u8, _: &[u8; 8], _: &[u8; 32], _: &[u8; 32]) -> Result<u64, Error> {
+        Err(Error::Unsupported("RandomX"))
+    }
+
+    fn prove_many(
+        &self,
+        _: Range<u32>,
+        _: &[u8; 8],
+        _: &[u8; 32],
+        _: &[u8; 32],
+    ) -> Result<Vec<(u32, u64)>, Error> {
+        Err(Error::Unsupported("RandomX"))
+    }
+
+    fn par(&self) -> bool {
+        false
+    }
+}
+
+impl PowVerifier for PoW {
+    fn verify(&self, _: u64, _: u8, _: &[u8; 8], _: &[u8; 32], _: &[u8; 32]) -> Result<(), Error> {
+        Err(Error::Unsupported("RandomX"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::RandomXFlag;
+
+    #[test]
+    fn formatting_flags() {
+        assert_eq!("", RandomXFlag::FLAG_DEFAULT.to_string());
+        let flags = RandomXFlag::FLAG_LARGE_PAGES | RandomXFlag::FLAG_FULL_MEM;
+        assert_eq!("FLAG_LARGE_PAGES | FLAG_FULL_MEM", flags.to_string());
+        assert_eq!(
+            "RandomXFlag(FLAG_FULL_MEM)",
+            format!("{:?}", flags - RandomXFlag::FLAG_LARGE_PAGES)
+        );
+        assert_eq!("FLAG_JIT | 0x100", RandomXFlag(0x108).to_string());
+    }
+}
@@ -1,28 +1,307 @@
 use super::{Error, Prover};
 use futures::future;
+use rand::RngCore;
 use reqwest;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, VecDeque};
 use std::ops::Range;
-use std::sync::Arc;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
 use std::time::Duration;
 use tokio::runtime::Runtime;
-use tokio::sync::Semaphore;
+use tokio::sync::oneshot;
 use tokio::time::sleep;
+use tracing::Instrument;
+
+/// Header identifying the client that submitted a job.
+///
+/// The k2pow service echoes the owner of the job in the response.
+/// Load balancers can also use it to route the requests of a client to the same worker.
+pub const OWNER_HEADER: &str = "x-k2pow-owner";
+
+/// The parameters of a k2pow job.
+#[serde_with::serde_as]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+struct JobKey {
+    #[serde_as(as = "serde_with::hex::Hex")]
+    miner_id: [u8; 32],
+    #[serde_as(as = "serde_with::hex::Hex")]
+    challenge: [u8; 8],
+    #[serde_as(as = "serde_with::hex::Hex")]
+    difficulty: [u8; 32],
+    nonce_group: u32,
+}
+
+impl JobKey {
+    /// Whether the job is for the same PoW (all but the nonce group match).
+    fn same_pow(&self, other: &JobKey) -> bool {
+        self.miner_id == other.miner_id
+            && self.challenge == other.challenge
+            && self.difficulty == other.difficulty
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct JobRecord {
+    #[serde(flatten)]
+    key: JobKey,
+    url: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct JobRecords {
+    owner: String,
+    jobs: Vec<JobRecord>,
+}
+
+/// Jobs submitted to the k2pow service that didn't finish yet, persisted in a file.
+///
+/// After a restart, the outstanding jobs are polled again (at the same URL and
+/// with the same owner token) instead of being submitted anew.
+#[derive(Debug)]
+pub struct JobStore {
+    path: PathBuf,
+    records: Mutex<JobRecords>,
+}
+
+impl JobStore {
+    /// Load the jobs from `path` or start with no jobs (and a new owner token) if it doesn't exist.
+    pub fn open(path: &Path) -> eyre::Result<Self> {
+        let records = match std::fs::read(path) {
+            Ok(data) => serde_json::from_slice(&data)?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                let mut token = [0u8; 16];
+                rand::thread_rng().fill_bytes(&mut token);
+                JobRecords {
+                    owner: hex::encode(token),
+                    jobs: Vec::new(),
+                }
+            }
+            Err(e) => return Err(e.into()),
+        };
+        Ok(Self {
+            path: path.to_path_buf(),
+            records: Mutex::new(records),
+        })
+    }
+
+    /// The token identifying this client as the owner of its jobs.
+    pub fn owner(&self) -> String {
+        self.records.lock().unwrap().owner.clone()
+    }
+
+    fn url(&self, key: &JobKey) -> Option<String> {
+        let records = self.records.lock().unwrap();
+        records
+            .jobs
+            .iter()
+            .find(|j| &j.key == key)
+            .map(|j| j.url.clone())
+    }
+
+    /// Forget the jobs of other PoWs (i.e. of previous challenges), their results are not needed anymore.
+    /// Returns the URLs of the forgotten jobs.
+    fn retain_pow(&self, key: &JobKey) -> Vec<String> {
+        self.update(|jobs| {
+            let (kept, forgotten) = std::mem::take(jobs)
+                .into_iter()
+                .partition(|j| j.key.same_pow(key));
+            *jobs = kept;
+            forgotten.into_iter().map(|j: JobRecord| j.url).collect()
+        })
+    }
+
+    fn insert(&self, key: &JobKey, url: &str) {
+        self.update(|jobs| {
+            if !jobs.iter().any(|j| &j.key == key) {
+                jobs.push(JobRecord {
+                    key: key.clone(),
+                    url: url.to_string(),
+                });
+            }
+        });
+    }
+
+    fn remove(&self, key: &JobKey) {
+        self.update(|jobs| jobs.retain(|j| &j.key != key));
+    }
+
+    fn update<R>(&self, f: impl FnOnce(&mut Vec<JobRecord>) -> R) -> R {
+        let mut records = self.records.lock().unwrap();
+        let result = f(&mut records.jobs);
+        // Losing the records only costs redoing the jobs after a restart.
+        if let Err(e) = self.save(&records) {
+            log::warn!(
+                "failed to persist k2pow jobs in {}: {e:?}",
+                self.path.display()
+            );
+        }
+        result
+    }
+
+    fn save(&self, records: &JobRecords) -> eyre::Result<()> {
+        let tmp_path = self.path.with_extension("tmp");
+        std::fs::write(&tmp_path, serde_json::to_vec(records)?)?;
+        std::fs::rename(&tmp_path, &self.path)?;
+        Ok(())
+    }
+}
+
+/// Limits of the remote k2pow jobs in flight, globally and per identity (miner id).
+///
+/// The free slots are handed to the waiting identities in turns, so that an identity
+/// submitting many jobs at once doesn't starve the others.
+/// Shared by the [K2powService]s of the identities.
+pub struct K2powLimits {
+    global: usize,
+    per_identity: usize,
+    state: Mutex<LimitsState>,
+}
+
+#[derive(Default)]
+struct IdentityJobs {
+    running: usize,
+    waiting: VecDeque<oneshot::Sender<K2powPermit>>,
+}
+
+#[derive(Default)]
+struct LimitsState {
+    running: usize,
+    identities: BTreeMap<[u8; 32], IdentityJobs>,
+    // the identity that got the last slot
+    last_served: Option<[u8; 32]>,
+}
+
+/// A slot for a remote k2pow job, freed when dropped.
+pub struct K2powPermit {
+    // None if the slot was never taken.
+    limits: Option<Arc<K2powLimits>>,
+    miner_id: [u8; 32],
+}
+
+impl K2powLimits {
+    pub fn new(global: usize, per_identity: usize) -> Arc<Self> {
+        Arc::new(Self {
+            global,
+            per_identity,
+            state: Mutex::new(LimitsState::default()),
+        })
+    }
+
+    /// Wait for a slot for a job of the given identity.
+    pub async fn acquire(self: &Arc<Self>, miner_id: [u8; 32]) -> K2powPermit {
+        let (tx, rx) = oneshot::channel();
+        {
+            let mut state = self.state.lock().unwrap();
+            state
+                .identities
+                .entry(miner_id)
+                .or_default()
+                .waiting
+                .push_back(tx);
+            self.dispatch(&mut state);
+        }
+        rx.await.expect("k2pow limits dropped with waiting jobs")
+    }
+
+    /// Hand the free slots to the waiting identities, in turns.
+    fn dispatch(self: &Arc<Self>, state: &mut LimitsState) {
+        while state.running < self.global {
+            // The first identity after the last served one that waits and is under its limit.
+            let next = {
+                let eligible = |(id, jobs): (&[u8; 32], &IdentityJobs)| {
+                    (!jobs.waiting.is_empty() && jobs.running < self.per_identity).then_some(*id)
+                };
+                let after = state.last_served.and_then(|last| {
+                    state
+                        .identities
+                        .range((std::ops::Bound::Excluded(last), std::ops::Bound::Unbounded))
+                        .find_map(eligible)
+                });
+                after.or_else(|| state.identities.iter().find_map(eligible))
+            };
+            let Some(miner_id) = next else {
+                break;
+            };
+            let jobs = state.identities.get_mut(&miner_id).unwrap();
+            let tx = jobs.waiting.pop_front().unwrap();
+            let permit = K2powPermit {
+                limits: Some(self.clone()),
+                miner_id,
+            };
+            match tx.send(permit) {
+                Ok(()) => {
+                    jobs.running += 1;
+                    state.running += 1;
+                    state.last_served = Some(miner_id);
+                }
+                // The waiter is gone, don't release the slot it never took.
+                Err(mut permit) => permit.limits = None,
+            }
+        }
+        state
+            .identities
+            .retain(|_, jobs| jobs.running > 0 || !jobs.waiting.is_empty());
+    }
+}
+
+impl Drop for K2powPermit {
+    fn drop(&mut self) {
+        let Some(limits) = self.limits.take() else {
+            return;
+        };
+        let mut state = limits.state.lock().unwrap();
+        state.running -= 1;
+        if let Some(jobs) = state.identities.get_mut(&self.miner_id) {
+            jobs.running -= 1;
+        }
+        limits.dispatch(&mut state);
+    }
+}
 
 pub struct K2powService {
     k2pow_service: String,
-    semaphore: Arc<Semaphore>,
+    limits: Arc<K2powLimits>,
     backoff: Duration,
+    job_store: Option<Arc<JobStore>>,
 }
 
 impl K2powService {
     pub fn new(k2pow_service: String, parallelism: usize, backoff: Duration) -> Self {
-        let semaphore = Arc::new(Semaphore::new(parallelism));
         Self {
             k2pow_service,
-            semaphore,
+            limits: K2powLimits::new(parallelism, parallelism),
             backoff,
+            job_store: None,
         }
     }
+
+    /// Share the limits of the jobs in flight with other services (i.e. of other identities).
+    pub fn with_limits(mut self, limits: Arc<K2powLimits>) -> Self {
+        self.limits = limits;
+        self
+    }
+
+    /// Persist the outstanding jobs to resume polling them after a restart.
+    pub fn with_job_store(mut self, job_store: Arc<JobStore>) -> Self {
+        self.job_store = Some(job_store);
+        self
+    }
+
+    /// Check that the k2pow service is reachable and healthy, without submitting any job.
+    pub async fn check_connection(&self, timeout: Duration) -> eyre::Result<()> {
+        let response = reqwest::Client::new()
+            .get(&self.k2pow_service)
+            .timeout(timeout)
+            .send()
+            .await?;
+        eyre::ensure!(
+            response.status().is_success(),
+            "unhealthy k2pow service: {}",
+            response.status()
+        );
+        Ok(())
+    }
 }
 
 impl Prover for K2powService {
@@ -39,26 +318,68 @@ impl Prover for K2powService {
     ) -> Result<Vec<(u32, u64)>, Error> {
         let rt = Runtime::new().unwrap();
         let k2p = self.k2pow_service.clone();
+        let key = |nonce_group| JobKey {
+            miner_id: *miner_id,
+            challenge: *challenge,
+            difficulty: *difficulty,
+            nonce_group,
+        };
+        let forgotten = match &self.job_store {
+            Some(store) => store.retain_pow(&key(nonce_groups.start)),
+            None => Vec::new(),
+        };
+        let owner = self.job_store.as_ref().map(|s| s.owner());
         rt.block_on(async {
+            // Cancel the forgotten jobs that might still wait in the queue of the k2pow service.
+            // It's best effort, the service also forgets them eventually.
+            if let Some(owner) = &owner {
+                let client = reqwest::Client::new();
+                future::join_all(forgotten.iter().map(|uri| {
+                    let request = client.delete(uri).header(OWNER_HEADER, owner);
+                    async move {
+                        if let Err(err) = request.send().await {
+                            log::debug!("failed to cancel k2pow job {uri}: {err}");
+                        }
+                    }
+                }))
+                .await;
+            }
+
             let mut tasks = vec![];
             let backoff = self.backoff;
             nonce_groups.into_iter().for_each(|nonce| {
-                let uri = format!(
-                    "{}/job/{}/{}/{}/{}",
-                    &k2p,
-                    hex::encode(miner_id),
-                    nonce,
-                    hex::encode(challenge),
-                    hex::encode(difficulty)
-                );
-                let semaphore = self.semaphore.clone();
+                let key = key(nonce);
+                let resumed = self.job_store.as_ref().and_then(|store| store.url(&key));
+                if let Some(uri) = &resumed {
+                    log::info!("resuming k2pow job for nonce group {nonce}: {uri}");
+                }
+                let uri = resumed.unwrap_or_else(|| {
+                    format!(
+                        "{}/job/{}/{}/{}/{}",
+                        &k2p,
+                        hex::encode(miner_id),
+                        nonce,
+                        hex::encode(challenge),
+                        hex::encode(difficulty)
+                    )
+                });
+                let limits = self.limits.clone();
+                let job_store = self.job_store.clone();
+                let owner = owner.clone();
 
                 let task = async move {
-                    let _permit = semaphore.acquire().await.unwrap();
+                    let _permit = limits.acquire(*miner_id).await;
                     let client = reqwest::Client::new();
+                    if let Some(store) = &job_store {
+                        store.insert(&key, &uri);
+                    }
 
-                    loop {
-                        let res = match client.get(&uri).send().await {
+                    let res = loop {
+                        let mut request = with_trace_context(client.get(&uri));
+                        if let Some(owner) = &owner {
+                            request = request.header(OWNER_HEADER, owner);
+                        }
+                        let res = match request.send().await {
                             Ok(res) => res,
                             Err(err) => {
                                 log::warn!("get job error: {}. backing off before retry", err);
@@ -92,12 +413,21 @@ impl Prover for K2powService {
                                 sleep(backoff).await;
                                 continue;
                             }
+                            reqwest::StatusCode::FORBIDDEN => Err(Error::Internal(
+                                "k2pow job is owned by another client".into(),
+                            )),
                             _ => Err(Error::Internal("unknown status code returned".into())),
                         };
-                        return res;
+                        break res;
+                    };
+                    if let Some(store) = &job_store {
+                        store.remove(&key);
                     }
+                    res
                 };
-                tasks.push(task);
+                tasks.push(
+                    task.instrument(tracing::info_span!("remote_k2pow", nonce_group = nonce)),
+                );
             });
 
             future::join_all(tasks)
@@ -111,3 +441,238 @@ impl Prover for K2powService {
         true
     }
 }
+
+/// Attach the trace context of the current span to the request,
+/// so that the k2pow service can correlate its spans with ours.
+#[cfg(feature = "otel")]
+fn with_trace_context(request: reqwest::RequestBuilder) -> reqwest::RequestBuilder {
+    use std::collections::HashMap;
+    use tracing_opentelemetry::OpenTelemetrySpanExt;
+
+    let mut headers = HashMap::<String, String>::new();
+    let context = tracing::Span::current().context();
+    opentelemetry::global::get_text_map_propagator(|p| p.inject_context(&context, &mut headers));
+    headers
+        .into_iter()
+        .fold(request, |request, (k, v)| request.header(k, v))
+}
+
+#[cfg(not(feature = "otel"))]
+fn with_trace_context(request: reqwest::RequestBuilder) -> reqwest::RequestBuilder {
+    request
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::FutureExt;
+    use httpmock::prelude::*;
+
+    use super::*;
+
+    #[test]
+    fn sharing_slots_between_identities() {
+        let limits = K2powLimits::new(2, 2);
+        let a1 = limits.acquire([1; 32]).now_or_never().unwrap();
+        let a2 = limits.acquire([1; 32]).now_or_never().unwrap();
+
+        // Both slots are taken, the identities wait in turns
+        let mut a3 = Box::pin(limits.acquire([1; 32]));
+        let mut a4 = Box::pin(limits.acquire([1; 32]));
+        let mut b1 = Box::pin(limits.acquire([2; 32]));
+        assert!((&mut a3).now_or_never().is_none());
+        assert!((&mut a4).now_or_never().is_none());
+        assert!((&mut b1).now_or_never().is_none());
+
+        drop(a1);
+        let b1 = (&mut b1).now_or_never().unwrap();
+        assert!((&mut a3).now_or_never().is_none());
+        drop(a2);
+        let a3 = (&mut a3).now_or_never().unwrap();
+
+        // A waiter that gave up doesn't take a slot
+        drop(a4);
+        drop(b1);
+        let b2 = limits.acquire([2; 32]).now_or_never().unwrap();
+        drop((a3, b2));
+        assert_eq!(0, limits.state.lock().unwrap().running);
+        assert!(limits.state.lock().unwrap().identities.is_empty());
+    }
+
+    #[test]
+    fn limiting_jobs_per_identity() {
+        let limits = K2powLimits::new(3, 1);
+        let _a1 = limits.acquire([1; 32]).now_or_never().unwrap();
+        // A global slot is free, but not for the same identity
+        let mut a2 = Box::pin(limits.acquire([1; 32]));
+        assert!((&mut a2).now_or_never().is_none());
+        let _b1 = limits.acquire([2; 32]).now_or_never().unwrap();
+    }
+
+    #[test]
+    fn identity_progresses_while_another_proves_many_groups() {
+        let server = MockServer::start();
+        // The jobs of the first identity never finish while the second one proves.
+        let mut busy = server.mock(|when, then| {
+            when.path_contains(hex::encode([1u8; 32]));
+            then.status(201);
+        });
+        server.mock(|when, then| {
+            when.path_contains(hex::encode([2u8; 32]));
+            then.status(200).body("7");
+        });
+
+        let limits = &K2powLimits::new(4, 2);
+        let service = |limits: &Arc<K2powLimits>| {
+            K2powService::new(server.url(""), 4, Duration::from_millis(10))
+                .with_limits(limits.clone())
+        };
+        std::thread::scope(|scope| {
+            // 128 nonces (8 nonce groups)
+            let first =
+                scope.spawn(|| service(limits).prove_many(0..8, &[1; 8], &[0xFF; 32], &[1; 32]));
+            let (tx, rx) = std::sync::mpsc::channel();
+            scope.spawn(move || {
+                let pows = service(limits).prove_many(0..1, &[1; 8], &[0xFF; 32], &[2; 32]);
+                tx.send(pows).unwrap();
+            });
+            let pows = rx.recv_timeout(Duration::from_secs(10)).unwrap().unwrap();
+            assert_eq!(vec![(0, 7)], pows);
+            assert!(!first.is_finished());
+            assert!(limits.state.lock().unwrap().running <= 2);
+
+            busy.delete();
+            server.mock(|when, then| {
+                when.path_contains(hex::encode([1u8; 32]));
+                then.status(200).body("8");
+            });
+            assert_eq!(8, first.join().unwrap().unwrap().len());
+        });
+    }
+
+    fn key(miner_id: u8, challenge: u8, nonce_group: u32) -> JobKey {
+        JobKey {
+            miner_id: [miner_id; 32],
+            challenge: [challenge; 8],
+            difficulty: [0xFF; 32],
+            nonce_group,
+        }
+    }
+
+    #[test]
+    fn job_store_survives_restart() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("jobs.json");
+        let store = JobStore::open(&path).unwrap();
+        store.insert(&key(1, 1, 3), "http://worker/job/3");
+        store.insert(&key(1, 2, 4), "http://worker/job/4");
+        store.insert(&key(2, 1, 5), "http://worker/job/5");
+
+        let reopened = JobStore::open(&path).unwrap();
+        assert_eq!(store.owner(), reopened.owner());
+        assert_eq!(
+            Some("http://worker/job/3".to_string()),
+            reopened.url(&key(1, 1, 3))
+        );
+        // The jobs are identified by all the parameters, including the miner
+        assert!(reopened.url(&key(2, 1, 3)).is_none());
+        assert_eq!(
+            vec![
+                "http://worker/job/4".to_string(),
+                "http://worker/job/5".into()
+            ],
+            reopened.retain_pow(&key(1, 1, 0))
+        );
+        reopened.remove(&key(1, 1, 3));
+        let reopened = JobStore::open(&path).unwrap();
+        assert!(reopened.url(&key(1, 1, 3)).is_none());
+        assert!(reopened.url(&key(1, 2, 4)).is_none());
+        assert!(reopened.url(&key(2, 1, 5)).is_none());
+
+        assert_ne!(
+            store.owner(),
+            JobStore::open(&dir.path().join("other.json"))
+                .unwrap()
+                .owner()
+        );
+    }
+
+    #[test]
+    fn resuming_outstanding_jobs() {
+        let server = MockServer::start();
+        let dir = tempfile::tempdir().unwrap();
+        let store = Arc::new(JobStore::open(&dir.path().join("jobs.json")).unwrap());
+        // Submitted before a restart, via another URL
+        store.insert(&key(0, 1, 0), &server.url("/other/job/0"));
+        // Of a previous challenge, cancelled
+        store.insert(&key(0, 9, 0), &server.url("/old/job/0"));
+        let cancelled = server.mock(|when, then| {
+            when.method(DELETE)
+                .path("/old/job/0")
+                .header(OWNER_HEADER, store.owner());
+            then.status(204);
+        });
+
+        let resumed = server.mock(|when, then| {
+            when.path("/other/job/0")
+                .header(OWNER_HEADER, store.owner());
+            then.status(200).body("7");
+        });
+        let new = server.mock(|when, then| {
+            when.path_contains("/job/")
+                .path_contains("/1/")
+                .header(OWNER_HEADER, store.owner());
+            then.status(200).body("8");
+        });
+
+        let service = K2powService::new(server.url(""), 2, Duration::from_millis(10))
+            .with_job_store(store.clone());
+        let mut pows = service
+            .prove_many(0..2, &[1; 8], &[0xFF; 32], &[0; 32])
+            .unwrap();
+        pows.sort();
+        assert_eq!(vec![(0, 7), (1, 8)], pows);
+        resumed.assert();
+        new.assert();
+        cancelled.assert();
+        // Finished jobs are forgotten
+        assert!(store.url(&key(0, 1, 0)).is_none());
+        assert!(store.url(&key(0, 1, 1)).is_none());
+    }
+
+    #[test]
+    fn job_owned_by_another_client() {
+        let server = MockServer::start();
+        server.mock(|when, then| {
+            when.path_contains("/job/");
+            then.status(403);
+        });
+        let service = K2powService::new(server.url(""), 1, Duration::from_millis(10));
+        assert!(service
+            .prove_many(0..1, &[1; 8], &[0xFF; 32], &[0; 32])
+            .is_err());
+    }
+
+    #[test]
+    fn checking_connection() {
+        let rt = Runtime::new().unwrap();
+        let server = MockServer::start();
+        let mut health = server.mock(|when, then| {
+            when.path("/");
+            then.status(200).body("OK");
+        });
+        let service = K2powService::new(server.url("/"), 1, Duration::from_millis(10));
+        let timeout = Duration::from_secs(5);
+        rt.block_on(service.check_connection(timeout)).unwrap();
+
+        health.delete();
+        server.mock(|when, then| {
+            when.path("/");
+            then.status(503);
+        });
+        assert!(rt.block_on(service.check_connection(timeout)).is_err());
+
+        // Nothing listens there
+        let service = K2powService::new("http://127.0.0.1:1".into(), 1, Duration::from_millis(10));
+        assert!(rt.block_on(service.check_connection(timeout)).is_err());
+    }
+}
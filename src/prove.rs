@@ -7,39 +7,55 @@
 //! TODO: describe the algorithm
 //! ## k2 proof of work
 //! TODO: explain
+//!
+//! Generating proofs needs the `parallel` feature.
+#![cfg_attr(not(feature = "parallel"), allow(unused_imports))]
 
 use std::borrow::{Borrow, Cow};
 
 use std::sync::Arc;
 use std::sync::{
-    atomic::{AtomicBool, Ordering},
+    atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering},
+    mpsc::sync_channel,
     Mutex,
 };
-use std::{collections::HashMap, ops::Range, path::Path, time::Instant};
+use std::{
+    collections::HashMap,
+    ops::Range,
+    path::{Path, PathBuf},
+    time::{Duration, Instant},
+};
 
 use aes::cipher::block_padding::NoPadding;
 use aes::cipher::BlockEncrypt;
 use eyre::Context;
 use mockall::automock;
-use primitive_types::U256;
-use randomx_rs::RandomXFlag;
+#[cfg(feature = "parallel")]
 use rayon::prelude::{ParallelBridge, ParallelIterator};
 use serde::{Deserialize, Serialize};
 use serde_with::{base64::Base64, serde_as};
 
+use crate::block_device::{self, BlockDevice};
 use crate::config;
+use crate::pow::randomx::RandomXFlag;
 use crate::{
     cipher::AesCipher,
     compression::{compress_indices, required_bits},
-    config::ProofConfig,
-    difficulty::proving_difficulty,
-    metadata::{self, PostMetadata},
+    config::{ConfigError, InitConfig, ProofConfig},
+    difficulty::{self, proving_difficulty, scale_pow_difficulty},
+    error::Error,
+    metadata::{self, PostMetadata, ProofMetadata},
+    pos_verification::DataSnapshot,
     pow,
-    reader::read_data,
+    reader::{read_batches, LocalDir},
+    verification::{Mode, Verifier},
 };
 
 const LABEL_SIZE: usize = 16;
 const BLOCK_SIZE: usize = 16; // size of the aes block
+
+/// The number of nonces that can be tried for a challenge (256 nonce groups).
+pub const MAX_NONCES: u32 = 256 * Prover8_56::NONCES_PER_AES;
 const AES_BATCH: usize = 8; // will use encrypt8 asm method
 const CHUNK_SIZE: usize = BLOCK_SIZE * AES_BATCH;
 
@@ -62,6 +78,31 @@ impl Proof<'static> {
     }
 }
 
+/// A proof with its metadata, as written to proof files.
+///
+/// It's encoded the same as the body of the `/certify` request of the certifier,
+/// so a proof generated on one machine can be submitted from another.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct ProofWithMetadata {
+    pub proof: Proof<'static>,
+    pub metadata: ProofMetadata,
+}
+
+impl ProofWithMetadata {
+    pub fn load(path: &Path) -> eyre::Result<Self> {
+        let data = std::fs::read(path).wrap_err_with(|| format!("reading {}", path.display()))?;
+        serde_json::from_slice(&data).wrap_err_with(|| format!("decoding {}", path.display()))
+    }
+
+    /// Write the file atomically (to a temporary file first).
+    pub fn save(&self, path: &Path) -> eyre::Result<()> {
+        let tmp_path = path.with_extension("json.tmp");
+        std::fs::write(&tmp_path, serde_json::to_vec_pretty(self)?)?;
+        std::fs::rename(&tmp_path, path)?;
+        Ok(())
+    }
+}
+
 #[derive(Debug, Clone, Copy)]
 pub struct ProvingParams {
     pub difficulty: u64,
@@ -69,13 +110,75 @@ pub struct ProvingParams {
 }
 
 impl ProvingParams {
-    pub fn new(metadata: &PostMetadata, cfg: &ProofConfig) -> eyre::Result<Self> {
-        let num_labels = metadata.num_units as u64 * metadata.labels_per_unit;
-        let difficulty_scaled = U256::from_big_endian(&cfg.pow_difficulty) / metadata.num_units;
-        let pow_difficulty = difficulty_scaled.to_big_endian();
+    pub fn new(metadata: &PostMetadata, cfg: &ProofConfig) -> Result<Self, Error> {
+        let num_labels = metadata.total_labels();
         Ok(Self {
-            difficulty: proving_difficulty(cfg.k1, num_labels).map_err(|e| eyre::eyre!(e))?,
-            pow_difficulty,
+            difficulty: proving_difficulty(cfg.k1, num_labels)?,
+            pow_difficulty: scale_pow_difficulty(&cfg.pow_difficulty, metadata.num_units)?,
+        })
+    }
+
+    pub fn builder() -> ProvingParamsBuilder {
+        ProvingParamsBuilder::default()
+    }
+}
+
+/// Builder of [ProvingParams] validating the proof config against the POS data.
+#[derive(Debug, Default, Clone)]
+pub struct ProvingParamsBuilder {
+    num_units: Option<u32>,
+    labels_per_unit: Option<u64>,
+    cfg: Option<ProofConfig>,
+}
+
+impl ProvingParamsBuilder {
+    /// Take the size of POS data from the metadata.
+    pub fn metadata(mut self, metadata: &PostMetadata) -> Self {
+        self.num_units = Some(metadata.num_units);
+        self.labels_per_unit = Some(metadata.labels_per_unit);
+        self
+    }
+
+    pub fn num_units(mut self, num_units: u32) -> Self {
+        self.num_units = Some(num_units);
+        self
+    }
+
+    pub fn labels_per_unit(mut self, labels_per_unit: u64) -> Self {
+        self.labels_per_unit = Some(labels_per_unit);
+        self
+    }
+
+    pub fn config(mut self, cfg: ProofConfig) -> Self {
+        self.cfg = Some(cfg);
+        self
+    }
+
+    pub fn build(self) -> Result<ProvingParams, ConfigError> {
+        let num_units = self.num_units.ok_or(ConfigError::Missing("num_units"))?;
+        let labels_per_unit = self
+            .labels_per_unit
+            .ok_or(ConfigError::Missing("labels_per_unit"))?;
+        let cfg = self.cfg.ok_or(ConfigError::Missing("config"))?;
+        cfg.validate()?;
+        if num_units == 0 {
+            return Err(ConfigError::ZeroNumUnits);
+        }
+        if labels_per_unit == 0 {
+            return Err(ConfigError::ZeroLabelsPerUnit);
+        }
+        let num_labels = num_units as u64 * labels_per_unit;
+        if num_labels <= cfg.k1 as u64 {
+            return Err(ConfigError::TooFewLabels {
+                num_labels,
+                k1: cfg.k1,
+            });
+        }
+        Ok(ProvingParams {
+            // Can't fail as num_labels > k1 and num_units > 0.
+            difficulty: proving_difficulty(cfg.k1, num_labels).expect("valid proving difficulty"),
+            pow_difficulty: scale_pow_difficulty(&cfg.pow_difficulty, num_units)
+                .expect("valid PoW difficulty"),
         })
     }
 }
@@ -84,6 +187,18 @@ impl ProvingParams {
 pub trait ProgressReporter {
     fn new_nonce_group(&self, nonces: Range<u32>);
     fn finished_chunk(&self, position: u64, len: usize);
+    /// Called when an index is found for a nonce during a pass over POS data,
+    /// with the number of indices found for the nonce so far in the pass.
+    fn found_index(&self, _nonce: u32, _indices_found: usize) {}
+    /// Called after a pass over POS data with the number of indices found for each nonce.
+    fn finished_pass(&self, _nonces: Range<u32>, _indices_found: &HashMap<u32, usize>) {}
+    /// Called when the k2pow of a nonce group is found.
+    fn finished_k2pow(&self, _nonce_group: u32, _pow: u64) {}
+    /// Called when the k2pows of the pass are found and reading POS data starts.
+    fn started_data_pass(&self, _nonces: Range<u32>) {}
+    /// Called when a thread of the proving pool (`worker` is its index, see [create_thread_pool])
+    /// finished proving a batch of `len` bytes, with the time it took.
+    fn finished_batch(&self, _worker: usize, _len: usize, _duration: Duration) {}
 }
 
 pub struct NoopProgressReporter {}
@@ -112,6 +227,13 @@ fn calc_nonce_group(nonce: u32, per_aes: u32) -> usize {
     (nonce / per_aes) as usize
 }
 
+/// PoWs are calculated for nonce groups up to 255.
+fn nonce_group_u8(nonce_group: u32) -> Result<u8, Error> {
+    nonce_group.try_into().map_err(|_| {
+        Error::InvalidArgument(format!("nonce group {nonce_group} out of bounds (max 255)"))
+    })
+}
+
 #[inline(always)]
 fn nonce_group_range(nonces: Range<u32>, per_aes: u32) -> Range<u32> {
     let start_group = nonces.start / per_aes;
@@ -119,10 +241,18 @@ fn nonce_group_range(nonces: Range<u32>, per_aes: u32) -> Range<u32> {
     start_group..end_group
 }
 
+/// Nonce groups (each with its own k2pow) covering the nonces.
+pub fn nonce_groups(nonces: Range<u32>) -> Range<u32> {
+    nonce_group_range(nonces, Prover8_56::NONCES_PER_AES)
+}
+
 #[derive(Debug)]
 pub struct Prover8_56 {
     ciphers: Vec<AesCipher>,
     lazy_ciphers: Vec<AesCipher>,
+    /// The first and last AES groups may contain nonces outside of this range,
+    /// which are skipped.
+    nonces: Range<u32>,
     difficulty_msb: u8,
     difficulty_lsb: u64,
 }
@@ -136,26 +266,43 @@ impl Prover8_56 {
         params: ProvingParams,
         pow_prover: &(dyn pow::Prover + Send + Sync),
         miner_id: &[u8; 32],
-    ) -> eyre::Result<Self> {
-        // TODO consider to relax it to allow any range of nonces
-        eyre::ensure!(
-            nonces.start % Self::NONCES_PER_AES == 0,
-            "nonces must start at a multiple of 16"
-        );
-        eyre::ensure!(
-            !nonces.is_empty() && nonces.len() % Self::NONCES_PER_AES as usize == 0,
-            "nonces must be a multiple of 16"
-        );
+    ) -> Result<Self, Error> {
+        Self::new_with_progress(challenge, nonces, params, pow_prover, miner_id, &|_, _| {})
+    }
+
+    /// Like [Prover8_56::new], calling `on_k2pow` with each found k2pow.
+    ///
+    /// Parallel PoW provers ([pow::Prover::par], i.e. a remote k2pow service) calculate
+    /// the k2pows of all nonce groups concurrently, bounded by their own limits.
+    /// Otherwise, the nonce groups are calculated one after another: a local PoW prover
+    /// already searches for each k2pow on all threads of the pool, running the groups
+    /// concurrently would split the same threads without finishing any sooner.
+    pub fn new_with_progress(
+        challenge: &[u8; 32],
+        nonces: Range<u32>,
+        params: ProvingParams,
+        pow_prover: &(dyn pow::Prover + Send + Sync),
+        miner_id: &[u8; 32],
+        on_k2pow: &(dyn Fn(u32, u64) + Sync),
+    ) -> Result<Self, Error> {
+        if nonces.is_empty() {
+            return Err(Error::InvalidArgument("nonces must not be empty".into()));
+        }
         log::info!("calculating proof of work for nonces {nonces:?}",);
-        let map_fn = |nonce_group: u32| -> eyre::Result<AesCipher> {
+        let groups = nonce_group_range(nonces.clone(), Self::NONCES_PER_AES);
+        let (done, total) = (AtomicUsize::new(0), groups.len());
+        let map_fn = |nonce_group: u32| -> Result<AesCipher, Error> {
+            let _span = tracing::debug_span!("k2pow_nonce_group", nonce_group).entered();
             log::debug!("calculating proof of work for nonce group {nonce_group}");
             let pow = pow_prover.prove(
-                nonce_group.try_into()?,
+                nonce_group_u8(nonce_group)?,
                 challenge[..8].try_into().unwrap(),
                 &params.pow_difficulty,
                 miner_id,
             )?;
-            log::debug!("proof of work for nonce group {nonce_group}: {pow}");
+            let done = done.fetch_add(1, Ordering::Relaxed) + 1;
+            log::info!("proof of work for nonce group {nonce_group}: {pow} ({done}/{total})");
+            on_k2pow(nonce_group, pow);
 
             Ok(AesCipher::new(challenge, nonce_group, pow))
         };
@@ -163,29 +310,29 @@ impl Prover8_56 {
         let ciphers: Vec<AesCipher> = match pow_prover.par() {
             true => pow_prover
                 .prove_many(
-                    nonce_group_range(nonces.clone(), Self::NONCES_PER_AES),
-                    challenge[..8].try_into()?,
+                    groups.clone(),
+                    challenge[..8].try_into().unwrap(),
                     &params.pow_difficulty,
                     miner_id,
-                )
-                .unwrap()
+                )?
                 .into_iter()
-                .map(|(nonce_group, pow)| -> eyre::Result<AesCipher> {
-                    Ok(AesCipher::new(challenge, nonce_group, pow))
+                .map(|(nonce_group, pow)| {
+                    on_k2pow(nonce_group, pow);
+                    AesCipher::new(challenge, nonce_group, pow)
                 })
-                .collect::<eyre::Result<_>>()?,
-            false => nonce_group_range(nonces.clone(), Self::NONCES_PER_AES)
-                .map(map_fn)
-                .collect::<eyre::Result<_>>()?,
+                .collect(),
+            false => groups.map(map_fn).collect::<Result<_, Error>>()?,
         };
+        let first_group = nonces.start / Self::NONCES_PER_AES;
         let lazy_ciphers = nonces
+            .clone()
             .map(|nonce| {
                 let nonce_group = calc_nonce_group(nonce, Self::NONCES_PER_AES);
                 AesCipher::new_lazy(
                     challenge,
                     nonce,
                     nonce_group as u32,
-                    ciphers[nonce_group % ciphers.len()].pow,
+                    ciphers[nonce_group - first_group as usize].pow,
                 )
             })
             .collect();
@@ -194,6 +341,7 @@ impl Prover8_56 {
         Ok(Self {
             ciphers,
             lazy_ciphers,
+            nonces,
             difficulty_msb,
             difficulty_lsb,
         })
@@ -205,14 +353,18 @@ impl Prover8_56 {
 
     #[inline(always)]
     fn cipher(&self, nonce: u32) -> Option<&AesCipher> {
+        if !self.nonces.contains(&nonce) {
+            return None;
+        }
+        let first_group = calc_nonce_group(self.nonces.start, Self::NONCES_PER_AES);
         self.ciphers
-            .get(calc_nonce_group(nonce, Self::NONCES_PER_AES) % self.ciphers.len())
+            .get(calc_nonce_group(nonce, Self::NONCES_PER_AES) - first_group)
     }
 
     #[inline(always)]
     fn lazy_cipher(&self, nonce: u32) -> Option<&AesCipher> {
         self.lazy_ciphers
-            .get(nonce as usize % self.lazy_ciphers.len())
+            .get(nonce.checked_sub(self.nonces.start)? as usize)
     }
 
     /// LSB part of the difficulty is checked with second sequence of AES ciphers.
@@ -262,10 +414,13 @@ impl Prover for Prover8_56 {
 
                 for (offset, &msb) in u8s.iter().enumerate() {
                     if msb <= self.difficulty_msb {
+                        let nonce = calc_nonce(cipher.nonce_group, Self::NONCES_PER_AES, offset);
+                        if !self.nonces.contains(&nonce) {
+                            // Outside of the range in the first or last AES group
+                            continue;
+                        }
                         if msb == self.difficulty_msb {
                             // Check LSB
-                            let nonce =
-                                calc_nonce(cipher.nonce_group, Self::NONCES_PER_AES, offset);
                             let label_offset = offset / Self::NONCES_PER_AES as usize * LABEL_SIZE;
                             if let Some(p) = self.check_lsb(
                                 &chunk[label_offset..label_offset + LABEL_SIZE],
@@ -279,8 +434,6 @@ impl Prover for Prover8_56 {
                         } else {
                             // valid label
                             let index = index + (offset as u32 / Self::NONCES_PER_AES) as u64;
-                            let nonce =
-                                calc_nonce(cipher.nonce_group, Self::NONCES_PER_AES, offset);
                             if let Some(indexes) = consume(nonce, index) {
                                 return Some((nonce, indexes));
                             }
@@ -295,7 +448,127 @@ impl Prover for Prover8_56 {
     }
 }
 
+pub use crate::reader::{
+    Batch, BatchSize, PosDataSource, PosFileSource, PosFiles, RateLimiter, ReadError, ReadRetry,
+};
+
+/// Pauses reading POS data while set.
+///
+/// The proof generation resumes where it stopped after the pause is lifted.
+#[derive(Debug, Default)]
+pub struct Pause {
+    paused: std::sync::Mutex<bool>,
+    resumed: std::sync::Condvar,
+}
+
+impl Pause {
+    pub fn set(&self, paused: bool) {
+        *self.paused.lock().unwrap() = paused;
+        if !paused {
+            self.resumed.notify_all();
+        }
+    }
+
+    pub fn is_paused(&self) -> bool {
+        *self.paused.lock().unwrap()
+    }
+
+    /// Block while paused. Returns early if `stop` is set.
+    pub fn wait(&self, stop: &AtomicBool) {
+        let mut paused = self.paused.lock().unwrap();
+        while *paused && !stop.load(Ordering::Relaxed) {
+            // Wake up periodically to check the stop flag.
+            (paused, _) = self
+                .resumed
+                .wait_timeout(paused, std::time::Duration::from_millis(100))
+                .unwrap();
+        }
+    }
+}
+
+/// Optional settings of proof generation.
+#[derive(Debug, Default, Clone)]
+pub struct ProvingOptions {
+    /// Limits the rate of reading POS data.
+    /// Can be shared with other proof generations to limit them together.
+    pub read_rate_limit: Option<Arc<RateLimiter>>,
+    /// Allows pausing and resuming the proof generation.
+    pub pause: Option<Arc<Pause>>,
+    /// Read POS data from this source instead of the data directory.
+    /// The metadata is still loaded from the data directory.
+    pub data_source: Option<Arc<dyn PosDataSource>>,
+    /// Start each data pass with the nonce groups whose k2pow is ready,
+    /// while the k2pows of the next nonce groups are calculated in the background.
+    ///
+    /// The nonce groups not ready yet are proven in the next pass. Only for local
+    /// (not parallel) PoW provers. Stopping might wait for the k2pow in progress.
+    pub pipelined_k2pow: bool,
+    /// Before each data pass, check that the POS data has not changed since the snapshot
+    /// (i.e. a file was truncated), failing instead of generating an invalid proof.
+    pub data_snapshot: Option<DataSnapshot>,
+    /// Retrying failed reads of POS data (not retried by default).
+    ///
+    /// When the retries are exhausted, the proof generation fails with a [ReadError].
+    pub read_retry: ReadRetry,
+    /// Size of the batches of POS data read for proving (1 MiB by default).
+    pub batch_size: BatchSize,
+    /// Limits the memory (in bytes) taken by the batches of POS data read for proving.
+    ///
+    /// The data is read on a separate thread, so that a disk faster than the CPUs
+    /// doesn't buffer many batches. The batches queued, being read and held by
+    /// the proving threads all count towards the budget: with a budget smaller than
+    /// a batch for every thread, some threads idle. A single batch is always let
+    /// through, even if bigger than the budget. Not limited by default.
+    pub memory_budget: Option<u64>,
+    /// Abort a data pass early when the chance that any of its nonces still finds
+    /// k2 indices in the rest of the POS data drops below this probability
+    /// and move on to the next nonces. Passes are never aborted by default.
+    ///
+    /// Saves reading the rest of the data in unlucky passes, at the cost of
+    /// (rarely) missing a proof that the rest of the data would give.
+    pub early_abort: Option<f64>,
+    /// Persist the k2pows found in the state directory and reuse them
+    /// when proving the same challenge again (i.e. after a restart).
+    pub k2pow_cache: bool,
+    /// The directory for the k2pow cache, [block_device::state_dir] of the data if not set.
+    pub state_dir: Option<PathBuf>,
+    /// The PoW of the k2pows, only the cached k2pows of the same PoW are reused.
+    pub pow_algorithm: pow::cache::PowAlgorithm,
+    /// Verify the labels of the found proof against this config before returning it,
+    /// failing with [Error::SelfCheckFailed] if any is invalid (i.e. the POS data is corrupted).
+    ///
+    /// All the labels are regenerated (the PoW is not verified). Not checked by default.
+    pub self_check: Option<InitConfig>,
+    /// Give up with [Error::NoncesExhausted] when no proof is found with this many nonces
+    /// (capped at and by default [MAX_NONCES]).
+    pub max_nonces: Option<u32>,
+    /// Prove only the units initialized completely of partially initialized POS data
+    /// (see [PostMetadata::initialized]). The proof is then valid only for that number
+    /// of units. Fails with [Error::PartiallyInitialized] by default.
+    pub partial_data: bool,
+}
+
+/// The source of the POS data to prove: [ProvingOptions::data_source] if set,
+/// the block device or the POS data files in the data directory otherwise.
+pub fn pos_data_source(
+    datadir: &Path,
+    metadata: &PostMetadata,
+    options: &ProvingOptions,
+) -> Arc<dyn PosDataSource> {
+    match &options.data_source {
+        Some(source) => source.clone(),
+        None if block_device::is_block_data(datadir) => {
+            Arc::new(BlockDevice(datadir.to_path_buf()))
+        }
+        None => Arc::new(PosFiles::new(
+            LocalDir(datadir.to_path_buf()),
+            metadata.max_file_size,
+        )),
+    }
+}
+
 /// Generate a proof that data is still held, given the challenge.
+#[cfg(feature = "parallel")]
 #[allow(clippy::too_many_arguments)]
 pub fn generate_proof<Reporter, Stopper>(
     datadir: &Path,
@@ -307,21 +580,104 @@ pub fn generate_proof<Reporter, Stopper>(
     stop: Stopper,
     reporter: Reporter,
     pow_prover: &(dyn pow::Prover + Send + Sync),
-) -> eyre::Result<Proof<'static>>
+) -> Result<Proof<'static>, Error>
+where
+    Stopper: Borrow<AtomicBool>,
+    Reporter: ProgressReporter + Send + Sync,
+{
+    generate_proof_with_options(
+        datadir,
+        challenge,
+        cfg,
+        nonces_size,
+        cores,
+        pow_flags,
+        stop,
+        reporter,
+        pow_prover,
+        &ProvingOptions::default(),
+    )
+}
+
+/// Generate a proof like [generate_proof] with optional settings.
+#[cfg(feature = "parallel")]
+#[allow(clippy::too_many_arguments)]
+#[tracing::instrument(skip_all, fields(challenge = hex::encode_upper(challenge), nonces = nonces_size))]
+pub fn generate_proof_with_options<Reporter, Stopper>(
+    datadir: &Path,
+    challenge: &[u8; 32],
+    cfg: ProofConfig,
+    nonces_size: usize,
+    cores: config::Cores,
+    pow_flags: RandomXFlag,
+    stop: Stopper,
+    reporter: Reporter,
+    pow_prover: &(dyn pow::Prover + Send + Sync),
+    options: &ProvingOptions,
+) -> Result<Proof<'static>, Error>
 where
     Stopper: Borrow<AtomicBool>,
     Reporter: ProgressReporter + Send + Sync,
 {
     let stop = stop.borrow();
-    let metadata = metadata::load(datadir).wrap_err("loading metadata")?;
+    let all_metadata = metadata::load(datadir)?;
+    // Only the initialized prefix of partially initialized POS data can be proven.
+    let metadata = all_metadata.initialized()?;
+    if metadata.num_units < all_metadata.num_units {
+        if !options.partial_data {
+            return Err(Error::PartiallyInitialized {
+                units: metadata.num_units,
+                total_units: all_metadata.num_units,
+            });
+        }
+        log::warn!(
+            "POS data is partially initialized, proving for {} of {} units",
+            metadata.num_units,
+            all_metadata.num_units
+        );
+    }
     let params = ProvingParams::new(&metadata, &cfg)?;
     log::info!(
         "generating proof with PoW flags: {pow_flags:?}, difficulty (scaled with SU): {}, K2PoW difficulty (scaled with SU): {}",
-        params.difficulty,
-        hex::encode_upper(params.pow_difficulty)
+        difficulty::describe_proving_difficulty(params.difficulty),
+        difficulty::describe_pow_difficulty(&params.pow_difficulty)
     );
 
-    let mut nonces = 0..nonces_size as u32;
+    let data_source = pos_data_source(datadir, &metadata, options);
+
+    let pow_cache = options.k2pow_cache.then(|| {
+        let state_dir = match &options.state_dir {
+            Some(dir) => dir.clone(),
+            None => block_device::state_dir(datadir),
+        };
+        let path = state_dir.join(pow::cache::FILE_NAME);
+        std::fs::create_dir_all(&state_dir)
+            .map_err(eyre::Report::from)
+            .and_then(|_| pow::cache::PowCache::open(&path, options.pow_algorithm.clone()))
+            .inspect_err(|e| log::warn!("failed to load k2pows from {}: {e:?}", path.display()))
+            .ok()
+    });
+    let pow_cache = pow_cache.flatten();
+    if let Some(cache) = &pow_cache {
+        cache.retain_challenge(challenge[..8].try_into().unwrap());
+    }
+    let caching_prover = pow_cache.as_ref().map(|cache| pow::cache::CachingProver {
+        inner: pow_prover,
+        cache,
+    });
+    let pow_prover: &(dyn pow::Prover + Send + Sync) = match &caching_prover {
+        Some(prover) => prover,
+        None => pow_prover,
+    };
+
+    let max_nonces = options
+        .max_nonces
+        .map_or(MAX_NONCES, |max| max.min(MAX_NONCES));
+    let exhausted = || Error::NoncesExhausted { nonces: max_nonces };
+    let mut nonces = 0..(nonces_size as u32).min(max_nonces);
+    // The k2pow of the nonce group shared by the last pass and the next one
+    // (if the passes are not aligned to nonce groups).
+    let mut carried_pow = None;
 
     let pool = create_thread_pool(cores, |id| {
         log::error!("failed to set core affinity for thread to {id}");
@@ -330,73 +686,508 @@ where
     .wrap_err("building thread pool")?;
 
     let total_time = Instant::now();
-    loop {
-        if stop.load(Ordering::Relaxed) {
-            eyre::bail!("proof generation was stopped");
-        }
-        reporter.new_nonce_group(nonces.clone());
+    // Set when the proving loop ends, to cancel the pipelined k2pows.
+    let finished = AtomicBool::new(false);
+    std::thread::scope(|scope| {
+        // In the pipelined mode, k2pows are calculated in the background
+        // (ahead of the data passes) and sent here one nonce group at a time.
+        let ready_pows = (options.pipelined_k2pow && !pow_prover.par()).then(|| {
+            let groups_per_pass = nonces_size.div_ceil(Prover8_56::NONCES_PER_AES as usize);
+            if nonces_size % Prover8_56::NONCES_PER_AES as usize != 0 {
+                log::warn!(
+                    "pipelined k2pow proves whole nonce groups, rounding {nonces_size} nonces up to {}",
+                    groups_per_pass * Prover8_56::NONCES_PER_AES as usize
+                );
+            }
+            let (tx, rx) = sync_channel(groups_per_pass);
+            let (pool, reporter, miner_id) = (&pool, &reporter, &metadata.node_id);
+            let finished = &finished;
+            let max_groups = max_nonces.div_ceil(Prover8_56::NONCES_PER_AES);
+            scope.spawn(move || {
+                for nonce_group in 0..max_groups {
+                    if stop.load(Ordering::Relaxed) || finished.load(Ordering::Relaxed) {
+                        return;
+                    }
+                    let pow = pool.install(|| -> Result<u64, Error> {
+                        Ok(pow_prover.prove(
+                            nonce_group_u8(nonce_group)?,
+                            challenge[..8].try_into().unwrap(),
+                            &params.pow_difficulty,
+                            miner_id,
+                        )?)
+                    });
+                    if let Ok(pow) = pow {
+                        log::info!("proof of work for nonce group {nonce_group}: {pow}");
+                        reporter.finished_k2pow(nonce_group, pow);
+                    }
+                    let failed = pow.is_err();
+                    // Stop when the proof was found or the PoW failed.
+                    if tx.send(pow.map(|pow| (nonce_group, pow))).is_err() || failed {
+                        return;
+                    }
+                }
+            });
+            (rx, groups_per_pass)
+        });
 
-        let indexes = Mutex::new(HashMap::<u32, Vec<u64>>::new());
+        let result = (|| loop {
+            if stop.load(Ordering::Relaxed) {
+                return Err(Error::Stopped);
+            }
+            if nonces.is_empty() {
+                return Err(exhausted());
+            }
+            if let Some(snapshot) = &options.data_snapshot {
+                snapshot.check(datadir)?;
+            }
+            let mut pipelined_pows = None;
+            if let Some((ready_pows, groups_per_pass)) = &ready_pows {
+                // Wait for at least one nonce group and take all that are ready.
+                let Ok(first) = ready_pows.recv() else {
+                    // All the nonce groups are proven, unless stopped.
+                    return Err(match stop.load(Ordering::Relaxed) {
+                        true => Error::Stopped,
+                        false => exhausted(),
+                    });
+                };
+                let mut pows = HashMap::from([first?]);
+                while pows.len() < *groups_per_pass {
+                    match ready_pows.try_recv() {
+                        Ok(pow) => {
+                            let (nonce_group, pow) = pow?;
+                            pows.insert(nonce_group, pow);
+                        }
+                        Err(_) => break,
+                    }
+                }
+                let first_group = *pows.keys().min().unwrap();
+                nonces = first_group * Prover8_56::NONCES_PER_AES
+                    ..((first_group + pows.len() as u32) * Prover8_56::NONCES_PER_AES)
+                        .min(max_nonces);
+                pipelined_pows = Some(ReadyPows(pows));
+            }
+            reporter.new_nonce_group(nonces.clone());
+            let _pass_span = tracing::info_span!("proving_pass", nonces = ?nonces).entered();
+
+            let indexes = Mutex::new(HashMap::<u32, Vec<u64>>::new());
+
+            let pow_time = Instant::now();
+            let k2pow_span = tracing::info_span!("k2pow");
+            let prover = pool.install(|| {
+                let _span = k2pow_span.enter();
+                let miner_id = &metadata.node_id;
+                match &pipelined_pows {
+                    Some(pows) => {
+                        Prover8_56::new(challenge, nonces.clone(), params, pows, miner_id)
+                    }
+                    None => Prover8_56::new_with_progress(
+                        challenge,
+                        nonces.clone(),
+                        params,
+                        &CarriedPow {
+                            inner: pow_prover,
+                            carried: carried_pow,
+                        },
+                        miner_id,
+                        &|nonce_group, pow| reporter.finished_k2pow(nonce_group, pow),
+                    ),
+                }
+            })?;
+            carried_pow = (nonces.end % Prover8_56::NONCES_PER_AES != 0).then(|| {
+                let pow = prover
+                    .get_pow(nonces.end - 1)
+                    .expect("pow of the last nonce");
+                (nonces.end / Prover8_56::NONCES_PER_AES, pow)
+            });
+
+            let pow_secs = pow_time.elapsed().as_secs();
+            let pow_mins = pow_secs / 60;
+            log::info!("finished k2pow in {pow_mins}m {}s", pow_secs % 60);
+
+            let read_time = Instant::now();
+            let read_rate_limit = options.read_rate_limit.clone();
+            let pause = options.pause.clone();
+            let read_error = Mutex::new(None);
+            let data_reader = read_batches(
+                data_source.clone(),
+                0,
+                metadata.total_size(),
+                options.batch_size,
+                options.read_retry,
+            )
+            .inspect(move |batch| {
+                if let Ok(batch) = batch {
+                    if let Some(pause) = &pause {
+                        pause.wait(stop);
+                    }
+                    if let Some(limiter) = &read_rate_limit {
+                        limiter.acquire(batch.data.len() as u64);
+                    }
+                }
+            });
+            let data_reader: Box<dyn Iterator<Item = _> + Send> = match options.memory_budget {
+                Some(budget) => {
+                    // Every batch read holds its bytes of the budget until it's proven,
+                    // so the batches queued and held by the proving threads fit in it together.
+                    let budget = MemoryBudget::new(budget);
+                    let batch_size = options.batch_size.max() as u64;
+                    let (tx, rx) = std::sync::mpsc::channel();
+                    // Stops when the data pass is finished and the receiver is dropped.
+                    let mut data_reader = data_reader;
+                    scope.spawn(move || loop {
+                        let mut permit = budget.acquire(batch_size);
+                        let Some(batch) = data_reader.next() else {
+                            return;
+                        };
+                        if let Ok(batch) = &batch {
+                            permit.shrink(batch.data.len() as u64);
+                        }
+                        if tx.send((batch, Some(permit))).is_err() {
+                            return;
+                        }
+                    });
+                    Box::new(rx.into_iter())
+                }
+                None => Box::new(data_reader.map(|batch| (batch, None))),
+            };
+            let total_size = metadata.total_size();
+            let nonces_count = nonces.len();
+            let processed = AtomicU64::new(0);
+            let hopeless = AtomicBool::new(false);
+            log::info!("started reading POST data");
+            reporter.started_data_pass(nonces.clone());
+            let data_pass_span = tracing::info_span!("data_pass");
+            let result = pool.install(|| {
+                let _span = data_pass_span.enter();
+                data_reader
+                    .par_bridge()
+                    .take_any_while(|_| {
+                        !stop.load(Ordering::Relaxed) && !hopeless.load(Ordering::Relaxed)
+                    })
+                    // The permit gives the bytes of the batch back to the budget once it's proven.
+                    .find_map_any(|(batch, _permit)| {
+                        let batch = match batch {
+                            Ok(batch) => batch,
+                            Err(e) => {
+                                *read_error.lock().unwrap() = Some(e);
+                                return None;
+                            }
+                        };
+                        let proving = Instant::now();
+                        let res = prover.prove(
+                            &batch.data,
+                            batch.pos / BLOCK_SIZE as u64,
+                            |nonce, index| {
+                                let mut indexes = indexes.lock().unwrap();
+                                let vec = indexes.entry(nonce).or_default();
+                                vec.push(index);
+                                reporter.found_index(nonce, vec.len());
+                                if vec.len() >= cfg.k2 as usize {
+                                    return Some(std::mem::take(vec));
+                                }
+                                None
+                            },
+                        );
+                        reporter.finished_batch(
+                            rayon::current_thread_index().unwrap_or_default(),
+                            batch.data.len(),
+                            proving.elapsed(),
+                        );
+                        reporter.finished_chunk(batch.pos, batch.data.len());
+
+                        if let (Some(threshold), None) = (options.early_abort, &res) {
+                            let len = batch.data.len() as u64;
+                            let done = processed.fetch_add(len, Ordering::Relaxed) + len;
+                            let found: Vec<usize> =
+                                indexes.lock().unwrap().values().map(Vec::len).collect();
+                            let chance = chance_of_proof(
+                                &cfg,
+                                1.0 - done as f64 / total_size as f64,
+                                nonces_count,
+                                &found,
+                            );
+                            if chance < threshold {
+                                hopeless.store(true, Ordering::Relaxed);
+                            }
+                        }
+                        res
+                    })
+            });
+            let read_secs = read_time.elapsed().as_secs();
+            let read_mins = read_secs / 60;
+            log::info!(
+                "finished reading POST data in {read_mins}m {}s",
+                read_secs % 60
+            );
+
+            let mut indices_found: HashMap<u32, usize> = indexes
+                .into_inner()
+                .unwrap()
+                .into_iter()
+                .map(|(nonce, indices)| (nonce, indices.len()))
+                .collect();
+            if let Some((nonce, indices)) = &result {
+                indices_found.insert(*nonce, indices.len());
+            }
+            reporter.finished_pass(nonces.clone(), &indices_found);
+
+            if let Some((nonce, indices)) = result {
+                let _span = tracing::info_span!("proof_assembly", nonce).entered();
+                let num_labels = metadata.total_labels();
+                let pow = prover.get_pow(nonce).unwrap();
+
+                let total_secs = total_time.elapsed().as_secs();
+                let total_mins = total_secs / 60;
+
+                log::info!("found proof for nonce: {nonce}, pow: {pow} with {indices:?} indices. It took {total_mins}m {}s", total_secs % 60);
+                let proof = Proof::new(nonce, &indices, num_labels, pow);
+                if let Some(init_cfg) = &options.self_check {
+                    self_check(&proof, &metadata, challenge, &cfg, init_cfg)?;
+                }
+                return Ok(proof);
+            }
+            // The data pass was incomplete, a proof could be missed.
+            if let Some(err) = read_error.into_inner().unwrap() {
+                return Err(err.into());
+            }
+            if hopeless.into_inner() {
+                log::info!(
+                    "aborted the pass for nonces {nonces:?} after {}/{total_size} bytes, \
+                    a proof is unlikely in the rest of the data",
+                    processed.into_inner()
+                );
+            }
 
-        let pow_time = Instant::now();
-        let prover = pool.install(|| {
-            let miner_id = &metadata.node_id;
-            Prover8_56::new(challenge, nonces.clone(), params, pow_prover, miner_id)
-                .wrap_err("creating prover")
+            nonces = nonces.end..(nonces.end + nonces_size as u32).min(max_nonces);
+        })();
+        // Don't let the pipelined k2pows go on after the proof is found or proving failed.
+        // The receiver is dropped to unblock a pending send.
+        finished.store(true, Ordering::Relaxed);
+        drop(ready_pows);
+        result
+    })
+}
+
+/// Verify the labels of a generated proof (see [ProvingOptions::self_check]).
+#[cfg(feature = "parallel")]
+fn self_check(
+    proof: &Proof,
+    metadata: &PostMetadata,
+    challenge: &[u8; 32],
+    cfg: &ProofConfig,
+    init_cfg: &InitConfig,
+) -> Result<(), Error> {
+    let _span = tracing::info_span!("proof_self_check").entered();
+    let metadata = ProofMetadata::new(*metadata, *challenge);
+    let verified = Verifier::labels_only()
+        .verify_labels_only(proof, &metadata, cfg, init_cfg, Mode::All)
+        .map_err(|source| Error::SelfCheckFailed {
+            index: source.index(),
+            source,
         })?;
+    log::info!(
+        "proof passed the self-check ({} labels verified)",
+        verified.labels
+    );
+    Ok(())
+}
 
-        let pow_secs = pow_time.elapsed().as_secs();
-        let pow_mins = pow_secs / 60;
-        log::info!("finished k2pow in {pow_mins}m {}s", pow_secs % 60);
-
-        let read_time = Instant::now();
-        let data_reader = read_data(datadir, 1024 * 1024, metadata.max_file_size)?;
-        log::info!("started reading POST data");
-        let result = pool.install(|| {
-            data_reader
-                .par_bridge()
-                .take_any_while(|_| !stop.load(Ordering::Relaxed))
-                .find_map_any(|batch| {
-                    let res = prover.prove(
-                        &batch.data,
-                        batch.pos / BLOCK_SIZE as u64,
-                        |nonce, index| {
-                            let mut indexes = indexes.lock().unwrap();
-                            let vec = indexes.entry(nonce).or_default();
-                            vec.push(index);
-                            if vec.len() >= cfg.k2 as usize {
-                                return Some(std::mem::take(vec));
-                            }
-                            None
-                        },
-                    );
-                    reporter.finished_chunk(batch.pos, batch.data.len());
+/// The chance that a nonce with `found` indices finds k2 indices in the remaining
+/// fraction of the POS data (`nonce_chance(cfg, 1.0, 0)` is the chance of a whole pass).
+///
+/// Every label is an index with a probability of k1 / num_labels, so the number
+/// of indices in the remaining data follows (approximately) the Poisson distribution
+/// with a mean of k1 * remaining.
+pub fn nonce_chance(cfg: &ProofConfig, remaining: f64, found: usize) -> f64 {
+    let mean = cfg.k1 as f64 * remaining.max(0.0);
+    let needed = (cfg.k2 as usize).saturating_sub(found);
+    // P(X >= needed) for X ~ Poisson(mean)
+    let mut term = (-mean).exp();
+    let mut below = 0.0;
+    for i in 0..needed {
+        below += term;
+        term *= mean / (i + 1) as f64;
+    }
+    (1.0 - below).max(0.0)
+}
 
-                    res
-                })
-        });
-        let read_secs = read_time.elapsed().as_secs();
-        let read_mins = read_secs / 60;
-        log::info!(
-            "finished reading POST data in {read_mins}m {}s",
-            read_secs % 60
-        );
+/// Upper bound of the chance that any of the nonces of a pass finds k2 indices
+/// in the remaining fraction of the POS data, given the indices found so far
+/// (`found` lists the counts of the nonces with at least one index).
+#[cfg(feature = "parallel")]
+fn chance_of_proof(cfg: &ProofConfig, remaining: f64, nonces: usize, found: &[usize]) -> f64 {
+    let without_indices =
+        nonces.saturating_sub(found.len()) as f64 * nonce_chance(cfg, remaining, 0);
+    let chance = found
+        .iter()
+        .map(|&count| nonce_chance(cfg, remaining, count))
+        .sum::<f64>()
+        + without_indices;
+    chance.min(1.0)
+}
 
-        if let Some((nonce, indices)) = result {
-            let num_labels = metadata.num_units as u64 * metadata.labels_per_unit;
-            let pow = prover.get_pow(nonce).unwrap();
+/// k2pows calculated ahead of a data pass in the pipelined mode.
+#[cfg(feature = "parallel")]
+struct ReadyPows(HashMap<u32, u64>);
 
-            let total_secs = total_time.elapsed().as_secs();
-            let total_mins = total_secs / 60;
+#[cfg(feature = "parallel")]
+impl pow::Prover for ReadyPows {
+    fn prove(
+        &self,
+        nonce_group: u8,
+        _: &[u8; 8],
+        _: &[u8; 32],
+        _: &[u8; 32],
+    ) -> Result<u64, pow::Error> {
+        self.0
+            .get(&(nonce_group as u32))
+            .copied()
+            .ok_or(pow::Error::PoWNotFound)
+    }
 
-            log::info!("found proof for nonce: {nonce}, pow: {pow} with {indices:?} indices. It took {total_mins}m {}s", total_secs % 60);
-            return Ok(Proof::new(nonce, &indices, num_labels, pow));
+    fn prove_many(
+        &self,
+        nonce_groups: Range<u32>,
+        _: &[u8; 8],
+        _: &[u8; 32],
+        _: &[u8; 32],
+    ) -> Result<Vec<(u32, u64)>, pow::Error> {
+        nonce_groups
+            .map(|group| {
+                let pow = self.0.get(&group).ok_or(pow::Error::PoWNotFound)?;
+                Ok((group, *pow))
+            })
+            .collect()
+    }
+
+    fn par(&self) -> bool {
+        false
+    }
+}
+
+/// Reuses the k2pow of a nonce group proven in the previous pass,
+/// delegating the other nonce groups to the inner prover.
+#[cfg(feature = "parallel")]
+struct CarriedPow<'a> {
+    inner: &'a (dyn pow::Prover + Send + Sync),
+    carried: Option<(u32, u64)>,
+}
+
+#[cfg(feature = "parallel")]
+impl pow::Prover for CarriedPow<'_> {
+    fn prove(
+        &self,
+        nonce_group: u8,
+        challenge: &[u8; 8],
+        difficulty: &[u8; 32],
+        miner_id: &[u8; 32],
+    ) -> Result<u64, pow::Error> {
+        match self.carried {
+            Some((group, pow)) if group == nonce_group as u32 => Ok(pow),
+            _ => self
+                .inner
+                .prove(nonce_group, challenge, difficulty, miner_id),
         }
+    }
+
+    fn prove_many(
+        &self,
+        nonce_groups: Range<u32>,
+        challenge: &[u8; 8],
+        difficulty: &[u8; 32],
+        miner_id: &[u8; 32],
+    ) -> Result<Vec<(u32, u64)>, pow::Error> {
+        match self.carried {
+            Some((group, pow)) if group == nonce_groups.start => {
+                let mut pows = vec![(group, pow)];
+                if nonce_groups.len() > 1 {
+                    pows.extend(self.inner.prove_many(
+                        group + 1..nonce_groups.end,
+                        challenge,
+                        difficulty,
+                        miner_id,
+                    )?);
+                }
+                Ok(pows)
+            }
+            _ => self
+                .inner
+                .prove_many(nonce_groups, challenge, difficulty, miner_id),
+        }
+    }
+
+    fn par(&self) -> bool {
+        self.inner.par()
+    }
+}
 
-        nonces = nonces.end..(nonces.end + nonces_size as u32);
+/// Bounds the bytes of POS data held in memory (see [ProvingOptions::memory_budget]).
+#[cfg(feature = "parallel")]
+struct MemoryBudget {
+    limit: u64,
+    used: Mutex<u64>,
+    released: std::sync::Condvar,
+}
+
+#[cfg(feature = "parallel")]
+impl MemoryBudget {
+    fn new(limit: u64) -> Arc<Self> {
+        Arc::new(Self {
+            limit,
+            used: Mutex::new(0),
+            released: std::sync::Condvar::new(),
+        })
+    }
+
+    /// Block until `bytes` fit in the budget and take them.
+    ///
+    /// A single batch bigger than the whole budget is let through when nothing else
+    /// is held, so that proving always makes progress.
+    fn acquire(self: &Arc<Self>, bytes: u64) -> MemoryPermit {
+        let mut used = self.used.lock().unwrap();
+        while *used > 0 && *used + bytes > self.limit {
+            used = self.released.wait(used).unwrap();
+        }
+        *used += bytes;
+        MemoryPermit {
+            budget: self.clone(),
+            bytes,
+        }
     }
 }
 
+/// Bytes taken from a [MemoryBudget], given back on drop.
+#[cfg(feature = "parallel")]
+struct MemoryPermit {
+    budget: Arc<MemoryBudget>,
+    bytes: u64,
+}
+
+#[cfg(feature = "parallel")]
+impl MemoryPermit {
+    /// Give back the bytes above `bytes` (i.e. a batch read smaller than expected).
+    fn shrink(&mut self, bytes: u64) {
+        if bytes < self.bytes {
+            *self.budget.used.lock().unwrap() -= self.bytes - bytes;
+            self.bytes = bytes;
+            self.budget.released.notify_all();
+        }
+    }
+}
+
+#[cfg(feature = "parallel")]
+impl Drop for MemoryPermit {
+    fn drop(&mut self) {
+        *self.budget.used.lock().unwrap() -= self.bytes;
+        self.budget.released.notify_all();
+    }
+}
+
+/// Create the pool of proving threads.
+///
+/// With [config::Cores::Pin], the thread with index `i` (see [rayon::current_thread_index])
+/// is pinned to the `i`-th core, calling `on_affinity_set_error` with the core if pinning fails.
+#[cfg(feature = "parallel")]
 pub fn create_thread_pool<F>(
     cores: config::Cores,
     on_affinity_set_error: F,
@@ -409,7 +1200,7 @@ where
     match cores {
         config::Cores::All => pool_builder.build(),
         config::Cores::Any(n) => pool_builder.num_threads(n).build(),
-        config::Cores::Pin(mut cores) => pool_builder
+        config::Cores::Pin(cores) => pool_builder
             .num_threads(cores.len())
             .spawn_handler(|thread| {
                 let mut b = std::thread::Builder::new();
@@ -419,7 +1210,7 @@ where
                 if let Some(stack_size) = thread.stack_size() {
                     b = b.stack_size(stack_size);
                 }
-                let id = cores.pop();
+                let id = cores.get(thread.index()).copied();
                 let on_fail = on_fail.clone();
                 b.spawn(move || {
                     if let Some(id) = id {
@@ -458,6 +1249,29 @@ mod tests {
         );
     }
 
+    #[test]
+    fn saving_proof_with_metadata() {
+        let tmp = tempfile::tempdir().unwrap();
+        let path = tmp.path().join("proof.json");
+        let proof = ProofWithMetadata {
+            proof: Proof::new(7, &[1, 2, 3], 9, 77),
+            metadata: ProofMetadata {
+                node_id: [1; 32],
+                commitment_atx_id: [2; 32],
+                challenge: [3; 32],
+                num_units: 4,
+            },
+        };
+        proof.save(&path).unwrap();
+        assert_eq!(proof, ProofWithMetadata::load(&path).unwrap());
+
+        // The body of a certify request
+        let json: serde_json::Value =
+            serde_json::from_slice(&std::fs::read(&path).unwrap()).unwrap();
+        assert_eq!(7, json["proof"]["nonce"]);
+        assert_eq!(4, json["metadata"]["num_units"]);
+    }
+
     #[test]
     fn creating_prover() {
         let meta = PostMetadata {
@@ -491,7 +1305,93 @@ mod tests {
         assert!(Prover8_56::new(&[0; 32], 16..32, params, &pow_prover, &meta.node_id).is_ok());
 
         assert!(Prover8_56::new(&[0; 32], 0..0, params, &pow_prover, &meta.node_id).is_err());
-        assert!(Prover8_56::new(&[0; 32], 1..16, params, &pow_prover, &meta.node_id).is_err());
+
+        pow_prover
+            .expect_prove()
+            .with(eq(0), eq([0; 8]), eq(cfg.pow_difficulty), always())
+            .once()
+            .returning(|_, _, _, _| Ok(0));
+        assert!(Prover8_56::new(&[0; 32], 1..15, params, &pow_prover, &meta.node_id).is_ok());
+    }
+
+    #[test]
+    fn building_proving_params() {
+        let meta = PostMetadata {
+            labels_per_unit: 1000,
+            num_units: 2,
+            ..Default::default()
+        };
+        let cfg = ProofConfig {
+            k1: 279,
+            k2: 300,
+            pow_difficulty: [0xFF; 32],
+        };
+        let params = ProvingParams::builder()
+            .metadata(&meta)
+            .config(cfg)
+            .build()
+            .unwrap();
+        let expected = ProvingParams::new(&meta, &cfg).unwrap();
+        assert_eq!(expected.difficulty, params.difficulty);
+        assert_eq!(expected.pow_difficulty, params.pow_difficulty);
+
+        let builder = ProvingParams::builder().labels_per_unit(100).config(cfg);
+        assert!(matches!(
+            builder.clone().build(),
+            Err(ConfigError::Missing("num_units"))
+        ));
+        assert!(matches!(
+            builder.clone().num_units(0).build(),
+            Err(ConfigError::ZeroNumUnits)
+        ));
+        assert!(matches!(
+            builder.clone().num_units(2).build(),
+            Err(ConfigError::TooFewLabels {
+                num_labels: 200,
+                k1: 279
+            })
+        ));
+        assert!(matches!(
+            builder
+                .num_units(10)
+                .config(ProofConfig { k2: 200, ..cfg })
+                .build(),
+            Err(ConfigError::K2NotAboveK1 { .. })
+        ));
+    }
+
+    #[test]
+    fn k2pows_are_reported() {
+        let params = ProvingParams {
+            difficulty: u64::MAX,
+            pow_difficulty: [0xFF; 32],
+        };
+        let mut pow_prover = pow::MockProver::new();
+        pow_prover.expect_par().return_const(false);
+        pow_prover
+            .expect_prove()
+            .times(4)
+            .returning(|group, _, _, _| Ok(group as u64 + 100));
+
+        let found = Mutex::new(Vec::new());
+        let prover = Prover8_56::new_with_progress(
+            &[0; 32],
+            0..64,
+            params,
+            &pow_prover,
+            &[7; 32],
+            &|group, pow| found.lock().unwrap().push((group, pow)),
+        )
+        .unwrap();
+
+        // The ciphers are in the order of nonce groups.
+        for group in 0..4 {
+            assert_eq!(Some(group as u64 + 100), prover.get_pow(group * 16));
+        }
+        assert_eq!(
+            vec![(0, 100), (1, 101), (2, 102), (3, 103)],
+            found.into_inner().unwrap()
+        );
     }
 
     #[test]
@@ -586,6 +1486,40 @@ mod tests {
         );
     }
 
+    #[test]
+    fn proving_unaligned_nonces() {
+        let challenge = b"hello world, challenge me!!!!!!!";
+        let params = ProvingParams {
+            difficulty: u64::MAX,
+            pow_difficulty: [0xFF; 32],
+        };
+        let mut pow_prover = pow::MockProver::new();
+        pow_prover.expect_par().returning(|| false);
+        pow_prover
+            .expect_prove()
+            .returning(|group, _, _, _| Ok(group as u64 + 100));
+
+        let prover = Prover8_56::new(challenge, 5..21, params, &pow_prover, &[7; 32]).unwrap();
+        let mut found = Vec::new();
+        let res = prover.prove(&[0u8; 8 * LABEL_SIZE], 0, |nonce, index| {
+            found.push((nonce, index));
+            None
+        });
+        assert!(res.is_none());
+        found.sort();
+        assert_eq!(
+            (5..21)
+                .flat_map(|nonce| (0..8).map(move |index| (nonce, index)))
+                .collect::<Vec<_>>(),
+            found
+        );
+
+        assert_eq!(None, prover.get_pow(4));
+        assert_eq!(Some(100), prover.get_pow(5));
+        assert_eq!(Some(101), prover.get_pow(20));
+        assert_eq!(None, prover.get_pow(21));
+    }
+
     #[test]
     /// Test if indicies in a proof are distributed more less uniformly across the whole input range.
     fn indicies_distribution() {
@@ -726,6 +1660,24 @@ mod tests {
         assert_eq!(2..3, nonce_group_range(47..48, 16));
     }
 
+    #[test]
+    fn chance_of_proof_in_rest_of_data() {
+        let cfg = ProofConfig {
+            k1: 26,
+            k2: 37,
+            pow_difficulty: [0xFF; 32],
+        };
+        // Nothing read yet, some of the many nonces are likely to find a proof
+        assert_eq!(1.0, chance_of_proof(&cfg, 1.0, 128, &[]));
+        // Nothing left to read
+        assert_eq!(0.0, chance_of_proof(&cfg, 0.0, 128, &[20, 30, 36]));
+        // 10% left to read, mean 2.6 indices per nonce
+        let hopeless = chance_of_proof(&cfg, 0.1, 128, &[20, 25, 22]);
+        assert!(hopeless < 1e-4, "{hopeless}");
+        let one_missing = chance_of_proof(&cfg, 0.1, 128, &[36]);
+        assert!((one_missing - (1.0 - (-2.6f64).exp())).abs() < 1e-3);
+    }
+
     #[test]
     fn nonce_group_for_nonce() {
         assert_eq!(0, calc_nonce_group(0, 16));
@@ -763,4 +1715,80 @@ mod tests {
         pool.install(|| {});
         assert!(failed.load(Ordering::Relaxed));
     }
+
+    #[test]
+    fn pausing() {
+        let pause = Pause::default();
+        let stop = AtomicBool::new(false);
+        // Not paused - doesn't block
+        pause.wait(&stop);
+
+        pause.set(true);
+        assert!(pause.is_paused());
+        std::thread::scope(|s| {
+            let waiter = s.spawn(|| pause.wait(&stop));
+            std::thread::sleep(std::time::Duration::from_millis(50));
+            assert!(!waiter.is_finished());
+            pause.set(false);
+            waiter.join().unwrap();
+        });
+
+        // Stopping wakes up a paused proof generation
+        pause.set(true);
+        stop.store(true, Ordering::Relaxed);
+        pause.wait(&stop);
+    }
+
+    #[test]
+    fn carrying_pow_to_next_pass() {
+        let mut pow_prover = pow::MockProver::new();
+        pow_prover.expect_par().returning(|| false);
+        // Only the nonce group not proven in the previous pass
+        pow_prover
+            .expect_prove()
+            .with(eq(2), always(), always(), always())
+            .once()
+            .returning(|_, _, _, _| Ok(102));
+        pow_prover
+            .expect_prove_many()
+            .with(eq(2..3), always(), always(), always())
+            .once()
+            .returning(|_, _, _, _| Ok(vec![(2, 102)]));
+        let carried = CarriedPow {
+            inner: &pow_prover,
+            carried: Some((1, 101)),
+        };
+
+        let params = ProvingParams {
+            difficulty: u64::MAX,
+            pow_difficulty: [0xFF; 32],
+        };
+        let prover = Prover8_56::new(&[0; 32], 20..40, params, &carried, &[0; 32]).unwrap();
+        assert_eq!(Some(101), prover.get_pow(20));
+        assert_eq!(Some(102), prover.get_pow(39));
+        assert_eq!(
+            vec![(1, 101), (2, 102)],
+            pow::Prover::prove_many(&carried, 1..3, &[0; 8], &[0xFF; 32], &[0; 32]).unwrap()
+        );
+    }
+
+    #[test]
+    fn memory_budget() {
+        let budget = MemoryBudget::new(100);
+        let held = budget.acquire(60);
+        std::thread::scope(|s| {
+            let waiter = s.spawn(|| budget.acquire(60));
+            std::thread::sleep(std::time::Duration::from_millis(50));
+            assert!(!waiter.is_finished());
+            drop(held);
+            waiter.join().unwrap();
+        });
+
+        // A batch bigger than the budget gets through when nothing else is held
+        let mut held = budget.acquire(200);
+        // Shrinking gives the rest back
+        held.shrink(40);
+        let _other = budget.acquire(60);
+        assert_eq!(100, *budget.used.lock().unwrap());
+    }
 }
@@ -1,3 +1,25 @@
+//! Deterministic selection of random, distinct positions.
+//!
+//! Used by the verifier to pick the subset of proof indices to check
+//! ([Mode::Subset](crate::verification::Mode::Subset)). Other implementations
+//! must select exactly the same positions, the algorithm is:
+//!
+//! 1. Hash the concatenated seed parts with blake3 and read its output as
+//!    an infinite stream (XOF).
+//! 2. Run a Fisher-Yates shuffle of `0..len`. For the `i`-th position:
+//!    - `remaining = len - i`,
+//!    - read the next 2 bytes of the stream as a little-endian `u16` (`rand`),
+//!      until `rand < u16::MAX - u16::MAX % remaining` (rejecting the values
+//!      that would make the selection biased),
+//!    - swap the values at positions `i` and `i + rand % remaining`,
+//!    - yield the value at position `i`.
+//!
+//! The verifier seeds it with `[seed, node_id, challenge]` of the proof and takes
+//! the first `k3` values. Known-answer vectors are in the tests of this module
+//! (and in [test_vectors](crate::test_vectors) with the `test-vectors` feature).
+
+use std::collections::HashMap;
+
 #[derive(Debug, Clone)]
 struct Blake3Rng(blake3::OutputReader);
 
@@ -17,29 +39,52 @@ impl Blake3Rng {
     }
 }
 
-/// Picks random items from the provided Vec.
-pub(crate) struct RandomValuesIterator<T> {
-    // data shuffled in-place
-    data: Vec<T>,
+/// Picks random, distinct positions out of `0..len`.
+///
+/// It's a lazy Fisher-Yates shuffle of `0..len`. Only the swapped positions are stored,
+/// so taking `k` positions needs `O(k)` memory regardless of `len`.
+///
+/// ```
+/// use post::random_values_gen::RandomValuesIterator;
+///
+/// let picked: Vec<usize> = RandomValuesIterator::new(100, &[b"seed"]).take(3).collect();
+/// assert_eq!(3, picked.len());
+/// assert!(picked.iter().all(|&v| v < 100));
+/// ```
+pub struct RandomValuesIterator {
+    len: usize,
+    // positions moved by the shuffle (position -> value)
+    swapped: HashMap<usize, usize>,
     rng: Blake3Rng,
     idx: usize,
 }
 
-impl<T> RandomValuesIterator<T> {
-    pub(crate) fn new(data: impl IntoIterator<Item = T>, seed: &[&[u8]]) -> Self {
+impl RandomValuesIterator {
+    /// Create an iterator over positions in `0..len`, shuffled with
+    /// a blake3 XOF seeded with the concatenated `seed` parts.
+    ///
+    /// # Panics
+    /// If `len` is greater than `u16::MAX`.
+    pub fn new(len: usize, seed: &[&[u8]]) -> Self {
+        assert!(len <= u16::MAX as usize, "too many values: {len}");
         Self {
+            len,
+            swapped: HashMap::new(),
             idx: 0,
-            data: data.into_iter().collect(),
             rng: Blake3Rng::from_seed(seed),
         }
     }
+
+    fn value_at(&self, position: usize) -> usize {
+        *self.swapped.get(&position).unwrap_or(&position)
+    }
 }
 
-impl<T: Copy> Iterator for RandomValuesIterator<T> {
-    type Item = T;
+impl Iterator for RandomValuesIterator {
+    type Item = usize;
 
     fn next(&mut self) -> Option<Self::Item> {
-        let remaining = self.data.len() - self.idx;
+        let remaining = self.len - self.idx;
         if remaining == 0 {
             return None;
         }
@@ -47,9 +92,11 @@ impl<T: Copy> Iterator for RandomValuesIterator<T> {
         loop {
             let rand_num = self.rng.next_u16();
             if rand_num < max_allowed {
-                self.data
-                    .swap(self.idx, (rand_num as usize % remaining) + self.idx);
-                let value = self.data[self.idx];
+                let to_swap = (rand_num as usize % remaining) + self.idx;
+                let value = self.value_at(to_swap);
+                let current = self.value_at(self.idx);
+                self.swapped.insert(to_swap, current);
+                self.swapped.remove(&self.idx);
                 self.idx += 1;
                 return Some(value);
             }
@@ -69,7 +116,7 @@ mod tests {
     #[test]
     fn gives_each_value_once() {
         let k2 = 1000;
-        let iter = RandomValuesIterator::new(0..k2, &[]);
+        let iter = RandomValuesIterator::new(k2, &[]);
         let mut occurences = HashSet::new();
         for item in iter {
             assert!(occurences.insert(item));
@@ -87,10 +134,46 @@ mod tests {
             65, 31, 4, 52, 3, 56, 21, 8, 28, 66, 47,
         ];
 
-        let iter = RandomValuesIterator::new(0..expected.len(), &[]);
+        let iter = RandomValuesIterator::new(expected.len(), &[]);
         assert_eq!(&expected, iter.collect_vec().as_slice());
     }
 
+    /// Known answers for seeds laid out like in the verifier (`[seed, node_id, challenge]`).
+    #[test]
+    fn known_answers() {
+        let node_id = [0xBE; 32];
+        let challenge = [0xCA; 32];
+        let vectors: [(usize, &[u8], &[usize]); 5] = [
+            (1, b"", &[0]),
+            (3, b"post", &[0, 1, 2]),
+            (37, &[1, 2, 3], &[24, 1, 36, 35, 28, 2, 9, 32, 25, 33]),
+            (1000, b"", &[746, 361, 144, 633, 767, 101, 560, 681]),
+            (
+                u16::MAX as usize,
+                b"max",
+                &[37029, 40717, 55383, 35642, 25836],
+            ),
+        ];
+        for (len, seed, expected) in vectors {
+            let picked = RandomValuesIterator::new(len, &[seed, &node_id, &challenge])
+                .take(expected.len())
+                .collect_vec();
+            assert_eq!(expected, picked, "len: {len}, seed: {seed:?}");
+        }
+    }
+
+    /// The seed parts are concatenated, not hashed separately.
+    #[test]
+    fn seed_parts_are_concatenated() {
+        let whole = RandomValuesIterator::new(100, &[b"abcd"])
+            .take(10)
+            .collect_vec();
+        let parts = RandomValuesIterator::new(100, &[b"ab", b"", b"cd"])
+            .take(10)
+            .collect_vec();
+        assert_eq!(whole, parts);
+    }
+
     #[test]
     fn distribution_is_uniform() {
         let data_set = (0..200).collect_vec();
@@ -102,8 +185,7 @@ mod tests {
         let n = 50;
         let iterations = 2_000_000;
         (0u64..iterations).into_par_iter().for_each(|seed| {
-            for value in RandomValuesIterator::new(data_set.clone(), &[&seed.to_le_bytes()]).take(n)
-            {
+            for value in RandomValuesIterator::new(data_set.len(), &[&seed.to_le_bytes()]).take(n) {
                 occurences[value].fetch_add(1, Ordering::Release);
             }
         });
@@ -1,7 +1,11 @@
 use std::{
+    fmt::Debug,
     fs::{DirEntry, File},
-    io::Read,
+    io::{Read, Seek, SeekFrom},
+    num::NonZeroU64,
     path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
 };
 
 use eyre::Context;
@@ -14,24 +18,164 @@ pub struct Batch {
     pub pos: u64,
 }
 
+/// A source of POS data.
+///
+/// Implement it to prove from a custom storage layout (i.e. raw block devices or network storage).
+pub trait PosDataSource: Debug + Send + Sync {
+    /// Read `len` bytes of POS data starting at `offset`, in batches of (at most) `batch_size` bytes.
+    ///
+    /// The batches must be ordered by their position. Reading stops
+    /// early if there is less data available. A failed read ends the iterator with the error.
+    fn read(
+        &self,
+        offset: u64,
+        len: u64,
+        batch_size: usize,
+    ) -> eyre::Result<Box<dyn Iterator<Item = std::io::Result<Batch>> + Send>>;
+}
+
+/// A POS data file of a [PosFileSource].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PosFile {
+    /// The name of the file (`postdata_N.bin`).
+    pub name: String,
+    /// The size of the file, `None` if it could not be determined.
+    pub size: Option<u64>,
+}
+
+/// A source of POS data split into files (`postdata_N.bin`).
+/// Use with [PosFiles] to read from it.
+pub trait PosFileSource: Debug + Send + Sync {
+    /// List POS data files, ordered by their number.
+    fn files(&self) -> eyre::Result<Vec<PosFile>>;
+
+    /// Open the file for reading from `offset`.
+    fn open(&self, file: &PosFile, offset: u64) -> std::io::Result<Box<dyn Read + Send>>;
+}
+
+/// POS data files in a local directory.
+#[derive(Debug, Clone)]
+pub struct LocalDir(pub PathBuf);
+
+impl PosFileSource for LocalDir {
+    fn files(&self) -> eyre::Result<Vec<PosFile>> {
+        let files = pos_files(&self.0)?
+            .map(|entry| {
+                let size = entry
+                    .metadata()
+                    .inspect_err(|_| {
+                        log::warn!(
+                            "could not read file metadata for {}",
+                            entry.path().display()
+                        )
+                    })
+                    .ok()
+                    .map(|m| m.len());
+                PosFile {
+                    name: entry.file_name().to_string_lossy().into_owned(),
+                    size,
+                }
+            })
+            .collect();
+        Ok(files)
+    }
+
+    fn open(&self, file: &PosFile, offset: u64) -> std::io::Result<Box<dyn Read + Send>> {
+        let path = self.0.join(&file.name);
+        log::info!("Reading file: {}", path.display());
+        let mut file = File::open(path)?;
+        if offset > 0 {
+            file.seek(SeekFrom::Start(offset))?;
+        }
+        Ok(Box::new(file))
+    }
+}
+
+/// Reads POS data from files of `file_size` bytes laid out one after another.
+#[derive(Debug)]
+pub struct PosFiles {
+    source: Arc<dyn PosFileSource>,
+    file_size: u64,
+}
+
+impl PosFiles {
+    pub fn new(source: impl PosFileSource + 'static, file_size: u64) -> Self {
+        Self {
+            source: Arc::new(source),
+            file_size,
+        }
+    }
+}
+
+impl PosDataSource for PosFiles {
+    fn read(
+        &self,
+        offset: u64,
+        len: u64,
+        batch_size: usize,
+    ) -> eyre::Result<Box<dyn Iterator<Item = std::io::Result<Batch>> + Send>> {
+        let end = offset.saturating_add(len);
+        let mut readers = Vec::<BatchingReader<LazyFile>>::new();
+        let mut files = self.source.files()?.into_iter().enumerate().peekable();
+
+        while let Some((id, file)) = files.next() {
+            let file_start = id as u64 * self.file_size;
+
+            let Some(size) = file.size else {
+                continue;
+            };
+
+            // If there are more files, check if the size of the file is correct
+            if files.peek().is_some() && size != self.file_size {
+                log::warn!(
+                    "invalid POS file size {}, expected: {} vs actual: {size}",
+                    file.name,
+                    self.file_size,
+                );
+            }
+
+            let start = offset.max(file_start);
+            let stop = end.min(file_start + self.file_size);
+            if start >= stop {
+                continue;
+            }
+            readers.push(BatchingReader::new(
+                LazyFile::new(self.source.clone(), file, start - file_start),
+                start,
+                batch_size,
+                stop - start,
+            ));
+        }
+
+        Ok(Box::new(readers.into_iter().flatten()))
+    }
+}
+
+/// Opens the file of a data source on the first read.
 struct LazyFile {
-    path: PathBuf,
-    file: Option<File>,
+    source: Arc<dyn PosFileSource>,
+    file: PosFile,
+    offset: u64,
+    reader: Option<Box<dyn Read + Send>>,
 }
 
 impl LazyFile {
-    pub fn new(path: PathBuf) -> LazyFile {
-        LazyFile { path, file: None }
+    pub fn new(source: Arc<dyn PosFileSource>, file: PosFile, offset: u64) -> LazyFile {
+        LazyFile {
+            source,
+            file,
+            offset,
+            reader: None,
+        }
     }
 }
 
 impl Read for LazyFile {
     fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
-        if self.file.is_none() {
-            log::info!("Reading file: {}", self.path.display());
-            self.file = Some(File::open(&self.path)?);
+        if self.reader.is_none() {
+            self.reader = Some(self.source.open(&self.file, self.offset)?);
         }
-        self.file.as_mut().unwrap().read(buf)
+        self.reader.as_mut().unwrap().read(buf)
     }
 }
 
@@ -44,6 +188,7 @@ where
     pos: u64,
     batch_size: usize,
     total_size: u64,
+    failed: bool,
 }
 
 impl<T: Read> BatchingReader<T> {
@@ -54,17 +199,18 @@ impl<T: Read> BatchingReader<T> {
             pos,
             batch_size,
             total_size,
+            failed: false,
         }
     }
 }
 
 impl<T: Read> Iterator for BatchingReader<T> {
-    type Item = Batch;
+    type Item = std::io::Result<Batch>;
 
     fn next(&mut self) -> Option<Self::Item> {
         // FIXME(poszu) avoid reallocating the vector
         let pos_in_file = self.pos - self.starting_pos;
-        if pos_in_file >= self.total_size {
+        if self.failed || pos_in_file >= self.total_size {
             return None;
         }
         let remaining = self.total_size - pos_in_file;
@@ -83,14 +229,361 @@ impl<T: Read> Iterator for BatchingReader<T> {
                     pos: self.pos,
                 };
                 self.pos += n as u64;
-                Some(batch)
+                Some(Ok(batch))
+            }
+            Err(e) => {
+                self.failed = true;
+                Some(Err(e))
             }
-            Err(_) => None,
         }
     }
 }
 
-pub(crate) fn pos_files(datadir: &Path) -> eyre::Result<impl Iterator<Item = DirEntry>> {
+/// Retrying failed reads of POS data.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ReadRetry {
+    /// How many times to retry a failed read before giving up.
+    pub retries: u32,
+    /// Time to wait before the first retry, doubled for every next one.
+    pub backoff: Duration,
+}
+
+impl Default for ReadRetry {
+    fn default() -> Self {
+        Self {
+            retries: 0,
+            backoff: Duration::from_secs(1),
+        }
+    }
+}
+
+/// Reading POS data failed even after retrying.
+#[derive(Debug, thiserror::Error)]
+#[error("reading POS data at {pos} failed (attempts: {attempts})")]
+pub struct ReadError {
+    /// The position of the data that could not be read.
+    pub pos: u64,
+    pub attempts: u32,
+    #[source]
+    pub source: std::io::Error,
+}
+
+/// Read `len` bytes of POS data from `source` starting at `offset`, like [PosDataSource::read],
+/// but retry failed reads according to `retry`.
+///
+/// A failed read is retried by reading the rest of the data again from the position
+/// where it failed (reopening the files), which helps with transient errors of network
+/// or USB storage. The iterator ends with a [ReadError] when the retries are exhausted.
+pub fn read_with_retry(
+    source: Arc<dyn PosDataSource>,
+    offset: u64,
+    len: u64,
+    batch_size: usize,
+    retry: ReadRetry,
+) -> impl Iterator<Item = Result<Batch, ReadError>> + Send {
+    RetryingReader {
+        source,
+        pos: offset,
+        end: offset.saturating_add(len),
+        batch_size,
+        retry,
+        batches: None,
+        failures: 0,
+        done: false,
+    }
+}
+
+struct RetryingReader {
+    source: Arc<dyn PosDataSource>,
+    pos: u64,
+    end: u64,
+    batch_size: usize,
+    retry: ReadRetry,
+    batches: Option<Box<dyn Iterator<Item = std::io::Result<Batch>> + Send>>,
+    // Number of failed attempts since the last successful read.
+    failures: u32,
+    done: bool,
+}
+
+impl RetryingReader {
+    fn read_next(&mut self) -> Option<std::io::Result<Batch>> {
+        let batches = match &mut self.batches {
+            Some(batches) => batches,
+            None => {
+                let batches = self
+                    .source
+                    .read(self.pos, self.end - self.pos, self.batch_size)
+                    .map_err(|e| {
+                        // Keep the kind of IO errors (i.e. NotFound).
+                        e.downcast::<std::io::Error>()
+                            .unwrap_or_else(std::io::Error::other)
+                    });
+                self.batches.insert(match batches {
+                    Ok(batches) => batches,
+                    Err(e) => return Some(Err(e)),
+                })
+            }
+        };
+        batches.next()
+    }
+}
+
+impl Iterator for RetryingReader {
+    type Item = Result<Batch, ReadError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while !self.done {
+            match self.read_next() {
+                Some(Ok(batch)) => {
+                    self.pos = batch.pos + batch.data.len() as u64;
+                    self.failures = 0;
+                    return Some(Ok(batch));
+                }
+                Some(Err(source)) => {
+                    self.batches = None;
+                    self.failures += 1;
+                    if self.failures > self.retry.retries {
+                        self.done = true;
+                        return Some(Err(ReadError {
+                            pos: self.pos,
+                            attempts: self.failures,
+                            source,
+                        }));
+                    }
+                    let backoff = self
+                        .retry
+                        .backoff
+                        .saturating_mul(2u32.saturating_pow(self.failures - 1));
+                    log::warn!(
+                        "reading POS data at {} failed: {source}, retrying in {backoff:?}",
+                        self.pos
+                    );
+                    std::thread::sleep(backoff);
+                }
+                None => self.done = true,
+            }
+        }
+        None
+    }
+}
+
+/// Size of the batches of POS data read for proving.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub enum BatchSize {
+    /// Always the same size (in bytes).
+    Fixed(usize),
+    /// Tuned while reading, between `min` and `max` bytes (rounded up to powers of two).
+    ///
+    /// The data is read in segments of [BatchSize::TUNING_BATCHES] batches. After each segment
+    /// the size is doubled or halved, whichever improved the read throughput, and doubled
+    /// when the read latency varies a lot (i.e. seeking HDDs or network storage).
+    Auto { min: usize, max: usize },
+}
+
+impl BatchSize {
+    /// Number of batches read with the same size before tuning it.
+    pub const TUNING_BATCHES: usize = 16;
+
+    /// The biggest size of a batch.
+    pub fn max(&self) -> usize {
+        match *self {
+            BatchSize::Fixed(size) => size,
+            BatchSize::Auto { max, .. } => max.next_power_of_two(),
+        }
+    }
+}
+
+impl Default for BatchSize {
+    fn default() -> Self {
+        BatchSize::Fixed(1024 * 1024)
+    }
+}
+
+/// Read `len` bytes of POS data like [read_with_retry], in batches of the given size.
+pub fn read_batches(
+    source: Arc<dyn PosDataSource>,
+    offset: u64,
+    len: u64,
+    batch_size: BatchSize,
+    retry: ReadRetry,
+) -> Batches {
+    match batch_size {
+        BatchSize::Fixed(size) => Box::new(read_with_retry(source, offset, len, size, retry)),
+        BatchSize::Auto { min, max } => Box::new(TunedReader {
+            source,
+            pos: offset,
+            end: offset.saturating_add(len),
+            retry,
+            tuner: BatchTuner::new(min, max),
+            segment: None,
+        }),
+    }
+}
+
+type Batches = Box<dyn Iterator<Item = Result<Batch, ReadError>> + Send>;
+
+/// Reads segments of data with the batch size chosen by [BatchTuner].
+struct TunedReader {
+    source: Arc<dyn PosDataSource>,
+    pos: u64,
+    end: u64,
+    retry: ReadRetry,
+    tuner: BatchTuner,
+    // The batches of the current segment and its end.
+    segment: Option<(Batches, u64)>,
+}
+
+impl Iterator for TunedReader {
+    type Item = Result<Batch, ReadError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let (batches, segment_end) = match &mut self.segment {
+                Some(segment) => segment,
+                None if self.pos >= self.end => return None,
+                None => {
+                    let size = self.tuner.size;
+                    let len =
+                        (size as u64 * BatchSize::TUNING_BATCHES as u64).min(self.end - self.pos);
+                    let batches =
+                        read_with_retry(self.source.clone(), self.pos, len, size, self.retry);
+                    self.segment.insert((Box::new(batches), self.pos + len))
+                }
+            };
+            let started = Instant::now();
+            match batches.next() {
+                Some(Ok(batch)) => {
+                    self.tuner.record(batch.data.len(), started.elapsed());
+                    return Some(Ok(batch));
+                }
+                Some(Err(e)) => {
+                    self.pos = self.end;
+                    self.segment = None;
+                    return Some(Err(e));
+                }
+                None => {
+                    self.pos = *segment_end;
+                    self.segment = None;
+                    self.tuner.tune();
+                }
+            }
+        }
+    }
+}
+
+/// Tunes the batch size by hill climbing on the read throughput.
+#[derive(Debug)]
+struct BatchTuner {
+    size: usize,
+    min: usize,
+    max: usize,
+    grow: bool,
+    // Read latencies of the batches of the current segment.
+    latencies: Vec<Duration>,
+    bytes: usize,
+    // Throughput of the previous segment (bytes per second).
+    last_throughput: Option<f64>,
+}
+
+impl BatchTuner {
+    /// Latency varying more than this (the coefficient of variation) calls for bigger batches.
+    const HIGH_VARIANCE: f64 = 0.5;
+
+    fn new(min: usize, max: usize) -> Self {
+        let min = min.max(1).next_power_of_two();
+        let max = max.next_power_of_two().max(min);
+        let size = BatchSize::default().max().clamp(min, max);
+        Self {
+            size,
+            min,
+            max,
+            grow: true,
+            latencies: Vec::new(),
+            bytes: 0,
+            last_throughput: None,
+        }
+    }
+
+    fn record(&mut self, bytes: usize, latency: Duration) {
+        self.bytes += bytes;
+        self.latencies.push(latency);
+    }
+
+    /// Choose the batch size of the next segment.
+    fn tune(&mut self) {
+        let latencies = std::mem::take(&mut self.latencies);
+        let bytes = std::mem::take(&mut self.bytes);
+        let total = latencies.iter().sum::<Duration>().as_secs_f64();
+        if latencies.len() < 2 || total == 0.0 {
+            return;
+        }
+        let throughput = bytes as f64 / total;
+        let mean = total / latencies.len() as f64;
+        let variance = latencies
+            .iter()
+            .map(|l| (l.as_secs_f64() - mean).powi(2))
+            .sum::<f64>()
+            / latencies.len() as f64;
+
+        if variance.sqrt() / mean > Self::HIGH_VARIANCE {
+            self.grow = true;
+        } else if self.last_throughput.is_some_and(|last| throughput < last) {
+            self.grow = !self.grow;
+        }
+        self.last_throughput = Some(throughput);
+
+        let size = match self.grow {
+            true => self.size.saturating_mul(2),
+            false => self.size / 2,
+        }
+        .clamp(self.min, self.max);
+        if size != self.size {
+            log::debug!(
+                "read {:.1} MiB/s in batches of {} KiB, changing the batch size to {} KiB",
+                throughput / (1024.0 * 1024.0),
+                self.size / 1024,
+                size / 1024
+            );
+            self.size = size;
+        }
+    }
+}
+
+/// Limits the rate of reading (or writing) POS data.
+///
+/// It can be shared by several readers, the limit then applies to all of them together.
+#[derive(Debug)]
+pub struct RateLimiter {
+    bytes_per_sec: NonZeroU64,
+    // The time when the next read can start without exceeding the rate.
+    next: Mutex<Instant>,
+}
+
+impl RateLimiter {
+    pub fn new(bytes_per_sec: NonZeroU64) -> Self {
+        Self {
+            bytes_per_sec,
+            next: Mutex::new(Instant::now()),
+        }
+    }
+
+    /// Wait until `bytes` can be read (or written) without exceeding the rate.
+    pub fn acquire(&self, bytes: u64) {
+        let now = Instant::now();
+        let start = {
+            let mut next = self.next.lock().unwrap();
+            let start = (*next).max(now);
+            *next = start + Duration::from_secs_f64(bytes as f64 / self.bytes_per_sec.get() as f64);
+            start
+        };
+        if start > now {
+            std::thread::sleep(start - now);
+        }
+    }
+}
+
+/// List POS data files (`postdata_N.bin`) in `datadir`, ordered by their number.
+pub fn pos_files(datadir: &Path) -> eyre::Result<impl Iterator<Item = DirEntry>> {
     let file_re = Regex::new(r"^postdata_(\d+)\.bin$").unwrap();
     let files = datadir
         .read_dir()
@@ -111,54 +604,47 @@ pub(crate) fn pos_files(datadir: &Path) -> eyre::Result<impl Iterator<Item = Dir
     Ok(files)
 }
 
-pub(crate) fn read_data(
-    datadir: &Path,
-    batch_size: usize,
-    file_size: u64,
-) -> eyre::Result<impl Iterator<Item = Batch>> {
-    let mut readers = Vec::<BatchingReader<LazyFile>>::new();
-    let mut files = pos_files(datadir)?.enumerate().peekable();
-
-    while let Some((id, entry)) = files.next() {
-        let pos = id as u64 * file_size;
-
-        // check the size of file at path
-        let Ok(metadata) = entry.metadata() else {
-            log::warn!(
-                "could not read file metadata for {}",
-                entry.path().display()
-            );
-            continue;
-        };
-
-        // If there are more files, check if the size of the file is correct
-        if files.peek().is_some() && metadata.len() != file_size {
-            log::warn!(
-                "invalid POS file size {}, expected: {file_size} vs actual: {}",
-                entry.path().display(),
-                metadata.len(),
-            );
-        }
-
-        readers.push(BatchingReader::new(
-            LazyFile::new(entry.path()),
-            pos,
-            batch_size,
-            file_size,
-        ));
-    }
-
-    Ok(readers.into_iter().flatten())
-}
-
 #[cfg(test)]
 mod tests {
-    use std::io::Write;
-    use std::{fs::File, io::Cursor};
+    use std::{
+        io::{Cursor, Write},
+        sync::atomic::{AtomicU32, Ordering},
+    };
 
     use tempfile::tempdir;
 
-    use super::{pos_files, read_data, Batch, BatchingReader};
+    use super::*;
+
+    fn read_data(
+        datadir: &Path,
+        batch_size: usize,
+        file_size: u64,
+    ) -> eyre::Result<impl Iterator<Item = Batch>> {
+        Ok(PosFiles::new(LocalDir(datadir.to_path_buf()), file_size)
+            .read(0, u64::MAX, batch_size)?
+            .map(Result::unwrap))
+    }
+
+    #[test]
+    fn rate_limiter() {
+        let limiter = RateLimiter::new(1000.try_into().unwrap());
+        let start = std::time::Instant::now();
+        std::thread::scope(|s| {
+            for _ in 0..4 {
+                s.spawn(|| {
+                    for _ in 0..5 {
+                        limiter.acquire(10);
+                    }
+                });
+            }
+        });
+        // 200 bytes at 1000 B/s, the first read is not delayed.
+        let elapsed = start.elapsed();
+        assert!(
+            elapsed >= std::time::Duration::from_millis(190),
+            "{elapsed:?}"
+        );
+    }
 
     #[test]
     fn batching_reader() {
@@ -166,27 +652,27 @@ mod tests {
         let file = Cursor::new(data);
         let mut reader = BatchingReader::new(file, 0, 16, 40);
         assert_eq!(
-            Some(Batch {
+            Batch {
                 data: (0..16).collect(),
                 pos: 0,
-            }),
-            reader.next()
+            },
+            reader.next().unwrap().unwrap()
         );
         assert_eq!(
-            Some(Batch {
+            Batch {
                 data: (16..32).collect(),
                 pos: 16,
-            }),
-            reader.next()
+            },
+            reader.next().unwrap().unwrap()
         );
         assert_eq!(
-            Some(Batch {
+            Batch {
                 data: (32..40).collect(),
                 pos: 32,
-            }),
-            reader.next()
+            },
+            reader.next().unwrap().unwrap()
         );
-        assert_eq!(None, reader.next());
+        assert!(reader.next().is_none());
     }
 
     #[test]
@@ -211,6 +697,182 @@ mod tests {
         assert_eq!(b"2Hell1Welc", result.as_slice());
     }
 
+    #[test]
+    fn reading_pos_data_range() {
+        let tmp_dir = tempdir().unwrap();
+        let data = (0..30).collect::<Vec<u8>>();
+        for (i, part) in data.chunks(8).enumerate() {
+            std::fs::write(tmp_dir.path().join(format!("postdata_{i}.bin")), part).unwrap();
+        }
+        let source = PosFiles::new(LocalDir(tmp_dir.path().to_path_buf()), 8);
+
+        let batches: Vec<_> = source.read(6, 13, 4).unwrap().map(Result::unwrap).collect();
+        assert_eq!(
+            vec![
+                Batch {
+                    data: vec![6, 7],
+                    pos: 6
+                },
+                Batch {
+                    data: (8..12).collect(),
+                    pos: 8
+                },
+                Batch {
+                    data: (12..16).collect(),
+                    pos: 12
+                },
+                Batch {
+                    data: (16..19).collect(),
+                    pos: 16
+                },
+            ],
+            batches
+        );
+
+        // Reading past the end of data
+        let result: Vec<u8> = source
+            .read(20, 100, 4)
+            .unwrap()
+            .flat_map(|b| b.unwrap().data)
+            .collect();
+        assert_eq!((20..30).collect::<Vec<u8>>(), result);
+    }
+
+    /// POS files in a local directory, failing the first `failures` reads.
+    #[derive(Debug)]
+    struct FlakyDir {
+        dir: LocalDir,
+        failures: Arc<AtomicU32>,
+    }
+
+    struct FlakyFile {
+        file: Box<dyn Read + Send>,
+        failures: Arc<AtomicU32>,
+    }
+
+    impl Read for FlakyFile {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            let fail = self
+                .failures
+                .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |n| n.checked_sub(1))
+                .is_ok();
+            match fail {
+                true => Err(std::io::Error::other("device not ready")),
+                false => self.file.read(buf),
+            }
+        }
+    }
+
+    impl PosFileSource for FlakyDir {
+        fn files(&self) -> eyre::Result<Vec<PosFile>> {
+            self.dir.files()
+        }
+
+        fn open(&self, file: &PosFile, offset: u64) -> std::io::Result<Box<dyn Read + Send>> {
+            Ok(Box::new(FlakyFile {
+                file: self.dir.open(file, offset)?,
+                failures: self.failures.clone(),
+            }))
+        }
+    }
+
+    fn flaky_source(datadir: &Path, failures: u32) -> Arc<dyn PosDataSource> {
+        let source = FlakyDir {
+            dir: LocalDir(datadir.to_path_buf()),
+            failures: Arc::new(AtomicU32::new(failures)),
+        };
+        Arc::new(PosFiles::new(source, 8))
+    }
+
+    #[test]
+    fn read_error_ends_reading() {
+        let tmp_dir = tempdir().unwrap();
+        std::fs::write(tmp_dir.path().join("postdata_0.bin"), [0u8; 8]).unwrap();
+
+        let mut batches = flaky_source(tmp_dir.path(), 1).read(0, 8, 4).unwrap();
+        assert!(batches.next().unwrap().is_err());
+        assert!(batches.next().is_none());
+    }
+
+    #[test]
+    fn retrying_failed_reads() {
+        let tmp_dir = tempdir().unwrap();
+        let data = (0..30).collect::<Vec<u8>>();
+        for (i, part) in data.chunks(8).enumerate() {
+            std::fs::write(tmp_dir.path().join(format!("postdata_{i}.bin")), part).unwrap();
+        }
+        let retry = ReadRetry {
+            retries: 2,
+            backoff: Duration::from_millis(1),
+        };
+
+        // Transient errors
+        let result = read_with_retry(flaky_source(tmp_dir.path(), 2), 2, 100, 4, retry)
+            .map(|b| b.unwrap().data)
+            .concat();
+        assert_eq!(&data[2..], result);
+
+        // Persistent errors
+        let mut batches = read_with_retry(flaky_source(tmp_dir.path(), 3), 2, 100, 4, retry);
+        let err = batches.next().unwrap().unwrap_err();
+        assert_eq!(2, err.pos);
+        assert_eq!(3, err.attempts);
+        assert!(batches.next().is_none());
+    }
+
+    #[test]
+    fn reading_in_tuned_batches() {
+        let tmp_dir = tempdir().unwrap();
+        let data = (0..=255).cycle().take(1000).collect::<Vec<u8>>();
+        for (i, part) in data.chunks(300).enumerate() {
+            std::fs::write(tmp_dir.path().join(format!("postdata_{i}.bin")), part).unwrap();
+        }
+        let source = Arc::new(PosFiles::new(LocalDir(tmp_dir.path().to_path_buf()), 300));
+
+        let batches = read_batches(
+            source,
+            10,
+            900,
+            BatchSize::Auto { min: 3, max: 8 },
+            ReadRetry::default(),
+        )
+        .map(Result::unwrap)
+        .collect::<Vec<_>>();
+        assert!(batches.iter().all(|b| b.data.len() <= 8));
+        assert_eq!(&data[10..910], batches.into_iter().map(|b| b.data).concat());
+    }
+
+    #[test]
+    fn tuning_batch_size() {
+        let ms = Duration::from_millis;
+        let mut tuner = BatchTuner::new(1024, 4096);
+        assert_eq!(4096, tuner.size);
+
+        // Steady latency: the throughput drops with bigger batches, so the size shrinks.
+        tuner.grow = false;
+        for _ in 0..4 {
+            tuner.record(4096, ms(4));
+        }
+        tuner.tune();
+        assert_eq!(2048, tuner.size);
+        for _ in 0..4 {
+            tuner.record(2048, ms(4));
+        }
+        // Worse throughput, going back
+        tuner.tune();
+        assert_eq!(4096, tuner.size);
+
+        // Varying latency grows the batches.
+        let mut tuner = BatchTuner::new(1024, 4096);
+        tuner.size = 1024;
+        tuner.grow = false;
+        for latency in [1, 10, 1, 10] {
+            tuner.record(1024, ms(latency));
+        }
+        tuner.tune();
+        assert_eq!(2048, tuner.size);
+    }
+
     #[rstest::rstest]
     #[case("other.bin")]
     #[case("_postadata_0.bin")]
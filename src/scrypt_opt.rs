@@ -0,0 +1,473 @@
+//! Optimized CPU scrypt for initializing POS data without a GPU.
+//!
+//! Only scrypt with `r = 1` and `p = 1` (the parameters of the POS labels) is supported.
+//! On x86_64 CPUs with AVX2, two labels are calculated at once, each in one
+//! 128-bit lane of the 256-bit registers. Other CPUs use a portable implementation.
+
+use std::{cell::RefCell, io::Write, ops::Range};
+
+use rayon::prelude::{IntoParallelRefIterator, ParallelIterator};
+use sha2::{Digest, Sha256};
+
+use crate::{
+    config::ScryptParams,
+    initialize::{initialize_in_chunks, CpuInitializer, Initialize, VrfNonce},
+};
+
+/// Initializes labels with the optimized scrypt (see the [module docs](self)).
+///
+/// The labels are the same as calculated by [CpuInitializer].
+#[derive(Debug, Clone)]
+pub struct OptimizedCpuInitializer {
+    n: usize,
+    chunk_size: u64,
+}
+
+impl OptimizedCpuInitializer {
+    pub fn new(scrypt_params: ScryptParams) -> eyre::Result<Self> {
+        let ScryptParams { n, r, p } = scrypt_params;
+        eyre::ensure!(
+            r == 1 && p == 1,
+            "optimized scrypt supports only r = 1 and p = 1 (got r = {r}, p = {p})"
+        );
+        eyre::ensure!(n.is_power_of_two() && n > 1, "invalid scrypt N: {n}");
+        Ok(Self {
+            n,
+            chunk_size: CpuInitializer::DEFAULT_CHUNK_SIZE,
+        })
+    }
+
+    /// Set the number of labels calculated at once (see [CpuInitializer::with_chunk_size]).
+    pub fn with_chunk_size(mut self, chunk_size: u64) -> Self {
+        self.chunk_size = chunk_size.max(1);
+        self
+    }
+
+    /// Is the AVX2 implementation used on this CPU.
+    pub fn uses_avx2() -> bool {
+        #[cfg(target_arch = "x86_64")]
+        {
+            std::arch::is_x86_feature_detected!("avx2")
+        }
+        #[cfg(not(target_arch = "x86_64"))]
+        {
+            false
+        }
+    }
+
+    fn scrypt_labels(&self, commitment: &[u8; 32], labels: Range<u64>) -> Vec<[u8; 32]> {
+        let passwords = labels
+            .map(|index| label_password(commitment, index))
+            .collect::<Vec<_>>();
+        #[cfg(target_arch = "x86_64")]
+        if Self::uses_avx2() {
+            use rayon::slice::ParallelSlice;
+            return passwords
+                .par_chunks(2)
+                .flat_map_iter(|pair| {
+                    // SAFETY: AVX2 is supported by the CPU.
+                    let [a, b] =
+                        unsafe { avx2::scrypt_pair(&pair[0], pair.last().unwrap(), self.n) };
+                    [a, b].into_iter().take(pair.len())
+                })
+                .collect();
+        }
+        passwords
+            .par_iter()
+            .map(|password| scrypt_label(password, self.n))
+            .collect()
+    }
+}
+
+impl Initialize for OptimizedCpuInitializer {
+    fn initialize_to(
+        &mut self,
+        writer: &mut dyn Write,
+        commitment: &[u8; 32],
+        labels: Range<u64>,
+        vrf_difficulty: Option<[u8; 32]>,
+    ) -> Result<Option<VrfNonce>, crate::Error> {
+        initialize_in_chunks(writer, labels, self.chunk_size, vrf_difficulty, |labels| {
+            self.scrypt_labels(commitment, labels)
+        })
+    }
+
+    fn scrypt_params(&self) -> Option<ScryptParams> {
+        Some(ScryptParams::new(self.n, 1, 1))
+    }
+}
+
+/// The scrypt input of a label: commitment || index (LE) || zero padding.
+fn label_password(commitment: &[u8; 32], index: u64) -> [u8; 72] {
+    let mut password = [0u8; 72];
+    password[0..32].copy_from_slice(commitment);
+    password[32..40].copy_from_slice(&index.to_le_bytes());
+    password
+}
+
+/// HMAC-SHA256 with the key already absorbed into the inner and outer hashes.
+#[derive(Clone)]
+struct HmacSha256 {
+    inner: Sha256,
+    outer: Sha256,
+}
+
+impl HmacSha256 {
+    fn new(key: &[u8]) -> Self {
+        let mut block = [0u8; 64];
+        if key.len() > block.len() {
+            block[..32].copy_from_slice(&Sha256::digest(key));
+        } else {
+            block[..key.len()].copy_from_slice(key);
+        }
+        let mut inner = Sha256::new();
+        inner.update(block.map(|b| b ^ 0x36));
+        let mut outer = Sha256::new();
+        outer.update(block.map(|b| b ^ 0x5c));
+        Self { inner, outer }
+    }
+
+    /// PBKDF2-HMAC-SHA256 with a single iteration.
+    fn pbkdf2(&self, salt: &[u8], output: &mut [u8]) {
+        for (i, block) in output.chunks_mut(32).enumerate() {
+            let mut inner = self.inner.clone();
+            inner.update(salt);
+            inner.update((i as u32 + 1).to_be_bytes());
+            let mut outer = self.outer.clone();
+            outer.update(inner.finalize());
+            block.copy_from_slice(&outer.finalize()[..block.len()]);
+        }
+    }
+}
+
+/// Scrypt (r = 1, p = 1) of a label password.
+fn scrypt_label(password: &[u8; 72], n: usize) -> [u8; 32] {
+    let mut label = [0u8; 32];
+    scrypt(password, &[], n, &mut label);
+    label
+}
+
+fn scrypt(password: &[u8], salt: &[u8], n: usize, output: &mut [u8]) {
+    let hmac = HmacSha256::new(password);
+    let mut b = [0u8; 128];
+    hmac.pbkdf2(salt, &mut b);
+    let mut x = [0u32; 32];
+    for (word, bytes) in x.iter_mut().zip(b.chunks_exact(4)) {
+        *word = u32::from_le_bytes(bytes.try_into().unwrap());
+    }
+    ROMIX_SCRATCH.with_borrow_mut(|v| romix(&mut x, n, v));
+    for (word, bytes) in x.iter().zip(b.chunks_exact_mut(4)) {
+        bytes.copy_from_slice(&word.to_le_bytes());
+    }
+    hmac.pbkdf2(&b, output);
+}
+
+thread_local! {
+    // Reused between labels to not allocate (and fault in) N blocks for each label.
+    static ROMIX_SCRATCH: RefCell<Vec<[u32; 32]>> = const { RefCell::new(Vec::new()) };
+}
+
+fn romix(x: &mut [u32; 32], n: usize, v: &mut Vec<[u32; 32]>) {
+    v.clear();
+    for _ in 0..n {
+        v.push(*x);
+        block_mix(x);
+    }
+    for _ in 0..n {
+        let j = x[16] as usize & (n - 1);
+        for (a, b) in x.iter_mut().zip(&v[j]) {
+            *a ^= b;
+        }
+        block_mix(x);
+    }
+}
+
+/// BlockMix with r = 1.
+fn block_mix(b: &mut [u32; 32]) {
+    let mut x: [u32; 16] = b[16..].try_into().unwrap();
+    for i in 0..2 {
+        for (a, b) in x.iter_mut().zip(&b[i * 16..(i + 1) * 16]) {
+            *a ^= b;
+        }
+        salsa20_8(&mut x);
+        b[i * 16..(i + 1) * 16].copy_from_slice(&x);
+    }
+}
+
+fn salsa20_8(b: &mut [u32; 16]) {
+    let mut x = *b;
+    macro_rules! quarter {
+        ($a:expr, $b:expr, $c:expr, $d:expr) => {
+            x[$b] ^= x[$a].wrapping_add(x[$d]).rotate_left(7);
+            x[$c] ^= x[$b].wrapping_add(x[$a]).rotate_left(9);
+            x[$d] ^= x[$c].wrapping_add(x[$b]).rotate_left(13);
+            x[$a] ^= x[$d].wrapping_add(x[$c]).rotate_left(18);
+        };
+    }
+    for _ in 0..4 {
+        // columns
+        quarter!(0, 4, 8, 12);
+        quarter!(5, 9, 13, 1);
+        quarter!(10, 14, 2, 6);
+        quarter!(15, 3, 7, 11);
+        // rows
+        quarter!(0, 1, 2, 3);
+        quarter!(5, 6, 7, 4);
+        quarter!(10, 11, 8, 9);
+        quarter!(15, 12, 13, 14);
+    }
+    for (a, x) in b.iter_mut().zip(x) {
+        *a = a.wrapping_add(x);
+    }
+}
+
+#[cfg(target_arch = "x86_64")]
+mod avx2 {
+    //! Two scrypt instances at once, one in each 128-bit lane.
+    //!
+    //! A 64-byte block is kept in 4 rows holding the diagonals of the salsa matrix
+    //! (the word `i` of the rows is the word `i * 5 % 16` of the block), so that
+    //! the salsa quarter-rounds work on whole rows.
+
+    use std::{arch::x86_64::*, cell::RefCell};
+
+    use super::HmacSha256;
+
+    type State = [__m256i; 8];
+
+    thread_local! {
+        static SCRATCH: RefCell<Vec<State>> = const { RefCell::new(Vec::new()) };
+    }
+
+    /// # Safety
+    /// The CPU must support AVX2.
+    #[target_feature(enable = "avx2")]
+    pub(super) unsafe fn scrypt_pair(a: &[u8; 72], b: &[u8; 72], n: usize) -> [[u8; 32]; 2] {
+        let hmacs = [HmacSha256::new(a), HmacSha256::new(b)];
+        let mut blocks = [[0u8; 128]; 2];
+        for (hmac, block) in hmacs.iter().zip(&mut blocks) {
+            hmac.pbkdf2(&[], block);
+        }
+
+        let mut x = to_state(&blocks);
+        SCRATCH.with_borrow_mut(|v| romix(&mut x, n, v));
+        blocks = from_state(&x);
+
+        let mut labels = [[0u8; 32]; 2];
+        for ((hmac, block), label) in hmacs.iter().zip(&blocks).zip(&mut labels) {
+            hmac.pbkdf2(block, label);
+        }
+        labels
+    }
+
+    fn to_state(blocks: &[[u8; 128]; 2]) -> State {
+        let mut words = [0u32; 64];
+        for (lane, block) in blocks.iter().enumerate() {
+            for k in 0..2 {
+                for i in 0..16 {
+                    let src = (k * 16 + i * 5 % 16) * 4;
+                    let word = u32::from_le_bytes(block[src..src + 4].try_into().unwrap());
+                    // row (i / 4) of block k, lane word (i % 4)
+                    words[(k * 4 + i / 4) * 8 + lane * 4 + i % 4] = word;
+                }
+            }
+        }
+        // SAFETY: the words are exactly 8 rows of 8 u32.
+        unsafe { std::mem::transmute(words) }
+    }
+
+    fn from_state(state: &State) -> [[u8; 128]; 2] {
+        // SAFETY: the state is exactly 8 rows of 8 u32.
+        let words: [u32; 64] = unsafe { std::mem::transmute(*state) };
+        let mut blocks = [[0u8; 128]; 2];
+        for (lane, block) in blocks.iter_mut().enumerate() {
+            for k in 0..2 {
+                for i in 0..16 {
+                    let dst = (k * 16 + i * 5 % 16) * 4;
+                    let word = words[(k * 4 + i / 4) * 8 + lane * 4 + i % 4];
+                    block[dst..dst + 4].copy_from_slice(&word.to_le_bytes());
+                }
+            }
+        }
+        blocks
+    }
+
+    #[target_feature(enable = "avx2")]
+    unsafe fn romix(x: &mut State, n: usize, v: &mut Vec<State>) {
+        v.clear();
+        for _ in 0..n {
+            v.push(*x);
+            block_mix(x);
+        }
+        for _ in 0..n {
+            // Integerify: the first word of the second block of each lane
+            let ja = _mm256_extract_epi32::<0>(x[4]) as u32 as usize & (n - 1);
+            let jb = _mm256_extract_epi32::<4>(x[4]) as u32 as usize & (n - 1);
+            let (va, vb) = (&v[ja], &v[jb]);
+            for row in 0..8 {
+                let vj = _mm256_blend_epi32::<0xF0>(va[row], vb[row]);
+                x[row] = _mm256_xor_si256(x[row], vj);
+            }
+            block_mix(x);
+        }
+    }
+
+    #[target_feature(enable = "avx2")]
+    unsafe fn block_mix(b: &mut State) {
+        let mut x = [b[4], b[5], b[6], b[7]];
+        for i in 0..2 {
+            for row in 0..4 {
+                x[row] = _mm256_xor_si256(x[row], b[i * 4 + row]);
+            }
+            salsa20_8(&mut x);
+            b[i * 4..(i + 1) * 4].copy_from_slice(&x);
+        }
+    }
+
+    macro_rules! rotl {
+        ($v:expr, $n:literal) => {
+            _mm256_or_si256(
+                _mm256_slli_epi32::<$n>($v),
+                _mm256_srli_epi32::<{ 32 - $n }>($v),
+            )
+        };
+    }
+
+    #[target_feature(enable = "avx2")]
+    unsafe fn salsa20_8(b: &mut [__m256i; 4]) {
+        let [mut x0, mut x1, mut x2, mut x3] = *b;
+        for _ in 0..4 {
+            // columns
+            x1 = _mm256_xor_si256(x1, rotl!(_mm256_add_epi32(x0, x3), 7));
+            x2 = _mm256_xor_si256(x2, rotl!(_mm256_add_epi32(x1, x0), 9));
+            x3 = _mm256_xor_si256(x3, rotl!(_mm256_add_epi32(x2, x1), 13));
+            x0 = _mm256_xor_si256(x0, rotl!(_mm256_add_epi32(x3, x2), 18));
+            x1 = _mm256_shuffle_epi32::<0x93>(x1);
+            x2 = _mm256_shuffle_epi32::<0x4E>(x2);
+            x3 = _mm256_shuffle_epi32::<0x39>(x3);
+            // rows
+            x3 = _mm256_xor_si256(x3, rotl!(_mm256_add_epi32(x0, x1), 7));
+            x2 = _mm256_xor_si256(x2, rotl!(_mm256_add_epi32(x3, x0), 9));
+            x1 = _mm256_xor_si256(x1, rotl!(_mm256_add_epi32(x2, x3), 13));
+            x0 = _mm256_xor_si256(x0, rotl!(_mm256_add_epi32(x1, x2), 18));
+            x1 = _mm256_shuffle_epi32::<0x39>(x1);
+            x2 = _mm256_shuffle_epi32::<0x4E>(x2);
+            x3 = _mm256_shuffle_epi32::<0x93>(x3);
+        }
+        b[0] = _mm256_add_epi32(b[0], x0);
+        b[1] = _mm256_add_epi32(b[1], x1);
+        b[2] = _mm256_add_epi32(b[2], x2);
+        b[3] = _mm256_add_epi32(b[3], x3);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hex(s: &str) -> Vec<u8> {
+        hex::decode(s).unwrap()
+    }
+
+    // Test vectors from RFC 7914.
+    #[test]
+    fn salsa20_8_core() {
+        let input = hex("7e879a214f3ec9867ca940e641718f26baee555b8c61c1b50df846116dcd3b1dee24f319df9b3d8514121e4b5ac5aa3276021d2909c74829edebc68db8b8c25e");
+        let expected = hex("a41f859c6608cc993b81cacb020cef05044b2181a2fd337dfd7b1c6396682f29b4393168e3c9e6bcfe6bc5b7a06d96bae424cc102c91745c24ad673dc7618f81");
+        let mut x = [0u32; 16];
+        for (word, bytes) in x.iter_mut().zip(input.chunks_exact(4)) {
+            *word = u32::from_le_bytes(bytes.try_into().unwrap());
+        }
+        salsa20_8(&mut x);
+        let output = x.iter().flat_map(|w| w.to_le_bytes()).collect::<Vec<_>>();
+        assert_eq!(expected, output);
+    }
+
+    #[test]
+    fn pbkdf2_hmac_sha256() {
+        let mut output = [0u8; 64];
+        HmacSha256::new(b"passwd").pbkdf2(b"salt", &mut output);
+        assert_eq!(
+            hex("55ac046e56e3089fec1691c22544b605f94185216dde0465e68b9d57c20dacbc49ca9cccf179b645991664b39d77ef317c71b845b1e30bd509112041d3a19783"),
+            output
+        );
+    }
+
+    #[test]
+    fn scrypt_rfc_vector() {
+        let mut output = [0u8; 64];
+        scrypt(b"", b"", 16, &mut output);
+        assert_eq!(
+            hex("77d6576238657b203b19ca42c18a0497f16b4844e3074ae8dfdffa3fede21442fcd0069ded0948f8326a753a0fc81f17e8d3e0fb2e0d3628cf35e20c38d18906"),
+            output
+        );
+    }
+
+    #[test]
+    fn scrypting_labels() {
+        let commitment = [0xAB; 32];
+        for (n, index, expected) in [
+            (
+                2,
+                0,
+                "38ffb34048b73bd47e083b9a44ae0ca96e9c7d74687c3364d291a6c8ae57a762",
+            ),
+            (
+                16,
+                7,
+                "b74d3556b3efd8c9b31fd0844cd612ef1e0cbb75bfacb349c335779a4721168f",
+            ),
+            (
+                8192,
+                1,
+                "269cd1f980f8de5a9fc6e1135c2e48991c6eaa25332f6f3c4a559f14d9e5cd2f",
+            ),
+        ] {
+            let label = scrypt_label(&label_password(&commitment, index), n);
+            assert_eq!(hex(expected), label);
+
+            let initializer = OptimizedCpuInitializer::new(ScryptParams::new(n, 1, 1)).unwrap();
+            assert_eq!(
+                vec![label],
+                initializer.scrypt_labels(&commitment, index..index + 1)
+            );
+        }
+    }
+
+    #[test]
+    fn optimized_is_the_same_as_portable() {
+        let commitment = [0x11; 32];
+        for n in [2, 16, 1024] {
+            let initializer = OptimizedCpuInitializer::new(ScryptParams::new(n, 1, 1)).unwrap();
+            let expected = (5..28)
+                .map(|index| scrypt_label(&label_password(&commitment, index), n))
+                .collect::<Vec<_>>();
+            assert_eq!(expected, initializer.scrypt_labels(&commitment, 5..28));
+        }
+    }
+
+    #[test]
+    fn same_labels_as_reference() {
+        let scrypt_params = ScryptParams::new(16, 1, 1);
+        let commitment = [0x22; 32];
+        let mut expected = Vec::new();
+        let expected_nonce = CpuInitializer::new(scrypt_params)
+            .initialize_to(&mut expected, &commitment, 0..100, Some([0xFF; 32]))
+            .unwrap();
+
+        let mut labels = Vec::new();
+        let nonce = OptimizedCpuInitializer::new(scrypt_params)
+            .unwrap()
+            .with_chunk_size(30)
+            .initialize_to(&mut labels, &commitment, 0..100, Some([0xFF; 32]))
+            .unwrap();
+        assert_eq!(expected, labels);
+        assert_eq!(expected_nonce, nonce);
+    }
+
+    #[test]
+    fn rejects_unsupported_params() {
+        assert!(OptimizedCpuInitializer::new(ScryptParams::new(16, 2, 1)).is_err());
+        assert!(OptimizedCpuInitializer::new(ScryptParams::new(16, 1, 2)).is_err());
+    }
+}
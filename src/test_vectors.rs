@@ -0,0 +1,374 @@
+//! Deterministic test vectors for other implementations of POST.
+//!
+//! The vectors are generated from fixed inputs, serialized to JSON and checked
+//! by other implementations (i.e. the go node) to stay byte-compatible.
+//! [TestVectors::verify] recomputes them with this implementation.
+//!
+//! The expected vectors are committed in [GOLDEN_VECTORS], so that a change of
+//! the outputs fails the tests instead of changing the vectors silently.
+//! Regenerate them (only for an intended change) with:
+//! ```sh
+//! UPDATE_TEST_VECTORS=1 cargo test -p post-rs --features test-vectors test_vectors
+//! ```
+//!
+//! Enabled with the `test-vectors` feature.
+
+use std::{path::Path, sync::atomic::AtomicBool};
+
+use serde::{Deserialize, Serialize};
+use serde_with::{hex::Hex, serde_as};
+
+use crate::{
+    cipher::AesCipher,
+    config::{Cores, InitConfig, ProofConfig, ScryptParams},
+    initialize::{calc_commitment, generate_label, CpuInitializer, Initialize},
+    metadata::ProofMetadata,
+    pow::randomx::{pow_input, PoW, RandomXFlag},
+    prove::{generate_proof, NoopProgressReporter, Proof},
+    random_values_gen::RandomValuesIterator,
+    verification::{Mode, Verifier},
+};
+
+#[derive(thiserror::Error, Debug)]
+pub enum Error {
+    #[error("label vector {0} doesn't match")]
+    Label(usize),
+    #[error("cipher vector {0} doesn't match")]
+    Cipher(usize),
+    #[error("PoW input vector {0} doesn't match")]
+    PowInput(usize),
+    #[error("subset vector {0} doesn't match")]
+    Subset(usize),
+}
+
+/// A label generated with scrypt for the given commitment and index.
+#[serde_as]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LabelVector {
+    #[serde_as(as = "Hex")]
+    pub commitment: [u8; 32],
+    pub scrypt: ScryptParams,
+    pub index: u64,
+    #[serde_as(as = "Hex")]
+    pub label: [u8; 16],
+}
+
+/// Outputs of the AES ciphers used in proving for the given nonce.
+///
+/// `output` is the label encrypted with the cipher of the nonce group (checking MSB)
+/// and `lazy_output` with the cipher of the nonce (checking LSB).
+#[serde_as]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CipherVector {
+    #[serde_as(as = "Hex")]
+    pub challenge: [u8; 32],
+    pub nonce: u32,
+    pub nonce_group: u32,
+    pub pow: u64,
+    #[serde_as(as = "Hex")]
+    pub label: [u8; 16],
+    #[serde_as(as = "Hex")]
+    pub output: [u8; 16],
+    #[serde_as(as = "Hex")]
+    pub lazy_output: [u8; 16],
+}
+
+/// The input hashed with RandomX for the k2pow.
+#[serde_as]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PowInputVector {
+    pub pow: u64,
+    pub nonce_group: u8,
+    #[serde_as(as = "Hex")]
+    pub challenge: [u8; 8],
+    #[serde_as(as = "Hex")]
+    pub miner_id: [u8; 32],
+    #[serde_as(as = "Hex")]
+    pub input: Vec<u8>,
+}
+
+/// The first positions of `0..len` picked with [RandomValuesIterator]
+/// seeded like in the verifier ([Mode::Subset]).
+#[serde_as]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SubsetVector {
+    pub len: usize,
+    #[serde_as(as = "Hex")]
+    pub seed: Vec<u8>,
+    #[serde_as(as = "Hex")]
+    pub node_id: [u8; 32],
+    #[serde_as(as = "Hex")]
+    pub challenge: [u8; 32],
+    pub picked: Vec<usize>,
+}
+
+/// A complete proof for a tiny POST.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ProofVector {
+    pub proof_cfg: ProofConfig,
+    pub init_cfg: InitConfig,
+    pub metadata: ProofMetadata,
+    pub proof: Proof<'static>,
+}
+
+/// The expected vectors in JSON (`tests/data/test_vectors.json`).
+pub const GOLDEN_VECTORS: &str = include_str!("../tests/data/test_vectors.json");
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TestVectors {
+    pub labels: Vec<LabelVector>,
+    pub ciphers: Vec<CipherVector>,
+    pub pow_inputs: Vec<PowInputVector>,
+    #[serde(default)]
+    pub subsets: Vec<SubsetVector>,
+}
+
+impl LabelVector {
+    fn compute(commitment: [u8; 32], scrypt: ScryptParams, index: u64) -> Self {
+        Self {
+            commitment,
+            scrypt,
+            index,
+            label: generate_label(&commitment, scrypt, index),
+        }
+    }
+}
+
+impl CipherVector {
+    fn compute(challenge: [u8; 32], nonce: u32, pow: u64, label: [u8; 16]) -> Self {
+        let nonce_group = nonce / crate::prove::Prover8_56::NONCES_PER_AES;
+        let output = AesCipher::new(&challenge, nonce_group, pow).encrypt(&label);
+        let lazy_output = AesCipher::new_lazy(&challenge, nonce, nonce_group, pow).encrypt(&label);
+        Self {
+            challenge,
+            nonce,
+            nonce_group,
+            pow,
+            label,
+            output,
+            lazy_output,
+        }
+    }
+}
+
+impl PowInputVector {
+    fn compute(pow: u64, nonce_group: u8, challenge: [u8; 8], miner_id: [u8; 32]) -> Self {
+        Self {
+            pow,
+            nonce_group,
+            challenge,
+            miner_id,
+            input: pow_input(pow, nonce_group, &challenge, &miner_id),
+        }
+    }
+}
+
+impl SubsetVector {
+    fn compute(
+        len: usize,
+        seed: Vec<u8>,
+        node_id: [u8; 32],
+        challenge: [u8; 32],
+        k: usize,
+    ) -> Self {
+        let picked = RandomValuesIterator::new(len, &[&seed, &node_id, &challenge])
+            .take(k)
+            .collect();
+        Self {
+            len,
+            seed,
+            node_id,
+            challenge,
+            picked,
+        }
+    }
+}
+
+impl TestVectors {
+    /// Generate the vectors from fixed inputs.
+    pub fn generate() -> Self {
+        let commitment = calc_commitment(&[0xBE; 32], &[0xCE; 32]);
+        let labels = [ScryptParams::new(2, 1, 1), ScryptParams::new(8192, 1, 1)]
+            .into_iter()
+            .flat_map(|scrypt| {
+                [0, 1, 255, 256, 1 << 32, (1 << 48) - 1]
+                    .into_iter()
+                    .map(move |index| LabelVector::compute(commitment, scrypt, index))
+            })
+            .collect();
+
+        let ciphers = [(0, 0), (1, 7), (15, 12345), (16, u64::MAX), (4095, 1 << 55)]
+            .into_iter()
+            .map(|(nonce, pow)| CipherVector::compute([0xCA; 32], nonce, pow, [0xAB; 16]))
+            .collect();
+
+        let pow_inputs = [(0, 0), (7, 1), (u64::MAX, 255), (1 << 55, 128)]
+            .into_iter()
+            .map(|(pow, nonce_group)| {
+                PowInputVector::compute(pow, nonce_group, [0xCA; 8], [0xBE; 32])
+            })
+            .collect();
+
+        let subsets = [
+            (1, &b""[..], 1),
+            (37, b"seed", 10),
+            (1000, b"", 8),
+            (65535, b"max", 5),
+        ]
+        .into_iter()
+        .map(|(len, seed, k)| SubsetVector::compute(len, seed.to_vec(), [0xBE; 32], [0xCA; 32], k))
+        .collect();
+
+        Self {
+            labels,
+            ciphers,
+            pow_inputs,
+            subsets,
+        }
+    }
+
+    /// Recompute the vectors and compare them with the expected outputs.
+    pub fn verify(&self) -> Result<(), Error> {
+        for (id, v) in self.labels.iter().enumerate() {
+            if LabelVector::compute(v.commitment, v.scrypt, v.index) != *v {
+                return Err(Error::Label(id));
+            }
+        }
+        for (id, v) in self.ciphers.iter().enumerate() {
+            if CipherVector::compute(v.challenge, v.nonce, v.pow, v.label) != *v {
+                return Err(Error::Cipher(id));
+            }
+        }
+        for (id, v) in self.pow_inputs.iter().enumerate() {
+            if PowInputVector::compute(v.pow, v.nonce_group, v.challenge, v.miner_id) != *v {
+                return Err(Error::PowInput(id));
+            }
+        }
+        for (id, v) in self.subsets.iter().enumerate() {
+            let computed = SubsetVector::compute(
+                v.len,
+                v.seed.clone(),
+                v.node_id,
+                v.challenge,
+                v.picked.len(),
+            );
+            if computed != *v {
+                return Err(Error::Subset(id));
+            }
+        }
+        Ok(())
+    }
+}
+
+impl ProofVector {
+    /// Initialize a tiny POST in `datadir` and generate a proof for it.
+    pub fn generate(datadir: &Path) -> eyre::Result<Self> {
+        let proof_cfg = ProofConfig {
+            k1: 8,
+            k2: 4,
+            pow_difficulty: [0xFF; 32],
+        };
+        let init_cfg = InitConfig {
+            min_num_units: 1,
+            max_num_units: 1,
+            labels_per_unit: 256,
+            scrypt: ScryptParams::new(2, 1, 1),
+        };
+        let post_metadata = CpuInitializer::new(init_cfg.scrypt)
+            .initialize(
+                datadir,
+                &[0xBE; 32],
+                &[0xCE; 32],
+                init_cfg.labels_per_unit,
+                1,
+                init_cfg.labels_per_unit,
+                None,
+            )
+            .map_err(|e| eyre::eyre!("initializing POST: {e}"))?;
+
+        let challenge = [0xCA; 32];
+        let pow_flags = RandomXFlag::get_recommended_flags();
+        let proof = generate_proof(
+            datadir,
+            &challenge,
+            proof_cfg,
+            16,
+            Cores::Any(1),
+            pow_flags,
+            AtomicBool::new(false),
+            NoopProgressReporter {},
+            &PoW::new(pow_flags)?,
+        )?;
+
+        Ok(Self {
+            proof_cfg,
+            init_cfg,
+            metadata: ProofMetadata::new(post_metadata, challenge),
+            proof,
+        })
+    }
+
+    /// Verify the proof with all indices.
+    pub fn verify(&self) -> eyre::Result<()> {
+        let pow_verifier = PoW::new(RandomXFlag::get_recommended_flags())?;
+        Verifier::new(Box::new(pow_verifier)).verify(
+            &self.proof,
+            &self.metadata,
+            &self.proof_cfg,
+            &self.init_cfg,
+            Mode::All,
+        )?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn matches_golden_vectors() {
+        let vectors = TestVectors::generate();
+        if std::env::var_os("UPDATE_TEST_VECTORS").is_some() {
+            let path = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/data/test_vectors.json");
+            let json = serde_json::to_string_pretty(&vectors).unwrap() + "\n";
+            std::fs::write(path, json).unwrap();
+            return;
+        }
+        let golden: TestVectors = serde_json::from_str(GOLDEN_VECTORS).unwrap();
+        golden.verify().unwrap();
+        assert_eq!(golden, vectors);
+    }
+
+    #[test]
+    fn generated_vectors_verify() {
+        let vectors = TestVectors::generate();
+        vectors.verify().unwrap();
+
+        let json = serde_json::to_string(&vectors).unwrap();
+        let decoded: TestVectors = serde_json::from_str(&json).unwrap();
+        decoded.verify().unwrap();
+    }
+
+    #[test]
+    fn detects_mismatch() {
+        let mut vectors = TestVectors::generate();
+        vectors.ciphers[2].output[0] ^= 0xFF;
+        assert!(matches!(vectors.verify(), Err(Error::Cipher(2))));
+
+        let mut vectors = TestVectors::generate();
+        vectors.pow_inputs[1].input[7] ^= 0xFF;
+        assert!(matches!(vectors.verify(), Err(Error::PowInput(1))));
+
+        let mut vectors = TestVectors::generate();
+        vectors.subsets[1].picked.swap(0, 1);
+        assert!(matches!(vectors.verify(), Err(Error::Subset(1))));
+    }
+
+    #[test]
+    fn proof_vector() {
+        let datadir = tempfile::tempdir().unwrap();
+        let vector = ProofVector::generate(datadir.path()).unwrap();
+        vector.verify().unwrap();
+    }
+}
@@ -0,0 +1,373 @@
+//! CPU topology relevant for pinning the proving threads to cores.
+//!
+//! Pinning two threads to the hyper-thread siblings of a physical core makes them share
+//! its AES units, and pinning to an offline core fails. On hybrid CPUs (i.e. Intel Alder Lake),
+//! the threads on efficiency cores prove much slower than the ones on performance cores.
+//!
+//! The logical cores available to the process come from [core_affinity], the physical cores
+//! they belong to and their kind are read from sysfs on Linux (unknown on other systems).
+use std::{collections::HashSet, path::Path};
+
+use crate::config::Cores;
+
+/// A logical core (as numbered by the OS).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LogicalCore {
+    pub id: usize,
+    /// The physical core (package and core id) it runs on, if known.
+    pub physical: Option<(usize, usize)>,
+    /// The kind of the core on hybrid CPUs.
+    pub kind: Option<CoreKind>,
+}
+
+/// The kind of a core of a hybrid CPU.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CoreKind {
+    Performance,
+    Efficiency,
+}
+
+/// A problem with the cores to pin the proving threads to.
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+pub enum PinningIssue {
+    #[error("core {0} is offline or not available to the process")]
+    Unavailable(usize),
+    #[error("core {0} is listed more than once")]
+    Duplicate(usize),
+    #[error("cores {0} and {1} are hyper-thread siblings of the same physical core")]
+    Siblings(usize, usize),
+}
+
+/// The logical cores available to the process.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Topology {
+    cores: Vec<LogicalCore>,
+}
+
+impl Topology {
+    pub fn new(cores: Vec<LogicalCore>) -> Self {
+        Self { cores }
+    }
+
+    /// Detect the cores available to the process. Returns `None` if they can't be listed.
+    pub fn detect() -> Option<Self> {
+        let mut ids: Vec<usize> = core_affinity::get_core_ids()?
+            .into_iter()
+            .map(|c| c.id)
+            .collect();
+        ids.sort_unstable();
+        let performance = cpu_list("cpu_core");
+        let efficiency = cpu_list("cpu_atom");
+        let cores = ids
+            .into_iter()
+            .map(|id| LogicalCore {
+                id,
+                physical: physical_core(id),
+                kind: if performance.contains(&id) {
+                    Some(CoreKind::Performance)
+                } else if efficiency.contains(&id) {
+                    Some(CoreKind::Efficiency)
+                } else {
+                    None
+                },
+            })
+            .collect();
+        Some(Self { cores })
+    }
+
+    pub fn cores(&self) -> &[LogicalCore] {
+        &self.cores
+    }
+
+    /// Whether the physical cores are known for all logical cores.
+    pub fn knows_physical_cores(&self) -> bool {
+        self.cores.iter().all(|c| c.physical.is_some())
+    }
+
+    /// Whether the CPU has both performance and efficiency cores.
+    pub fn is_hybrid(&self) -> bool {
+        let has = |kind| self.cores.iter().any(|c| c.kind == Some(kind));
+        has(CoreKind::Performance) && has(CoreKind::Efficiency)
+    }
+
+    /// The logical cores of the given kind.
+    pub fn cores_of_kind(&self, kind: CoreKind) -> Vec<usize> {
+        self.cores
+            .iter()
+            .filter(|c| c.kind == Some(kind))
+            .map(|c| c.id)
+            .collect()
+    }
+
+    /// The topology without the efficiency cores of a hybrid CPU
+    /// (unchanged on other CPUs).
+    pub fn without_efficiency_cores(&self) -> Self {
+        if !self.is_hybrid() {
+            return self.clone();
+        }
+        let cores = self
+            .cores
+            .iter()
+            .filter(|c| c.kind != Some(CoreKind::Efficiency))
+            .copied()
+            .collect();
+        Self { cores }
+    }
+
+    /// Restrict the proving threads to the performance cores of a hybrid CPU
+    /// (see [restrict]). Unchanged on other CPUs.
+    ///
+    /// The threads go to different physical cores first, their hyper-thread siblings
+    /// are used only for more threads than physical performance cores.
+    pub fn avoid_efficiency_cores(&self, cores: Cores) -> Cores {
+        if !self.is_hybrid() {
+            return cores;
+        }
+        let performance = self.without_efficiency_cores();
+        let mut allowed = performance.one_per_physical_core(None);
+        let siblings: Vec<usize> = performance
+            .cores
+            .iter()
+            .map(|c| c.id)
+            .filter(|id| !allowed.contains(id))
+            .collect();
+        allowed.extend(siblings);
+        restrict(cores, &allowed)
+    }
+
+    /// Check the cores to pin the proving threads to.
+    pub fn validate(&self, pinned: &[usize]) -> Vec<PinningIssue> {
+        let mut issues = Vec::new();
+        let mut seen = HashSet::new();
+        let mut physical = Vec::new();
+        for &id in pinned {
+            if !seen.insert(id) {
+                issues.push(PinningIssue::Duplicate(id));
+                continue;
+            }
+            let Some(core) = self.cores.iter().find(|c| c.id == id) else {
+                issues.push(PinningIssue::Unavailable(id));
+                continue;
+            };
+            let Some(p) = core.physical else {
+                continue;
+            };
+            match physical.iter().find(|(_, other)| *other == p) {
+                Some(&(sibling, _)) => issues.push(PinningIssue::Siblings(sibling, id)),
+                None => physical.push((id, p)),
+            }
+        }
+        issues
+    }
+
+    /// Pick one logical core of each physical core, at most `limit` (all if `None`).
+    ///
+    /// If the physical cores are unknown, every logical core is taken as a physical one.
+    pub fn one_per_physical_core(&self, limit: Option<usize>) -> Vec<usize> {
+        let mut physical = HashSet::new();
+        self.cores
+            .iter()
+            .filter(|c| c.physical.is_none_or(|p| physical.insert(p)))
+            .map(|c| c.id)
+            .take(limit.unwrap_or(usize::MAX))
+            .collect()
+    }
+}
+
+/// Pin the threads of `cores` to the `allowed` cores: all of them for [Cores::All]
+/// and the first `n` for [Cores::Any] (fewer if not that many are allowed).
+/// The threads already pinned are left alone.
+pub fn restrict(cores: Cores, allowed: &[usize]) -> Cores {
+    match cores {
+        Cores::All => Cores::Pin(allowed.to_vec()),
+        Cores::Any(n) => {
+            if n > allowed.len() {
+                log::warn!(
+                    "only {} cores are allowed, using {} threads instead of {n}",
+                    allowed.len(),
+                    allowed.len()
+                );
+            }
+            Cores::Pin(allowed.iter().copied().take(n).collect())
+        }
+        Cores::Pin(pinned) => Cores::Pin(pinned),
+    }
+}
+
+/// The logical cores of a PMU of a hybrid CPU (`cpu_core` for the performance cores,
+/// `cpu_atom` for the efficiency ones). Empty if there is no such PMU.
+fn cpu_list(pmu: &str) -> Vec<usize> {
+    if !cfg!(target_os = "linux") {
+        return Vec::new();
+    }
+    std::fs::read_to_string(Path::new("/sys/devices").join(pmu).join("cpus"))
+        .ok()
+        .and_then(|list| parse_cpu_list(&list))
+        .unwrap_or_default()
+}
+
+/// Parse a list of CPUs in the format of sysfs, i.e. `0-3,8,10-11`.
+fn parse_cpu_list(list: &str) -> Option<Vec<usize>> {
+    let mut cpus = Vec::new();
+    for part in list.trim().split(',').filter(|p| !p.is_empty()) {
+        match part.split_once('-') {
+            Some((first, last)) => cpus.extend(first.parse::<usize>().ok()?..=last.parse().ok()?),
+            None => cpus.push(part.parse().ok()?),
+        }
+    }
+    Some(cpus)
+}
+
+fn physical_core(id: usize) -> Option<(usize, usize)> {
+    if !cfg!(target_os = "linux") {
+        return None;
+    }
+    let dir = Path::new("/sys/devices/system/cpu")
+        .join(format!("cpu{id}"))
+        .join("topology");
+    let read = |name: &str| {
+        std::fs::read_to_string(dir.join(name))
+            .ok()?
+            .trim()
+            .parse::<usize>()
+            .ok()
+    };
+    Some((read("physical_package_id")?, read("core_id")?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{CoreKind, LogicalCore, PinningIssue, Topology};
+    use crate::config::Cores;
+
+    /// 2 physical cores with 2 hyper-threads each, the siblings numbered like on Linux (0 & 2, 1 & 3).
+    fn smt() -> Topology {
+        Topology::new(
+            [(0, 0), (1, 1), (2, 0), (3, 1)]
+                .into_iter()
+                .map(|(id, core)| LogicalCore {
+                    id,
+                    physical: Some((0, core)),
+                    kind: None,
+                })
+                .collect(),
+        )
+    }
+
+    #[test]
+    fn validating_pinned_cores() {
+        let topology = smt();
+        assert!(topology.validate(&[0, 1]).is_empty());
+        assert_eq!(
+            vec![
+                PinningIssue::Siblings(0, 2),
+                PinningIssue::Unavailable(7),
+                PinningIssue::Duplicate(0),
+            ],
+            topology.validate(&[0, 2, 7, 0])
+        );
+
+        let unknown = Topology::new(
+            (0..4)
+                .map(|id| LogicalCore {
+                    id,
+                    physical: None,
+                    kind: None,
+                })
+                .collect(),
+        );
+        assert!(!unknown.knows_physical_cores());
+        assert!(unknown.validate(&[0, 2]).is_empty());
+    }
+
+    #[test]
+    fn picking_physical_cores() {
+        let topology = smt();
+        assert!(topology.knows_physical_cores());
+        assert_eq!(vec![0, 1], topology.one_per_physical_core(None));
+        assert_eq!(vec![0], topology.one_per_physical_core(Some(1)));
+        assert_eq!(vec![0, 1], topology.one_per_physical_core(Some(8)));
+
+        let unknown = Topology::new(
+            (0..4)
+                .map(|id| LogicalCore {
+                    id,
+                    physical: None,
+                    kind: None,
+                })
+                .collect(),
+        );
+        assert_eq!(vec![0, 1, 2], unknown.one_per_physical_core(Some(3)));
+    }
+
+    /// 2 performance cores with 2 hyper-threads each (0-3) and 4 efficiency cores (4-7).
+    fn hybrid() -> Topology {
+        Topology::new(
+            (0..8)
+                .map(|id| LogicalCore {
+                    id,
+                    physical: Some((0, if id < 4 { id / 2 } else { id })),
+                    kind: Some(if id < 4 {
+                        CoreKind::Performance
+                    } else {
+                        CoreKind::Efficiency
+                    }),
+                })
+                .collect(),
+        )
+    }
+
+    #[test]
+    fn avoiding_efficiency_cores() {
+        let topology = hybrid();
+        assert!(topology.is_hybrid());
+        assert_eq!(
+            vec![4, 5, 6, 7],
+            topology.cores_of_kind(CoreKind::Efficiency)
+        );
+        assert_eq!(
+            vec![0, 2],
+            topology
+                .without_efficiency_cores()
+                .one_per_physical_core(None)
+        );
+
+        assert_eq!(
+            Cores::Pin(vec![0, 2, 1, 3]),
+            topology.avoid_efficiency_cores(Cores::All)
+        );
+        // One thread per physical core first.
+        assert_eq!(
+            Cores::Pin(vec![0, 2]),
+            topology.avoid_efficiency_cores(Cores::Any(2))
+        );
+        assert_eq!(
+            Cores::Pin(vec![0, 2, 1]),
+            topology.avoid_efficiency_cores(Cores::Any(3))
+        );
+        assert_eq!(
+            Cores::Pin(vec![0, 2, 1, 3]),
+            topology.avoid_efficiency_cores(Cores::Any(16))
+        );
+        assert_eq!(
+            Cores::Pin(vec![5]),
+            topology.avoid_efficiency_cores(Cores::Pin(vec![5]))
+        );
+
+        // Not hybrid.
+        let topology = smt();
+        assert!(!topology.is_hybrid());
+        assert_eq!(topology, topology.without_efficiency_cores());
+        assert_eq!(Cores::All, topology.avoid_efficiency_cores(Cores::All));
+    }
+
+    #[test]
+    fn parsing_cpu_list() {
+        assert_eq!(
+            Some(vec![0, 1, 2, 3, 8, 10, 11]),
+            super::parse_cpu_list("0-3,8,10-11\n")
+        );
+        assert_eq!(Some(vec![]), super::parse_cpu_list(""));
+        assert_eq!(None, super::parse_cpu_list("0-x"));
+    }
+}
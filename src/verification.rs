@@ -25,6 +25,11 @@
 //! ```
 //! indices[0..K3] now contains randomly picked values
 //!
+//! Verifiers of a committee can derive the seed from public randomness
+//! (i.e. a block hash) with [subset_seed] to check the same indices, or check disjoint
+//! parts of the same shuffle with [Mode::SubsetPart]. [selected_index_ids] reproduces
+//! which indices a mode selects.
+//!
 //! ## Verifying K3 indexes
 //!
 //! We must check if every index satisfies the difficulty condition.
@@ -35,16 +40,24 @@
 //!     - encrypt it with AES,
 //!     - convert AES output to u64,
 //!     - compare it with difficulty.
-use std::cmp::Ordering;
+use std::{
+    cmp::Ordering,
+    sync::atomic::{self, AtomicUsize},
+    time::{Duration, Instant},
+};
 
 use cipher::BlockEncrypt;
 use log::debug;
+#[cfg(feature = "parallel")]
+use rayon::iter::{ParallelBridge, ParallelIterator};
+#[cfg(feature = "parallel")]
+use std::sync::Arc;
 
 use crate::{
     cipher::AesCipher,
-    compression::{decompress_indexes, required_bits},
+    compression::{self, required_bits, CompressedIndices},
     config::{InitConfig, ProofConfig},
-    difficulty::{proving_difficulty, scale_pow_difficulty},
+    difficulty::{self, proving_difficulty, scale_pow_difficulty},
     initialize::{calc_commitment, generate_label},
     metadata::ProofMetadata,
     pow::PowVerifier,
@@ -55,7 +68,11 @@ use crate::{
 const NONCES_PER_AES: u32 = Prover8_56::NONCES_PER_AES;
 
 pub struct Verifier {
-    pow_verifier: Box<dyn PowVerifier + Send + Sync>,
+    // Not set for verifiers of the labels only.
+    pow_verifier: Option<Box<dyn PowVerifier + Send + Sync>>,
+    timeout: Option<Duration>,
+    #[cfg(feature = "parallel")]
+    thread_pool: Option<Arc<rayon::ThreadPool>>,
 }
 
 #[derive(thiserror::Error, Debug)]
@@ -84,8 +101,75 @@ pub enum Error {
     },
     #[error(transparent)]
     InvalidMetadata(#[from] MetadataValidationError),
-    #[error("invalid number of labels: (0)")]
-    InvalidNumLabels(String),
+    #[error("invalid difficulty: {0}")]
+    InvalidDifficulty(#[from] difficulty::Error),
+    #[error("invalid indices: {0}")]
+    InvalidIndices(#[from] compression::Error),
+    #[error("verification timed out after {0:?}")]
+    Timeout(Duration),
+    #[error("no PoW verifier (created for verifying the labels only)")]
+    NoPowVerifier,
+}
+
+impl Error {
+    /// The index (position in the POS data) of the invalid label, if the error is about one.
+    pub fn index(&self) -> Option<u64> {
+        match self {
+            Error::InvalidMsb { index, .. } | Error::InvalidLsb { index, .. } => Some(*index),
+            _ => None,
+        }
+    }
+}
+
+/// The PoW of a proof, to be verified separately (see [Verifier::verify_labels_only]).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PendingPow {
+    pub pow: u64,
+    pub nonce_group: u8,
+    pub challenge: [u8; 8],
+    /// The PoW difficulty, already scaled with the number of units.
+    pub difficulty: [u8; 32],
+    pub miner_id: [u8; 32],
+}
+
+impl PendingPow {
+    fn new(proof: &Proof, metadata: &ProofMetadata, cfg: &ProofConfig) -> Result<Self, Error> {
+        let nonce_group = proof.nonce / NONCES_PER_AES;
+        Ok(Self {
+            pow: proof.pow,
+            nonce_group: nonce_group
+                .try_into()
+                .map_err(|_| Error::NonceGroupOutOfBounds(nonce_group))?,
+            challenge: metadata.challenge[..8].try_into().unwrap(),
+            difficulty: scale_pow_difficulty(&cfg.pow_difficulty, metadata.num_units)?,
+            miner_id: metadata.node_id,
+        })
+    }
+
+    pub fn verify(&self, pow_verifier: &dyn PowVerifier) -> Result<(), Error> {
+        debug!(
+            "verifying K2 pow for nonce group: {} with difficulty: {}",
+            self.nonce_group,
+            hex::encode_upper(self.difficulty)
+        );
+        pow_verifier.verify(
+            self.pow,
+            self.nonce_group,
+            &self.challenge,
+            &self.difficulty,
+            &self.miner_id,
+        )?;
+        Ok(())
+    }
+}
+
+/// A proof with valid labels, but its PoW not verified yet.
+#[must_use = "the PoW of the proof is not verified"]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LabelsVerified {
+    /// The number of labels verified (depends on the [Mode]).
+    pub labels: usize,
+    pub pow: PendingPow,
 }
 
 #[derive(thiserror::Error, Debug)]
@@ -129,11 +213,99 @@ pub enum Mode<'a> {
         k3: usize,
         seed: &'a [u8],
     },
+    // Verify the `part`-th k3 indices of the shuffle of `Subset` (which is the part 0).
+    // Verifiers checking different parts with the same `seed` check disjoint indices.
+    SubsetPart {
+        k3: usize,
+        seed: &'a [u8],
+        part: usize,
+    },
+}
+
+/// Derive the seed of [Mode::Subset] from public randomness (i.e. a block hash).
+///
+/// Verifiers using the same `randomness` and `member` select the same indices.
+pub fn subset_seed(randomness: &[u8], member: u32) -> [u8; 32] {
+    let mut hasher = blake3::Hasher::new();
+    hasher.update(b"post-rs subset seed");
+    hasher.update(randomness);
+    hasher.update(&member.to_le_bytes());
+    hasher.finalize().into()
+}
+
+/// The ids (positions in the proof) of the indices verified in the given `mode`,
+/// in the order they are verified.
+pub fn selected_index_ids(mode: Mode, k2: u32, metadata: &ProofMetadata) -> Vec<usize> {
+    select_index_ids(mode, k2 as usize, metadata).collect()
+}
+
+fn select_index_ids(
+    mode: Mode,
+    k2: usize,
+    metadata: &ProofMetadata,
+) -> Box<dyn Iterator<Item = usize> + Send> {
+    let shuffle = |seed: &[u8]| {
+        let seed = &[
+            seed,
+            metadata.node_id.as_slice(),
+            metadata.challenge.as_slice(),
+        ];
+        RandomValuesIterator::new(k2, seed)
+    };
+    match mode {
+        Mode::All => Box::new(0..k2),
+        Mode::Subset { k3, .. } if k3 >= k2 => Box::new(0..k2),
+        Mode::One { index } => Box::new((index < k2).then_some(index).into_iter()),
+        Mode::Subset { k3, seed } => Box::new(shuffle(seed).take(k3)),
+        Mode::SubsetPart { k3, seed, part } => {
+            Box::new(shuffle(seed).skip(k3.saturating_mul(part)).take(k3))
+        }
+    }
 }
 
 impl Verifier {
+    /// Create a verifier verifying the PoW with `pow_verifier`.
+    ///
+    /// A PoW verifier can be shared by many verifiers by passing it in an [Arc](std::sync::Arc).
     pub fn new(pow_verifier: Box<dyn PowVerifier + Send + Sync>) -> Self {
-        Self { pow_verifier }
+        Self {
+            pow_verifier: Some(pow_verifier),
+            timeout: None,
+            #[cfg(feature = "parallel")]
+            thread_pool: None,
+        }
+    }
+
+    /// Create a verifier for [Verifier::verify_labels_only], without a PoW verifier
+    /// (i.e. without the memory of a RandomX VM). [Verifier::verify] fails with
+    /// [Error::NoPowVerifier].
+    pub fn labels_only() -> Self {
+        Self {
+            pow_verifier: None,
+            timeout: None,
+            #[cfg(feature = "parallel")]
+            thread_pool: None,
+        }
+    }
+
+    /// Verify the labels of proofs in parallel on the threads of `pool`
+    /// (instead of the calling thread).
+    ///
+    /// The pool can be shared by many verifiers to bound their CPU usage together.
+    /// If a proof has several invalid labels, any of them can be reported.
+    #[cfg(feature = "parallel")]
+    pub fn with_thread_pool(mut self, pool: Arc<rayon::ThreadPool>) -> Self {
+        self.thread_pool = Some(pool);
+        self
+    }
+
+    /// Abort verifications taking longer than `timeout` with [Error::Timeout].
+    ///
+    /// Verifying a proof against heavy scrypt parameters can take a long time.
+    /// The deadline is checked before generating each label.
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
     }
 
     /// Verify if a proof is valid.
@@ -144,6 +316,7 @@ impl Verifier {
     /// * `metadata`: ProofMetadata
     /// * `params`: VerifyingParams
     /// * `threads`: The number of threads to use for verification.
+    #[tracing::instrument(skip_all, fields(nonce = proof.nonce, mode = ?mode))]
     pub fn verify(
         &self,
         proof: &Proof,
@@ -152,29 +325,53 @@ impl Verifier {
         init_cfg: &InitConfig,
         mode: Mode,
     ) -> Result<(), Error> {
+        let started = Instant::now();
         verify_metadata(metadata, init_cfg)?;
 
-        let challenge = metadata.challenge;
-        let pow_difficulty = scale_pow_difficulty(&cfg.pow_difficulty, metadata.num_units);
-
         // Verify K2 PoW
+        let pow_verifier = self.pow_verifier.as_deref().ok_or(Error::NoPowVerifier)?;
+        PendingPow::new(proof, metadata, cfg)?.verify(pow_verifier)?;
+
+        self.verify_labels(proof, metadata, cfg, init_cfg, mode, started)?;
+        Ok(())
+    }
+
+    /// Verify a proof like [Verifier::verify], except its PoW.
+    ///
+    /// For callers verifying the PoW elsewhere (or later, with [PendingPow::verify]).
+    #[tracing::instrument(skip_all, fields(nonce = proof.nonce, mode = ?mode))]
+    pub fn verify_labels_only(
+        &self,
+        proof: &Proof,
+        metadata: &ProofMetadata,
+        cfg: &ProofConfig,
+        init_cfg: &InitConfig,
+        mode: Mode,
+    ) -> Result<LabelsVerified, Error> {
+        let started = Instant::now();
+        verify_metadata(metadata, init_cfg)?;
+        let pow = PendingPow::new(proof, metadata, cfg)?;
+        let labels = self.verify_labels(proof, metadata, cfg, init_cfg, mode, started)?;
+        Ok(LabelsVerified { labels, pow })
+    }
+
+    /// Verify the number of indices and the labels selected by `mode`.
+    /// Returns the number of labels verified.
+    fn verify_labels(
+        &self,
+        proof: &Proof,
+        metadata: &ProofMetadata,
+        cfg: &ProofConfig,
+        init_cfg: &InitConfig,
+        mode: Mode,
+        started: Instant,
+    ) -> Result<usize, Error> {
+        let deadline = self.timeout.map(|timeout| (started + timeout, timeout));
+        let challenge = metadata.challenge;
         let nonce_group = proof.nonce / NONCES_PER_AES;
-        debug!(
-            "verifying K2 pow for nonce group: {nonce_group} with difficulty: {}",
-            hex::encode_upper(pow_difficulty)
-        );
-        self.pow_verifier.verify(
-            proof.pow,
-            nonce_group
-                .try_into()
-                .map_err(|_| Error::NonceGroupOutOfBounds(nonce_group))?,
-            &challenge[..8].try_into().unwrap(),
-            &pow_difficulty,
-            &metadata.node_id,
-        )?;
 
         // Verify the number of indices against K2
-        let num_labels = metadata.num_units as u64 * init_cfg.labels_per_unit;
+        let num_labels = init_cfg.unit_size().labels(metadata.num_units);
         let bits_per_index = required_bits(num_labels);
         let expected = expected_indices_bytes(bits_per_index, cfg.k2);
         if proof.indices.len() != expected {
@@ -188,31 +385,31 @@ impl Verifier {
         let cipher = AesCipher::new(&challenge, nonce_group, proof.pow);
         let lazy_cipher = AesCipher::new_lazy(&challenge, proof.nonce, nonce_group, proof.pow);
 
-        let difficulty = proving_difficulty(cfg.k1, num_labels).map_err(Error::InvalidNumLabels)?;
+        let difficulty = proving_difficulty(cfg.k1, num_labels)?;
         let (difficulty_msb, difficulty_lsb) = Prover8_56::split_difficulty(difficulty);
 
         let output_index = (proof.nonce % NONCES_PER_AES) as usize;
 
-        let indices_unpacked = decompress_indexes(&proof.indices, bits_per_index)
-            .take(cfg.k2 as usize)
-            .enumerate();
-
-        let indices: Box<dyn Iterator<Item = (usize, u64)>> = match mode {
-            Mode::All => Box::new(indices_unpacked),
-            Mode::Subset { k3, .. } if k3 == cfg.k2 as usize => Box::new(indices_unpacked),
-            Mode::One { index } => Box::new(indices_unpacked.skip(index).take(1)),
-            Mode::Subset { k3, seed } => {
-                // Shuffle and take k3 indices
-                let seed = &[
-                    seed,
-                    metadata.node_id.as_slice(),
-                    metadata.challenge.as_slice(),
-                ];
-                Box::new(RandomValuesIterator::new(indices_unpacked, seed).take(k3))
-            }
+        // Indices are decoded lazily, verifying a proof doesn't allocate for them.
+        let indices_unpacked =
+            CompressedIndices::new(&proof.indices, bits_per_index, cfg.k2 as usize)?;
+
+        let indices: Box<dyn Iterator<Item = (usize, u64)> + Send> = match mode {
+            Mode::All => Box::new(indices_unpacked.iter().enumerate()),
+            mode => Box::new(
+                select_index_ids(mode, indices_unpacked.len(), metadata)
+                    .filter_map(move |id| Some((id, indices_unpacked.get(id)?))),
+            ),
         };
 
-        for (index_id, index) in indices {
+        let verified = AtomicUsize::new(0);
+        let verify_label = |(index_id, index): (usize, u64)| {
+            verified.fetch_add(1, atomic::Ordering::Relaxed);
+            if let Some((deadline, timeout)) = deadline {
+                if Instant::now() >= deadline {
+                    return Err(Error::Timeout(timeout));
+                }
+            }
             let mut output = [0u8; 16];
             let label = generate_label(&commitment, init_cfg.scrypt, index);
             cipher
@@ -252,8 +449,15 @@ impl Verifier {
                     }
                 }
             }
+            Ok(())
+        };
+        #[cfg(feature = "parallel")]
+        if let Some(pool) = &self.thread_pool {
+            pool.install(|| indices.par_bridge().try_for_each(verify_label))?;
+            return Ok(verified.into_inner());
         }
-        Ok(())
+        indices.into_iter().try_for_each(verify_label)?;
+        Ok(verified.into_inner())
     }
 }
 
@@ -274,7 +478,7 @@ fn expected_indices_bytes(required_bits: usize, k2: u32) -> usize {
 
 #[cfg(test)]
 mod tests {
-    use std::borrow::Cow;
+    use std::{borrow::Cow, time::Duration};
 
     use crate::{
         config::{InitConfig, ProofConfig, ScryptParams},
@@ -284,7 +488,9 @@ mod tests {
         verification::Error,
     };
 
-    use super::{expected_indices_bytes, next_multiple_of, Mode, Verifier};
+    use super::{
+        expected_indices_bytes, next_multiple_of, selected_index_ids, subset_seed, Mode, Verifier,
+    };
 
     #[test]
     fn test_next_mutliple_of() {
@@ -299,6 +505,51 @@ mod tests {
         assert_eq!(10, expected_indices_bytes(8, 10));
     }
 
+    #[test]
+    fn selecting_subsets() {
+        let metadata = ProofMetadata {
+            node_id: [0; 32],
+            commitment_atx_id: [0; 32],
+            challenge: [0; 32],
+            num_units: 1,
+        };
+        let seed = subset_seed(b"block hash", 0);
+        assert_eq!(seed, subset_seed(b"block hash", 0));
+        assert_ne!(seed, subset_seed(b"block hash", 1));
+        assert_ne!(seed, subset_seed(b"other block hash", 0));
+
+        let subset = selected_index_ids(Mode::Subset { k3: 4, seed: &seed }, 10, &metadata);
+        assert_eq!(4, subset.len());
+        assert_eq!(
+            subset,
+            selected_index_ids(Mode::Subset { k3: 4, seed: &seed }, 10, &metadata)
+        );
+
+        // The parts are disjoint slices of the same shuffle, the first one is the subset
+        let parts: Vec<_> = (0..3)
+            .map(|part| {
+                let mode = Mode::SubsetPart {
+                    k3: 4,
+                    seed: &seed,
+                    part,
+                };
+                selected_index_ids(mode, 10, &metadata)
+            })
+            .collect();
+        assert_eq!(subset, parts[0]);
+        let mut all = parts.concat();
+        assert_eq!(10, all.len());
+        all.sort();
+        assert_eq!((0..10).collect::<Vec<_>>(), all);
+
+        assert_eq!(
+            vec![3],
+            selected_index_ids(Mode::One { index: 3 }, 10, &metadata)
+        );
+        assert!(selected_index_ids(Mode::One { index: 10 }, 10, &metadata).is_empty());
+        assert_eq!(10, selected_index_ids(Mode::All, 10, &metadata).len());
+    }
+
     #[test]
     fn reject_invalid_pow() {
         let cfg = ProofConfig {
@@ -411,6 +662,40 @@ mod tests {
         }
     }
 
+    #[test]
+    fn verification_timeout() {
+        let pcfg = ProofConfig {
+            k1: 10,
+            k2: 10,
+            pow_difficulty: [0xFF; 32],
+        };
+        let icfg = InitConfig {
+            min_num_units: 1,
+            max_num_units: 10,
+            labels_per_unit: 2048,
+            scrypt: ScryptParams::new(4, 1, 1),
+        };
+        let fake_metadata = ProofMetadata {
+            node_id: [0u8; 32],
+            commitment_atx_id: [0u8; 32],
+            challenge: [0u8; 32],
+            num_units: 10,
+        };
+        let mut pow_verifier = Box::new(MockPowVerifier::new());
+        pow_verifier
+            .expect_verify()
+            .returning(|_, _, _, _, _| Ok(()));
+        let verifier = Verifier::new(pow_verifier).with_timeout(Duration::ZERO);
+
+        let proof = Proof {
+            nonce: 0,
+            indices: Cow::from(vec![0; expected_indices_bytes(15, 10)]),
+            pow: 0,
+        };
+        let result = verifier.verify(&proof, &fake_metadata, &pcfg, &icfg, Mode::All);
+        assert!(matches!(result, Err(Error::Timeout(Duration::ZERO))));
+    }
+
     #[test]
     fn verify_metadata() {
         let valid_meta = ProofMetadata {
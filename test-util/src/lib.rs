@@ -0,0 +1,7 @@
+//! Utilities for integration tests against the post service protocol.
+//!
+//! - [server] is a node accepting connections from post services,
+//! - [post_data] initializes tiny POST data and generates proofs for it quickly.
+
+pub mod post_data;
+pub mod server;
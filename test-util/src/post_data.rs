@@ -0,0 +1,124 @@
+//! Tiny POST data for tests.
+//!
+//! The data is initialized with the smallest scrypt parameters and
+//! proofs are generated and verified in milliseconds.
+
+use std::{path::Path, sync::atomic::AtomicBool};
+
+use post::{
+    config::{Cores, InitConfig, ProofConfig, ScryptParams},
+    initialize::{CpuInitializer, Initialize},
+    metadata::{PostMetadata, ProofMetadata},
+    pow::randomx::{PoW, RandomXFlag},
+    prove::{generate_proof, NoopProgressReporter, Proof},
+    verification::{Mode, Verifier},
+};
+use tempfile::TempDir;
+
+pub const NODE_ID: [u8; 32] = [0xBE; 32];
+pub const COMMITMENT_ATX_ID: [u8; 32] = [0xCE; 32];
+pub const LABELS_PER_UNIT: u64 = 256;
+pub const NUM_UNITS: u32 = 4;
+pub const MAX_FILE_SIZE: u64 = 256;
+/// Number of nonces to use in proving.
+pub const NONCES: usize = 16;
+
+pub fn scrypt() -> ScryptParams {
+    ScryptParams::new(2, 1, 1)
+}
+
+pub fn proof_config() -> ProofConfig {
+    ProofConfig {
+        k1: 8,
+        k2: 4,
+        pow_difficulty: [0xFF; 32],
+    }
+}
+
+pub fn init_config() -> InitConfig {
+    InitConfig {
+        min_num_units: 1,
+        max_num_units: NUM_UNITS,
+        labels_per_unit: LABELS_PER_UNIT,
+        scrypt: scrypt(),
+    }
+}
+
+/// Initialize tiny POST data in `datadir`.
+pub fn initialize(datadir: &Path) -> eyre::Result<PostMetadata> {
+    CpuInitializer::new(scrypt())
+        .initialize(
+            datadir,
+            &NODE_ID,
+            &COMMITMENT_ATX_ID,
+            LABELS_PER_UNIT,
+            NUM_UNITS,
+            MAX_FILE_SIZE,
+            None,
+        )
+        .map_err(|e| eyre::eyre!("initializing POST data: {e}"))
+}
+
+/// Tiny POST data in a temporary directory, removed when dropped.
+#[derive(Debug)]
+pub struct TinyPost {
+    pub datadir: TempDir,
+    pub metadata: PostMetadata,
+}
+
+impl TinyPost {
+    pub fn new() -> eyre::Result<Self> {
+        let datadir = tempfile::tempdir()?;
+        let metadata = initialize(datadir.path())?;
+        Ok(Self { datadir, metadata })
+    }
+
+    pub fn path(&self) -> &Path {
+        self.datadir.path()
+    }
+
+    /// Generate a proof for the challenge.
+    ///
+    /// The data is always the same, so is the proof of a challenge.
+    pub fn generate_proof(&self, challenge: &[u8; 32]) -> eyre::Result<Proof<'static>> {
+        let pow_flags = RandomXFlag::get_recommended_flags();
+        Ok(generate_proof(
+            self.path(),
+            challenge,
+            proof_config(),
+            NONCES,
+            Cores::Any(1),
+            pow_flags,
+            AtomicBool::new(false),
+            NoopProgressReporter {},
+            &PoW::new(pow_flags)?,
+        )?)
+    }
+
+    /// Verify a proof for the challenge.
+    pub fn verify_proof(&self, proof: &Proof, challenge: &[u8; 32]) -> eyre::Result<()> {
+        let verifier = Verifier::new(Box::new(PoW::new(RandomXFlag::get_recommended_flags())?));
+        verifier.verify(
+            proof,
+            &ProofMetadata::new(self.metadata, *challenge),
+            &proof_config(),
+            &init_config(),
+            Mode::All,
+        )?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn generating_and_verifying_proofs() {
+        let post = TinyPost::new().unwrap();
+        let proof = post.generate_proof(&[0xCA; 32]).unwrap();
+        assert_eq!(proof, post.generate_proof(&[0xCA; 32]).unwrap());
+        post.verify_proof(&proof, &[0xCA; 32]).unwrap();
+        assert!(post.verify_proof(&proof, &[0xCB; 32]).is_err());
+    }
+}
@@ -1,4 +1,4 @@
-//! Simple server implementation for tests
+//! Node server for tests.
 //!
 //! Accepts connections from the service clients and
 //! allows the tests to interact with it via a channel
@@ -81,7 +81,7 @@ impl post_service_server::PostService for TestPostService {
     }
 }
 
-pub(crate) struct TlsConfig {
+pub struct TlsConfig {
     pub server_ca_cert: Certificate,
     pub server: Identity,
 
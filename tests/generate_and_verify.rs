@@ -1,13 +1,17 @@
-use std::{borrow::Cow, sync::atomic::AtomicBool};
+use std::{
+    borrow::Cow,
+    sync::atomic::{AtomicBool, Ordering},
+};
 
 use post::{
     compression::{compress_indices, decompress_indexes, required_bits},
     config::{InitConfig, ScryptParams},
     initialize::{CpuInitializer, Initialize},
+    inspect::{inspect, LabelLocation},
     metadata::ProofMetadata,
     pow::randomx::{PoW, RandomXFlag},
     prove::{self, generate_proof, Proof},
-    verification::{Error, Mode, Verifier},
+    verification::{selected_index_ids, subset_seed, Error, Mode, Verifier},
 };
 use tempfile::tempdir;
 
@@ -46,7 +50,26 @@ fn test_generate_and_verify() {
     let stop = AtomicBool::new(false);
     let mut reporter = prove::MockProgressReporter::new();
     reporter.expect_new_nonce_group().once().return_const(());
+    reporter.expect_finished_k2pow().times(2).return_const(());
+    reporter
+        .expect_started_data_pass()
+        .once()
+        .withf(|nonces| *nonces == (0..32))
+        .return_const(());
     reporter.expect_finished_chunk().times(1..).return_const(());
+    reporter.expect_finished_batch().times(1..).return_const(());
+    reporter
+        .expect_found_index()
+        .times(32..)
+        .withf(|nonce, found| (0..32).contains(nonce) && (1..=32).contains(found))
+        .return_const(());
+    reporter
+        .expect_finished_pass()
+        .once()
+        .withf(|nonces, indices_found| {
+            *nonces == (0..32) && indices_found.values().any(|&found| found == 32)
+        })
+        .return_const(());
     let pow_prover = post::pow::randomx::PoW::new(pow_flags).unwrap();
     let proof = generate_proof(
         datadir.path(),
@@ -110,6 +133,19 @@ fn test_generate_and_verify() {
         result,
         Err(Error::InvalidMsb { index_id, .. }) if index_id == 7
     ));
+
+    // verify disjoint parts of a committee seed - only the part with the invalid index fails
+    let seed = subset_seed(b"block hash", 0);
+    for part in 0..4 {
+        let mode = Mode::SubsetPart {
+            k3: cfg.k2 as usize / 4,
+            seed: &seed,
+            part,
+        };
+        let selected = selected_index_ids(mode, cfg.k2, &metadata);
+        let result = verifier.verify(&invalid_proof, &metadata, &cfg, &init_cfg, mode);
+        assert_eq!(selected.contains(&7), result.is_err());
+    }
 }
 
 #[test]
@@ -189,3 +225,803 @@ fn test_generate_and_verify_difficulty_msb_not_zero() {
         Err(Error::InvalidMsb { index_id, .. }) if index_id == 4
     ));
 }
+
+#[test]
+fn generate_and_verify_from_block_device() {
+    let challenge = b"hello world, challenge me!!!!!!!";
+    let tmp = tempdir().unwrap();
+    let device = tmp.path().join("device");
+
+    let cfg = post::config::ProofConfig {
+        k1: 20,
+        k2: 30,
+        pow_difficulty: [0xFF; 32],
+    };
+    let init_cfg = InitConfig {
+        min_num_units: 1,
+        max_num_units: 1000,
+        labels_per_unit: 200,
+        scrypt: ScryptParams::new(2, 1, 1),
+    };
+
+    let metadata = post::block_device::initialize(
+        &mut CpuInitializer::new(init_cfg.scrypt),
+        &device,
+        &[0u8; 32],
+        &[0u8; 32],
+        init_cfg.labels_per_unit,
+        2,
+        None,
+    )
+    .unwrap();
+
+    let pow_flags = RandomXFlag::get_recommended_flags();
+    let pow_prover = post::pow::randomx::PoW::new(pow_flags).unwrap();
+    let proof = generate_proof(
+        &device,
+        challenge,
+        cfg,
+        32,
+        post::config::Cores::Any(1),
+        pow_flags,
+        AtomicBool::new(false),
+        prove::NoopProgressReporter {},
+        &pow_prover,
+    )
+    .unwrap();
+
+    let metadata = ProofMetadata::new(metadata, *challenge);
+    let verifier = Verifier::new(Box::new(PoW::new(pow_flags).unwrap()));
+    verifier
+        .verify(&proof, &metadata, &cfg, &init_cfg, Mode::All)
+        .expect("proof should be valid");
+}
+
+#[test]
+fn generate_and_verify_with_pipelined_k2pow() {
+    let challenge = b"hello world, challenge me!!!!!!!";
+    let datadir = tempdir().unwrap();
+
+    let cfg = post::config::ProofConfig {
+        k1: 20,
+        k2: 30,
+        pow_difficulty: [0xFF; 32],
+    };
+    let init_cfg = InitConfig {
+        min_num_units: 1,
+        max_num_units: 1000,
+        labels_per_unit: 200,
+        scrypt: ScryptParams::new(2, 1, 1),
+    };
+
+    let metadata = CpuInitializer::new(init_cfg.scrypt)
+        .initialize(
+            datadir.path(),
+            &[0u8; 32],
+            &[0u8; 32],
+            init_cfg.labels_per_unit,
+            2,
+            init_cfg.labels_per_unit,
+            None,
+        )
+        .unwrap();
+
+    let pow_flags = RandomXFlag::get_recommended_flags();
+    let pow_prover = post::pow::randomx::PoW::new(pow_flags).unwrap();
+    let options = prove::ProvingOptions {
+        pipelined_k2pow: true,
+        ..Default::default()
+    };
+    let proof = prove::generate_proof_with_options(
+        datadir.path(),
+        challenge,
+        cfg,
+        40,
+        post::config::Cores::Any(2),
+        pow_flags,
+        AtomicBool::new(false),
+        prove::NoopProgressReporter {},
+        &pow_prover,
+        &options,
+    )
+    .unwrap();
+
+    let metadata = ProofMetadata::new(metadata, *challenge);
+    let verifier = Verifier::new(Box::new(PoW::new(pow_flags).unwrap()));
+    verifier
+        .verify(&proof, &metadata, &cfg, &init_cfg, Mode::All)
+        .expect("proof should be valid");
+}
+
+#[test]
+fn generate_and_verify_with_memory_budget() {
+    let challenge = b"hello world, challenge me!!!!!!!";
+    let datadir = tempdir().unwrap();
+
+    let cfg = post::config::ProofConfig {
+        k1: 40,
+        k2: 30,
+        pow_difficulty: [0xFF; 32],
+    };
+    // 1 MiB units, several batches of POS data
+    let init_cfg = InitConfig {
+        min_num_units: 1,
+        max_num_units: 1000,
+        labels_per_unit: 1 << 16,
+        scrypt: ScryptParams::new(2, 1, 1),
+    };
+
+    let metadata = CpuInitializer::new(init_cfg.scrypt)
+        .initialize(
+            datadir.path(),
+            &[0u8; 32],
+            &[0u8; 32],
+            init_cfg.labels_per_unit,
+            3,
+            init_cfg.labels_per_unit,
+            None,
+        )
+        .unwrap();
+
+    let pow_flags = RandomXFlag::get_recommended_flags();
+    let pow_prover = post::pow::randomx::PoW::new(pow_flags).unwrap();
+    let generate = |memory_budget| {
+        let options = prove::ProvingOptions {
+            memory_budget,
+            ..Default::default()
+        };
+        prove::generate_proof_with_options(
+            datadir.path(),
+            challenge,
+            cfg,
+            16,
+            post::config::Cores::Any(1),
+            pow_flags,
+            AtomicBool::new(false),
+            prove::NoopProgressReporter {},
+            &pow_prover,
+            &options,
+        )
+        .unwrap()
+    };
+    // A single batch at a time
+    let proof = generate(Some(0));
+    assert_eq!(generate(None), proof);
+    assert_eq!(proof, generate(Some(4 * 1024 * 1024)));
+
+    let metadata = ProofMetadata::new(metadata, *challenge);
+    let verifier = Verifier::new(Box::new(PoW::new(pow_flags).unwrap()));
+    verifier
+        .verify(&proof, &metadata, &cfg, &init_cfg, Mode::All)
+        .expect("proof should be valid");
+}
+
+#[derive(Debug)]
+struct BrokenDisk;
+
+impl prove::PosDataSource for BrokenDisk {
+    fn read(
+        &self,
+        _: u64,
+        _: u64,
+        _: usize,
+    ) -> eyre::Result<Box<dyn Iterator<Item = std::io::Result<prove::Batch>> + Send>> {
+        let err = std::io::Error::other("input/output error");
+        Ok(Box::new(std::iter::once(Err(err))))
+    }
+}
+
+#[test]
+fn failing_on_read_error() {
+    let datadir = tempdir().unwrap();
+    CpuInitializer::new(ScryptParams::new(2, 1, 1))
+        .initialize(datadir.path(), &[0u8; 32], &[0u8; 32], 200, 2, 200, None)
+        .unwrap();
+
+    let cfg = post::config::ProofConfig {
+        k1: 20,
+        k2: 30,
+        pow_difficulty: [0xFF; 32],
+    };
+    let pow_flags = RandomXFlag::get_recommended_flags();
+    let options = prove::ProvingOptions {
+        data_source: Some(std::sync::Arc::new(BrokenDisk)),
+        read_retry: prove::ReadRetry {
+            retries: 2,
+            backoff: std::time::Duration::from_millis(1),
+        },
+        ..Default::default()
+    };
+    let err = prove::generate_proof_with_options(
+        datadir.path(),
+        b"hello world, challenge me!!!!!!!",
+        cfg,
+        16,
+        post::config::Cores::Any(1),
+        pow_flags,
+        AtomicBool::new(false),
+        prove::NoopProgressReporter {},
+        &PoW::new(pow_flags).unwrap(),
+        &options,
+    )
+    .unwrap_err();
+
+    assert_eq!(post::ErrorCode::ReadFailed, err.code());
+    let post::Error::Read(err) = err else {
+        panic!("unexpected error: {err}");
+    };
+    assert_eq!(0, err.pos);
+    assert_eq!(3, err.attempts);
+}
+
+#[test]
+fn generate_and_verify_partially_initialized() {
+    let challenge = b"hello world, challenge me!!!!!!!";
+    let datadir = tempdir().unwrap();
+
+    let cfg = post::config::ProofConfig {
+        k1: 20,
+        k2: 30,
+        pow_difficulty: [0xFF; 32],
+    };
+    let init_cfg = InitConfig {
+        min_num_units: 1,
+        max_num_units: 1000,
+        labels_per_unit: 200,
+        scrypt: ScryptParams::new(2, 1, 1),
+    };
+
+    let metadata = CpuInitializer::new(init_cfg.scrypt)
+        .initialize(datadir.path(), &[0u8; 32], &[0u8; 32], 200, 4, 200, None)
+        .unwrap();
+
+    // Only 2.5 of 4 units are initialized
+    std::fs::remove_file(datadir.path().join("postdata_3.bin")).unwrap();
+    let file = datadir.path().join("postdata_2.bin");
+    let data = std::fs::read(&file).unwrap();
+    std::fs::write(&file, &data[..100 * 16]).unwrap();
+    let metadata = post::metadata::PostMetadata {
+        last_position: Some(500),
+        ..metadata
+    };
+    std::fs::write(
+        datadir.path().join(post::metadata::METADATA_FILE_NAME),
+        serde_json::to_vec(&metadata).unwrap(),
+    )
+    .unwrap();
+
+    let pow_flags = RandomXFlag::get_recommended_flags();
+    let generate = |partial_data| {
+        prove::generate_proof_with_options(
+            datadir.path(),
+            challenge,
+            cfg,
+            16,
+            post::config::Cores::Any(1),
+            pow_flags,
+            AtomicBool::new(false),
+            prove::NoopProgressReporter {},
+            &PoW::new(pow_flags).unwrap(),
+            &prove::ProvingOptions {
+                partial_data,
+                ..Default::default()
+            },
+        )
+    };
+    // Proving fewer units must be opted in
+    assert!(matches!(
+        generate(false),
+        Err(post::Error::PartiallyInitialized {
+            units: 2,
+            total_units: 4
+        })
+    ));
+    let proof = generate(true).unwrap();
+
+    // The proof is valid for the initialized units
+    let initialized = metadata.initialized().unwrap();
+    assert_eq!(2, initialized.num_units);
+    let verifier = Verifier::new(Box::new(PoW::new(pow_flags).unwrap()));
+    verifier
+        .verify(
+            &proof,
+            &ProofMetadata::new(initialized, *challenge),
+            &cfg,
+            &init_cfg,
+            Mode::All,
+        )
+        .expect("proof should be valid");
+
+    // Nothing to prove if not even one unit is initialized
+    let metadata = post::metadata::PostMetadata {
+        last_position: Some(150),
+        ..metadata
+    };
+    std::fs::write(
+        datadir.path().join(post::metadata::METADATA_FILE_NAME),
+        serde_json::to_vec(&metadata).unwrap(),
+    )
+    .unwrap();
+    assert!(matches!(
+        generate(true),
+        Err(post::Error::NotInitialized { .. })
+    ));
+}
+
+/// Counts the passes and the bytes of POS data read in them.
+#[derive(Default)]
+struct ReadCounter {
+    passes: std::sync::atomic::AtomicUsize,
+    bytes: std::sync::atomic::AtomicUsize,
+}
+
+impl prove::ProgressReporter for &ReadCounter {
+    fn new_nonce_group(&self, _: std::ops::Range<u32>) {
+        self.passes.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn finished_chunk(&self, _: u64, len: usize) {
+        self.bytes.fetch_add(len, Ordering::Relaxed);
+    }
+}
+
+#[test]
+fn generate_and_verify_with_early_abort() {
+    let challenge = b"hello world, challenge me!!!!!!!";
+    let datadir = tempdir().unwrap();
+
+    let cfg = post::config::ProofConfig {
+        k1: 20,
+        k2: 30,
+        pow_difficulty: [0xFF; 32],
+    };
+    let init_cfg = InitConfig {
+        min_num_units: 1,
+        max_num_units: 1000,
+        labels_per_unit: 1 << 16,
+        scrypt: ScryptParams::new(2, 1, 1),
+    };
+
+    let metadata = CpuInitializer::new(init_cfg.scrypt)
+        .initialize(
+            datadir.path(),
+            &[0u8; 32],
+            &[0u8; 32],
+            init_cfg.labels_per_unit,
+            2,
+            init_cfg.labels_per_unit,
+            None,
+        )
+        .unwrap();
+
+    let pow_flags = RandomXFlag::get_recommended_flags();
+    let pow_prover = post::pow::randomx::PoW::new(pow_flags).unwrap();
+    let generate = |early_abort| {
+        let counter = ReadCounter::default();
+        let options = prove::ProvingOptions {
+            early_abort,
+            batch_size: prove::BatchSize::Fixed(64 * 1024),
+            ..Default::default()
+        };
+        let proof = prove::generate_proof_with_options(
+            datadir.path(),
+            challenge,
+            cfg,
+            16,
+            post::config::Cores::Any(1),
+            pow_flags,
+            AtomicBool::new(false),
+            &counter,
+            &pow_prover,
+            &options,
+        )
+        .unwrap();
+        (proof, counter)
+    };
+    let (proof, counter) = generate(None);
+    let (aborted_proof, aborted_counter) = generate(Some(0.01));
+    // Aborting a pass can only skip a proof, never find one sooner.
+    let passes = counter.passes.into_inner();
+    let aborted_passes = aborted_counter.passes.into_inner();
+    assert!(aborted_passes >= passes);
+    if aborted_passes == passes {
+        // Only the failing passes were aborted
+        assert_eq!(proof, aborted_proof);
+        assert!(aborted_counter.bytes.into_inner() <= counter.bytes.into_inner());
+    }
+
+    let metadata = ProofMetadata::new(metadata, *challenge);
+    let verifier = Verifier::new(Box::new(PoW::new(pow_flags).unwrap()));
+    verifier
+        .verify(&aborted_proof, &metadata, &cfg, &init_cfg, Mode::All)
+        .expect("proof should be valid");
+}
+
+#[test]
+fn generate_proof_reusing_cached_k2pows() {
+    let challenge = b"hello world, challenge me!!!!!!!";
+    let datadir = tempdir().unwrap();
+
+    let cfg = post::config::ProofConfig {
+        k1: 23,
+        k2: 32,
+        pow_difficulty: [0xFF; 32],
+    };
+    CpuInitializer::new(ScryptParams::new(2, 1, 1))
+        .initialize(
+            datadir.path(),
+            &[77; 32],
+            &[0u8; 32],
+            256 * 16,
+            4,
+            1000,
+            None,
+        )
+        .unwrap();
+
+    let pow_flags = RandomXFlag::get_recommended_flags();
+    let options = prove::ProvingOptions {
+        k2pow_cache: true,
+        ..Default::default()
+    };
+    let generate = |pow_prover: &(dyn post::pow::Prover + Send + Sync)| {
+        prove::generate_proof_with_options(
+            datadir.path(),
+            challenge,
+            cfg,
+            32,
+            post::config::Cores::Any(1),
+            pow_flags,
+            AtomicBool::new(false),
+            prove::NoopProgressReporter {},
+            pow_prover,
+            &options,
+        )
+        .unwrap()
+    };
+    let proof = generate(&PoW::new(pow_flags).unwrap());
+
+    // Proving the same challenge again doesn't calculate the k2pows
+    let mut pow_prover = post::pow::MockProver::new();
+    pow_prover.expect_par().return_const(false);
+    pow_prover.expect_prove().never();
+    pow_prover.expect_prove_many().never();
+    assert_eq!(proof, generate(&pow_prover));
+}
+
+#[test]
+fn verify_on_shared_thread_pool() {
+    let challenge = b"hello world, challenge me!!!!!!!";
+    let datadir = tempdir().unwrap();
+
+    let cfg = post::config::ProofConfig {
+        k1: 20,
+        k2: 30,
+        pow_difficulty: [0xFF; 32],
+    };
+    let init_cfg = InitConfig {
+        min_num_units: 1,
+        max_num_units: 1000,
+        labels_per_unit: 200,
+        scrypt: ScryptParams::new(2, 1, 1),
+    };
+    let metadata = CpuInitializer::new(init_cfg.scrypt)
+        .initialize(
+            datadir.path(),
+            &[0u8; 32],
+            &[0u8; 32],
+            init_cfg.labels_per_unit,
+            2,
+            init_cfg.labels_per_unit,
+            None,
+        )
+        .unwrap();
+
+    let pow_flags = RandomXFlag::get_recommended_flags();
+    let pow = std::sync::Arc::new(PoW::new(pow_flags).unwrap());
+    let proof = generate_proof(
+        datadir.path(),
+        challenge,
+        cfg,
+        32,
+        post::config::Cores::Any(1),
+        pow_flags,
+        AtomicBool::new(false),
+        prove::NoopProgressReporter {},
+        pow.as_ref(),
+    )
+    .unwrap();
+
+    // Two verifiers sharing the threads and the PoW verifier
+    let pool = std::sync::Arc::new(
+        rayon::ThreadPoolBuilder::new()
+            .num_threads(2)
+            .build()
+            .unwrap(),
+    );
+    let verifiers =
+        [(); 2].map(|_| Verifier::new(Box::new(pow.clone())).with_thread_pool(pool.clone()));
+
+    let metadata = ProofMetadata::new(metadata, *challenge);
+    for verifier in &verifiers {
+        verifier
+            .verify(&proof, &metadata, &cfg, &init_cfg, Mode::All)
+            .expect("proof should be valid");
+    }
+
+    let bits = required_bits(metadata.num_units as u64 * init_cfg.labels_per_unit);
+    let mut indices = decompress_indexes(&proof.indices, bits).collect::<Vec<_>>();
+    indices[7] ^= u64::MAX;
+    let invalid_proof = Proof {
+        indices: Cow::Owned(compress_indices(&indices, bits)),
+        ..proof
+    };
+    let result = verifiers[0].verify(&invalid_proof, &metadata, &cfg, &init_cfg, Mode::All);
+    assert!(matches!(
+        result,
+        Err(Error::InvalidMsb { index_id, .. }) if index_id == 7
+    ));
+}
+
+#[test]
+fn verify_labels_only() {
+    let challenge = b"hello world, challenge me!!!!!!!";
+    let datadir = tempdir().unwrap();
+
+    let cfg = post::config::ProofConfig {
+        k1: 20,
+        k2: 30,
+        pow_difficulty: [0xFF; 32],
+    };
+    let init_cfg = InitConfig {
+        min_num_units: 1,
+        max_num_units: 1000,
+        labels_per_unit: 200,
+        scrypt: ScryptParams::new(2, 1, 1),
+    };
+    let metadata = CpuInitializer::new(init_cfg.scrypt)
+        .initialize(
+            datadir.path(),
+            &[0u8; 32],
+            &[0u8; 32],
+            init_cfg.labels_per_unit,
+            2,
+            init_cfg.labels_per_unit,
+            None,
+        )
+        .unwrap();
+
+    let pow_flags = RandomXFlag::get_recommended_flags();
+    let pow = PoW::new(pow_flags).unwrap();
+    let proof = generate_proof(
+        datadir.path(),
+        challenge,
+        cfg,
+        32,
+        post::config::Cores::Any(1),
+        pow_flags,
+        AtomicBool::new(false),
+        prove::NoopProgressReporter {},
+        &pow,
+    )
+    .unwrap();
+
+    let verifier = Verifier::labels_only();
+    let metadata = ProofMetadata::new(metadata, *challenge);
+    let verified = verifier
+        .verify_labels_only(&proof, &metadata, &cfg, &init_cfg, Mode::All)
+        .expect("labels should be valid");
+    assert_eq!(cfg.k2 as usize, verified.labels);
+    assert_eq!(proof.pow, verified.pow.pow);
+    verified.pow.verify(&pow).expect("pow should be valid");
+
+    // The PoW can't be verified without a PoW verifier
+    assert!(matches!(
+        verifier.verify(&proof, &metadata, &cfg, &init_cfg, Mode::All),
+        Err(Error::NoPowVerifier)
+    ));
+
+    let bits = required_bits(metadata.num_units as u64 * init_cfg.labels_per_unit);
+    let mut indices = decompress_indexes(&proof.indices, bits).collect::<Vec<_>>();
+    indices[7] ^= u64::MAX;
+    let invalid_proof = Proof {
+        indices: Cow::Owned(compress_indices(&indices, bits)),
+        ..proof
+    };
+    let result = verifier.verify_labels_only(&invalid_proof, &metadata, &cfg, &init_cfg, Mode::All);
+    assert!(matches!(
+        result,
+        Err(Error::InvalidMsb { index_id, .. }) if index_id == 7
+    ));
+}
+
+#[test]
+fn inspecting_proof() {
+    let challenge = b"hello world, challenge me!!!!!!!";
+    let datadir = tempdir().unwrap();
+
+    let cfg = post::config::ProofConfig {
+        k1: 20,
+        k2: 30,
+        pow_difficulty: [0xFF; 32],
+    };
+    let init_cfg = InitConfig {
+        min_num_units: 1,
+        max_num_units: 1000,
+        labels_per_unit: 200,
+        scrypt: ScryptParams::new(2, 1, 1),
+    };
+    let post_metadata = CpuInitializer::new(init_cfg.scrypt)
+        .initialize(
+            datadir.path(),
+            &[0u8; 32],
+            &[0u8; 32],
+            init_cfg.labels_per_unit,
+            2,
+            init_cfg.labels_per_unit,
+            None,
+        )
+        .unwrap();
+
+    let pow_flags = RandomXFlag::get_recommended_flags();
+    let pow = PoW::new(pow_flags).unwrap();
+    let proof = generate_proof(
+        datadir.path(),
+        challenge,
+        cfg,
+        32,
+        post::config::Cores::Any(1),
+        pow_flags,
+        AtomicBool::new(false),
+        prove::NoopProgressReporter {},
+        &pow,
+    )
+    .unwrap();
+
+    let metadata = ProofMetadata::new(post_metadata, *challenge);
+    let mut report = inspect(&proof, &metadata, &cfg, &init_cfg).unwrap();
+    assert_eq!(cfg.k2 as usize, report.indices.len());
+    assert_eq!(0, report.invalid().count());
+
+    report.read_labels(datadir.path(), &post_metadata).unwrap();
+    assert_eq!(0, report.mismatched().count());
+    for index in &report.indices {
+        assert_eq!(
+            Some(LabelLocation::new(index.index, post_metadata.max_file_size)),
+            index.location
+        );
+    }
+
+    // Corrupt a label in the POS data and an index in the proof
+    let corrupted = &report.indices[3];
+    let location = corrupted.location.unwrap();
+    let file = datadir
+        .path()
+        .join(format!("postdata_{}.bin", location.file));
+    let mut data = std::fs::read(&file).unwrap();
+    data[location.offset as usize] ^= 0xFF;
+    std::fs::write(&file, data).unwrap();
+
+    let bits = required_bits(metadata.num_units as u64 * init_cfg.labels_per_unit);
+    let mut indices = decompress_indexes(&proof.indices, bits).collect::<Vec<_>>();
+    // Out of the range of the data
+    indices[7] = (1 << bits) - 1;
+    let invalid_proof = Proof {
+        indices: Cow::Owned(compress_indices(&indices, bits)),
+        ..proof
+    };
+    let mut report = inspect(&invalid_proof, &metadata, &cfg, &init_cfg).unwrap();
+    report.read_labels(datadir.path(), &post_metadata).unwrap();
+    assert!(report.invalid().all(|r| r.index_id == 7));
+    // The same outcome as verifying
+    let verified = Verifier::labels_only()
+        .verify_labels_only(&invalid_proof, &metadata, &cfg, &init_cfg, Mode::All)
+        .is_ok();
+    assert_eq!(verified, report.indices[7].condition.is_satisfied());
+    assert_eq!(None, report.indices[7].stored_label);
+    let mismatched = report.mismatched().map(|r| r.index_id).collect::<Vec<_>>();
+    assert_eq!(vec![3], mismatched);
+}
+
+#[test]
+fn self_checking_generated_proof() {
+    let challenge = b"hello world, challenge me!!!!!!!";
+    let datadir = tempdir().unwrap();
+
+    let cfg = post::config::ProofConfig {
+        k1: 20,
+        k2: 30,
+        pow_difficulty: [0xFF; 32],
+    };
+    let init_cfg = InitConfig {
+        min_num_units: 1,
+        max_num_units: 1000,
+        labels_per_unit: 200,
+        scrypt: ScryptParams::new(2, 1, 1),
+    };
+    CpuInitializer::new(init_cfg.scrypt)
+        .initialize(
+            datadir.path(),
+            &[0u8; 32],
+            &[0u8; 32],
+            init_cfg.labels_per_unit,
+            2,
+            init_cfg.labels_per_unit,
+            None,
+        )
+        .unwrap();
+
+    let mut pow_prover = post::pow::MockProver::new();
+    pow_prover.expect_par().return_const(false);
+    pow_prover.expect_prove().returning(|_, _, _, _| Ok(0));
+    let generate = |self_check| {
+        prove::generate_proof_with_options(
+            datadir.path(),
+            challenge,
+            cfg,
+            16,
+            post::config::Cores::Any(1),
+            RandomXFlag::get_recommended_flags(),
+            AtomicBool::new(false),
+            prove::NoopProgressReporter {},
+            &pow_prover,
+            &prove::ProvingOptions {
+                self_check,
+                ..Default::default()
+            },
+        )
+    };
+
+    generate(Some(init_cfg)).expect("proof should pass the self-check");
+
+    // The labels don't match the scrypt parameters the proof is checked against.
+    let wrong_init_cfg = InitConfig {
+        scrypt: ScryptParams::new(4, 1, 1),
+        ..init_cfg
+    };
+    let err = generate(Some(wrong_init_cfg)).unwrap_err();
+    assert_eq!(post::ErrorCode::SelfCheckFailed, err.code());
+    let post::Error::SelfCheckFailed { index, .. } = err else {
+        panic!("unexpected error: {err}");
+    };
+    assert!(index.is_some());
+}
+
+#[test]
+fn giving_up_after_max_nonces() {
+    let datadir = tempdir().unwrap();
+    CpuInitializer::new(ScryptParams::new(2, 1, 1))
+        .initialize(datadir.path(), &[0u8; 32], &[0u8; 32], 200, 1, 200, None)
+        .unwrap();
+    // Way too few candidate labels for any nonce to find k2 indices.
+    let cfg = post::config::ProofConfig {
+        k1: 2,
+        k2: 100,
+        pow_difficulty: [0xFF; 32],
+    };
+
+    let mut pow_prover = post::pow::MockProver::new();
+    pow_prover.expect_par().return_const(false);
+    pow_prover.expect_prove().returning(|_, _, _, _| Ok(0));
+    for pipelined_k2pow in [false, true] {
+        let err = prove::generate_proof_with_options(
+            datadir.path(),
+            b"hello world, challenge me!!!!!!!",
+            cfg,
+            16,
+            post::config::Cores::Any(1),
+            RandomXFlag::get_recommended_flags(),
+            AtomicBool::new(false),
+            prove::NoopProgressReporter {},
+            &pow_prover,
+            &prove::ProvingOptions {
+                max_nonces: Some(40),
+                pipelined_k2pow,
+                ..Default::default()
+            },
+        )
+        .unwrap_err();
+        assert_eq!(post::ErrorCode::NoncesExhausted, err.code());
+        assert!(matches!(err, post::Error::NoncesExhausted { nonces: 40 }));
+    }
+}
@@ -1,9 +1,13 @@
-use std::io::Write;
+use std::io::{Seek, SeekFrom, Write};
 
 use post::{
+    block_device::{self, SUPERBLOCK_SIZE},
     config::ScryptParams,
     initialize::{CpuInitializer, Initialize},
-    pos_verification::verify_files,
+    pos_verification::{
+        check_data_size, verify_file, verify_files, verify_sample, DataReport, DataSizeError,
+        DataSnapshot, FileReport,
+    },
 };
 
 use tempfile::tempdir;
@@ -46,3 +50,282 @@ fn test_generate_and_verify() {
     verify_files(datadir.path(), 100.0, None, Some(0), scrypt).unwrap();
     verify_files(datadir.path(), 100.0, Some(2), None, scrypt).unwrap();
 }
+
+#[test]
+fn data_in_use_is_not_verified() {
+    let datadir = tempdir().unwrap();
+    let scrypt = ScryptParams::new(2, 1, 1);
+
+    CpuInitializer::new(scrypt)
+        .initialize(datadir.path(), &[0u8; 32], &[0u8; 32], 256, 1, 700, None)
+        .unwrap();
+
+    let lock = post::lock::DataDirLock::acquire(datadir.path()).unwrap();
+    assert!(matches!(
+        verify_files(datadir.path(), 100.0, None, None, scrypt),
+        Err(post::pos_verification::VerificationError::Locked(_))
+    ));
+    drop(lock);
+    verify_files(datadir.path(), 100.0, None, None, scrypt).unwrap();
+}
+
+#[test]
+fn reporting_all_invalid_labels() {
+    let datadir = tempdir().unwrap();
+    let scrypt = ScryptParams::new(2, 1, 1);
+
+    let metadata = CpuInitializer::new(scrypt)
+        .initialize(datadir.path(), &[0u8; 32], &[0u8; 32], 256, 6, 700, None)
+        .unwrap();
+
+    let report = verify_file(datadir.path(), 1, 100.0, &metadata, scrypt).unwrap();
+    assert_eq!(700, report.labels_checked);
+    assert!(report.invalid_labels.is_empty());
+
+    // Corrupt 2 labels in the second file
+    let mut file = std::fs::OpenOptions::new()
+        .write(true)
+        .open(datadir.path().join("postdata_1.bin"))
+        .unwrap();
+    file.write_all(&[0u8; 16]).unwrap();
+    file.seek(SeekFrom::Start(5 * 16)).unwrap();
+    file.write_all(&[0u8; 16]).unwrap();
+
+    let report = verify_file(datadir.path(), 1, 100.0, &metadata, scrypt).unwrap();
+    assert_eq!(
+        FileReport {
+            file: 1,
+            labels_checked: 700,
+            invalid_labels: vec![700, 705],
+        },
+        report
+    );
+    assert!(verify_file(datadir.path(), 0, 100.0, &metadata, scrypt)
+        .unwrap()
+        .invalid_labels
+        .is_empty());
+}
+
+#[test]
+fn verifying_sample_of_all_files() {
+    let datadir = tempdir().unwrap();
+    let scrypt = ScryptParams::new(2, 1, 1);
+
+    CpuInitializer::new(scrypt)
+        .initialize(datadir.path(), &[0u8; 32], &[0u8; 32], 256, 6, 700, None)
+        .unwrap();
+
+    let reports = verify_sample(datadir.path(), 100.0, scrypt).unwrap();
+    assert_eq!(3, reports.len());
+    assert!(reports.iter().all(|r| r.invalid_labels.is_empty()));
+
+    // Corrupt a label in the first and the last file
+    for (file, offset) in [(0, 3), (2, 10)] {
+        let mut file = std::fs::OpenOptions::new()
+            .write(true)
+            .open(datadir.path().join(format!("postdata_{file}.bin")))
+            .unwrap();
+        file.seek(SeekFrom::Start(offset * 16)).unwrap();
+        file.write_all(&[0u8; 16]).unwrap();
+    }
+
+    let invalid = verify_sample(datadir.path(), 100.0, scrypt)
+        .unwrap()
+        .into_iter()
+        .flat_map(|r| r.invalid_labels)
+        .collect::<Vec<_>>();
+    assert_eq!(vec![3, 1410], invalid);
+}
+
+#[test]
+fn verifying_data_on_block_device() {
+    let tmp = tempdir().unwrap();
+    let device = tmp.path().join("device");
+    let scrypt = ScryptParams::new(2, 1, 1);
+
+    block_device::initialize(
+        &mut CpuInitializer::new(scrypt),
+        &device,
+        &[0u8; 32],
+        &[0u8; 32],
+        256,
+        2,
+        None,
+    )
+    .unwrap();
+
+    verify_files(&device, 100.0, None, None, scrypt).unwrap();
+    assert!(verify_files(&device, 100.0, None, None, ScryptParams::new(4, 1, 1)).is_err());
+    let reports = verify_sample(&device, 100.0, scrypt).unwrap();
+    assert_eq!(1, reports.len());
+    assert_eq!(512, reports[0].labels_checked);
+    assert!(reports[0].invalid_labels.is_empty());
+
+    // Corrupt 2 labels
+    let mut file = std::fs::OpenOptions::new()
+        .write(true)
+        .open(&device)
+        .unwrap();
+    for idx in [0, 300] {
+        file.seek(SeekFrom::Start(SUPERBLOCK_SIZE + idx * 16))
+            .unwrap();
+        file.write_all(&[0u8; 16]).unwrap();
+    }
+
+    assert!(verify_files(&device, 100.0, None, None, scrypt).is_err());
+    let reports = verify_sample(&device, 100.0, scrypt).unwrap();
+    assert_eq!(vec![0, 300], reports[0].invalid_labels);
+}
+
+#[test]
+fn checking_data_snapshot() {
+    let datadir = tempdir().unwrap();
+    let scrypt = ScryptParams::new(2, 1, 1);
+
+    CpuInitializer::new(scrypt)
+        .initialize(datadir.path(), &[0u8; 32], &[0u8; 32], 256, 4, 300, None)
+        .unwrap();
+
+    let snapshot = DataSnapshot::take(datadir.path()).unwrap();
+    snapshot.check(datadir.path()).unwrap();
+
+    // Truncated file
+    let file = datadir.path().join("postdata_1.bin");
+    let data = std::fs::read(&file).unwrap();
+    std::fs::write(&file, &data[..100]).unwrap();
+    assert!(snapshot.check(datadir.path()).is_err());
+    assert!(DataSnapshot::take(datadir.path()).is_err());
+    std::fs::write(&file, &data).unwrap();
+    snapshot.check(datadir.path()).unwrap();
+
+    // Extended file
+    std::fs::write(&file, [data.as_slice(), &[0u8; 16]].concat()).unwrap();
+    assert!(snapshot.check(datadir.path()).is_err());
+    assert!(DataSnapshot::take(datadir.path()).is_err());
+    std::fs::write(&file, &data).unwrap();
+    snapshot.check(datadir.path()).unwrap();
+
+    // Missing file
+    std::fs::rename(&file, datadir.path().join("postdata_1.bak")).unwrap();
+    assert!(snapshot.check(datadir.path()).is_err());
+    std::fs::rename(datadir.path().join("postdata_1.bak"), &file).unwrap();
+
+    // Changed metadata
+    let mut metadata = post::metadata::load(datadir.path()).unwrap();
+    metadata.nonce = Some(7);
+    std::fs::write(
+        datadir.path().join(post::metadata::METADATA_FILE_NAME),
+        serde_json::to_vec(&metadata).unwrap(),
+    )
+    .unwrap();
+    assert!(snapshot.check(datadir.path()).is_err());
+}
+
+#[test]
+fn checking_data_snapshot_of_partial_data() {
+    let datadir = tempdir().unwrap();
+    let scrypt = ScryptParams::new(2, 1, 1);
+
+    let mut metadata = CpuInitializer::new(scrypt)
+        .initialize(datadir.path(), &[0u8; 32], &[0u8; 32], 256, 4, 300, None)
+        .unwrap();
+    metadata.last_position = Some(2 * 256 + 100);
+    std::fs::write(
+        datadir.path().join(post::metadata::METADATA_FILE_NAME),
+        serde_json::to_vec(&metadata).unwrap(),
+    )
+    .unwrap();
+
+    // The second file holds labels of the third (not yet initialized) unit.
+    let snapshot = DataSnapshot::take(datadir.path()).unwrap();
+
+    let file = datadir.path().join("postdata_0.bin");
+    let data = std::fs::read(&file).unwrap();
+    std::fs::write(&file, [data.as_slice(), &[0u8; 16]].concat()).unwrap();
+    assert!(snapshot.check(datadir.path()).is_err());
+}
+
+#[test]
+fn checking_data_size() {
+    let datadir = tempdir().unwrap();
+    let scrypt = ScryptParams::new(2, 1, 1);
+
+    let metadata = CpuInitializer::new(scrypt)
+        .initialize(datadir.path(), &[0u8; 32], &[0u8; 32], 256, 4, 300, None)
+        .unwrap();
+    check_data_size(datadir.path()).unwrap();
+
+    // Data resized to 5 units
+    let extra = datadir.path().join("postdata_4.bin");
+    std::fs::write(&extra, [0u8; 256 * 16]).unwrap();
+    assert!(matches!(
+        check_data_size(datadir.path()),
+        Err(DataSizeError::NumUnits {
+            metadata: 4,
+            data: 5
+        })
+    ));
+
+    // Not whole units
+    std::fs::write(&extra, [0u8; 100 * 16]).unwrap();
+    assert!(matches!(
+        check_data_size(datadir.path()),
+        Err(DataSizeError::LabelsPerUnit {
+            labels: 1124,
+            labels_per_unit: 256
+        })
+    ));
+
+    // Not whole labels
+    std::fs::write(&extra, [0u8; 8]).unwrap();
+    assert!(matches!(
+        check_data_size(datadir.path()),
+        Err(DataSizeError::PartialLabel { size: 16392 })
+    ));
+    std::fs::remove_file(&extra).unwrap();
+
+    // Partially initialized data
+    let metadata = post::metadata::PostMetadata {
+        last_position: Some(700),
+        ..metadata
+    };
+    std::fs::write(
+        datadir.path().join(post::metadata::METADATA_FILE_NAME),
+        serde_json::to_vec(&metadata).unwrap(),
+    )
+    .unwrap();
+    check_data_size(datadir.path()).unwrap();
+    std::fs::remove_file(datadir.path().join("postdata_3.bin")).unwrap();
+    std::fs::remove_file(datadir.path().join("postdata_2.bin")).unwrap();
+    assert!(matches!(
+        check_data_size(datadir.path()),
+        Err(DataSizeError::MissingLabels {
+            labels: 600,
+            expected: 700
+        })
+    ));
+}
+
+#[test]
+fn resuming_report_only_for_same_data() {
+    let datadir = tempdir().unwrap();
+    let scrypt = ScryptParams::new(2, 1, 1);
+    let metadata = CpuInitializer::new(scrypt)
+        .initialize(datadir.path(), &[0u8; 32], &[0u8; 32], 256, 2, 700, None)
+        .unwrap();
+
+    let mut report = DataReport::new(datadir.path(), metadata, scrypt, 5.0);
+    report
+        .files
+        .push(verify_file(datadir.path(), 0, 5.0, &metadata, scrypt).unwrap());
+    let report: DataReport = serde_json::from_slice(&serde_json::to_vec(&report).unwrap()).unwrap();
+    assert!(report.is_for(datadir.path(), &metadata, scrypt, 5.0));
+
+    let other_dir = tempdir().unwrap();
+    assert!(!report.is_for(other_dir.path(), &metadata, scrypt, 5.0));
+    let mut other_metadata = metadata;
+    other_metadata.num_units = 3;
+    assert!(!report.is_for(datadir.path(), &other_metadata, scrypt, 5.0));
+    assert!(!report.is_for(datadir.path(), &metadata, ScryptParams::new(4, 1, 1), 5.0));
+    assert!(!report.is_for(datadir.path(), &metadata, scrypt, 10.0));
+}
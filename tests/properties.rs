@@ -0,0 +1,76 @@
+//! Property tests of the encodings shared with other implementations.
+
+use std::{borrow::Cow, collections::HashSet};
+
+use post::{
+    compression::{compress_indices, decompress_indexes, required_bits, CompressedIndices},
+    prove::Proof,
+    random_values_gen::RandomValuesIterator,
+};
+use proptest::prelude::*;
+
+proptest! {
+    #[test]
+    fn compressed_indices_match_decompress(indices: Vec<u64>, num_labels in 1u64..) {
+        let bits = required_bits(num_labels);
+        let indices: Vec<_> = indices.into_iter().map(|i| i % num_labels).collect();
+        let compressed = compress_indices(&indices, bits);
+
+        let view = CompressedIndices::new(&compressed, bits, indices.len()).unwrap();
+        let streamed: Vec<_> = decompress_indexes(&compressed, bits).take(indices.len()).collect();
+        prop_assert_eq!(&indices, &streamed);
+        prop_assert_eq!(indices, view.iter().collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn compressed_indices_reject_wrong_length(count in 1usize..1000, bits in 1usize..=64, extra in 1usize..8) {
+        let len = (count * bits).div_ceil(8);
+        prop_assert!(CompressedIndices::new(&vec![0; len + extra], bits, count).is_err());
+        prop_assert!(CompressedIndices::new(&vec![0; len - 1], bits, count).is_err());
+    }
+
+    #[test]
+    fn random_values_are_distinct(len in 0usize..5000, take: u16, seed: Vec<u8>) {
+        let values: Vec<_> = RandomValuesIterator::new(len, &[&seed]).take(take as usize).collect();
+        prop_assert_eq!(len.min(take as usize), values.len());
+        let unique: HashSet<_> = values.iter().collect();
+        prop_assert_eq!(unique.len(), values.len());
+        prop_assert!(values.iter().all(|v| *v < len));
+    }
+
+    #[test]
+    fn random_values_are_deterministic(len in 0usize..5000, seed: Vec<u8>) {
+        let first: Vec<_> = RandomValuesIterator::new(len, &[&seed]).take(50).collect();
+        let second: Vec<_> = RandomValuesIterator::new(len, &[&seed]).take(50).collect();
+        prop_assert_eq!(first, second);
+    }
+
+    #[test]
+    fn proof_serde_roundtrip(nonce: u32, indices: Vec<u8>, pow: u64) {
+        let proof = Proof {
+            nonce,
+            indices: Cow::Owned(indices),
+            pow,
+        };
+        let json = serde_json::to_string(&proof).unwrap();
+        let decoded: Proof = serde_json::from_str(&json).unwrap();
+        prop_assert_eq!(proof, decoded);
+    }
+}
+
+/// Run the checked-in fuzzing corpus of the `proof_json` target.
+#[test]
+fn proof_json_corpus() {
+    let dir = std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("fuzz/corpus/proof_json");
+    let mut count = 0;
+    for entry in std::fs::read_dir(dir).unwrap() {
+        let data = std::fs::read(entry.unwrap().path()).unwrap();
+        if let Ok(proof) = serde_json::from_slice::<Proof>(&data) {
+            let encoded = serde_json::to_vec(&proof).unwrap();
+            let decoded: Proof = serde_json::from_slice(&encoded).unwrap();
+            assert_eq!(proof, decoded);
+        }
+        count += 1;
+    }
+    assert!(count > 0, "empty corpus");
+}